@@ -4,7 +4,7 @@ use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
 pub mod common;
 use common::*;
 
-mod constraints;
+pub mod constraints;
 // mod constraints_test;
 
 #[derive(Clone)]
@@ -0,0 +1,52 @@
+//! Time witness generation separately from proving, for a batch of two
+//! transfers. Run with `cargo run --release --example witness_timing`.
+
+use ark_bls12_381::Bls12_381;
+use ark_groth16::Groth16;
+use ark_rollup::rollup::Rollup;
+use ark_simple_payments::ledger::{Amount, Parameters, State};
+use ark_simple_payments::transaction::Transaction;
+use ark_snark::SNARK;
+use std::time::Instant;
+
+fn main() {
+    let mut rng = ark_std::test_rng();
+    let pp = Parameters::sample(&mut rng);
+    let mut state = State::new(32, &pp);
+    let (alice_id, _alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+    state
+        .update_balance(alice_id, Amount(20))
+        .expect("Alice's account should exist");
+    let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+    let transactions = [
+        Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+        Transaction::create(&pp, alice_id, bob_id, Amount(3), &alice_sk, &mut rng),
+    ];
+    let rollup =
+        Rollup::<2>::with_state_and_transactions(pp, &transactions, &mut state, true).unwrap();
+
+    let start = Instant::now();
+    let (pk, _vk) = Groth16::<Bls12_381>::circuit_specific_setup(rollup.clone(), &mut rng).unwrap();
+    println!("setup: {:?}", start.elapsed());
+
+    // Proving synthesizes the witness again, and so the time spent on the rest
+    // of proving is the difference of the two.
+    let start = Instant::now();
+    let cs = rollup.synthesize_only().unwrap();
+    let synthesis = start.elapsed();
+    println!(
+        "witness generation: {:?} ({} constraints)",
+        synthesis,
+        cs.num_constraints()
+    );
+    assert!(cs.is_satisfied().unwrap());
+
+    let start = Instant::now();
+    Groth16::prove(&pk, rollup, &mut rng).unwrap();
+    let proving = start.elapsed();
+    println!("full proving: {:?}", proving);
+    println!(
+        "proving without witness generation: {:?}",
+        proving.saturating_sub(synthesis)
+    );
+}
@@ -1,16 +1,58 @@
-use crate::ledger::*;
+use crate::ledger::{ParametersVar, *};
 use crate::ConstraintF;
+use ark_crypto_primitives::crh::{CRHGadget, CRH};
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
-use ark_r1cs_std::bits::{uint8::UInt8, ToBytesGadget};
+use ark_r1cs_std::bits::{uint64::UInt64, uint8::UInt8, ToBytesGadget};
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_relations::r1cs::{ConstraintSystemRef, Namespace, SynthesisError};
 use ark_simple_payments::account::*;
+use ark_simple_payments::ledger::{Amount, AssetId, Balances, LeafDomain, LeafHash, State};
 use ark_simple_payments::signature::schnorr::constraints::*;
 use std::borrow::Borrow;
+use std::collections::hash_map::{Entry, HashMap};
 
 /// Account public key used to verify transaction signatures.
 pub type AccountPublicKeyVar = PublicKeyVar<EdwardsProjective, EdwardsVar>;
 
+/// Account signature, encoded as specified by `ParametersVar::signature_encoding`.
+#[derive(Clone)]
+pub enum AccountSignatureVar {
+    /// A signature in the `SignatureEncoding::Challenge` encoding.
+    Challenge(SignatureVar<EdwardsProjective, EdwardsVar>),
+    /// A signature in the `SignatureEncoding::Point` encoding.
+    Point(PointSignatureVar<EdwardsProjective, EdwardsVar>),
+}
+
+impl AllocVar<AccountSignature, ConstraintF> for AccountSignatureVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AccountSignature>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|signature| match signature.borrow() {
+            AccountSignature::Challenge(signature) => {
+                SignatureVar::new_variable(cs, || Ok(signature), mode).map(Self::Challenge)
+            }
+            AccountSignature::Point(signature) => {
+                PointSignatureVar::new_variable(cs, || Ok(signature), mode).map(Self::Point)
+            }
+        })
+    }
+}
+
+impl AccountSignatureVar {
+    /// Convert the signature to bytes, as `AccountSignature::to_bytes_le` does.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        match self {
+            Self::Challenge(signature) => signature.to_bytes(),
+            Self::Point(signature) => signature.to_bytes(),
+        }
+    }
+}
+
 /// Account identifier. This prototype supports only 256 accounts at a time.
 #[derive(Clone, Debug)]
 pub struct AccountIdVar(pub UInt8<ConstraintF>);
@@ -23,6 +65,29 @@ impl AccountIdVar {
     }
 }
 
+impl AccountIdVar {
+    /// Compute the account identifier of the handle whose UTF-8 encoding is
+    /// `handle`, as `AccountId::from_handle` does. The handle must already be
+    /// normalized, since it is hashed as is.
+    #[tracing::instrument(target = "r1cs", skip(parameters, handle))]
+    pub fn from_handle(
+        parameters: &ParametersVar,
+        handle: &[UInt8<ConstraintF>],
+    ) -> Result<Self, SynthesisError> {
+        assert!(handle.len() <= MAX_HANDLE_LEN, "handle is too long");
+        // The hashed input consists of
+        // (Tag || HandleLength || Handle)
+        let mut input = UInt8::constant_vec(HANDLE_TAG);
+        input.push(UInt8::constant(handle.len() as u8));
+        input.extend_from_slice(handle);
+        let hash = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )?;
+        Ok(Self(hash.to_bytes()?[0].clone()))
+    }
+}
+
 impl AllocVar<AccountId, ConstraintF> for AccountIdVar {
     #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
     fn new_variable<T: Borrow<AccountId>>(
@@ -34,25 +99,327 @@ impl AllocVar<AccountId, ConstraintF> for AccountIdVar {
     }
 }
 
-/// Information about the account, such as the balance and the associated public key.
+/// Information about the account, such as the balances and the associated public key.
 #[derive(Clone)]
 pub struct AccountInformationVar {
     /// The account public key.
     pub public_key: AccountPublicKeyVar,
-    /// The balance associated with this this account.
-    pub balance: AmountVar,
+    /// The key that may replace `public_key` if it is lost, or the identity if
+    /// the account has none.
+    pub recovery_key: AccountPublicKeyVar,
+    /// The balance of each asset associated with this this account.
+    pub balances: BalancesVar,
+    /// The number of transactions and swaps authorized by this account.
+    pub nonce: UInt64<ConstraintF>,
+    /// The amount sent by this account during the epoch `limit_epoch`.
+    pub spent_today: AmountVar,
+    /// The epoch during which `spent_today` was sent.
+    pub limit_epoch: UInt64<ConstraintF>,
+    /// The amount of the native asset that is locked in escrow.
+    pub held: AmountVar,
+    /// The total amount spent from this account by its delegates.
+    pub delegated_spent: AmountVar,
+    /// The highest balance that a transfer may credit this account up to, or
+    /// zero if it is uncapped.
+    pub balance_cap: AmountVar,
+    /// The index of the key derived from `public_key` that authorized the last
+    /// transaction of this account.
+    pub key_index: UInt64<ConstraintF>,
+    /// The epoch during which this account was last credited by a transfer.
+    pub last_credited_epoch: UInt64<ConstraintF>,
+    /// The commitment to the allowlist of the recipients that the account may
+    /// pay, or zero if it may pay anybody.
+    pub allowlist: FpVar<ConstraintF>,
+    /// The hash of the metadata that applications attach to the account, or zero
+    /// if it has none.
+    pub metadata_hash: FpVar<ConstraintF>,
+    /// The commitment to the signers of the account if it is a multisig account,
+    /// and zero otherwise.
+    pub signers: FpVar<ConstraintF>,
+    /// The number of signers that must authorize a transaction of a multisig
+    /// account, or zero if the account is not a multisig account.
+    pub threshold: UInt8<ConstraintF>,
+    /// Whether the account accepts transfers without the consent of its owner.
+    pub accepts_transfers: Boolean<ConstraintF>,
 }
 
 impl AccountInformationVar {
-    /// Convert the account information to bytes.
+    /// Convert the account information to bytes, in the canonical packed form of
+    /// `AccountInformation::to_bytes_le`. The field elements are encoded in
+    /// their reduced form, and so each account has a single encoding.
     #[tracing::instrument(target = "r1cs", skip(self))]
     pub fn to_bytes_le(&self) -> Vec<UInt8<crate::ConstraintF>> {
-        self.public_key
-            .to_bytes()
-            .unwrap()
+        let mut bytes: Vec<_> = self
+            .balances
+            .to_bytes_le()
             .into_iter()
-            .chain(self.balance.to_bytes_le())
-            .collect()
+            .chain(self.nonce.to_bytes().unwrap())
+            .chain(self.public_key.to_bytes().unwrap())
+            .chain(self.recovery_key.to_bytes().unwrap())
+            .chain(self.spent_today.to_bytes_le())
+            .chain(self.limit_epoch.to_bytes().unwrap())
+            .chain(self.held.to_bytes_le())
+            .chain(self.delegated_spent.to_bytes_le())
+            .chain(self.balance_cap.to_bytes_le())
+            .chain(self.key_index.to_bytes().unwrap())
+            .chain(self.last_credited_epoch.to_bytes().unwrap())
+            .chain(self.allowlist.to_bytes().unwrap())
+            .chain(self.metadata_hash.to_bytes().unwrap())
+            .chain(self.signers.to_bytes().unwrap())
+            .chain(std::iter::once(self.threshold.clone()))
+            .chain(self.accepts_transfers.to_bytes().unwrap())
+            .collect();
+        assert!(bytes.len() < PACKED_ACCOUNT_INFO_LEN);
+        bytes.resize(PACKED_ACCOUNT_INFO_LEN - 1, UInt8::constant(0));
+        bytes.push(UInt8::constant(PACKED_ACCOUNT_INFO_END));
+        bytes
+    }
+
+    /// Are the held funds covered by the native balance? This holds as long as
+    /// no held funds have been spent.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn covers_held(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.held
+            .is_le(&self.balances.0[AssetId::NATIVE.0 as usize])
+    }
+
+    /// Return the balance of `asset` that can be spent, i.e., the balance
+    /// excluding the held funds, as `AccountInformation::available_balance`
+    /// does. Held funds are always in the native asset, and they must be covered
+    /// by the native balance: otherwise, the constraint system is unsatisfiable.
+    #[tracing::instrument(target = "r1cs", skip(self, asset))]
+    pub fn available_balance(&self, asset: &AssetIdVar) -> Result<AmountVar, SynthesisError> {
+        let is_native = &asset.selectors()?[AssetId::NATIVE.0 as usize];
+        let held = is_native.select(&self.held.0, &UInt64::constant(0))?;
+        self.balances.get(asset)?.checked_sub(&AmountVar(held))
+    }
+
+    /// Have at least `grace` epochs passed since this account was last credited,
+    /// as of the epoch `epoch`?
+    #[tracing::instrument(target = "r1cs", skip(self, epoch, grace))]
+    pub fn is_past_grace(
+        &self,
+        epoch: &UInt64<ConstraintF>,
+        grace: &UInt64<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // All three values are less than 2^64, so
+        // `epoch - last_credited_epoch - grace + 2^65` lies strictly between 0 and
+        // 2^66, and its 66th bit is set iff `epoch - last_credited_epoch >= grace`.
+        let to_fe = |value: &UInt64<ConstraintF>| Boolean::le_bits_to_fp_var(&value.to_bits_le());
+        let offset = FpVar::constant(ConstraintF::from(1u128 << 65));
+        let res_bits = (to_fe(epoch)? - to_fe(&self.last_credited_epoch)? - to_fe(grace)? + offset)
+            .to_bits_le()?;
+        Ok(res_bits[65].clone())
+    }
+
+    /// Enforce that none of the balances of the account exceeds `supply`. No
+    /// single account can hold more than the total supply of an asset, so this
+    /// catches value minted into the account.
+    #[tracing::instrument(target = "r1cs", skip(self, supply))]
+    pub fn enforce_balance_le(&self, supply: &AmountVar) -> Result<(), SynthesisError> {
+        self.is_balance_le(supply)?.enforce_equal(&Boolean::TRUE)
+    }
+
+    /// Check whether none of the balances of the account exceeds `supply`, as
+    /// `enforce_balance_le` enforces.
+    #[tracing::instrument(target = "r1cs", skip(self, supply))]
+    pub fn is_balance_le(
+        &self,
+        supply: &AmountVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let within_supply = self
+            .balances
+            .0
+            .iter()
+            .map(|balance| balance.is_le(supply))
+            .collect::<Result<Vec<_>, _>>()?;
+        Boolean::kary_and(&within_supply)
+    }
+
+    /// Commit to the account information.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters))]
+    pub fn commitment(
+        &self,
+        parameters: &ParametersVar,
+    ) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &self.to_bytes_le(),
+        )
+    }
+
+    /// Return the leaf of the account Merkle tree that stores this account
+    /// information, i.e., the marked bytes of its commitment, see
+    /// `mark_commitment`, personalized as `ParametersVar::personalize_leaf`
+    /// does. This is never the blank leaf of an empty slot.
+    pub fn to_leaf(
+        &self,
+        parameters: &ParametersVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let commitment = mark_commitment(&self.commitment(parameters)?)?;
+        Ok(parameters.personalize_leaf(LeafDomain::Account, commitment))
+    }
+
+    /// Enforce that registering this account, i.e., storing it in the blank leaf
+    /// at the end of `path`, changes the root of the account tree from `pre_root`
+    /// to `post_root`, as `State::register` does.
+    ///
+    /// A new account must start with a zero nonce: otherwise, the window of
+    /// nonces that its signatures can be replayed in would be ambiguous.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, path, pre_root, post_root))]
+    pub fn enforce_registration(
+        &self,
+        parameters: &ParametersVar,
+        path: &AccPathVar,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<(), SynthesisError> {
+        self.nonce.enforce_equal(&UInt64::constant(0))?;
+        let blank = vec![UInt8::constant(0); <LeafHash as CRH>::INPUT_SIZE_BITS / 8];
+        path.update_and_check(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            pre_root,
+            post_root,
+            &blank.as_slice(),
+            &self.to_leaf(parameters)?.as_slice(),
+        )?
+        .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+/// Return the bytes of the account commitment `commitment`, as stored in the
+/// leaf of the account, marked as `account::mark_commitment` does. The blank
+/// leaf consists of zero bytes, and so it is never the leaf of an account.
+pub fn mark_commitment(
+    commitment: &FpVar<ConstraintF>,
+) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+    let mut bytes = commitment.to_bytes()?;
+    let last = bytes.last_mut().expect("commitments have bytes");
+    let mut bits = last.to_bits_le()?;
+    // The bytes of a field element are canonical, and so the marked bit is a
+    // constant zero, which can be replaced without a constraint.
+    bits[ACCOUNT_LEAF_MARKER.trailing_zeros() as usize] = Boolean::TRUE;
+    *last = UInt8::from_bits_le(&bits);
+    Ok(bytes)
+}
+
+impl AccountInformationVar {
+    /// Compute which of the buckets delimited by `bounds` contains the balance of
+    /// `asset`.
+    ///
+    /// `bounds` must be strictly increasing, and bucket `i` contains the balances
+    /// in `bounds[i - 1]..bounds[i]`, where the first bucket starts at 0 and the
+    /// last bucket is unbounded. For example, the bounds `[100, 1000]` define the
+    /// buckets `<100`, `100..1000` and `>=1000`.
+    ///
+    /// Returns the index of the bucket, along with a flag indicating whether
+    /// `bounds` is strictly increasing. The index is only meaningful when the
+    /// flag is set.
+    #[tracing::instrument(target = "r1cs", skip(self, asset, bounds))]
+    pub fn balance_bucket(
+        &self,
+        asset: &AssetIdVar,
+        bounds: &[AmountVar],
+    ) -> Result<(UInt8<ConstraintF>, Boolean<ConstraintF>), SynthesisError> {
+        assert!(bounds.len() < 256, "too many buckets");
+        let balance = self.balances.get(asset)?;
+        // Since the bounds are sorted, the index of the bucket is the number of
+        // bounds that the balance is above.
+        let mut index = FpVar::zero();
+        for bound in bounds {
+            index += FpVar::from(bound.is_le(&balance)?);
+        }
+        // The index is less than 256, so its top bits are 0.
+        let index = UInt8::from_bits_le(&index.to_bits_le()?[..8]);
+
+        let mut sorted = Boolean::TRUE;
+        for pair in bounds.windows(2) {
+            sorted = sorted.and(&pair[0].is_lt(&pair[1])?)?;
+        }
+        Ok((index, sorted))
+    }
+}
+
+impl AccountInformationVar {
+    /// Allocate the information of each account in `ids`, as found in `state`.
+    /// Returns a map from each identifier to the allocated information. Fails
+    /// with `SynthesisError::AssignmentMissing` if some account doesn't exist.
+    #[tracing::instrument(target = "r1cs", skip(cs, state, ids, mode))]
+    pub fn alloc_from_state(
+        cs: impl Into<Namespace<ConstraintF>>,
+        state: &State,
+        ids: &[AccountId],
+        mode: AllocationMode,
+    ) -> Result<HashMap<AccountId, Self>, SynthesisError> {
+        let cs = cs.into();
+        let mut infos = HashMap::with_capacity(ids.len());
+        for &id in ids {
+            if let Entry::Vacant(entry) = infos.entry(id) {
+                let info = Self::new_variable(
+                    cs.clone(),
+                    || {
+                        state
+                            .id_to_account_info
+                            .get(&id)
+                            .ok_or(SynthesisError::AssignmentMissing)
+                    },
+                    mode,
+                )?;
+                entry.insert(info);
+            }
+        }
+        Ok(infos)
+    }
+}
+
+impl R1CSVar<ConstraintF> for AccountInformationVar {
+    type Value = AccountInformation;
+
+    fn cs(&self) -> ConstraintSystemRef<ConstraintF> {
+        self.balances
+            .0
+            .iter()
+            .fold(self.public_key.cs(), |cs, balance| cs.or(balance.0.cs()))
+            .or(self.recovery_key.cs())
+            .or(self.nonce.cs())
+            .or(self.spent_today.0.cs())
+            .or(self.limit_epoch.cs())
+            .or(self.held.0.cs())
+            .or(self.delegated_spent.0.cs())
+            .or(self.balance_cap.0.cs())
+            .or(self.key_index.cs())
+            .or(self.last_credited_epoch.cs())
+            .or(self.allowlist.cs())
+            .or(self.metadata_hash.cs())
+            .or(self.signers.cs())
+            .or(self.threshold.cs())
+            .or(self.accepts_transfers.cs())
+    }
+
+    fn value(&self) -> Result<Self::Value, SynthesisError> {
+        let mut balances = Balances::default();
+        for (balance, var) in balances.0.iter_mut().zip(&self.balances.0) {
+            *balance = Amount(var.0.value()?);
+        }
+        Ok(AccountInformation {
+            public_key: self.public_key.value()?,
+            recovery_key: self.recovery_key.value()?,
+            balances,
+            nonce: self.nonce.value()?,
+            spent_today: Amount(self.spent_today.0.value()?),
+            limit_epoch: self.limit_epoch.value()?,
+            held: Amount(self.held.0.value()?),
+            delegated_spent: Amount(self.delegated_spent.0.value()?),
+            balance_cap: Amount(self.balance_cap.0.value()?),
+            key_index: self.key_index.value()?,
+            last_credited_epoch: self.last_credited_epoch.value()?,
+            allowlist: self.allowlist.value()?,
+            metadata_hash: self.metadata_hash.value()?,
+            signers: self.signers.value()?,
+            threshold: self.threshold.value()?,
+            accepts_transfers: self.accepts_transfers.value()?,
+        })
     }
 }
 
@@ -68,11 +435,445 @@ impl AllocVar<AccountInformation, ConstraintF> for AccountInformationVar {
             let cs = cs.into();
             let public_key =
                 AccountPublicKeyVar::new_variable(cs.clone(), || Ok(&info.public_key), mode)?;
-            let balance = AmountVar::new_variable(cs, || Ok(&info.balance), mode)?;
+            let recovery_key =
+                AccountPublicKeyVar::new_variable(cs.clone(), || Ok(&info.recovery_key), mode)?;
+            let balances = BalancesVar::new_variable(cs.clone(), || Ok(&info.balances), mode)?;
+            let nonce = UInt64::new_variable(cs.clone(), || Ok(info.nonce), mode)?;
+            let spent_today = AmountVar::new_variable(cs.clone(), || Ok(&info.spent_today), mode)?;
+            let limit_epoch = UInt64::new_variable(cs.clone(), || Ok(info.limit_epoch), mode)?;
+            let held = AmountVar::new_variable(cs.clone(), || Ok(&info.held), mode)?;
+            let delegated_spent =
+                AmountVar::new_variable(cs.clone(), || Ok(&info.delegated_spent), mode)?;
+            let balance_cap = AmountVar::new_variable(cs.clone(), || Ok(&info.balance_cap), mode)?;
+            let key_index = UInt64::new_variable(cs.clone(), || Ok(info.key_index), mode)?;
+            let last_credited_epoch =
+                UInt64::new_variable(cs.clone(), || Ok(info.last_credited_epoch), mode)?;
+            let allowlist = FpVar::new_variable(cs.clone(), || Ok(info.allowlist), mode)?;
+            let metadata_hash = FpVar::new_variable(cs.clone(), || Ok(info.metadata_hash), mode)?;
+            let signers = FpVar::new_variable(cs.clone(), || Ok(info.signers), mode)?;
+            let threshold = UInt8::new_variable(cs.clone(), || Ok(info.threshold), mode)?;
+            let accepts_transfers = Boolean::new_variable(cs, || Ok(info.accepts_transfers), mode)?;
             Ok(Self {
                 public_key,
-                balance,
+                recovery_key,
+                balances,
+                nonce,
+                spent_today,
+                limit_epoch,
+                held,
+                delegated_spent,
+                balance_cap,
+                key_index,
+                last_credited_epoch,
+                allowlist,
+                metadata_hash,
+                signers,
+                threshold,
+                accepts_transfers,
             })
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{AccRoot, Parameters, NUM_ASSETS};
+    use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+
+    #[test]
+    fn balance_bucket_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let (public_key, _) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let cases: &[(&[u64], u64, u8)] = &[
+            (&[100, 1000], 0, 0),
+            (&[100, 1000], 99, 0),
+            (&[100, 1000], 100, 1),
+            (&[100, 1000], 999, 1),
+            (&[100, 1000], 1000, 2),
+            (&[100, 1000], u64::MAX, 2),
+            (&[10], 5, 0),
+            (&[10], 10, 1),
+            (&[], 42, 0),
+        ];
+        for &(bounds, balance, expected) in cases {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            // The balance of the other assets doesn't matter.
+            let mut balances = Balances([Amount(u64::MAX); NUM_ASSETS]);
+            balances.set(AssetId(1), Amount(balance)).unwrap();
+            let info = AccountInformation {
+                balances,
+                ..AccountInformation::new(public_key)
+            };
+            let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+            let bounds = bounds
+                .iter()
+                .map(|b| AmountVar::new_constant(cs.clone(), Amount(*b)).unwrap())
+                .collect::<Vec<_>>();
+            let asset = AssetIdVar::new_constant(cs.clone(), AssetId(1)).unwrap();
+            let (index, sorted) = info.balance_bucket(&asset, &bounds).unwrap();
+            assert!(sorted.value().unwrap());
+            // The bucket index is declared as a public input.
+            let claimed = UInt8::new_input(cs.clone(), || Ok(expected)).unwrap();
+            index.enforce_equal(&claimed).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+
+            // Claiming a different bucket fails.
+            let wrong = UInt8::new_input(cs.clone(), || Ok(expected + 1)).unwrap();
+            index.enforce_equal(&wrong).unwrap();
+            assert!(!cs.is_satisfied().unwrap());
+        }
+
+        // Bounds that are not strictly increasing are flagged.
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let info = AccountInformation {
+            balances: Balances([Amount(500); NUM_ASSETS]),
+            ..AccountInformation::new(public_key)
+        };
+        let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+        let bounds = [1000, 100]
+            .iter()
+            .map(|b| AmountVar::new_constant(cs.clone(), Amount(*b)).unwrap())
+            .collect::<Vec<_>>();
+        let asset = AssetIdVar::new_constant(cs.clone(), AssetId::NATIVE).unwrap();
+        let (_, sorted) = info.balance_bucket(&asset, &bounds).unwrap();
+        assert!(!sorted.value().unwrap());
+    }
+
+    #[test]
+    fn from_handle_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let alice_id = AccountId::from_handle("alice@example.com", &pp).unwrap();
+
+        // Handles are normalized, ...
+        assert_eq!(
+            AccountId::from_handle("  Alice@Example.COM ", &pp),
+            Some(alice_id)
+        );
+        // ... but distinct handles map to distinct accounts.
+        assert_ne!(
+            AccountId::from_handle("bob@example.com", &pp),
+            Some(alice_id)
+        );
+        assert_eq!(
+            AccountId::from_handle(&"a".repeat(MAX_HANDLE_LEN + 1), &pp),
+            None
+        );
+
+        // The gadget computes the same mapping for a witnessed handle.
+        let prove_handle = |handle: &str, id| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let handle = UInt8::new_witness_vec(cs.clone(), handle.as_bytes()).unwrap();
+            let id = AccountIdVar::new_input(cs.clone(), || Ok(id)).unwrap();
+            AccountIdVar::from_handle(&params, &handle)
+                .unwrap()
+                .0
+                .enforce_equal(&id.0)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(prove_handle("alice@example.com", alice_id));
+        assert!(!prove_handle("bob@example.com", alice_id));
+    }
+
+    #[test]
+    fn commitment_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let proof = state.prove_account(alice_id).unwrap();
+        let info = proof.info;
+
+        // Tamper with each of the committed fields in turn.
+        let (other_public_key, _) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut other_balances = info.balances;
+        other_balances.set(AssetId(1), Amount(1)).unwrap();
+        let tampered = [
+            AccountInformation {
+                balances: other_balances,
+                ..info
+            },
+            AccountInformation { nonce: 1, ..info },
+            AccountInformation {
+                public_key: other_public_key,
+                ..info
+            },
+            AccountInformation {
+                recovery_key: other_public_key,
+                ..info
+            },
+            AccountInformation {
+                spent_today: Amount(1),
+                ..info
+            },
+            AccountInformation {
+                limit_epoch: 1,
+                ..info
+            },
+            AccountInformation {
+                held: Amount(1),
+                ..info
+            },
+            AccountInformation {
+                delegated_spent: Amount(1),
+                ..info
+            },
+            AccountInformation {
+                balance_cap: Amount(1),
+                ..info
+            },
+            AccountInformation {
+                key_index: 1,
+                ..info
+            },
+            AccountInformation {
+                last_credited_epoch: 1,
+                ..info
+            },
+            AccountInformation {
+                allowlist: 1u8.into(),
+                ..info
+            },
+            AccountInformation {
+                metadata_hash: 1u8.into(),
+                ..info
+            },
+            AccountInformation {
+                signers: 1u8.into(),
+                ..info
+            },
+            AccountInformation {
+                threshold: 1,
+                ..info
+            },
+            AccountInformation {
+                accepts_transfers: true,
+                ..info
+            },
+        ];
+
+        let mut commitments = Vec::new();
+        for (i, candidate) in std::iter::once(info).chain(tampered).enumerate() {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let info_var =
+                AccountInformationVar::new_witness(cs.clone(), || Ok(candidate)).unwrap();
+            let commitment = info_var.commitment(&params).unwrap();
+            assert_eq!(
+                commitment.value().unwrap(),
+                candidate.commitment(&pp.account_crh_params)
+            );
+            commitments.push(commitment.value().unwrap());
+
+            // Only the untampered information is a member of the tree.
+            let path = AccPathVar::new_witness(cs.clone(), || Ok(&proof.path)).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+            let is_member = path
+                .verify_membership(
+                    &params.leaf_crh_params,
+                    &params.two_to_one_crh_params,
+                    &root,
+                    &info_var.to_leaf(&params).unwrap().as_slice(),
+                )
+                .unwrap();
+            assert_eq!(is_member.value().unwrap(), i == 0);
+            assert!(cs.is_satisfied().unwrap());
+        }
+        commitments.sort();
+        commitments.dedup();
+        assert_eq!(commitments.len(), 1 + tampered.len());
+    }
+
+    #[test]
+    fn alloc_from_state_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(bob_id, AssetId(2), Amount(7))
+            .unwrap();
+
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let infos = AccountInformationVar::alloc_from_state(
+            cs.clone(),
+            &state,
+            &[alice_id, bob_id, alice_id],
+            AllocationMode::Witness,
+        )
+        .unwrap();
+        assert_eq!(infos.len(), 2);
+        for id in [alice_id, bob_id] {
+            assert_eq!(infos[&id].value().unwrap(), state.id_to_account_info[&id]);
+        }
+        assert!(cs.is_satisfied().unwrap());
+
+        // Accounts that don't exist can't be allocated.
+        let result = AccountInformationVar::alloc_from_state(
+            cs,
+            &state,
+            &[alice_id, AccountId(10)],
+            AllocationMode::Witness,
+        );
+        assert!(matches!(result, Err(SynthesisError::AssignmentMissing)));
+    }
+
+    #[test]
+    fn registration_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let id = state.next_available_account.unwrap();
+        let pre_root = state.root();
+        let path = state
+            .account_merkle_tree()
+            .generate_proof(id.0 as usize)
+            .unwrap();
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(alice_id, id);
+        let info = state.id_to_account_info[&id];
+
+        // Register `info`, in a tree whose root becomes `post_root`.
+        let register = |info: AccountInformation, post_root: AccRoot| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let path = AccPathVar::new_witness(cs.clone(), || Ok(&path)).unwrap();
+            let pre_root = AccRootVar::new_input(cs.clone(), || Ok(pre_root)).unwrap();
+            let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_root)).unwrap();
+            let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+            info.enforce_registration(&params, &path, &pre_root, &post_root)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(register(info, state.root()));
+
+        // An account created with a nonzero nonce is rejected, even though the
+        // roots are consistent with it.
+        let replayable = AccountInformation { nonce: 5, ..info };
+        let mut tree = state.account_merkle_tree().clone();
+        tree.update(id.0 as usize, &replayable.to_leaf(&pp))
+            .unwrap();
+        assert!(!register(replayable, tree.root()));
+    }
+
+    #[test]
+    fn blank_commitment_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let alice_info = state.id_to_account_info[&alice_id];
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+
+        // The leaf of a real account is marked as the native one is.
+        let info = AccountInformationVar::new_witness(cs.clone(), || Ok(alice_info)).unwrap();
+        let leaf = info.to_leaf(&params).unwrap();
+        assert_eq!(leaf.value().unwrap(), alice_info.to_leaf(&pp));
+
+        // Suppose that some account information committed to zero. Its leaf would
+        // still differ from the blank leaf of an empty slot, e.g., the one of an
+        // unregistered account, even once hashing pads it with zero bytes, ...
+        let commitment = FpVar::new_witness(cs.clone(), || Ok(ConstraintF::from(0u8))).unwrap();
+        let leaf = mark_commitment(&commitment).unwrap();
+        let blank = vec![0u8; <LeafHash as CRH>::INPUT_SIZE_BITS / 8];
+        let mut padded = leaf.value().unwrap();
+        padded.resize(blank.len(), 0);
+        assert_ne!(padded, blank);
+        // ... and so it can't pass for the leaf of an empty slot.
+        let path = state.account_merkle_tree().generate_proof(7).unwrap();
+        let path = AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+        let root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let is_member = path
+            .verify_membership(
+                &params.leaf_crh_params,
+                &params.two_to_one_crh_params,
+                &root,
+                &leaf.as_slice(),
+            )
+            .unwrap();
+        assert!(!is_member.value().unwrap());
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn packed_encoding_test() {
+        use ark_ff::{FpParameters, PrimeField};
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_accepts_transfers(alice_id, true).unwrap();
+        let info = state.id_to_account_info[&alice_id];
+        let proof = state.prove_account(alice_id).unwrap();
+
+        // The gadget produces the canonical encoding, which fills the input of
+        // the hash.
+        let canonical = info.to_bytes_le();
+        assert_eq!(canonical.len(), PACKED_ACCOUNT_INFO_LEN);
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let info_var = AccountInformationVar::new_witness(cs, || Ok(info)).unwrap();
+        assert_eq!(info_var.to_bytes_le().value().unwrap(), canonical);
+
+        // Other encodings of the same account that a lenient decoder would
+        // accept: without the end marker, with the signers unreduced, and with a
+        // nonzero byte other than one for `accepts_transfers`.
+        // The fields end with the signers, the threshold, and `accepts_transfers`,
+        // which is the last nonzero byte before the end marker.
+        let fields_len = canonical[..PACKED_ACCOUNT_INFO_LEN - 1]
+            .iter()
+            .rposition(|b| *b != 0)
+            .unwrap()
+            + 1;
+        let unmarked = canonical[..fields_len].to_vec();
+        let mut unreduced = canonical.clone();
+        let modulus = ark_ff::to_bytes![<ConstraintF as PrimeField>::Params::MODULUS].unwrap();
+        let signers_offset = fields_len - 34;
+        unreduced[signers_offset..signers_offset + 32].copy_from_slice(&modulus);
+        let mut wide_flag = canonical.clone();
+        wide_flag[fields_len - 1] = 0xff;
+
+        // Only the canonical encoding is a member of the tree.
+        for (i, encoding) in [canonical, unmarked, unreduced, wide_flag]
+            .iter()
+            .enumerate()
+        {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let bytes = UInt8::new_witness_vec(cs.clone(), encoding).unwrap();
+            let leaf = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+                &params.account_crh_params,
+                &bytes,
+            )
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+            let path = AccPathVar::new_witness(cs.clone(), || Ok(&proof.path)).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+            let is_member = path
+                .verify_membership(
+                    &params.leaf_crh_params,
+                    &params.two_to_one_crh_params,
+                    &root,
+                    &leaf.as_slice(),
+                )
+                .unwrap();
+            assert_eq!(is_member.value().unwrap(), i == 0);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::account::AccountInformationVar;
+use crate::ledger::{verify_membership_sorted, ParametersVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+
+impl TransactionVar {
+    /// Verify that the recipient is allowed by the sender, whose information
+    /// before the transaction is `pre_sender_acc_info`: either the sender has no
+    /// allowlist, or `allowlist_path` proves that the recipient is on it. This
+    /// always holds if the ledger doesn't enable allowlists.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pre_sender_acc_info))]
+    pub fn verify_allowlist(
+        &self,
+        parameters: &ParametersVar,
+        pre_sender_acc_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        if !parameters.allowlists {
+            return Ok(Boolean::TRUE);
+        }
+        let has_no_allowlist = pre_sender_acc_info.allowlist.is_eq(&FpVar::zero())?;
+        let is_on_allowlist = verify_membership_sorted(
+            parameters,
+            &pre_sender_acc_info.allowlist,
+            &self.allowlist_path,
+            &self.recipient,
+        )?;
+        is_on_allowlist.or(&has_no_allowlist)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::UnaryRollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::allowlist::allowlist;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn allowlist_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.allowlists = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let is_satisfied = |state: &State, tx: &Transaction| {
+            let rollup = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut state.clone(),
+                false,
+            )
+            .unwrap();
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // Without an allowlist, Alice may pay anybody.
+        let to_carol = Transaction::create(&pp, alice_id, carol_id, Amount(4), &alice_sk, &mut rng);
+        assert!(is_satisfied(&state, &to_carol));
+
+        // Once Alice only allows Bob, a payment to Bob must prove it, ...
+        let list = allowlist(&pp, &[bob_id]);
+        state.set_allowlist(alice_id, list.root()).unwrap();
+        let to_bob = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(!is_satisfied(&state, &to_bob));
+        let to_bob = to_bob.with_allowlist_proof(&list).unwrap();
+        assert!(is_satisfied(&state, &to_bob));
+        // ... and a payment to Carol is rejected, even with Bob's proof.
+        assert!(!is_satisfied(&state, &to_carol));
+        let mut forged = to_carol;
+        forged.allowlist_proof = to_bob.allowlist_proof;
+        assert!(!is_satisfied(&state, &forged));
+    }
+}
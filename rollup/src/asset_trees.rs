@@ -0,0 +1,180 @@
+use crate::ledger::{AccRootVar, AccountHashGadget, AssetIdVar, ParametersVar};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::asset_trees::ASSET_ROOTS_TAG;
+use ark_simple_payments::ledger::AccRoot;
+use std::borrow::Borrow;
+
+/// Commit to the roots `roots` of the account trees of the assets, as
+/// `asset_trees::asset_roots_digest` does.
+#[tracing::instrument(target = "r1cs", skip(parameters, roots))]
+pub fn asset_roots_digest(
+    parameters: &ParametersVar,
+    roots: &[AccRootVar],
+) -> Result<AccRootVar, SynthesisError> {
+    // The hashed input consists of (Tag || Root_0 || ... || Root_n)
+    let mut input = UInt8::constant_vec(ASSET_ROOTS_TAG);
+    for root in roots {
+        input.extend(root.to_bytes()?);
+    }
+    <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+        &parameters.account_crh_params,
+        &input,
+    )
+}
+
+/// The roots of the account tree of each asset, see `asset_trees`, while a batch
+/// updates them. The batch starts from `pre_roots` and must end at `post_roots`,
+/// while `roots` follows the batch, as each transaction updates the tree of its
+/// asset.
+#[derive(Clone)]
+pub struct AssetRootsVar {
+    /// The root of the tree of each asset before the batch.
+    pub pre_roots: Vec<AccRootVar>,
+    /// The root of the tree of each asset after the batch.
+    pub post_roots: Vec<AccRootVar>,
+    /// The current root of the tree of each asset.
+    pub roots: Vec<AccRootVar>,
+}
+
+impl AssetRootsVar {
+    /// Return the current root of the tree of `asset`, which the transactions of
+    /// `asset` are checked against, or zero if `asset` is not supported.
+    #[tracing::instrument(target = "r1cs", skip(self, asset))]
+    pub fn root_of(&self, asset: &AssetIdVar) -> Result<AccRootVar, SynthesisError> {
+        let mut root = FpVar::zero();
+        for (is_asset, current) in asset.selectors()?.iter().zip(&self.roots) {
+            root = is_asset.select(current, &root)?;
+        }
+        Ok(root)
+    }
+
+    /// Set the current root of the tree of `asset` to `root`, leaving the roots
+    /// of the other trees unchanged. Nothing changes if `asset` is not supported.
+    #[tracing::instrument(target = "r1cs", skip(self, asset, root))]
+    pub fn update(&mut self, asset: &AssetIdVar, root: &AccRootVar) -> Result<(), SynthesisError> {
+        for (is_asset, current) in asset.selectors()?.iter().zip(&mut self.roots) {
+            *current = is_asset.select(root, current)?;
+        }
+        Ok(())
+    }
+
+    /// Enforce that the trees are committed to by `initial_root` before the
+    /// batch and by `final_root` after it, and that the batch ends at
+    /// `post_roots`. This must be called once all the transactions of the batch
+    /// have updated `roots`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, initial_root, final_root))]
+    pub fn enforce_digests(
+        &self,
+        parameters: &ParametersVar,
+        initial_root: &AccRootVar,
+        final_root: &AccRootVar,
+    ) -> Result<(), SynthesisError> {
+        asset_roots_digest(parameters, &self.pre_roots)?.enforce_equal(initial_root)?;
+        asset_roots_digest(parameters, &self.post_roots)?.enforce_equal(final_root)?;
+        self.roots.enforce_equal(&self.post_roots)
+    }
+}
+
+impl AllocVar<Vec<(AccRoot, AccRoot)>, ConstraintF> for AssetRootsVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Vec<(AccRoot, AccRoot)>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        f().and_then(|roots| {
+            let (pre_roots, post_roots): (Vec<_>, Vec<_>) = roots.borrow().iter().copied().unzip();
+            let pre_roots = Vec::new_variable(cs.clone(), || Ok(&pre_roots[..]), mode)?;
+            let post_roots = Vec::new_variable(cs, || Ok(&post_roots[..]), mode)?;
+            Ok(Self {
+                roots: pre_roots.clone(),
+                pre_roots,
+                post_roots,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rollup::Rollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::asset_trees::asset_roots_digest;
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State, NUM_ASSETS};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn asset_trees_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        // Each asset has a tree of its own, in which Alice and Bob hold only the
+        // balance of that asset.
+        let mut states = vec![State::new(32, &pp); NUM_ASSETS];
+        let (alice_id, alice_pk, alice_sk) =
+            states[0].sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = states[0].sample_keys_and_register(&pp, &mut rng).unwrap();
+        for state in &mut states[1..] {
+            assert_eq!(state.register(alice_pk), Some(alice_id));
+            assert_eq!(state.register(bob_pk), Some(bob_id));
+        }
+        let usd = AssetId(1);
+        states[0].update_balance(alice_id, Amount(20)).unwrap();
+        states[1]
+            .update_asset_balance(alice_id, usd, Amount(20))
+            .unwrap();
+        let roots = states.iter().map(State::root).collect::<Vec<_>>();
+
+        // A transfer of USD only changes the root of the USD tree.
+        let tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            bob_id,
+            usd,
+            Amount(5),
+            Amount(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut post_states = states.clone();
+        let rollup =
+            Rollup::<1>::with_asset_trees(pp.clone(), &[tx], &mut post_states, true).unwrap();
+        let asset_roots = rollup.asset_roots.clone().unwrap();
+        for (asset, (pre_root, post_root)) in asset_roots.iter().enumerate() {
+            assert_eq!(*pre_root, roots[asset]);
+            assert_eq!(*post_root, post_states[asset].root());
+            assert_eq!(pre_root == post_root, asset != usd.0 as usize);
+        }
+        assert_eq!(rollup.initial_root, Some(asset_roots_digest(&pp, &roots)));
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        rollup.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // The transfer can't be proven against the native tree instead, ...
+        let mut bad_rollup = rollup.clone();
+        let native_root = roots[0];
+        bad_rollup.pre_tx_roots = Some(vec![native_root]);
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        bad_rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // ... nor can the batch claim that the native tree changed.
+        let mut bad_rollup = rollup;
+        let mut bad_roots = asset_roots;
+        bad_roots[0].1 = post_states[1].root();
+        bad_rollup.final_root = Some(asset_roots_digest(
+            &pp,
+            &bad_roots.iter().map(|(_, post)| *post).collect::<Vec<_>>(),
+        ));
+        bad_rollup.asset_roots = Some(bad_roots);
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        bad_rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+}
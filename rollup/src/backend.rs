@@ -0,0 +1,122 @@
+//! An abstraction over the proving system that batches are proven with, so that
+//! the same `Rollup` circuit can be proven with different SNARKs.
+
+use crate::ConstraintF;
+use ark_bls12_381::Bls12_381;
+use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_relations::r1cs::{ConstraintSynthesizer, SynthesisError};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+
+/// A proving system for circuits over `ConstraintF`.
+///
+/// The setup takes the circuit itself, so that both circuit-specific SNARKs,
+/// such as Groth16, and universal ones, such as Marlin or PlonK, which would
+/// sample a universal SRS large enough for the circuit and then index it, fit
+/// behind the same interface.
+pub trait ProvingBackend {
+    /// The key that proofs are created with.
+    type ProvingKey: Clone;
+    /// The key that proofs are verified with.
+    type VerifyingKey: Clone;
+    /// A proof that a circuit is satisfied.
+    type Proof: Clone;
+
+    /// Generate the keys for circuits with the same shape as `circuit`. Only
+    /// the shape of `circuit` matters, and not its witnesses.
+    fn setup<C: ConstraintSynthesizer<ConstraintF>, R: RngCore + CryptoRng>(
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), SynthesisError>;
+
+    /// Prove that `circuit` is satisfied with the proving key `pk`.
+    fn prove<C: ConstraintSynthesizer<ConstraintF>, R: RngCore + CryptoRng>(
+        pk: &Self::ProvingKey,
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<Self::Proof, SynthesisError>;
+
+    /// Verify the proof `proof` against the public inputs `public_input` with
+    /// the verifying key `vk`.
+    fn verify(
+        vk: &Self::VerifyingKey,
+        public_input: &[ConstraintF],
+        proof: &Self::Proof,
+    ) -> Result<bool, SynthesisError>;
+}
+
+/// The Groth16 proving system over BLS12-381, which needs a trusted setup for
+/// each circuit.
+pub struct Groth16Backend;
+
+impl ProvingBackend for Groth16Backend {
+    type ProvingKey = ProvingKey<Bls12_381>;
+    type VerifyingKey = VerifyingKey<Bls12_381>;
+    type Proof = Proof<Bls12_381>;
+
+    fn setup<C: ConstraintSynthesizer<ConstraintF>, R: RngCore + CryptoRng>(
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), SynthesisError> {
+        Groth16::<Bls12_381>::circuit_specific_setup(circuit, rng)
+    }
+
+    fn prove<C: ConstraintSynthesizer<ConstraintF>, R: RngCore + CryptoRng>(
+        pk: &Self::ProvingKey,
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<Self::Proof, SynthesisError> {
+        Groth16::<Bls12_381>::prove(pk, circuit, rng)
+    }
+
+    fn verify(
+        vk: &Self::VerifyingKey,
+        public_input: &[ConstraintF],
+        proof: &Self::Proof,
+    ) -> Result<bool, SynthesisError> {
+        Groth16::<Bls12_381>::verify(vk, public_input, proof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::inputs::BatchPublicInputs;
+    use crate::rollup::Rollup;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    /// Set up, prove and verify `rollup` with the backend `B`, and return
+    /// whether the proof verifies against the public inputs of the batch, and
+    /// whether it also verifies against those with the roots swapped.
+    fn prove_and_verify<B: ProvingBackend>(rollup: Rollup<1>) -> (bool, bool) {
+        let mut rng = ark_std::test_rng();
+        let (pk, vk) = B::setup(rollup.clone(), &mut rng).unwrap();
+        let public_input = BatchPublicInputs::from_batch(&rollup);
+        let proof = B::prove(&pk, rollup, &mut rng).unwrap();
+        let mut swapped = public_input.clone();
+        swapped.swap(0, 1);
+        (
+            B::verify(&vk, &public_input, &proof).unwrap(),
+            B::verify(&vk, &swapped, &proof).unwrap(),
+        )
+    }
+
+    #[test]
+    fn groth16_backend_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        assert_eq!(prove_and_verify::<Groth16Backend>(rollup), (true, false));
+    }
+}
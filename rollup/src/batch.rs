@@ -0,0 +1,137 @@
+use crate::account::{AccountPublicKeyVar, AccountSignatureVar};
+use crate::ledger::{AccPathVar, AccRootVar, AccountHashGadget, ParametersVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::batch::{BATCH_AUTHORIZATION_TAG, BATCH_OUTPUT_TAG};
+use ark_simple_payments::ledger::LeafDomain;
+
+/// The root of a batch Merkle tree.
+pub type BatchRootVar = AccRootVar;
+/// A membership proof for a given transaction in a batch.
+pub type BatchPathVar = AccPathVar;
+
+/// Check that `path` proves that `tx` is in the batch with root `batch_root`, as
+/// `batch::verify_tx_in_batch` does.
+#[tracing::instrument(target = "r1cs", skip(parameters, tx, path, batch_root))]
+pub fn verify_tx_in_batch(
+    parameters: &ParametersVar,
+    tx: &TransactionVar,
+    path: &BatchPathVar,
+    batch_root: &BatchRootVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let leaf =
+        parameters.personalize_leaf(LeafDomain::Transaction, tx.hash(parameters)?.to_bytes()?);
+    path.verify_membership(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        batch_root,
+        &leaf.as_slice(),
+    )
+}
+
+/// Commit to the output of a batch that leaves the account tree with root `root`
+/// at the epoch `epoch`, and extends the chain of batches whose last output
+/// commitment is `prev`, as `batch::batch_output_commitment` does.
+#[tracing::instrument(target = "r1cs", skip(parameters, prev, root, epoch))]
+pub fn batch_output_commitment(
+    parameters: &ParametersVar,
+    prev: &FpVar<ConstraintF>,
+    root: &AccRootVar,
+    epoch: &UInt64<ConstraintF>,
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    // The committed input consists of
+    // (Tag || PreviousCommitment || Root || Epoch)
+    let mut input = UInt8::constant_vec(BATCH_OUTPUT_TAG);
+    input.extend(prev.to_bytes()?);
+    input.extend(root.to_bytes()?);
+    input.extend(epoch.to_bytes()?);
+    <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+        &parameters.account_crh_params,
+        &input,
+    )
+}
+
+/// Check that `signature` is signed by `sequencer_key`, and authorizes the batch
+/// of `transactions` that takes the account tree from the root `initial_root`
+/// to the root `final_root`, as `batch::verify_batch_authorization` does.
+#[tracing::instrument(
+    target = "r1cs",
+    skip(
+        parameters,
+        sequencer_key,
+        initial_root,
+        final_root,
+        transactions,
+        signature
+    )
+)]
+pub fn verify_batch_authorization(
+    parameters: &ParametersVar,
+    sequencer_key: &AccountPublicKeyVar,
+    initial_root: &AccRootVar,
+    final_root: &AccRootVar,
+    transactions: &[TransactionVar],
+    signature: &AccountSignatureVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    // The signed message consists of
+    // (Tag || InitialRoot || FinalRoot || TxHash_1 || ... || TxHash_n)
+    let mut message = UInt8::constant_vec(BATCH_AUTHORIZATION_TAG);
+    message.extend(initial_root.to_bytes()?);
+    message.extend(final_root.to_bytes()?);
+    for tx in transactions {
+        message.extend(tx.hash(parameters)?.to_bytes()?);
+    }
+    parameters.verify_signature(sequencer_key, message, signature)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::batch::BatchTree;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn tx_in_batch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let txs = (1..=2)
+            .map(|amount| {
+                Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng)
+            })
+            .collect::<Vec<_>>();
+        let absent = Transaction::create(&pp, alice_id, bob_id, Amount(3), &alice_sk, &mut rng);
+        let tree = BatchTree::from_transactions(&pp, &txs);
+
+        // Check the inclusion of `tx` with the proof of the transaction at `index`.
+        let is_included = |tx: &Transaction, index: usize| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let parameters = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let batch_root = BatchRootVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+            let path =
+                BatchPathVar::new_witness(cs.clone(), || Ok(tree.generate_proof(index).unwrap()))
+                    .unwrap();
+            let tx = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            verify_tx_in_batch(&parameters, &tx, &path, &batch_root)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(is_included(&txs[0], 0));
+        assert!(is_included(&txs[1], 1));
+        // A transaction that is not in the batch has no valid proof.
+        assert!(!is_included(&absent, 0));
+        assert!(!is_included(&absent, 1));
+    }
+}
@@ -0,0 +1,305 @@
+//! Proving of batches that are too large to be proven as a single circuit. The
+//! batch is split into chunks of `NUM_TX` transactions, which are proven one
+//! after the other, so that only the witness of a single chunk is ever in memory.
+//! Each chunk starts from the root that the previous one ends with, and so the
+//! proofs of all chunks form a chain that proves the whole batch.
+
+use crate::onchain::{self, VerifierCost};
+use crate::rollup::Rollup;
+use crate::ConstraintF;
+use ark_bls12_381::{Bls12_381, G1Projective};
+use ark_crypto_primitives::Error;
+use ark_ec::{AffineCurve, PairingEngine, ProjectiveCurve};
+use ark_ff::{One, PrimeField, Zero};
+use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_relations::r1cs::SynthesisError;
+use ark_serialize::CanonicalSerialize;
+use ark_simple_payments::ledger::{AccRoot, BlockCommitment, Parameters, ParamsCommitment, State};
+use ark_simple_payments::transaction::Transaction;
+use ark_simple_payments::withdrawal::WithdrawalRoot;
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+use blake2::Blake2s;
+use digest::Digest;
+
+/// The proof of a chunk of a batch, along with the public inputs of the chunk.
+#[derive(Clone)]
+pub struct RollupProof {
+    /// The proof of the chunk.
+    pub proof: Proof<Bls12_381>,
+    /// The Merkle tree root before applying the chunk.
+    pub initial_root: AccRoot,
+    /// The Merkle tree root after applying the chunk.
+    pub final_root: AccRoot,
+    /// The root of the withdrawal tree of the chunk.
+    pub withdrawal_root: WithdrawalRoot,
+    /// The ledger epoch before applying the chunk.
+    pub pre_epoch: u64,
+    /// The ledger epoch after applying the chunk.
+    pub post_epoch: u64,
+    /// The commitment to the block at which the chunk is applied.
+    pub block_commitment: BlockCommitment,
+    /// The commitment to the ledger parameters of the chunk.
+    pub params_commitment: ParamsCommitment,
+}
+
+impl RollupProof {
+    /// The public inputs that the proof is verified against, in the order in
+    /// which the rollup circuit declares them.
+    pub fn public_input(&self) -> Vec<ConstraintF> {
+        vec![
+            self.initial_root,
+            self.final_root,
+            self.withdrawal_root,
+            ConstraintF::from(self.pre_epoch),
+            ConstraintF::from(self.post_epoch),
+            self.block_commitment,
+            self.params_commitment,
+        ]
+    }
+
+    /// Serialize the proof and its public inputs, in that order, as they are
+    /// passed to the contract that verifies the chunk. Points and field elements
+    /// are serialized in their compressed, canonical form. The number of public
+    /// inputs is fixed by the circuit, and so it is not encoded.
+    pub fn to_calldata(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.onchain_calldata_size());
+        self.proof.serialize(&mut bytes).unwrap();
+        for input in self.public_input() {
+            input.serialize(&mut bytes).unwrap();
+        }
+        bytes
+    }
+
+    /// The size in bytes of `to_calldata`, e.g., to estimate the cost of
+    /// submitting the chunk. This doesn't depend on the chunk itself, but only on
+    /// the number of public inputs of the circuit.
+    pub fn onchain_calldata_size(&self) -> usize {
+        let inputs = self.public_input();
+        self.proof.serialized_size() + inputs.iter().map(|i| i.serialized_size()).sum::<usize>()
+    }
+
+    /// Estimate the cost of verifying this chunk in a contract, see
+    /// `onchain::verifier_cost`. Like `onchain_calldata_size`, this only depends
+    /// on the number of public inputs of the circuit.
+    pub fn verifier_cost_estimate(&self) -> VerifierCost {
+        onchain::verifier_cost(self.public_input().len(), self.onchain_calldata_size())
+    }
+
+    /// Verify the proof of this chunk alone with the verifying key `vk`.
+    pub fn verify(&self, vk: &VerifyingKey<Bls12_381>) -> Result<bool, SynthesisError> {
+        Groth16::verify(vk, &self.public_input(), &self.proof)
+    }
+}
+
+impl<const NUM_TX: usize> Rollup<NUM_TX> {
+    /// Prove the batch `transactions` in chunks of `NUM_TX` transactions with the
+    /// proving key `pk`, starting from the ledger state `state`, which is updated
+    /// along the way. Each chunk is a batch of its own, and so it advances the
+    /// epoch.
+    ///
+    /// An error is returned if the number of transactions is not a multiple of
+    /// `NUM_TX`, or if any of the transactions is invalid.
+    pub fn prove_chunked<R: RngCore + CryptoRng>(
+        ledger_params: &Parameters,
+        transactions: &[Transaction],
+        state: &mut State,
+        pk: &ProvingKey<Bls12_381>,
+        rng: &mut R,
+    ) -> Result<Vec<RollupProof>, Error> {
+        if !transactions.len().is_multiple_of(NUM_TX) {
+            return Err("the batch must consist of whole chunks".into());
+        }
+        let mut proofs = Vec::with_capacity(transactions.len() / NUM_TX);
+        for chunk in transactions.chunks(NUM_TX) {
+            let rollup =
+                Self::with_state_and_transactions(ledger_params.clone(), chunk, state, true)
+                    .ok_or("invalid transaction")?;
+            let missing = || SynthesisError::AssignmentMissing;
+            let (initial_root, final_root) = (
+                rollup.initial_root.ok_or_else(missing)?,
+                rollup.final_root.ok_or_else(missing)?,
+            );
+            let withdrawal_root = rollup.withdrawal_root.ok_or_else(missing)?;
+            let (pre_epoch, post_epoch) = (
+                rollup.pre_epoch.ok_or_else(missing)?,
+                rollup.post_epoch.ok_or_else(missing)?,
+            );
+            let block_commitment = rollup.block_commitment.ok_or_else(missing)?;
+            let proof = Groth16::prove(pk, rollup, rng)?;
+            proofs.push(RollupProof {
+                proof,
+                initial_root,
+                final_root,
+                withdrawal_root,
+                pre_epoch,
+                post_epoch,
+                block_commitment,
+                params_commitment: ledger_params.commitment(),
+            });
+        }
+        Ok(proofs)
+    }
+}
+
+/// Verify the chain of proofs `proofs`, produced by `Rollup::prove_chunked`,
+/// with the verifying key `vk`. Besides each proof, this checks that every chunk
+/// continues from the root and the epoch that the previous chunk ends with, so
+/// that together they prove the transition from the initial root of the first
+/// chunk to the final root of the last one.
+pub fn verify_chunked(
+    vk: &VerifyingKey<Bls12_381>,
+    proofs: &[RollupProof],
+) -> Result<bool, SynthesisError> {
+    let continuous = proofs.windows(2).all(|pair| {
+        pair[1].initial_root == pair[0].final_root && pair[1].pre_epoch == pair[0].post_epoch
+    });
+    if !continuous {
+        return Ok(false);
+    }
+    for proof in proofs {
+        if !proof.verify(vk)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Verify the independent proofs `proofs` with the verifying key `vk` at once.
+/// Unlike `verify_chunked`, this doesn't check that the proofs form a chain.
+///
+/// Rather than checking the verification equation
+/// e(A, B) = e(alpha, beta) * e(L, gamma) * e(C, delta) of each proof, see
+/// `onchain::verifier_cost`, this checks a random linear combination of them,
+/// which takes a single pairing per proof on top of three shared ones. The
+/// coefficients are derived from a hash of all the proofs and their public
+/// inputs, so that they can't be chosen by whoever submits the proofs. Proofs
+/// that don't verify, including malformed ones, are rejected.
+pub fn verify_many(vk: &VerifyingKey<Bls12_381>, proofs: &[RollupProof]) -> bool {
+    let num_inputs = vk.gamma_abc_g1.len().saturating_sub(1);
+    if proofs
+        .iter()
+        .any(|proof| proof.public_input().len() != num_inputs)
+    {
+        return false;
+    }
+    let seed = Blake2s::digest(
+        &proofs
+            .iter()
+            .flat_map(RollupProof::to_calldata)
+            .collect::<Vec<_>>(),
+    );
+    let coefficient = |i: u64| {
+        ConstraintF::from_le_bytes_mod_order(&Blake2s::digest(
+            &[&seed[..], &i.to_le_bytes()].concat(),
+        ))
+    };
+
+    // Each proof contributes its own pairing e(r * A, B), while the others are
+    // shared, with their G1 points combined across the proofs.
+    let mut pairs = Vec::with_capacity(proofs.len() + 3);
+    let mut coefficient_sum = ConstraintF::zero();
+    let mut inputs = G1Projective::zero();
+    let mut c = G1Projective::zero();
+    for (i, proof) in proofs.iter().enumerate() {
+        let r = coefficient(i as u64);
+        let l = proof
+            .public_input()
+            .iter()
+            .zip(&vk.gamma_abc_g1[1..])
+            .fold(vk.gamma_abc_g1[0].into_projective(), |l, (input, base)| {
+                l + base.mul(input.into_repr())
+            });
+        pairs.push((
+            proof.proof.a.mul(r.into_repr()).into_affine().into(),
+            proof.proof.b.into(),
+        ));
+        coefficient_sum += r;
+        inputs += l.mul(r.into_repr());
+        c += proof.proof.c.mul(r.into_repr());
+    }
+    pairs.push((
+        (-vk.alpha_g1.mul(coefficient_sum.into_repr()))
+            .into_affine()
+            .into(),
+        vk.beta_g2.into(),
+    ));
+    pairs.push(((-inputs).into_affine().into(), vk.gamma_g2.into()));
+    pairs.push(((-c).into_affine().into(), vk.delta_g2.into()));
+    Bls12_381::product_of_pairings(&pairs).is_one()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments::ledger::Amount;
+
+    #[test]
+    fn prove_chunked_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(3), &alice_sk, &mut rng),
+        ];
+        let big_batch = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+
+        let circuit = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            &transactions[..1],
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        let (pk, vk) = Groth16::<Bls12_381>::circuit_specific_setup(circuit, &mut rng).unwrap();
+        let proofs =
+            Rollup::<1>::prove_chunked(&pp, &transactions, &mut state.clone(), &pk, &mut rng)
+                .unwrap();
+
+        // The chunks prove the same transition as the big batch, ...
+        assert_eq!(proofs.len(), 2);
+        assert!(verify_chunked(&vk, &proofs).unwrap());
+        assert_eq!(proofs[0].initial_root, big_batch.initial_root.unwrap());
+        assert_eq!(proofs[1].final_root, big_batch.final_root.unwrap());
+        // ... but only as a chain, in order.
+        let swapped = [proofs[1].clone(), proofs[0].clone()];
+        assert!(!verify_chunked(&vk, &swapped).unwrap());
+        // Verified at once as independent proofs, they may come in any order, ...
+        assert!(verify_many(&vk, &proofs));
+        assert!(verify_many(&vk, &swapped));
+        // ... but they fail together if any of them fails.
+        let mut tampered = proofs.clone();
+        tampered[1].final_root += ConstraintF::one();
+        assert!(!tampered[1].verify(&vk).unwrap());
+        assert!(!verify_many(&vk, &tampered));
+        // Batches must consist of whole chunks.
+        assert!(Rollup::<2>::prove_chunked(
+            &pp,
+            &transactions[..1],
+            &mut state.clone(),
+            &pk,
+            &mut rng
+        )
+        .is_err());
+
+        // The calldata of each chunk consists of a compressed proof of 192 bytes,
+        // and of the seven public inputs of 32 bytes each.
+        for proof in &proofs {
+            assert_eq!(proof.onchain_calldata_size(), proof.to_calldata().len());
+            assert_eq!(proof.onchain_calldata_size(), 192 + 7 * 32);
+        }
+    }
+}
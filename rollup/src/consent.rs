@@ -0,0 +1,93 @@
+use crate::account::AccountInformationVar;
+use crate::ledger::ParametersVar;
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::consent::CONSENT_TAG;
+
+impl TransactionVar {
+    /// Construct the message that the recipient signs to consent to the transfer.
+    fn consent_message(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The signed message consists of
+        // (Tag || TransactionMessage), where the transaction message is the one
+        // signed by the sender, before it is encoded.
+        let mut message = UInt8::constant_vec(CONSENT_TAG);
+        message.extend(self.raw_message()?);
+        Ok(message)
+    }
+
+    /// Verify that the recipient, whose information before the transaction is
+    /// `pre_recipient_acc_info`, accepts transfers or consented to this one. This
+    /// always holds if the ledger doesn't require consent, and for withdrawals
+    /// and self-transfers.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pre_recipient_acc_info))]
+    pub fn verify_consent(
+        &self,
+        parameters: &ParametersVar,
+        pre_recipient_acc_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        if !parameters.require_consent {
+            return Ok(Boolean::TRUE);
+        }
+        let consented = parameters.verify_signature(
+            &pre_recipient_acc_info.public_key,
+            self.consent_message()?,
+            &self.consent,
+        )?;
+        let is_self_transfer = self.sender.0.is_eq(&self.recipient.0)?;
+        consented
+            .or(&pre_recipient_acc_info.accepts_transfers)?
+            .or(&self.is_withdrawal()?)?
+            .or(&is_self_transfer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::UnaryRollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn consent_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.require_consent = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let is_satisfied = |state: &State, tx: &Transaction| {
+            let rollup = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut state.clone(),
+                false,
+            )
+            .unwrap();
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // Bob hasn't opted in, so a transfer without his consent is rejected, ...
+        let mut tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(!is_satisfied(&state, &tx));
+        // ... as is one with a consent that he didn't sign, ...
+        tx.consent = Some(tx.sign_consent(&pp, &alice_sk, &mut rng));
+        assert!(!is_satisfied(&state, &tx));
+        // ... while his consent is enough.
+        tx.consent = Some(tx.sign_consent(&pp, &bob_sk, &mut rng));
+        assert!(is_satisfied(&state, &tx));
+
+        // Once Bob opts in, he doesn't need to consent anymore.
+        tx.consent = None;
+        state.set_accepts_transfers(bob_id, true).unwrap();
+        assert!(is_satisfied(&state, &tx));
+    }
+}
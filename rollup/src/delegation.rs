@@ -0,0 +1,196 @@
+use crate::account::{AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar};
+use crate::ledger::{AmountVar, ParametersVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_r1cs_std::bits::{uint32::UInt32, uint64::UInt64};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::delegation::{SpendingAuthorization, DELEGATION_TAG};
+use ark_simple_payments::ledger::AssetId;
+use std::borrow::Borrow;
+
+/// The authorization of a delegate to spend from an account, see
+/// `SpendingAuthorization`, or a placeholder for transactions that are not
+/// delegated.
+pub struct SpendingAuthorizationVar {
+    /// Whether the transaction is delegated, i.e., whether this is a real
+    /// authorization rather than a placeholder.
+    pub is_present: Boolean<ConstraintF>,
+    /// The key that signs the transactions of the delegate.
+    pub delegate: AccountPublicKeyVar,
+    /// The maximum total amount that delegates may spend from the account.
+    pub cap: AmountVar,
+    /// The signature of the owner over the account, the delegate, and the cap.
+    pub signature: AccountSignatureVar,
+}
+
+impl AllocVar<Option<SpendingAuthorization>, ConstraintF> for SpendingAuthorizationVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Option<SpendingAuthorization>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|authorization| {
+            let authorization: &Option<_> = authorization.borrow();
+            let is_present =
+                Boolean::new_variable(cs.clone(), || Ok(authorization.is_some()), mode)?;
+            // Transactions that are not delegated are witnessed with a placeholder.
+            let authorization = authorization
+                .clone()
+                .unwrap_or_else(SpendingAuthorization::placeholder);
+            Ok(Self {
+                is_present,
+                delegate: AccountPublicKeyVar::new_variable(
+                    cs.clone(),
+                    || Ok(authorization.delegate),
+                    mode,
+                )?,
+                cap: AmountVar::new_variable(cs.clone(), || Ok(authorization.cap), mode)?,
+                signature: AccountSignatureVar::new_variable(
+                    cs,
+                    || Ok(&authorization.signature),
+                    mode,
+                )?,
+            })
+        })
+    }
+}
+
+impl TransactionVar {
+    /// Is the transaction sent by a delegate, as `Transaction::is_delegated`
+    /// checks?
+    fn is_delegated(&self, parameters: &ParametersVar) -> Boolean<ConstraintF> {
+        match parameters.delegation {
+            true => self.authorization.is_present.clone(),
+            false => Boolean::FALSE,
+        }
+    }
+
+    /// The key that must sign the transaction, whose sender has the information
+    /// `pre_sender_acc_info`, as `Transaction::signer_key` computes.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pre_sender_acc_info))]
+    pub fn signer_key(
+        &self,
+        parameters: &ParametersVar,
+        pre_sender_acc_info: &AccountInformationVar,
+    ) -> Result<AccountPublicKeyVar, SynthesisError> {
+        let signing_key = pre_sender_acc_info.signing_key(parameters)?;
+        if !parameters.delegation {
+            return Ok(signing_key);
+        }
+        self.authorization
+            .is_present
+            .select(&self.authorization.delegate, &signing_key)
+    }
+
+    /// Verify that a delegated transaction stays within its authorization, as
+    /// `Transaction::is_within_authorization` checks, and count its amount and
+    /// its fee towards the spending of the delegates in `post_sender_acc_info`,
+    /// the information of the sender after the transaction. This always holds
+    /// for transactions that are not delegated.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, parameters, pre_sender_acc_info, post_sender_acc_info)
+    )]
+    pub fn verify_delegation(
+        &self,
+        parameters: &ParametersVar,
+        pre_sender_acc_info: &AccountInformationVar,
+        post_sender_acc_info: &mut AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        if !parameters.delegation {
+            return Ok(Boolean::TRUE);
+        }
+        let is_delegated = self.is_delegated(parameters);
+        // The signed message consists of (Tag || OwnerAccId || Delegate || Cap).
+        let mut message = UInt8::constant_vec(DELEGATION_TAG);
+        message.extend(self.sender.to_bytes_le());
+        message.extend(self.authorization.delegate.to_bytes()?);
+        message.extend(self.authorization.cap.to_bytes_le());
+        let authorized = parameters.verify_signature(
+            &pre_sender_acc_info.public_key,
+            message,
+            &self.authorization.signature,
+        )?;
+        let is_native = self
+            .asset_id
+            .0
+            .is_eq(&UInt32::constant(AssetId::NATIVE.0))?;
+        // Only delegated transactions count towards the cap, and so the others
+        // add nothing.
+        let (cost, cost_fits) = self.amount.try_add(&self.fee)?;
+        let increment = is_delegated.select(&cost.0, &UInt64::constant(0))?;
+        let (spent, spent_fits) = pre_sender_acc_info
+            .delegated_spent
+            .try_add(&AmountVar(increment))?;
+        let within_cap = spent.is_le(&self.authorization.cap)?;
+        post_sender_acc_info.delegated_spent = spent;
+        Boolean::kary_and(&[authorized, is_native, cost_fits, spent_fits, within_cap])?
+            .or(&is_delegated.not())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::UnaryRollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::delegation::SpendingAuthorization;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn delegation_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.delegation = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Alice lets Dave spend up to 10 from her account.
+        let (dave_pk, dave_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let authorization =
+            SpendingAuthorization::sign(&pp, alice_id, dave_pk, Amount(10), &alice_sk, &mut rng);
+
+        let is_satisfied = |state: &State, tx: &Transaction| {
+            let rollup = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut state.clone(),
+                false,
+            )
+            .unwrap();
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        let delegated = |amount, rng: &mut _| {
+            Transaction::create(&pp, alice_id, bob_id, Amount(amount), &dave_sk, rng)
+                .with_authorization(authorization.clone())
+        };
+
+        // Dave's transaction within the cap is accepted, and its amount is
+        // counted towards the cap, ...
+        let tx = delegated(7, &mut rng);
+        assert!(is_satisfied(&state, &tx));
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert!(is_satisfied(&state, &delegated(3, &mut rng)));
+        // ... so that a transaction that exceeds the rest of it is rejected, ...
+        assert!(!is_satisfied(&state, &delegated(4, &mut rng)));
+        // ... as is one without the authorization of Alice.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(1), &dave_sk, &mut rng);
+        assert!(!is_satisfied(&state, &tx));
+        let forged =
+            SpendingAuthorization::sign(&pp, alice_id, dave_pk, Amount(100), &dave_sk, &mut rng);
+        assert!(!is_satisfied(&state, &tx.with_authorization(forged)));
+
+        // Alice's own transactions are not limited by the cap.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(12), &alice_sk, &mut rng);
+        assert!(is_satisfied(&state, &tx));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::account::{mark_commitment, AccountIdVar, AccountInformationVar};
+use crate::ledger::{AccountHashGadget, ParametersVar};
+use crate::transaction::{TransactionVar, TransactionWitnessVar};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::diff::{blank_diff_leaf, DIFF_TAG};
+
+/// The updates that a transaction makes to the leaves of its sender and of its
+/// recipient, as `LeafUpdate`. The leaves are listed sender first.
+pub struct LeafUpdateVar {
+    /// The sender, whose identifier is the position of its leaf.
+    pub sender: AccountIdVar,
+    /// The recipient, whose identifier is the position of its leaf.
+    pub recipient: AccountIdVar,
+    /// The leaves of the sender and of the recipient before the transaction.
+    pub old_leaves: [Vec<UInt8<ConstraintF>>; 2],
+    /// The leaves of the sender and of the recipient after the transaction.
+    pub new_leaves: [Vec<UInt8<ConstraintF>>; 2],
+}
+
+impl LeafUpdateVar {
+    /// Extend the diff commitment `commitment` with this update, as
+    /// `diff_commitment` does.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, commitment))]
+    pub fn commit(
+        &self,
+        parameters: &ParametersVar,
+        commitment: &FpVar<ConstraintF>,
+    ) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        // The committed input consists of
+        // (Tag || PreviousCommitment || Sender || Recipient || OldLeaves || NewLeaves)
+        let mut input = UInt8::constant_vec(DIFF_TAG);
+        input.extend(commitment.to_bytes()?);
+        input.extend([self.sender.0.clone(), self.recipient.0.clone()]);
+        input.extend(self.old_leaves.concat());
+        input.extend(self.new_leaves.concat());
+        <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )
+    }
+}
+
+impl TransactionVar {
+    /// Compute the updates that the transaction makes to the leaves of its
+    /// accounts, as `Transaction::leaf_update` does, given the information of
+    /// the sender and of the recipient after the transaction,
+    /// `post_sender_acc_info` and `post_recipient_acc_info`. The information
+    /// before the transaction is taken from `witness`.
+    ///
+    /// Also returns whether updating the accounts with the information after
+    /// the transaction leads to `witness.post_root`, see `update_accounts`,
+    /// which binds it to the update that `validate` checks. Withdrawals credit
+    /// nobody, and so the information of their recipient is ignored.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(
+            self,
+            parameters,
+            witness,
+            post_sender_acc_info,
+            post_recipient_acc_info
+        )
+    )]
+    pub fn leaf_update(
+        &self,
+        parameters: &ParametersVar,
+        witness: &TransactionWitnessVar,
+        post_sender_acc_info: &AccountInformationVar,
+        post_recipient_acc_info: &AccountInformationVar,
+    ) -> Result<(Boolean<ConstraintF>, LeafUpdateVar), SynthesisError> {
+        let is_withdrawal = self.is_withdrawal()?;
+        // The leaves of the recipient of a withdrawal are blank, i.e., zero bytes.
+        let blank = UInt8::constant_vec(&blank_diff_leaf());
+        let leaf = |info: &AccountInformationVar, is_blank: &Boolean<ConstraintF>| {
+            let marked = mark_commitment(&info.commitment(parameters)?)?;
+            blank
+                .iter()
+                .zip(&marked)
+                .map(|(blank, byte)| is_blank.select(blank, byte))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let update = LeafUpdateVar {
+            sender: self.sender.clone(),
+            recipient: self.recipient.clone(),
+            old_leaves: [
+                leaf(witness.sender.pre_info.0, &Boolean::FALSE)?,
+                leaf(witness.recipient.pre_info.0, &is_withdrawal)?,
+            ],
+            new_leaves: [
+                leaf(post_sender_acc_info, &Boolean::FALSE)?,
+                leaf(post_recipient_acc_info, &is_withdrawal)?,
+            ],
+        };
+
+        // The information is stored if updating the accounts with it leads to
+        // `witness.post_root`.
+        let (_, post_root) = self.update_accounts(
+            parameters,
+            witness,
+            post_sender_acc_info,
+            post_recipient_acc_info,
+        )?;
+        Ok((post_root.is_eq(witness.post_root.0)?, update))
+    }
+}
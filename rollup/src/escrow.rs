@@ -0,0 +1,328 @@
+use crate::account::{AccountIdVar, AccountInformationVar, AccountSignatureVar};
+use crate::ledger::{AccPathVar, AccRootVar, AmountVar, ParametersVar};
+use crate::ConstraintF;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::escrow::{Escrow, EscrowKind, ESCROW_TAG};
+use std::borrow::Borrow;
+
+/// An escrow operation, which locks or releases `amount` of the native asset
+/// held by `account`.
+pub struct EscrowVar {
+    /// The account whose funds are locked or released.
+    pub account: AccountIdVar,
+    /// Whether the funds are released rather than locked.
+    pub is_release: Boolean<ConstraintF>,
+    /// The amount of the native asset being locked or released.
+    pub amount: AmountVar,
+    /// The signature of `account` over the operation.
+    pub signature: AccountSignatureVar,
+}
+
+impl EscrowVar {
+    /// Construct the message that is signed to authorize the operation.
+    fn message(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The authorized message consists of
+        // (Tag || AccountId || Kind || Amount)
+        let mut message = UInt8::constant_vec(ESCROW_TAG);
+        message.extend(self.account.to_bytes_le());
+        message.extend(self.is_release.to_bytes()?);
+        message.extend(self.amount.to_bytes_le());
+        Ok(message)
+    }
+
+    /// Check that the operation is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the signature is valid with respect to the public key of
+    ///    the account.
+    /// 2. Verify that a lock doesn't hold more than the native balance of the
+    ///    account. A release can't release more than is held: otherwise, the
+    ///    constraint system is unsatisfiable.
+    /// 3. Verify that the account exists, and is updated accordingly.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(
+            self,
+            parameters,
+            pre_acc_info,
+            pre_path,
+            post_path,
+            pre_root,
+            post_root
+        )
+    )]
+    pub fn validate(
+        &self,
+        parameters: &ParametersVar,
+        pre_acc_info: &AccountInformationVar,
+        pre_path: &AccPathVar,
+        post_path: &AccPathVar,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let sig_verifies = parameters.verify_signature(
+            &pre_acc_info.public_key,
+            self.message()?,
+            &self.signature,
+        )?;
+
+        // Only one of the two operations takes effect, so that neither can fail
+        // on behalf of the other.
+        let zero = AmountVar(UInt64::constant(0));
+        let locked = AmountVar(self.is_release.select(&zero.0, &self.amount.0)?);
+        let released = AmountVar(self.is_release.select(&self.amount.0, &zero.0)?);
+        let mut post_acc_info = pre_acc_info.clone();
+        post_acc_info.held = pre_acc_info
+            .held
+            .checked_add(&locked)?
+            .checked_sub(&released)?;
+        post_acc_info.nonce = UInt64::addmany(&[pre_acc_info.nonce.clone(), UInt64::constant(1)])?;
+        let held_covered = post_acc_info.covers_held()?;
+
+        // Check that the account information is correct with respect to
+        // `pre_root` before the operation, and with respect to `post_root` after it.
+        let check_membership = |path: &AccPathVar, info: &AccountInformationVar, root| {
+            path.verify_membership(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                root,
+                &info.to_leaf(parameters)?.as_slice(),
+            )
+        };
+        let existed = check_membership(pre_path, pre_acc_info, pre_root)?;
+        let will_exist = check_membership(post_path, &post_acc_info, post_root)?;
+        let exists = existed.and(&will_exist)?;
+
+        exists.and(&sig_verifies)?.and(&held_covered)
+    }
+}
+
+impl AllocVar<Escrow, ConstraintF> for EscrowVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Escrow>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|escrow| {
+            let escrow: &Escrow = escrow.borrow();
+            let account = AccountIdVar::new_variable(cs.clone(), || Ok(&escrow.account), mode)?;
+            let is_release =
+                Boolean::new_variable(cs.clone(), || Ok(escrow.kind == EscrowKind::Release), mode)?;
+            let amount = AmountVar::new_variable(cs.clone(), || Ok(&escrow.amount), mode)?;
+            let signature =
+                AccountSignatureVar::new_variable(cs.clone(), || Ok(&escrow.signature), mode)?;
+            Ok(Self {
+                account,
+                is_release,
+                amount,
+                signature,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::AssetIdVar;
+    use crate::transaction::{
+        AccountWitnessVar, PostState, PreState, TransactionVar, TransactionWitnessVar,
+    };
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    /// Validate `escrow` in a fresh constraint system, using witnesses taken from
+    /// `state`, and return whether the constraint system is satisfied.
+    fn escrow_is_valid(pp: &Parameters, state: &State, escrow: &Escrow) -> bool {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let mut post_state = state.clone();
+        let _ = post_state.apply_escrow(pp, escrow);
+        let path = |state: &State| {
+            let path = state.prove_account(escrow.account).unwrap().path;
+            AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap()
+        };
+        let info = state.id_to_account_info[&escrow.account];
+        let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+        let params = ParametersVar::new_constant(cs.clone(), pp).unwrap();
+        let escrow_var = EscrowVar::new_witness(cs.clone(), || Ok(escrow)).unwrap();
+        let pre_root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_state.root())).unwrap();
+        escrow_var
+            .validate(
+                &params,
+                &info,
+                &path(state),
+                &path(&post_state),
+                &pre_root,
+                &post_root,
+            )
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    /// Validate `tx` in a fresh constraint system, using witnesses taken from
+    /// `state`, and return whether the constraint system is satisfied.
+    fn tx_is_valid(pp: &Parameters, state: &State, tx: &Transaction) -> bool {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let mut post_state = state.clone();
+        let _ = post_state.apply_transaction(pp, tx);
+        let path = |state: &State, id| {
+            let path = state.prove_account(id).unwrap().path;
+            AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap()
+        };
+        let info = |id| {
+            let info = state.id_to_account_info[&id];
+            AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap()
+        };
+        let params = ParametersVar::new_constant(cs.clone(), pp).unwrap();
+        let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+        let pre_root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_state.root())).unwrap();
+        let (sender_info, recipient_info) = (info(tx.sender), info(tx.recipient));
+        let (sender_pre_path, sender_post_path) =
+            (path(state, tx.sender), path(&post_state, tx.sender));
+        let (recipient_pre_path, recipient_post_path) =
+            (path(state, tx.recipient), path(&post_state, tx.recipient));
+        let witness = TransactionWitnessVar {
+            sender: AccountWitnessVar {
+                pre_info: PreState(&sender_info),
+                pre_path: PreState(&sender_pre_path),
+                post_path: PostState(&sender_post_path),
+            },
+            recipient: AccountWitnessVar {
+                pre_info: PreState(&recipient_info),
+                pre_path: PreState(&recipient_pre_path),
+                post_path: PostState(&recipient_post_path),
+            },
+            pre_root: PreState(&pre_root),
+            post_root: PostState(&post_root),
+        };
+        tx_var
+            .validate(&params, &witness, &UInt64::constant(state.epoch))
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn escrow_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice can't hold more than she has, or release what she doesn't hold.
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(11),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!escrow_is_valid(&pp, &state, &lock));
+        let release = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Release,
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!escrow_is_valid(&pp, &state, &release));
+        // Only Alice can hold her funds.
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(6),
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!escrow_is_valid(&pp, &state, &lock));
+
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(6),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(lock.validate(&pp, &state));
+        assert!(escrow_is_valid(&pp, &state, &lock));
+        state.apply_escrow(&pp, &lock).unwrap();
+
+        // Held funds can't be spent, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(!tx_is_valid(&pp, &state, &tx));
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(tx_is_valid(&pp, &state, &tx));
+
+        // ... until they are released.
+        let release = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Release,
+            Amount(6),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(escrow_is_valid(&pp, &state, &release));
+        state.apply_escrow(&pp, &release).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng);
+        assert!(tx_is_valid(&pp, &state, &tx));
+    }
+
+    #[test]
+    fn available_balance_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(10),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_escrow(&pp, &lock).unwrap();
+
+        // All of Alice's funds are held, and so nothing is available to her,
+        // although her balance isn't zero.
+        let info = state.id_to_account_info[&alice_id];
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let info_var = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+        for asset in [AssetId::NATIVE, AssetId(1)] {
+            let asset_var = AssetIdVar::new_witness(cs.clone(), || Ok(asset)).unwrap();
+            let available = info_var.available_balance(&asset_var).unwrap();
+            assert_eq!(
+                Some(Amount(available.0.value().unwrap())),
+                info.available_balance(asset)
+            );
+        }
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(info.balances.get(AssetId::NATIVE), Some(Amount(10)));
+        assert_eq!(info.available_balance(AssetId::NATIVE), Some(Amount(0)));
+
+        // Hence she can't spend any of them.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(1), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        assert!(!tx_is_valid(&pp, &state, &tx));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::account::{AccountIdVar, AccountInformationVar};
+use crate::ledger::{AccRootVar, AmountVar, AssetIdVar, ParametersVar, PositionedPathVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+
+impl TransactionVar {
+    /// Check whether the transaction pays the base fee `base_fee`, as
+    /// `Transaction::split_fee` does. No-op transactions are exempt, since they
+    /// only pad batches.
+    #[tracing::instrument(target = "r1cs", skip(self, base_fee))]
+    pub fn pays_base_fee(
+        &self,
+        base_fee: &AmountVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.fee.is_lt(base_fee)?.not().or(&self.is_noop()?)
+    }
+
+    /// Compute the priority fee of the transaction, i.e., whatever its fee pays
+    /// beyond the base fee `base_fee`, if `is_collected` is set, and zero
+    /// otherwise. Collected transactions must pay the base fee: otherwise, the
+    /// constraint system is unsatisfiable.
+    #[tracing::instrument(target = "r1cs", skip(self, base_fee, is_collected))]
+    pub fn priority_fee(
+        &self,
+        base_fee: &AmountVar,
+        is_collected: &Boolean<ConstraintF>,
+    ) -> Result<AmountVar, SynthesisError> {
+        // Transactions that are not collected pay exactly the base fee, so that
+        // the subtraction can't underflow for them.
+        AmountVar(is_collected.select(&self.fee.0, &base_fee.0)?).checked_sub(base_fee)
+    }
+}
+
+/// Credit the priority fees `priority_fees`, each in its asset, to the fee
+/// collector `collector`, as `State::collect_fees` does, and return the root of
+/// the account tree after the credit. `collector_info` is the information of the
+/// fee collector before the credit, and `path` proves it at the leaf of
+/// `collector` in the tree with root `root`.
+#[tracing::instrument(
+    target = "r1cs",
+    skip(parameters, root, collector, collector_info, path, priority_fees)
+)]
+pub fn collect_fees(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    collector: &AccountIdVar,
+    collector_info: &AccountInformationVar,
+    path: &PositionedPathVar,
+    priority_fees: &[(AssetIdVar, AmountVar)],
+) -> Result<AccRootVar, SynthesisError> {
+    let index = Boolean::le_bits_to_fp_var(&collector.0.to_bits_le()?)?;
+    path.verify_membership_at(
+        parameters,
+        root,
+        &collector_info.to_leaf(parameters)?,
+        &index,
+    )?
+    .enforce_equal(&Boolean::TRUE)?;
+    let mut post_info = collector_info.clone();
+    for (asset, fee) in priority_fees {
+        let balance = post_info.balances.get(asset)?.checked_add(fee)?;
+        post_info.balances = post_info.balances.with_balance(asset, &balance)?;
+    }
+    path.calculate_root(parameters, &post_info.to_leaf(parameters)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rollup::Rollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn fee_market_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, Amount(5)).unwrap();
+        let (sequencer_id, _sequencer_pk, _sequencer_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        pp.fee_collector = Some(sequencer_id);
+        state.base_fee = Amount(2);
+
+        let is_satisfied = |rollup: Rollup<2>| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // Alice pays Bob with a priority fee of 3, and Bob pays the sequencer,
+        // which only collects its fees at the end of the batch.
+        let txs = [
+            Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(5),
+                Amount(5),
+                &alice_sk,
+                &mut rng,
+            ),
+            Transaction::create_with_fee(
+                &pp,
+                bob_id,
+                sequencer_id,
+                Amount(1),
+                Amount(2),
+                &bob_sk,
+                &mut rng,
+            ),
+        ];
+        let mut temp_state = state.clone();
+        let rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &txs, &mut temp_state, true)
+                .unwrap();
+        assert!(is_satisfied(rollup));
+        let balance = |id| {
+            temp_state.id_to_account_info[&id]
+                .balances
+                .get(AssetId::NATIVE)
+        };
+        assert_eq!(balance(alice_id), Some(Amount(10)));
+        assert_eq!(balance(bob_id), Some(Amount(7)));
+        assert_eq!(balance(sequencer_id), Some(Amount(4)));
+
+        // A transaction underpaying the base fee is rejected, ...
+        let underpaying = [
+            txs[0].clone(),
+            Transaction::create_with_fee(
+                &pp,
+                bob_id,
+                sequencer_id,
+                Amount(1),
+                Amount(1),
+                &bob_sk,
+                &mut rng,
+            ),
+        ];
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &underpaying,
+            &mut state.clone(),
+            false,
+        )
+        .unwrap();
+        assert!(!is_satisfied(rollup));
+        // ... and so is a batch that claims a lower base fee than its
+        // transactions burned, since it credits the sequencer more than their
+        // priority fees.
+        let mut rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &txs, &mut state.clone(), true)
+                .unwrap();
+        rollup.base_fee = Some(Amount(1));
+        assert!(!is_satisfied(rollup));
+    }
+
+    #[test]
+    fn sequencer_id_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (sequencer_id, _sequencer_pk, _sequencer_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.base_fee = Amount(2);
+
+        let is_satisfied = |rollup: Rollup<1>| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        // Alice pays Bob with a priority fee of 1, under a ledger whose fees are
+        // collected by `collector`.
+        let batch = |collector, rng: &mut _| {
+            let mut pp = pp.clone();
+            pp.fee_collector = Some(collector);
+            let tx = Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(5),
+                Amount(3),
+                &alice_sk,
+                rng,
+            );
+            Rollup::<1>::with_state_and_transactions(pp, &[tx], &mut state.clone(), true).unwrap()
+        };
+
+        // A batch whose fees are collected by the sequencer is accepted, ...
+        let rollup = batch(sequencer_id, &mut rng)
+            .with_sequencer_id(sequencer_id)
+            .unwrap();
+        assert!(is_satisfied(rollup));
+        // ... while one paying its fees to another account is rejected natively,
+        // ...
+        assert!(batch(bob_id, &mut rng)
+            .with_sequencer_id(sequencer_id)
+            .is_none());
+        // ... and in the circuit.
+        let rollup = Rollup {
+            sequencer_id: Some(sequencer_id),
+            ..batch(bob_id, &mut rng)
+        };
+        assert!(!is_satisfied(rollup));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::account::{AccountInformationVar, AccountPublicKeyVar};
+use crate::ledger::{AccountHashGadget, ParametersVar};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::hd::DERIVE_KEY_TAG;
+
+/// Derive the public key at `index` from the master public key `master`, as
+/// `hd::derive_public_key` does.
+#[tracing::instrument(target = "r1cs", skip(parameters, master, index))]
+pub fn derive_public_key(
+    parameters: &ParametersVar,
+    master: &AccountPublicKeyVar,
+    index: &UInt64<ConstraintF>,
+) -> Result<AccountPublicKeyVar, SynthesisError> {
+    // The hashed input consists of
+    // (Tag || MasterPublicKey || Index)
+    let mut input = UInt8::constant_vec(DERIVE_KEY_TAG);
+    input.extend(master.to_bytes()?);
+    input.extend(index.to_bytes()?);
+    let hash = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+        &parameters.account_crh_params,
+        &input,
+    )?;
+    master.tweak(&parameters.sig_params, &hash.to_bits_le()?)
+}
+
+impl AccountInformationVar {
+    /// Return the index of the key that must authorize the next transaction of
+    /// this account.
+    pub fn next_key_index(&self) -> Result<UInt64<ConstraintF>, SynthesisError> {
+        UInt64::addmany(&[self.key_index.clone(), UInt64::constant(1)])
+    }
+
+    /// Return the key that must authorize the next transaction of this account:
+    /// the key derived at `next_key_index` if the ledger derives a fresh key per
+    /// transaction, and `public_key` otherwise.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters))]
+    pub fn signing_key(
+        &self,
+        parameters: &ParametersVar,
+    ) -> Result<AccountPublicKeyVar, SynthesisError> {
+        match parameters.hd_keys {
+            true => derive_public_key(parameters, &self.public_key, &self.next_key_index()?),
+            false => Ok(self.public_key.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::UnaryRollup;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::hd::{derive_public_key as derive_native, derive_secret_key};
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn derive_key_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.hd_keys = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // The gadget derives the same key as the native code.
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+        let master = AccountPublicKeyVar::new_witness(cs.clone(), || Ok(alice_pk)).unwrap();
+        let index = UInt64::new_witness(cs.clone(), || Ok(7)).unwrap();
+        let derived = derive_public_key(&params, &master, &index).unwrap();
+        assert_eq!(derived.value().unwrap(), derive_native(&pp, &alice_pk, 7));
+        assert!(cs.is_satisfied().unwrap());
+
+        let pay = |index, rng: &mut _| {
+            let sk = derive_secret_key(&pp, &alice_sk, index);
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &sk, rng)
+        };
+        let rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            pay(1, &mut rng),
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        let is_satisfied = |rollup: UnaryRollup| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        // The first transaction is authorized by the key at index 1, ...
+        assert!(is_satisfied(rollup.clone()));
+        // ... and not by the keys out of sequence, even with the same witnesses.
+        for index in [0, 2] {
+            let mut bad_rollup = rollup.clone();
+            bad_rollup.transaction = pay(index, &mut rng);
+            assert!(!is_satisfied(bad_rollup));
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! The public inputs of a batch, derived from the native batch in a single
+//! place, so that the prover and the verifier can't disagree on them.
+
+use crate::ledger::{AccountHashGadget, ParametersVar};
+use crate::rollup::Rollup;
+use crate::ConstraintF;
+use ark_bls12_381::Bls12_381;
+use ark_crypto_primitives::crh::{CRHGadget, CRH};
+use ark_ff::Zero;
+use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::ledger::{AccountHash, Parameters};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+
+/// The tag that separates the hashes of public inputs from other uses of
+/// `AccountHash`.
+pub const PUBLIC_INPUT_HASH_TAG: &[u8] = b"public-inputs";
+
+/// The number of public inputs that each step of `hash_inputs` absorbs, so
+/// that the input of each step fits in the input of `AccountHash`.
+const INPUTS_PER_STEP: usize = 4;
+
+/// The public inputs of a batch, in the order in which the rollup circuit
+/// declares them.
+pub struct BatchPublicInputs;
+
+impl BatchPublicInputs {
+    /// Derive the public inputs of `batch` from its native values. These are
+    /// the single hash of the inputs listed by `unhashed`, see
+    /// `public_input_hash`, if the batch hashes its public inputs, and these
+    /// inputs themselves otherwise, followed by the paths of the transactions,
+    /// see `path_inputs`, if they are public.
+    pub fn from_batch<const NUM_TX: usize>(batch: &Rollup<NUM_TX>) -> Vec<ConstraintF> {
+        let mut inputs = if batch.hash_public_inputs {
+            vec![public_input_hash(batch)]
+        } else {
+            Self::unhashed(batch)
+        };
+        if batch.public_paths {
+            inputs.extend(path_inputs(batch));
+        }
+        inputs
+    }
+
+    /// The public inputs of `batch` before they are hashed, if they are. These
+    /// are the initial, final and withdrawal roots, the epochs before and after
+    /// the batch, the block commitment and the parameters commitment, followed
+    /// by the optional inputs that the shape of the batch adds: the applied
+    /// flags, the untouched account, the fee asset, the total supply, the scale,
+    /// the roots of the set of processed transactions, the tree height, the
+    /// dust threshold, the diff commitment, the output commitment of the
+    /// previous batch, the base fee, the key of the sequencer, by its
+    /// coordinates, the identifier of the shard, the hash and the position of
+    /// the protected transaction, and the difficulty of the proofs of work.
+    /// Integers are declared bit by bit, as the circuit allocates them, and so
+    /// they take one input per bit, starting from the least significant one.
+    ///
+    /// Panics if `batch` lacks any of the roots, epochs or block commitment, or
+    /// its base fee if the ledger has a fee market.
+    pub fn unhashed<const NUM_TX: usize>(batch: &Rollup<NUM_TX>) -> Vec<ConstraintF> {
+        let mut inputs = vec![
+            batch.initial_root.expect("the batch has no initial root"),
+            batch.final_root.expect("the batch has no final root"),
+            batch
+                .withdrawal_root
+                .expect("the batch has no withdrawal root"),
+            ConstraintF::from(batch.pre_epoch.expect("the batch has no pre-batch epoch")),
+            ConstraintF::from(batch.post_epoch.expect("the batch has no post-batch epoch")),
+            batch
+                .block_commitment
+                .expect("the batch has no block commitment"),
+            batch.ledger_params.commitment(),
+        ];
+        if let Some(applied) = &batch.applied {
+            inputs.extend(applied.iter().map(|&applied| ConstraintF::from(applied)));
+        }
+        if let Some(id) = batch.untouched_account {
+            push_bits(&mut inputs, id.0.into(), 8);
+        }
+        if let Some(fee_asset) = batch.fee_asset {
+            push_bits(&mut inputs, fee_asset.0.into(), 32);
+        }
+        if let Some(supply) = batch.total_supply {
+            push_bits(&mut inputs, supply.0, 64);
+        }
+        if let Some(scale) = batch.scale {
+            push_bits(&mut inputs, scale.into(), 8);
+        }
+        if let Some((pre_root, post_root)) = batch.processed_roots {
+            inputs.extend([pre_root, post_root]);
+        }
+        if let Some(height) = batch.tree_height {
+            inputs.push(height.into());
+        }
+        if let Some(threshold) = batch.dust_threshold {
+            push_bits(&mut inputs, threshold.0, 64);
+        }
+        if let Some(commitment) = batch.diff_commitment {
+            inputs.push(commitment);
+        }
+        if let Some(commitment) = batch.prev_batch_commitment {
+            inputs.push(commitment);
+        }
+        if batch.ledger_params.fee_collector.is_some() {
+            let base_fee = batch.base_fee.expect("the batch has no base fee");
+            push_bits(&mut inputs, base_fee.0, 64);
+        }
+        if let Some(id) = batch.sequencer_id {
+            push_bits(&mut inputs, id.0.into(), 8);
+        }
+        if let Some(key) = batch.sequencer_key {
+            inputs.extend([key.x, key.y]);
+        }
+        if let Some(shard) = batch.shard {
+            push_bits(&mut inputs, shard.id.into(), 8);
+        }
+        if let Some((hash, position)) = batch.protected_tx {
+            inputs.extend([hash, position.into()]);
+        }
+        if let Some(difficulty) = batch.pow_difficulty {
+            push_bits(&mut inputs, difficulty.into(), 8);
+        }
+        inputs
+    }
+}
+
+/// Hash the public inputs of `batch`, as the circuit does if the batch hashes
+/// its public inputs, into the single public input of its proof.
+///
+/// Panics if `batch` lacks any of the roots, epochs or block commitment.
+pub fn public_input_hash<const NUM_TX: usize>(batch: &Rollup<NUM_TX>) -> ConstraintF {
+    hash_inputs(&batch.ledger_params, &BatchPublicInputs::unhashed(batch))
+}
+
+/// Hash the public inputs `inputs` into a single field element. The hash is a
+/// chain that starts from zero and absorbs `INPUTS_PER_STEP` inputs per step:
+/// H_i = H(Tag || H_{i-1} || Inputs_i)
+pub fn hash_inputs(parameters: &Parameters, inputs: &[ConstraintF]) -> ConstraintF {
+    inputs
+        .chunks(INPUTS_PER_STEP)
+        .fold(ConstraintF::zero(), |hash, chunk| {
+            let mut input = PUBLIC_INPUT_HASH_TAG.to_vec();
+            input.extend(ark_ff::to_bytes![hash, chunk.to_vec()].unwrap());
+            AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+        })
+}
+
+/// Hash the public inputs `inputs` into a single field element, as
+/// `hash_inputs` does.
+pub fn hash_input_vars(
+    parameters: &ParametersVar,
+    inputs: &[FpVar<ConstraintF>],
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    let mut hash = FpVar::zero();
+    for chunk in inputs.chunks(INPUTS_PER_STEP) {
+        let mut input = UInt8::constant_vec(PUBLIC_INPUT_HASH_TAG);
+        input.extend(hash.to_bytes()?);
+        for value in chunk {
+            input.extend(value.to_bytes()?);
+        }
+        hash = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )?;
+    }
+    Ok(hash)
+}
+
+/// The public inputs that the paths of the transactions of `batch` take if they
+/// are public: for each transaction, the paths of its sender before and after
+/// it, followed by those of its recipient. Each path takes the sibling of its
+/// leaf, the least significant bit of its leaf index, the other bits of the
+/// index from the most significant one, and the siblings of the other nodes on
+/// the path from the top down, as `AccPathVar` allocates them.
+///
+/// Panics if `batch` lacks any of the paths.
+pub fn path_inputs<const NUM_TX: usize>(batch: &Rollup<NUM_TX>) -> Vec<ConstraintF> {
+    let senders = batch
+        .sender_pre_tx_info_and_paths
+        .as_ref()
+        .zip(batch.sender_post_paths.as_ref())
+        .expect("the batch has no sender paths");
+    let recipients = batch
+        .recv_pre_tx_info_and_paths
+        .as_ref()
+        .zip(batch.recv_post_paths.as_ref())
+        .expect("the batch has no recipient paths");
+    let mut inputs = Vec::new();
+    for i in 0..NUM_TX {
+        for path in [
+            &senders.0[i].1,
+            &senders.1[i],
+            &recipients.0[i].1,
+            &recipients.1[i],
+        ] {
+            inputs.push(path.leaf_sibling_hash);
+            inputs.push(ConstraintF::from(path.leaf_index & 1 == 1));
+            inputs.extend(
+                (1..=path.auth_path.len())
+                    .rev()
+                    .map(|i| ConstraintF::from((path.leaf_index >> i) & 1 == 1)),
+            );
+            inputs.extend(&path.auth_path);
+        }
+    }
+    inputs
+}
+
+/// Append the `num_bits` least significant bits of `value` to `inputs`, one
+/// input per bit, starting from the least significant one.
+fn push_bits(inputs: &mut Vec<ConstraintF>, value: u64, num_bits: usize) {
+    inputs.extend((0..num_bits).map(|i| ConstraintF::from((value >> i) & 1)));
+}
+
+impl<const NUM_TX: usize> Rollup<NUM_TX> {
+    /// Prove this batch with the proving key `pk`. Returns the proof along with
+    /// the public inputs that it must be verified against.
+    pub fn prove<R: RngCore + CryptoRng>(
+        &self,
+        pk: &ProvingKey<Bls12_381>,
+        rng: &mut R,
+    ) -> Result<(Proof<Bls12_381>, Vec<ConstraintF>), SynthesisError> {
+        let proof = Groth16::prove(pk, self.clone(), rng)?;
+        Ok((proof, BatchPublicInputs::from_batch(self)))
+    }
+
+    /// Verify the proof `proof` of this batch with the verifying key `vk`. Only
+    /// the public values of the batch are used, and so the witnesses may be
+    /// missing.
+    pub fn verify(
+        &self,
+        vk: &VerifyingKey<Bls12_381>,
+        proof: &Proof<Bls12_381>,
+    ) -> Result<bool, SynthesisError> {
+        Groth16::verify(vk, &BatchPublicInputs::from_batch(self), proof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State};
+    use ark_simple_payments::processed::ProcessedSet;
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn from_batch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        // The batch declares every optional public input.
+        let batch = Rollup::<1>::with_state_and_transactions_skipping_invalid(
+            pp.clone(),
+            &[tx],
+            &mut state,
+        )
+        .unwrap();
+        let batch = Rollup {
+            untouched_account: Some(carol_id),
+            fee_asset: Some(AssetId::NATIVE),
+            total_supply: Some(Amount(20)),
+            scale: Some(0),
+            tree_height: Some(5),
+            dust_threshold: Some(Amount(1)),
+            pow_difficulty: Some(0),
+            ..batch
+        }
+        .with_processed_set(&mut ProcessedSet::new(&pp, 3))
+        .unwrap()
+        .with_diff_commitment()
+        .unwrap()
+        .with_sequencer_signature(&carol_sk, &mut rng)
+        .unwrap();
+
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(batch.clone(), &mut rng).unwrap();
+        let (proof, public_input) = batch.prove(&pk, &mut rng).unwrap();
+        assert!(batch.verify(&vk, &proof).unwrap());
+        assert!(Groth16::verify(&vk, &public_input, &proof).unwrap());
+        // The proof only verifies against the inputs in this order.
+        let mut permuted = public_input.clone();
+        permuted.swap(0, 1);
+        assert!(!Groth16::verify(&vk, &permuted, &proof).unwrap());
+        let mut permuted = public_input;
+        permuted.rotate_right(1);
+        assert!(!Groth16::verify(&vk, &permuted, &proof).unwrap());
+    }
+
+    #[test]
+    fn hashed_inputs_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let batch =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        let batch = Rollup {
+            total_supply: Some(Amount(20)),
+            tree_height: Some(5),
+            hash_public_inputs: true,
+            ..batch
+        };
+
+        // The proof has a single public input, which is the native hash.
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(batch.clone(), &mut rng).unwrap();
+        assert_eq!(vk.gamma_abc_g1.len(), 2);
+        let (proof, public_input) = batch.prove(&pk, &mut rng).unwrap();
+        assert_eq!(public_input, vec![public_input_hash(&batch)]);
+        assert!(Groth16::verify(&vk, &public_input, &proof).unwrap());
+        // The hash covers every public input.
+        let unhashed = BatchPublicInputs::unhashed(&batch);
+        for i in [0, unhashed.len() - 1] {
+            let mut tampered = unhashed.clone();
+            tampered[i] += ConstraintF::from(1u64);
+            let hash = hash_inputs(&pp, &tampered);
+            assert!(!Groth16::verify(&vk, &[hash], &proof).unwrap());
+        }
+    }
+
+    #[test]
+    fn path_inputs_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let batch =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        let batch = Rollup {
+            hash_public_inputs: true,
+            public_paths: true,
+            ..batch
+        };
+
+        // The hash of the other public inputs is followed by the four paths of
+        // the transaction.
+        let height = batch.sender_post_paths.as_ref().unwrap()[0].auth_path.len();
+        let paths = path_inputs(&batch);
+        assert_eq!(paths.len(), 4 * (2 + 2 * height));
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(batch.clone(), &mut rng).unwrap();
+        assert_eq!(vk.gamma_abc_g1.len(), 2 + paths.len());
+        let (proof, public_input) = batch.prove(&pk, &mut rng).unwrap();
+        assert_eq!(public_input[1..], paths[..]);
+        assert!(Groth16::verify(&vk, &public_input, &proof).unwrap());
+        // The proof doesn't verify against any other path, e.g., one at another
+        // leaf.
+        for i in [2, paths.len()] {
+            let mut tampered = public_input.clone();
+            tampered[i] += ConstraintF::from(1u64);
+            assert!(!Groth16::verify(&vk, &tampered, &proof).unwrap());
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::ledger::{AccRootVar, LeafHashGadget, LeafHashParamsVar};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::constraints::CRHGadget;
+use ark_crypto_primitives::crh::injective_map::{
+    constraints::{PedersenCRHCompressorGadget, TECompressorGadget},
+    TECompressor,
+};
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::kary::{KaryHash, KaryPath, KaryWindow};
+use std::borrow::Borrow;
+
+pub type KaryHashGadget<const ARITY: usize> = PedersenCRHCompressorGadget<
+    EdwardsProjective,
+    TECompressor,
+    KaryWindow<ARITY>,
+    EdwardsVar,
+    TECompressorGadget,
+>;
+
+/// The parameters of `KaryHashGadget`, which don't depend on the arity.
+pub type KaryHashParamsVar =
+    <KaryHashGadget<2> as CRHGadget<KaryHash<2>, ConstraintF>>::ParametersVar;
+
+/// A membership proof in a `KaryMerkleTree`, in which every node has `ARITY`
+/// children. Unlike an `AccPathVar`, which is always binary, each level of the
+/// path has `ARITY - 1` siblings, and so a tree with the same number of leaves
+/// has fewer levels.
+pub struct KaryPathVar<const ARITY: usize> {
+    /// For each level, from the leaf up, a flag for each of the `ARITY` positions
+    /// among its siblings, which is set for the position of the node on the path.
+    pub positions: Vec<Vec<Boolean<ConstraintF>>>,
+    /// The `ARITY - 1` siblings of the node on the path at each level, from the
+    /// leaf up, and from the leftmost one.
+    pub siblings: Vec<Vec<AccRootVar>>,
+}
+
+impl<const ARITY: usize> KaryPathVar<ARITY> {
+    /// Compute the root of the tree from `leaf`, the leaf at the end of the path.
+    /// The root is only meaningful if exactly one position is set at each level.
+    #[tracing::instrument(target = "r1cs", skip(self, leaf_crh_params, node_crh_params, leaf))]
+    pub fn calculate_root(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        node_crh_params: &KaryHashParamsVar,
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<AccRootVar, SynthesisError> {
+        let mut node =
+            <LeafHashGadget as CRHGadget<_, ConstraintF>>::evaluate(leaf_crh_params, leaf)?;
+        for (positions, siblings) in self.positions.iter().zip(&self.siblings) {
+            // The node takes its place among its siblings: the siblings before it
+            // keep their positions, while those after it move one to the right.
+            let mut bytes = Vec::with_capacity(ARITY * 32);
+            let mut is_before = Boolean::FALSE;
+            for (i, is_node) in positions.iter().enumerate() {
+                let after = &siblings[i.min(ARITY - 2)];
+                let before = &siblings[i.saturating_sub(1)];
+                let child = is_node.select(&node, &is_before.select(before, after)?)?;
+                bytes.extend(child.to_bytes()?);
+                is_before = is_before.or(is_node)?;
+            }
+            node = <KaryHashGadget<ARITY> as CRHGadget<_, ConstraintF>>::evaluate(
+                node_crh_params,
+                &bytes,
+            )?;
+        }
+        Ok(node)
+    }
+
+    /// Check that this path proves the membership of `leaf` in the tree with
+    /// root `root`.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, leaf_crh_params, node_crh_params, root, leaf)
+    )]
+    pub fn verify_membership(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        node_crh_params: &KaryHashParamsVar,
+        root: &AccRootVar,
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let mut is_member = self
+            .calculate_root(leaf_crh_params, node_crh_params, leaf)?
+            .is_eq(root)?;
+        // The node must be at exactly one position at each level.
+        for positions in &self.positions {
+            let set = positions.iter().fold(FpVar::zero(), |set, is_node| {
+                set + FpVar::from(is_node.clone())
+            });
+            is_member = is_member.and(&set.is_one()?)?;
+        }
+        Ok(is_member)
+    }
+}
+
+impl<const ARITY: usize> AllocVar<KaryPath<ARITY>, ConstraintF> for KaryPathVar<ARITY> {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<KaryPath<ARITY>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|path| {
+            let path = path.borrow();
+            let mut index = path.leaf_index;
+            let mut positions = Vec::with_capacity(path.siblings.len());
+            let mut siblings = Vec::with_capacity(path.siblings.len());
+            for level in &path.siblings {
+                let position = index % ARITY;
+                positions.push(
+                    (0..ARITY)
+                        .map(|i| Boolean::new_variable(cs.clone(), || Ok(i == position), mode))
+                        .collect::<Result<_, _>>()?,
+                );
+                siblings.push(
+                    level
+                        .iter()
+                        .map(|node| AccRootVar::new_variable(cs.clone(), || Ok(node), mode))
+                        .collect::<Result<_, _>>()?,
+                );
+                index /= ARITY;
+            }
+            Ok(Self {
+                positions,
+                siblings,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{AccPathVar, TwoToOneHashParamsVar};
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::kary::{sample_kary_hash_params, KaryMerkleTree};
+    use ark_simple_payments::ledger::{AccMerkleTree, Parameters};
+
+    #[test]
+    fn kary_path_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let node_crh_params = sample_kary_hash_params::<4, _>(&mut rng);
+        // Both trees have 16 leaves, which takes two levels in the 4-ary tree, and
+        // four in the binary one.
+        let leaves = (0u8..16).map(|i| vec![i; 4]).collect::<Vec<_>>();
+        let kary_tree =
+            KaryMerkleTree::<4>::new(&pp.leaf_crh_params, &node_crh_params, &leaves).unwrap();
+        let binary_tree =
+            AccMerkleTree::new(&pp.leaf_crh_params, &pp.two_to_one_crh_params, &leaves).unwrap();
+
+        // Verify the membership of `leaf` with the path of the leaf at `index` in
+        // the 4-ary tree, and return whether the constraint system is satisfied,
+        // along with its number of constraints.
+        let verify_kary = |index: usize, leaf: &[u8]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let leaf_params =
+                LeafHashParamsVar::new_constant(cs.clone(), &pp.leaf_crh_params).unwrap();
+            let node_params =
+                KaryHashParamsVar::new_constant(cs.clone(), &node_crh_params).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(kary_tree.root())).unwrap();
+            let path = kary_tree.generate_proof(index).unwrap();
+            let path = KaryPathVar::<4>::new_witness(cs.clone(), || Ok(path)).unwrap();
+            let leaf = UInt8::new_witness_vec(cs.clone(), leaf).unwrap();
+            path.verify_membership(&leaf_params, &node_params, &root, &leaf)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            (cs.is_satisfied().unwrap(), cs.num_constraints())
+        };
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert!(verify_kary(i, leaf).0);
+        }
+        // A path only proves the leaf at its own position.
+        assert!(!verify_kary(6, &leaves[7]).0);
+
+        // The binary tree of the same capacity needs twice as many levels, ...
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let leaf_params = LeafHashParamsVar::new_constant(cs.clone(), &pp.leaf_crh_params).unwrap();
+        let two_to_one_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &pp.two_to_one_crh_params).unwrap();
+        let root = AccRootVar::new_input(cs.clone(), || Ok(binary_tree.root())).unwrap();
+        let binary_path = binary_tree.generate_proof(6).unwrap();
+        assert_eq!(binary_path.auth_path.len() + 1, 2 * kary_tree.depth());
+        let path = AccPathVar::new_witness(cs.clone(), || Ok(binary_path)).unwrap();
+        let leaf = UInt8::new_witness_vec(cs.clone(), &leaves[6]).unwrap();
+        path.verify_membership(&leaf_params, &two_to_one_params, &root, &leaf.as_slice())
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        // ... but since Pedersen hashes cost constraints in proportion to their
+        // input, both paths hash as many bytes in total, and so they cost about
+        // the same. Arity only trades depth for the width of each level.
+        let (binary_constraints, kary_constraints) =
+            (cs.num_constraints(), verify_kary(6, &leaves[6]).1);
+        assert!(kary_constraints.abs_diff(binary_constraints) < binary_constraints / 100);
+    }
+}
@@ -0,0 +1,87 @@
+//! Generation and persistence of the Groth16 keys of a `Rollup`, so that the
+//! verifying key of a deployment can be published once and loaded by verifiers.
+
+use crate::rollup::Rollup;
+use ark_bls12_381::Bls12_381;
+use ark_groth16::{Groth16, ProvingKey, VerifyingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+use std::io::{Error, ErrorKind};
+
+impl<const NUM_TX: usize> Rollup<NUM_TX> {
+    /// Run the circuit-specific setup for batches of `NUM_TX` transactions, and
+    /// write the verifying key to the file at `path`, so that it can be loaded
+    /// with `load_vk`. Returns the proving key, which must be kept by the prover.
+    ///
+    /// Only the shape of this rollup matters, i.e., its ledger parameters and
+    /// whether it has an untouched account, and not the transactions in it.
+    /// These must still be set, though, since the witnesses are allocated even
+    /// during the setup.
+    pub fn setup_and_save_vk<R: RngCore + CryptoRng>(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        rng: &mut R,
+    ) -> std::io::Result<ProvingKey<Bls12_381>> {
+        let (pk, vk) = Groth16::<Bls12_381>::circuit_specific_setup(self.clone(), rng)
+            .map_err(Error::other)?;
+        let mut bytes = Vec::new();
+        vk.serialize(&mut bytes)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        std::fs::write(path, bytes)?;
+        Ok(pk)
+    }
+
+    /// Load the verifying key written by `setup_and_save_vk` from the file at
+    /// `path`.
+    pub fn load_vk(path: impl AsRef<std::path::Path>) -> std::io::Result<VerifyingKey<Bls12_381>> {
+        let bytes = std::fs::read(path)?;
+        VerifyingKey::deserialize(&bytes[..]).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::inputs::BatchPublicInputs;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn saved_vk_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let path = std::env::temp_dir().join(format!("rollup-vk-{}.bin", std::process::id()));
+
+        let batch = |amount, state: &mut State, rng: &mut _| {
+            let tx = Transaction::create(&pp, alice_id, bob_id, amount, &alice_sk, rng);
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], state, true).unwrap()
+        };
+
+        let pk = batch(Amount(1), &mut state.clone(), &mut rng)
+            .setup_and_save_vk(&path, &mut rng)
+            .unwrap();
+        let vk = Rollup::<1>::load_vk(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // A proof generated after the setup, for another batch, verifies with the
+        // loaded key.
+        let rollup = batch(Amount(5), &mut state, &mut rng);
+        let public_input = BatchPublicInputs::from_batch(&rollup);
+        let proof = Groth16::prove(&pk, rollup, &mut rng).unwrap();
+        assert!(Groth16::verify(&vk, &public_input, &proof).unwrap());
+        // A truncated key can't be loaded.
+        let mut bytes = Vec::new();
+        vk.serialize(&mut bytes).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(Rollup::<1>::load_vk(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,12 @@
+use crate::account::{
+    AccountIdVar, AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar,
+};
+use crate::signature::TxSignatureScheme;
 use crate::ConstraintF;
+use ark_crypto_primitives::commitment::{
+    constraints::CommitmentGadget,
+    pedersen::constraints::{CommGadget, RandomnessVar},
+};
 use ark_crypto_primitives::crh::injective_map::constraints::{
     PedersenCRHCompressorGadget, TECompressorGadget,
 };
@@ -8,12 +16,23 @@ use ark_crypto_primitives::crh::{
 };
 use ark_crypto_primitives::merkle_tree::constraints::PathVar;
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
-use ark_r1cs_std::bits::uint64::UInt64;
+use ark_ff::PrimeField;
+use ark_r1cs_std::bits::{uint32::UInt32, uint64::UInt64};
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
+use ark_simple_payments::account::{AccountId, AccountInformation};
 use ark_simple_payments::ledger::*;
-use ark_simple_payments::signature::schnorr::constraints::ParametersVar as SchnorrParamsVar;
+use ark_simple_payments::random_oracle::sha256::constraints::{
+    ParametersVar as Sha256ParamsVar, ROGadget as Sha256Gadget,
+};
+use ark_simple_payments::random_oracle::RandomOracleGadget;
+use ark_simple_payments::signature::schnorr::{
+    constraints::ParametersVar as SchnorrParamsVar, Schnorr, SchnorrPoint,
+};
+use ark_simple_payments::withdrawal::WITHDRAWAL_ACCOUNT;
 use std::borrow::Borrow;
+use std::convert::TryFrom;
 
 /// Represents transaction amounts and account balances.
 #[derive(Clone, Debug)]
@@ -27,6 +46,17 @@ impl AmountVar {
 
     #[tracing::instrument(target = "r1cs", skip(self, other))]
     pub fn checked_add(&self, other: &Self) -> Result<Self, SynthesisError> {
+        let (result, no_overflow) = self.try_add(other)?;
+        no_overflow.enforce_equal(&Boolean::TRUE)?;
+        Ok(result)
+    }
+
+    /// Add `other` to `self`, like `checked_add`, but rather than enforcing that
+    /// the sum doesn't overflow, return whether it doesn't, along with the sum,
+    /// which wraps around if it does. This lets invalid transactions be skipped
+    /// instead of making the whole constraint system unsatisfiable.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn try_add(&self, other: &Self) -> Result<(Self, Boolean<ConstraintF>), SynthesisError> {
         // To do a checked add, we add two uint64's directly.
         // We also check for overflow, by casting them to field elements,
         // adding the field element representation
@@ -39,19 +69,29 @@ impl AmountVar {
         let other_fe = Boolean::le_bits_to_fp_var(&other_bits)?;
         let res_fe = self_fe + other_fe;
         let res_bz = res_fe.to_bytes()?;
-        // Ensure 65th bit is 0
-        // implies 8th word (0-indexed) is 0
-        res_bz[8].enforce_equal(&UInt8::<ConstraintF>::constant(0))?;
+        // The 65th bit is 0
+        // iff the 8th word (0-indexed) is 0
+        let no_overflow = res_bz[8].is_eq(&UInt8::<ConstraintF>::constant(0))?;
         // Add sum
         let result = UInt64::addmany(&[self.0.clone(), other.0.clone()])?;
-        Ok(AmountVar(result))
+        Ok((AmountVar(result), no_overflow))
     }
 
     #[tracing::instrument(target = "r1cs", skip(self, other))]
     pub fn checked_sub(&self, other: &Self) -> Result<Self, SynthesisError> {
+        let (result, no_underflow) = self.try_sub(other)?;
+        no_underflow.enforce_equal(&Boolean::TRUE)?;
+        Ok(result)
+    }
+
+    /// Subtract `other` from `self`, like `checked_sub`, but rather than
+    /// enforcing that the difference doesn't underflow, return whether it
+    /// doesn't, along with the difference, which is meaningless if it does.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn try_sub(&self, other: &Self) -> Result<(Self, Boolean<ConstraintF>), SynthesisError> {
         // To do a checked sub, we convert the uints to a field element.
         // We do the sub on the field element.
-        // We then cast the field element to bits, and ensure the top bits are 0.
+        // We then cast the field element to bits, and check that the top bits are 0.
         // We then convert these bits to a field element
         // TODO: Demonstrate via circuit profiling if this needs optimization.
         let self_bits = self.0.to_bits_le();
@@ -60,11 +100,61 @@ impl AmountVar {
         let other_fe = Boolean::le_bits_to_fp_var(&other_bits)?;
         let res_fe = self_fe - other_fe;
         let res_bz = res_fe.to_bytes()?;
-        // Ensure top bit is 0
-        res_bz[res_bz.len() - 1].enforce_equal(&UInt8::<ConstraintF>::constant(0))?;
+        // Check that the top bit is 0
+        let no_underflow = res_bz[res_bz.len() - 1].is_eq(&UInt8::<ConstraintF>::constant(0))?;
         // Convert to UInt64
         let res = UInt64::from_bits_le(&res_fe.to_bits_le()?[..64]);
-        Ok(AmountVar(res))
+        Ok((AmountVar(res), no_underflow))
+    }
+
+    /// Check whether `self < other`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn is_lt(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // Both amounts are less than 2^64, so `self - other + 2^64` lies strictly
+        // between 0 and 2^65, and its 65th bit is set iff `self >= other`.
+        let self_fe = Boolean::le_bits_to_fp_var(&self.0.to_bits_le())?;
+        let other_fe = Boolean::le_bits_to_fp_var(&other.0.to_bits_le())?;
+        let offset = FpVar::constant(ConstraintF::from(u64::MAX)) + FpVar::one();
+        let res_bits = (self_fe - other_fe + offset).to_bits_le()?;
+        Ok(res_bits[64].not())
+    }
+
+    /// Check whether `self <= other`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn is_le(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        Ok(other.is_lt(self)?.not())
+    }
+
+    /// Enforce that `self` is a whole number of units of a token with `decimals`
+    /// decimals, i.e., a multiple of `Amount::unit(decimals)`. `decimals` must be
+    /// at most `MAX_DECIMALS`: otherwise, the constraint system is unsatisfiable.
+    #[tracing::instrument(target = "r1cs", skip(self, decimals))]
+    pub fn enforce_precision(&self, decimals: &FpVar<ConstraintF>) -> Result<(), SynthesisError> {
+        let cs = self.0.cs().or(decimals.cs());
+        // Select the unit implied by `decimals`, which must be one of the
+        // supported numbers of decimals.
+        let mut unit = FpVar::zero();
+        let mut is_supported = Vec::with_capacity(MAX_DECIMALS as usize + 1);
+        for d in 0..=MAX_DECIMALS {
+            let is_d = decimals.is_eq(&FpVar::constant(ConstraintF::from(d)))?;
+            let unit_d = FpVar::constant(ConstraintF::from(Amount::unit(d).unwrap().0));
+            unit = is_d.select(&unit_d, &unit)?;
+            is_supported.push(is_d);
+        }
+        Boolean::kary_or(&is_supported)?.enforce_equal(&Boolean::TRUE)?;
+        // The quotient is less than 2^64, and the unit is at most 10^MAX_DECIMALS,
+        // so their product can't wrap around the field.
+        let quotient = UInt64::new_witness(ark_relations::ns!(cs, "Quotient"), || {
+            let decimals = decimals.value()?;
+            let amount = self.0.value()?;
+            Ok((0..=MAX_DECIMALS)
+                .find(|d| ConstraintF::from(*d) == decimals)
+                .and_then(Amount::unit)
+                .map_or(0, |unit| amount / unit.0))
+        })?;
+        let quotient = Boolean::le_bits_to_fp_var(&quotient.to_bits_le())?;
+        let amount = Boolean::le_bits_to_fp_var(&self.0.to_bits_le())?;
+        (quotient * unit).enforce_equal(&amount)
     }
 }
 
@@ -79,6 +169,897 @@ impl AllocVar<Amount, ConstraintF> for AmountVar {
     }
 }
 
+/// A signed amount, such as the net change of a balance, which may be negative
+/// while intermediate credits and debits are combined. It is represented by its
+/// sign and its magnitude, which must fit in 64 bits.
+#[derive(Clone, Debug)]
+pub struct SignedAmountVar {
+    /// Whether the amount is negative. Zero may be represented with either sign.
+    pub is_negative: Boolean<ConstraintF>,
+    /// The absolute value of the amount.
+    pub magnitude: AmountVar,
+}
+
+impl SignedAmountVar {
+    /// The signed amount zero.
+    pub fn zero() -> Self {
+        Self::from_amount(&AmountVar(UInt64::constant(0)))
+    }
+
+    /// Convert the non-negative amount `amount` into a signed amount.
+    pub fn from_amount(amount: &AmountVar) -> Self {
+        Self {
+            is_negative: Boolean::FALSE,
+            magnitude: amount.clone(),
+        }
+    }
+
+    /// Convert the signed amount into a field element, so that negative amounts
+    /// are mapped to the negation of their magnitude.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    fn to_fp(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        let magnitude = Boolean::le_bits_to_fp_var(&self.magnitude.0.to_bits_le())?;
+        self.is_negative.select(&magnitude.negate()?, &magnitude)
+    }
+
+    /// Convert the field element `value` back into a signed amount. The
+    /// constraint system is unsatisfiable if the magnitude of `value` doesn't fit
+    /// in 64 bits.
+    #[tracing::instrument(target = "r1cs", skip(value))]
+    fn from_fp(value: &FpVar<ConstraintF>) -> Result<Self, SynthesisError> {
+        let cs = value.cs();
+        // Split the value into its sign and magnitude. If the magnitude doesn't
+        // fit in 64 bits, any split fails the check below.
+        let split = value.value().map(|value| {
+            let magnitude = |value: ConstraintF| {
+                let limbs = value.into_repr().0;
+                limbs[1..].iter().all(|&limb| limb == 0).then_some(limbs[0])
+            };
+            match (magnitude(value), magnitude(-value)) {
+                (Some(magnitude), _) => (false, magnitude),
+                (None, Some(magnitude)) => (true, magnitude),
+                (None, None) => (false, 0),
+            }
+        });
+        let mode = if cs.is_none() {
+            AllocationMode::Constant
+        } else {
+            AllocationMode::Witness
+        };
+        let signed = Self {
+            is_negative: Boolean::new_variable(cs.clone(), || split.map(|s| s.0), mode)?,
+            magnitude: AmountVar::new_variable(cs, || split.map(|s| Amount(s.1)), mode)?,
+        };
+        signed.to_fp()?.enforce_equal(value)?;
+        Ok(signed)
+    }
+
+    /// Add `other` to `self`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn add(&self, other: &Self) -> Result<Self, SynthesisError> {
+        Self::from_fp(&(self.to_fp()? + other.to_fp()?))
+    }
+
+    /// Subtract `other` from `self`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn sub(&self, other: &Self) -> Result<Self, SynthesisError> {
+        Self::from_fp(&(self.to_fp()? - other.to_fp()?))
+    }
+
+    /// Convert the signed amount back into an amount, enforcing that it is not
+    /// negative.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_amount(&self) -> Result<AmountVar, SynthesisError> {
+        let is_zero = self.magnitude.0.is_eq(&UInt64::constant(0))?;
+        self.is_negative
+            .and(&is_zero.not())?
+            .enforce_equal(&Boolean::FALSE)?;
+        Ok(self.magnitude.clone())
+    }
+}
+
+/// Asset identifier. The ledger tracks the assets `0..NUM_ASSETS`.
+#[derive(Clone, Debug)]
+pub struct AssetIdVar(pub UInt32<ConstraintF>);
+
+impl AssetIdVar {
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        self.0.to_bytes().unwrap()
+    }
+
+    /// Return a flag for each asset tracked by the ledger, indicating whether
+    /// `self` is that asset. At most one of the flags is set, and none of them
+    /// is set if `self` is not supported.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn selectors(&self) -> Result<Vec<Boolean<ConstraintF>>, SynthesisError> {
+        // Comparing field elements is much cheaper than comparing the bits.
+        let asset = Boolean::le_bits_to_fp_var(&self.0.to_bits_le())?;
+        (0..NUM_ASSETS)
+            .map(|i| asset.is_eq(&FpVar::constant(ConstraintF::from(i as u64))))
+            .collect()
+    }
+
+    /// Is this asset tracked by the ledger?
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn is_supported(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        Boolean::kary_or(&self.selectors()?)
+    }
+}
+
+impl AllocVar<AssetId, ConstraintF> for AssetIdVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AssetId>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        UInt32::new_variable(cs.into(), || f().map(|u| u.borrow().0), mode).map(Self)
+    }
+}
+
+/// The balances of an account, indexed by asset identifier.
+#[derive(Clone, Debug)]
+pub struct BalancesVar(pub Vec<AmountVar>);
+
+impl BalancesVar {
+    /// Convert the balances to bytes, in order of asset identifier.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        self.0.iter().flat_map(AmountVar::to_bytes_le).collect()
+    }
+
+    /// Return the balance of `asset`, or 0 if `asset` is not supported.
+    #[tracing::instrument(target = "r1cs", skip(self, asset))]
+    pub fn get(&self, asset: &AssetIdVar) -> Result<AmountVar, SynthesisError> {
+        let mut balance = UInt64::constant(0);
+        for (is_asset, amount) in asset.selectors()?.iter().zip(&self.0) {
+            balance = is_asset.select(&amount.0, &balance)?;
+        }
+        Ok(AmountVar(balance))
+    }
+
+    /// Return the balances obtained by setting the balance of `asset` to `amount`,
+    /// leaving the balances of the other assets unchanged. If `asset` is not
+    /// supported, no balance changes.
+    #[tracing::instrument(target = "r1cs", skip(self, asset, amount))]
+    pub fn with_balance(
+        &self,
+        asset: &AssetIdVar,
+        amount: &AmountVar,
+    ) -> Result<Self, SynthesisError> {
+        asset
+            .selectors()?
+            .iter()
+            .zip(&self.0)
+            .map(|(is_asset, balance)| is_asset.select(&amount.0, &balance.0).map(AmountVar))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl AllocVar<Balances, ConstraintF> for BalancesVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Balances>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|balances| {
+            balances
+                .borrow()
+                .0
+                .iter()
+                .map(|amount| AmountVar::new_variable(cs.clone(), || Ok(amount), mode))
+                .collect::<Result<_, _>>()
+                .map(Self)
+        })
+    }
+}
+
+/// Accumulates a running sum of amounts without overflowing.
+///
+/// Adding many `UInt64`s directly wraps around, and the overflow checks in
+/// `AmountVar::checked_add` cost a bit decomposition per addition. Instead, we
+/// accumulate the sum as a field element, which has plenty of headroom above
+/// 64 bits, and only range check the result once in `finalize`.
+#[derive(Clone, Debug)]
+pub struct AmountAccumulator {
+    sum: FpVar<ConstraintF>,
+    num_terms: u64,
+}
+
+impl AmountAccumulator {
+    /// The maximum number of amounts that can be accumulated. Since each amount is
+    /// less than 2^64, the sum is less than 2^96, which is far below the field
+    /// modulus, so the field element sum never wraps around.
+    pub const MAX_TERMS: u64 = 1 << 32;
+
+    pub fn new() -> Self {
+        Self {
+            sum: FpVar::zero(),
+            num_terms: 0,
+        }
+    }
+
+    /// Add `amount` to the running sum.
+    #[tracing::instrument(target = "r1cs", skip(self, amount))]
+    pub fn add(&mut self, amount: &AmountVar) -> Result<(), SynthesisError> {
+        assert!(
+            self.num_terms < Self::MAX_TERMS,
+            "too many amounts accumulated"
+        );
+        self.sum += Boolean::le_bits_to_fp_var(&amount.0.to_bits_le())?;
+        self.num_terms += 1;
+        Ok(())
+    }
+
+    /// Return the running sum as a field element, without any range checks.
+    pub fn sum(&self) -> &FpVar<ConstraintF> {
+        &self.sum
+    }
+
+    /// Range check the running sum, and convert it into an `AmountVar`.
+    /// This enforces that the sum fits in 64 bits.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn finalize(&self) -> Result<AmountVar, SynthesisError> {
+        // `to_bits_le` returns the unique (i.e., non-wrapping) bit decomposition
+        // of the sum, so we just need to check that the high bits are 0.
+        let bits = self.sum.to_bits_le()?;
+        for bit in &bits[64..] {
+            bit.enforce_equal(&Boolean::FALSE)?;
+        }
+        Ok(AmountVar(UInt64::from_bits_le(&bits[..64])))
+    }
+}
+
+impl Default for AmountAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decompose the leaf index of the account `id` into the position bits of its
+/// path in an account Merkle tree of height `height`.
+///
+/// This is the circuit equivalent of `ark_simple_payments::ledger::leaf_index`:
+/// account identifiers map directly to leaf indices. The bits are ordered from
+/// the top of the tree to the bottom, and `bits[i]` is `true` iff the `i`-th node
+/// on the path is a right child. Identifiers that don't fit in the tree make the
+/// constraint system unsatisfiable. A tree of height 0 has no leaves, and so it
+/// is rejected with `SynthesisError::Unsatisfiable`.
+#[tracing::instrument(target = "r1cs", skip(id))]
+pub fn leaf_index_bits(
+    id: &AccountIdVar,
+    height: usize,
+) -> Result<Vec<Boolean<ConstraintF>>, SynthesisError> {
+    let depth = height.checked_sub(1).ok_or(SynthesisError::Unsatisfiable)?;
+    let mut bits = id.0.to_bits_le()?;
+    if depth < bits.len() {
+        for bit in &bits[depth..] {
+            bit.enforce_equal(&Boolean::FALSE)?;
+        }
+    }
+    bits.resize(depth, Boolean::FALSE);
+    bits.reverse();
+    Ok(bits)
+}
+
+/// Enforce that `post_epoch` is the epoch directly following `pre_epoch`, so
+/// that batches are applied in order and cannot be replayed.
+#[tracing::instrument(target = "r1cs", skip(pre_epoch, post_epoch))]
+pub fn enforce_epoch_increment(
+    pre_epoch: &FpVar<ConstraintF>,
+    post_epoch: &FpVar<ConstraintF>,
+) -> Result<(), SynthesisError> {
+    post_epoch.enforce_equal(&(pre_epoch + FpVar::one()))
+}
+
+/// Convert `epoch` into a `UInt64`. This makes the constraint system unsatisfiable
+/// if `epoch` doesn't fit in 64 bits.
+#[tracing::instrument(target = "r1cs", skip(epoch))]
+pub fn epoch_to_uint64(epoch: &FpVar<ConstraintF>) -> Result<UInt64<ConstraintF>, SynthesisError> {
+    let bits = epoch.to_bits_le()?;
+    for bit in &bits[64..] {
+        bit.enforce_equal(&Boolean::FALSE)?;
+    }
+    Ok(UInt64::from_bits_le(&bits[..64]))
+}
+
+/// The number of transactions applied to the ledger during the epoch `epoch`.
+/// See `ark_simple_payments::ledger::TxCounter`.
+#[derive(Clone, Debug)]
+pub struct TxCounterVar {
+    /// The number of transactions applied during `epoch`.
+    pub count: UInt64<ConstraintF>,
+    /// The epoch during which `count` transactions were applied.
+    pub epoch: UInt64<ConstraintF>,
+}
+
+impl TxCounterVar {
+    /// Convert the counter to bytes. These bytes are the leaf storing the counter.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let mut bytes = self.count.to_bytes()?;
+        bytes.extend(self.epoch.to_bytes()?);
+        Ok(bytes)
+    }
+
+    /// Return the counter after applying `num_transactions` more transactions
+    /// during the epoch `epoch`, along with a flag indicating whether the count
+    /// stays within `max_per_epoch`.
+    #[tracing::instrument(target = "r1cs", skip(self, epoch, max_per_epoch))]
+    pub fn increment(
+        &self,
+        epoch: &UInt64<ConstraintF>,
+        num_transactions: u64,
+        max_per_epoch: &UInt64<ConstraintF>,
+    ) -> Result<(Self, Boolean<ConstraintF>), SynthesisError> {
+        // The count is reset when a new epoch starts. Counts are compared in the
+        // same way as amounts.
+        let same_epoch = self.epoch.is_eq(epoch)?;
+        let count = AmountVar(same_epoch.select(&self.count, &UInt64::constant(0))?)
+            .checked_add(&AmountVar(UInt64::constant(num_transactions)))?;
+        let within_limit = count.is_le(&AmountVar(max_per_epoch.clone()))?;
+        let counter = Self {
+            count: count.0,
+            epoch: epoch.clone(),
+        };
+        Ok((counter, within_limit))
+    }
+}
+
+impl AllocVar<TxCounter, ConstraintF> for TxCounterVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<TxCounter>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|counter| {
+            let counter = counter.borrow();
+            let count = UInt64::new_variable(cs.clone(), || Ok(counter.count), mode)?;
+            let epoch = UInt64::new_variable(cs, || Ok(counter.epoch), mode)?;
+            Ok(Self { count, epoch })
+        })
+    }
+}
+
+/// Compute the root of the Merkle tree whose leaves are `leaves`. This is the
+/// circuit equivalent of `MerkleTree::new(.., leaves).root()`, and so the number
+/// of leaves must be a power of two.
+#[tracing::instrument(target = "r1cs", skip(parameters, leaves))]
+pub fn merkle_root(
+    parameters: &ParametersVar,
+    leaves: &[Vec<UInt8<ConstraintF>>],
+) -> Result<AccRootVar, SynthesisError> {
+    assert!(
+        leaves.len() >= 2 && leaves.len().is_power_of_two(),
+        "the number of leaves must be a power of two"
+    );
+    let mut nodes = leaves
+        .iter()
+        .map(|leaf| {
+            <LeafHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+                &parameters.leaf_crh_params,
+                leaf,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| {
+                <TwoToOneHashGadget as TwoToOneCRHGadget<_, ConstraintF>>::evaluate(
+                    &parameters.two_to_one_crh_params,
+                    &pair[0].to_bytes()?,
+                    &pair[1].to_bytes()?,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    Ok(nodes.remove(0))
+}
+
+/// A membership proof in a `LayeredLedger`.
+pub struct LayeredPathVar {
+    /// The root of the bottom tree containing the leaf.
+    pub bottom_root: AccRootVar,
+    /// The membership proof of the leaf in its bottom tree.
+    pub bottom: AccPathVar,
+    /// The membership proof of `bottom_root` in the top tree.
+    pub top: AccPathVar,
+}
+
+impl AllocVar<LayeredPath, ConstraintF> for LayeredPathVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<LayeredPath>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|path| {
+            let path = path.borrow();
+            let bottom_root = AccRootVar::new_variable(cs.clone(), || Ok(path.bottom_root), mode)?;
+            let bottom = AccPathVar::new_variable(cs.clone(), || Ok(&path.bottom), mode)?;
+            let top = AccPathVar::new_variable(cs, || Ok(&path.top), mode)?;
+            Ok(Self {
+                bottom_root,
+                bottom,
+                top,
+            })
+        })
+    }
+}
+
+/// Check that `path` proves the membership of `leaf` in the layered ledger with
+/// root `root`: `leaf` must be in the bottom tree with root `path.bottom_root`,
+/// which must in turn be in the top tree. See `LayeredLedger`. As with any
+/// `AccPathVar`, the position of the leaf is part of the witness.
+#[tracing::instrument(target = "r1cs", skip(parameters, root, path, leaf))]
+pub fn verify_membership_layered(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    path: &LayeredPathVar,
+    leaf: &[UInt8<ConstraintF>],
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let in_bottom = path.bottom.verify_membership(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        &path.bottom_root,
+        &leaf,
+    )?;
+    let in_top = path.top.verify_membership(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        root,
+        &path.bottom_root.to_bytes()?.as_slice(),
+    )?;
+    in_bottom.and(&in_top)
+}
+
+/// Enforce that `path`, the native path that an `AccPathVar` is allocated
+/// from, belongs to a tree of height `height`. The length of an `AccPathVar` is
+/// fixed by the path it is allocated from, rather than by a variable, and so a
+/// prover could otherwise set up the circuit with shorter paths, i.e., with a
+/// smaller tree, without the verifier noticing.
+pub fn enforce_path_height(
+    height: &FpVar<ConstraintF>,
+    path: &AccPath,
+) -> Result<(), SynthesisError> {
+    // A tree of height `h` has `h - 1` levels above its leaves, of which the
+    // path holds the sibling of the leaf and the siblings of the `h - 2` inner
+    // nodes below the root.
+    let path_height = path.auth_path.len() as u64 + 2;
+    height.enforce_equal(&FpVar::constant(ConstraintF::from(path_height)))
+}
+
+/// An authentication path that, unlike an `AccPathVar`, exposes the position of
+/// its leaf, so that it can be constrained.
+pub struct PositionedPathVar {
+    /// The bits of the index of the leaf, from the least significant one.
+    pub index_bits: Vec<Boolean<ConstraintF>>,
+    /// The sibling of the leaf.
+    pub leaf_sibling: AccRootVar,
+    /// The siblings of the other nodes on the path, from the top down.
+    pub auth_path: Vec<AccRootVar>,
+}
+
+impl PositionedPathVar {
+    /// The index of the leaf.
+    pub fn leaf_index(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        Boolean::le_bits_to_fp_var(&self.index_bits)
+    }
+
+    /// Compute the root of the tree from `leaf`, the leaf at the end of the path.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, leaf))]
+    pub fn calculate_root(
+        &self,
+        parameters: &ParametersVar,
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<AccRootVar, SynthesisError> {
+        let mut node = <LeafHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.leaf_crh_params,
+            leaf,
+        )?;
+        // The siblings are ordered from the leaf up, starting with the leaf's own.
+        let siblings = std::iter::once(&self.leaf_sibling).chain(self.auth_path.iter().rev());
+        for (is_right, sibling) in self.index_bits.iter().zip(siblings) {
+            let left = is_right.select(sibling, &node)?;
+            let right = is_right.select(&node, sibling)?;
+            node = <TwoToOneHashGadget as TwoToOneCRHGadget<_, ConstraintF>>::evaluate(
+                &parameters.two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+        }
+        Ok(node)
+    }
+
+    /// Check that this path proves the membership of `leaf` in the tree with
+    /// root `root`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, root, leaf))]
+    pub fn verify_membership(
+        &self,
+        parameters: &ParametersVar,
+        root: &AccRootVar,
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.calculate_root(parameters, leaf)?.is_eq(root)
+    }
+
+    /// Check that this path proves the membership of `leaf` in the tree with
+    /// root `root`, at the leaf index `index`. Otherwise, a path to any other
+    /// leaf holding the same information would do.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, root, leaf, index))]
+    pub fn verify_membership_at(
+        &self,
+        parameters: &ParametersVar,
+        root: &AccRootVar,
+        leaf: &[UInt8<ConstraintF>],
+        index: &FpVar<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.leaf_index()?
+            .is_eq(index)?
+            .and(&self.verify_membership(parameters, root, leaf)?)
+    }
+}
+
+impl AllocVar<AccPath, ConstraintF> for PositionedPathVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AccPath>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|path| {
+            let path = path.borrow();
+            let index_bits = (0..=path.auth_path.len())
+                .map(|i| {
+                    Boolean::new_variable(cs.clone(), || Ok((path.leaf_index >> i) & 1 == 1), mode)
+                })
+                .collect::<Result<_, _>>()?;
+            let leaf_sibling =
+                AccRootVar::new_variable(cs.clone(), || Ok(path.leaf_sibling_hash), mode)?;
+            let auth_path = path
+                .auth_path
+                .iter()
+                .map(|node| AccRootVar::new_variable(cs.clone(), || Ok(node), mode))
+                .collect::<Result<_, _>>()?;
+            Ok(Self {
+                index_bits,
+                leaf_sibling,
+                auth_path,
+            })
+        })
+    }
+}
+
+/// A proof that an identifier is absent from a `SortedIdTree`.
+pub struct NonMembershipProofVar {
+    /// The largest key that is smaller than the identifier.
+    pub low_key: UInt64<ConstraintF>,
+    /// The membership proof of `low_key`.
+    pub low_path: PositionedPathVar,
+    /// The smallest key that is larger than the identifier.
+    pub high_key: UInt64<ConstraintF>,
+    /// The membership proof of `high_key`.
+    pub high_path: PositionedPathVar,
+}
+
+impl AllocVar<NonMembershipProof, ConstraintF> for NonMembershipProofVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<NonMembershipProof>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|proof| {
+            let proof = proof.borrow();
+            let low_key = UInt64::new_variable(cs.clone(), || Ok(proof.low_key), mode)?;
+            let low_path =
+                PositionedPathVar::new_variable(cs.clone(), || Ok(&proof.low_path), mode)?;
+            let high_key = UInt64::new_variable(cs.clone(), || Ok(proof.high_key), mode)?;
+            let high_path = PositionedPathVar::new_variable(cs, || Ok(&proof.high_path), mode)?;
+            Ok(Self {
+                low_key,
+                low_path,
+                high_key,
+                high_path,
+            })
+        })
+    }
+}
+
+/// Check that `proof` proves that `target_id` is absent from the `SortedIdTree`
+/// with root `root`: the keys of `proof` must be in adjacent leaves of the tree,
+/// and `target_id` must fall strictly between them.
+#[tracing::instrument(target = "r1cs", skip(parameters, root, proof, target_id))]
+pub fn verify_non_membership_sorted(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    proof: &NonMembershipProofVar,
+    target_id: &AccountIdVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let low_exists =
+        proof
+            .low_path
+            .verify_membership(parameters, root, &proof.low_key.to_bytes()?)?;
+    let high_exists =
+        proof
+            .high_path
+            .verify_membership(parameters, root, &proof.high_key.to_bytes()?)?;
+    let adjacent = proof
+        .high_path
+        .leaf_index()?
+        .is_eq(&(proof.low_path.leaf_index()? + FpVar::one()))?;
+    // Compare the keys as amounts, which are also 64-bit integers.
+    let mut target_bits = target_id.0.to_bits_le()?;
+    target_bits.resize(64, Boolean::FALSE);
+    let target = AmountVar(UInt64::from_bits_le(&target_bits));
+    let above_low = AmountVar(proof.low_key.clone()).is_lt(&target)?;
+    let below_high = target.is_lt(&AmountVar(proof.high_key.clone()))?;
+    Boolean::kary_and(&[low_exists, high_exists, adjacent, above_low, below_high])
+}
+
+/// Check that `path` proves that `target_id` is in the `SortedIdTree` with root
+/// `root`, i.e., that it leads to the leaf storing `target_id`.
+#[tracing::instrument(target = "r1cs", skip(parameters, root, path, target_id))]
+pub fn verify_membership_sorted(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    path: &PositionedPathVar,
+    target_id: &AccountIdVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    // Keys are stored as 64-bit integers.
+    let mut key = target_id.to_bytes_le();
+    key.resize(8, UInt8::constant(0));
+    path.verify_membership(parameters, root, &key)
+}
+
+/// Enforce that `accounts` are all the accounts in the account tree with root
+/// `root`, and return the sum of their balances, for each asset.
+///
+/// `accounts` must contain every leaf of the tree, in order: the `i`-th entry
+/// holds a flag indicating whether account `i` is registered, along with its
+/// information. The information of unregistered accounts is ignored, as their
+/// leaves are blank. The entry of `WITHDRAWAL_ACCOUNT` is ignored too: its leaf
+/// stores `tx_counter` instead of an account, and is left out of the sums.
+///
+/// Rather than checking the membership of each account separately, we recompute
+/// the root of the whole tree from `accounts`. This ensures that no account is
+/// left out or counted twice, but it does mean that the cost of this gadget is
+/// linear in the size of the tree.
+#[tracing::instrument(target = "r1cs", skip(parameters, root, accounts, tx_counter))]
+pub fn sum_balances(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    accounts: &[(Boolean<ConstraintF>, AccountInformationVar)],
+    tx_counter: &TxCounterVar,
+) -> Result<Vec<AmountVar>, SynthesisError> {
+    let mut totals = vec![AmountAccumulator::new(); NUM_ASSETS];
+    let mut leaves = Vec::with_capacity(accounts.len());
+    for (i, (is_registered, info)) in accounts.iter().enumerate() {
+        if i == WITHDRAWAL_ACCOUNT.0 as usize {
+            leaves.push(tx_counter.to_bytes_le()?);
+            continue;
+        }
+        let bytes = info.to_leaf(parameters)?;
+        let blank = vec![UInt8::constant(0); bytes.len()];
+        let leaf = bytes
+            .iter()
+            .zip(&blank)
+            .map(|(byte, zero)| is_registered.select(byte, zero))
+            .collect::<Result<Vec<_>, _>>()?;
+        leaves.push(leaf);
+        for (total, balance) in totals.iter_mut().zip(&info.balances.0) {
+            let balance = is_registered.select(&balance.0, &UInt64::constant(0))?;
+            total.add(&AmountVar(balance))?;
+        }
+    }
+    merkle_root(parameters, &leaves)?.enforce_equal(root)?;
+    totals.iter().map(AmountAccumulator::finalize).collect()
+}
+
+/// Enforce that the balances of all accounts in the account tree with root `root`
+/// sum up to `supply`, for each asset. See `sum_balances` for the layout of
+/// `accounts` and `tx_counter`.
+#[tracing::instrument(target = "r1cs", skip(parameters, root, accounts, tx_counter, supply))]
+pub fn prove_total_supply(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    accounts: &[(Boolean<ConstraintF>, AccountInformationVar)],
+    tx_counter: &TxCounterVar,
+    supply: &BalancesVar,
+) -> Result<(), SynthesisError> {
+    let totals = sum_balances(parameters, root, accounts, tx_counter)?;
+    for (total, supply) in totals.iter().zip(&supply.0) {
+        total.0.enforce_equal(&supply.0)?;
+    }
+    Ok(())
+}
+
+/// Enforce that the native balances of all accounts in the account tree with
+/// root `root` sum up to `reserves`, the amount that the custodian of the rollup
+/// holds on L1. See `sum_balances` for the layout of `accounts` and `tx_counter`.
+///
+/// Like `prove_total_supply`, this hashes every leaf of the tree, and so it is
+/// only practical for small trees: each leaf costs about 12,000 constraints, and
+/// so a tree with `2^20` leaves takes over ten billion.
+#[tracing::instrument(
+    target = "r1cs",
+    skip(parameters, root, accounts, tx_counter, reserves)
+)]
+pub fn prove_solvency(
+    parameters: &ParametersVar,
+    root: &AccRootVar,
+    accounts: &[(Boolean<ConstraintF>, AccountInformationVar)],
+    tx_counter: &TxCounterVar,
+    reserves: &AmountVar,
+) -> Result<(), SynthesisError> {
+    let totals = sum_balances(parameters, root, accounts, tx_counter)?;
+    totals[AssetId::NATIVE.0 as usize]
+        .0
+        .enforce_equal(&reserves.0)
+}
+
+/// A circuit proving that the balances of all accounts in a ledger sum up to
+/// its total supply, for each asset.
+pub struct TotalSupply {
+    /// The ledger parameters.
+    pub ledger_params: Parameters,
+    /// The root of the account Merkle tree.
+    pub root: AccRoot,
+    /// The claimed total supply of each asset in the ledger.
+    pub supply: Balances,
+    /// The information of every account in the tree, or `None` for the leaves
+    /// that don't hold an account.
+    pub accounts: Vec<Option<AccountInformation>>,
+    /// The transaction counter, stored in the leaf of `WITHDRAWAL_ACCOUNT`.
+    pub tx_counter: TxCounter,
+}
+
+impl TotalSupply {
+    pub fn with_state(ledger_params: Parameters, state: &State) -> Self {
+        let num_leaves: usize = 1 << (state.account_merkle_tree().height() - 1);
+        // Leaves past the range of `AccountId` can never hold an account.
+        let accounts = (0..num_leaves)
+            .map(|i| {
+                let id = AccountId(u8::try_from(i).ok()?);
+                state.id_to_account_info.get(&id).copied()
+            })
+            .collect();
+        Self {
+            ledger_params,
+            root: state.root(),
+            supply: state.total_supply().expect("total supply overflows"),
+            accounts,
+            tx_counter: state.tx_counter,
+        }
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for TotalSupply {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Declare the parameters as constants.
+        let ledger_params = ParametersVar::new_constant(
+            ark_relations::ns!(cs, "Ledger parameters"),
+            &self.ledger_params,
+        )?;
+        // Declare the root and the total supply as public inputs.
+        let root = AccRootVar::new_input(ark_relations::ns!(cs, "Root"), || Ok(self.root))?;
+        let supply =
+            BalancesVar::new_input(ark_relations::ns!(cs, "Total supply"), || Ok(self.supply))?;
+        // Declare every account as a witness. Unregistered accounts use a
+        // placeholder, which is ignored by `prove_total_supply`.
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|info| {
+                let is_registered =
+                    Boolean::new_witness(ark_relations::ns!(cs, "Is registered"), || {
+                        Ok(info.is_some())
+                    })?;
+                let info = AccountInformationVar::new_witness(
+                    ark_relations::ns!(cs, "Account Info"),
+                    || Ok(info.unwrap_or(AccountInformation::new(Default::default()))),
+                )?;
+                Ok((is_registered, info))
+            })
+            .collect::<Result<Vec<_>, SynthesisError>>()?;
+        let tx_counter =
+            TxCounterVar::new_witness(
+                ark_relations::ns!(cs, "Tx Counter"),
+                || Ok(self.tx_counter),
+            )?;
+        prove_total_supply(&ledger_params, &root, &accounts, &tx_counter, &supply)
+    }
+}
+
+/// A circuit proving that the reserves that the custodian of a ledger holds on
+/// L1 are exactly the native balances of all accounts in the ledger.
+pub struct Solvency {
+    /// The ledger parameters.
+    pub ledger_params: Parameters,
+    /// The root of the account Merkle tree.
+    pub root: AccRoot,
+    /// The reserves committed on L1, in the native asset.
+    pub reserves: Amount,
+    /// The information of every account in the tree, or `None` for the leaves
+    /// that don't hold an account.
+    pub accounts: Vec<Option<AccountInformation>>,
+    /// The transaction counter, stored in the leaf of `WITHDRAWAL_ACCOUNT`.
+    pub tx_counter: TxCounter,
+}
+
+impl Solvency {
+    pub fn with_state(ledger_params: Parameters, state: &State, reserves: Amount) -> Self {
+        let TotalSupply {
+            ledger_params,
+            root,
+            accounts,
+            tx_counter,
+            ..
+        } = TotalSupply::with_state(ledger_params, state);
+        Self {
+            ledger_params,
+            root,
+            reserves,
+            accounts,
+            tx_counter,
+        }
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for Solvency {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Declare the parameters as constants.
+        let ledger_params = ParametersVar::new_constant(
+            ark_relations::ns!(cs, "Ledger parameters"),
+            &self.ledger_params,
+        )?;
+        // Declare the root and the reserves as public inputs.
+        let root = AccRootVar::new_input(ark_relations::ns!(cs, "Root"), || Ok(self.root))?;
+        let reserves =
+            AmountVar::new_input(ark_relations::ns!(cs, "Reserves"), || Ok(self.reserves))?;
+        // Declare every account as a witness, as in `TotalSupply`.
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|info| {
+                let is_registered =
+                    Boolean::new_witness(ark_relations::ns!(cs, "Is registered"), || {
+                        Ok(info.is_some())
+                    })?;
+                let info = AccountInformationVar::new_witness(
+                    ark_relations::ns!(cs, "Account Info"),
+                    || Ok(info.unwrap_or(AccountInformation::new(Default::default()))),
+                )?;
+                Ok((is_registered, info))
+            })
+            .collect::<Result<Vec<_>, SynthesisError>>()?;
+        let tx_counter =
+            TxCounterVar::new_witness(
+                ark_relations::ns!(cs, "Tx Counter"),
+                || Ok(self.tx_counter),
+            )?;
+        prove_solvency(&ledger_params, &root, &accounts, &tx_counter, &reserves)
+    }
+}
+
 pub type TwoToOneHashGadget = PedersenCRHCompressorGadget<
     EdwardsProjective,
     TECompressor,
@@ -95,40 +1076,654 @@ pub type LeafHashGadget = PedersenCRHCompressorGadget<
     TECompressorGadget,
 >;
 
+pub type AccountHashGadget = PedersenCRHCompressorGadget<
+    EdwardsProjective,
+    TECompressor,
+    AccountWindow,
+    EdwardsVar,
+    TECompressorGadget,
+>;
+
+pub type AmountCommitmentGadget = CommGadget<EdwardsProjective, EdwardsVar, AmountCommitmentWindow>;
+
 pub type AccRootVar =
     <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::OutputVar;
 pub type AccPathVar = PathVar<MerkleConfig, LeafHashGadget, TwoToOneHashGadget, ConstraintF>;
 pub type LeafHashParamsVar = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::ParametersVar;
+pub type AccountHashParamsVar =
+    <AccountHashGadget as CRHGadget<AccountHash, ConstraintF>>::ParametersVar;
 pub type TwoToOneHashParamsVar =
     <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::ParametersVar;
+pub type AmountCommitmentParamsVar = <AmountCommitmentGadget as CommitmentGadget<
+    AmountCommitmentScheme,
+    ConstraintF,
+>>::ParametersVar;
+/// A commitment to an amount.
+pub type AmountCommitmentVar =
+    <AmountCommitmentGadget as CommitmentGadget<AmountCommitmentScheme, ConstraintF>>::OutputVar;
+/// The blinding factor of a commitment to an amount.
+pub type AmountBlindingVar = RandomnessVar<ConstraintF>;
 
 /// The parameters that are used in transaction creation and validation.
+///
+/// The parameters must be allocated as constants, with
+/// `ParametersVar::new_constant`, so that they are embedded in the circuit
+/// rather than chosen by the prover. Other modes are rejected.
 pub struct ParametersVar {
     pub sig_params: SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
     pub leaf_crh_params: LeafHashParamsVar,
+    /// The parameters used to commit to the information of each account.
+    pub account_crh_params: AccountHashParamsVar,
     pub two_to_one_crh_params: TwoToOneHashParamsVar,
+    /// The parameters used to commit to transaction amounts.
+    pub amount_commitment_params: AmountCommitmentParamsVar,
+    /// The commitment to the parameters, see `Parameters::commitment`. Like the
+    /// parameters themselves, this is a constant.
+    pub commitment: FpVar<ConstraintF>,
+    /// How the transaction message is encoded before it is signed. This affects
+    /// the shape of the circuit, and so is not allocated as a variable.
+    pub message_encoding: MessageEncoding,
+    /// How accounts encode their signatures. Like `message_encoding`, this
+    /// affects the shape of the circuit.
+    pub signature_encoding: SignatureEncoding,
+    /// The maximum amount that each account can send per epoch, or `None` if
+    /// spending is unlimited. Like `message_encoding`, this affects the shape of
+    /// the circuit.
+    pub daily_limit: Option<AmountVar>,
+    /// The maximum number of transactions that can be applied per epoch, or
+    /// `None` if the number of transactions is unlimited. Like
+    /// `message_encoding`, this affects the shape of the circuit.
+    pub max_per_epoch: Option<UInt64<ConstraintF>>,
+    /// Whether each transaction is authorized by a fresh key derived from the
+    /// public key of its sender. Like `message_encoding`, this affects the shape
+    /// of the circuit.
+    pub hd_keys: bool,
+    /// The key of the sequencer, which must sign a receipt for the balance change
+    /// of the sender of each transaction, or `None` if no receipts are required.
+    /// Like `message_encoding`, this affects the shape of the circuit.
+    pub sequencer_key: Option<AccountPublicKeyVar>,
+    /// Whether the transactions of each batch must be sorted by their hashes.
+    /// Like `message_encoding`, this affects the shape of the circuit.
+    pub canonical_order: bool,
+    /// The number of epochs that must pass after an account is credited before
+    /// it can withdraw, or `None` if withdrawals are not delayed. Like
+    /// `message_encoding`, this affects the shape of the circuit.
+    pub withdrawal_grace: Option<UInt64<ConstraintF>>,
+    /// Whether transfers to accounts that don't accept transfers need the
+    /// consent of their recipient. Like `message_encoding`, this affects the
+    /// shape of the circuit.
+    pub require_consent: bool,
+    /// Whether transfers sign the public key of their recipient. Like
+    /// `message_encoding`, this affects the shape of the circuit.
+    pub bind_recipient_key: bool,
+    /// Whether transactions sign the leaf indices of their accounts. Like
+    /// `message_encoding`, this affects the shape of the circuit.
+    pub bind_leaf_indices: bool,
+    /// Whether accounts may restrict the recipients that they pay to an
+    /// allowlist. Like `message_encoding`, this affects the shape of the circuit.
+    pub allowlists: bool,
+    /// The account that collects the priority fees of the transactions, or
+    /// `None` if the ledger has no fee market. Like `message_encoding`, this
+    /// affects the shape of the circuit.
+    pub fee_collector: Option<AccountIdVar>,
+    /// Whether the owners of accounts may let delegates spend from them, up to
+    /// a cap. Like `message_encoding`, this affects the shape of the circuit.
+    pub delegation: bool,
+    /// The personalization of the leaves of the account, withdrawal and batch
+    /// trees, or `None` if leaves are hashed as is. Like `message_encoding`,
+    /// this affects the shape of the circuit.
+    pub leaf_personalization: Option<[u8; LEAF_PERSONALIZATION_LEN]>,
+    /// Whether transfers may not credit an account beyond the cap on its
+    /// balance. Like `message_encoding`, this affects the shape of the circuit.
+    pub balance_caps: bool,
+    /// Whether the transactions of each batch must have distinct identifiers.
+    /// Like `message_encoding`, this affects the shape of the circuit.
+    pub unique_tx_ids: bool,
+    /// Whether transactions sign the root of the account tree that they are
+    /// applied to. Like `message_encoding`, this affects the shape of the
+    /// circuit.
+    pub bind_pre_root: bool,
+}
+
+impl ParametersVar {
+    /// Convert `leaf` to the leaf of a tree of the domain `domain`, as
+    /// `ledger::personalize_leaf` does. The personalization is a constant.
+    pub fn personalize_leaf(
+        &self,
+        domain: LeafDomain,
+        leaf: Vec<UInt8<ConstraintF>>,
+    ) -> Vec<UInt8<ConstraintF>> {
+        match self.leaf_personalization {
+            Some(personalization) => {
+                let mut personalized = UInt8::constant_vec(&personalization);
+                personalized.push(UInt8::constant(domain as u8));
+                personalized.extend(leaf);
+                personalized
+            }
+            None => leaf,
+        }
+    }
+
+    /// Verify `signature` over `message` against `pub_key`, after encoding
+    /// `message` as specified by `self.message_encoding`. Signatures that are not
+    /// in the encoding specified by `self.signature_encoding` are rejected.
+    pub fn verify_signature(
+        &self,
+        pub_key: &AccountPublicKeyVar,
+        message: Vec<UInt8<ConstraintF>>,
+        signature: &AccountSignatureVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let message = match self.message_encoding {
+            MessageEncoding::Raw => message,
+            MessageEncoding::Sha256 | MessageEncoding::Structured => sha256_digest(&message)?,
+        };
+        self.verify_encoded_signature(pub_key, &message, signature)
+    }
+
+    /// Verify `signature` over `message` against `pub_key`, where `message` is
+    /// already encoded, e.g., as the structured digest of a transaction.
+    /// Signatures that are not in the encoding specified by
+    /// `self.signature_encoding` are rejected.
+    pub fn verify_encoded_signature(
+        &self,
+        pub_key: &AccountPublicKeyVar,
+        message: &[UInt8<ConstraintF>],
+        signature: &AccountSignatureVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        match (self.signature_encoding, signature) {
+            (SignatureEncoding::Challenge, AccountSignatureVar::Challenge(signature)) => {
+                Schnorr::verify_gadget(&self.sig_params, pub_key, message, signature)
+            }
+            (SignatureEncoding::Point, AccountSignatureVar::Point(signature)) => {
+                SchnorrPoint::verify_gadget(&self.sig_params, pub_key, message, signature)
+            }
+            _ => Ok(Boolean::FALSE),
+        }
+    }
+}
+
+/// Compute the SHA-256 digest of `message`, as `sha256::digest` does.
+pub fn sha256_digest(
+    message: &[UInt8<ConstraintF>],
+) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+    let params =
+        <Sha256ParamsVar as AllocVar<_, ConstraintF>>::new_constant(ConstraintSystemRef::None, ())?;
+    Ok(Sha256Gadget::evaluate(&params, message)?.0)
 }
 
 impl AllocVar<Parameters, ConstraintF> for ParametersVar {
-    #[tracing::instrument(target = "r1cs", skip(cs, f, _mode))]
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
     fn new_variable<T: Borrow<Parameters>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
-        _mode: AllocationMode,
+        mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
+        // The parameters are always embedded as constants. Otherwise, the prover
+        // could choose them, e.g., sign with keys of its own or lift the limits.
+        if mode != AllocationMode::Constant {
+            return Err(SynthesisError::AssignmentMissing);
+        }
         let cs = cs.into();
         f().and_then(|params| {
             let params: &Parameters = params.borrow();
             let sig_params = SchnorrParamsVar::new_constant(cs.clone(), &params.sig_params)?;
             let leaf_crh_params =
                 LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params)?;
+            let account_crh_params =
+                AccountHashParamsVar::new_constant(cs.clone(), &params.account_crh_params)?;
             let two_to_one_crh_params =
                 TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params)?;
+            let amount_commitment_params = AmountCommitmentParamsVar::new_constant(
+                cs.clone(),
+                &params.amount_commitment_params,
+            )?;
             Ok(Self {
                 sig_params,
                 leaf_crh_params,
+                account_crh_params,
                 two_to_one_crh_params,
+                amount_commitment_params,
+                commitment: FpVar::new_constant(cs.clone(), params.commitment())?,
+                message_encoding: params.message_encoding,
+                signature_encoding: params.signature_encoding,
+                daily_limit: params
+                    .daily_limit
+                    .map(|limit| AmountVar::new_constant(cs.clone(), limit))
+                    .transpose()?,
+                max_per_epoch: params
+                    .max_per_epoch
+                    .map(|max| UInt64::new_constant(cs.clone(), max))
+                    .transpose()?,
+                hd_keys: params.hd_keys,
+                sequencer_key: params
+                    .sequencer_key
+                    .map(|key| AccountPublicKeyVar::new_constant(cs.clone(), key))
+                    .transpose()?,
+                canonical_order: params.canonical_order,
+                withdrawal_grace: params
+                    .withdrawal_grace
+                    .map(|grace| UInt64::new_constant(cs.clone(), grace))
+                    .transpose()?,
+                require_consent: params.require_consent,
+                bind_recipient_key: params.bind_recipient_key,
+                bind_leaf_indices: params.bind_leaf_indices,
+                allowlists: params.allowlists,
+                fee_collector: params
+                    .fee_collector
+                    .map(|id| AccountIdVar::new_constant(cs.clone(), id))
+                    .transpose()?,
+                delegation: params.delegation,
+                leaf_personalization: params.leaf_personalization,
+                balance_caps: params.balance_caps,
+                unique_tx_ids: params.unique_tx_ids,
+                bind_pre_root: params.bind_pre_root,
             })
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountInformationVar;
+    use crate::withdrawal::WithdrawalVar;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::account::AccountId;
+    use ark_simple_payments::withdrawal::Withdrawal;
+
+    #[test]
+    fn path_field_elements_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let height = state.account_merkle_tree().height();
+        let proof = state.prove_account(alice_id).unwrap();
+        let elements = proof.path.to_field_elements();
+        assert_eq!(elements.len(), height);
+
+        // The round trip reproduces the path, ...
+        let path = AccPath::from_field_elements(&elements, height).unwrap();
+        assert_eq!(path.leaf_index, proof.path.leaf_index);
+        assert_eq!(path.leaf_sibling_hash, proof.path.leaf_sibling_hash);
+        assert_eq!(path.auth_path, proof.path.auth_path);
+        // ... which still proves the membership of the account.
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+        let root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let leaf = UInt8::new_witness_vec(cs.clone(), &proof.info.to_leaf(&pp)).unwrap();
+        let path = AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+        path.verify_membership(
+            &params.leaf_crh_params,
+            &params.two_to_one_crh_params,
+            &root,
+            &leaf.as_slice(),
+        )
+        .unwrap()
+        .enforce_equal(&Boolean::TRUE)
+        .unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // Elements of a path in a tree of another height are rejected, and so are
+        // leaf indices beyond the tree.
+        assert!(AccPath::from_field_elements(&elements, height + 1).is_none());
+        assert!(AccPath::from_field_elements(&elements[1..], height).is_none());
+        let mut elements = elements;
+        for index in [(1u64 << (height - 1)).into(), -ConstraintF::from(1u8)] {
+            elements[0] = index;
+            assert!(AccPath::from_field_elements(&elements, height).is_none());
+        }
+    }
+
+    #[test]
+    fn amount_accumulator_test() {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let mut acc = AmountAccumulator::new();
+        for amount in [1u64, 20, 300] {
+            let amount = AmountVar::new_witness(cs.clone(), || Ok(Amount(amount))).unwrap();
+            acc.add(&amount).unwrap();
+        }
+        let sum = acc.finalize().unwrap();
+        assert_eq!(sum.0.value().unwrap(), 321);
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn amount_accumulator_overflow_test() {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let mut acc = AmountAccumulator::new();
+        for _ in 0..16 {
+            let amount = AmountVar::new_witness(cs.clone(), || Ok(Amount(u64::MAX - 1))).unwrap();
+            acc.add(&amount).unwrap();
+        }
+        // The running sum doesn't wrap around, ...
+        let expected = ConstraintF::from(u64::MAX - 1) * ConstraintF::from(16u64);
+        assert_eq!(acc.sum().value().unwrap(), expected);
+        assert!(cs.is_satisfied().unwrap());
+        // ... and the overflow is caught by the final range check.
+        acc.finalize().unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn signed_amount_test() {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let amount = |amount| {
+            let amount = AmountVar::new_witness(cs.clone(), || Ok(Amount(amount))).unwrap();
+            SignedAmountVar::from_amount(&amount)
+        };
+        // Subtracting more than there is crosses zero, ...
+        let balance = amount(3).sub(&amount(5)).unwrap();
+        assert!(balance.is_negative.value().unwrap());
+        assert_eq!(balance.magnitude.0.value().unwrap(), 2);
+        // ... and adding it back crosses it again.
+        let balance = balance.add(&amount(7)).unwrap();
+        assert!(!balance.is_negative.value().unwrap());
+        assert_eq!(balance.to_amount().unwrap().0.value().unwrap(), 5);
+        let zero = balance.sub(&amount(5)).unwrap();
+        assert_eq!(zero.to_amount().unwrap().0.value().unwrap(), 0);
+        assert!(cs.is_satisfied().unwrap());
+        // A negative amount can't be converted back.
+        amount(1).sub(&amount(2)).unwrap().to_amount().unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // Magnitudes must fit in 64 bits.
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let max = AmountVar::new_witness(cs.clone(), || Ok(Amount(u64::MAX))).unwrap();
+        let max = SignedAmountVar::from_amount(&max);
+        SignedAmountVar::zero().sub(&max).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        max.add(&max).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn amount_comparison_test() {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let values = [0u64, 1, 99, 100, u64::MAX - 1, u64::MAX];
+        for a in values {
+            for b in values {
+                let a_var = AmountVar::new_witness(cs.clone(), || Ok(Amount(a))).unwrap();
+                let b_var = AmountVar::new_witness(cs.clone(), || Ok(Amount(b))).unwrap();
+                assert_eq!(a_var.is_lt(&b_var).unwrap().value().unwrap(), a < b);
+                assert_eq!(a_var.is_le(&b_var).unwrap().value().unwrap(), a <= b);
+            }
+        }
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn total_supply_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        for balance in [20, 5, 100] {
+            let (id, _pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(id, Amount(balance)).unwrap();
+            state
+                .update_asset_balance(id, AssetId(1), Amount(2 * balance))
+                .unwrap();
+        }
+        let supply = state.total_supply().unwrap();
+        assert_eq!(supply.get(AssetId::NATIVE), Some(Amount(125)));
+        assert_eq!(supply.get(AssetId(1)), Some(Amount(250)));
+        assert_eq!(supply.get(AssetId(2)), Some(Amount(0)));
+
+        let circuit = TotalSupply::with_state(pp.clone(), &state);
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // Claiming a different supply of any asset fails.
+        let mut circuit = TotalSupply::with_state(pp.clone(), &state);
+        circuit.supply.set(AssetId(1), Amount(249)).unwrap();
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // So does leaving out an account.
+        let mut circuit = TotalSupply::with_state(pp.clone(), &state);
+        circuit.accounts[1] = None;
+        circuit.supply.set(AssetId::NATIVE, Amount(105)).unwrap();
+        circuit.supply.set(AssetId(1), Amount(210)).unwrap();
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // In trees with more leaves than account ids, the extra leaves are blank
+        // instead of aliasing the accounts of smaller ids.
+        let mut state = State::new(1024, &pp);
+        let _ = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let circuit = TotalSupply::with_state(pp, &state);
+        assert_eq!(circuit.accounts.len(), 512);
+        assert_eq!(circuit.accounts.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn total_supply_with_counter_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.max_per_epoch = Some(10);
+        let mut state = State::new(8, &pp);
+        let (id, _pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(id, Amount(20)).unwrap();
+        // Once a batch is recorded, the leaf of `WITHDRAWAL_ACCOUNT` is no
+        // longer blank.
+        state.record_batch(&pp, 2).unwrap();
+        assert_ne!(state.tx_counter, TxCounter::default());
+
+        let circuit = TotalSupply::with_state(pp.clone(), &state);
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        let circuit = Solvency::with_state(pp.clone(), &state, Amount(20));
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // A counter that isn't the one in the tree fails.
+        let mut circuit = TotalSupply::with_state(pp, &state);
+        circuit.tx_counter = TxCounter::default();
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn solvency_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(8, &pp);
+        for balance in [20, 5, 100] {
+            let (id, _pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(id, Amount(balance)).unwrap();
+            // Balances of other assets aren't backed by the reserves.
+            state
+                .update_asset_balance(id, AssetId(1), Amount(2 * balance))
+                .unwrap();
+        }
+        let is_solvent = |reserves: u64| {
+            let circuit = Solvency::with_state(pp.clone(), &state, Amount(reserves));
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            circuit.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(is_solvent(125));
+        // Reserves that don't cover all balances fail, ...
+        assert!(!is_solvent(124));
+        assert!(!is_solvent(0));
+        // ... and so do reserves that don't match them exactly.
+        assert!(!is_solvent(126));
+
+        // Hiding an account to lower the total fails too.
+        let mut circuit = Solvency::with_state(pp, &state, Amount(105));
+        circuit.accounts[1] = None;
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn leaf_personalization_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.leaf_personalization = Some(*b"rollup1");
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let proof = state.prove_account(alice_id).unwrap();
+
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+        // The leaves are personalized as they are natively, ...
+        let info = AccountInformationVar::new_witness(cs.clone(), || Ok(proof.info)).unwrap();
+        let leaf = info.to_leaf(&params).unwrap();
+        assert_eq!(leaf.value().unwrap(), proof.info.to_leaf(&pp));
+        let withdrawal_leaf = WithdrawalVar::empty().to_leaf(&params).unwrap();
+        assert_eq!(
+            withdrawal_leaf.value().unwrap(),
+            Withdrawal::EMPTY.to_leaf(&pp)
+        );
+        // ... and so the account is proven to be in the tree.
+        let root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let path = AccPathVar::new_witness(cs.clone(), || Ok(proof.path)).unwrap();
+        path.verify_membership(
+            &params.leaf_crh_params,
+            &params.two_to_one_crh_params,
+            &root,
+            &leaf.as_slice(),
+        )
+        .unwrap()
+        .enforce_equal(&Boolean::TRUE)
+        .unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // The same bytes make different leaves in different trees.
+        let bytes = UInt8::constant_vec(&[7; 32]);
+        assert_ne!(
+            params
+                .personalize_leaf(LeafDomain::Account, bytes.clone())
+                .value()
+                .unwrap(),
+            params
+                .personalize_leaf(LeafDomain::Withdrawal, bytes)
+                .value()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn constant_parameters_test() {
+        use ark_simple_payments::signature::SignatureScheme;
+
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.daily_limit = Some(Amount(100));
+        pp.max_per_epoch = Some(10);
+        let (sequencer_pk, _sequencer_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        pp.sequencer_key = Some(sequencer_pk);
+        pp.withdrawal_grace = Some(3);
+        pp.fee_collector = Some(AccountId(1));
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+        // Constant parameters don't take up any variables, ...
+        assert_eq!(cs.num_instance_variables(), 1);
+        assert_eq!(cs.num_witness_variables(), 0);
+        // ... and the prover can't choose them, so they can't be allocated as
+        // inputs or witnesses.
+        for mode in [AllocationMode::Input, AllocationMode::Witness] {
+            let result = ParametersVar::new_variable(cs.clone(), || Ok(&pp), mode);
+            assert!(matches!(result, Err(SynthesisError::AssignmentMissing)));
+        }
+    }
+
+    #[test]
+    fn leaf_index_bits_test() {
+        let height = 5;
+        for id in [0u8, 1, 6, 15] {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let id = AccountId(id);
+            let id_var = AccountIdVar::new_witness(cs.clone(), || Ok(id)).unwrap();
+            let bits = leaf_index_bits(&id_var, height).unwrap();
+            assert_eq!(bits.len(), height - 1);
+            let index = bits
+                .iter()
+                .fold(0u64, |acc, bit| (acc << 1) | bit.value().unwrap() as u64);
+            assert_eq!(Some(index), leaf_index(&id, height));
+            assert!(cs.is_satisfied().unwrap());
+        }
+
+        // An identifier that doesn't fit in the tree is rejected, ...
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let id_var = AccountIdVar::new_witness(cs.clone(), || Ok(AccountId(16))).unwrap();
+        leaf_index_bits(&id_var, height).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+        // ... and so is a tree without leaves.
+        assert!(leaf_index_bits(&id_var, 0).is_err());
+    }
+
+    #[test]
+    fn layered_membership_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut ledger = LayeredLedger::blank(&pp, 3, 3);
+        ledger.update(5, &[1u8; 32]).unwrap();
+        ledger.update(10, &[2u8; 32]).unwrap();
+
+        let is_member = |path: &LayeredPath, leaf: &[u8]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(ledger.root())).unwrap();
+            let path = LayeredPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+            let leaf = UInt8::new_witness_vec(cs.clone(), leaf).unwrap();
+            verify_membership_layered(&params, &root, &path, &leaf)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        let path = ledger.prove(5).unwrap();
+        assert!(is_member(&path, &[1u8; 32]));
+        assert!(!is_member(&path, &[2u8; 32]));
+        // The proof doesn't hold with the path of another bottom tree.
+        let mut wrong_path = path.clone();
+        wrong_path.top = ledger.prove(9).unwrap().top;
+        assert!(!is_member(&wrong_path, &[1u8; 32]));
+    }
+
+    #[test]
+    fn sorted_non_membership_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let tree = SortedIdTree::new(&pp, 3, &[AccountId(3), AccountId(9), AccountId(42)]).unwrap();
+
+        let is_absent = |proof: &NonMembershipProof, id: AccountId| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+            let proof = NonMembershipProofVar::new_witness(cs.clone(), || Ok(proof)).unwrap();
+            let id = AccountIdVar::new_input(cs.clone(), || Ok(id)).unwrap();
+            verify_non_membership_sorted(&params, &root, &proof, &id)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // 5 falls strictly between the adjacent keys 3 and 9, ...
+        let proof = tree.prove_non_membership(AccountId(5)).unwrap();
+        assert!(is_absent(&proof, AccountId(5)));
+        // ... but 9 is in the tree, and can't be proven absent with its own leaf.
+        let proof = tree.prove_non_membership(AccountId(10)).unwrap();
+        assert_eq!(proof.low_key, 9);
+        assert!(!is_absent(&proof, AccountId(9)));
+        // Skipping over a leaf doesn't work either.
+        let mut proof = tree.prove_non_membership(AccountId(5)).unwrap();
+        let far_proof = tree.prove_non_membership(AccountId(10)).unwrap();
+        proof.high_key = far_proof.high_key;
+        proof.high_path = far_proof.high_path;
+        assert!(!is_absent(&proof, AccountId(9)));
+    }
+}
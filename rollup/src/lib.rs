@@ -1,7 +1,38 @@
 pub type ConstraintF = ark_bls12_381::Fr;
 
 pub mod account;
+pub mod allowlist;
+pub mod asset_trees;
+pub mod backend;
+pub mod batch;
+pub mod chunked;
+pub mod consent;
+pub mod delegation;
+pub mod diff;
+pub mod escrow;
+pub mod fee_market;
+pub mod hd;
+pub mod inputs;
+pub mod kary;
+pub mod keys;
 pub mod ledger;
+pub mod metadata;
+pub mod multisig;
+pub mod onchain;
+pub mod oracle;
+pub mod ownership;
+pub mod pow;
+pub mod processed;
+pub mod profiling;
+pub mod receipt;
+pub mod repro;
+pub mod rotation;
+pub mod schedule;
+pub mod shard;
+pub mod signature;
+pub mod swap;
 pub mod transaction;
+pub mod withdrawal;
+pub mod witness;
 
 pub mod rollup;
@@ -0,0 +1,104 @@
+use crate::account::AccountInformationVar;
+use crate::ledger::{AccountHashGadget, ParametersVar};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::metadata::{MAX_METADATA_LEN, METADATA_TAG};
+
+/// Hash the metadata `preimage`, as `metadata::metadata_hash` does. The length
+/// of the preimage is part of the shape of the circuit, and it must be at most
+/// `MAX_METADATA_LEN`.
+#[tracing::instrument(target = "r1cs", skip(parameters, preimage))]
+pub fn metadata_hash(
+    parameters: &ParametersVar,
+    preimage: &[UInt8<ConstraintF>],
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    assert!(preimage.len() <= MAX_METADATA_LEN);
+    // The hashed input consists of (Tag || Length || Preimage).
+    let mut input = UInt8::constant_vec(METADATA_TAG);
+    input.push(UInt8::constant(preimage.len() as u8));
+    input.extend_from_slice(preimage);
+    <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+        &parameters.account_crh_params,
+        &input,
+    )
+}
+
+impl AccountInformationVar {
+    /// Check whether `preimage` opens the metadata of the account, i.e., whether
+    /// it hashes to the metadata hash of the account. Since the metadata hash is
+    /// part of the leaf of the account, proving the membership of the account
+    /// binds its metadata.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, preimage))]
+    pub fn check_metadata(
+        &self,
+        parameters: &ParametersVar,
+        preimage: &[UInt8<ConstraintF>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        metadata_hash(parameters, preimage)?.is_eq(&self.metadata_hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{AccPathVar, AccRootVar};
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{Parameters, State};
+    use ark_simple_payments::metadata;
+
+    #[test]
+    fn check_metadata_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let hash = metadata::metadata_hash(&pp, b"kyc:verified").unwrap();
+        state.set_metadata_hash(alice_id, hash).unwrap();
+        let proof = state.prove_account(alice_id).unwrap();
+
+        // Open the metadata of the account `info`, and prove that the account is
+        // a member of the current tree.
+        let check = |info, preimage: &[u8]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+            let preimage = UInt8::new_witness_vec(cs.clone(), preimage).unwrap();
+            let path = AccPathVar::new_witness(cs.clone(), || Ok(&proof.path)).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+            path.verify_membership(
+                &params.leaf_crh_params,
+                &params.two_to_one_crh_params,
+                &root,
+                &info.to_leaf(&params).unwrap().as_slice(),
+            )
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+            info.check_metadata(&params, &preimage)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // The metadata of a member opens to its preimage, ...
+        assert!(check(proof.info, b"kyc:verified"));
+        // ... but not to other metadata, ...
+        assert!(!check(proof.info, b"kyc:rejected"));
+        // ... and other metadata can't be claimed for the account, since the
+        // metadata hash is bound into its leaf.
+        let mut forged = proof.info;
+        forged.metadata_hash = metadata::metadata_hash(&pp, b"kyc:rejected").unwrap();
+        assert!(!check(forged, b"kyc:rejected"));
+
+        // Changing the metadata changes the root.
+        let root = state.root();
+        let other = metadata::metadata_hash(&pp, b"tier:gold").unwrap();
+        state.set_metadata_hash(alice_id, other).unwrap();
+        assert_ne!(state.root(), root);
+    }
+}
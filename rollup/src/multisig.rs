@@ -0,0 +1,202 @@
+use crate::account::{AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar};
+use crate::ledger::{AccountHashGadget, ParametersVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::multisig::{MultisigAuthorization, SIGNER_SET_TAG};
+use std::borrow::Borrow;
+
+/// The signatures of the signers of a multisig account over a transaction:
+/// `signatures[i]` is the signature of `signers[i]`, which only counts if
+/// `signed[i]` is set.
+pub struct MultisigAuthorizationVar {
+    /// The public keys of the signers of the account.
+    pub signers: Vec<AccountPublicKeyVar>,
+    /// Whether each signer has signed.
+    pub signed: Vec<Boolean<ConstraintF>>,
+    /// The signature of each signer. The signatures of the signers who haven't
+    /// signed are arbitrary.
+    pub signatures: Vec<AccountSignatureVar>,
+}
+
+impl MultisigAuthorizationVar {
+    /// Commit to the signers, as `SignerSet::commitment` does.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters))]
+    pub fn signers_commitment(
+        &self,
+        parameters: &ParametersVar,
+    ) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        // The commitment is a hash chain over the signers, which starts from zero:
+        // C_i = H(Tag || C_{i-1} || PublicKey_i)
+        let mut commitment = FpVar::zero();
+        for key in &self.signers {
+            let mut input = UInt8::constant_vec(SIGNER_SET_TAG);
+            input.extend(commitment.to_bytes()?);
+            input.extend(key.to_bytes()?);
+            commitment = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+                &parameters.account_crh_params,
+                &input,
+            )?;
+        }
+        Ok(commitment)
+    }
+
+    /// Check that `tx` is authorized by the multisig account with information
+    /// `sender_info`, as `MultisigAuthorization::validate` does. This checks the
+    /// following conditions:
+    /// 1. Verify that the account is a multisig account, whose signer set is
+    ///    `self.signers`. Since the signers are committed to, only members of
+    ///    the set can sign.
+    /// 2. Verify that each signature that counts is valid under the key of its
+    ///    signer.
+    /// 3. Verify that the number of signatures that count is at least the
+    ///    threshold of the account.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, tx, sender_info))]
+    pub fn validate(
+        &self,
+        parameters: &ParametersVar,
+        tx: &TransactionVar,
+        sender_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        assert!(self.signers.len() < 256, "too many signers");
+        let is_multisig = sender_info.threshold.is_eq(&UInt8::constant(0))?.not();
+        let is_member = self
+            .signers_commitment(parameters)?
+            .is_eq(&sender_info.signers)?;
+
+        let message = tx.signed_message(parameters)?;
+        let mut signatures_verify = Boolean::TRUE;
+        let mut num_signed = FpVar::zero();
+        for ((key, signed), signature) in
+            self.signers.iter().zip(&self.signed).zip(&self.signatures)
+        {
+            let verifies = parameters.verify_encoded_signature(key, &message, signature)?;
+            signatures_verify = signatures_verify.and(&verifies.or(&signed.not())?)?;
+            num_signed += FpVar::from(signed.clone());
+        }
+
+        // Both values are less than 2^8, so `num_signed - threshold + 2^8` lies
+        // strictly between 0 and 2^9, and its 9th bit is set iff
+        // `num_signed >= threshold`.
+        let threshold = Boolean::le_bits_to_fp_var(&sender_info.threshold.to_bits_le()?)?;
+        let offset = FpVar::constant(ConstraintF::from(1u64 << 8));
+        let meets_threshold = (num_signed - threshold + offset).to_bits_le()?[8].clone();
+
+        is_multisig
+            .and(&is_member)?
+            .and(&signatures_verify)?
+            .and(&meets_threshold)
+    }
+}
+
+impl AllocVar<MultisigAuthorization, ConstraintF> for MultisigAuthorizationVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<MultisigAuthorization>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|auth| {
+            let auth: &MultisigAuthorization = auth.borrow();
+            let mut signers = Vec::with_capacity(auth.signers.signers.len());
+            let mut signed = Vec::with_capacity(auth.signatures.len());
+            let mut signatures = Vec::with_capacity(auth.signatures.len());
+            for key in &auth.signers.signers {
+                signers.push(AccountPublicKeyVar::new_variable(
+                    cs.clone(),
+                    || Ok(key),
+                    mode,
+                )?);
+            }
+            for signature in &auth.signatures {
+                signed.push(Boolean::new_variable(
+                    cs.clone(),
+                    || Ok(signature.is_some()),
+                    mode,
+                )?);
+                let signature = signature.clone().unwrap_or_default();
+                signatures.push(AccountSignatureVar::new_variable(
+                    cs.clone(),
+                    || Ok(signature),
+                    mode,
+                )?);
+            }
+            Ok(Self {
+                signers,
+                signed,
+                signatures,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::account::AccountInformation;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::multisig::SignerSet;
+    use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+    use ark_simple_payments::transaction::Transaction;
+
+    /// Validate `auth` of `tx` in a fresh constraint system, and return whether
+    /// the constraint system is satisfied.
+    fn auth_is_valid(
+        pp: &Parameters,
+        tx: &Transaction,
+        sender_info: &AccountInformation,
+        auth: &MultisigAuthorization,
+    ) -> bool {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let params = ParametersVar::new_constant(cs.clone(), pp).unwrap();
+        let tx = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+        let info = AccountInformationVar::new_witness(cs.clone(), || Ok(sender_info)).unwrap();
+        let auth = MultisigAuthorizationVar::new_witness(cs.clone(), || Ok(auth)).unwrap();
+        auth.validate(&params, &tx, &info)
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn two_of_three_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let keys = (0..3)
+            .map(|_| Schnorr::keygen(&pp.sig_params, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let signers = SignerSet::new(2, keys.iter().map(|(pk, _)| *pk).collect()).unwrap();
+        state.set_signers(&pp, alice_id, &signers).unwrap();
+        let alice_info = state.id_to_account_info[&alice_id];
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(0), &alice_sk, &mut rng);
+
+        // One valid signature is not enough, ...
+        let mut auth = MultisigAuthorization::new(signers);
+        auth.sign(&pp, &tx, 1, &keys[1].1, &mut rng);
+        assert!(!auth_is_valid(&pp, &tx, &alice_info, &auth));
+        // ... but two are.
+        auth.sign(&pp, &tx, 0, &keys[0].1, &mut rng);
+        assert!(auth.validate(&pp, &tx, &alice_info));
+        assert!(auth_is_valid(&pp, &tx, &alice_info, &auth));
+
+        // A signer outside of the set can't sign.
+        let mut bad_auth = auth.clone();
+        let (mallory_pk, mallory_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        bad_auth.signers.signers[1] = mallory_pk;
+        bad_auth.sign(&pp, &tx, 1, &mallory_sk, &mut rng);
+        assert!(!auth_is_valid(&pp, &tx, &alice_info, &bad_auth));
+        // An account that is not a multisig account can't be authorized.
+        let bob_info = state.id_to_account_info[&bob_id];
+        assert!(!auth_is_valid(&pp, &tx, &bob_info, &auth));
+    }
+}
@@ -0,0 +1,416 @@
+//! Verification of batch proofs from the inputs that an L1 contract has at hand:
+//! the roots of a batch are passed as calldata, while the rest of the public
+//! inputs are part of the state of the contract.
+//!
+//! The gas estimates follow the prices of the BLS12-381 precompiles of EIP-2537.
+
+use crate::ConstraintF;
+use ark_bls12_381::Bls12_381;
+use ark_groth16::{Groth16, Proof, VerifyingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_simple_payments::ledger::{AccRoot, BlockCommitment, Parameters, ParamsCommitment};
+use ark_simple_payments::withdrawal::{Withdrawal, WithdrawalRoot, WithdrawalTree};
+use ark_snark::SNARK;
+
+/// The gas of a pairing check, for each pair of points.
+pub const PAIRING_PAIR_GAS: u64 = 32_600;
+
+/// The gas of a pairing check, on top of that of its pairs.
+pub const PAIRING_BASE_GAS: u64 = 37_700;
+
+/// The gas of a scalar multiplication in G1.
+pub const G1_MUL_GAS: u64 = 12_000;
+
+/// The gas of an addition in G1.
+pub const G1_ADD_GAS: u64 = 375;
+
+/// The gas of a byte of calldata, assuming that it is not zero.
+pub const CALLDATA_BYTE_GAS: u64 = 16;
+
+/// The estimated cost of verifying a Groth16 proof in a contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerifierCost {
+    /// The number of pairings of the verification equation.
+    pub pairings: usize,
+    /// The number of scalar multiplications in G1 that combine the public inputs.
+    pub scalar_mults: usize,
+    /// The approximate gas of the verification, including the calldata.
+    pub approx_gas: u64,
+}
+
+/// Estimate the cost of verifying a Groth16 proof with `num_inputs` public
+/// inputs, whose proof and inputs take `calldata_size` bytes of calldata. The
+/// estimate only covers the precompiles and the calldata, and so it is a lower
+/// bound on the gas of a real verifier.
+pub fn verifier_cost(num_inputs: usize, calldata_size: usize) -> VerifierCost {
+    // The verifier checks that e(A, B) = e(alpha, beta) * e(L, gamma) * e(C, delta),
+    // where L = IC_0 + sum_i x_i * IC_i combines the public inputs x_i, as a
+    // single check that a product of four pairings is one.
+    let pairings = 4;
+    let scalar_mults = num_inputs;
+    let approx_gas = PAIRING_BASE_GAS
+        + PAIRING_PAIR_GAS * pairings as u64
+        + (G1_MUL_GAS + G1_ADD_GAS) * scalar_mults as u64
+        + CALLDATA_BYTE_GAS * calldata_size as u64;
+    VerifierCost {
+        pairings,
+        scalar_mults,
+        approx_gas,
+    }
+}
+
+/// The public inputs of a batch that the contract verifying it keeps track of
+/// itself, rather than receiving them along with the proof.
+#[derive(Clone, Copy, Debug)]
+pub struct ChainState {
+    /// The ledger epoch before the batch. Each batch advances it by one.
+    pub epoch: u64,
+    /// The commitment to the block at which the batch is applied.
+    pub block_commitment: BlockCommitment,
+    /// The commitment to the ledger parameters of the deployment.
+    pub params_commitment: ParamsCommitment,
+}
+
+/// Verify the proof `proof` of a batch with the verifying key `vk`, against the
+/// roots `pre_root` and `post_root` of the account tree before and after the
+/// batch, and the root `withdrawal_root` of its withdrawal tree. The inputs are
+/// packed in the order in which the rollup circuit declares them, along with
+/// those taken from `chain`.
+///
+/// Only batches without any of the optional public inputs listed in
+/// `BatchPublicInputs::from_batch` can be verified this way. Proofs that don't
+/// verify, including malformed ones, are rejected.
+pub fn verify_batch_onchain(
+    vk: &VerifyingKey<Bls12_381>,
+    proof: &Proof<Bls12_381>,
+    pre_root: AccRoot,
+    post_root: AccRoot,
+    withdrawal_root: WithdrawalRoot,
+    chain: &ChainState,
+) -> bool {
+    let public_input = [
+        pre_root,
+        post_root,
+        withdrawal_root,
+        ConstraintF::from(chain.epoch),
+        ConstraintF::from(chain.epoch + 1),
+        chain.block_commitment,
+        chain.params_commitment,
+    ];
+    Groth16::verify(vk, &public_input, proof).unwrap_or(false)
+}
+
+/// Verify the proof `proof` of a batch of `num_transactions` transactions as
+/// `verify_batch_onchain` does, but rebuild the root of its withdrawal tree from
+/// the withdrawal events of the batch, as the bridge contract logged them. Each
+/// event is a withdrawal along with the index of its transaction in the batch,
+/// see `WithdrawalTree::from_events`. Malformed events are rejected.
+///
+/// The circuit doesn't commit to deposits, which are credited to accounts
+/// outside of batches, and so they can't be checked against the proof.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_against_events(
+    parameters: &Parameters,
+    vk: &VerifyingKey<Bls12_381>,
+    proof: &Proof<Bls12_381>,
+    pre_root: AccRoot,
+    post_root: AccRoot,
+    num_transactions: usize,
+    withdrawals: &[(usize, Withdrawal)],
+    chain: &ChainState,
+) -> bool {
+    match WithdrawalTree::from_events(parameters, num_transactions, withdrawals) {
+        Some(tree) => verify_batch_onchain(vk, proof, pre_root, post_root, tree.root(), chain),
+        None => false,
+    }
+}
+
+/// The size of the uncompressed encoding of an element of G1.
+pub const G1_ENCODING_SIZE: usize = 96;
+
+/// The size of the uncompressed encoding of an element of G2.
+pub const G2_ENCODING_SIZE: usize = 192;
+
+/// The components of a verifying key that a verifier contract stores, each in
+/// the uncompressed canonical encoding of its group element, so that the
+/// contract never decompresses points. An element of G1 takes
+/// `G1_ENCODING_SIZE` bytes, and one of G2 takes `G2_ENCODING_SIZE` bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VkFields {
+    /// The element `alpha` of G1.
+    pub alpha_g1: Vec<u8>,
+    /// The element `beta` of G2.
+    pub beta_g2: Vec<u8>,
+    /// The element `gamma` of G2.
+    pub gamma_g2: Vec<u8>,
+    /// The element `delta` of G2.
+    pub delta_g2: Vec<u8>,
+    /// The elements `IC_0, ..., IC_n` of G1 that combine the `n` public inputs,
+    /// see `verifier_cost`.
+    pub ic: Vec<Vec<u8>>,
+}
+
+impl VkFields {
+    /// The bytes that the contract stores, which are the components in the
+    /// order (Alpha || Beta || Gamma || Delta || IC_0 || ... || IC_n).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.alpha_g1.clone();
+        bytes.extend(&self.beta_g2);
+        bytes.extend(&self.gamma_g2);
+        bytes.extend(&self.delta_g2);
+        bytes.extend(self.ic.concat());
+        bytes
+    }
+
+    /// Reassemble the verifying key from its components. Returns `None` if a
+    /// component isn't the encoding of a group element.
+    pub fn to_vk(&self) -> Option<VerifyingKey<Bls12_381>> {
+        fn decode<T: CanonicalDeserialize>(bytes: &[u8], size: usize) -> Option<T> {
+            match bytes.len() == size {
+                true => T::deserialize_uncompressed(bytes).ok(),
+                false => None,
+            }
+        }
+        Some(VerifyingKey {
+            alpha_g1: decode(&self.alpha_g1, G1_ENCODING_SIZE)?,
+            beta_g2: decode(&self.beta_g2, G2_ENCODING_SIZE)?,
+            gamma_g2: decode(&self.gamma_g2, G2_ENCODING_SIZE)?,
+            delta_g2: decode(&self.delta_g2, G2_ENCODING_SIZE)?,
+            gamma_abc_g1: self
+                .ic
+                .iter()
+                .map(|point| decode(point, G1_ENCODING_SIZE))
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+/// Break the verifying key `vk` into the components that a verifier contract
+/// stores, see `VkFields`.
+pub fn export_vk_fields(vk: &VerifyingKey<Bls12_381>) -> VkFields {
+    fn encode<T: CanonicalSerialize>(point: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        point.serialize_uncompressed(&mut bytes).unwrap();
+        bytes
+    }
+    VkFields {
+        alpha_g1: encode(&vk.alpha_g1),
+        beta_g2: encode(&vk.beta_g2),
+        gamma_g2: encode(&vk.gamma_g2),
+        delta_g2: encode(&vk.delta_g2),
+        ic: vk.gamma_abc_g1.iter().map(encode).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chunked::RollupProof;
+    use crate::rollup::Rollup;
+    use ark_simple_payments::ledger::{Amount, AssetId, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn verify_batch_onchain_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let chain = ChainState {
+            epoch: state.epoch,
+            block_commitment: state.block_commitment,
+            params_commitment: pp.commitment(),
+        };
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(rollup.clone(), &mut rng).unwrap();
+        let (pre_root, post_root, withdrawal_root) = (
+            rollup.initial_root.unwrap(),
+            rollup.final_root.unwrap(),
+            rollup.withdrawal_root.unwrap(),
+        );
+        let proof = Groth16::prove(&pk, rollup, &mut rng).unwrap();
+
+        assert!(verify_batch_onchain(
+            &vk,
+            &proof,
+            pre_root,
+            post_root,
+            withdrawal_root,
+            &chain
+        ));
+        // Passing the roots in the wrong order is caught.
+        assert!(!verify_batch_onchain(
+            &vk,
+            &proof,
+            post_root,
+            pre_root,
+            withdrawal_root,
+            &chain
+        ));
+    }
+
+    #[test]
+    fn export_vk_fields_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let chain = ChainState {
+            epoch: state.epoch,
+            block_commitment: state.block_commitment,
+            params_commitment: pp.commitment(),
+        };
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(rollup.clone(), &mut rng).unwrap();
+        let (pre_root, post_root, withdrawal_root) = (
+            rollup.initial_root.unwrap(),
+            rollup.final_root.unwrap(),
+            rollup.withdrawal_root.unwrap(),
+        );
+        let proof = Groth16::prove(&pk, rollup, &mut rng).unwrap();
+
+        // The key has one IC point for each of the seven public inputs, plus one,
+        // and the stored bytes are exactly the components in order.
+        let fields = export_vk_fields(&vk);
+        assert_eq!(fields.ic.len(), 8);
+        assert_eq!(
+            fields.to_bytes().len(),
+            G1_ENCODING_SIZE * 9 + G2_ENCODING_SIZE * 3
+        );
+        assert_eq!(&fields.to_bytes()[..G1_ENCODING_SIZE], &fields.alpha_g1[..]);
+
+        // The components reassemble into a key that verifies the proof, ...
+        let reassembled = fields.to_vk().unwrap();
+        assert!(verify_batch_onchain(
+            &reassembled,
+            &proof,
+            pre_root,
+            post_root,
+            withdrawal_root,
+            &chain
+        ));
+        // ... but not once two of them are swapped, ...
+        let swapped = VkFields {
+            gamma_g2: fields.delta_g2.clone(),
+            delta_g2: fields.gamma_g2.clone(),
+            ..fields.clone()
+        };
+        assert!(!verify_batch_onchain(
+            &swapped.to_vk().unwrap(),
+            &proof,
+            pre_root,
+            post_root,
+            withdrawal_root,
+            &chain
+        ));
+        // ... and truncated components aren't accepted.
+        let mut truncated = fields;
+        truncated.ic[0].pop();
+        assert!(truncated.to_vk().is_none());
+    }
+
+    #[test]
+    fn verify_against_events_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let chain = ChainState {
+            epoch: state.epoch,
+            block_commitment: state.block_commitment,
+            params_commitment: pp.commitment(),
+        };
+
+        // Alice pays Bob, and then withdraws some of her funds to L1.
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            AssetId::NATIVE,
+            Amount(4),
+            Amount(0),
+            [7; 20],
+            &alice_sk,
+            &mut rng,
+        );
+        let txs = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            withdrawal.clone(),
+        ];
+        let rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &txs, &mut state, true).unwrap();
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(rollup.clone(), &mut rng).unwrap();
+        let (pre_root, post_root) = (rollup.initial_root.unwrap(), rollup.final_root.unwrap());
+        let proof = Groth16::prove(&pk, rollup, &mut rng).unwrap();
+        let verify = |events: &[(usize, Withdrawal)]| {
+            verify_against_events(&pp, &vk, &proof, pre_root, post_root, 2, events, &chain)
+        };
+
+        // The events of the batch match the proof, ...
+        let event = Withdrawal::from_transaction(&withdrawal).unwrap();
+        assert!(verify(&[(1, event)]));
+        // ... but a missing withdrawal, one at another index, one for another
+        // amount, or an extra one don't.
+        assert!(!verify(&[]));
+        assert!(!verify(&[(0, event)]));
+        let inflated = Withdrawal {
+            amount: Amount(5),
+            ..event
+        };
+        assert!(!verify(&[(1, inflated)]));
+        assert!(!verify(&[(0, event), (1, event)]));
+        // Malformed events are rejected outright.
+        assert!(!verify(&[(1, event), (1, event)]));
+        assert!(!verify(&[(2, event)]));
+    }
+
+    #[test]
+    fn verifier_cost_test() {
+        let proof = RollupProof {
+            proof: Proof::default(),
+            initial_root: Default::default(),
+            final_root: Default::default(),
+            withdrawal_root: Default::default(),
+            pre_epoch: 0,
+            post_epoch: 1,
+            block_commitment: Default::default(),
+            params_commitment: Default::default(),
+        };
+        let cost = proof.verifier_cost_estimate();
+        assert_eq!(cost.pairings, 4);
+        assert_eq!(cost.scalar_mults, proof.public_input().len());
+        assert_eq!(
+            cost,
+            verifier_cost(proof.public_input().len(), proof.onchain_calldata_size())
+        );
+
+        // Each public input costs a scalar multiplication, and the calldata to
+        // pass it.
+        let more_inputs = verifier_cost(
+            cost.scalar_mults + 1,
+            proof.onchain_calldata_size() + proof.initial_root.serialized_size(),
+        );
+        assert!(more_inputs.scalar_mults > cost.scalar_mults);
+        assert!(more_inputs.approx_gas > cost.approx_gas);
+        assert_eq!(more_inputs.pairings, cost.pairings);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::account::{AccountPublicKeyVar, AccountSignatureVar};
+use crate::ledger::{AmountVar, AssetIdVar, ParametersVar};
+use crate::swap::SwapVar;
+use crate::ConstraintF;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::oracle::{PriceQuote, ORACLE_TAG, PRICE_DECIMALS};
+use std::borrow::Borrow;
+
+/// The price of `asset_in` in units of `asset_out` during `epoch`, as signed by
+/// the oracle.
+pub struct PriceQuoteVar {
+    /// The asset being sold at this price.
+    pub asset_in: AssetIdVar,
+    /// The asset being bought at this price.
+    pub asset_out: AssetIdVar,
+    /// The price, with `PRICE_DECIMALS` decimals.
+    pub price: AmountVar,
+    /// The epoch during which the price holds.
+    pub epoch: UInt64<ConstraintF>,
+    /// The signature of the oracle over the whole quote.
+    pub signature: AccountSignatureVar,
+}
+
+impl PriceQuoteVar {
+    /// Construct the message that the oracle signs to quote the price.
+    fn message(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The signed message consists of
+        // (Tag || AssetIn || AssetOut || Price || Epoch)
+        let mut message = UInt8::constant_vec(ORACLE_TAG);
+        message.extend(self.asset_in.to_bytes_le());
+        message.extend(self.asset_out.to_bytes_le());
+        message.extend(self.price.to_bytes_le());
+        message.extend(self.epoch.to_bytes()?);
+        Ok(message)
+    }
+
+    /// Verify that the quote is signed by the oracle with key `oracle_key`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, oracle_key))]
+    pub fn verify(
+        &self,
+        parameters: &ParametersVar,
+        oracle_key: &AccountPublicKeyVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        parameters.verify_signature(oracle_key, self.message()?, &self.signature)
+    }
+
+    /// Check whether `amount_out` is what `amount_in` is worth at this price,
+    /// rounded down, as `PriceQuote::amount_out` computes.
+    #[tracing::instrument(target = "r1cs", skip(self, amount_in, amount_out))]
+    pub fn is_amount_out(
+        &self,
+        amount_in: &AmountVar,
+        amount_out: &AmountVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // The output is rounded down iff the remainder
+        // `amount_in * price - amount_out * 10^PRICE_DECIMALS` lies in
+        // `[0, 10^PRICE_DECIMALS)`. The product is less than 2^128, so it can't
+        // wrap around the field, while a negative remainder does, and so it
+        // doesn't fit in 64 bits.
+        let to_fp = |amount: &AmountVar| Boolean::le_bits_to_fp_var(&amount.0.to_bits_le());
+        let scale = 10u64.pow(PRICE_DECIMALS);
+        let remainder = to_fp(amount_in)? * to_fp(&self.price)?
+            - to_fp(amount_out)? * FpVar::constant(ConstraintF::from(scale));
+        let bits = remainder.to_bits_le()?;
+        let fits = Boolean::kary_or(&bits[64..])?.not();
+        let remainder = AmountVar(UInt64::from_bits_le(&bits[..64]));
+        let is_below_scale = remainder.is_lt(&AmountVar(UInt64::constant(scale)))?;
+        fits.and(&is_below_scale)
+    }
+}
+
+impl SwapVar {
+    /// Check that the swap trades `amount_a` of `asset_a` for `amount_b` of
+    /// `asset_b` at the price of `quote`, during the epoch `epoch`, as
+    /// `Swap::is_at_price` does.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, quote, oracle_key, epoch))]
+    pub fn is_at_price(
+        &self,
+        parameters: &ParametersVar,
+        quote: &PriceQuoteVar,
+        oracle_key: &AccountPublicKeyVar,
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        Boolean::kary_and(&[
+            quote.verify(parameters, oracle_key)?,
+            quote.asset_in.0.is_eq(&self.asset_a.0)?,
+            quote.asset_out.0.is_eq(&self.asset_b.0)?,
+            quote.epoch.is_eq(epoch)?,
+            quote.is_amount_out(&self.amount_a, &self.amount_b)?,
+        ])
+    }
+}
+
+impl AllocVar<PriceQuote, ConstraintF> for PriceQuoteVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<PriceQuote>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|quote| {
+            let quote: &PriceQuote = quote.borrow();
+            let asset_in = AssetIdVar::new_variable(cs.clone(), || Ok(&quote.asset_in), mode)?;
+            let asset_out = AssetIdVar::new_variable(cs.clone(), || Ok(&quote.asset_out), mode)?;
+            let price = AmountVar::new_variable(cs.clone(), || Ok(&quote.price), mode)?;
+            let epoch = UInt64::new_variable(cs.clone(), || Ok(quote.epoch), mode)?;
+            let signature =
+                AccountSignatureVar::new_variable(cs.clone(), || Ok(&quote.signature), mode)?;
+            Ok(Self {
+                asset_in,
+                asset_out,
+                price,
+                epoch,
+                signature,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State};
+    use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+    use ark_simple_payments::swap::Swap;
+
+    #[test]
+    fn price_quote_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (oracle_pk, oracle_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        // One unit of asset 1 is worth 2.5 units of asset 2.
+        let quote = PriceQuote::create(
+            &pp,
+            AssetId(1),
+            AssetId(2),
+            Amount(2_500_000),
+            0,
+            &oracle_sk,
+            &mut rng,
+        );
+        let mut is_at_price = |amount_a, amount_b| {
+            let swap = Swap::create(
+                &pp,
+                alice_id,
+                bob_id,
+                AssetId(1),
+                Amount(amount_a),
+                AssetId(2),
+                Amount(amount_b),
+                &alice_sk,
+                &bob_sk,
+                &mut rng,
+            );
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let oracle_key = AccountPublicKeyVar::new_input(cs.clone(), || Ok(oracle_pk)).unwrap();
+            let epoch = UInt64::new_input(cs.clone(), || Ok(0)).unwrap();
+            let swap_var = SwapVar::new_witness(cs.clone(), || Ok(&swap)).unwrap();
+            let quote_var = PriceQuoteVar::new_witness(cs.clone(), || Ok(&quote)).unwrap();
+            swap_var
+                .is_at_price(&params, &quote_var, &oracle_key, &epoch)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            let is_satisfied = cs.is_satisfied().unwrap();
+            assert_eq!(is_satisfied, swap.is_at_price(&pp, &quote, &oracle_pk, 0));
+            is_satisfied
+        };
+
+        // The output is rounded down, ...
+        assert!(is_at_price(3, 7));
+        assert!(is_at_price(4, 10));
+        // ... and an output inconsistent with the price is rejected, whether it
+        // is too large or too small.
+        assert!(!is_at_price(3, 8));
+        assert!(!is_at_price(3, 6));
+        assert!(!is_at_price(4, 11));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::account::{AccountInformationVar, AccountSignatureVar};
+use crate::ledger::{AccPathVar, AccRootVar, ParametersVar};
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::ownership::OWNERSHIP_TAG;
+
+/// Check that `signature` answers the challenge `challenge` under the key of
+/// some account in the tree with root `set_root`, as
+/// `ownership::verify_ownership` does. The account `info` and its `path` are
+/// witnesses, and the index of the leaf is only constrained by `path`, so the
+/// proof reveals neither which account signed nor where it is in the tree.
+#[tracing::instrument(
+    target = "r1cs",
+    skip(parameters, set_root, challenge, info, path, signature)
+)]
+pub fn prove_ownership_anonymous(
+    parameters: &ParametersVar,
+    set_root: &AccRootVar,
+    challenge: &[UInt8<ConstraintF>],
+    info: &AccountInformationVar,
+    path: &AccPathVar,
+    signature: &AccountSignatureVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    // Empty slots of the tree hold the blank leaf, which belongs to no one, and
+    // which is never the leaf of an account.
+    let is_member = path.verify_membership(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        set_root,
+        &info.to_leaf(parameters)?.as_slice(),
+    )?;
+    // The signed message consists of (Tag || Root || Challenge).
+    let mut message = UInt8::constant_vec(OWNERSHIP_TAG);
+    message.extend(set_root.to_bytes()?);
+    message.extend_from_slice(challenge);
+    let signed = parameters.verify_signature(&info.public_key, message, signature)?;
+    is_member.and(&signed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::account::AccountSignature;
+    use ark_simple_payments::ledger::{AccountProof, Parameters, State};
+    use ark_simple_payments::ownership::sign_ownership;
+
+    #[test]
+    fn prove_ownership_anonymous_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (_alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let root = state.root();
+        // Mallory has an account too, but in another tree.
+        let mut other = state.clone();
+        let (mallory_id, _mallory_pk, mallory_sk) =
+            other.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let check = |proof: &AccountProof, signature: &AccountSignature, challenge: &[u8]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let set_root = AccRootVar::new_input(cs.clone(), || Ok(root)).unwrap();
+            let challenge = UInt8::new_input_vec(cs.clone(), challenge).unwrap();
+            let info = AccountInformationVar::new_witness(cs.clone(), || Ok(proof.info)).unwrap();
+            let path = AccPathVar::new_witness(cs.clone(), || Ok(&proof.path)).unwrap();
+            let signature = AccountSignatureVar::new_witness(cs.clone(), || Ok(signature)).unwrap();
+            prove_ownership_anonymous(&params, &set_root, &challenge, &info, &path, &signature)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // Bob proves that he owns some account in the tree, ...
+        let proof = state.prove_account(bob_id).unwrap();
+        let signature = sign_ownership(&pp, &bob_sk, &root, b"login:42", &mut rng);
+        assert!(check(&proof, &signature, b"login:42"));
+        // ... but his answer doesn't hold for another challenge.
+        assert!(!check(&proof, &signature, b"login:43"));
+
+        // Mallory isn't a member, whether she proves her own account, ...
+        let proof = other.prove_account(mallory_id).unwrap();
+        let signature = sign_ownership(&pp, &mallory_sk, &root, b"login:42", &mut rng);
+        assert!(!check(&proof, &signature, b"login:42"));
+        // ... or claims Bob's.
+        let proof = state.prove_account(bob_id).unwrap();
+        assert!(!check(&proof, &signature, b"login:42"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::ledger::{AccountHashGadget, ParametersVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::pow::{POW_DIGEST_BITS, POW_TAG};
+
+/// Count the leading zero bits of `digest`, out of its `POW_DIGEST_BITS` bits,
+/// as `pow::leading_zero_bits` does.
+#[tracing::instrument(target = "r1cs", skip(digest))]
+pub fn leading_zero_bits(
+    digest: &FpVar<ConstraintF>,
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    let bits = digest.to_bits_le()?;
+    // A bit counts if it and all the bits above it are zero.
+    let mut is_leading = Boolean::TRUE;
+    let mut count = FpVar::zero();
+    for bit in bits[..POW_DIGEST_BITS as usize].iter().rev() {
+        is_leading = is_leading.and(&bit.not())?;
+        count += FpVar::from(is_leading.clone());
+    }
+    Ok(count)
+}
+
+impl TransactionVar {
+    /// Does the proof of work of the transaction meet the difficulty
+    /// `difficulty`, as `Transaction::meets_difficulty` checks?
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, difficulty))]
+    pub fn meets_difficulty(
+        &self,
+        parameters: &ParametersVar,
+        difficulty: &UInt8<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // The hashed input consists of
+        // (Tag || TransactionHash || Nonce)
+        let mut input = UInt8::constant_vec(POW_TAG);
+        input.extend(self.hash(parameters)?.to_bytes()?);
+        input.extend(self.pow_nonce.to_bytes()?);
+        let digest = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )?;
+        let zeros = leading_zero_bits(&digest)?;
+
+        // Both values are less than 2^8, so `zeros - difficulty + 2^8` lies
+        // strictly between 0 and 2^9, and its 9th bit is set iff
+        // `zeros >= difficulty`.
+        let difficulty = Boolean::le_bits_to_fp_var(&difficulty.to_bits_le()?)?;
+        let offset = FpVar::constant(ConstraintF::from(1u64 << 8));
+        Ok((zeros - difficulty + offset).to_bits_le()?[8].clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::pow;
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn pow_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng)
+            .with_pow(&pp, 6);
+        let zeros = pow::leading_zero_bits(&tx.pow_digest(&pp)) as u8;
+
+        // Check the proof of work of `tx` against the public difficulty
+        // `difficulty`, and return whether the constraint system is satisfied.
+        let meets_difficulty = |tx: &Transaction, difficulty: u8| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let difficulty = UInt8::new_input(cs.clone(), || Ok(difficulty)).unwrap();
+            let tx = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            tx.meets_difficulty(&params, &difficulty)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        // The nonce meets the difficulty that it is found for, and any lower one,
+        // ...
+        assert!(meets_difficulty(&tx, 6));
+        assert!(meets_difficulty(&tx, 0));
+        assert!(meets_difficulty(&tx, zeros));
+        // ... but not a higher one, ...
+        assert!(!meets_difficulty(&tx, zeros + 1));
+        // ... and another nonce doesn't meet it in general.
+        let mut weak_tx = tx.clone();
+        weak_tx.pow_nonce += 1;
+        while weak_tx.meets_difficulty(&pp, 6) {
+            weak_tx.pow_nonce += 1;
+        }
+        assert!(!meets_difficulty(&weak_tx, 6));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::ledger::{AccRootVar, AccountHashGadget, ParametersVar, PositionedPathVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::processed::{InsertionProof, ProcessedLeaf, PROCESSED_LEAF_TAG};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+/// A leaf of the set of processed transactions.
+#[derive(Clone, Debug)]
+pub struct ProcessedLeafVar {
+    /// The identifier stored in the leaf.
+    pub key: FpVar<ConstraintF>,
+    /// The smallest identifier in the set that is larger than `key`.
+    pub next: FpVar<ConstraintF>,
+}
+
+impl ProcessedLeafVar {
+    /// Convert the leaf to the bytes stored in the tree, as
+    /// `ProcessedLeaf::to_leaf` does.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters))]
+    pub fn to_leaf(
+        &self,
+        parameters: &ParametersVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The committed input consists of
+        // (Tag || Key || Next)
+        let mut input = UInt8::constant_vec(PROCESSED_LEAF_TAG);
+        input.extend(self.key.to_bytes()?);
+        input.extend(self.next.to_bytes()?);
+        <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )?
+        .to_bytes()
+    }
+
+    /// Does `id` fall strictly between the identifiers of this leaf?
+    #[tracing::instrument(target = "r1cs", skip(self, id))]
+    pub fn brackets(
+        &self,
+        id: &FpVar<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        is_less(&self.key, id)?.and(&is_less(id, &self.next)?)
+    }
+}
+
+/// Is `a` smaller than `b`, as integers? Identifiers are full field elements,
+/// which can't be compared directly, so each is split into two halves of at
+/// most 128 bits, which can.
+fn is_less(
+    a: &FpVar<ConstraintF>,
+    b: &FpVar<ConstraintF>,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let halves = |value: &FpVar<ConstraintF>| -> Result<_, SynthesisError> {
+        let bits = value.to_bits_le()?;
+        let (low, high) = bits.split_at(128);
+        Ok((
+            Boolean::le_bits_to_fp_var(high)?,
+            Boolean::le_bits_to_fp_var(low)?,
+        ))
+    };
+    let ((high, low), (other_high, other_low)) = (halves(a)?, halves(b)?);
+    let is_lower = high.is_cmp(&other_high, Ordering::Less, false)?;
+    let is_lower_on_tie =
+        high.is_eq(&other_high)?
+            .and(&low.is_cmp(&other_low, Ordering::Less, false)?)?;
+    is_lower.or(&is_lower_on_tie)
+}
+
+/// A proof that an identifier was inserted into the set of processed
+/// transactions. Unlike `InsertionProof`, it doesn't hold the intermediate
+/// root, which is recomputed instead.
+pub struct InsertionProofVar {
+    /// The leaf that brackets the identifier before the insertion.
+    pub low_leaf: ProcessedLeafVar,
+    /// The membership proof of `low_leaf` before the insertion.
+    pub low_path: PositionedPathVar,
+    /// The membership proof of the blank leaf that the identifier fills.
+    pub new_path: PositionedPathVar,
+}
+
+impl InsertionProofVar {
+    /// Insert `id` into the set of processed transactions with root `pre_root`,
+    /// as `InsertionProof::verify` checks natively. Returns whether the proof is
+    /// valid, which shows that `id` was absent from the set, along with the root
+    /// of the set after the insertion, in which `id` is present.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pre_root, id))]
+    pub fn insert(
+        &self,
+        parameters: &ParametersVar,
+        pre_root: &AccRootVar,
+        id: &FpVar<ConstraintF>,
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError> {
+        let updated_low_leaf = ProcessedLeafVar {
+            key: self.low_leaf.key.clone(),
+            next: id.clone(),
+        };
+        let new_leaf = ProcessedLeafVar {
+            key: id.clone(),
+            next: self.low_leaf.next.clone(),
+        };
+        let brackets = self.low_leaf.brackets(id)?;
+        let low_exists = self.low_path.verify_membership(
+            parameters,
+            pre_root,
+            &self.low_leaf.to_leaf(parameters)?,
+        )?;
+        // The siblings of the updated leaf don't change, and so the same path
+        // leads to the root after the update.
+        let mid_root = self
+            .low_path
+            .calculate_root(parameters, &updated_low_leaf.to_leaf(parameters)?)?;
+        let blank = vec![UInt8::constant(0); 32];
+        let new_is_blank = self
+            .new_path
+            .verify_membership(parameters, &mid_root, &blank)?;
+        let post_root = self
+            .new_path
+            .calculate_root(parameters, &new_leaf.to_leaf(parameters)?)?;
+        let is_valid = Boolean::kary_and(&[brackets, low_exists, new_is_blank])?;
+        Ok((is_valid, post_root))
+    }
+}
+
+impl TransactionVar {
+    /// Record the transaction in the set of processed transactions with root
+    /// `pre_root`, by inserting its identifier as shown by `proof`. Returns
+    /// whether the proof is valid, which shows that the transaction was not
+    /// processed before, along with the root of the set after the insertion.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pre_root, proof))]
+    pub fn record_processed(
+        &self,
+        parameters: &ParametersVar,
+        pre_root: &AccRootVar,
+        proof: &InsertionProofVar,
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError> {
+        proof.insert(parameters, pre_root, &self.id(parameters)?)
+    }
+}
+
+impl AllocVar<ProcessedLeaf, ConstraintF> for ProcessedLeafVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<ProcessedLeaf>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|leaf| {
+            let leaf = leaf.borrow();
+            let key = FpVar::new_variable(cs.clone(), || Ok(leaf.key), mode)?;
+            let next = FpVar::new_variable(cs, || Ok(leaf.next), mode)?;
+            Ok(Self { key, next })
+        })
+    }
+}
+
+impl AllocVar<InsertionProof, ConstraintF> for InsertionProofVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<InsertionProof>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|proof| {
+            let proof = proof.borrow();
+            let low_leaf = ProcessedLeafVar::new_variable(cs.clone(), || Ok(proof.low_leaf), mode)?;
+            let low_path =
+                PositionedPathVar::new_variable(cs.clone(), || Ok(&proof.low_path), mode)?;
+            let new_path = PositionedPathVar::new_variable(cs, || Ok(&proof.new_path), mode)?;
+            Ok(Self {
+                low_leaf,
+                low_path,
+                new_path,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_ff::One;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{AccRoot, Parameters};
+    use ark_simple_payments::processed::ProcessedSet;
+    use ark_simple_payments::transaction::TransactionId;
+
+    /// Insert `id` into the set with root `pre_root` as shown by `proof` in a
+    /// fresh constraint system, and return whether the proof is valid and leads to
+    /// `post_root`.
+    fn insertion_is_valid(
+        pp: &Parameters,
+        pre_root: AccRoot,
+        post_root: AccRoot,
+        id: TransactionId,
+        proof: &InsertionProof,
+    ) -> bool {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let params = ParametersVar::new_constant(cs.clone(), pp).unwrap();
+        let pre_root = AccRootVar::new_input(cs.clone(), || Ok(pre_root)).unwrap();
+        let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_root)).unwrap();
+        let id = FpVar::new_witness(cs.clone(), || Ok(id)).unwrap();
+        let proof = InsertionProofVar::new_witness(cs.clone(), || Ok(proof)).unwrap();
+        let (is_valid, root) = proof.insert(&params, &pre_root, &id).unwrap();
+        is_valid.enforce_equal(&Boolean::TRUE).unwrap();
+        root.enforce_equal(&post_root).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn insertion_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut set = ProcessedSet::new(&pp, 3);
+        let id = TransactionId::from(42u64);
+        let pre_root = set.root();
+        let proof = set.insert(&pp, id).unwrap();
+        let post_root = set.root();
+
+        // The insertion is accepted, ...
+        assert!(insertion_is_valid(&pp, pre_root, post_root, id, &proof));
+        // ... but not that of another identifier, ...
+        let other = id + TransactionId::one();
+        assert!(!insertion_is_valid(&pp, pre_root, post_root, other, &proof));
+        // ... nor that of an identifier already in the set.
+        let post_root = set.root();
+        let proof = set.placeholder_proof();
+        assert!(!insertion_is_valid(&pp, post_root, post_root, id, &proof));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::account::{AccountPublicKeyVar, AccountSignatureVar};
+use crate::ledger::ParametersVar;
+use crate::rollup::Rollup;
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{
+    ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError,
+    TracingMode,
+};
+use ark_simple_payments::account::AccountSignature;
+use ark_simple_payments::ledger::Parameters;
+use ark_simple_payments::random_oracle::{
+    blake2s::{constraints, RO},
+    RandomOracleGadget,
+};
+use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+use ark_std::rand::Rng;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::{span, Subscriber};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+
+/// The number of constraints generated inside each instrumented span, keyed by
+/// the name of the span (e.g., `verify_signature`).
+///
+/// The counts are inclusive: constraints generated in nested spans also count
+/// towards their parents. Spans that are entered several times accumulate the
+/// constraints generated across all of their invocations.
+#[derive(Clone, Default)]
+pub struct ConstraintCounts(Arc<Mutex<BTreeMap<&'static str, usize>>>);
+
+impl ConstraintCounts {
+    /// Return the number of constraints generated inside spans named `name`.
+    pub fn get(&self, name: &str) -> usize {
+        self.0.lock().unwrap().get(name).copied().unwrap_or(0)
+    }
+
+    /// Return the number of constraints generated inside each span.
+    pub fn to_map(&self) -> BTreeMap<&'static str, usize> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+thread_local! {
+    /// The constraint system whose constraints are being counted. Subscribers must
+    /// be thread-safe, while constraint systems are not, and so the constraint
+    /// system is stored here rather than in `ConstraintCountLayer`.
+    static COUNTED_CS: RefCell<Option<ConstraintSystemRef<ConstraintF>>> =
+        const { RefCell::new(None) };
+}
+
+/// Return the number of constraints in the constraint system being counted on
+/// this thread, if any.
+fn num_constraints() -> Option<usize> {
+    COUNTED_CS.with(|cs| cs.borrow().as_ref().map(|cs| cs.num_constraints()))
+}
+
+/// A tracing layer recording `ConstraintCounts` for the `r1cs` spans.
+pub struct ConstraintCountLayer {
+    counts: ConstraintCounts,
+}
+
+/// The number of constraints in the constraint system when a span was entered.
+struct NumConstraintsOnEnter(usize);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ConstraintCountLayer {
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if span.metadata().target() == "r1cs" {
+                if let Some(num_constraints) = num_constraints() {
+                    span.extensions_mut()
+                        .replace(NumConstraintsOnEnter(num_constraints));
+                }
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let start = span.extensions_mut().remove::<NumConstraintsOnEnter>();
+            if let (Some(NumConstraintsOnEnter(start)), Some(end)) = (start, num_constraints()) {
+                let delta = end - start;
+                *self
+                    .counts
+                    .0
+                    .lock()
+                    .unwrap()
+                    .entry(span.metadata().name())
+                    .or_default() += delta;
+            }
+        }
+    }
+}
+
+/// Create a tracing layer that attributes the constraints generated in `cs` to
+/// the instrumented spans (such as `verify_signature` and
+/// `check_account_existence`) they are generated in. Once the layer is
+/// subscribed, the returned `ConstraintCounts` are updated whenever a span exits.
+///
+/// Only constraints generated on the current thread are counted, and only one
+/// constraint system can be counted per thread at a time.
+pub fn count_constraints_by_span(
+    cs: ConstraintSystemRef<ConstraintF>,
+) -> (ConstraintCountLayer, ConstraintCounts) {
+    COUNTED_CS.with(|counted| *counted.borrow_mut() = Some(cs));
+    let counts = ConstraintCounts::default();
+    let layer = ConstraintCountLayer {
+        counts: counts.clone(),
+    };
+    (layer, counts)
+}
+
+/// The constraints of a single signature verification, split by what they are
+/// spent on. See `signature_cost_breakdown`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignatureCostBreakdown {
+    /// The constraints of the whole verification.
+    pub total: usize,
+    /// The constraints of the scalar multiplications, i.e., of multiplying the
+    /// generator by the response and the public key by the challenge.
+    pub scalar_mul: usize,
+    /// The constraints of hashing the public key, the commitment and the message
+    /// into the challenge.
+    pub hashing: usize,
+}
+
+impl SignatureCostBreakdown {
+    /// The constraints spent on neither scalar multiplication nor hashing, such
+    /// as those of decomposing points and scalars into bits.
+    pub fn other(&self) -> usize {
+        self.total - self.scalar_mul - self.hashing
+    }
+}
+
+impl fmt::Display for SignatureCostBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} constraints: {} in scalar multiplications, {} in hashing, {} in the rest",
+            self.total,
+            self.scalar_mul,
+            self.hashing,
+            self.other()
+        )
+    }
+}
+
+/// Verify a single signature over a random message of `message_len` bytes
+/// under `parameters`, and break down the constraints that the verification
+/// generates. The signing key, the message and the signature are witnesses, so
+/// that their allocation isn't counted.
+///
+/// The scalar multiplications are counted by their span, while the hash of the
+/// challenge is measured on its own, over an input of the same length, since the
+/// random oracle is not instrumented.
+pub fn signature_cost_breakdown<R: Rng>(
+    parameters: &Parameters,
+    message_len: usize,
+    rng: &mut R,
+) -> SignatureCostBreakdown {
+    let (pk, sk) = Schnorr::keygen(&parameters.sig_params, rng).unwrap();
+    let message = (0..message_len).map(|_| rng.gen()).collect::<Vec<u8>>();
+    let signature = AccountSignature::sign(parameters, &sk, &message, rng);
+
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    let params = ParametersVar::new_constant(cs.clone(), parameters).unwrap();
+    let pk = AccountPublicKeyVar::new_witness(cs.clone(), || Ok(pk)).unwrap();
+    let message = UInt8::new_witness_vec(cs.clone(), &message).unwrap();
+    let signature = AccountSignatureVar::new_witness(cs.clone(), || Ok(signature)).unwrap();
+    let (layer, counts) = count_constraints_by_span(cs.clone());
+    let subscriber = tracing_subscriber::Registry::default().with(layer);
+    let start = cs.num_constraints();
+    tracing::subscriber::with_default(subscriber, || {
+        params
+            .verify_encoded_signature(&pk, &message, &signature)
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+    });
+    assert!(cs.is_satisfied().unwrap());
+    let total = cs.num_constraints() - start;
+
+    // The hashed input consists of (Salt || PublicKey || Commitment || Message),
+    // where both points take 64 bytes.
+    let salt_len = parameters.sig_params.salt.map_or(0, |salt| salt.len());
+    let hash_cs = ConstraintSystem::<ConstraintF>::new_ref();
+    let input = vec![0u8; salt_len + 2 * 64 + message_len];
+    let input = UInt8::new_witness_vec(hash_cs.clone(), &input).unwrap();
+    let start = hash_cs.num_constraints();
+    <constraints::ROGadget as RandomOracleGadget<RO, ConstraintF>>::evaluate(
+        &constraints::ParametersVar,
+        &input,
+    )
+    .unwrap();
+    let hashing = hash_cs.num_constraints() - start;
+
+    SignatureCostBreakdown {
+        total,
+        scalar_mul: counts.get("scalar_mul_le"),
+        hashing,
+    }
+}
+
+/// Count the constraints of the circuits of the batches `config_a` and
+/// `config_b` of `NUM_TX` transactions, which configure the circuit differently,
+/// e.g., with other ledger parameters. Returns both counts, and how many more
+/// constraints the circuit of `config_b` has.
+///
+/// Only the shape of the batches matters, and so their witnesses need not
+/// satisfy the circuit. They must still be set, though, as for the setup.
+pub fn compare_constraints<const NUM_TX: usize>(
+    config_a: Rollup<NUM_TX>,
+    config_b: Rollup<NUM_TX>,
+) -> (usize, usize, i64) {
+    let count = |config: Rollup<NUM_TX>| {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        config
+            .generate_constraints(cs.clone())
+            .expect("constraint generation should succeed");
+        cs.num_constraints()
+    };
+    let (count_a, count_b) = (count(config_a), count(config_b));
+    (count_a, count_b, count_b as i64 - count_a as i64)
+}
+
+/// A constraint that is not satisfied by the witness assigned to a constraint
+/// system.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsatisfiedConstraint {
+    /// The index of the constraint in the constraint system.
+    pub index: usize,
+    /// The path of `r1cs` spans the constraint was generated in (e.g.,
+    /// `rollup::transaction::validate/verify_signature`), if the constraint
+    /// system was generated with a `ConstraintLayer` subscribed.
+    pub trace: Option<String>,
+}
+
+impl fmt::Display for UnsatisfiedConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "constraint {} is not satisfied", self.index)?;
+        if let Some(trace) = &self.trace {
+            write!(f, " (in {})", trace)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnsatisfiedConstraint {}
+
+/// Return the first constraint of `cs` that is not satisfied by its witness, if
+/// any. `cs` must have been finalized, so that its constraints can be converted
+/// to matrices.
+pub fn first_unsatisfied_constraint(
+    cs: &ConstraintSystemRef<ConstraintF>,
+) -> Result<Option<UnsatisfiedConstraint>, SynthesisError> {
+    let matrices = cs.to_matrices().ok_or(SynthesisError::MissingCS)?;
+    let assignment = {
+        let cs = cs.borrow().ok_or(SynthesisError::MissingCS)?;
+        if cs.is_in_setup_mode() {
+            return Err(SynthesisError::AssignmentMissing);
+        }
+        cs.instance_assignment
+            .iter()
+            .chain(&cs.witness_assignment)
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    let evaluate = |row: &[(ConstraintF, usize)]| -> ConstraintF {
+        row.iter()
+            .map(|(coeff, var)| *coeff * assignment[*var])
+            .sum()
+    };
+    let index = (0..matrices.num_constraints)
+        .find(|&i| evaluate(&matrices.a[i]) * evaluate(&matrices.b[i]) != evaluate(&matrices.c[i]));
+    Ok(index.map(|index| UnsatisfiedConstraint {
+        index,
+        trace: cs
+            .constraint_names()
+            .and_then(|names| names.get(index).cloned()),
+    }))
+}
+
+/// Generate the constraints of `circuit`, with witness values, and check that
+/// they are satisfied. Otherwise, return the first unsatisfied constraint
+/// together with the spans it was generated in.
+///
+/// This is a debugging aid: it is much slower than generating a proof, and
+/// panics if `circuit` fails to generate its constraints.
+pub fn check_satisfied<C: ConstraintSynthesizer<ConstraintF>>(
+    circuit: C,
+) -> Result<(), UnsatisfiedConstraint> {
+    let mut layer = ConstraintLayer::default();
+    layer.mode = TracingMode::OnlyConstraints;
+    let subscriber = tracing_subscriber::Registry::default().with(layer);
+    let cs = ConstraintSystem::new_ref();
+    tracing::subscriber::with_default(subscriber, || circuit.generate_constraints(cs.clone()))
+        .expect("constraint generation should succeed");
+    cs.finalize();
+    match first_unsatisfied_constraint(&cs).expect("constraint system should have a witness") {
+        Some(unsatisfied) => Err(unsatisfied),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::UnaryRollup;
+    use ark_simple_payments::ledger::{Amount, State};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn constraint_counts_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup = UnaryRollup::with_state_and_transaction(pp, tx, &mut state, true).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        let (layer, counts) = count_constraints_by_span(cs.clone());
+        let subscriber = tracing_subscriber::Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            rollup.generate_constraints(cs.clone()).unwrap();
+        });
+        assert!(cs.is_satisfied().unwrap());
+
+        let validate = counts.get("validate");
+        let signature = counts.get("verify_signature");
+        let membership = counts.get("check_account_existence");
+        assert!(validate > 0 && membership > 0);
+        assert!(validate <= cs.num_constraints());
+        assert!(signature + membership <= validate);
+        // The signature check is the most expensive part of validation. Since
+        // account leaves hold a balance per asset, the membership checks of the
+        // two accounts cost more than they used to, but the signature check
+        // still makes up over a third of `validate`.
+        assert!(3 * signature > validate);
+    }
+
+    #[test]
+    fn compare_constraints_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let txs = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(3), &alice_sk, &mut rng),
+        ];
+        let without_receipts =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &txs, &mut state, true).unwrap();
+        // Requiring receipts adds the verification of a signature of the
+        // sequencer per transaction, whether the receipts are valid or not.
+        let (sequencer_pk, _sequencer_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut with_receipts = without_receipts.clone();
+        with_receipts.ledger_params.sequencer_key = Some(sequencer_pk);
+
+        let (count_a, count_b, delta) =
+            compare_constraints(without_receipts.clone(), with_receipts.clone());
+        assert_eq!(delta, count_b as i64 - count_a as i64);
+        // Each signature costs thousands of constraints, most of them hashing
+        // the challenge. Receipts sign 24-byte messages.
+        let breakdown = signature_cost_breakdown(&pp, 24, &mut rng);
+        assert!(delta > 2 * breakdown.hashing as i64);
+        let (_, _, reverse) = compare_constraints(with_receipts, without_receipts);
+        assert_eq!(reverse, -delta);
+    }
+
+    #[test]
+    fn signature_cost_breakdown_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let breakdown = signature_cost_breakdown(&pp, 32, &mut rng);
+        println!("{}", breakdown);
+        assert!(breakdown.hashing > 0);
+        assert!(breakdown.scalar_mul > 0);
+        assert!(breakdown.scalar_mul + breakdown.hashing <= breakdown.total);
+        // The scalar multiplications are cheap on the embedded curve, and so it is
+        // hashing the challenge, at several Blake2s blocks, that dominates.
+        assert!(2 * breakdown.hashing > breakdown.total);
+        assert!(breakdown.scalar_mul < breakdown.hashing);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::account::{AccountIdVar, AccountInformationVar};
+use crate::ledger::{AmountVar, ParametersVar};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::receipt::RECEIPT_TAG;
+
+/// Construct the message that the sequencer signs to certify that the balance of
+/// `account` changed from `pre_balance` to `post_balance`.
+fn message(
+    account: &AccountIdVar,
+    pre_balance: &AmountVar,
+    post_balance: &AmountVar,
+) -> Vec<UInt8<ConstraintF>> {
+    // The signed message consists of
+    // (Tag || AccountId || PreBalance || PostBalance)
+    let mut message = UInt8::constant_vec(RECEIPT_TAG);
+    message.extend(account.to_bytes_le());
+    message.extend(pre_balance.to_bytes_le());
+    message.extend(post_balance.to_bytes_le());
+    message
+}
+
+impl TransactionVar {
+    /// Verify the receipt of the transaction for the balance change of the sender,
+    /// from `pre_sender_acc_info` to `post_sender_acc_info`. This always holds if
+    /// the ledger doesn't require receipts.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, parameters, pre_sender_acc_info, post_sender_acc_info)
+    )]
+    pub fn verify_receipt(
+        &self,
+        parameters: &ParametersVar,
+        pre_sender_acc_info: &AccountInformationVar,
+        post_sender_acc_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let sequencer_key = match &parameters.sequencer_key {
+            Some(key) => key,
+            None => return Ok(Boolean::TRUE),
+        };
+        let message = message(
+            &self.sender,
+            &pre_sender_acc_info.balances.get(&self.asset_id)?,
+            &post_sender_acc_info.balances.get(&self.asset_id)?,
+        );
+        parameters.verify_signature(sequencer_key, message, &self.receipt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::UnaryRollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::receipt::sign_receipt;
+    use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn receipt_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        let (sequencer_pk, sequencer_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        pp.sequencer_key = Some(sequencer_pk);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let mut tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        let alice_info = state.id_to_account_info[&alice_id];
+        tx.receipt = tx.sign_receipt(&pp, &alice_info, &sequencer_sk, &mut rng);
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state, true).unwrap();
+        let is_satisfied = |rollup: UnaryRollup| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(is_satisfied(rollup.clone()));
+
+        // A receipt for another post balance is rejected.
+        let mut bad_rollup = rollup;
+        bad_rollup.transaction.receipt = Some(sign_receipt(
+            &pp,
+            &sequencer_sk,
+            alice_id,
+            Amount(10),
+            Amount(7),
+            &mut rng,
+        ));
+        assert!(!is_satisfied(bad_rollup));
+    }
+}
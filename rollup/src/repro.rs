@@ -0,0 +1,562 @@
+//! Conversion of the native inputs of a `Rollup` to and from JSON, so that a
+//! batch that fails to prove can be reproduced elsewhere, e.g., in a bug report.
+//!
+//! Field elements and curve points are encoded as the hex string of their
+//! compressed `CanonicalSerialize` encoding. The ledger parameters are not
+//! included, since they are public and much larger than the rest of the witness.
+
+use crate::rollup::Rollup;
+use ark_ed_on_bls12_381::{EdwardsAffine, Fq, Fr};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_simple_payments::account::{AccountId, AccountInformation, AccountSignature};
+use ark_simple_payments::allowlist::AllowlistProof;
+use ark_simple_payments::delegation::SpendingAuthorization;
+use ark_simple_payments::ledger::{
+    AccPath, Amount, AssetId, Balances, Parameters, TxCounter, NUM_ASSETS,
+};
+use ark_simple_payments::processed::{InsertionProof, ProcessedLeaf};
+use ark_simple_payments::shard::Shard;
+use ark_simple_payments::signature::schnorr::{PointSignature, Signature};
+use ark_simple_payments::transaction::Transaction;
+use serde_json::{json, Value};
+use std::convert::TryInto;
+
+/// Types that can be converted to and from JSON.
+pub trait Json: Sized {
+    /// Convert `self` to JSON.
+    fn to_json(&self) -> Value;
+    /// Returns `None` if `value` is not a valid encoding.
+    fn from_json(value: &Value) -> Option<Self>;
+}
+
+macro_rules! impl_json_hex {
+    ($($ty:ty),*) => {$(
+        impl Json for $ty {
+            fn to_json(&self) -> Value {
+                let mut bytes = Vec::new();
+                self.serialize(&mut bytes).expect("serialization to a vector can't fail");
+                Value::String(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+            }
+
+            fn from_json(value: &Value) -> Option<Self> {
+                let hex = value.as_str()?;
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<Vec<_>>>()?;
+                Self::deserialize(&bytes[..]).ok()
+            }
+        }
+    )*};
+}
+
+impl_json_hex!(Fq, Fr, EdwardsAffine);
+
+impl Json for u64 {
+    fn to_json(&self) -> Value {
+        json!(self)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_u64()
+    }
+}
+
+impl Json for u8 {
+    fn to_json(&self) -> Value {
+        json!(self)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_u64()?.try_into().ok()
+    }
+}
+
+impl Json for bool {
+    fn to_json(&self) -> Value {
+        json!(self)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl Json for AccountId {
+    fn to_json(&self) -> Value {
+        json!(self.0)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self(value.as_u64()?.try_into().ok()?))
+    }
+}
+
+impl Json for AssetId {
+    fn to_json(&self) -> Value {
+        json!(self.0)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self(value.as_u64()?.try_into().ok()?))
+    }
+}
+
+impl Json for Amount {
+    fn to_json(&self) -> Value {
+        json!(self.0)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self(value.as_u64()?))
+    }
+}
+
+impl<T: Json> Json for Option<T> {
+    fn to_json(&self) -> Value {
+        self.as_ref().map_or(Value::Null, T::to_json)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            value => Some(Some(T::from_json(value)?)),
+        }
+    }
+}
+
+impl<T: Json> Json for Vec<T> {
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(T::to_json).collect())
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_array()?.iter().map(T::from_json).collect()
+    }
+}
+
+impl<A: Json, B: Json> Json for (A, B) {
+    fn to_json(&self) -> Value {
+        json!([self.0.to_json(), self.1.to_json()])
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        match value.as_array()?.as_slice() {
+            [a, b] => Some((A::from_json(a)?, B::from_json(b)?)),
+            _ => None,
+        }
+    }
+}
+
+impl Json for AccPath {
+    fn to_json(&self) -> Value {
+        json!({
+            "leaf_sibling_hash": self.leaf_sibling_hash.to_json(),
+            "auth_path": self.auth_path.to_json(),
+            "leaf_index": self.leaf_index,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            leaf_sibling_hash: Json::from_json(&value["leaf_sibling_hash"])?,
+            auth_path: Json::from_json(&value["auth_path"])?,
+            leaf_index: value["leaf_index"].as_u64()?.try_into().ok()?,
+        })
+    }
+}
+
+impl Json for AllowlistProof {
+    fn to_json(&self) -> Value {
+        self.0.to_json()
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Json::from_json(value).map(Self)
+    }
+}
+
+impl Json for AccountInformation {
+    fn to_json(&self) -> Value {
+        json!({
+            "public_key": self.public_key.to_json(),
+            "recovery_key": self.recovery_key.to_json(),
+            "balances": self.balances.0.to_vec().to_json(),
+            "nonce": self.nonce,
+            "spent_today": self.spent_today.to_json(),
+            "limit_epoch": self.limit_epoch,
+            "held": self.held.to_json(),
+            "delegated_spent": self.delegated_spent.to_json(),
+            "balance_cap": self.balance_cap.to_json(),
+            "key_index": self.key_index,
+            "last_credited_epoch": self.last_credited_epoch,
+            "allowlist": self.allowlist.to_json(),
+            "metadata_hash": self.metadata_hash.to_json(),
+            "signers": self.signers.to_json(),
+            "threshold": self.threshold,
+            "accepts_transfers": self.accepts_transfers,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let balances: Vec<Amount> = Json::from_json(&value["balances"])?;
+        let balances: [Amount; NUM_ASSETS] = balances.try_into().ok()?;
+        Some(Self {
+            public_key: Json::from_json(&value["public_key"])?,
+            recovery_key: Json::from_json(&value["recovery_key"])?,
+            balances: Balances(balances),
+            nonce: value["nonce"].as_u64()?,
+            spent_today: Json::from_json(&value["spent_today"])?,
+            limit_epoch: value["limit_epoch"].as_u64()?,
+            held: Json::from_json(&value["held"])?,
+            delegated_spent: Json::from_json(&value["delegated_spent"])?,
+            balance_cap: Json::from_json(&value["balance_cap"])?,
+            key_index: value["key_index"].as_u64()?,
+            last_credited_epoch: value["last_credited_epoch"].as_u64()?,
+            allowlist: Json::from_json(&value["allowlist"])?,
+            metadata_hash: Json::from_json(&value["metadata_hash"])?,
+            signers: Json::from_json(&value["signers"])?,
+            threshold: Json::from_json(&value["threshold"])?,
+            accepts_transfers: value["accepts_transfers"].as_bool()?,
+        })
+    }
+}
+
+impl Json for AccountSignature {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Challenge(signature) => json!({
+                "prover_response": signature.prover_response.to_json(),
+                "verifier_challenge": signature.verifier_challenge.to_vec(),
+            }),
+            Self::Point(signature) => json!({
+                "prover_commitment": signature.prover_commitment.to_json(),
+                "prover_response": signature.prover_response.to_json(),
+            }),
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let prover_response = Json::from_json(&value["prover_response"])?;
+        if value["prover_commitment"].is_null() {
+            let verifier_challenge = value["verifier_challenge"]
+                .as_array()?
+                .iter()
+                .map(|byte| byte.as_u64()?.try_into().ok())
+                .collect::<Option<Vec<u8>>>()?;
+            Some(Self::Challenge(Signature {
+                prover_response,
+                verifier_challenge: verifier_challenge.try_into().ok()?,
+            }))
+        } else {
+            Some(Self::Point(PointSignature {
+                prover_commitment: Json::from_json(&value["prover_commitment"])?,
+                prover_response,
+            }))
+        }
+    }
+}
+
+impl Json for SpendingAuthorization {
+    fn to_json(&self) -> Value {
+        json!({
+            "delegate": self.delegate.to_json(),
+            "cap": self.cap.to_json(),
+            "signature": self.signature.to_json(),
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            delegate: Json::from_json(&value["delegate"])?,
+            cap: Json::from_json(&value["cap"])?,
+            signature: Json::from_json(&value["signature"])?,
+        })
+    }
+}
+
+impl Json for Transaction {
+    fn to_json(&self) -> Value {
+        json!({
+            "sender": self.sender.to_json(),
+            "recipient": self.recipient.to_json(),
+            "asset_id": self.asset_id.to_json(),
+            "amount": self.amount.to_json(),
+            "fee": self.fee.to_json(),
+            "expiry_block_commitment": self.expiry_block_commitment.to_json(),
+            "params_commitment": self.params_commitment.to_json(),
+            "l1_address": self.l1_address.to_vec(),
+            "scale": self.scale.to_json(),
+            "signature": self.signature.to_json(),
+            "receipt": self.receipt.to_json(),
+            "consent": self.consent.to_json(),
+            "allowlist_proof": self.allowlist_proof.to_json(),
+            "authorization": self.authorization.to_json(),
+            "pow_nonce": self.pow_nonce,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let l1_address = value["l1_address"]
+            .as_array()?
+            .iter()
+            .map(|byte| byte.as_u64()?.try_into().ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Self {
+            sender: Json::from_json(&value["sender"])?,
+            recipient: Json::from_json(&value["recipient"])?,
+            asset_id: Json::from_json(&value["asset_id"])?,
+            amount: Json::from_json(&value["amount"])?,
+            fee: Json::from_json(&value["fee"])?,
+            expiry_block_commitment: Json::from_json(&value["expiry_block_commitment"])?,
+            params_commitment: Json::from_json(&value["params_commitment"])?,
+            // Addresses must have exactly 20 bytes.
+            l1_address: l1_address.try_into().ok()?,
+            scale: Json::from_json(&value["scale"])?,
+            signature: Json::from_json(&value["signature"])?,
+            receipt: Json::from_json(&value["receipt"])?,
+            consent: Json::from_json(&value["consent"])?,
+            allowlist_proof: Json::from_json(&value["allowlist_proof"])?,
+            authorization: Json::from_json(&value["authorization"])?,
+            pow_nonce: value["pow_nonce"].as_u64()?,
+        })
+    }
+}
+
+impl Json for Shard {
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id.to_json(),
+            "log_size": self.log_size.to_json(),
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Shard::new(
+            Json::from_json(&value["id"])?,
+            Json::from_json(&value["log_size"])?,
+        )
+    }
+}
+
+impl Json for TxCounter {
+    fn to_json(&self) -> Value {
+        json!({ "count": self.count, "epoch": self.epoch })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            count: value["count"].as_u64()?,
+            epoch: value["epoch"].as_u64()?,
+        })
+    }
+}
+
+impl Json for ProcessedLeaf {
+    fn to_json(&self) -> Value {
+        json!({ "key": self.key.to_json(), "next": self.next.to_json() })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            key: Json::from_json(&value["key"])?,
+            next: Json::from_json(&value["next"])?,
+        })
+    }
+}
+
+impl Json for InsertionProof {
+    fn to_json(&self) -> Value {
+        json!({
+            "low_leaf": self.low_leaf.to_json(),
+            "low_path": self.low_path.to_json(),
+            "mid_root": self.mid_root.to_json(),
+            "new_path": self.new_path.to_json(),
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            low_leaf: Json::from_json(&value["low_leaf"])?,
+            low_path: Json::from_json(&value["low_path"])?,
+            mid_root: Json::from_json(&value["mid_root"])?,
+            new_path: Json::from_json(&value["new_path"])?,
+        })
+    }
+}
+
+impl<const NUM_TX: usize> Rollup<NUM_TX> {
+    /// Convert the native inputs of this rollup to JSON.
+    pub fn witness_to_json(&self) -> Value {
+        json!({
+            "num_transactions": NUM_TX,
+            "initial_root": self.initial_root.to_json(),
+            "final_root": self.final_root.to_json(),
+            "withdrawal_root": self.withdrawal_root.to_json(),
+            "pre_epoch": self.pre_epoch.to_json(),
+            "post_epoch": self.post_epoch.to_json(),
+            "block_commitment": self.block_commitment.to_json(),
+            "transactions": self.transactions.to_json(),
+            "sender_pre_tx_info_and_paths": self.sender_pre_tx_info_and_paths.to_json(),
+            "sender_post_paths": self.sender_post_paths.to_json(),
+            "recv_pre_tx_info_and_paths": self.recv_pre_tx_info_and_paths.to_json(),
+            "recv_post_paths": self.recv_post_paths.to_json(),
+            "pre_tx_roots": self.pre_tx_roots.to_json(),
+            "post_tx_roots": self.post_tx_roots.to_json(),
+            "tx_counter": self.tx_counter.to_json(),
+            "tx_counter_path": self.tx_counter_path.to_json(),
+            "applied": self.applied.to_json(),
+            "untouched_account": self.untouched_account.to_json(),
+            "max_touched": self.max_touched.to_json(),
+            "fee_asset": self.fee_asset.to_json(),
+            "total_supply": self.total_supply.to_json(),
+            "scale": self.scale.to_json(),
+            "processed_roots": self.processed_roots.to_json(),
+            "processed_proofs": self.processed_proofs.to_json(),
+            "tree_height": self.tree_height.to_json(),
+            "dust_threshold": self.dust_threshold.to_json(),
+            "diff_commitment": self.diff_commitment.to_json(),
+            "post_tx_infos": self.post_tx_infos.to_json(),
+            "hash_public_inputs": self.hash_public_inputs.to_json(),
+            "public_paths": self.public_paths.to_json(),
+            "prev_batch_commitment": self.prev_batch_commitment.to_json(),
+            "prev_batch_parent": self.prev_batch_parent.to_json(),
+            "base_fee": self.base_fee.to_json(),
+            "fee_collector_info_and_path": self.fee_collector_info_and_path.to_json(),
+            "sequencer_id": self.sequencer_id.to_json(),
+            "sequencer_key": self.sequencer_key.to_json(),
+            "sequencer_signature": self.sequencer_signature.to_json(),
+            "shard": self.shard.to_json(),
+            "protected_tx": self.protected_tx.to_json(),
+            "asset_roots": self.asset_roots.to_json(),
+            "pow_difficulty": self.pow_difficulty.to_json(),
+        })
+    }
+
+    /// Reconstruct a rollup with the ledger parameters `ledger_params` from the
+    /// JSON produced by `witness_to_json`. Returns `None` if `value` is not a
+    /// valid encoding, or if it encodes a batch of a different size.
+    pub fn witness_from_json(ledger_params: Parameters, value: &Value) -> Option<Self> {
+        if value["num_transactions"].as_u64()? != NUM_TX as u64 {
+            return None;
+        }
+        let field = |name: &str| &value[name];
+        Some(Self {
+            ledger_params,
+            initial_root: Json::from_json(field("initial_root"))?,
+            final_root: Json::from_json(field("final_root"))?,
+            withdrawal_root: Json::from_json(field("withdrawal_root"))?,
+            pre_epoch: Json::from_json(field("pre_epoch"))?,
+            post_epoch: Json::from_json(field("post_epoch"))?,
+            block_commitment: Json::from_json(field("block_commitment"))?,
+            transactions: Json::from_json(field("transactions"))?,
+            sender_pre_tx_info_and_paths: Json::from_json(field("sender_pre_tx_info_and_paths"))?,
+            sender_post_paths: Json::from_json(field("sender_post_paths"))?,
+            recv_pre_tx_info_and_paths: Json::from_json(field("recv_pre_tx_info_and_paths"))?,
+            recv_post_paths: Json::from_json(field("recv_post_paths"))?,
+            pre_tx_roots: Json::from_json(field("pre_tx_roots"))?,
+            post_tx_roots: Json::from_json(field("post_tx_roots"))?,
+            tx_counter: Json::from_json(field("tx_counter"))?,
+            tx_counter_path: Json::from_json(field("tx_counter_path"))?,
+            applied: Json::from_json(field("applied"))?,
+            untouched_account: Json::from_json(field("untouched_account"))?,
+            max_touched: Json::from_json(field("max_touched"))?,
+            fee_asset: Json::from_json(field("fee_asset"))?,
+            total_supply: Json::from_json(field("total_supply"))?,
+            scale: Json::from_json(field("scale"))?,
+            processed_roots: Json::from_json(field("processed_roots"))?,
+            processed_proofs: Json::from_json(field("processed_proofs"))?,
+            tree_height: Json::from_json(field("tree_height"))?,
+            dust_threshold: Json::from_json(field("dust_threshold"))?,
+            diff_commitment: Json::from_json(field("diff_commitment"))?,
+            post_tx_infos: Json::from_json(field("post_tx_infos"))?,
+            hash_public_inputs: Json::from_json(field("hash_public_inputs"))?,
+            public_paths: Json::from_json(field("public_paths"))?,
+            prev_batch_commitment: Json::from_json(field("prev_batch_commitment"))?,
+            prev_batch_parent: Json::from_json(field("prev_batch_parent"))?,
+            base_fee: Json::from_json(field("base_fee"))?,
+            fee_collector_info_and_path: Json::from_json(field("fee_collector_info_and_path"))?,
+            sequencer_id: Json::from_json(field("sequencer_id"))?,
+            sequencer_key: Json::from_json(field("sequencer_key"))?,
+            sequencer_signature: Json::from_json(field("sequencer_signature"))?,
+            shard: Json::from_json(field("shard"))?,
+            protected_tx: Json::from_json(field("protected_tx"))?,
+            asset_roots: Json::from_json(field("asset_roots"))?,
+            pow_difficulty: Json::from_json(field("pow_difficulty"))?,
+        })
+    }
+
+    /// If the witness of this rollup doesn't satisfy the circuit, write its native
+    /// inputs to the file at `path` as JSON, so that the failure can be reproduced
+    /// with `load_witness`. Returns whether the witness was written.
+    pub fn dump_failing_witness(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<bool> {
+        if self.check_satisfied().is_ok() {
+            return Ok(false);
+        }
+        let json = serde_json::to_string_pretty(&self.witness_to_json())?;
+        std::fs::write(path, json)?;
+        Ok(true)
+    }
+
+    /// Load a rollup with the ledger parameters `ledger_params` from the file at
+    /// `path`, written by `dump_failing_witness`.
+    pub fn load_witness(
+        ledger_params: Parameters,
+        path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<Self> {
+        let value: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        Self::witness_from_json(ledger_params, &value).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid rollup witness")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments::ledger::State;
+
+    #[test]
+    fn dump_failing_witness_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let path = std::env::temp_dir().join(format!("rollup-repro-{}.json", std::process::id()));
+
+        // A valid batch is not dumped.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state.clone(), true)
+                .unwrap();
+        assert!(!rollup.dump_failing_witness(&path).unwrap());
+        assert!(!path.exists());
+
+        // Bob can't sign on behalf of Alice.
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            &[bad_tx],
+            &mut state.clone(),
+            false,
+        )
+        .unwrap();
+        let failure = rollup.check_satisfied().unwrap_err();
+        assert!(rollup.dump_failing_witness(&path).unwrap());
+
+        // The dump fails in the same way as the original rollup.
+        let reloaded = Rollup::<1>::load_witness(pp.clone(), &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.witness_to_json(), rollup.witness_to_json());
+        let reloaded_failure = reloaded.check_satisfied().unwrap_err();
+        assert_eq!(reloaded_failure.to_string(), failure.to_string());
+        // It can only be reloaded as a batch of the same size.
+        let json = rollup.witness_to_json();
+        assert!(Rollup::<2>::witness_from_json(pp, &json).is_none());
+    }
+}
@@ -1,15 +1,46 @@
-use crate::account::AccountInformationVar;
+use crate::account::{
+    AccountIdVar, AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar,
+};
+use crate::asset_trees::AssetRootsVar;
+use crate::batch::{batch_output_commitment, verify_batch_authorization};
+use crate::fee_market::collect_fees;
+use crate::inputs::hash_input_vars;
 use crate::ledger::*;
-use crate::transaction::TransactionVar;
+use crate::processed::InsertionProofVar;
+use crate::profiling::{self, UnsatisfiedConstraint};
+use crate::shard::ShardVar;
+use crate::transaction::{
+    AccountWitnessVar, PostState, PreState, TransactionVar, TransactionWitnessVar,
+};
+use crate::withdrawal::{withdrawal_root, WithdrawalRootVar, WithdrawalVar};
 use crate::ConstraintF;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_r1cs_std::ToConstraintFieldGadget;
+use ark_relations::r1cs::{
+    ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, OptimizationGoal, SynthesisError,
+};
 use ark_simple_payments::{
-    account::AccountInformation,
-    ledger::{AccPath, AccRoot, Parameters, State},
-    transaction::Transaction,
+    account::{
+        AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+    },
+    asset_trees::asset_roots_digest,
+    batch::{self, BatchCommitment},
+    diff::{diff_commitment, DiffCommitment},
+    ledger::{
+        AccPath, AccRoot, Amount, AssetId, BlockCommitment, Parameters, State, TxCounter,
+        NUM_ASSETS,
+    },
+    processed::{InsertionProof, ProcessedSet},
+    shard::Shard,
+    transaction::{self, Transaction, TransactionHash},
+    withdrawal::{WithdrawalRoot, WithdrawalTree, WITHDRAWAL_ACCOUNT},
 };
+use ark_std::rand::Rng;
+use std::ops::Range;
 
+#[derive(Clone)]
 pub struct Rollup<const NUM_TX: usize> {
     /// The ledger parameters.
     pub ledger_params: Parameters,
@@ -17,26 +48,191 @@ pub struct Rollup<const NUM_TX: usize> {
     pub initial_root: Option<AccRoot>,
     /// The Merkle tree root after applying this batch of transactions.
     pub final_root: Option<AccRoot>,
+    /// The root of the withdrawal tree of this batch of transactions.
+    pub withdrawal_root: Option<WithdrawalRoot>,
+    /// The ledger epoch before applying this batch of transactions.
+    pub pre_epoch: Option<u64>,
+    /// The ledger epoch after applying this batch of transactions. This must be
+    /// `pre_epoch + 1`.
+    pub post_epoch: Option<u64>,
+    /// The commitment to the block at which this batch of transactions is
+    /// applied. Transactions bound to any other block are rejected.
+    pub block_commitment: Option<BlockCommitment>,
     /// The current batch of transactions.
     pub transactions: Option<Vec<Transaction>>,
     /// The sender's account information and corresponding authentication path,
     /// *before* applying the transactions.
     pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
-    /// The authentication path corresponding to the sender's account information
-    /// *after* applying the transactions.
-    pub sender_post_paths: Option<Vec<AccPath>>,
-    /// The recipient's account information and corresponding authentication path,
-    /// *before* applying the transactions.
-    pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
+    /// The recipient's account information *before* applying the transactions.
+    pub recv_pre_tx_infos: Option<Vec<AccountInformation>>,
     /// The authentication path corresponding to the recipient's account information
-    /// *after* applying the transactions.
+    /// *after* applying the transactions. The recipient is updated after the
+    /// sender, on this same path, see `TransactionVar::update_accounts`.
     pub recv_post_paths: Option<Vec<AccPath>>,
+    /// Whether each transaction in this batch is applied, or skipped because it
+    /// is invalid. If set, the flags are declared as public inputs after the
+    /// parameters commitment, and a skipped transaction must leave the root
+    /// unchanged instead of failing the whole batch. Skipping a transaction
+    /// doesn't prove that it is invalid, and transactions that break the amount
+    /// rules can't be skipped, since these rules only depend on the transaction.
+    /// Like `untouched_account`, this changes the shape of the circuit.
+    pub applied: Option<Vec<bool>>,
     /// List of state roots, so that the i-th root is the state roots before applying
     /// the i-th transaction. This means that `pre_tx_roots[0] == initial_root`.
     pub pre_tx_roots: Option<Vec<AccRoot>>,
     /// List of state roots, so that the i-th root is the state root after applying
-    /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`.
+    /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`,
+    /// unless the batch is recorded in the transaction counter.
     pub post_tx_roots: Option<Vec<AccRoot>>,
+    /// The transaction counter before applying this batch of transactions. This
+    /// is only used if the ledger limits the number of transactions per epoch.
+    pub tx_counter: Option<TxCounter>,
+    /// The authentication path of the transaction counter. The batch is recorded
+    /// in the counter after applying all of its transactions.
+    pub tx_counter_path: Option<AccPath>,
+    /// An account that none of the transactions in this batch may send from or
+    /// to. If set, it is declared as a public input after the applied flags, if
+    /// any, so that the proof also shows that this account was untouched by the
+    /// batch.
+    /// This changes the shape of the circuit, and so it must also be set when
+    /// generating the parameters of the proof system.
+    pub untouched_account: Option<AccountId>,
+    /// The maximum number of distinct accounts that the transactions in this batch
+    /// may send from or to, so that the number of leaves that the batch updates is
+    /// bounded. The recipients of withdrawals don't count, since withdrawals only
+    /// update their sender. Like `untouched_account`, this changes the shape of the
+    /// circuit.
+    pub max_touched: Option<u64>,
+    /// The asset that all the transactions in this batch must pay their fees in,
+    /// so that the sequencer only collects fees in a single asset. If set, it is
+    /// declared as a public input after the untouched account, if any.
+    /// Transactions that pay no fee may transfer any asset. Like
+    /// `untouched_account`, this changes the shape of the circuit.
+    pub fee_asset: Option<AssetId>,
+    /// The total supply of the ledger, which no balance of an account touched by
+    /// this batch may exceed, so that a prover can't mint value into a single
+    /// account. If set, it is declared as a public input after the fee asset, if
+    /// any. Both accounts of each transaction are checked before it, and the
+    /// recipient also after it, since only the recipient's balance grows. Note
+    /// that the recipients of skipped transactions are checked as if they were
+    /// applied. Like `untouched_account`, this changes the shape of the circuit.
+    pub total_supply: Option<Amount>,
+    /// The fixed-point scale, as a number of decimals, that all the transactions
+    /// in this batch must express their amounts in, so that a prover can't mix
+    /// in an amount with a different scale. If set, it is declared as a public
+    /// input after the total supply, if any. No-op transactions may have any
+    /// scale. Like `untouched_account`, this changes the shape of the circuit.
+    pub scale: Option<u8>,
+    /// The roots of the set of processed transactions before and after this
+    /// batch, if the batch records its transactions in the set, see
+    /// `with_processed_set`. If set, they are declared as public inputs after
+    /// the scale, if any. Each applied transaction that is not a no-op must then
+    /// be absent from the set before it, and is present after it, so that it
+    /// can't be applied again in a later batch. Like `untouched_account`, this
+    /// changes the shape of the circuit.
+    pub processed_roots: Option<(AccRoot, AccRoot)>,
+    /// The proof of the insertion of each transaction in this batch into the set
+    /// of processed transactions. The proofs of the transactions that are not
+    /// recorded are placeholders.
+    pub processed_proofs: Option<Vec<InsertionProof>>,
+    /// The height of the account tree. If set, it is declared as a public input
+    /// after the roots of the set of processed transactions, if any, and every
+    /// authentication path of the batch must belong to a tree of this height,
+    /// so that the proof can't be made for a smaller, less secure, tree. Like
+    /// `untouched_account`, this changes the shape of the circuit.
+    pub tree_height: Option<u64>,
+    /// The dust threshold of this batch. If set, it is declared as a public
+    /// input after the tree height, if any, and each transfer in the batch must
+    /// move at least this amount, and leave the balance of its sender either
+    /// empty or at least this amount. Like `untouched_account`, this changes the
+    /// shape of the circuit.
+    pub dust_threshold: Option<Amount>,
+    /// The commitment to the updates that this batch makes to the leaves of the
+    /// account tree, see `diff::diff_commitment`, if the batch commits to them,
+    /// see `with_diff_commitment`. If set, it is declared as a public input
+    /// after the dust threshold, if any. Only the applied transactions that are
+    /// not control operations are committed to. Like `untouched_account`, this
+    /// changes the shape of the circuit.
+    pub diff_commitment: Option<DiffCommitment>,
+    /// The information of the sender and of the recipient after each transaction,
+    /// which opens the diff commitment. That of the transactions that are not
+    /// committed to is arbitrary.
+    pub post_tx_infos: Option<Vec<(AccountInformation, AccountInformation)>>,
+    /// Whether the public inputs are hashed into a single public input, see
+    /// `inputs::public_input_hash`, so that the proof is cheaper to verify
+    /// inside another circuit. If set, all the other public inputs are declared
+    /// as witnesses instead, and their hash is the only public input. Like
+    /// `untouched_account`, this changes the shape of the circuit.
+    pub hash_public_inputs: bool,
+    /// Whether the paths of the senders and the recipients of the transactions
+    /// are declared as public inputs rather than witnesses, e.g., because they
+    /// are published on-chain, see `inputs::path_inputs`. If set, they are
+    /// declared after all the other public inputs, or after their hash if they
+    /// are hashed, and they are not hashed themselves. Like `untouched_account`,
+    /// this changes the shape of the circuit.
+    pub public_paths: bool,
+    /// The output commitment of the previous batch, see
+    /// `batch::batch_output_commitment`, if this batch extends a chain of
+    /// batches, see `with_prev_batch`. If set, it is declared as a public input
+    /// after the diff commitment, if any, and it must commit to the initial root
+    /// and the pre-batch epoch of this batch. Like `untouched_account`, this
+    /// changes the shape of the circuit.
+    pub prev_batch_commitment: Option<BatchCommitment>,
+    /// The output commitment that the previous batch extends, which opens
+    /// `prev_batch_commitment`.
+    pub prev_batch_parent: Option<BatchCommitment>,
+    /// The base fee that each applied transaction of this batch must pay, see
+    /// `fee_market`. It is declared as a public input after the output
+    /// commitment of the previous batch, if any, if the ledger has a fee market.
+    pub base_fee: Option<Amount>,
+    /// The information of the fee collector after the transactions of this
+    /// batch, along with its path, if the ledger has a fee market. The priority
+    /// fees of the applied transactions are then credited to it, before the
+    /// batch is recorded in the transaction counter.
+    pub fee_collector_info_and_path: Option<(AccountInformation, AccPath)>,
+    /// The account of the sequencer, which must collect the priority fees of
+    /// this batch, see `with_sequencer_id`, so that a prover can't redirect them
+    /// to another account. If set, it is declared as a public input after the
+    /// base fee, and it must be the fee collector of the ledger, which must then
+    /// have a fee market. Like `untouched_account`, this changes the shape of
+    /// the circuit.
+    pub sequencer_id: Option<AccountId>,
+    /// The public key of the sequencer that must authorize this batch, see
+    /// `with_sequencer_signature`, so that only batches submitted by an
+    /// authorized sequencer are accepted. If set, it is declared as a public
+    /// input after the account of the sequencer, if any. Like
+    /// `untouched_account`, this changes the shape of the circuit.
+    pub sequencer_key: Option<AccountPublicKey>,
+    /// The signature of the sequencer authorizing this batch, see
+    /// `batch::sign_batch`.
+    pub sequencer_signature: Option<AccountSignature>,
+    /// The shard that the applied transactions of this batch must stay within,
+    /// see `with_shard`, if the ledger is sharded. If set, the identifier of
+    /// the shard is declared as a public input after the key of the sequencer,
+    /// if any, while its size is part of the shape of the circuit. Like
+    /// `untouched_account`, this changes the shape of the circuit.
+    pub shard: Option<Shard>,
+    /// The hash of the transaction of this batch that is protected from
+    /// front-running, along with its position in the batch, see
+    /// `with_protected_transaction`. If set, both are declared as public inputs
+    /// after the identifier of the shard, if any, and no applied transaction
+    /// before the protected one may touch any of its accounts. Like
+    /// `untouched_account`, this changes the shape of the circuit.
+    pub protected_tx: Option<(TransactionHash, u64)>,
+    /// The roots of the account tree of each asset before and after this batch,
+    /// in order of asset identifier, if each asset has a tree of its own, see
+    /// `with_asset_trees`. `initial_root` and `final_root` are then the digests
+    /// of these roots, see `asset_trees::asset_roots_digest`, rather than the
+    /// roots of a single tree, and each transaction is checked against the tree
+    /// of its asset, leaving the other trees unchanged. Like `untouched_account`,
+    /// this changes the shape of the circuit.
+    pub asset_roots: Option<Vec<(AccRoot, AccRoot)>>,
+    /// The difficulty that the proof of work of each applied transaction of this
+    /// batch must meet, see `with_pow_difficulty`, if the batch requires proofs
+    /// of work. If set, it is declared as a public input after the protected
+    /// transaction, if any. Like `untouched_account`, this changes the shape of
+    /// the circuit.
+    pub pow_difficulty: Option<u8>,
 }
 
 impl<const NUM_TX: usize> Rollup<NUM_TX> {
@@ -45,13 +241,43 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
             ledger_params,
             initial_root: None,
             final_root: None,
+            withdrawal_root: None,
+            pre_epoch: None,
+            post_epoch: None,
+            block_commitment: None,
             transactions: None,
             sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
-            recv_pre_tx_info_and_paths: None,
+            recv_pre_tx_infos: None,
             recv_post_paths: None,
+            applied: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            tx_counter: None,
+            tx_counter_path: None,
+            untouched_account: None,
+            max_touched: None,
+            fee_asset: None,
+            total_supply: None,
+            scale: None,
+            processed_roots: None,
+            processed_proofs: None,
+            tree_height: None,
+            dust_threshold: None,
+            diff_commitment: None,
+            post_tx_infos: None,
+            hash_public_inputs: false,
+            public_paths: false,
+            prev_batch_commitment: None,
+            prev_batch_parent: None,
+            base_fee: None,
+            fee_collector_info_and_path: None,
+            sequencer_id: None,
+            sequencer_key: None,
+            sequencer_signature: None,
+            shard: None,
+            protected_tx: None,
+            asset_roots: None,
+            pow_difficulty: None,
         }
     }
 
@@ -64,13 +290,43 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
             ledger_params,
             initial_root: Some(initial_root),
             final_root: Some(final_root),
+            withdrawal_root: None,
+            pre_epoch: None,
+            post_epoch: None,
+            block_commitment: None,
             transactions: None,
             sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
-            recv_pre_tx_info_and_paths: None,
+            recv_pre_tx_infos: None,
             recv_post_paths: None,
+            applied: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            tx_counter: None,
+            tx_counter_path: None,
+            untouched_account: None,
+            max_touched: None,
+            fee_asset: None,
+            total_supply: None,
+            scale: None,
+            processed_roots: None,
+            processed_proofs: None,
+            tree_height: None,
+            dust_threshold: None,
+            diff_commitment: None,
+            post_tx_infos: None,
+            hash_public_inputs: false,
+            public_paths: false,
+            prev_batch_commitment: None,
+            prev_batch_parent: None,
+            base_fee: None,
+            fee_collector_info_and_path: None,
+            sequencer_id: None,
+            sequencer_key: None,
+            sequencer_signature: None,
+            shard: None,
+            protected_tx: None,
+            asset_roots: None,
+            pow_difficulty: None,
         }
     }
 
@@ -79,93 +335,1232 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
         transactions: &[Transaction],
         state: &mut State,
         validate_transactions: bool,
+    ) -> Option<Self> {
+        Self::with_state_and_transactions_inner(
+            ledger_params,
+            transactions,
+            std::slice::from_mut(state),
+            validate_transactions,
+            false,
+        )
+    }
+
+    /// Like `with_state_and_transactions`, but each transaction that is invalid
+    /// for the state left by the previous ones is skipped, rather than failing the
+    /// whole batch. Records whether each transaction is applied in `applied`.
+    pub fn with_state_and_transactions_skipping_invalid(
+        ledger_params: Parameters,
+        transactions: &[Transaction],
+        state: &mut State,
+    ) -> Option<Self> {
+        Self::with_state_and_transactions_inner(
+            ledger_params,
+            transactions,
+            std::slice::from_mut(state),
+            false,
+            true,
+        )
+    }
+
+    /// Like `with_state_and_transactions`, but each asset has an account tree of
+    /// its own, see `asset_trees`, whose state is `states[asset]`, and each
+    /// transaction is applied to the tree of its asset. Returns `None` if there
+    /// isn't a state for each asset, if the states are at different epochs or
+    /// blocks, or if the ledger has a fee market or a transaction counter, which
+    /// are kept in a single tree.
+    pub fn with_asset_trees(
+        ledger_params: Parameters,
+        transactions: &[Transaction],
+        states: &mut [State],
+        validate_transactions: bool,
+    ) -> Option<Self> {
+        let first = states.first()?;
+        if states.len() != NUM_ASSETS
+            || states.iter().any(|state| {
+                state.epoch != first.epoch || state.block_commitment != first.block_commitment
+            })
+            || ledger_params.fee_collector.is_some()
+            || ledger_params.max_per_epoch.is_some()
+        {
+            return None;
+        }
+        Self::with_state_and_transactions_inner(
+            ledger_params,
+            transactions,
+            states,
+            validate_transactions,
+            false,
+        )
+    }
+
+    /// Record the transactions of this batch in the set of processed transactions
+    /// `processed`, so that the batch also proves that none of them was applied
+    /// before. Only the applied transactions that are not no-ops are recorded.
+    /// Returns `None`, leaving `processed` unchanged, if any of them is already
+    /// in the set, or if the set is full.
+    pub fn with_processed_set(mut self, processed: &mut ProcessedSet) -> Option<Self> {
+        let transactions = self.transactions.as_ref()?;
+        let mut set = processed.clone();
+        let pre_root = set.root();
+        let mut proofs = Vec::with_capacity(NUM_TX);
+        for (i, tx) in transactions.iter().enumerate() {
+            let is_applied = self.applied.as_ref().is_none_or(|applied| applied[i]);
+            if is_applied && !tx.is_noop() {
+                proofs.push(set.insert(&self.ledger_params, tx.id(&self.ledger_params))?);
+            } else {
+                proofs.push(set.placeholder_proof());
+            }
+        }
+        self.processed_roots = Some((pre_root, set.root()));
+        self.processed_proofs = Some(proofs);
+        *processed = set;
+        Some(self)
+    }
+
+    /// Commit to the updates that the transactions of this batch make to the
+    /// leaves of the account tree, so that the batch also proves the diff
+    /// commitment. Returns `None` if the batch lacks the witnesses of its
+    /// transactions.
+    pub fn with_diff_commitment(mut self) -> Option<Self> {
+        let transactions = self.transactions.as_ref()?;
+        let senders = self.sender_pre_tx_info_and_paths.as_ref()?;
+        let recipients = self.recv_pre_tx_infos.as_ref()?;
+        let epoch = self.pre_epoch?;
+        let mut updates = Vec::with_capacity(NUM_TX);
+        let mut post_tx_infos = Vec::with_capacity(NUM_TX);
+        for (i, tx) in transactions.iter().enumerate() {
+            let (sender_info, recipient_info) = (&senders[i].0, &recipients[i]);
+            let is_applied = self.applied.as_ref().is_none_or(|applied| applied[i]);
+            let post_infos = tx
+                .new_account_infos(
+                    &self.ledger_params,
+                    sender_info,
+                    Some(recipient_info),
+                    epoch,
+                )
+                .filter(|_| is_applied && !tx.is_control_op());
+            match post_infos {
+                Some((post_sender_info, post_recipient_info)) => {
+                    updates.push(tx.leaf_update(
+                        &self.ledger_params,
+                        sender_info,
+                        recipient_info,
+                        epoch,
+                    )?);
+                    post_tx_infos.push((
+                        post_sender_info,
+                        post_recipient_info.unwrap_or(*recipient_info),
+                    ));
+                }
+                None => post_tx_infos.push((*sender_info, *recipient_info)),
+            }
+        }
+        self.diff_commitment = Some(diff_commitment(&self.ledger_params, &updates));
+        self.post_tx_infos = Some(post_tx_infos);
+        Some(self)
+    }
+
+    /// Chain this batch to the previous one, whose output commitment extends
+    /// `parent`, so that the batch also proves that it starts from the output of
+    /// the previous batch. `parent` is the previous commitment of the previous
+    /// batch, or zero if the previous output is the initial state of the ledger.
+    /// Returns `None` if the batch lacks its initial root or pre-batch epoch.
+    pub fn with_prev_batch(mut self, parent: BatchCommitment) -> Option<Self> {
+        self.prev_batch_commitment = Some(batch::batch_output_commitment(
+            &self.ledger_params,
+            &parent,
+            &self.initial_root?,
+            self.pre_epoch?,
+        ));
+        self.prev_batch_parent = Some(parent);
+        Some(self)
+    }
+
+    /// Sign this batch with the sequencer's key `sequencer_sk`, see
+    /// `batch::sign_batch`, so that the batch also proves that the sequencer
+    /// authorized it. Returns `None` if the batch lacks its roots or its
+    /// transactions.
+    pub fn with_sequencer_signature<R: Rng>(
+        mut self,
+        sequencer_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Option<Self> {
+        self.sequencer_signature = Some(batch::sign_batch(
+            &self.ledger_params,
+            sequencer_sk,
+            &self.initial_root?,
+            &self.final_root?,
+            self.transactions.as_ref()?,
+            rng,
+        ));
+        self.sequencer_key = Some(sequencer_sk.public_key);
+        Some(self)
+    }
+
+    /// Name the account of the sequencer, `sequencer_id`, so that the batch also
+    /// proves that the priority fees of its transactions are credited to it.
+    /// Returns `None` if the ledger has no fee market, or if another account
+    /// collects its fees.
+    pub fn with_sequencer_id(mut self, sequencer_id: AccountId) -> Option<Self> {
+        if self.ledger_params.fee_collector != Some(sequencer_id) {
+            return None;
+        }
+        self.sequencer_id = Some(sequencer_id);
+        Some(self)
+    }
+
+    /// Restrict this batch to the shard `shard`, so that the batch also proves
+    /// that each of its applied transactions stays within the shard, see
+    /// `Transaction::is_in_shard`. Returns `None` if the batch lacks its
+    /// transactions, or if any applied transaction leaves the shard.
+    pub fn with_shard(mut self, shard: Shard) -> Option<Self> {
+        for (i, tx) in self.transactions.as_ref()?.iter().enumerate() {
+            let is_applied = self.applied.as_ref().is_none_or(|applied| applied[i]);
+            if is_applied && !tx.is_in_shard(&shard) {
+                return None;
+            }
+        }
+        self.shard = Some(shard);
+        Some(self)
+    }
+
+    /// Protect the transaction at `position` in this batch from front-running, so
+    /// that the batch also proves that the transaction with its hash is at that
+    /// position, and that no applied transaction before it touches any of its
+    /// accounts, see `Transaction::shares_account_with`. Returns `None` if the
+    /// batch lacks its transactions, if it has no transaction at `position`, or
+    /// if an applied transaction before it touches one of its accounts.
+    pub fn with_protected_transaction(mut self, position: usize) -> Option<Self> {
+        let transactions = self.transactions.as_ref()?;
+        let protected = transactions.get(position)?;
+        for (i, tx) in transactions[..position].iter().enumerate() {
+            let is_applied = self.applied.as_ref().is_none_or(|applied| applied[i]);
+            if is_applied && tx.shares_account_with(protected) {
+                return None;
+            }
+        }
+        self.protected_tx = Some((protected.hash(&self.ledger_params), position as u64));
+        Some(self)
+    }
+
+    /// Require a proof of work of difficulty `difficulty` from each applied
+    /// transaction of this batch, so that the batch also proves that each of
+    /// them meets it, see `Transaction::meets_difficulty`. Returns `None` if the
+    /// batch lacks its transactions, or if any applied transaction falls short.
+    pub fn with_pow_difficulty(mut self, difficulty: u8) -> Option<Self> {
+        for (i, tx) in self.transactions.as_ref()?.iter().enumerate() {
+            let is_applied = self.applied.as_ref().is_none_or(|applied| applied[i]);
+            if is_applied && !tx.meets_difficulty(&self.ledger_params, difficulty) {
+                return None;
+            }
+        }
+        self.pow_difficulty = Some(difficulty);
+        Some(self)
+    }
+
+    /// The output commitment of this batch, which the next batch in the chain
+    /// extends. Returns `None` if the batch doesn't extend a chain, or lacks its
+    /// final root or post-batch epoch.
+    pub fn output_commitment(&self) -> Option<BatchCommitment> {
+        Some(batch::batch_output_commitment(
+            &self.ledger_params,
+            &self.prev_batch_commitment?,
+            &self.final_root?,
+            self.post_epoch?,
+        ))
+    }
+
+    /// The public inputs of the single-transaction proof of each step of this
+    /// batch, see `transaction::UnaryRollup`: the roots before and after the
+    /// transaction, the pre-batch epoch, and the block commitment. Each step
+    /// starts from the root at which the previous one ends, unless the ledger
+    /// keeps a tree per asset, in which case each step starts from the root at
+    /// which the previous step of its asset ends. Returns an empty vector if the
+    /// batch lacks its intermediate roots, pre-batch epoch, or block commitment.
+    pub fn step_public_inputs(&self) -> Vec<Vec<ConstraintF>> {
+        match (
+            &self.pre_tx_roots,
+            &self.post_tx_roots,
+            self.pre_epoch,
+            self.block_commitment,
+        ) {
+            (Some(pre_roots), Some(post_roots), Some(epoch), Some(block_commitment)) => pre_roots
+                .iter()
+                .zip(post_roots)
+                .map(|(pre_root, post_root)| {
+                    vec![
+                        *pre_root,
+                        *post_root,
+                        ConstraintF::from(epoch),
+                        block_commitment,
+                    ]
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Build the batch of `transactions` from `states`, which hold either the
+    /// single account tree of the ledger, or the tree of each asset, see
+    /// `with_asset_trees`.
+    fn with_state_and_transactions_inner(
+        ledger_params: Parameters,
+        transactions: &[Transaction],
+        states: &mut [State],
+        validate_transactions: bool,
+        skip_invalid: bool,
     ) -> Option<Self> {
         assert_eq!(transactions.len(), NUM_TX);
-        let initial_root = Some(state.root());
+        if ledger_params.canonical_order
+            && !transaction::is_canonically_ordered(&ledger_params, transactions)
+        {
+            return None;
+        }
+        if ledger_params.unique_tx_ids && !transaction::has_unique_ids(&ledger_params, transactions)
+        {
+            return None;
+        }
+        // Each transaction is applied to the tree of its asset, if each asset
+        // has a tree of its own.
+        let has_asset_trees = states.len() > 1;
+        let tree = |tx: &Transaction| match has_asset_trees {
+            true => tx.asset_id.0 as usize,
+            false => 0,
+        };
+        let pre_roots = states.iter().map(State::root).collect::<Vec<_>>();
+        let pre_epoch = states.first()?.epoch;
         let mut sender_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
-        let mut recipient_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
-        let mut sender_post_paths = Vec::with_capacity(NUM_TX);
+        let mut recipient_pre_tx_infos = Vec::with_capacity(NUM_TX);
         let mut recipient_post_paths = Vec::with_capacity(NUM_TX);
         let mut pre_tx_roots = Vec::with_capacity(NUM_TX);
         let mut post_tx_roots = Vec::with_capacity(NUM_TX);
+        let mut applied = Vec::with_capacity(NUM_TX);
+        let mut withdrawal_tree = WithdrawalTree::new(&ledger_params, NUM_TX);
+        // Skipped transactions need placeholder witnesses for accounts that don't exist.
+        let placeholder = || AccountInformation::new(Default::default());
         for tx in transactions {
-            if !tx.validate(&ledger_params, &*state) && validate_transactions {
+            if !tx.validate(&ledger_params, states.get(tree(tx))?) && validate_transactions {
                 return None;
             }
         }
         for tx in transactions {
+            let state = states.get_mut(tree(tx))?;
             let sender_id = tx.sender;
             let recipient_id = tx.recipient;
             let pre_tx_root = state.root();
-            let sender_pre_acc_info = *state.id_to_account_info.get(&sender_id)?;
+            let sender_pre_acc_info = match state.id_to_account_info.get(&sender_id) {
+                Some(info) => *info,
+                None if skip_invalid => placeholder(),
+                None => return None,
+            };
             let sender_pre_path = state
-                .account_merkle_tree
+                .account_merkle_tree()
                 .generate_proof(sender_id.0 as usize)
                 .unwrap();
-            let recipient_pre_acc_info = *state.id_to_account_info.get(&recipient_id)?;
-            let recipient_pre_path = state
-                .account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
+            // Withdrawals have no recipient account, so we use a placeholder instead.
+            let recipient_pre_acc_info = match state.id_to_account_info.get(&recipient_id) {
+                Some(info) => *info,
+                None if tx.is_withdrawal() || skip_invalid => placeholder(),
+                None => return None,
+            };
 
-            if validate_transactions {
+            let is_applied = if validate_transactions {
                 state.apply_transaction(&ledger_params, tx)?;
+                true
+            } else {
+                state.apply_transaction(&ledger_params, tx).is_some()
+            };
+            // Only applied transactions can withdraw funds.
+            if is_applied || !skip_invalid {
+                withdrawal_tree.append(tx);
             } else {
-                let _ = state.apply_transaction(&ledger_params, tx);
+                withdrawal_tree.skip();
             }
+            applied.push(is_applied);
             let post_tx_root = state.root();
-            let sender_post_path = state
-                .account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
             let recipient_post_path = state
-                .account_merkle_tree
+                .account_merkle_tree()
                 .generate_proof(recipient_id.0 as usize)
                 .unwrap();
             sender_pre_tx_info_and_paths.push((sender_pre_acc_info, sender_pre_path));
-            recipient_pre_tx_info_and_paths.push((recipient_pre_acc_info, recipient_pre_path));
-            sender_post_paths.push(sender_post_path);
+            recipient_pre_tx_infos.push(recipient_pre_acc_info);
             recipient_post_paths.push(recipient_post_path);
             pre_tx_roots.push(pre_tx_root);
             post_tx_roots.push(post_tx_root);
         }
 
+        // Only a ledger with a single tree has a fee market or a transaction
+        // counter, see `with_asset_trees`.
+        let state = &mut states[0];
+        // Credit the priority fees of the applied transactions to the fee
+        // collector, if the ledger has a fee market.
+        let (base_fee, fee_collector_info_and_path) = match ledger_params.fee_collector {
+            Some(collector) => {
+                let collector_info = match state.id_to_account_info.get(&collector) {
+                    Some(info) => *info,
+                    None => placeholder(),
+                };
+                let collector_path = state
+                    .account_merkle_tree()
+                    .generate_proof(collector.0 as usize)
+                    .unwrap();
+                let applied_transactions = transactions
+                    .iter()
+                    .zip(&applied)
+                    .filter(|(_, &is_applied)| is_applied)
+                    .map(|(tx, _)| tx.clone())
+                    .collect::<Vec<_>>();
+                if validate_transactions {
+                    state.collect_fees(&ledger_params, &applied_transactions)?;
+                } else {
+                    let _ = state.collect_fees(&ledger_params, &applied_transactions);
+                }
+                (Some(state.base_fee), Some((collector_info, collector_path)))
+            }
+            None => (None, None),
+        };
+
+        // Record the batch in the transaction counter, if it is tracked.
+        let (tx_counter, tx_counter_path) = match ledger_params.max_per_epoch {
+            Some(_) => {
+                let tx_counter = state.tx_counter;
+                let tx_counter_path = state.prove_tx_counter();
+                if validate_transactions {
+                    state.record_batch(&ledger_params, NUM_TX as u64)?;
+                } else {
+                    let _ = state.record_batch(&ledger_params, NUM_TX as u64);
+                }
+                (Some(tx_counter), Some(tx_counter_path))
+            }
+            None => (None, None),
+        };
+
+        let withdrawal_root = withdrawal_tree.root();
+        for state in states.iter_mut() {
+            state.epoch += 1;
+        }
+        let post_roots = states.iter().map(State::root).collect::<Vec<_>>();
+        // The trees of the assets are committed to by the digest of their roots.
+        let (initial_root, final_root, asset_roots) = match has_asset_trees {
+            true => (
+                asset_roots_digest(&ledger_params, &pre_roots),
+                asset_roots_digest(&ledger_params, &post_roots),
+                Some(pre_roots.into_iter().zip(post_roots).collect()),
+            ),
+            false => (pre_roots[0], post_roots[0], None),
+        };
+
         Some(Self {
             ledger_params,
-            initial_root,
-            final_root: Some(state.root()),
+            initial_root: Some(initial_root),
+            final_root: Some(final_root),
+            withdrawal_root: Some(withdrawal_root),
+            pre_epoch: Some(pre_epoch),
+            post_epoch: Some(states[0].epoch),
+            block_commitment: Some(states[0].block_commitment),
             transactions: Some(transactions.to_vec()),
             sender_pre_tx_info_and_paths: Some(sender_pre_tx_info_and_paths),
-            recv_pre_tx_info_and_paths: Some(recipient_pre_tx_info_and_paths),
-            sender_post_paths: Some(sender_post_paths),
+            recv_pre_tx_infos: Some(recipient_pre_tx_infos),
             recv_post_paths: Some(recipient_post_paths),
+            applied: skip_invalid.then_some(applied),
             pre_tx_roots: Some(pre_tx_roots),
             post_tx_roots: Some(post_tx_roots),
+            tx_counter,
+            tx_counter_path,
+            untouched_account: None,
+            max_touched: None,
+            fee_asset: None,
+            total_supply: None,
+            scale: None,
+            processed_roots: None,
+            processed_proofs: None,
+            tree_height: None,
+            dust_threshold: None,
+            diff_commitment: None,
+            post_tx_infos: None,
+            hash_public_inputs: false,
+            public_paths: false,
+            prev_batch_commitment: None,
+            prev_batch_parent: None,
+            base_fee,
+            fee_collector_info_and_path,
+            sequencer_id: None,
+            sequencer_key: None,
+            sequencer_signature: None,
+            shard: None,
+            protected_tx: None,
+            asset_roots,
+            pow_difficulty: None,
         })
     }
+
+    /// Check that the witness of this rollup satisfies the circuit, returning the
+    /// first unsatisfied constraint and the span it was generated in otherwise.
+    /// See `profiling::check_satisfied`.
+    pub fn check_satisfied(&self) -> Result<(), UnsatisfiedConstraint> {
+        profiling::check_satisfied(self.clone())
+    }
+
+    /// Generate the constraints of this rollup along with the assignment of
+    /// every variable, as a prover does before proving, but without proving, so
+    /// that the cost of witness generation can be measured on its own. As in
+    /// the prover, the constraint system optimizes for fewer constraints, and
+    /// it is finalized.
+    pub fn synthesize_only(&self) -> Result<ConstraintSystemRef<ConstraintF>, SynthesisError> {
+        let cs = ConstraintSystem::new_ref();
+        cs.set_optimization_goal(OptimizationGoal::Constraints);
+        self.clone().generate_constraints(cs.clone())?;
+        cs.finalize();
+        Ok(cs)
+    }
+
+    /// Return, for each transaction in the batch, the range of the indices of the
+    /// constraints generated while validating it, in the constraint system of
+    /// this rollup. The ranges are in the order of the transactions.
+    ///
+    /// This is a profiling aid: it generates all the constraints of the rollup,
+    /// and panics if that fails.
+    pub fn constraint_ranges(&self) -> Vec<Range<usize>> {
+        let cs = ConstraintSystem::new_ref();
+        self.clone()
+            .generate_constraints_and_ranges(cs)
+            .expect("constraint generation should succeed")
+    }
+
+    /// Return the largest number of transactions that a batch like this one,
+    /// i.e., under the same ledger parameters and over an account tree of the
+    /// same height, can hold within `max_constraints` constraints, e.g., to fit
+    /// the memory of the proving hardware. The cost is measured on batches of
+    /// one and of two transactions: their difference is the cost of each
+    /// transaction, and the rest is the fixed overhead of a batch. Whether the
+    /// batch may skip transactions is accounted for, but its other optional
+    /// features are not.
+    ///
+    /// This is an estimate: the overhead grows slightly with the size of the
+    /// batch, as its withdrawal tree is padded to a power of two, and so a batch
+    /// of more than two transactions may exceed its estimate by the cost of
+    /// hashing the padding.
+    ///
+    /// The constraints are counted as in `synthesize_only`, i.e., as the prover
+    /// generates them. This is a profiling aid: it generates all the constraints
+    /// of both batches, and panics if this batch lacks its paths.
+    pub fn max_batch_for_budget(&self, max_constraints: usize) -> usize {
+        let path = &self
+            .sender_pre_tx_info_and_paths
+            .as_ref()
+            .and_then(|paths| paths.first())
+            .expect("the batch should have its paths")
+            .1
+            .auth_path;
+        // The batches are made of transfers of nothing from an account to itself,
+        // which are skipped if this batch may skip transactions.
+        let mut state = State::new(1 << (path.len() + 2), &self.ledger_params);
+        let id = state.register(AccountPublicKey::default()).unwrap();
+        let tx = Transaction {
+            sender: id,
+            recipient: id,
+            ..Transaction::NOOP
+        };
+        fn count<const N: usize>(
+            ledger_params: &Parameters,
+            tx: &Transaction,
+            state: &State,
+            skip_invalid: bool,
+        ) -> usize {
+            Rollup::<N>::with_state_and_transactions_inner(
+                ledger_params.clone(),
+                &vec![tx.clone(); N],
+                &mut [state.clone()],
+                false,
+                skip_invalid,
+            )
+            .expect("the batches should be built")
+            .synthesize_only()
+            .expect("constraint generation should succeed")
+            .num_constraints()
+        }
+        let skip_invalid = self.applied.is_some();
+        let one = count::<1>(&self.ledger_params, &tx, &state, skip_invalid);
+        let two = count::<2>(&self.ledger_params, &tx, &state, skip_invalid);
+        let per_tx = two - one;
+        max_constraints.saturating_sub(one - per_tx) / per_tx
+    }
+
+    /// Enforce that the account `id` is neither the sender nor the recipient of
+    /// any of the transactions `transactions` in the batch.
+    #[tracing::instrument(target = "r1cs", skip(transactions, id))]
+    pub fn enforce_account_untouched(
+        transactions: &[TransactionVar],
+        id: &AccountIdVar,
+    ) -> Result<(), SynthesisError> {
+        let untouched = transactions
+            .iter()
+            .map(|tx| tx.leaves_untouched(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        Boolean::kary_and(&untouched)?.enforce_equal(&Boolean::TRUE)
+    }
+
+    /// Enforce that all the transactions `transactions` in the batch that pay a
+    /// fee pay it in the asset `fee_asset`.
+    #[tracing::instrument(target = "r1cs", skip(transactions, fee_asset))]
+    pub fn enforce_fee_asset(
+        transactions: &[TransactionVar],
+        fee_asset: &AssetIdVar,
+    ) -> Result<(), SynthesisError> {
+        for tx in transactions {
+            tx.enforce_fee_asset(fee_asset)?;
+        }
+        Ok(())
+    }
+
+    /// Compute the balance of `asset` of the account `account` after the
+    /// transactions `transactions` in the batch, from its balance `pre_balance`
+    /// before them. The credits and debits of the account across the batch are
+    /// netted first, and so the net change is negative when the account spends
+    /// more than it receives, but the resulting balance is enforced to be
+    /// non-negative. Withdrawals credit no account.
+    #[tracing::instrument(target = "r1cs", skip(transactions, account, asset, pre_balance))]
+    pub fn reconcile_balance(
+        transactions: &[TransactionVar],
+        account: &AccountIdVar,
+        asset: &AssetIdVar,
+        pre_balance: &AmountVar,
+    ) -> Result<AmountVar, SynthesisError> {
+        let zero = UInt64::constant(0);
+        let mut net_change = SignedAmountVar::zero();
+        for tx in transactions {
+            let is_asset = tx.asset_id.0.is_eq(&asset.0)?;
+            let is_credited = tx
+                .recipient
+                .0
+                .is_eq(&account.0)?
+                .and(&tx.is_withdrawal()?.not())?
+                .and(&is_asset)?;
+            let is_debited = tx.sender.0.is_eq(&account.0)?.and(&is_asset)?;
+            let amount_if = |flag: &Boolean<ConstraintF>, amount: &AmountVar| {
+                flag.select(&amount.0, &zero)
+                    .map(|amount| SignedAmountVar::from_amount(&AmountVar(amount)))
+            };
+            // The sender pays both the amount and the fee.
+            net_change = net_change
+                .add(&amount_if(&is_credited, &tx.amount)?)?
+                .sub(&amount_if(&is_debited, &tx.amount)?)?
+                .sub(&amount_if(&is_debited, &tx.fee)?)?;
+        }
+        SignedAmountVar::from_amount(pre_balance)
+            .add(&net_change)?
+            .to_amount()
+    }
+
+    /// Enforce that the account `id` holds exactly `expected` of `asset` in the
+    /// account tree with root `final_root`, so that a proof of the batch also
+    /// shows the owner of the account its balance after the batch. The account
+    /// information `final_info` must be the leaf of `id` under `final_root`, as
+    /// shown by `final_path`.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(parameters, final_root, id, asset, expected, final_info, final_path)
+    )]
+    pub fn enforce_final_balance(
+        parameters: &ParametersVar,
+        final_root: &AccRootVar,
+        id: &AccountIdVar,
+        asset: &AssetIdVar,
+        expected: &AmountVar,
+        final_info: &AccountInformationVar,
+        final_path: &PositionedPathVar,
+    ) -> Result<(), SynthesisError> {
+        // The path must lead to the leaf of `id`, or else the balance of any
+        // other account could be shown instead.
+        final_path
+            .leaf_index()?
+            .enforce_equal(&Boolean::le_bits_to_fp_var(&id.0.to_bits_le()?)?)?;
+        final_path
+            .verify_membership(parameters, final_root, &final_info.to_leaf(parameters)?)?
+            .enforce_equal(&Boolean::TRUE)?;
+        final_info.balances.get(asset)?.0.enforce_equal(&expected.0)
+    }
+
+    /// Enforce that the transactions `transactions` in the batch have distinct
+    /// identifiers, as `transaction::has_unique_ids` checks natively, by
+    /// comparing the identifiers of each pair of transactions. No-op transactions
+    /// may repeat.
+    #[tracing::instrument(target = "r1cs", skip(parameters, transactions))]
+    pub fn enforce_unique_tx_ids(
+        parameters: &ParametersVar,
+        transactions: &[TransactionVar],
+    ) -> Result<(), SynthesisError> {
+        let ids = transactions
+            .iter()
+            .map(|tx| Ok((tx.id(parameters)?, tx.is_noop()?)))
+            .collect::<Result<Vec<_>, SynthesisError>>()?;
+        for (i, (id, is_noop)) in ids.iter().enumerate() {
+            // A transaction that isn't a no-op never shares its identifier with
+            // one that is, and so only the first of each pair is checked.
+            for (other_id, _) in &ids[i + 1..] {
+                id.is_neq(other_id)?
+                    .or(is_noop)?
+                    .enforce_equal(&Boolean::TRUE)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enforce that the transactions `transactions` in the batch are sorted by
+    /// their hashes, as `transaction::is_canonically_ordered` checks natively.
+    #[tracing::instrument(target = "r1cs", skip(parameters, transactions))]
+    pub fn enforce_canonical_order(
+        parameters: &ParametersVar,
+        transactions: &[TransactionVar],
+    ) -> Result<(), SynthesisError> {
+        // Hashes are full field elements, which can't be compared directly, so
+        // each is split into two halves of at most 128 bits, which can.
+        let halves = |tx: &TransactionVar| -> Result<_, SynthesisError> {
+            let bits = tx.hash(parameters)?.to_bits_le()?;
+            let (low, high) = bits.split_at(128);
+            Ok((
+                Boolean::le_bits_to_fp_var(high)?,
+                Boolean::le_bits_to_fp_var(low)?,
+            ))
+        };
+        let hashes = transactions
+            .iter()
+            .map(halves)
+            .collect::<Result<Vec<_>, _>>()?;
+        for pair in hashes.windows(2) {
+            let ((high, low), (next_high, next_low)) = (&pair[0], &pair[1]);
+            let is_lower = high.is_cmp(next_high, std::cmp::Ordering::Less, false)?;
+            let is_le_on_tie = high.is_eq(next_high)?.and(&low.is_cmp(
+                next_low,
+                std::cmp::Ordering::Less,
+                true,
+            )?)?;
+            is_lower.or(&is_le_on_tie)?.enforce_equal(&Boolean::TRUE)?;
+        }
+        Ok(())
+    }
+
+    /// Enforce that the transaction with hash `hash` is at `position` among the
+    /// transactions `transactions` in the batch, and that no transaction before
+    /// it touches any of its accounts, unless it is skipped, where `applied[i]`
+    /// is whether the `i`-th transaction is applied. See
+    /// `with_protected_transaction`.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(parameters, transactions, applied, hash, position)
+    )]
+    pub fn enforce_protected_order(
+        parameters: &ParametersVar,
+        transactions: &[TransactionVar],
+        applied: &[Boolean<ConstraintF>],
+        hash: &FpVar<ConstraintF>,
+        position: &FpVar<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Exactly one transaction is at `position`, and it must be the protected
+        // one. A position past the end of the batch matches no transaction.
+        let is_protected = (0..transactions.len())
+            .map(|i| position.is_eq(&FpVar::constant(ConstraintF::from(i as u64))))
+            .collect::<Result<Vec<_>, _>>()?;
+        Boolean::kary_or(&is_protected)?.enforce_equal(&Boolean::TRUE)?;
+        for (tx, is_protected) in transactions.iter().zip(&is_protected) {
+            tx.hash(parameters)?
+                .conditional_enforce_equal(hash, is_protected)?;
+        }
+        // A transaction precedes the protected one if any later transaction is
+        // the protected one, and then it must not touch the same accounts.
+        for (i, (tx, applied)) in transactions.iter().zip(applied).enumerate() {
+            for (protected, is_protected) in
+                transactions[i + 1..].iter().zip(&is_protected[i + 1..])
+            {
+                let front_runs = is_protected
+                    .and(applied)?
+                    .and(&tx.shares_account_with(protected)?)?;
+                front_runs.enforce_equal(&Boolean::FALSE)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enforce that as many signatures are verified as there are transactions
+    /// that require authorization, where `verified_signatures[i]` is whether a
+    /// signature was verified for the `i`-th transaction, and `authorizations[i]`
+    /// is whether it requires authorization. Each authorized transaction is also
+    /// checked on its own, and so this is a guard against a prover that skips a
+    /// signature check, should one of the individual checks ever fail to bind.
+    #[tracing::instrument(target = "r1cs", skip(verified_signatures, authorizations))]
+    pub fn enforce_signature_count(
+        verified_signatures: &[Boolean<ConstraintF>],
+        authorizations: &[Boolean<ConstraintF>],
+    ) -> Result<(), SynthesisError> {
+        let count = |flags: &[Boolean<ConstraintF>]| {
+            flags.iter().fold(FpVar::zero(), |count, flag| {
+                count + FpVar::from(flag.clone())
+            })
+        };
+        count(verified_signatures).enforce_equal(&count(authorizations))
+    }
+
+    /// Enforce that the transactions `transactions` in the batch send from or to
+    /// at most `max_touched` distinct accounts, not counting the recipients of
+    /// withdrawals. `applied[i]` is whether the `i`-th transaction is applied:
+    /// skipped transactions and no-ops modify no leaf, and so they touch nothing.
+    #[tracing::instrument(target = "r1cs", skip(transactions, applied, max_touched))]
+    pub fn enforce_max_touched(
+        transactions: &[TransactionVar],
+        applied: &[Boolean<ConstraintF>],
+        max_touched: u64,
+    ) -> Result<(), SynthesisError> {
+        let mut touched = Vec::with_capacity(2 * transactions.len());
+        for (tx, is_applied) in transactions.iter().zip(applied) {
+            let modifies_leaves = is_applied.and(&tx.is_noop()?.not())?;
+            let credits_recipient = modifies_leaves.and(&tx.is_withdrawal()?.not())?;
+            touched.push((&tx.sender, modifies_leaves));
+            touched.push((&tx.recipient, credits_recipient));
+        }
+        // Count each touched account the first time it occurs.
+        let mut count = FpVar::zero();
+        for (i, (id, is_touched)) in touched.iter().enumerate() {
+            let mut is_first = is_touched.clone();
+            for (other_id, other_is_touched) in &touched[..i] {
+                let is_repeat = other_is_touched.and(&other_id.0.is_eq(&id.0)?)?;
+                is_first = is_first.and(&is_repeat.not())?;
+            }
+            count += FpVar::from(is_first);
+        }
+        count.enforce_cmp(
+            &FpVar::constant(ConstraintF::from(max_touched)),
+            std::cmp::Ordering::Less,
+            true,
+        )
+    }
+
+    /// Enforce that no account touched by the transactions `transactions` in the
+    /// batch has a negative balance after them, as a final sweep over the batch.
+    /// `pre_infos[i]` is the information of the sender and of the recipient of
+    /// the `i`-th transaction before it, and `applied[i]` is whether it is
+    /// applied. Each transaction already checks its own debit and credit, and so
+    /// this is a guard against one of those checks ever wrapping around: the
+    /// balances after each applied transaction are recomputed as field elements,
+    /// which don't wrap around at 64 bits, and range checked.
+    #[tracing::instrument(target = "r1cs", skip(transactions, pre_infos, applied))]
+    pub fn enforce_no_negative_balances(
+        transactions: &[TransactionVar],
+        pre_infos: &[(AccountInformationVar, AccountInformationVar)],
+        applied: &[Boolean<ConstraintF>],
+    ) -> Result<(), SynthesisError> {
+        let to_fe = |amount: &AmountVar| Boolean::le_bits_to_fp_var(&amount.0.to_bits_le());
+        for ((tx, (sender_info, recipient_info)), applied) in
+            transactions.iter().zip(pre_infos).zip(applied)
+        {
+            // The sender pays both the amount and the fee, while withdrawals
+            // credit no account.
+            let debit = to_fe(&tx.amount)? + to_fe(&tx.fee)?;
+            let credit = tx
+                .is_withdrawal()?
+                .select(&FpVar::zero(), &to_fe(&tx.amount)?)?;
+            let post_balances = [
+                to_fe(&sender_info.balances.get(&tx.asset_id)?)? - debit,
+                to_fe(&recipient_info.balances.get(&tx.asset_id)?)? + credit,
+            ];
+            for balance in &post_balances {
+                // A negative balance wraps around to a field element far above
+                // 2^64, and so do underflowed ones.
+                let bits = applied.select(balance, &FpVar::zero())?.to_bits_le()?;
+                Boolean::kary_or(&bits[64..])?.enforce_equal(&Boolean::FALSE)?;
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX> {
-    #[tracing::instrument(target = "r1cs", skip(self, cs))]
-    fn generate_constraints(
+impl<const NUM_TX: usize> Rollup<NUM_TX> {
+    /// Generate the constraints of this rollup in `cs`, and return the range of
+    /// the indices of the constraints generated by the validation of each of the
+    /// transactions.
+    fn generate_constraints_and_ranges(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
-    ) -> Result<(), SynthesisError> {
+    ) -> Result<Vec<Range<usize>>, SynthesisError> {
         // Declare the parameters as constants.
         let ledger_params = ParametersVar::new_constant(
             ark_relations::ns!(cs, "Ledger parameters"),
             &self.ledger_params,
         )?;
+        // The public inputs are declared as witnesses if they are hashed into a
+        // single public input.
+        let input_mode = if self.hash_public_inputs {
+            AllocationMode::Witness
+        } else {
+            AllocationMode::Input
+        };
         // Declare the initial root as a public input.
-        let initial_root = AccRootVar::new_input(ark_relations::ns!(cs, "Initial root"), || {
-            self.initial_root.ok_or(SynthesisError::AssignmentMissing)
-        })?;
+        let initial_root = AccRootVar::new_variable(
+            ark_relations::ns!(cs, "Initial root"),
+            || self.initial_root.ok_or(SynthesisError::AssignmentMissing),
+            input_mode,
+        )?;
 
         // Declare the final root as a public input.
-        let final_root = AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || {
-            self.final_root.ok_or(SynthesisError::AssignmentMissing)
-        })?;
-        let mut prev_root = initial_root;
+        let final_root = AccRootVar::new_variable(
+            ark_relations::ns!(cs, "Final root"),
+            || self.final_root.ok_or(SynthesisError::AssignmentMissing),
+            input_mode,
+        )?;
+        // Declare the withdrawal root as a public input.
+        let withdrawal_root_var = WithdrawalRootVar::new_variable(
+            ark_relations::ns!(cs, "Withdrawal root"),
+            || {
+                self.withdrawal_root
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+            input_mode,
+        )?;
+        // Declare the epochs before and after this batch as public inputs, and
+        // check that the batch advances the epoch by one.
+        let pre_epoch = FpVar::new_variable(
+            ark_relations::ns!(cs, "Pre-batch epoch"),
+            || {
+                self.pre_epoch
+                    .map(ConstraintF::from)
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+            input_mode,
+        )?;
+        let post_epoch = FpVar::new_variable(
+            ark_relations::ns!(cs, "Post-batch epoch"),
+            || {
+                self.post_epoch
+                    .map(ConstraintF::from)
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+            input_mode,
+        )?;
+        enforce_epoch_increment(&pre_epoch, &post_epoch)?;
+        // The transactions in the batch are applied during the pre-batch epoch.
+        let epoch = epoch_to_uint64(&pre_epoch)?;
+        // Declare the current block commitment as a public input.
+        let block_commitment = FpVar::new_variable(
+            ark_relations::ns!(cs, "Block commitment"),
+            || {
+                self.block_commitment
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+            input_mode,
+        )?;
+        // Declare the commitment to the ledger parameters as a public input. The
+        // transactions are checked against the parameters that the circuit is
+        // built with, and so their commitment must match.
+        let params_commitment = FpVar::new_variable(
+            ark_relations::ns!(cs, "Parameters commitment"),
+            || Ok(self.ledger_params.commitment()),
+            input_mode,
+        )?;
+        params_commitment.enforce_equal(&ledger_params.commitment)?;
+        // Declare whether each transaction is applied as public inputs, if
+        // transactions may be skipped.
+        let applied = self
+            .applied
+            .as_ref()
+            .map(|applied| {
+                (0..NUM_TX)
+                    .map(|i| {
+                        Boolean::new_variable(
+                            ark_relations::ns!(cs, "Applied"),
+                            || {
+                                applied
+                                    .get(i)
+                                    .copied()
+                                    .ok_or(SynthesisError::AssignmentMissing)
+                            },
+                            input_mode,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        // Declare the untouched account as a public input, if there is one, ...
+        let untouched_account = self
+            .untouched_account
+            .map(|id| {
+                AccountIdVar::new_variable(
+                    ark_relations::ns!(cs, "Untouched account"),
+                    || Ok(id),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... along with the fee asset, ...
+        let fee_asset = self
+            .fee_asset
+            .map(|fee_asset| {
+                AssetIdVar::new_variable(
+                    ark_relations::ns!(cs, "Fee asset"),
+                    || Ok(fee_asset),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the total supply, if balances are bounded by it, ...
+        let total_supply = self
+            .total_supply
+            .map(|supply| {
+                AmountVar::new_variable(
+                    ark_relations::ns!(cs, "Total supply"),
+                    || Ok(supply),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the scale of the amounts, if there is one, ...
+        let scale = self
+            .scale
+            .map(|scale| {
+                UInt8::new_variable(ark_relations::ns!(cs, "Scale"), || Ok(scale), input_mode)
+            })
+            .transpose()?;
+        // ... the roots of the set of processed transactions, if the batch
+        // records its transactions, ...
+        let processed_roots = self
+            .processed_roots
+            .map(|(pre_root, post_root)| {
+                Ok((
+                    AccRootVar::new_variable(
+                        ark_relations::ns!(cs, "Pre-batch processed root"),
+                        || Ok(pre_root),
+                        input_mode,
+                    )?,
+                    AccRootVar::new_variable(
+                        ark_relations::ns!(cs, "Post-batch processed root"),
+                        || Ok(post_root),
+                        input_mode,
+                    )?,
+                ))
+            })
+            .transpose()?;
+        let mut processed_root = processed_roots.as_ref().map(|(root, _)| root.clone());
+        // ... the height of the account tree, if it is checked, ...
+        let tree_height = self
+            .tree_height
+            .map(|height| {
+                FpVar::new_variable(
+                    ark_relations::ns!(cs, "Tree height"),
+                    || Ok(ConstraintF::from(height)),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the dust threshold, if there is one, ...
+        let dust_threshold = self
+            .dust_threshold
+            .map(|threshold| {
+                AmountVar::new_variable(
+                    ark_relations::ns!(cs, "Dust threshold"),
+                    || Ok(threshold),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the diff commitment, if the batch commits to its updates, ...
+        let diff_commitment = self
+            .diff_commitment
+            .map(|commitment| {
+                FpVar::new_variable(
+                    ark_relations::ns!(cs, "Diff commitment"),
+                    || Ok(commitment),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the output commitment of the previous batch, if the batch extends a
+        // chain, ...
+        let prev_batch_commitment = self
+            .prev_batch_commitment
+            .map(|commitment| {
+                FpVar::new_variable(
+                    ark_relations::ns!(cs, "Previous batch commitment"),
+                    || Ok(commitment),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the base fee, if the ledger has a fee market, ...
+        let base_fee = ledger_params
+            .fee_collector
+            .as_ref()
+            .map(|_| {
+                AmountVar::new_variable(
+                    ark_relations::ns!(cs, "Base fee"),
+                    || self.base_fee.ok_or(SynthesisError::AssignmentMissing),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the account of the sequencer, if it must collect the fees, ...
+        let sequencer_id = self
+            .sequencer_id
+            .map(|id| {
+                AccountIdVar::new_variable(
+                    ark_relations::ns!(cs, "Sequencer account"),
+                    || Ok(id),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the key of the sequencer, if the batch must be authorized by one,
+        // ...
+        let sequencer_key = self
+            .sequencer_key
+            .map(|key| {
+                AccountPublicKeyVar::new_variable(
+                    ark_relations::ns!(cs, "Sequencer key"),
+                    || Ok(key),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // ... the identifier of the shard, if the batch is restricted to one, ...
+        let shard = self
+            .shard
+            .map(|shard| {
+                ShardVar::new_variable(ark_relations::ns!(cs, "Shard"), || Ok(shard), input_mode)
+            })
+            .transpose()?;
+        // ... the hash and the position of the protected transaction, if there
+        // is one, ...
+        let protected_tx = self
+            .protected_tx
+            .map(|(hash, position)| {
+                let hash = FpVar::new_variable(
+                    ark_relations::ns!(cs, "Protected transaction hash"),
+                    || Ok(hash),
+                    input_mode,
+                )?;
+                let position = FpVar::new_variable(
+                    ark_relations::ns!(cs, "Protected transaction position"),
+                    || Ok(ConstraintF::from(position)),
+                    input_mode,
+                )?;
+                Ok((hash, position))
+            })
+            .transpose()?;
+        // ... and the difficulty of the proofs of work, if the batch requires
+        // them. No other public inputs are declared after this point, except for
+        // the paths of the transactions if they are public, so that they are
+        // declared in the order of `BatchPublicInputs::from_batch`.
+        let pow_difficulty = self
+            .pow_difficulty
+            .map(|difficulty| {
+                UInt8::new_variable(
+                    ark_relations::ns!(cs, "Proof of work difficulty"),
+                    || Ok(difficulty),
+                    input_mode,
+                )
+            })
+            .transpose()?;
+        // Declare the hash of the public inputs as the only public input, if
+        // they are hashed. Integers are hashed bit by bit, as they are declared.
+        if self.hash_public_inputs {
+            let bits = |bits: Vec<Boolean<ConstraintF>>| bits.into_iter().map(FpVar::from);
+            let mut inputs = vec![
+                initial_root.clone(),
+                final_root.clone(),
+                withdrawal_root_var.clone(),
+                pre_epoch.clone(),
+                post_epoch.clone(),
+                block_commitment.clone(),
+                params_commitment.clone(),
+            ];
+            inputs.extend(applied.iter().flatten().cloned().map(FpVar::from));
+            if let Some(id) = &untouched_account {
+                inputs.extend(bits(id.0.to_bits_le()?));
+            }
+            if let Some(fee_asset) = &fee_asset {
+                inputs.extend(bits(fee_asset.0.to_bits_le()));
+            }
+            if let Some(supply) = &total_supply {
+                inputs.extend(bits(supply.0.to_bits_le()));
+            }
+            if let Some(scale) = &scale {
+                inputs.extend(bits(scale.to_bits_le()?));
+            }
+            if let Some((pre_root, post_root)) = &processed_roots {
+                inputs.extend([pre_root.clone(), post_root.clone()]);
+            }
+            inputs.extend(tree_height.clone());
+            if let Some(threshold) = &dust_threshold {
+                inputs.extend(bits(threshold.0.to_bits_le()));
+            }
+            inputs.extend(diff_commitment.clone());
+            inputs.extend(prev_batch_commitment.clone());
+            if let Some(base_fee) = &base_fee {
+                inputs.extend(bits(base_fee.0.to_bits_le()));
+            }
+            if let Some(id) = &sequencer_id {
+                inputs.extend(bits(id.0.to_bits_le()?));
+            }
+            if let Some(key) = &sequencer_key {
+                inputs.extend(key.to_constraint_field()?);
+            }
+            if let Some(shard) = &shard {
+                inputs.extend(bits(shard.id.to_bits_le()?));
+            }
+            if let Some((hash, position)) = &protected_tx {
+                inputs.extend([hash.clone(), position.clone()]);
+            }
+            if let Some(difficulty) = &pow_difficulty {
+                inputs.extend(bits(difficulty.to_bits_le()?));
+            }
+            let hash = hash_input_vars(&ledger_params, &inputs)?;
+            let declared_hash =
+                FpVar::new_input(ark_relations::ns!(cs, "Public input hash"), || hash.value())?;
+            declared_hash.enforce_equal(&hash)?;
+        }
+        // Check that the batch starts from the root and the epoch that the
+        // previous batch committed to, if the batch extends a chain.
+        if let Some(commitment) = &prev_batch_commitment {
+            let parent =
+                FpVar::new_witness(ark_relations::ns!(cs, "Previous batch parent"), || {
+                    self.prev_batch_parent
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })?;
+            batch_output_commitment(&ledger_params, &parent, &initial_root, &epoch)?
+                .enforce_equal(commitment)?;
+        }
+        let mut diff = diff_commitment.as_ref().map(|_| FpVar::zero());
+        // The paths of the transactions are declared as public inputs if they
+        // are public.
+        let path_mode = if self.public_paths {
+            AllocationMode::Input
+        } else {
+            AllocationMode::Witness
+        };
+
+        // Open the roots of the tree of each asset, if each asset has a tree of
+        // its own.
+        let mut asset_roots = self
+            .asset_roots
+            .as_ref()
+            .map(|roots| {
+                AssetRootsVar::new_witness(ark_relations::ns!(cs, "Asset roots"), || Ok(roots))
+            })
+            .transpose()?;
+
+        let mut prev_root = initial_root.clone();
+        let mut withdrawals = Vec::with_capacity(NUM_TX);
+        let mut transactions = Vec::with_capacity(NUM_TX);
+        let mut ranges = Vec::with_capacity(NUM_TX);
+        // Whether a signature was verified for each transaction that requires
+        // authorization, and whether each transaction requires it.
+        let mut verified_signatures = Vec::with_capacity(NUM_TX);
+        let mut authorizations = Vec::with_capacity(NUM_TX);
+        // The priority fee of each transaction, in its asset, if the ledger has
+        // a fee market.
+        let mut priority_fees = Vec::with_capacity(NUM_TX);
+        // The information of the sender and of the recipient of each transaction
+        // before it, and whether it is applied, for the final balance sweep.
+        let mut pre_infos = Vec::with_capacity(NUM_TX);
+        let mut is_applied = Vec::with_capacity(NUM_TX);
 
         for i in 0..NUM_TX {
             let tx = self.transactions.as_ref().and_then(|t| t.get(i));
@@ -173,15 +1568,20 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
             let sender_acc_info = self.sender_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
             let sender_pre_path = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
 
-            let recipient_acc_info = self.recv_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
-            let recipient_pre_path = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
-
-            let sender_post_path = self.sender_post_paths.as_ref().map(|t| &t[i]);
+            let recipient_acc_info = self.recv_pre_tx_infos.as_ref().map(|t| t[i]);
             let recipient_post_path = self.recv_post_paths.as_ref().map(|t| &t[i]);
 
             let pre_tx_root = self.pre_tx_roots.as_ref().map(|t| t[i]);
             let post_tx_root = self.post_tx_roots.as_ref().map(|t| t[i]);
 
+            // Enforce that all the paths belong to a tree of the public height,
+            // if it is checked.
+            if let Some(height) = &tree_height {
+                for path in [sender_pre_path, recipient_post_path] {
+                    enforce_path_height(height, path.ok_or(SynthesisError::AssignmentMissing)?)?;
+                }
+            }
+
             // Let's declare all these things!
 
             let tx = TransactionVar::new_witness(ark_relations::ns!(cs, "Transaction"), || {
@@ -192,33 +1592,24 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                 ark_relations::ns!(cs, "Sender Account Info"),
                 || sender_acc_info.ok_or(SynthesisError::AssignmentMissing),
             )?;
-            // ..., corresponding authentication path, ...
-            let sender_pre_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Pre-Path"), || {
-                    sender_pre_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
-            // ... and authentication path after the update.
-            let sender_post_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Post-Path"), || {
-                    sender_post_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
+            // ... and corresponding authentication path.
+            let sender_pre_path = PositionedPathVar::new_variable(
+                ark_relations::ns!(cs, "Sender Pre-Path"),
+                || sender_pre_path.ok_or(SynthesisError::AssignmentMissing),
+                path_mode,
+            )?;
 
             // Declare the recipient's initial account balance...
             let recipient_acc_info = AccountInformationVar::new_witness(
                 ark_relations::ns!(cs, "Recipient Account Info"),
                 || recipient_acc_info.ok_or(SynthesisError::AssignmentMissing),
             )?;
-            // ..., corresponding authentication path, ...
-            let recipient_pre_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Pre-Path"), || {
-                    recipient_pre_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
-
             // ... and authentication path after the update.
-            let recipient_post_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Post-Path"), || {
-                    recipient_post_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
+            let recipient_post_path = PositionedPathVar::new_variable(
+                ark_relations::ns!(cs, "Recipient Post-Path"),
+                || recipient_post_path.ok_or(SynthesisError::AssignmentMissing),
+                path_mode,
+            )?;
 
             // Declare the state root before the transaction...
             let pre_tx_root =
@@ -232,42 +1623,344 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                 })?;
 
             // Enforce that the state root after the previous transaction equals
-            // the starting state root for this transaction
-            prev_root.enforce_equal(&pre_tx_root)?;
+            // the starting state root for this transaction, or the current root
+            // of the tree of its asset if each asset has a tree of its own.
+            match &asset_roots {
+                Some(asset_roots) => asset_roots
+                    .root_of(&tx.asset_id)?
+                    .enforce_equal(&pre_tx_root)?,
+                None => prev_root.enforce_equal(&pre_tx_root)?,
+            }
 
-            // Validate that the transaction signature and amount is correct.
-            tx.validate(
-                &ledger_params,
-                &sender_acc_info,
-                &sender_pre_path,
-                &sender_post_path,
-                &recipient_acc_info,
-                &recipient_pre_path,
-                &recipient_post_path,
-                &pre_tx_root,
-                &post_tx_root,
-            )?
-            .enforce_equal(&Boolean::TRUE)?;
+            let witness = TransactionWitnessVar {
+                sender: AccountWitnessVar {
+                    pre_info: PreState(&sender_acc_info),
+                    path: &sender_pre_path,
+                },
+                recipient: AccountWitnessVar {
+                    pre_info: PreState(&recipient_acc_info),
+                    path: &recipient_post_path,
+                },
+                pre_root: PreState(&pre_tx_root),
+                post_root: PostState(&post_tx_root),
+            };
+            // Validate that the transaction signature and amount is correct, and
+            // reject transactions bound to another block.
+            let start = cs.num_constraints();
+            let (is_valid, sig_verifies) =
+                tx.validate_with_signature(&ledger_params, &witness, &epoch)?;
+            ranges.push(start..cs.num_constraints());
+            // Only the applied transactions require authorization.
+            let requires_authorization = applied
+                .as_ref()
+                .map_or(Boolean::TRUE, |applied| applied[i].clone());
+            verified_signatures.push(sig_verifies.and(&requires_authorization)?);
+            authorizations.push(requires_authorization);
+            let mut is_valid = is_valid.and(&tx.is_unexpired_at(&block_commitment)?)?;
+            // Collect the priority fee of the transaction, unless it is skipped or
+            // a no-op, if the ledger has a fee market. Transactions must pay the
+            // base fee to be valid.
+            if let Some(base_fee) = &base_fee {
+                is_valid = is_valid.and(&tx.pays_base_fee(base_fee)?)?;
+                let is_collected = applied
+                    .as_ref()
+                    .map_or(Boolean::TRUE, |applied| applied[i].clone())
+                    .and(&tx.is_noop()?.not())?;
+                priority_fees.push((
+                    tx.asset_id.clone(),
+                    tx.priority_fee(base_fee, &is_collected)?,
+                ));
+            }
+            // Transactions must stay within the shard of the batch to be valid,
+            // if it has one.
+            if let Some(shard) = &shard {
+                is_valid = is_valid.and(&tx.is_in_shard(shard)?)?;
+            }
+            // Transactions must carry enough work to be valid, if the batch
+            // requires proofs of work.
+            if let Some(difficulty) = &pow_difficulty {
+                is_valid = is_valid.and(&tx.meets_difficulty(&ledger_params, difficulty)?)?;
+            }
+            // Transactions must leave no dust to be valid, if there is a
+            // threshold.
+            if let Some(threshold) = &dust_threshold {
+                is_valid = is_valid.and(&tx.leaves_no_dust(threshold, &sender_acc_info)?)?;
+            }
+            // No account touched by a valid transaction holds more than the total
+            // supply, if balances are bounded by it.
+            if let Some(supply) = &total_supply {
+                let (post_recipient_acc_info, credit_fits) =
+                    tx.credit_recipient(&recipient_acc_info)?;
+                is_valid = is_valid
+                    .and(&sender_acc_info.is_balance_le(supply)?)?
+                    .and(&recipient_acc_info.is_balance_le(supply)?)?
+                    .and(&credit_fits)?
+                    .and(&post_recipient_acc_info.is_balance_le(supply)?)?;
+            }
+            match applied.as_ref().map(|applied| &applied[i]) {
+                Some(applied) => {
+                    // Applied transactions must be valid, while skipped ones leave
+                    // the root unchanged.
+                    is_valid.conditional_enforce_equal(&Boolean::TRUE, applied)?;
+                    post_tx_root.conditional_enforce_equal(&pre_tx_root, &applied.not())?;
+                    // Record the withdrawal made by the transaction, if it is applied.
+                    withdrawals.push(WithdrawalVar::from_transaction_if(&tx, applied)?);
+                }
+                None => {
+                    is_valid.enforce_equal(&Boolean::TRUE)?;
+                    // Record the withdrawal made by the transaction, if any.
+                    withdrawals.push(WithdrawalVar::from_transaction(&tx)?);
+                }
+            }
+            // Record the transaction in the set of processed transactions, unless
+            // it is skipped or a no-op, so that it can't be applied again.
+            if let Some(processed_root) = processed_root.as_mut() {
+                let proof = InsertionProofVar::new_witness(
+                    ark_relations::ns!(cs, "Insertion proof"),
+                    || {
+                        self.processed_proofs
+                            .as_ref()
+                            .and_then(|proofs| proofs.get(i))
+                            .ok_or(SynthesisError::AssignmentMissing)
+                    },
+                )?;
+                let is_recorded = applied
+                    .as_ref()
+                    .map_or(Boolean::TRUE, |applied| applied[i].clone())
+                    .and(&tx.is_noop()?.not())?;
+                let (is_fresh, next_root) =
+                    tx.record_processed(&ledger_params, processed_root, &proof)?;
+                is_fresh.conditional_enforce_equal(&Boolean::TRUE, &is_recorded)?;
+                *processed_root = is_recorded.select(&next_root, processed_root)?;
+            }
+            // Commit to the updates of the transaction, unless it is skipped or a
+            // control operation, which update nothing.
+            if let Some(diff) = diff.as_mut() {
+                let post_infos = self.post_tx_infos.as_ref().map(|infos| infos[i]);
+                let post_sender_acc_info = AccountInformationVar::new_witness(
+                    ark_relations::ns!(cs, "Sender Post-Account Info"),
+                    || {
+                        post_infos
+                            .map(|infos| infos.0)
+                            .ok_or(SynthesisError::AssignmentMissing)
+                    },
+                )?;
+                let post_recipient_acc_info = AccountInformationVar::new_witness(
+                    ark_relations::ns!(cs, "Recipient Post-Account Info"),
+                    || {
+                        post_infos
+                            .map(|infos| infos.1)
+                            .ok_or(SynthesisError::AssignmentMissing)
+                    },
+                )?;
+                let is_committed = applied
+                    .as_ref()
+                    .map_or(Boolean::TRUE, |applied| applied[i].clone())
+                    .and(&tx.is_control_op()?.not())?;
+                let (is_stored, update) = tx.leaf_update(
+                    &ledger_params,
+                    &witness,
+                    &post_sender_acc_info,
+                    &post_recipient_acc_info,
+                )?;
+                is_stored.conditional_enforce_equal(&Boolean::TRUE, &is_committed)?;
+                *diff = is_committed.select(&update.commit(&ledger_params, diff)?, diff)?;
+            }
+            pre_infos.push((sender_acc_info, recipient_acc_info));
+            is_applied.push(
+                applied
+                    .as_ref()
+                    .map_or(Boolean::TRUE, |applied| applied[i].clone()),
+            );
 
             // Set the root for the next transaction.
-            prev_root = post_tx_root;
+            match asset_roots.as_mut() {
+                Some(asset_roots) => asset_roots.update(&tx.asset_id, &post_tx_root)?,
+                None => prev_root = post_tx_root,
+            }
+            transactions.push(tx);
+        }
+        // The fee collector must be the sequencer, if the batch names one, so
+        // that the priority fees can't be credited to another account.
+        if let Some(sequencer_id) = &sequencer_id {
+            let is_collector = match &ledger_params.fee_collector {
+                Some(collector) => collector.0.is_eq(&sequencer_id.0)?,
+                None => Boolean::FALSE,
+            };
+            is_collector.enforce_equal(&Boolean::TRUE)?;
+        }
+        // Credit the priority fees of the batch to the fee collector, if the
+        // ledger has a fee market.
+        if let Some(collector) = &ledger_params.fee_collector {
+            let collector_info = self.fee_collector_info_and_path.as_ref().map(|t| t.0);
+            let collector_path = self.fee_collector_info_and_path.as_ref().map(|t| &t.1);
+            if let Some(height) = &tree_height {
+                enforce_path_height(
+                    height,
+                    collector_path.ok_or(SynthesisError::AssignmentMissing)?,
+                )?;
+            }
+            let collector_info = AccountInformationVar::new_witness(
+                ark_relations::ns!(cs, "Fee collector info"),
+                || collector_info.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            let collector_path = PositionedPathVar::new_witness(
+                ark_relations::ns!(cs, "Fee collector path"),
+                || collector_path.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            prev_root = collect_fees(
+                &ledger_params,
+                &prev_root,
+                collector,
+                &collector_info,
+                &collector_path,
+                &priority_fees,
+            )?;
+        }
+        // Record the batch in the transaction counter, and check that it doesn't
+        // exceed the number of transactions allowed per epoch.
+        if let Some(max_per_epoch) = &ledger_params.max_per_epoch {
+            let tx_counter =
+                TxCounterVar::new_witness(ark_relations::ns!(cs, "Transaction counter"), || {
+                    self.tx_counter.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+            if let (Some(height), Some(path)) = (&tree_height, &self.tx_counter_path) {
+                enforce_path_height(height, path)?;
+            }
+            let tx_counter_path = PositionedPathVar::new_witness(
+                ark_relations::ns!(cs, "Transaction counter path"),
+                || {
+                    self.tx_counter_path
+                        .as_ref()
+                        .ok_or(SynthesisError::AssignmentMissing)
+                },
+            )?;
+            let (post_tx_counter, within_limit) =
+                tx_counter.increment(&epoch, NUM_TX as u64, max_per_epoch)?;
+            within_limit.enforce_equal(&Boolean::TRUE)?;
+            // The counter is updated in place, so the same path proves it before and
+            // after the update. The path must lead to the reserved leaf, or else a
+            // fresh counter could be placed at any blank leaf of the tree.
+            let counter_index = FpVar::constant(ConstraintF::from(WITHDRAWAL_ACCOUNT.0));
+            tx_counter_path
+                .verify_membership_at(
+                    &ledger_params,
+                    &prev_root,
+                    &tx_counter.to_bytes_le()?,
+                    &counter_index,
+                )?
+                .enforce_equal(&Boolean::TRUE)?;
+            tx_counter_path
+                .calculate_root(&ledger_params, &post_tx_counter.to_bytes_le()?)?
+                .enforce_equal(&final_root)?;
+            prev_root = final_root.clone();
         }
         // Check that the final root is consistent with the root computed after
-        // applying all state transitions
-        prev_root.enforce_equal(&final_root)?;
-        Ok(())
+        // applying all state transitions, or with the roots of the asset trees
+        // if each asset has a tree of its own.
+        match &asset_roots {
+            Some(asset_roots) => {
+                asset_roots.enforce_digests(&ledger_params, &initial_root, &final_root)?
+            }
+            None => prev_root.enforce_equal(&final_root)?,
+        }
+        // Check that the sequencer authorized the batch, if it must.
+        if let Some(key) = &sequencer_key {
+            let signature = AccountSignatureVar::new_witness(
+                ark_relations::ns!(cs, "Sequencer signature"),
+                || {
+                    self.sequencer_signature
+                        .as_ref()
+                        .ok_or(SynthesisError::AssignmentMissing)
+                },
+            )?;
+            verify_batch_authorization(
+                &ledger_params,
+                key,
+                &initial_root,
+                &final_root,
+                &transactions,
+                &signature,
+            )?
+            .enforce_equal(&Boolean::TRUE)?;
+        }
+        // Check that no signature check was skipped.
+        Self::enforce_signature_count(&verified_signatures, &authorizations)?;
+        // Check that no touched account ends up with a negative balance.
+        Self::enforce_no_negative_balances(&transactions, &pre_infos, &is_applied)?;
+        // Check that the set of processed transactions ends up with its public
+        // root, if the batch records its transactions.
+        if let (Some(root), Some((_, post_root))) = (&processed_root, &processed_roots) {
+            root.enforce_equal(post_root)?;
+        }
+        // Check that the updates of the batch match the public diff commitment,
+        // if the batch commits to them.
+        if let (Some(diff), Some(commitment)) = (&diff, &diff_commitment) {
+            diff.enforce_equal(commitment)?;
+        }
+        // Check that the withdrawal root is consistent with the withdrawals made
+        // in this batch.
+        withdrawal_root(&ledger_params, &withdrawals)?.enforce_equal(&withdrawal_root_var)?;
+        // Check that the untouched account, if any, is not in the batch.
+        if let Some(id) = &untouched_account {
+            Self::enforce_account_untouched(&transactions, id)?;
+        }
+        // Check that the batch touches few enough accounts, if they are capped.
+        if let Some(max_touched) = self.max_touched {
+            Self::enforce_max_touched(&transactions, &is_applied, max_touched)?;
+        }
+        // Check that the batch pays its fees in the fee asset, if there is one.
+        if let Some(fee_asset) = &fee_asset {
+            Self::enforce_fee_asset(&transactions, fee_asset)?;
+        }
+        // Check that the batch expresses its amounts in the scale, if there is one.
+        if let Some(scale) = &scale {
+            for tx in &transactions {
+                tx.enforce_scale(scale)?;
+            }
+        }
+        // Check that the batch is in canonical order, if the ledger requires it.
+        if ledger_params.canonical_order {
+            Self::enforce_canonical_order(&ledger_params, &transactions)?;
+        }
+        // Check that no transaction is included twice, if the ledger requires it.
+        if ledger_params.unique_tx_ids {
+            Self::enforce_unique_tx_ids(&ledger_params, &transactions)?;
+        }
+        // Check that the protected transaction, if any, is not front-run.
+        if let Some((hash, position)) = &protected_tx {
+            let applied = applied
+                .clone()
+                .unwrap_or_else(|| vec![Boolean::TRUE; transactions.len()]);
+            Self::enforce_protected_order(&ledger_params, &transactions, &applied, hash, position)?;
+        }
+        Ok(ranges)
+    }
+}
+
+impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX> {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.generate_constraints_and_ranges(cs).map(|_| ())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::inputs::BatchPublicInputs;
+    use crate::transaction::UnaryRollup;
+    use ark_ff::One;
     use ark_relations::r1cs::{
         ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints,
     };
     use ark_simple_payments::account::AccountId;
-    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State};
     use ark_simple_payments::transaction::Transaction;
+    use ark_simple_payments::withdrawal::{verify_withdrawal, Withdrawal, WITHDRAWAL_ACCOUNT};
     use tracing_subscriber::layer::SubscriberExt;
 
     fn test_cs<const NUM_TX: usize>(rollup: Rollup<NUM_TX>) -> bool {
@@ -305,7 +1998,7 @@ mod test {
         assert!(tx1.validate(&pp, &temp_state));
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[tx1.clone()],
+            std::slice::from_ref(&tx1),
             &mut temp_state,
             true,
         )
@@ -315,10 +2008,10 @@ mod test {
         let mut temp_state = state.clone();
         let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_none());
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[bad_tx.clone()],
+            std::slice::from_ref(&bad_tx),
             &mut temp_state,
             false,
         )
@@ -347,7 +2040,7 @@ mod test {
         assert!(tx1.validate(&pp, &temp_state));
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[tx1.clone()],
+            std::slice::from_ref(&tx1),
             &mut temp_state,
             true,
         )
@@ -363,28 +2056,23 @@ mod test {
         )
         .unwrap();
         assert!(test_cs(rollup));
-        assert_eq!(
-            temp_state
-                .id_to_account_info
-                .get(&alice_id)
-                .unwrap()
-                .balance,
-            Amount(10)
-        );
-        assert_eq!(
-            temp_state.id_to_account_info.get(&bob_id).unwrap().balance,
-            Amount(10)
-        );
+        let balance = |id| {
+            temp_state.id_to_account_info[&id]
+                .balances
+                .get(AssetId::NATIVE)
+        };
+        assert_eq!(balance(alice_id), Some(Amount(10)));
+        assert_eq!(balance(bob_id), Some(Amount(10)));
 
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
         let mut temp_state = state.clone();
         let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(21), &alice_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_none());
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[bad_tx.clone()],
+            std::slice::from_ref(&bad_tx),
             &mut temp_state,
             false,
         )
@@ -395,10 +2083,10 @@ mod test {
         let mut temp_state = state.clone();
         let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_none());
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[bad_tx.clone()],
+            std::slice::from_ref(&bad_tx),
             &mut temp_state,
             false,
         )
@@ -409,13 +2097,11 @@ mod test {
         let bad_tx =
             Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_none());
     }
 
-    // Builds a circuit with two txs, using different pubkeys & amounts every time.
-    // It returns this circuit
-    fn build_two_tx_circuit() -> Rollup<2> {
-        use ark_std::rand::Rng;
+    #[test]
+    fn withdrawal_test() {
         let mut rng = ark_std::test_rng();
         let pp = Parameters::sample(&mut rng);
         let mut state = State::new(32, &pp);
@@ -424,13 +2110,1797 @@ mod test {
             state.sample_keys_and_register(&pp, &mut rng).unwrap();
         // Let's give her some initial balance to start with.
         state
-            .update_balance(alice_id, Amount(1000))
+            .update_balance(alice_id, Amount(20))
             .expect("Alice's account should exist");
         // Let's make an account for Bob.
         let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
-        let amount_to_send = rng.gen_range(0..200);
-
+        // Alice pays Bob, and then withdraws some of her remaining funds.
+        let transfer = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            AssetId::NATIVE,
+            Amount(10),
+            Amount(0),
+            [0xab; 20],
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(withdrawal.recipient, WITHDRAWAL_ACCOUNT);
+        let transactions = [transfer, withdrawal];
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        let exposed_root = rollup.withdrawal_root.unwrap();
+        assert!(test_cs(rollup));
+
+        // Alice can prove her withdrawal against the exposed withdrawal root.
+        let tree = WithdrawalTree::from_transactions(&pp, &transactions);
+        let path = tree.generate_proof(1).unwrap();
+        let claim = Withdrawal {
+            recipient: alice_id,
+            asset_id: AssetId::NATIVE,
+            amount: Amount(10),
+            l1_address: [0xab; 20],
+        };
+        assert!(verify_withdrawal(&pp, &exposed_root, &claim, &path));
+        // The funds can't be claimed to any other address.
+        let misdirected = Withdrawal {
+            l1_address: [0xcd; 20],
+            ..claim
+        };
+        assert!(!verify_withdrawal(&pp, &exposed_root, &misdirected, &path));
+
+        // The batch is rejected if it exposes a withdrawal root that omits the withdrawal.
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.withdrawal_root = Some(WithdrawalTree::new(&pp, 2).root());
+        assert!(!test_cs(rollup));
+
+        // It is also rejected if the withdrawal root commits to a different
+        // address than the one Alice signed.
+        let mut redirected = transactions.clone();
+        redirected[1].l1_address = [0xcd; 20];
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.withdrawal_root = Some(WithdrawalTree::from_transactions(&pp, &redirected).root());
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn epoch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        // Let's make an account for Alice.
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Let's give her some initial balance to start with.
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        // Let's make an account for Bob.
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx1),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(rollup.pre_epoch, Some(0));
+        assert_eq!(rollup.post_epoch, Some(1));
+        assert_eq!(temp_state.epoch, 1);
+        assert!(test_cs(rollup));
+
+        // A batch that doesn't advance the epoch is rejected.
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx1),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.post_epoch = rollup.pre_epoch;
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn daily_limit_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.daily_limit = Some(Amount(10));
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(100))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng);
+
+        // Two transfers of 6 units in the same epoch exceed Alice's limit of 10
+        // units, so the second one is rejected.
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // The same transfers succeed across an epoch boundary.
+        for expected_epoch in 0..2 {
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(&tx),
+                &mut state,
+                true,
+            )
+            .unwrap();
+            assert_eq!(rollup.pre_epoch, Some(expected_epoch));
+            assert!(test_cs(rollup));
+        }
+        let alice_info = state.id_to_account_info[&alice_id];
+        assert_eq!(alice_info.balances.get(AssetId::NATIVE), Some(Amount(88)));
+        assert_eq!(alice_info.spent_today, Amount(6));
+        assert_eq!(alice_info.limit_epoch, 1);
+    }
+
+    #[test]
+    fn withdrawal_grace_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.withdrawal_grace = Some(2);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(100))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let apply = |state: &mut State, tx: &Transaction, validate| {
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(tx),
+                state,
+                validate,
+            )
+            .unwrap();
+            test_cs(rollup)
+        };
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            bob_id,
+            AssetId::NATIVE,
+            Amount(5),
+            Amount(0),
+            Default::default(),
+            &bob_sk,
+            &mut rng,
+        );
+
+        // Alice credits Bob during the epoch 0.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng);
+        assert!(apply(&mut state, &tx, true));
+        assert_eq!(state.id_to_account_info[&bob_id].last_credited_epoch, 0);
+
+        // Bob can't withdraw during the epoch 1, ...
+        assert!(!apply(&mut state.clone(), &withdrawal, false));
+        let tx = Transaction::create(&pp, alice_id, carol_id, Amount(10), &alice_sk, &mut rng);
+        assert!(apply(&mut state, &tx, true));
+
+        // ... but he can once the grace period has elapsed.
+        assert_eq!(state.epoch, 2);
+        assert!(apply(&mut state, &withdrawal, true));
+        let bob_info = state.id_to_account_info[&bob_id];
+        assert_eq!(bob_info.balances.get(AssetId::NATIVE), Some(Amount(5)));
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.max_per_epoch = Some(2);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(100)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+
+        // A batch of two transactions fits in the limit, and is recorded in the
+        // transaction counter.
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_ne!(rollup.final_root, Some(rollup.post_tx_roots.unwrap()[1]));
+        assert_eq!(temp_state.tx_counter, TxCounter { count: 2, epoch: 0 });
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+
+        // A batch that pushes the counter over the limit is rejected.
+        let mut temp_state = state.clone();
+        temp_state.record_batch(&pp, 1).unwrap();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state.clone(),
+            true,
+        );
+        assert!(rollup.is_none());
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state.clone(),
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+        // The prover can't reset the counter, either.
+        let mut rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state.clone(),
+            false,
+        )
+        .unwrap();
+        let mut reset_state = temp_state.clone();
+        reset_state.tx_counter = TxCounter::default();
+        for tx in [&tx, &tx] {
+            reset_state.apply_transaction(&pp, tx).unwrap();
+        }
+        reset_state.record_batch(&pp, 2).unwrap();
+        rollup.tx_counter = Some(TxCounter::default());
+        rollup.final_root = Some(reset_state.root());
+        assert!(!test_cs(rollup));
+        // Nor can the prover start a fresh counter at another blank leaf, which
+        // hashes to the same leaf as a fresh counter.
+        let mut rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state.clone(),
+            false,
+        )
+        .unwrap();
+        let mut forged_state = temp_state.clone();
+        for tx in [&tx, &tx] {
+            forged_state.apply_transaction(&pp, tx).unwrap();
+        }
+        let blank_leaf = 10;
+        assert!(!forged_state
+            .id_to_account_info
+            .contains_key(&AccountId(blank_leaf as u8)));
+        let mut tree = forged_state.account_merkle_tree().clone();
+        let forged_path = tree.generate_proof(blank_leaf).unwrap();
+        tree.update(blank_leaf, &TxCounter { count: 2, epoch: 0 }.to_bytes_le())
+            .unwrap();
+        rollup.tx_counter = Some(TxCounter::default());
+        rollup.tx_counter_path = Some(forged_path);
+        rollup.final_root = Some(tree.root());
+        assert!(!test_cs(rollup));
+
+        // Batches that don't fit are rejected even in a fresh epoch.
+        let rollup = Rollup::<3>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone(), tx.clone()],
+            &mut state.clone(),
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn replay_test() {
+        use ark_simple_payments::ledger::AccountProof;
+        use ark_simple_payments::replay::{replay_batch, TransactionWitness};
+
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.daily_limit = Some(Amount(10));
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(3), &alice_sk, &mut rng),
+        ];
+        let initial_root = state.root();
+        let epoch = state.epoch;
+        let block_commitment = state.block_commitment;
+
+        // Witnesses collected independently of the rollup, ...
+        let mut temp_state = state.clone();
+        let mut witnesses = Vec::new();
+        for tx in &transactions {
+            witnesses.push(TransactionWitness::new(&temp_state, tx));
+            temp_state.apply_transaction(&pp, tx).unwrap();
+        }
+        let root = replay_batch(
+            &pp,
+            &initial_root,
+            epoch,
+            &block_commitment,
+            &transactions,
+            &witnesses,
+        )
+        .unwrap();
+
+        // ... and those of the rollup, lead to the same root, ...
+        let rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &transactions, &mut state, true)
+                .unwrap();
+        let proof = |(info, path): &(AccountInformation, AccPath)| {
+            Some(AccountProof {
+                info: *info,
+                path: path.clone(),
+            })
+        };
+        let rollup_witnesses = rollup
+            .sender_pre_tx_info_and_paths
+            .as_ref()
+            .unwrap()
+            .iter()
+            .zip(rollup.recv_pre_tx_info_and_paths.as_ref().unwrap())
+            .map(|(sender, recipient)| TransactionWitness {
+                sender: proof(sender),
+                recipient: proof(recipient),
+            })
+            .collect::<Vec<_>>();
+        let replayed_root = replay_batch(
+            &pp,
+            &initial_root,
+            epoch,
+            &block_commitment,
+            &transactions,
+            &rollup_witnesses,
+        )
+        .unwrap();
+        assert_eq!(root, replayed_root);
+
+        // ... which is the root enforced by the circuit, and only that one.
+        assert_eq!(rollup.final_root, Some(root));
+        assert_eq!(state.root(), root);
+        let mut bad_rollup = rollup.clone();
+        bad_rollup.final_root = Some(initial_root);
+        assert!(test_cs(rollup));
+        assert!(!test_cs(bad_rollup));
+    }
+
+    #[test]
+    fn untouched_account_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut state,
+            true,
+        )
+        .unwrap();
+
+        // Carol takes no part in the batch, ...
+        let mut carol_rollup = rollup.clone();
+        carol_rollup.untouched_account = Some(carol_id);
+        assert!(test_cs(carol_rollup));
+        // ... but both Alice and Bob do.
+        for id in [alice_id, bob_id] {
+            let mut bad_rollup = rollup.clone();
+            bad_rollup.untouched_account = Some(id);
+            assert!(!test_cs(bad_rollup));
+        }
+    }
+
+    #[test]
+    fn max_touched_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let batch = |recipients: [AccountId; 2], rng: &mut _| {
+            let transactions = recipients
+                .map(|id| Transaction::create(&pp, alice_id, id, Amount(5), &alice_sk, rng));
+            Rollup::<2>::with_state_and_transactions(
+                pp.clone(),
+                &transactions,
+                &mut state.clone(),
+                true,
+            )
+            .unwrap()
+        };
+        let with_max = |rollup: &Rollup<2>, max_touched| Rollup {
+            max_touched: Some(max_touched),
+            ..rollup.clone()
+        };
+
+        // Paying Bob twice touches two accounts, ...
+        let rollup = batch([bob_id, bob_id], &mut rng);
+        assert!(test_cs(with_max(&rollup, 2)));
+        assert!(!test_cs(with_max(&rollup, 1)));
+        // ... while also paying Carol touches three.
+        let rollup = batch([bob_id, carol_id], &mut rng);
+        assert!(test_cs(with_max(&rollup, 3)));
+        assert!(!test_cs(with_max(&rollup, 2)));
+        // Withdrawals only touch their sender.
+        let rollup = batch([bob_id, WITHDRAWAL_ACCOUNT], &mut rng);
+        assert!(test_cs(with_max(&rollup, 2)));
+        // Skipped transactions and no-ops touch nothing: Alice can't spend Bob's
+        // funds, and the padding moves nothing.
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, carol_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::NOOP,
+        ];
+        let rollup = Rollup::<3>::with_state_and_transactions_skipping_invalid(
+            pp.clone(),
+            &transactions,
+            &mut state.clone(),
+        )
+        .unwrap();
+        assert_eq!(rollup.applied, Some(vec![true, false, false]));
+        let with_max = |max_touched| Rollup {
+            max_touched: Some(max_touched),
+            ..rollup.clone()
+        };
+        assert!(test_cs(with_max(2)));
+        assert!(!test_cs(with_max(1)));
+    }
+
+    #[test]
+    fn fee_asset_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        state
+            .update_asset_balance(alice_id, AssetId(1), Amount(10))
+            .unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let batch = |asset_id, fee, rng: &mut _| {
+            let tx = Transaction::create_with_asset(
+                &pp,
+                alice_id,
+                bob_id,
+                asset_id,
+                Amount(4),
+                fee,
+                &alice_sk,
+                rng,
+            );
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                &[tx],
+                &mut state.clone(),
+                true,
+            )
+            .unwrap();
+            Rollup {
+                fee_asset: Some(AssetId::NATIVE),
+                ..rollup
+            }
+        };
+
+        // Fees paid in the fee asset are accepted, ...
+        assert!(test_cs(batch(AssetId::NATIVE, Amount(1), &mut rng)));
+        // ... while fees paid in any other asset are rejected.
+        assert!(!test_cs(batch(AssetId(1), Amount(1), &mut rng)));
+        // Transactions that pay no fee may still transfer other assets.
+        assert!(test_cs(batch(AssetId(1), Amount(0), &mut rng)));
+    }
+
+    #[test]
+    fn scale_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Transactions aren't validated natively, since a relabeled one is invalid.
+        let batch = |tx: Transaction| {
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                &[tx],
+                &mut state.clone(),
+                false,
+            )
+            .unwrap();
+            Rollup {
+                scale: Some(6),
+                ..rollup
+            }
+        };
+
+        // Amounts in the batch's scale are accepted, ...
+        let tx = Transaction::create_with_scale(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            6,
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(test_cs(batch(tx)));
+        // ... while amounts in any other scale are rejected.
+        let tx = Transaction::create_with_scale(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            2,
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!test_cs(batch(tx.clone())));
+        // The scale is signed, so the prover can't relabel it either.
+        let mut relabeled = tx;
+        relabeled.scale = 6;
+        assert!(!test_cs(batch(relabeled)));
+    }
+
+    #[test]
+    fn total_supply_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(12))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(bob_id, Amount(10))
+            .expect("Bob's account should exist");
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        let with_supply = |supply| Rollup {
+            total_supply: Some(Amount(supply)),
+            ..rollup.clone()
+        };
+
+        // Bob holds 15 after being paid, which a supply of 15 allows, ...
+        assert!(test_cs(with_supply(15)));
+        // ... but a supply of 14 doesn't, even though none of the balances before
+        // the transaction exceeds it.
+        assert!(!test_cs(with_supply(14)));
+    }
+
+    #[test]
+    fn phantom_update_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [5, 3].map(|amount| {
+            Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng)
+        });
+        // Carol takes no part in the batch, but the sequencer tries to sneak in a
+        // change of her balance.
+        let mint_for_carol = |state: &mut State| state.update_balance(carol_id, Amount(1000));
+
+        let mut post_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut post_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup.clone()));
+
+        // A change after the last transaction isn't explained by the batch, ...
+        mint_for_carol(&mut post_state).unwrap();
+        let mut bad_rollup = rollup.clone();
+        bad_rollup.final_root = Some(post_state.root());
+        assert!(!test_cs(bad_rollup.clone()));
+        bad_rollup.post_tx_roots.as_mut().unwrap()[1] = post_state.root();
+        assert!(!test_cs(bad_rollup));
+
+        // ... and neither is a change in between two transactions, even if the
+        // witnesses of the second one are consistent with it. Splice the batch
+        // from the witnesses of each transaction, proven separately.
+        let splice = |tamper: bool| {
+            let mut state = state.clone();
+            let first = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                &transactions[..1],
+                &mut state,
+                true,
+            )
+            .unwrap();
+            if tamper {
+                mint_for_carol(&mut state).unwrap();
+            }
+            let second = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                &transactions[1..],
+                &mut state,
+                true,
+            )
+            .unwrap();
+            fn concat<T>(first: Option<Vec<T>>, second: Option<Vec<T>>) -> Option<Vec<T>> {
+                Some(first?.into_iter().chain(second?).collect())
+            }
+            Rollup {
+                final_root: second.final_root,
+                sender_pre_tx_info_and_paths: concat(
+                    first.sender_pre_tx_info_and_paths,
+                    second.sender_pre_tx_info_and_paths,
+                ),
+                sender_post_paths: concat(first.sender_post_paths, second.sender_post_paths),
+                recv_pre_tx_info_and_paths: concat(
+                    first.recv_pre_tx_info_and_paths,
+                    second.recv_pre_tx_info_and_paths,
+                ),
+                recv_post_paths: concat(first.recv_post_paths, second.recv_post_paths),
+                pre_tx_roots: concat(first.pre_tx_roots, second.pre_tx_roots),
+                post_tx_roots: concat(first.post_tx_roots, second.post_tx_roots),
+                ..rollup.clone()
+            }
+        };
+        assert!(test_cs(splice(false)));
+        assert!(!test_cs(splice(true)));
+    }
+
+    #[test]
+    fn canonical_order_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.canonical_order = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut sorted = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng),
+        ];
+        transaction::sort_canonically(&pp, &mut sorted);
+        let unsorted = [sorted[1].clone(), sorted[0].clone()];
+
+        // A sorted batch is accepted, ...
+        let rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &sorted, &mut state.clone(), true)
+                .unwrap();
+        assert!(test_cs(rollup));
+        // ... while an unsorted one is rejected natively, ...
+        assert!(Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &unsorted,
+            &mut state.clone(),
+            true
+        )
+        .is_none());
+        // ... and in the circuit.
+        let is_ordered = |transactions: &[Transaction]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let parameters = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let transactions = transactions
+                .iter()
+                .map(|tx| TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap())
+                .collect::<Vec<_>>();
+            Rollup::<2>::enforce_canonical_order(&parameters, &transactions).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(is_ordered(&sorted));
+        assert!(!is_ordered(&unsorted));
+    }
+
+    #[test]
+    fn step_public_inputs_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(bob_id, Amount(10))
+            .expect("Bob's account should exist");
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(7), &alice_sk, &mut rng),
+        ];
+        let mut step_state = state.clone();
+        let rollup =
+            Rollup::<3>::with_state_and_transactions(pp.clone(), &transactions, &mut state, true)
+                .unwrap();
+        let steps = rollup.step_public_inputs();
+        assert_eq!(steps.len(), transactions.len());
+
+        // Each step starts where the previous one ends, ...
+        for (step, next) in steps.iter().zip(&steps[1..]) {
+            assert_eq!(step[1], next[0]);
+        }
+        assert_eq!(steps[0][0], rollup.initial_root.unwrap());
+        // ... and matches the single-transaction proof of its transaction.
+        for (tx, step) in transactions.iter().zip(&steps) {
+            let pre_root = step_state.root();
+            let unary = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut step_state,
+                true,
+            )
+            .unwrap();
+            assert_eq!(step[0], pre_root);
+            assert_eq!(step[1], step_state.root());
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            unary.generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            assert_eq!(cs.borrow().unwrap().instance_assignment[1..], step[..]);
+        }
+
+        // A batch without its intermediate roots has no steps.
+        let roots_only = Rollup::<3>::only_initial_and_final_roots(
+            pp,
+            rollup.initial_root.unwrap(),
+            rollup.final_root.unwrap(),
+        );
+        assert!(roots_only.step_public_inputs().is_empty());
+    }
+
+    #[test]
+    fn unique_tx_ids_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.unique_tx_ids = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let other = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let duplicated = [tx.clone(), tx.clone(), Transaction::NOOP];
+        let distinct = [tx, other, Transaction::NOOP];
+
+        // A batch of distinct transactions is accepted, even if it is padded
+        // with more than one no-op, ...
+        let rollup = Rollup::<3>::with_state_and_transactions_skipping_invalid(
+            pp.clone(),
+            &distinct,
+            &mut state.clone(),
+        )
+        .unwrap();
+        assert_eq!(rollup.applied, Some(vec![true, true, false]));
+        assert!(test_cs(rollup));
+        let padded = [distinct[0].clone(), Transaction::NOOP, Transaction::NOOP];
+        assert!(transaction::has_unique_ids(&pp, &padded));
+        // ... while a batch that includes a transaction twice is rejected
+        // natively, ...
+        assert!(!transaction::has_unique_ids(&pp, &duplicated));
+        assert!(Rollup::<3>::with_state_and_transactions(
+            pp.clone(),
+            &duplicated,
+            &mut state.clone(),
+            false
+        )
+        .is_none());
+        // ... and in the circuit.
+        let is_unique = |transactions: &[Transaction]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let parameters = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let transactions = transactions
+                .iter()
+                .map(|tx| TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap())
+                .collect::<Vec<_>>();
+            Rollup::<3>::enforce_unique_tx_ids(&parameters, &transactions).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(is_unique(&distinct));
+        assert!(is_unique(&padded));
+        assert!(!is_unique(&duplicated));
+    }
+
+    #[test]
+    fn protected_order_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(carol_id, Amount(20)).unwrap();
+        let (dave_id, _dave_pk, _dave_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let protected = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let unrelated = Transaction::create(&pp, carol_id, dave_id, Amount(3), &carol_sk, &mut rng);
+        let front_runner =
+            Transaction::create(&pp, carol_id, bob_id, Amount(3), &carol_sk, &mut rng);
+        let batch = |transactions: &[Transaction]| {
+            Rollup::<2>::with_state_and_transactions(
+                pp.clone(),
+                transactions,
+                &mut state.clone(),
+                true,
+            )
+            .unwrap()
+        };
+
+        // A transaction that touches other accounts may precede the protected
+        // one, ...
+        let rollup = batch(&[unrelated, protected.clone()])
+            .with_protected_transaction(1)
+            .unwrap();
+        assert_eq!(rollup.protected_tx, Some((protected.hash(&pp), 1)));
+        assert!(test_cs(rollup.clone()));
+        // ... but the protected transaction must be at its committed position, ...
+        let mut misplaced = rollup;
+        misplaced.protected_tx = Some((protected.hash(&pp), 0));
+        assert!(!test_cs(misplaced));
+
+        // ... and a transaction that touches one of its accounts before it is
+        // rejected, both natively and in the circuit.
+        let front_run = batch(&[front_runner, protected.clone()]);
+        assert!(front_run.clone().with_protected_transaction(1).is_none());
+        let mut front_run = front_run;
+        front_run.protected_tx = Some((protected.hash(&pp), 1));
+        assert!(!test_cs(front_run));
+    }
+
+    #[test]
+    fn pow_difficulty_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng)
+            .with_pow(&pp, 4);
+        let mut weak_tx = tx.clone();
+        while weak_tx.meets_difficulty(&pp, 4) {
+            weak_tx.pow_nonce += 1;
+        }
+        let batch = |tx: &Transaction| {
+            Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(tx),
+                &mut state.clone(),
+                true,
+            )
+            .unwrap()
+        };
+
+        // A transaction that carries enough work is accepted, ...
+        let rollup = batch(&tx).with_pow_difficulty(4).unwrap();
+        assert!(test_cs(rollup));
+        // ... while one whose nonce falls short is rejected natively, ...
+        assert!(batch(&weak_tx).with_pow_difficulty(4).is_none());
+        // ... and in the circuit.
+        let rollup = Rollup {
+            pow_difficulty: Some(4),
+            ..batch(&weak_tx)
+        };
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn reconcile_balance_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Alice pays Bob 5 with a fee of 1, and Bob pays 2 back, so that Alice's
+        // balance changes by -4 across the batch.
+        let transactions = [
+            Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(5),
+                Amount(1),
+                &alice_sk,
+                &mut rng,
+            ),
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+        ];
+        let reconcile = |pre_balance| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let transactions = transactions
+                .iter()
+                .map(|tx| TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap())
+                .collect::<Vec<_>>();
+            let account = AccountIdVar::new_witness(cs.clone(), || Ok(alice_id)).unwrap();
+            let asset = AssetIdVar::new_constant(cs.clone(), AssetId::NATIVE).unwrap();
+            let pre_balance =
+                AmountVar::new_witness(cs.clone(), || Ok(Amount(pre_balance))).unwrap();
+            let post_balance =
+                Rollup::<2>::reconcile_balance(&transactions, &account, &asset, &pre_balance)
+                    .unwrap();
+            (post_balance.0.value().unwrap(), cs.is_satisfied().unwrap())
+        };
+
+        assert_eq!(reconcile(20), (16, true));
+        assert_eq!(reconcile(4), (0, true));
+        // The net change can't exceed the balance before the batch.
+        assert!(!reconcile(3).1);
+    }
+
+    #[test]
+    fn final_balance_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Alice pays Bob 5 with a fee of 1, so that she is left with 14.
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let rollup =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        let final_root = rollup.final_root.unwrap();
+
+        let is_valid = |id: AccountId, leaf_id: AccountId, expected| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let root = AccRootVar::new_input(cs.clone(), || Ok(final_root)).unwrap();
+            let id = AccountIdVar::new_input(cs.clone(), || Ok(id)).unwrap();
+            let asset = AssetIdVar::new_constant(cs.clone(), AssetId::NATIVE).unwrap();
+            let expected = AmountVar::new_input(cs.clone(), || Ok(Amount(expected))).unwrap();
+            let info = AccountInformationVar::new_witness(cs.clone(), || {
+                Ok(state.id_to_account_info[&leaf_id])
+            })
+            .unwrap();
+            let path = state
+                .account_merkle_tree()
+                .generate_proof(leaf_id.0 as usize)
+                .unwrap();
+            let path = PositionedPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+            Rollup::<1>::enforce_final_balance(
+                &params, &root, &id, &asset, &expected, &info, &path,
+            )
+            .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // The correct balance is accepted, ...
+        assert!(is_valid(alice_id, alice_id, 14));
+        assert!(is_valid(bob_id, bob_id, 5));
+        // ... while a wrong one is rejected, ...
+        assert!(!is_valid(alice_id, alice_id, 15));
+        // ... as is the balance of another account.
+        assert!(!is_valid(alice_id, bob_id, 5));
+    }
+
+    #[test]
+    fn signed_leaf_indices_test() {
+        let mut rng = ark_std::test_rng();
+        for bind_leaf_indices in [false, true] {
+            let mut pp = Parameters::sample(&mut rng);
+            pp.bind_leaf_indices = bind_leaf_indices;
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            let pre_state = state.clone();
+            let rollup =
+                Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true)
+                    .unwrap();
+            assert!(test_cs(rollup.clone()));
+
+            // Copy Alice's leaf to a blank leaf, and prove her account at the
+            // copy rather than at the leaf that her transaction signs. The batch
+            // then spends from the copy, and leaves her original leaf untouched.
+            let copy = 10;
+            let leaf = |state: &State, id: AccountId| state.id_to_account_info[&id].to_leaf(&pp);
+            let mut tree = pre_state.account_merkle_tree().clone();
+            tree.update(copy, &leaf(&pre_state, alice_id)).unwrap();
+            let pre_root = tree.root();
+            let sender_pre_path = tree.generate_proof(copy).unwrap();
+            let recipient_pre_path = tree.generate_proof(bob_id.0 as usize).unwrap();
+            tree.update(copy, &leaf(&state, alice_id)).unwrap();
+            tree.update(bob_id.0 as usize, &leaf(&state, bob_id))
+                .unwrap();
+            let post_root = tree.root();
+            let mut forged = rollup;
+            forged.initial_root = Some(pre_root);
+            forged.pre_tx_roots = Some(vec![pre_root]);
+            forged.final_root = Some(post_root);
+            forged.post_tx_roots = Some(vec![post_root]);
+            forged.sender_pre_tx_info_and_paths = Some(vec![(
+                pre_state.id_to_account_info[&alice_id],
+                sender_pre_path,
+            )]);
+            forged.recv_pre_tx_info_and_paths = Some(vec![(
+                pre_state.id_to_account_info[&bob_id],
+                recipient_pre_path,
+            )]);
+            forged.sender_post_paths = Some(vec![tree.generate_proof(copy).unwrap()]);
+            forged.recv_post_paths = Some(vec![tree.generate_proof(bob_id.0 as usize).unwrap()]);
+            // The copy is only rejected if the ledger binds the leaf indices.
+            assert_eq!(test_cs(forged), !bind_leaf_indices);
+        }
+    }
+
+    #[test]
+    fn processed_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let mut processed = ProcessedSet::new(&pp, 3);
+
+        // The transaction is recorded as it is applied, ...
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut state,
+            true,
+        )
+        .unwrap()
+        .with_processed_set(&mut processed)
+        .unwrap();
+        assert!(processed.contains(tx.id(&pp)));
+        assert!(test_cs(rollup));
+
+        // ... and so it can't be replayed in a later batch, ...
+        let replay =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap();
+        assert!(replay
+            .clone()
+            .with_processed_set(&mut processed.clone())
+            .is_none());
+        // ... even with a proof of its insertion into a set that lacks it.
+        let mut forged = replay
+            .with_processed_set(&mut ProcessedSet::new(&pp, 3))
+            .unwrap();
+        let post_root = forged.processed_roots.unwrap().1;
+        forged.processed_roots = Some((processed.root(), post_root));
+        assert!(!test_cs(forged.clone()));
+        forged.processed_roots = Some((processed.root(), processed.root()));
+        forged.processed_proofs = Some(vec![processed.placeholder_proof()]);
+        assert!(!test_cs(forged));
+    }
+
+    #[test]
+    fn tree_height_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut batch = |num_accounts| {
+            let mut state = State::new(num_accounts, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state, true).unwrap()
+        };
+
+        // The paths of a ledger of 32 accounts belong to a tree of height 5, ...
+        let rollup = Rollup {
+            tree_height: Some(5),
+            ..batch(32)
+        };
+        assert!(test_cs(rollup));
+        // ... while those of a ledger of 8 accounts are too short for it.
+        let rollup = Rollup {
+            tree_height: Some(5),
+            ..batch(8)
+        };
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn dust_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let alice_info = state.id_to_account_info[&alice_id];
+        let mut leaves_no_dust = |amount| {
+            let tx =
+                Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng);
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(&tx),
+                &mut state.clone(),
+                true,
+            )
+            .unwrap();
+            let rollup = Rollup {
+                dust_threshold: Some(Amount(5)),
+                ..rollup
+            };
+            let is_valid = test_cs(rollup);
+            assert_eq!(is_valid, tx.leaves_no_dust(Amount(5), &alice_info));
+            is_valid
+        };
+
+        // Transfers that leave at least the threshold are accepted, ...
+        assert!(leaves_no_dust(15));
+        // ... as are those that leave exactly nothing, ...
+        assert!(leaves_no_dust(20));
+        // ... while those that leave a dust remainder are rejected, ...
+        assert!(!leaves_no_dust(17));
+        // ... as are those that move less than the threshold.
+        assert!(!leaves_no_dust(4));
+    }
+
+    #[test]
+    fn diff_commitment_test() {
+        use ark_simple_payments::diff::{blank_diff_leaf, LeafUpdate};
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create_withdrawal(
+                &pp,
+                alice_id,
+                AssetId::NATIVE,
+                Amount(3),
+                Amount(0),
+                Default::default(),
+                &alice_sk,
+                &mut rng,
+            ),
+        ];
+        // The leaves of the touched accounts, after each transaction.
+        let mut states = vec![state.clone()];
+        for tx in &transactions {
+            let mut next = states.last().unwrap().clone();
+            next.apply_transaction(&pp, tx).unwrap();
+            states.push(next);
+        }
+        let leaf = |i: usize, id| states[i].id_to_account_info[&id].to_leaf(&pp);
+        let updates = [
+            LeafUpdate {
+                sender: alice_id,
+                recipient: bob_id,
+                old_leaves: [leaf(0, alice_id), leaf(0, bob_id)],
+                new_leaves: [leaf(1, alice_id), leaf(1, bob_id)],
+            },
+            LeafUpdate {
+                sender: alice_id,
+                recipient: WITHDRAWAL_ACCOUNT,
+                old_leaves: [leaf(1, alice_id), blank_diff_leaf()],
+                new_leaves: [leaf(2, alice_id), blank_diff_leaf()],
+            },
+        ];
+
+        // The native and the circuit commitments match, and cover exactly the
+        // touched leaves, ...
+        let rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &transactions, &mut state, true)
+                .unwrap()
+                .with_diff_commitment()
+                .unwrap();
+        assert_eq!(rollup.diff_commitment, Some(diff_commitment(&pp, &updates)));
+        assert!(test_cs(rollup.clone()));
+        // ... so a commitment that leaves out an update is rejected, ...
+        let mut bad_rollup = rollup.clone();
+        bad_rollup.diff_commitment = Some(diff_commitment(&pp, &updates[..1]));
+        assert!(!test_cs(bad_rollup));
+        // ... as is an opening with information that is not in the tree.
+        let mut bad_rollup = rollup;
+        let post_infos = bad_rollup.post_tx_infos.as_mut().unwrap();
+        post_infos[0].1.balances = post_infos[0].0.balances;
+        assert!(!test_cs(bad_rollup));
+    }
+
+    #[test]
+    fn batch_chain_test() {
+        use ark_ff::Zero;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let genesis = batch::batch_output_commitment(
+            &pp,
+            &BatchCommitment::zero(),
+            &state.root(),
+            state.epoch,
+        );
+        let mut next_batch = |amount, parent, state: &mut State| {
+            let tx =
+                Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng);
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], state, true)
+                .unwrap()
+                .with_prev_batch(parent)
+                .unwrap()
+        };
+
+        // The first batch extends the initial state, ...
+        let first = next_batch(5, BatchCommitment::zero(), &mut state);
+        assert_eq!(first.prev_batch_commitment, Some(genesis));
+        assert!(test_cs(first.clone()));
+        // ... and the second one extends the output of the first one.
+        let second = next_batch(3, genesis, &mut state);
+        assert_eq!(second.prev_batch_commitment, first.output_commitment());
+        assert!(test_cs(second.clone()));
+
+        // Chaining the second batch to the initial state instead, as if the first
+        // batch was skipped, fails, ...
+        let mut skipping = second.clone();
+        skipping.prev_batch_commitment = Some(genesis);
+        skipping.prev_batch_parent = Some(BatchCommitment::zero());
+        assert!(!test_cs(skipping));
+        // ... as does chaining it to a commitment that it can't open.
+        let mut unopened = second;
+        unopened.prev_batch_commitment = Some(genesis);
+        assert!(!test_cs(unopened));
+    }
+
+    #[test]
+    fn bind_recipient_key_test() {
+        use ark_simple_payments::account::AccountPublicKey;
+        use ark_simple_payments::ledger::MessageEncoding;
+        use ark_simple_payments::rotation::RotateKey;
+        use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+
+        let mut rng = ark_std::test_rng();
+        for encoding in [MessageEncoding::Raw, MessageEncoding::Structured] {
+            let mut pp = Parameters::sample(&mut rng);
+            pp.message_encoding = encoding;
+            pp.bind_recipient_key = true;
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state
+                .update_balance(alice_id, Amount(100))
+                .expect("Alice's account should exist");
+            let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            let apply = |state: &mut State, tx: &Transaction, validate| {
+                let rollup = Rollup::<1>::with_state_and_transactions(
+                    pp.clone(),
+                    std::slice::from_ref(tx),
+                    state,
+                    validate,
+                )
+                .unwrap();
+                test_cs(rollup)
+            };
+
+            // Alice signs the key of Bob's account along with its identifier, ...
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng)
+                .sign_to(&pp, &bob_pk, &alice_sk, &mut rng);
+            assert!(apply(&mut state.clone(), &tx, true));
+            // ... and withdrawals sign the default key.
+            let withdrawal = Transaction::create_withdrawal(
+                &pp,
+                alice_id,
+                AssetId::NATIVE,
+                Amount(5),
+                Amount(0),
+                Default::default(),
+                &alice_sk,
+                &mut rng,
+            )
+            .sign_to(&pp, &AccountPublicKey::default(), &alice_sk, &mut rng);
+            assert!(apply(&mut state.clone(), &withdrawal, true));
+
+            // Once Bob's identifier points to another key, the transfer is rejected.
+            let (new_pk, _new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+            let rotation = RotateKey::create(&pp, bob_id, new_pk, &bob_sk, &mut rng);
+            state.apply_rotation(&pp, &rotation).unwrap();
+            assert!(!tx.validate(&pp, &state));
+            assert!(!apply(&mut state, &tx, false));
+        }
+    }
+
+    #[test]
+    fn params_commitment_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Other parameters, with the same keys but a different daily limit.
+        let other_pp = Parameters {
+            daily_limit: Some(Amount(1000)),
+            ..pp.clone()
+        };
+        assert_ne!(pp.commitment(), other_pp.commitment());
+
+        let create = |pp: &Parameters, rng: &mut _| {
+            Transaction::create(pp, alice_id, bob_id, Amount(5), &alice_sk, rng)
+        };
+        let transactions = [create(&pp, &mut rng), create(&pp, &mut rng)];
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup.clone()));
+
+        // Mixing in a transaction signed under the other parameters is rejected,
+        // even though it is otherwise the same transaction.
+        let mixed_tx = create(&other_pp, &mut rng);
+        assert!(!mixed_tx.validate(&pp, &state));
+        let mut mixed_rollup = rollup;
+        mixed_rollup.transactions.as_mut().unwrap()[1] = mixed_tx;
+        assert!(!test_cs(mixed_rollup));
+    }
+
+    #[test]
+    fn skip_invalid_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Bob can't spend Alice's funds, so his transaction is skipped.
+        let valid_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let invalid_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let mut applied_state = state.clone();
+        applied_state.apply_transaction(&pp, &valid_tx).unwrap();
+        let mut final_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions_skipping_invalid(
+            pp.clone(),
+            &[valid_tx.clone(), invalid_tx],
+            &mut final_state,
+        )
+        .unwrap();
+        assert_eq!(rollup.applied, Some(vec![true, false]));
+        assert_eq!(rollup.final_root, Some(applied_state.root()));
+        assert!(test_cs(rollup.clone()));
+
+        // The flags must match the transactions that are actually applied.
+        for flags in [vec![true, true], vec![false, false]] {
+            let mut bad_rollup = rollup.clone();
+            bad_rollup.applied = Some(flags);
+            assert!(!test_cs(bad_rollup));
+        }
+        // Transactions that can't be applied at all, such as an underfunded one
+        // or a transfer moving nothing, are skipped as well.
+        let underfunded_tx =
+            Transaction::create(&pp, alice_id, bob_id, Amount(25), &alice_sk, &mut rng);
+        let zero_amount_tx =
+            Transaction::create(&pp, alice_id, bob_id, Amount(0), &alice_sk, &mut rng);
+        let skipping = Rollup::<3>::with_state_and_transactions_skipping_invalid(
+            pp.clone(),
+            &[valid_tx.clone(), underfunded_tx, zero_amount_tx],
+            &mut state.clone(),
+        )
+        .unwrap();
+        assert_eq!(skipping.applied, Some(vec![true, false, false]));
+        assert_eq!(skipping.final_root, Some(applied_state.root()));
+        assert!(test_cs(skipping.clone()));
+        let mut bad_rollup = skipping;
+        bad_rollup.applied = Some(vec![true, true, false]);
+        assert!(!test_cs(bad_rollup));
+
+        // Without skipping, the invalid transaction fails the whole batch.
+        assert!(Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[valid_tx, rollup.transactions.unwrap()[1].clone()],
+            &mut state.clone(),
+            true,
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn noop_padding_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // A batch with a single transfer is padded with a no-op, which is
+        // skipped and leaves the root unchanged.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let mut applied_state = state.clone();
+        applied_state.apply_transaction(&pp, &tx).unwrap();
+        let rollup = Rollup::<3>::with_state_and_transactions_skipping_invalid(
+            pp.clone(),
+            &[tx, Transaction::NOOP, Transaction::NOOP],
+            &mut state.clone(),
+        )
+        .unwrap();
+        assert_eq!(rollup.applied, Some(vec![true, false, false]));
+        assert_eq!(rollup.final_root, Some(applied_state.root()));
+        assert!(test_cs(rollup.clone()));
+
+        // The padding can't be applied, ...
+        let mut applied_padding = rollup.clone();
+        applied_padding.applied = Some(vec![true, true, false]);
+        assert!(!test_cs(applied_padding));
+        // ... and it can't change the root either.
+        let mut moved_root = rollup;
+        let initial_root = moved_root.initial_root;
+        moved_root.post_tx_roots.as_mut().unwrap()[2] = initial_root.unwrap();
+        moved_root.final_root = initial_root;
+        assert!(!test_cs(moved_root));
+    }
+
+    #[test]
+    fn signature_count_test() {
+        // Count the signatures `verified` against the authorizations `required`.
+        let counts_match = |verified: [bool; 2], required: [bool; 2]| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let flags = |flags: [bool; 2]| {
+                flags
+                    .iter()
+                    .map(|flag| Boolean::new_witness(cs.clone(), || Ok(*flag)).unwrap())
+                    .collect::<Vec<_>>()
+            };
+            Rollup::<2>::enforce_signature_count(&flags(verified), &flags(required)).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(counts_match([true, true], [true, true]));
+        assert!(counts_match([true, false], [true, false]));
+        // An under-counted set of signatures is rejected.
+        assert!(!counts_match([true, false], [true, true]));
+        assert!(!counts_match([false, false], [false, true]));
+
+        // A batch verifies the signature of each of its transactions, and so
+        // forging one of them is rejected.
+        let rollup = build_two_tx_circuit();
+        assert!(test_cs(rollup.clone()));
+        let mut forged = rollup;
+        forged.transactions.as_mut().unwrap()[1].signature = Default::default();
+        assert!(!test_cs(forged));
+    }
+
+    #[test]
+    fn no_negative_balances_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(3)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let info = |id| state.id_to_account_info[&id];
+
+        // Sweep a transaction of `amount` from Alice to Bob, as if it slipped past
+        // its own checks.
+        let sweep = |amount, applied| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let tx = Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(amount),
+                &alice_sk,
+                &mut ark_std::test_rng(),
+            );
+            let tx = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            let pre_infos = (
+                AccountInformationVar::new_witness(cs.clone(), || Ok(info(alice_id))).unwrap(),
+                AccountInformationVar::new_witness(cs.clone(), || Ok(info(bob_id))).unwrap(),
+            );
+            let applied = Boolean::new_witness(cs.clone(), || Ok(applied)).unwrap();
+            Rollup::<1>::enforce_no_negative_balances(&[tx], &[pre_infos], &[applied]).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        assert!(sweep(3, true));
+        // An overdraft, which would wrap around to a huge balance as a `UInt64`,
+        // is caught, ...
+        assert!(!sweep(5, true));
+        // ... unless the transaction is skipped.
+        assert!(sweep(5, false));
+
+        // The sweep accepts the balances of a valid batch.
+        assert!(test_cs(build_two_tx_circuit()));
+    }
+
+    #[test]
+    fn sequencer_signature_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (_sequencer_id, sequencer_pk, sequencer_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let batch = |amount, rng: &mut _| {
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, rng);
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state.clone(), true)
+                .unwrap()
+        };
+
+        let signed = batch(5, &mut rng)
+            .with_sequencer_signature(&sequencer_sk, &mut rng)
+            .unwrap();
+        assert_eq!(signed.sequencer_key, Some(sequencer_pk));
+        assert!(test_cs(signed.clone()));
+        // A batch signed by another key is rejected, ...
+        let mut forged = batch(5, &mut rng)
+            .with_sequencer_signature(&bob_sk, &mut rng)
+            .unwrap();
+        forged.sequencer_key = Some(sequencer_pk);
+        assert!(!test_cs(forged));
+        // ... and so is the authorization of another batch.
+        let other = batch(3, &mut rng)
+            .with_sequencer_signature(&sequencer_sk, &mut rng)
+            .unwrap();
+        let mut replayed = signed;
+        replayed.sequencer_signature = other.sequencer_signature;
+        assert!(!test_cs(replayed));
+    }
+
+    #[test]
+    fn check_satisfied_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(rollup.check_satisfied(), Ok(()));
+
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&bad_tx),
+            &mut state.clone(),
+            false,
+        )
+        .unwrap();
+        // The signature check only computes a flag, so an honestly assigned bad
+        // signature is caught when the validity of the transaction is enforced.
+        let unsatisfied = rollup.check_satisfied().unwrap_err();
+        let trace = unsatisfied.trace.unwrap();
+        assert!(trace.starts_with("/rollup::generate_constraints/eq::enforce_equal"));
+
+        // A prover claiming that the bad signature verifies must instead assign a
+        // wrong value to the result of the signature check itself.
+        let mut layer = ConstraintLayer::default();
+        layer.mode = OnlyConstraints;
+        let subscriber = tracing_subscriber::Registry::default().with(layer);
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        let last_signature_constraint = cs
+            .constraint_names()
+            .unwrap()
+            .iter()
+            .rposition(|name| name.contains("/verify_signature/"))
+            .unwrap();
+        cs.finalize();
+        let matrices = cs.to_matrices().unwrap();
+        let result = matrices.c[last_signature_constraint]
+            .iter()
+            .map(|(_, var)| *var)
+            .max()
+            .unwrap();
+        {
+            let mut cs = cs.borrow_mut().unwrap();
+            let witness = &mut cs.witness_assignment[result - matrices.num_instance_variables];
+            *witness = ConstraintF::one() - *witness;
+        }
+        let unsatisfied = profiling::first_unsatisfied_constraint(&cs)
+            .unwrap()
+            .unwrap();
+        assert!(unsatisfied.index <= last_signature_constraint);
+        assert!(unsatisfied.trace.unwrap().contains("/verify_signature/"));
+    }
+
+    #[test]
+    fn constraint_ranges_test() {
+        let rollup = build_two_tx_circuit();
+        let ranges = rollup.constraint_ranges();
+        assert_eq!(ranges.len(), 2);
+
+        let cs = ConstraintSystem::new_ref();
+        let (layer, counts) = profiling::count_constraints_by_span(cs.clone());
+        let subscriber = tracing_subscriber::Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            rollup.generate_constraints(cs.clone()).unwrap();
+        });
+        // The ranges are in order, don't overlap, and lie within the circuit, ...
+        assert!(ranges.iter().all(|range| !range.is_empty()));
+        assert!(ranges[0].end <= ranges[1].start);
+        assert!(ranges[1].end <= cs.num_constraints());
+        // ... and together they cover exactly the validation of the transactions,
+        // including their signature checks and Merkle path checks.
+        let covered: usize = ranges.iter().map(|range| range.len()).sum();
+        assert_eq!(covered, counts.get("validate_with_signature"));
+        let signature = counts.get("verify_signature");
+        let membership = counts.get("check_account_existence");
+        assert!(signature > 0 && membership > 0);
+        assert!(signature + membership <= covered);
+    }
+
+    #[test]
+    fn max_batch_for_budget_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        fn count<const N: usize>(pp: &Parameters, tx: &Transaction, state: &State) -> usize {
+            Rollup::<N>::with_state_and_transactions(
+                pp.clone(),
+                &vec![tx.clone(); N],
+                &mut state.clone(),
+                true,
+            )
+            .unwrap()
+            .synthesize_only()
+            .unwrap()
+            .num_constraints()
+        }
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+
+        // A budget of exactly two transactions fits two, but not three, ...
+        let budget = count::<2>(&pp, &tx, &state);
+        assert_eq!(rollup.max_batch_for_budget(budget), 2);
+        assert!(count::<3>(&pp, &tx, &state) > budget);
+        // ... while a constraint less only fits one, ...
+        assert_eq!(rollup.max_batch_for_budget(budget - 1), 1);
+        assert!(count::<1>(&pp, &tx, &state) < budget);
+        // ... and a budget below the overhead fits none.
+        assert_eq!(rollup.max_batch_for_budget(0), 0);
+    }
+
+    // Builds a circuit with two txs, using different pubkeys & amounts every time.
+    // It returns this circuit
+    fn build_two_tx_circuit() -> Rollup<2> {
+        use ark_std::rand::Rng;
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        // Let's make an account for Alice.
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Let's give her some initial balance to start with.
+        state
+            .update_balance(alice_id, Amount(1000))
+            .expect("Alice's account should exist");
+        // Let's make an account for Bob.
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let amount_to_send = rng.gen_range(1..200);
+
         // Alice wants to transfer amount_to_send units to Bob, and does this twice
         let mut temp_state = state.clone();
         let tx1 = Transaction::create(
@@ -441,14 +3911,31 @@ mod test {
             &alice_sk,
             &mut rng,
         );
-        let rollup = Rollup::<2>::with_state_and_transactions(
+        Rollup::<2>::with_state_and_transactions(
             pp.clone(),
             &[tx1.clone(), tx1.clone()],
             &mut temp_state,
             true,
         )
-        .unwrap();
-        rollup
+        .unwrap()
+    }
+
+    #[test]
+    fn synthesize_only_test() {
+        let rollup = build_two_tx_circuit();
+        let cs = rollup.synthesize_only().unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(
+            cs.num_instance_variables(),
+            1 + BatchPublicInputs::from_batch(&rollup).len()
+        );
+
+        // A batch with a wrong final root is synthesized all the same, but its
+        // constraint system is not satisfied.
+        let mut bad_rollup = rollup;
+        bad_rollup.final_root = bad_rollup.initial_root;
+        let cs = bad_rollup.synthesize_only().unwrap();
+        assert!(!cs.is_satisfied().unwrap());
     }
 
     #[test]
@@ -466,10 +3953,7 @@ mod test {
         // Use the same circuit but with different inputs to verify against
         // This test checks that the SNARK passes on the provided input
         let circuit_to_verify_against = build_two_tx_circuit();
-        let public_input = [
-            circuit_to_verify_against.initial_root.unwrap(),
-            circuit_to_verify_against.final_root.unwrap(),
-        ];
+        let public_input = BatchPublicInputs::from_batch(&circuit_to_verify_against);
 
         let proof = Groth16::prove(&pk, circuit_to_verify_against, &mut rng).unwrap();
         let valid_proof = Groth16::verify(&vk, &public_input, &proof).unwrap();
@@ -482,6 +3966,11 @@ mod test {
         let public_input = [
             circuit_to_verify_against.final_root.unwrap(),
             circuit_to_verify_against.final_root.unwrap(),
+            circuit_to_verify_against.withdrawal_root.unwrap(),
+            ConstraintF::from(circuit_to_verify_against.pre_epoch.unwrap()),
+            ConstraintF::from(circuit_to_verify_against.post_epoch.unwrap()),
+            circuit_to_verify_against.block_commitment.unwrap(),
+            circuit_to_verify_against.ledger_params.commitment(),
         ];
 
         let proof = Groth16::prove(&pk, circuit_to_verify_against, &mut rng).unwrap();
@@ -0,0 +1,239 @@
+use crate::account::{
+    AccountIdVar, AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar,
+};
+use crate::ledger::{AccPathVar, AccRootVar, ParametersVar};
+use crate::ConstraintF;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::account::AccountPublicKey;
+use ark_simple_payments::rotation::{RotateKey, ROTATE_KEY_TAG};
+use std::borrow::Borrow;
+
+/// A key rotation, which replaces the public key of `account` by
+/// `new_public_key`.
+pub struct RotateKeyVar {
+    /// The account whose key is rotated.
+    pub account: AccountIdVar,
+    /// The public key of the account after the rotation.
+    pub new_public_key: AccountPublicKeyVar,
+    /// The signature over the rotation, under the key before the rotation, or
+    /// under the recovery key of the account if `by_recovery_key` is set.
+    pub signature: AccountSignatureVar,
+    /// Whether the rotation is authorized by the recovery key of the account
+    /// instead of its current key.
+    pub by_recovery_key: Boolean<ConstraintF>,
+}
+
+impl RotateKeyVar {
+    /// Construct the message that is signed to authorize the rotation.
+    fn message(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The authorized message consists of
+        // (Tag || AccountId || NewPublicKey)
+        let mut message = UInt8::constant_vec(ROTATE_KEY_TAG);
+        message.extend(self.account.to_bytes_le());
+        message.extend(self.new_public_key.to_bytes()?);
+        Ok(message)
+    }
+
+    /// Check that the rotation is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the signature is valid with respect to the public key of
+    ///    the account *before* the rotation, or with respect to its recovery key
+    ///    if the rotation is authorized by it, as `RotateKey::signer_key` selects.
+    /// 2. Verify that the account exists, and is updated accordingly: only its
+    ///    public key and its nonce change.
+    ///
+    /// Unlike `RotateKey::validate`, this doesn't check that the new key is
+    /// unused, just like registration isn't proven in the circuit.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(
+            self,
+            parameters,
+            pre_acc_info,
+            pre_path,
+            post_path,
+            pre_root,
+            post_root
+        )
+    )]
+    pub fn validate(
+        &self,
+        parameters: &ParametersVar,
+        pre_acc_info: &AccountInformationVar,
+        pre_path: &AccPathVar,
+        post_path: &AccPathVar,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // An account without a recovery key, i.e., whose recovery key is the
+        // identity, can't be recovered: anyone could sign under the identity.
+        let no_recovery_key =
+            pre_acc_info
+                .recovery_key
+                .is_eq(&AccountPublicKeyVar::new_constant(
+                    pre_acc_info.recovery_key.cs(),
+                    AccountPublicKey::default(),
+                )?)?;
+        let signer_key = self
+            .by_recovery_key
+            .select(&pre_acc_info.recovery_key, &pre_acc_info.public_key)?;
+        let sig_verifies = parameters
+            .verify_signature(&signer_key, self.message()?, &self.signature)?
+            .and(&self.by_recovery_key.and(&no_recovery_key)?.not())?;
+
+        let mut post_acc_info = pre_acc_info.clone();
+        post_acc_info.public_key = self.new_public_key.clone();
+        post_acc_info.nonce = UInt64::addmany(&[pre_acc_info.nonce.clone(), UInt64::constant(1)])?;
+
+        // Check that the account information is correct with respect to
+        // `pre_root` before the rotation, and with respect to `post_root` after it.
+        let check_membership = |path: &AccPathVar, info: &AccountInformationVar, root| {
+            path.verify_membership(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                root,
+                &info.to_leaf(parameters)?.as_slice(),
+            )
+        };
+        let existed = check_membership(pre_path, pre_acc_info, pre_root)?;
+        let will_exist = check_membership(post_path, &post_acc_info, post_root)?;
+
+        existed.and(&will_exist)?.and(&sig_verifies)
+    }
+}
+
+impl AllocVar<RotateKey, ConstraintF> for RotateKeyVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<RotateKey>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|rotation| {
+            let rotation: &RotateKey = rotation.borrow();
+            let account = AccountIdVar::new_variable(cs.clone(), || Ok(&rotation.account), mode)?;
+            let new_public_key = AccountPublicKeyVar::new_variable(
+                cs.clone(),
+                || Ok(&rotation.new_public_key),
+                mode,
+            )?;
+            let signature =
+                AccountSignatureVar::new_variable(cs.clone(), || Ok(&rotation.signature), mode)?;
+            let by_recovery_key =
+                Boolean::new_variable(cs.clone(), || Ok(rotation.by_recovery_key), mode)?;
+            Ok(Self {
+                account,
+                new_public_key,
+                signature,
+                by_recovery_key,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+
+    /// Validate `rotation` in a fresh constraint system, using witnesses taken
+    /// from `state` before the rotation and from `post_state` after it, and
+    /// return whether the constraint system is satisfied.
+    fn rotation_is_valid(
+        pp: &Parameters,
+        state: &State,
+        post_state: &State,
+        rotation: &RotateKey,
+    ) -> bool {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let path = |state: &State| {
+            let path = state.prove_account(rotation.account).unwrap().path;
+            AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap()
+        };
+        let info = state.id_to_account_info[&rotation.account];
+        let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+        let params = ParametersVar::new_constant(cs.clone(), pp).unwrap();
+        let rotation_var = RotateKeyVar::new_witness(cs.clone(), || Ok(rotation)).unwrap();
+        let pre_root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_state.root())).unwrap();
+        rotation_var
+            .validate(
+                &params,
+                &info,
+                &path(state),
+                &path(post_state),
+                &pre_root,
+                &post_root,
+            )
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn rotate_key_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (new_pk, new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let rotation = RotateKey::create(&pp, alice_id, new_pk, &alice_sk, &mut rng);
+        let mut post_state = state.clone();
+        post_state.apply_rotation(&pp, &rotation).unwrap();
+
+        // The rotation is authorized by the old key, ...
+        assert!(rotation_is_valid(&pp, &state, &post_state, &rotation));
+        // ... and not by the new one.
+        let bad_rotation = RotateKey::create(&pp, alice_id, new_pk, &new_sk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+        assert!(!rotation_is_valid(&pp, &state, &post_state, &bad_rotation));
+        // The balance of the account can't change along with its key.
+        let mut bad_post_state = post_state.clone();
+        bad_post_state.update_balance(alice_id, Amount(11)).unwrap();
+        assert!(!rotation_is_valid(&pp, &state, &bad_post_state, &rotation));
+    }
+
+    #[test]
+    fn recovery_key_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (recovery_pk, recovery_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (new_pk, _new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let rotation =
+            RotateKey::create_with_recovery_key(&pp, alice_id, new_pk, &recovery_sk, &mut rng);
+
+        // An account without a recovery key can't be recovered, even towards the
+        // state that its current key would rotate it to, ...
+        let mut post_state = state.clone();
+        let own_rotation = RotateKey::create(&pp, alice_id, new_pk, &alice_sk, &mut rng);
+        post_state.apply_rotation(&pp, &own_rotation).unwrap();
+        assert!(!rotation_is_valid(&pp, &state, &post_state, &rotation));
+
+        // ... while one with a recovery key is recovered by it, ...
+        state.set_recovery_key(alice_id, recovery_pk).unwrap();
+        let mut post_state = state.clone();
+        post_state.apply_rotation(&pp, &rotation).unwrap();
+        assert!(rotation_is_valid(&pp, &state, &post_state, &rotation));
+        // ... and not by an unauthorized key, ...
+        let bad_rotation =
+            RotateKey::create_with_recovery_key(&pp, alice_id, new_pk, &alice_sk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+        assert!(!rotation_is_valid(&pp, &state, &post_state, &bad_rotation));
+        // ... nor by a signature of the recovery key that claims to be under the
+        // current key.
+        let bad_rotation = RotateKey::create(&pp, alice_id, new_pk, &recovery_sk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+        assert!(!rotation_is_valid(&pp, &state, &post_state, &bad_rotation));
+    }
+}
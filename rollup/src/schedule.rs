@@ -0,0 +1,193 @@
+use crate::account::{AccountPublicKeyVar, AccountSignatureVar};
+use crate::ledger::{AccRootVar, AccountHashGadget, ParametersVar};
+use crate::processed::InsertionProofVar;
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::schedule::{ScheduledAuthorization, EXECUTION_TAG, SCHEDULE_TAG};
+use std::borrow::Borrow;
+
+/// The authorization of a transaction to be executed on each of the epochs of a
+/// schedule, see `ScheduledAuthorization`.
+pub struct ScheduledAuthorizationVar {
+    /// The epochs on which the transaction may be executed.
+    pub epochs: Vec<UInt64<ConstraintF>>,
+    /// The signature of the sender over the hash of the transaction and the
+    /// epochs.
+    pub signature: AccountSignatureVar,
+}
+
+impl AllocVar<ScheduledAuthorization, ConstraintF> for ScheduledAuthorizationVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<ScheduledAuthorization>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|authorization| {
+            let authorization = authorization.borrow();
+            let epochs = authorization
+                .epochs
+                .iter()
+                .map(|epoch| UInt64::new_variable(cs.clone(), || Ok(*epoch), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            let signature =
+                AccountSignatureVar::new_variable(cs, || Ok(&authorization.signature), mode)?;
+            Ok(Self { epochs, signature })
+        })
+    }
+}
+
+impl TransactionVar {
+    /// The identifier of the execution of the transaction on `epoch`, as
+    /// computed by `schedule::execution_id`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, epoch))]
+    pub fn execution_id(
+        &self,
+        parameters: &ParametersVar,
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        // The hashed input consists of
+        // (Tag || TransactionHash || Epoch)
+        let mut input = UInt8::constant_vec(EXECUTION_TAG);
+        input.extend(self.hash(parameters)?.to_bytes()?);
+        input.extend(epoch.to_bytes()?);
+        <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )
+    }
+
+    /// Verify the execution of the transaction on `epoch` under the schedule
+    /// `authorization`, as `ScheduledAuthorization::execute` does natively: the
+    /// schedule must be signed by the sender, whose public key is `sender_key`,
+    /// and include `epoch`, and the execution must be inserted into the set of
+    /// used executions with root `used_root` as shown by `proof`, which shows that
+    /// it wasn't made before. Returns whether all of this holds, along with the
+    /// root of the set of used executions after the insertion.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, parameters, sender_key, authorization, epoch, used_root, proof)
+    )]
+    pub fn verify_scheduled_execution(
+        &self,
+        parameters: &ParametersVar,
+        sender_key: &AccountPublicKeyVar,
+        authorization: &ScheduledAuthorizationVar,
+        epoch: &UInt64<ConstraintF>,
+        used_root: &AccRootVar,
+        proof: &InsertionProofVar,
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError> {
+        let tx_hash = self.hash(parameters)?;
+        // The signed message consists of
+        // (Tag || TransactionHash || Epoch_1 || ... || Epoch_n).
+        let mut message = UInt8::constant_vec(SCHEDULE_TAG);
+        message.extend(tx_hash.to_bytes()?);
+        for scheduled in &authorization.epochs {
+            message.extend(scheduled.to_bytes()?);
+        }
+        let authorized =
+            parameters.verify_signature(sender_key, message, &authorization.signature)?;
+        let is_scheduled = Boolean::kary_or(
+            &authorization
+                .epochs
+                .iter()
+                .map(|scheduled| scheduled.is_eq(epoch))
+                .collect::<Result<Vec<_>, _>>()?,
+        )?;
+        let (is_unused, post_root) = proof.insert(
+            parameters,
+            used_root,
+            &self.execution_id(parameters, epoch)?,
+        )?;
+        let is_valid = Boolean::kary_and(&[authorized, is_scheduled, is_unused])?;
+        Ok((is_valid, post_root))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::account::AccountPublicKey;
+    use ark_simple_payments::ledger::{AccRoot, Amount, Parameters, State};
+    use ark_simple_payments::processed::{InsertionProof, ProcessedSet};
+    use ark_simple_payments::schedule::execution_id;
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn scheduled_execution_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let schedule =
+            ScheduledAuthorization::sign(&pp, &tx, &[3, 5], &alice_sk, &mut rng).unwrap();
+        let mut used = ProcessedSet::new(&pp, 3);
+
+        // Execute `tx` on `epoch` under `schedule` in a fresh constraint system,
+        // inserting it into the set with root `pre_root` as shown by `proof`, and
+        // return whether the execution is valid and leads to `post_root`.
+        let execution_is_valid = |sender_key: AccountPublicKey,
+                                  epoch: u64,
+                                  pre_root: AccRoot,
+                                  post_root: AccRoot,
+                                  proof: &InsertionProof| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let tx = TransactionVar::new_witness(cs.clone(), || Ok(&tx)).unwrap();
+            let sender_key =
+                AccountPublicKeyVar::new_witness(cs.clone(), || Ok(sender_key)).unwrap();
+            let authorization =
+                ScheduledAuthorizationVar::new_witness(cs.clone(), || Ok(&schedule)).unwrap();
+            let epoch = UInt64::new_input(cs.clone(), || Ok(epoch)).unwrap();
+            let pre_root = AccRootVar::new_input(cs.clone(), || Ok(pre_root)).unwrap();
+            let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_root)).unwrap();
+            let proof = InsertionProofVar::new_witness(cs.clone(), || Ok(proof)).unwrap();
+            let (is_valid, root) = tx
+                .verify_scheduled_execution(
+                    &params,
+                    &sender_key,
+                    &authorization,
+                    &epoch,
+                    &pre_root,
+                    &proof,
+                )
+                .unwrap();
+            is_valid.enforce_equal(&Boolean::TRUE).unwrap();
+            root.enforce_equal(&post_root).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // The payment is executed on a scheduled epoch, ...
+        let pre_root = used.root();
+        let proof = schedule.execute(&pp, &tx, &alice_pk, 5, &mut used).unwrap();
+        let post_root = used.root();
+        assert!(execution_is_valid(alice_pk, 5, pre_root, post_root, &proof));
+        // ... but not under the key of someone else, ...
+        assert!(!execution_is_valid(bob_pk, 5, pre_root, post_root, &proof));
+        // ... nor on an epoch that is off the schedule, ...
+        let mut off_schedule = ProcessedSet::new(&pp, 3);
+        let id = execution_id(&pp, &tx, 4);
+        let proof = off_schedule.insert(&pp, id).unwrap();
+        assert!(!execution_is_valid(
+            alice_pk,
+            4,
+            pre_root,
+            off_schedule.root(),
+            &proof
+        ));
+        // ... nor twice on the same epoch.
+        let proof = used.placeholder_proof();
+        assert!(!execution_is_valid(
+            alice_pk, 5, post_root, post_root, &proof
+        ));
+    }
+}
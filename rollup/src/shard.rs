@@ -0,0 +1,118 @@
+use crate::account::AccountIdVar;
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_r1cs_std::bits::uint8::UInt8;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::shard::Shard;
+use std::borrow::Borrow;
+
+/// A shard of the account tree, see `Shard`. Only the identifier of the shard
+/// is a variable: its size fixes which bits of the account identifiers are
+/// compared, and so it is part of the shape of the circuit.
+#[derive(Clone)]
+pub struct ShardVar {
+    /// The index of the shard among the shards of its size.
+    pub id: UInt8<ConstraintF>,
+    /// The logarithm of the number of leaves in the shard.
+    pub log_size: u8,
+}
+
+impl ShardVar {
+    /// Is the account `account` in this shard, as `Shard::contains` checks?
+    #[tracing::instrument(target = "r1cs", skip(self, account))]
+    pub fn contains(&self, account: &AccountIdVar) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // The bits of the index above the size of the shard must spell out the
+        // identifier of the shard, and the identifier can't have any other bits.
+        let log_size = usize::from(self.log_size);
+        let index = account.0.to_bits_le()?;
+        let id = self.id.to_bits_le()?;
+        let matches = id
+            .iter()
+            .enumerate()
+            .map(|(i, bit)| bit.is_eq(index.get(i + log_size).unwrap_or(&Boolean::FALSE)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Boolean::kary_and(&matches)
+    }
+}
+
+impl AllocVar<Shard, ConstraintF> for ShardVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Shard>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|shard| {
+            let shard = *shard.borrow();
+            Ok(Self {
+                id: UInt8::new_variable(cs, || Ok(shard.id), mode)?,
+                log_size: shard.log_size,
+            })
+        })
+    }
+}
+
+impl TransactionVar {
+    /// Check whether the transaction stays within the shard `shard`, as
+    /// `Transaction::is_in_shard` does.
+    #[tracing::instrument(target = "r1cs", skip(self, shard))]
+    pub fn is_in_shard(&self, shard: &ShardVar) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        shard.contains(&self.sender)?.and(
+            &self
+                .is_withdrawal()?
+                .or(&shard.contains(&self.recipient)?)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rollup::Rollup;
+    use crate::ConstraintF;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::shard::Shard;
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn shard_gadget_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (_carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (dave_id, _dave_pk, _dave_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Alice and Bob share the first shard of size 4, while Dave is in the
+        // next one.
+        let shard = Shard::new(0, 2).unwrap();
+        assert!(shard.contains(alice_id) && shard.contains(bob_id));
+        assert!(!shard.contains(dave_id));
+
+        let is_satisfied = |rollup: Rollup<1>| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+        let batch = |recipient, rng: &mut _| {
+            let tx = Transaction::create(&pp, alice_id, recipient, Amount(5), &alice_sk, rng);
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &mut state.clone(), true)
+                .unwrap()
+        };
+
+        // A transaction within the shard is accepted in single-shard mode, ...
+        let rollup = batch(bob_id, &mut rng).with_shard(shard).unwrap();
+        assert!(is_satisfied(rollup));
+        // ... while a cross-shard transaction is rejected, even if the batch
+        // claims its shard anyway.
+        assert!(batch(dave_id, &mut rng).with_shard(shard).is_none());
+        let mut rollup = batch(dave_id, &mut rng);
+        assert!(is_satisfied(rollup.clone()));
+        rollup.shard = Some(shard);
+        assert!(!is_satisfied(rollup));
+    }
+}
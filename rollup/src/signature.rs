@@ -0,0 +1,171 @@
+//! Pluggable signature schemes, and a standalone circuit that proves the
+//! validity of a single signature, for use outside of rollup batches.
+
+use crate::ConstraintF;
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_ff::ToConstraintField;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_simple_payments::signature::schnorr::{
+    constraints::{SchnorrPointSignatureVerifyGadget, SchnorrSignatureVerifyGadget},
+    Schnorr, SchnorrPoint,
+};
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
+use derivative::Derivative;
+
+/// The parameters of the signature scheme `S` in the circuit.
+pub type SigParametersVar<S> =
+    <<S as TxSignatureScheme>::Gadget as SigVerifyGadget<S, ConstraintF>>::ParametersVar;
+/// A public key of the signature scheme `S` in the circuit.
+pub type SigPublicKeyVar<S> =
+    <<S as TxSignatureScheme>::Gadget as SigVerifyGadget<S, ConstraintF>>::PublicKeyVar;
+/// A signature of the signature scheme `S` in the circuit.
+pub type SigSignatureVar<S> =
+    <<S as TxSignatureScheme>::Gadget as SigVerifyGadget<S, ConstraintF>>::SignatureVar;
+
+/// A signature scheme that transactions can be signed with. It bundles the
+/// native scheme, whose parameter, key, and signature types it shares, with the
+/// gadget that verifies its signatures, so that the transaction logic doesn't
+/// depend on a particular scheme. Schnorr signatures are the default.
+pub trait TxSignatureScheme: SignatureScheme + Sized {
+    /// The gadget that verifies the signatures of the scheme.
+    type Gadget: SigVerifyGadget<Self, ConstraintF>;
+
+    /// Verify `signature` over `message` against `pub_key`. Malformed
+    /// signatures are rejected.
+    fn verify_native(
+        parameters: &Self::Parameters,
+        pub_key: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> bool {
+        Self::verify(parameters, pub_key, message, signature).unwrap_or(false)
+    }
+
+    /// Verify `signature` over `message` against `pub_key`, as `verify_native`
+    /// does.
+    fn verify_gadget(
+        parameters: &SigParametersVar<Self>,
+        pub_key: &SigPublicKeyVar<Self>,
+        message: &[UInt8<ConstraintF>],
+        signature: &SigSignatureVar<Self>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        Self::Gadget::verify(parameters, pub_key, message, signature)
+    }
+}
+
+impl TxSignatureScheme for Schnorr<EdwardsProjective> {
+    type Gadget = SchnorrSignatureVerifyGadget<EdwardsProjective, EdwardsVar>;
+}
+
+impl TxSignatureScheme for SchnorrPoint<EdwardsProjective> {
+    type Gadget = SchnorrPointSignatureVerifyGadget<EdwardsProjective, EdwardsVar>;
+}
+
+/// A circuit proving that `signature` is a valid signature over `message` under
+/// `pubkey`, in the signature scheme `S`. The public key and the message are
+/// public inputs, while the signature is a witness, and so the proof doesn't
+/// reveal it.
+///
+/// The length of the message affects the shape of the circuit, and so it is
+/// fixed by the setup.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct SignatureCircuit<S: TxSignatureScheme = Schnorr<EdwardsProjective>> {
+    /// The parameters of the signature scheme.
+    pub params: S::Parameters,
+    /// The public key that the signature is verified against.
+    pub pubkey: S::PublicKey,
+    /// The signed message.
+    pub message: Vec<u8>,
+    /// The signature over `message`.
+    pub signature: S::Signature,
+}
+
+impl<S: TxSignatureScheme> SignatureCircuit<S>
+where
+    S::PublicKey: ToConstraintField<ConstraintF>,
+{
+    /// The public inputs of the circuit, in the order in which it declares them:
+    /// the public key as field elements, e.g., its coordinates, followed by the
+    /// message packed into field elements.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = self.pubkey.to_field_elements().unwrap();
+        inputs.extend(
+            ToConstraintField::<ConstraintF>::to_field_elements(&self.message[..]).unwrap(),
+        );
+        inputs
+    }
+}
+
+impl<S: TxSignatureScheme> ConstraintSynthesizer<ConstraintF> for SignatureCircuit<S> {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let params = SigParametersVar::<S>::new_constant(cs.clone(), &self.params)?;
+        let pubkey = SigPublicKeyVar::<S>::new_input(cs.clone(), || Ok(&self.pubkey))?;
+        let message = UInt8::new_input_vec(cs.clone(), &self.message)?;
+        let signature = SigSignatureVar::<S>::new_witness(cs, || Ok(&self.signature))?;
+        S::verify_gadget(&params, &pubkey, &message, &signature)?.enforce_equal(&Boolean::TRUE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_bls12_381::Bls12_381;
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+
+    /// Prove a signature in the scheme `S` with `SignatureCircuit`, and check
+    /// that the proof only verifies for the signed message and key.
+    fn check_signature_circuit<S: TxSignatureScheme>()
+    where
+        S::PublicKey: ToConstraintField<ConstraintF>,
+    {
+        let mut rng = ark_std::test_rng();
+        let params = S::setup(&mut rng).unwrap();
+        let (pubkey, sk) = S::keygen(&params, &mut rng).unwrap();
+        let message = b"Pay Bob 5 coins".to_vec();
+        let signature = S::sign(&params, &sk, &message, &mut rng).unwrap();
+        assert!(S::verify_native(&params, &pubkey, &message, &signature));
+        let circuit = SignatureCircuit::<S> {
+            params,
+            pubkey,
+            message,
+            signature,
+        };
+
+        let (pk, vk) =
+            Groth16::<Bls12_381>::circuit_specific_setup(circuit.clone(), &mut rng).unwrap();
+        let proof = Groth16::prove(&pk, circuit.clone(), &mut rng).unwrap();
+        assert!(Groth16::verify(&vk, &circuit.public_inputs(), &proof).unwrap());
+
+        // The proof doesn't verify for another message of the same length, ...
+        let other = SignatureCircuit {
+            message: b"Pay Bob 9 coins".to_vec(),
+            ..circuit.clone()
+        };
+        assert!(!Groth16::verify(&vk, &other.public_inputs(), &proof).unwrap());
+        // ... nor under another public key.
+        let (other_pubkey, _) = S::keygen(&circuit.params, &mut rng).unwrap();
+        let other = SignatureCircuit {
+            pubkey: other_pubkey,
+            ..circuit
+        };
+        assert!(!Groth16::verify(&vk, &other.public_inputs(), &proof).unwrap());
+    }
+
+    #[test]
+    fn signature_circuit_test() {
+        check_signature_circuit::<Schnorr<EdwardsProjective>>();
+    }
+
+    #[test]
+    fn swapped_scheme_test() {
+        // Point signatures plug into the same circuit as the default scheme.
+        check_signature_circuit::<SchnorrPoint<EdwardsProjective>>();
+    }
+}
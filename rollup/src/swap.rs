@@ -0,0 +1,348 @@
+use crate::account::{AccountIdVar, AccountInformationVar, AccountSignatureVar};
+use crate::ledger::{AccPathVar, AccRootVar, AmountVar, AssetIdVar, BalancesVar, ParametersVar};
+use crate::ConstraintF;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::swap::{Swap, SWAP_TAG};
+use std::borrow::Borrow;
+
+/// An atomic swap, in which `party_a` sends `amount_a` of the asset `asset_a` to
+/// `party_b`, and `party_b` sends `amount_b` of the asset `asset_b` to `party_a`.
+pub struct SwapVar {
+    /// The account sending `amount_a`, and receiving `amount_b`.
+    pub party_a: AccountIdVar,
+    /// The account sending `amount_b`, and receiving `amount_a`.
+    pub party_b: AccountIdVar,
+    /// The asset being transferred from `party_a` to `party_b`.
+    pub asset_a: AssetIdVar,
+    /// The amount being transferred from `party_a` to `party_b`.
+    pub amount_a: AmountVar,
+    /// The asset being transferred from `party_b` to `party_a`.
+    pub asset_b: AssetIdVar,
+    /// The amount being transferred from `party_b` to `party_a`.
+    pub amount_b: AmountVar,
+    /// The signature of `party_a` over the whole swap.
+    pub signature_a: AccountSignatureVar,
+    /// The signature of `party_b` over the whole swap.
+    pub signature_b: AccountSignatureVar,
+}
+
+impl SwapVar {
+    /// Construct the message that is signed by both parties to authorize the swap.
+    fn message(&self) -> Vec<UInt8<ConstraintF>> {
+        // The authorized message consists of
+        // (Tag || PartyA || PartyB || AssetA || AssetB || AmountA || AmountB)
+        let mut message = UInt8::constant_vec(SWAP_TAG);
+        message.extend(self.party_a.to_bytes_le());
+        message.extend(self.party_b.to_bytes_le());
+        message.extend(self.asset_a.to_bytes_le());
+        message.extend(self.asset_b.to_bytes_le());
+        message.extend(self.amount_a.to_bytes_le());
+        message.extend(self.amount_b.to_bytes_le());
+        message
+    }
+
+    /// Check that the swap is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the two parties are distinct.
+    /// 2. Verify that both signatures are valid with respect to the public keys
+    ///    of the respective parties.
+    /// 3. Verify that both assets are supported, that both parties have
+    ///    sufficient balance, excluding held funds, to finance their leg of the
+    ///    swap, and that neither balance overflows.
+    /// 4. Verify that both parties' accounts exist, and are updated accordingly.
+    ///
+    /// A single flag is returned for the whole swap, so that either both legs
+    /// of the swap are valid, or neither is.
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(
+            self,
+            parameters,
+            pre_acc_info_a,
+            pre_path_a,
+            post_path_a,
+            pre_acc_info_b,
+            pre_path_b,
+            post_path_b,
+            pre_root,
+            post_root
+        )
+    )]
+    pub fn validate(
+        &self,
+        parameters: &ParametersVar,
+        pre_acc_info_a: &AccountInformationVar,
+        pre_path_a: &AccPathVar,
+        post_path_a: &AccPathVar,
+        pre_acc_info_b: &AccountInformationVar,
+        pre_path_b: &AccPathVar,
+        post_path_b: &AccPathVar,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // A party can't swap with itself: both legs would be checked against
+        // the same leaf, and only one of the two updates would be applied.
+        let parties_distinct = self.party_a.0.is_neq(&self.party_b.0)?;
+
+        // Verify the signatures of both parties.
+        let message = self.message();
+        let sig_a_verifies = parameters.verify_signature(
+            &pre_acc_info_a.public_key,
+            message.clone(),
+            &self.signature_a,
+        )?;
+        let sig_b_verifies =
+            parameters.verify_signature(&pre_acc_info_b.public_key, message, &self.signature_b)?;
+
+        let assets_supported = self
+            .asset_a
+            .is_supported()?
+            .and(&self.asset_b.is_supported()?)?;
+
+        // Compute the new balances, ensuring that both legs are funded and that
+        // neither balance overflows. Both parties pay their leg before receiving
+        // the other one, so that neither leg can be funded by the proceeds of
+        // the swap.
+        let debit = |balances: &BalancesVar, asset, amount| {
+            let balance = balances.get(asset)?.checked_sub(amount)?;
+            balances.with_balance(asset, &balance)
+        };
+        let credit = |balances: &BalancesVar, asset, amount| {
+            let balance = balances.get(asset)?.checked_add(amount)?;
+            balances.with_balance(asset, &balance)
+        };
+        let mut post_acc_info_a = pre_acc_info_a.clone();
+        let mut post_acc_info_b = pre_acc_info_b.clone();
+        post_acc_info_a.balances = debit(&pre_acc_info_a.balances, &self.asset_a, &self.amount_a)?;
+        post_acc_info_b.balances = debit(&pre_acc_info_b.balances, &self.asset_b, &self.amount_b)?;
+        let held_covered = post_acc_info_a
+            .covers_held()?
+            .and(&post_acc_info_b.covers_held()?)?;
+        post_acc_info_a.balances =
+            credit(&post_acc_info_a.balances, &self.asset_b, &self.amount_b)?;
+        post_acc_info_b.balances =
+            credit(&post_acc_info_b.balances, &self.asset_a, &self.amount_a)?;
+        // The swap is authorized by both parties, so both nonces are incremented.
+        post_acc_info_a.nonce =
+            UInt64::addmany(&[pre_acc_info_a.nonce.clone(), UInt64::constant(1)])?;
+        post_acc_info_b.nonce =
+            UInt64::addmany(&[pre_acc_info_b.nonce.clone(), UInt64::constant(1)])?;
+
+        // Check that the account information of both parties is correct with
+        // respect to `pre_root` before the swap, and with respect to `post_root`
+        // after it.
+        let check_membership = |path: &AccPathVar, info: &AccountInformationVar, root| {
+            path.verify_membership(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                root,
+                &info.to_leaf(parameters)?.as_slice(),
+            )
+        };
+        let a_exists = check_membership(pre_path_a, pre_acc_info_a, pre_root)?
+            .and(&check_membership(post_path_a, &post_acc_info_a, post_root)?)?;
+        let b_exists = check_membership(pre_path_b, pre_acc_info_b, pre_root)?
+            .and(&check_membership(post_path_b, &post_acc_info_b, post_root)?)?;
+
+        parties_distinct
+            .and(&a_exists)?
+            .and(&b_exists)?
+            .and(&sig_a_verifies)?
+            .and(&sig_b_verifies)?
+            .and(&assets_supported)?
+            .and(&held_covered)
+    }
+}
+
+impl AllocVar<Swap, ConstraintF> for SwapVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Swap>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|swap| {
+            let swap: &Swap = swap.borrow();
+            let party_a = AccountIdVar::new_variable(cs.clone(), || Ok(&swap.party_a), mode)?;
+            let party_b = AccountIdVar::new_variable(cs.clone(), || Ok(&swap.party_b), mode)?;
+            let asset_a = AssetIdVar::new_variable(cs.clone(), || Ok(&swap.asset_a), mode)?;
+            let amount_a = AmountVar::new_variable(cs.clone(), || Ok(&swap.amount_a), mode)?;
+            let asset_b = AssetIdVar::new_variable(cs.clone(), || Ok(&swap.asset_b), mode)?;
+            let amount_b = AmountVar::new_variable(cs.clone(), || Ok(&swap.amount_b), mode)?;
+            let signature_a =
+                AccountSignatureVar::new_variable(cs.clone(), || Ok(&swap.signature_a), mode)?;
+            let signature_b =
+                AccountSignatureVar::new_variable(cs.clone(), || Ok(&swap.signature_b), mode)?;
+            Ok(Self {
+                party_a,
+                party_b,
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+                signature_a,
+                signature_b,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::account::AccountId;
+    use ark_simple_payments::ledger::{Amount, AssetId, Parameters, State};
+
+    /// Validate `swap` in a fresh constraint system, using witnesses taken from
+    /// `state`, and return whether the constraint system is satisfied.
+    fn swap_is_valid(pp: &Parameters, state: &State, swap: &Swap) -> bool {
+        let mut post_state = state.clone();
+        let _ = post_state.apply_swap(pp, swap);
+        swap_is_valid_with_post_state(pp, state, &post_state, swap)
+    }
+
+    /// Like `swap_is_valid`, but takes the paths after the swap from
+    /// `post_state` instead of applying `swap` natively.
+    fn swap_is_valid_with_post_state(
+        pp: &Parameters,
+        state: &State,
+        post_state: &State,
+        swap: &Swap,
+    ) -> bool {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let path = |state: &State, id: AccountId| {
+            let path = state.prove_account(id).unwrap().path;
+            AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap()
+        };
+        let info = |id| {
+            let info = state.id_to_account_info[&id];
+            AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap()
+        };
+        let params = ParametersVar::new_constant(cs.clone(), pp).unwrap();
+        let swap_var = SwapVar::new_witness(cs.clone(), || Ok(swap)).unwrap();
+        let pre_root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+        let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_state.root())).unwrap();
+        swap_var
+            .validate(
+                &params,
+                &info(swap.party_a),
+                &path(state, swap.party_a),
+                &path(post_state, swap.party_a),
+                &info(swap.party_b),
+                &path(state, swap.party_b),
+                &path(post_state, swap.party_b),
+                &pre_root,
+                &post_root,
+            )
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn swap_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(alice_id, AssetId(1), Amount(10))
+            .unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(bob_id, AssetId(2), Amount(3))
+            .unwrap();
+
+        // Alice sends 10 units of asset 1 to Bob in exchange for 3 units of asset 2.
+        let swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(2),
+            Amount(3),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(swap.validate(&pp, &state));
+        assert!(swap_is_valid(&pp, &state, &swap));
+
+        // If Bob's leg is underfunded, the whole swap is rejected.
+        let bad_swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(2),
+            Amount(4),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!bad_swap.validate(&pp, &state));
+        assert!(!swap_is_valid(&pp, &state, &bad_swap));
+
+        // Bob can't pay with the asset he receives from Alice.
+        let bad_swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(1),
+            Amount(3),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!bad_swap.validate(&pp, &state));
+        assert!(!swap_is_valid(&pp, &state, &bad_swap));
+
+        // Alice can't swap with herself. Giving and receiving the same amount
+        // of the same asset only increments her nonce, which an empty swap with
+        // Bob does too, so the root after that swap would match both legs.
+        let self_swap = Swap::create(
+            &pp,
+            alice_id,
+            alice_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(1),
+            Amount(10),
+            &alice_sk,
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!self_swap.validate(&pp, &state));
+        let empty_swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(0),
+            AssetId(2),
+            Amount(0),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        let mut post_state = state.clone();
+        post_state.apply_swap(&pp, &empty_swap).unwrap();
+        assert!(!swap_is_valid_with_post_state(
+            &pp,
+            &state,
+            &post_state,
+            &self_swap
+        ));
+    }
+}
@@ -1,16 +1,32 @@
-use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar};
-use crate::ledger::{self, AccPathVar, AccRootVar, AmountVar, ParametersVar};
+use crate::account::{
+    AccountIdVar, AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar,
+};
+use crate::delegation::SpendingAuthorizationVar;
+use crate::ledger::{
+    self, sha256_digest, AccRootVar, AccountHashGadget, AmountBlindingVar, AmountCommitmentGadget,
+    AmountCommitmentVar, AmountVar, AssetIdVar, ParametersVar, PositionedPathVar,
+};
+use crate::withdrawal::L1AddressVar;
 use crate::ConstraintF;
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_crypto_primitives::commitment::constraints::CommitmentGadget;
+use ark_crypto_primitives::crh::constraints::CRHGadget;
+use ark_r1cs_std::bits::{uint32::UInt32, uint64::UInt64};
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
+use ark_relations::r1cs::{
+    ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, Namespace, SynthesisError,
+};
 use ark_simple_payments::account::AccountInformation;
-use ark_simple_payments::ledger::{AccPath, AccRoot, Parameters, State};
-use ark_simple_payments::signature::schnorr::constraints::{
-    ParametersVar as SchnorrParamsVar, SchnorrSignatureVerifyGadget, SignatureVar,
+use ark_simple_payments::account::AccountPublicKey;
+use ark_simple_payments::allowlist::AllowlistProof;
+use ark_simple_payments::ledger::{
+    AccPath, AccRoot, Amount, BlockCommitment, MessageEncoding, Parameters, State,
+};
+use ark_simple_payments::random_oracle::sha256;
+use ark_simple_payments::transaction::{
+    transaction_type, Transaction, DOMAIN_TYPE, STRUCTURED_PREFIX, TX_HASH_TAG, TX_ID_TAG,
 };
-use ark_simple_payments::signature::SigVerifyGadget;
-use ark_simple_payments::transaction::Transaction;
+use ark_simple_payments::withdrawal::WITHDRAWAL_ACCOUNT;
 use std::borrow::Borrow;
 
 /// Transaction transferring some amount from one account to another.
@@ -19,127 +35,1002 @@ pub struct TransactionVar {
     pub sender: AccountIdVar,
     /// The account information of the recipient.
     pub recipient: AccountIdVar,
+    /// The asset being transferred. The fee is paid in the same asset.
+    pub asset_id: AssetIdVar,
     /// The amount being transferred from the sender to the receiver.
     pub amount: AmountVar,
+    /// The fee paid by the sender on top of `amount`. Fees are burned.
+    pub fee: AmountVar,
+    /// The block that the transaction is bound to, or zero if it is not bound to
+    /// any block.
+    pub expiry_block_commitment: FpVar<ConstraintF>,
+    /// The commitment to the ledger parameters that the transaction is signed
+    /// under.
+    pub params_commitment: FpVar<ConstraintF>,
+    /// The address on L1 that the funds of a withdrawal are paid out to.
+    pub l1_address: L1AddressVar,
+    /// The number of decimals of the fixed-point scale of the amount and the fee.
+    pub scale: UInt8<ConstraintF>,
     /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: SignatureVar<EdwardsProjective, EdwardsVar>,
+    /// the asset, the amount, the fee, the expiry block commitment, the
+    /// parameters commitment, the L1 address, and the scale.
+    pub signature: AccountSignatureVar,
+    /// The sequencer's receipt for the balance change of the sender. This is
+    /// only checked if the ledger requires receipts.
+    pub receipt: AccountSignatureVar,
+    /// The recipient's consent to receive the transfer. This is only checked if
+    /// the ledger requires consent and the recipient doesn't accept transfers.
+    pub consent: AccountSignatureVar,
+    /// The path proving that the recipient is on the allowlist of the sender.
+    /// This is only checked if the ledger enables allowlists and the sender has
+    /// one.
+    pub allowlist_path: PositionedPathVar,
+    /// The authorization of the delegate that signed the transaction instead of
+    /// the sender. This is only checked if the ledger enables delegation and the
+    /// transaction is delegated.
+    pub authorization: SpendingAuthorizationVar,
+    /// The nonce of the proof of work of the transaction. This is only checked
+    /// if the batch requires proofs of work.
+    pub pow_nonce: UInt64<ConstraintF>,
+}
+
+/// A value that refers to the ledger state *before* a transaction.
+pub struct PreState<T>(pub T);
+
+/// A value that refers to the ledger state *after* a transaction.
+pub struct PostState<T>(pub T);
+
+/// The witnesses proving an account involved in a transaction. There is no
+/// post-transaction account information: it is always computed from the
+/// pre-transaction information. Neither is there a post-transaction path: the
+/// account is updated in place, on the same path.
+pub struct AccountWitnessVar<'a> {
+    /// The account information before the transaction.
+    pub pre_info: PreState<&'a AccountInformationVar>,
+    /// The authentication path of the account in the tree that it is updated
+    /// in. This is the tree before the transaction for the sender, and the tree
+    /// after the sender's update for the recipient. Since the recipient's update
+    /// leaves the siblings on its path unchanged, the latter is also the
+    /// recipient's path after the transaction.
+    pub path: &'a PositionedPathVar,
+}
+
+/// The witnesses needed to validate a transaction, grouped so that values
+/// referring to the state before and after the transaction can't be swapped.
+pub struct TransactionWitnessVar<'a> {
+    /// The witnesses of the sender's account.
+    pub sender: AccountWitnessVar<'a>,
+    /// The witnesses of the recipient's account.
+    pub recipient: AccountWitnessVar<'a>,
+    /// The root of the account tree before the transaction.
+    pub pre_root: PreState<&'a AccRootVar>,
+    /// The root of the account tree after the transaction.
+    pub post_root: PostState<&'a AccRootVar>,
 }
 
 impl TransactionVar {
-    /// Verify just the signature in the transaction.
-    #[tracing::instrument(target = "r1cs", skip(self, pp, pub_key))]
-    fn verify_signature(
-        &self,
-        pp: &SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
-        pub_key: &AccountPublicKeyVar,
-    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    /// Assemble a transaction from already allocated variables.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        sender: AccountIdVar,
+        recipient: AccountIdVar,
+        asset_id: AssetIdVar,
+        amount: AmountVar,
+        fee: AmountVar,
+        expiry_block_commitment: FpVar<ConstraintF>,
+        params_commitment: FpVar<ConstraintF>,
+        l1_address: L1AddressVar,
+        scale: UInt8<ConstraintF>,
+        signature: AccountSignatureVar,
+        receipt: AccountSignatureVar,
+        consent: AccountSignatureVar,
+        allowlist_path: PositionedPathVar,
+        authorization: SpendingAuthorizationVar,
+        pow_nonce: UInt64<ConstraintF>,
+    ) -> Self {
+        Self {
+            sender,
+            recipient,
+            asset_id,
+            amount,
+            fee,
+            expiry_block_commitment,
+            params_commitment,
+            l1_address,
+            scale,
+            signature,
+            receipt,
+            consent,
+            allowlist_path,
+            authorization,
+            pow_nonce,
+        }
+    }
+
+    /// The message that the sender signs, before it is encoded as specified by
+    /// the ledger parameters.
+    pub(crate) fn raw_message(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
         // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
+        // (SenderAccId || RecipientAccId || AssetId || Amount || Fee || ExpiryBlockCommitment
+        //  || ParamsCommitment || L1Address || Scale)
         let mut message = self.sender.to_bytes_le();
         message.extend(self.recipient.to_bytes_le());
+        message.extend(self.asset_id.to_bytes_le());
         message.extend(self.amount.to_bytes_le());
-        SchnorrSignatureVerifyGadget::verify(pp, pub_key, &message, &self.signature)
+        message.extend(self.fee.to_bytes_le());
+        message.extend(self.expiry_block_commitment.to_bytes()?);
+        message.extend(self.params_commitment.to_bytes()?);
+        message.extend(self.l1_address.0.iter().cloned());
+        message.push(self.scale.clone());
+        Ok(message)
+    }
+
+    /// The leaf indices of the sender and of the recipient, as little-endian
+    /// 64-bit integers, as computed by `Transaction::leaf_indices`.
+    fn leaf_indices_bytes(&self) -> [Vec<UInt8<ConstraintF>>; 2] {
+        let index = |id: &AccountIdVar| {
+            let mut bytes = id.to_bytes_le();
+            bytes.resize(8, UInt8::constant(0));
+            bytes
+        };
+        [index(&self.sender), index(&self.recipient)]
+    }
+
+    /// The leaf indices of the sender and of the recipient, as signed if the
+    /// ledger binds leaf indices.
+    pub fn leaf_indices(&self) -> Result<[FpVar<ConstraintF>; 2], SynthesisError> {
+        Ok([
+            Boolean::le_bits_to_fp_var(&self.sender.0.to_bits_le()?)?,
+            Boolean::le_bits_to_fp_var(&self.recipient.0.to_bits_le()?)?,
+        ])
+    }
+
+    /// Check that `sender_path` and `recipient_path` lead to the leaf indices
+    /// that the transaction signs. These are the paths that `validate` proves
+    /// and updates the accounts on, and so a path to another leaf is rejected
+    /// even if that leaf holds the same information, both before and after the
+    /// transaction. Withdrawals have no recipient account, and so only the
+    /// sender is checked for them.
+    #[tracing::instrument(target = "r1cs", skip(self, sender_path, recipient_path))]
+    pub fn is_at_signed_indices(
+        &self,
+        sender_path: &PositionedPathVar,
+        recipient_path: &PositionedPathVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let [sender_index, recipient_index] = self.leaf_indices()?;
+        sender_path.leaf_index()?.is_eq(&sender_index)?.and(
+            &recipient_path
+                .leaf_index()?
+                .is_eq(&recipient_index)?
+                .or(&self.is_withdrawal()?)?,
+        )
+    }
+
+    /// The structured digest of the transaction, as computed by
+    /// `Transaction::structured_message`.
+    pub fn structured_message(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        self.structured_message_with(None, false)
+    }
+
+    /// The structured digest of the transaction, which also binds the bytes of
+    /// the public key of the recipient if `recipient_key` is given, and the leaf
+    /// indices of both accounts if `leaf_indices` is set.
+    #[tracing::instrument(target = "r1cs", skip(self, recipient_key))]
+    fn structured_message_with(
+        &self,
+        recipient_key: Option<&[UInt8<ConstraintF>]>,
+        leaf_indices: bool,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The unsigned integer with the little-endian bytes `bytes_le`, as a
+        // big-endian and left-padded 32-byte word.
+        let uint_word = |bytes_le: Vec<UInt8<ConstraintF>>| {
+            let mut word = vec![UInt8::constant(0); 32 - bytes_le.len()];
+            word.extend(bytes_le.into_iter().rev());
+            word
+        };
+        let mut domain = UInt8::constant_vec(&sha256::digest(DOMAIN_TYPE));
+        domain.extend(self.params_commitment.to_bytes()?);
+        let transaction_type = transaction_type(recipient_key.is_some(), leaf_indices);
+        let mut fields = UInt8::constant_vec(&sha256::digest(&transaction_type));
+        fields.extend(uint_word(self.sender.to_bytes_le()));
+        fields.extend(uint_word(self.recipient.to_bytes_le()));
+        fields.extend(uint_word(self.asset_id.to_bytes_le()));
+        fields.extend(uint_word(self.amount.to_bytes_le()));
+        fields.extend(uint_word(self.fee.to_bytes_le()));
+        fields.extend(self.expiry_block_commitment.to_bytes()?);
+        // Addresses are left-padded, but not reversed.
+        fields.extend(UInt8::constant_vec(&[0; 12]));
+        fields.extend(self.l1_address.0.iter().cloned());
+        fields.extend(uint_word(vec![self.scale.clone()]));
+        if let Some(key) = recipient_key {
+            fields.extend(sha256_digest(key)?);
+        }
+        if leaf_indices {
+            for index in self.leaf_indices_bytes() {
+                fields.extend(uint_word(index));
+            }
+        }
+        let mut message = UInt8::constant_vec(STRUCTURED_PREFIX);
+        message.extend(sha256_digest(&domain)?);
+        message.extend(sha256_digest(&fields)?);
+        sha256_digest(&message)
+    }
+
+    /// The message that the sender signs to authorize the transaction, encoded
+    /// as specified by the ledger parameters, as computed by
+    /// `Transaction::signed_message`.
+    #[tracing::instrument(target = "r1cs", skip(self, pp))]
+    pub fn signed_message(
+        &self,
+        pp: &ledger::ParametersVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        self.signed_message_with(pp, None)
+    }
+
+    /// The message that the sender signs, which also binds the bytes of the
+    /// public key of the recipient if `recipient_key` is given.
+    fn signed_message_with(
+        &self,
+        pp: &ledger::ParametersVar,
+        recipient_key: Option<&[UInt8<ConstraintF>]>,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let message = || -> Result<_, SynthesisError> {
+            let mut message = self.raw_message()?;
+            if let Some(key) = recipient_key {
+                message.extend(key.iter().cloned());
+            }
+            if pp.bind_leaf_indices {
+                message.extend(self.leaf_indices_bytes().concat());
+            }
+            Ok(message)
+        };
+        match pp.message_encoding {
+            MessageEncoding::Raw => message(),
+            MessageEncoding::Sha256 => sha256_digest(&message()?),
+            MessageEncoding::Structured => {
+                self.structured_message_with(recipient_key, pp.bind_leaf_indices)
+            }
+        }
+    }
+
+    /// The bytes of the public key that the signature binds for the recipient
+    /// with information `recipient_info`, as `Transaction::recipient_key`
+    /// computes. The information of the recipient of a withdrawal is ignored.
+    #[tracing::instrument(target = "r1cs", skip(self, recipient_info))]
+    fn recipient_key_bytes(
+        &self,
+        recipient_info: &AccountInformationVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let is_withdrawal = self.is_withdrawal()?;
+        let default_key = ark_ff::to_bytes![AccountPublicKey::default()].unwrap();
+        recipient_info
+            .public_key
+            .to_bytes()?
+            .iter()
+            .zip(default_key)
+            .map(|(byte, default)| is_withdrawal.select(&UInt8::constant(default), byte))
+            .collect()
+    }
+
+    /// The message that the sender signs to authorize the transaction to the
+    /// recipient with information `recipient_info`, as computed by
+    /// `Transaction::signed_message_to`.
+    #[tracing::instrument(target = "r1cs", skip(self, pp, recipient_info))]
+    pub fn signed_message_to(
+        &self,
+        pp: &ledger::ParametersVar,
+        recipient_info: &AccountInformationVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        if !pp.bind_recipient_key {
+            return self.signed_message(pp);
+        }
+        let key = self.recipient_key_bytes(recipient_info)?;
+        self.signed_message_with(pp, Some(&key))
+    }
+
+    /// The message that the sender signs to authorize the transaction to the
+    /// recipient with information `recipient_info`, when applied to the account
+    /// tree with root `pre_root`, as computed by `Transaction::message_at`.
+    #[tracing::instrument(target = "r1cs", skip(self, pp, recipient_info, pre_root))]
+    pub fn message_at(
+        &self,
+        pp: &ledger::ParametersVar,
+        recipient_info: &AccountInformationVar,
+        pre_root: &AccRootVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let mut message = self.signed_message_to(pp, recipient_info)?;
+        if pp.bind_pre_root {
+            message.extend(pre_root.to_bytes()?);
+        }
+        Ok(message)
+    }
+
+    /// Verify just the signature in the transaction, given the information of
+    /// the recipient `recipient_info` and the root `pre_root` of the account
+    /// tree that the transaction is applied to, see `message_at`.
+    #[tracing::instrument(target = "r1cs", skip(self, pp, pub_key, recipient_info, pre_root))]
+    fn verify_signature(
+        &self,
+        pp: &ledger::ParametersVar,
+        pub_key: &AccountPublicKeyVar,
+        recipient_info: &AccountInformationVar,
+        pre_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let message = self.message_at(pp, recipient_info, pre_root)?;
+        pp.verify_encoded_signature(pub_key, &message, &self.signature)
+    }
+
+    /// The hash of the transaction, as computed by `Transaction::hash`.
+    #[tracing::instrument(target = "r1cs", skip(self, pp))]
+    pub fn hash(&self, pp: &ledger::ParametersVar) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        // The hashed input consists of
+        // (Tag || SignedMessage), where the message is not encoded.
+        let mut input = UInt8::constant_vec(TX_HASH_TAG);
+        input.extend(self.raw_message()?);
+        <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(&pp.account_crh_params, &input)
+    }
+
+    /// The identifier of the transaction, as computed by `Transaction::id`.
+    #[tracing::instrument(target = "r1cs", skip(self, pp))]
+    pub fn id(&self, pp: &ledger::ParametersVar) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        // The hashed input consists of
+        // (Tag || TransactionHash || Signature).
+        let mut input = UInt8::constant_vec(TX_ID_TAG);
+        input.extend(self.hash(pp)?.to_bytes()?);
+        input.extend(self.signature.to_bytes_le()?);
+        <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(&pp.account_crh_params, &input)
     }
 
+    /// Enforce that the amount being transferred is exactly `expected`.
+    /// This is used when paying a fixed invoice, where `expected` is public.
+    #[tracing::instrument(target = "r1cs", skip(self, expected))]
+    pub fn enforce_amount_equals(&self, expected: &AmountVar) -> Result<(), SynthesisError> {
+        self.amount.0.enforce_equal(&expected.0)
+    }
+
+    /// Check that `commitment` opens to the amount of the transaction, with the
+    /// blinding factor `blinding`. This is the amount used in the balance
+    /// updates, and so the commitment can stand in for it once amounts are
+    /// confidential.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, commitment, blinding))]
+    pub fn verify_amount_commitment(
+        &self,
+        parameters: &ledger::ParametersVar,
+        commitment: &AmountCommitmentVar,
+        blinding: &AmountBlindingVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let opening = <AmountCommitmentGadget as CommitmentGadget<_, ConstraintF>>::commit(
+            &parameters.amount_commitment_params,
+            &self.amount.to_bytes_le(),
+            blinding,
+        )?;
+        opening.is_eq(commitment)
+    }
+
+    /// Is this transaction withdrawing funds from the ledger?
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn is_withdrawal(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.recipient
+            .0
+            .is_eq(&UInt8::constant(WITHDRAWAL_ACCOUNT.0))
+    }
+
+    /// Is this a control operation, i.e., a transaction that doesn't move funds?
+    /// See `Transaction::is_control_op`.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn is_control_op(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let control = UInt8::constant(WITHDRAWAL_ACCOUNT.0);
+        self.sender
+            .0
+            .is_eq(&control)?
+            .and(&self.recipient.0.is_eq(&control)?)
+    }
+
+    /// Enforce that the amount follows the rules of the kind of this transaction:
+    /// transfers (including withdrawals) must move a nonzero amount, while control
+    /// operations must not move anything. See `Transaction::follows_amount_rules`.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn enforce_amount_rules(&self) -> Result<(), SynthesisError> {
+        self.follows_amount_rules()?.enforce_equal(&Boolean::TRUE)
+    }
+
+    /// Does the amount follow the rules of the kind of this transaction? See
+    /// `enforce_amount_rules`.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn follows_amount_rules(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let moves_nothing = self.amount.0.is_eq(&UInt64::constant(0))?;
+        moves_nothing.is_eq(&self.is_control_op()?)
+    }
+
+    /// Enforce that this transaction has not expired when the current block has
+    /// the commitment `block_commitment`, i.e., that it is either not bound to
+    /// any block, or bound to exactly that one. See `Transaction::is_unexpired_at`.
+    #[tracing::instrument(target = "r1cs", skip(self, block_commitment))]
+    pub fn enforce_unexpired_at(
+        &self,
+        block_commitment: &FpVar<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.is_unexpired_at(block_commitment)?
+            .enforce_equal(&Boolean::TRUE)
+    }
+
+    /// Has this transaction not expired when the current block has the
+    /// commitment `block_commitment`? See `enforce_unexpired_at`.
+    #[tracing::instrument(target = "r1cs", skip(self, block_commitment))]
+    pub fn is_unexpired_at(
+        &self,
+        block_commitment: &FpVar<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let unbound = self.expiry_block_commitment.is_eq(&FpVar::zero())?;
+        let bound_to_current = self.expiry_block_commitment.is_eq(block_commitment)?;
+        unbound.or(&bound_to_current)
+    }
+
+    /// Does this transaction leave the account `id` untouched, i.e., is `id`
+    /// neither its sender nor its recipient?
+    #[tracing::instrument(target = "r1cs", skip(self, id))]
+    pub fn leaves_untouched(
+        &self,
+        id: &AccountIdVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.sender
+            .0
+            .is_neq(&id.0)?
+            .and(&self.recipient.0.is_neq(&id.0)?)
+    }
+
+    /// Does this transaction touch an account that `other` touches too? See
+    /// `Transaction::shares_account_with`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn shares_account_with(
+        &self,
+        other: &TransactionVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let withdrawal_account = UInt8::constant(WITHDRAWAL_ACCOUNT.0);
+        let mut shares = Boolean::FALSE;
+        for id in [&self.sender, &self.recipient] {
+            let is_touched = id.0.is_neq(&withdrawal_account)?;
+            for other_id in [&other.sender, &other.recipient] {
+                shares = shares.or(&is_touched.and(&id.0.is_eq(&other_id.0)?)?)?;
+            }
+        }
+        Ok(shares)
+    }
+
+    /// Enforce that this transaction pays its fee in the asset `fee_asset`. Since
+    /// the fee is paid in the asset that is transferred, this means that the
+    /// transaction must transfer `fee_asset`, unless it pays no fee at all, as
+    /// is the case for the no-op transactions that pad a batch.
+    #[tracing::instrument(target = "r1cs", skip(self, fee_asset))]
+    pub fn enforce_fee_asset(&self, fee_asset: &AssetIdVar) -> Result<(), SynthesisError> {
+        let pays_fee = self.fee.0.is_neq(&UInt64::constant(0))?;
+        self.asset_id
+            .0
+            .conditional_enforce_equal(&fee_asset.0, &pays_fee)
+    }
+
+    /// Enforce that the amount and the fee of this transaction are expressed
+    /// with `scale` decimals, unless this is a no-op transaction, which
+    /// transfers nothing.
+    ///
+    /// This only checks the scale that the sender signed. Amounts are range
+    /// checked as 64-bit integers of units of `10^-scale`, whatever `scale` is,
+    /// and the balances they are added to and subtracted from carry no scale,
+    /// so an amount with a different scale would pass those checks while being
+    /// worth a power of ten more or less than it should.
+    #[tracing::instrument(target = "r1cs", skip(self, scale))]
+    pub fn enforce_scale(&self, scale: &UInt8<ConstraintF>) -> Result<(), SynthesisError> {
+        self.scale
+            .conditional_enforce_equal(scale, &self.is_noop()?.not())
+    }
+
+    /// Enforce that the transaction leaves no dust behind, for the dust
+    /// threshold `threshold`, as `Transaction::leaves_no_dust` checks: a
+    /// transfer must move at least `threshold`, and must leave the balance of
+    /// the sender, whose information before the transaction is
+    /// `pre_sender_acc_info`, either empty or at least `threshold`. Control
+    /// operations are exempt.
+    #[tracing::instrument(target = "r1cs", skip(self, threshold, pre_sender_acc_info))]
+    pub fn enforce_no_dust(
+        &self,
+        threshold: &AmountVar,
+        pre_sender_acc_info: &AccountInformationVar,
+    ) -> Result<(), SynthesisError> {
+        self.leaves_no_dust(threshold, pre_sender_acc_info)?
+            .enforce_equal(&Boolean::TRUE)
+    }
+
+    /// Does the transaction leave no dust behind, for the dust threshold
+    /// `threshold`? See `enforce_no_dust`.
+    #[tracing::instrument(target = "r1cs", skip(self, threshold, pre_sender_acc_info))]
+    pub fn leaves_no_dust(
+        &self,
+        threshold: &AmountVar,
+        pre_sender_acc_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let is_control_op = self.is_control_op()?;
+        let amount_is_dust = self.amount.is_lt(threshold)?;
+        // Validation already checks that the sender can pay the amount and the
+        // fee, so the remaining balance only matters if it doesn't underflow,
+        // and the amount of a self-transfer returns to the sender.
+        let (debited, _) = pre_sender_acc_info
+            .balances
+            .get(&self.asset_id)?
+            .try_sub(&self.amount)?;
+        let (debited, _) = debited.try_sub(&self.fee)?;
+        let (returned, _) = debited.try_add(&self.amount)?;
+        let is_self_transfer = self.sender.0.is_eq(&self.recipient.0)?;
+        let remaining = AmountVar(is_self_transfer.select(&returned.0, &debited.0)?);
+        let remaining_is_dust = remaining
+            .is_lt(threshold)?
+            .and(&remaining.0.is_eq(&UInt64::constant(0))?.not())?;
+        amount_is_dust
+            .or(&remaining_is_dust)?
+            .not()
+            .or(&is_control_op)
+    }
+
+    /// The canonical no-op transaction, `Transaction::NOOP`, as a constant, so
+    /// that the circuit can pad a batch without witnessing the padding.
+    pub fn noop() -> Result<Self, SynthesisError> {
+        Self::new_constant(ConstraintSystemRef::None, Transaction::NOOP)
+    }
+
+    /// Is this the canonical no-op transaction, `Transaction::NOOP`? The
+    /// signature is ignored.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn is_noop(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let noop = Transaction::NOOP;
+        Boolean::kary_and(&[
+            self.sender.0.is_eq(&UInt8::constant(noop.sender.0))?,
+            self.recipient.0.is_eq(&UInt8::constant(noop.recipient.0))?,
+            self.asset_id.0.is_eq(&UInt32::constant(noop.asset_id.0))?,
+            self.amount.0.is_eq(&UInt64::constant(noop.amount.0))?,
+            self.fee.0.is_eq(&UInt64::constant(noop.fee.0))?,
+            self.expiry_block_commitment
+                .is_eq(&FpVar::constant(noop.expiry_block_commitment))?,
+            self.params_commitment
+                .is_eq(&FpVar::constant(noop.params_commitment))?,
+            self.l1_address.0[..].is_eq(&UInt8::constant_vec(&noop.l1_address))?,
+            self.scale.is_eq(&UInt8::constant(noop.scale))?,
+        ])
+    }
+
+    /// Enforce that applying this transaction leaves the account tree unchanged,
+    /// i.e., that `post_root == pre_root`, if this is a no-op transaction.
+    #[tracing::instrument(target = "r1cs", skip(self, pre_root, post_root))]
+    pub fn enforce_noop_keeps_root(
+        &self,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<(), SynthesisError> {
+        post_root.conditional_enforce_equal(pre_root, &self.is_noop()?)
+    }
+
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, account_path, account, root))]
     fn check_account_existence(
         &self,
         parameters: &ledger::ParametersVar,
-        account_path: &AccPathVar,
+        account_path: &PositionedPathVar,
         account: &AccountInformationVar,
         root: &AccRootVar,
     ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        account_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            root,
-            &account.to_bytes_le().as_slice(),
-        )
+        account_path.verify_membership(parameters, root, &account.to_leaf(parameters)?)
     }
 
-    /// Check that the transaction is valid for the given ledger state. This checks
-    /// the following conditions:
-    /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
-    /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
-    /// 3. Verify that the recipient's account exists.
-    #[allow(clippy::too_many_arguments)]
+    /// Compute the sender's account information after paying both the amount
+    /// and the fee during the epoch `epoch`, along with whether its available
+    /// balance covers them, and whether the sender stays within the daily limit.
+    /// Neither condition is enforced, so that the transaction can be skipped if
+    /// it fails either of them.
+    fn debit_sender(
+        &self,
+        parameters: &ledger::ParametersVar,
+        pre_sender_acc_info: &AccountInformationVar,
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<
+        (
+            AccountInformationVar,
+            Boolean<ConstraintF>,
+            Boolean<ConstraintF>,
+        ),
+        SynthesisError,
+    > {
+        // Compute the new sender balance, after paying both the amount and the fee.
+        let mut post_sender_acc_info = pre_sender_acc_info.clone();
+        let (debited, amount_covered) = pre_sender_acc_info
+            .balances
+            .get(&self.asset_id)?
+            .try_sub(&self.amount)?;
+        let (post_sender_balance, fee_covered) = debited.try_sub(&self.fee)?;
+        post_sender_acc_info.balances = pre_sender_acc_info
+            .balances
+            .with_balance(&self.asset_id, &post_sender_balance)?;
+        post_sender_acc_info.nonce =
+            UInt64::addmany(&[pre_sender_acc_info.nonce.clone(), UInt64::constant(1)])?;
+        // The next transaction must be authorized by the next derived key.
+        if parameters.hd_keys {
+            post_sender_acc_info.key_index = pre_sender_acc_info.next_key_index()?;
+        }
+        // The held funds can't be spent.
+        let (total, total_fits) = self.amount.try_add(&self.fee)?;
+        let is_funded = total
+            .is_le(&pre_sender_acc_info.available_balance(&self.asset_id)?)?
+            .and(&total_fits)?
+            .and(&amount_covered)?
+            .and(&fee_covered)?;
+
+        // Keep track of the sender's spending, and check that it stays within
+        // the daily limit.
+        let within_daily_limit = match &parameters.daily_limit {
+            Some(daily_limit) => {
+                // The amount spent so far is reset when a new epoch starts.
+                let same_epoch = pre_sender_acc_info.limit_epoch.is_eq(epoch)?;
+                let spent =
+                    same_epoch.select(&pre_sender_acc_info.spent_today.0, &UInt64::constant(0))?;
+                let (spent, spent_fits) = AmountVar(spent).try_add(&self.amount)?;
+                post_sender_acc_info.spent_today = spent;
+                post_sender_acc_info.limit_epoch = epoch.clone();
+                post_sender_acc_info
+                    .spent_today
+                    .is_le(daily_limit)?
+                    .and(&spent_fits)?
+            }
+            None => Boolean::TRUE,
+        };
+        Ok((post_sender_acc_info, is_funded, within_daily_limit))
+    }
+
+    /// Compute the recipient's account information after receiving the amount,
+    /// along with whether its balance doesn't overflow.
+    #[tracing::instrument(target = "r1cs", skip(self, pre_recipient_acc_info))]
+    pub fn credit_recipient(
+        &self,
+        pre_recipient_acc_info: &AccountInformationVar,
+    ) -> Result<(AccountInformationVar, Boolean<ConstraintF>), SynthesisError> {
+        let mut post_recipient_acc_info = pre_recipient_acc_info.clone();
+        let (post_recipient_balance, no_overflow) = pre_recipient_acc_info
+            .balances
+            .get(&self.asset_id)?
+            .try_add(&self.amount)?;
+        post_recipient_acc_info.balances = pre_recipient_acc_info
+            .balances
+            .with_balance(&self.asset_id, &post_recipient_balance)?;
+        Ok((post_recipient_acc_info, no_overflow))
+    }
+
+    /// Verify that the recipient's balance after the credit, as given by
+    /// `post_recipient_acc_info`, stays within its cap. This always holds if the
+    /// ledger doesn't enable balance caps, if the recipient is uncapped, and for
+    /// withdrawals and self-transfers, which credit no account.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, post_recipient_acc_info))]
+    pub fn verify_balance_cap(
+        &self,
+        parameters: &ledger::ParametersVar,
+        post_recipient_acc_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        if !parameters.balance_caps {
+            return Ok(Boolean::TRUE);
+        }
+        let cap = &post_recipient_acc_info.balance_cap;
+        let is_uncapped = cap.0.is_eq(&UInt64::constant(0))?;
+        let is_self_transfer = self.sender.0.is_eq(&self.recipient.0)?;
+        post_recipient_acc_info
+            .balances
+            .get(&self.asset_id)?
+            .is_le(cap)?
+            .or(&is_uncapped)?
+            .or(&self.is_withdrawal()?)?
+            .or(&is_self_transfer)
+    }
+
+    /// Check the conditions of `validate` that only involve the sender, whose
+    /// information before the transaction is `pre_sender_acc_info`: that the
+    /// transaction to the recipient with information `recipient_acc_info` is
+    /// signed, under `parameters`, by the sender or a delegate within its
+    /// authorization, that the asset is supported, that the recipient is on the
+    /// allowlist of the sender, and that the sender can pay for the transaction
+    /// during the epoch `epoch`, see `debit_sender`.
+    ///
+    /// Returns the sender's account information after the debit, whether all of
+    /// these conditions hold, and whether the signature verifies on its own.
     #[tracing::instrument(
         target = "r1cs",
         skip(
             self,
             parameters,
             pre_sender_acc_info,
-            pre_sender_path,
-            post_sender_path,
-            pre_recipient_acc_info,
-            pre_recipient_path,
-            post_recipient_path,
+            recipient_acc_info,
             pre_root,
-            post_root
+            epoch
         )
     )]
-    pub fn validate(
+    fn authorize_and_debit(
         &self,
         parameters: &ledger::ParametersVar,
         pre_sender_acc_info: &AccountInformationVar,
-        pre_sender_path: &AccPathVar,
-        post_sender_path: &AccPathVar,
-        pre_recipient_acc_info: &AccountInformationVar,
-        pre_recipient_path: &AccPathVar,
-        post_recipient_path: &AccPathVar,
+        recipient_acc_info: &AccountInformationVar,
         pre_root: &AccRootVar,
-        post_root: &AccRootVar,
-    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        // Verify the signature against the sender pubkey.
-        let sig_verifies =
-            self.verify_signature(&parameters.sig_params, &pre_sender_acc_info.public_key)?;
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<
+        (
+            AccountInformationVar,
+            Boolean<ConstraintF>,
+            Boolean<ConstraintF>,
+        ),
+        SynthesisError,
+    > {
+        // Verify the signature against the sender pubkey, under `parameters`.
+        let sig_verifies = self.verify_signature(
+            parameters,
+            &self.signer_key(parameters, pre_sender_acc_info)?,
+            recipient_acc_info,
+            pre_root,
+        )?;
+        let signed_under_params = self.params_commitment.is_eq(&parameters.commitment)?;
 
-        // Compute the new sender balance.
-        let mut post_sender_acc_info = pre_sender_acc_info.clone();
-        post_sender_acc_info.balance = post_sender_acc_info.balance.checked_sub(&self.amount)?;
+        let asset_supported = self.asset_id.is_supported()?;
 
-        // Compute the new receiver balance, ensure its overflow safe.
-        let mut post_recipient_acc_info = pre_recipient_acc_info.clone();
-        post_recipient_acc_info.balance =
-            post_recipient_acc_info.balance.checked_add(&self.amount)?;
+        let (mut post_sender_acc_info, is_funded, within_daily_limit) =
+            self.debit_sender(parameters, pre_sender_acc_info, epoch)?;
+        let within_authorization =
+            self.verify_delegation(parameters, pre_sender_acc_info, &mut post_sender_acc_info)?;
+
+        let recipient_allowed = self.verify_allowlist(parameters, pre_sender_acc_info)?;
+
+        let is_authorized = sig_verifies
+            .and(&signed_under_params)?
+            .and(&asset_supported)?
+            .and(&is_funded)?
+            .and(&within_daily_limit)?
+            .and(&within_authorization)?
+            .and(&recipient_allowed)?;
+        Ok((post_sender_acc_info, is_authorized, sig_verifies))
+    }
+
+    /// Check that the account proven by `witness` holds `witness.pre_info` in
+    /// the tree with root `root`, and compute the root of the tree after
+    /// replacing its information by `post_acc_info`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, witness, post_acc_info, root))]
+    fn update_account(
+        &self,
+        parameters: &ledger::ParametersVar,
+        witness: &AccountWitnessVar,
+        post_acc_info: &AccountInformationVar,
+        root: &AccRootVar,
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError> {
+        let existed =
+            self.check_account_existence(parameters, witness.path, witness.pre_info.0, root)?;
+        let updated_root = witness
+            .path
+            .calculate_root(parameters, &post_acc_info.to_leaf(parameters)?)?;
+        Ok((existed, updated_root))
+    }
 
-        // Check that the pre-tx sender account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx sender account
-        // information is correct with respect to `post_tx_root`.
-        let sender_existed = self.check_account_existence(
+    /// Compute the root of the account tree after the transaction, given the
+    /// information of the sender and of the recipient after it, along with
+    /// whether both accounts held their information before it.
+    ///
+    /// The root after the transaction is never trusted: the sender's leaf is
+    /// updated on its path from `witness.pre_root`, and then the recipient's
+    /// leaf on its path from the resulting root. Every other leaf keeps its
+    /// value, so the transaction can't hide any other update. Withdrawals have
+    /// no recipient account, and so only the sender's leaf is updated for them.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(
+            self,
             parameters,
-            pre_sender_path,
-            pre_sender_acc_info,
-            pre_root,
+            witness,
+            post_sender_acc_info,
+            post_recipient_acc_info
+        )
+    )]
+    pub fn update_accounts(
+        &self,
+        parameters: &ledger::ParametersVar,
+        witness: &TransactionWitnessVar,
+        post_sender_acc_info: &AccountInformationVar,
+        post_recipient_acc_info: &AccountInformationVar,
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError> {
+        let (sender_existed, debited_root) = self.update_account(
+            parameters,
+            &witness.sender,
+            post_sender_acc_info,
+            witness.pre_root.0,
         )?;
-        let sender_will_exist = self.check_account_existence(
+        let (recipient_existed, credited_root) = self.update_account(
             parameters,
-            post_sender_path,
-            &post_sender_acc_info,
-            post_root,
+            &witness.recipient,
+            post_recipient_acc_info,
+            &debited_root,
         )?;
-        let sender_exists = sender_existed.and(&sender_will_exist)?;
+        let is_withdrawal = self.is_withdrawal()?;
+        let existed = sender_existed.and(&recipient_existed.or(&is_withdrawal)?)?;
+        let post_root = is_withdrawal.select(&debited_root, &credited_root)?;
+        Ok((existed, post_root))
+    }
+
+    /// Check that the transaction is valid for the ledger state proven by
+    /// `witness`. This checks the following conditions:
+    /// 1. Verify that the signature is valid with respect to the public key
+    ///    corresponding to `self.sender`, or to the next key derived from it if
+    ///    the ledger derives a fresh key per transaction.
+    /// 2. Verify that the asset is supported, and that the sender's account has
+    ///    sufficient balance of it, excluding held funds, to finance the
+    ///    transaction and its fee.
+    /// 3. Verify that the recipient's account exists, unless the transaction
+    ///    is a withdrawal.
+    /// 4. Verify that the sender stays within the daily limit during the epoch
+    ///    `epoch`, if there is a limit.
+    /// 5. Verify that the amount follows the rules of the kind of transaction.
+    /// 6. The public keys of both accounts are unchanged. This needs no
+    ///    constraint: the post-transaction information is derived from the
+    ///    pre-transaction information, see below, and no step replaces its key.
+    /// 7. Verify that the transaction is signed under `parameters`, i.e., that
+    ///    it refers to their commitment.
+    /// 8. Verify that the sequencer signed a receipt for the balance change of
+    ///    the sender, if the ledger requires receipts.
+    /// 9. Neither post-transaction leaf is the blank leaf, so that a transaction
+    ///    can't empty the slot of an account. This needs no constraint: the
+    ///    leaves of accounts are marked, see `account::mark_commitment`.
+    /// 10. Verify that the grace period since the sender was last credited has
+    ///     elapsed by the epoch `epoch`, if the transaction is a withdrawal and
+    ///     the ledger delays them.
+    /// 11. Verify that the recipient accepts transfers or consented to this one,
+    ///     if the ledger requires consent.
+    /// 12. Verify that the recipient is on the allowlist of the sender, if the
+    ///     ledger enables allowlists and the sender has one.
+    /// 13. Verify that a delegated transaction is authorized by the sender, and
+    ///     that it stays within the cap of its authorization, if the ledger
+    ///     enables delegation. The signature of condition 1 is then checked
+    ///     against the key of the delegate instead.
+    /// 14. Verify that the recipient's balance stays within its cap after the
+    ///     credit, if the ledger enables balance caps and the recipient has one.
+    /// 15. Verify that the accounts are proven at the leaf indices that the
+    ///     transaction signs, if the ledger binds leaf indices.
+    ///
+    /// Only the balances of the asset `self.asset_id` change.
+    ///
+    /// The post-transaction account information is never supplied as a
+    /// witness: it is computed here from the pre-transaction information. In
+    /// particular, the sender's post balance is bound to
+    /// `pre_balance - amount - fee`. Neither is the root after the transaction:
+    /// it is computed from the paths of the accounts, see `update_accounts`,
+    /// and only then compared with `post_root`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, witness, epoch))]
+    pub fn validate(
+        &self,
+        parameters: &ledger::ParametersVar,
+        witness: &TransactionWitnessVar,
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.validate_with_signature(parameters, witness, epoch)
+            .map(|(is_valid, _)| is_valid)
+    }
+
+    /// Check that the transaction is valid for the ledger state proven by
+    /// `witness`, as `validate` does, and also return whether its signature
+    /// verifies on its own, so that the verified signatures can be counted.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, witness, epoch))]
+    pub fn validate_with_signature(
+        &self,
+        parameters: &ledger::ParametersVar,
+        witness: &TransactionWitnessVar,
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<(Boolean<ConstraintF>, Boolean<ConstraintF>), SynthesisError> {
+        let pre_sender_acc_info = witness.sender.pre_info.0;
+        let pre_recipient_acc_info = witness.recipient.pre_info.0;
 
-        // Check that the pre-tx recipient account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx recipient account
-        // information is correct with respect to `post_tx_root`.
-        let recipient_existed = self.check_account_existence(
+        let follows_amount_rules = self.follows_amount_rules()?;
+
+        let (post_sender_acc_info, is_authorized, sig_verifies) = self.authorize_and_debit(
             parameters,
-            pre_recipient_path,
+            pre_sender_acc_info,
             pre_recipient_acc_info,
-            pre_root,
+            witness.pre_root.0,
+            epoch,
         )?;
-        let recipient_will_exist = self.check_account_existence(
+
+        let receipt_verifies =
+            self.verify_receipt(parameters, pre_sender_acc_info, &post_sender_acc_info)?;
+
+        // Recently credited accounts can't withdraw until the grace period
+        // has elapsed.
+        let past_withdrawal_grace = match &parameters.withdrawal_grace {
+            Some(grace) => pre_sender_acc_info
+                .is_past_grace(epoch, grace)?
+                .or(&self.is_withdrawal()?.not())?,
+            None => Boolean::TRUE,
+        };
+
+        let consent_verifies = self.verify_consent(parameters, pre_recipient_acc_info)?;
+
+        // Compute the new receiver balance, and check that it doesn't overflow.
+        let (mut post_recipient_acc_info, credit_fits) =
+            self.credit_recipient(pre_recipient_acc_info)?;
+        let within_balance_cap = self.verify_balance_cap(parameters, &post_recipient_acc_info)?;
+        // The recipient's credits are only tracked if withdrawals are delayed
+        // after them.
+        if parameters.withdrawal_grace.is_some() {
+            post_recipient_acc_info.last_credited_epoch = epoch.clone();
+        }
+
+        // Check that the accounts hold their information before the
+        // transaction, and that updating them leads to `post_root`.
+        let (accounts_exist, post_root) = self.update_accounts(
             parameters,
-            post_recipient_path,
+            witness,
+            &post_sender_acc_info,
             &post_recipient_acc_info,
-            post_root,
         )?;
-        let recipient_exists = recipient_existed.and(&recipient_will_exist)?;
+        let reaches_post_root = post_root.is_eq(witness.post_root.0)?;
+        let at_signed_indices = match parameters.bind_leaf_indices {
+            true => self.is_at_signed_indices(witness.sender.path, witness.recipient.path)?,
+            false => Boolean::TRUE,
+        };
+
+        let is_valid = follows_amount_rules
+            .and(&accounts_exist)?
+            .and(&reaches_post_root)?
+            .and(&at_signed_indices)?
+            .and(&is_authorized)?
+            .and(&credit_fits)?
+            .and(&receipt_verifies)?
+            .and(&past_withdrawal_grace)?
+            .and(&consent_verifies)?
+            .and(&within_balance_cap)?;
+        Ok((is_valid, sig_verifies))
+    }
+
+    /// Check that the transaction is a valid self-transfer, i.e., a transaction
+    /// whose sender is also its recipient, for the ledger state proven by
+    /// `witness`. This checks the same conditions as `validate`, but since both
+    /// roles are played by a single account, it is proven and updated once,
+    /// rather than once for each role.
+    ///
+    /// The amount returns to the sender, who only pays the fee, but the whole
+    /// amount still counts towards the daily limit. The amount doesn't count as
+    /// a credit, and a self-transfer is never a withdrawal.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, parameters, witness, pre_root, post_root, epoch)
+    )]
+    pub fn validate_self_transfer(
+        &self,
+        parameters: &ledger::ParametersVar,
+        witness: &AccountWitnessVar,
+        pre_root: PreState<&AccRootVar>,
+        post_root: PostState<&AccRootVar>,
+        epoch: &UInt64<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let pre_acc_info = witness.pre_info.0;
+
+        self.sender.0.enforce_equal(&self.recipient.0)?;
+        let follows_amount_rules = self.follows_amount_rules()?;
 
-        sender_exists.and(&recipient_exists)?.and(&sig_verifies)
+        // Debit the account as a sender, and then credit the amount back to it.
+        let (post_acc_info, is_authorized, _) =
+            self.authorize_and_debit(parameters, pre_acc_info, pre_acc_info, pre_root.0, epoch)?;
+        let (post_acc_info, credit_fits) = self.credit_recipient(&post_acc_info)?;
+
+        let receipt_verifies = self.verify_receipt(parameters, pre_acc_info, &post_acc_info)?;
+
+        let (exists, updated_root) =
+            self.update_account(parameters, witness, &post_acc_info, pre_root.0)?;
+        let reaches_post_root = updated_root.is_eq(post_root.0)?;
+        let at_signed_index = match parameters.bind_leaf_indices {
+            true => self.is_at_signed_indices(witness.path, witness.path)?,
+            false => Boolean::TRUE,
+        };
+
+        follows_amount_rules
+            .and(&exists)?
+            .and(&reaches_post_root)?
+            .and(&at_signed_index)?
+            .and(&is_authorized)?
+            .and(&credit_fits)?
+            .and(&receipt_verifies)
     }
 }
 
@@ -155,18 +1046,66 @@ impl AllocVar<Transaction, ConstraintF> for TransactionVar {
             let tx: &Transaction = tx.borrow();
             let sender = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.sender), mode)?;
             let recipient = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.recipient), mode)?;
+            let asset_id = AssetIdVar::new_variable(cs.clone(), || Ok(&tx.asset_id), mode)?;
             let amount = AmountVar::new_variable(cs.clone(), || Ok(&tx.amount), mode)?;
-            let signature = SignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
-            Ok(Self {
+            let fee = AmountVar::new_variable(cs.clone(), || Ok(&tx.fee), mode)?;
+            let expiry_block_commitment =
+                FpVar::new_variable(cs.clone(), || Ok(tx.expiry_block_commitment), mode)?;
+            let params_commitment =
+                FpVar::new_variable(cs.clone(), || Ok(tx.params_commitment), mode)?;
+            let l1_address = L1AddressVar::new_variable(cs.clone(), || Ok(tx.l1_address), mode)?;
+            let scale = UInt8::new_variable(cs.clone(), || Ok(tx.scale), mode)?;
+            let signature =
+                AccountSignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
+            // Transactions without a receipt are witnessed with a placeholder.
+            let receipt = AccountSignatureVar::new_variable(
+                cs.clone(),
+                || Ok(tx.receipt.clone().unwrap_or_default()),
+                mode,
+            )?;
+            // Likewise for transactions without consent.
+            let consent = AccountSignatureVar::new_variable(
+                cs.clone(),
+                || Ok(tx.consent.clone().unwrap_or_default()),
+                mode,
+            )?;
+            // Likewise for transactions without an allowlist proof.
+            let allowlist_path = PositionedPathVar::new_variable(
+                cs.clone(),
+                || {
+                    Ok(tx
+                        .allowlist_proof
+                        .clone()
+                        .unwrap_or_else(AllowlistProof::placeholder)
+                        .0)
+                },
+                mode,
+            )?;
+            let authorization =
+                SpendingAuthorizationVar::new_variable(cs.clone(), || Ok(&tx.authorization), mode)?;
+            let pow_nonce = UInt64::new_variable(cs.clone(), || Ok(tx.pow_nonce), mode)?;
+            Ok(Self::from_parts(
                 sender,
                 recipient,
+                asset_id,
                 amount,
+                fee,
+                expiry_block_commitment,
+                params_commitment,
+                l1_address,
+                scale,
                 signature,
-            })
+                receipt,
+                consent,
+                allowlist_path,
+                authorization,
+                pow_nonce,
+            ))
         })
     }
 }
 
+#[derive(Clone)]
 pub struct UnaryRollup {
     /// The ledger parameters.
     pub ledger_params: Parameters,
@@ -180,16 +1119,22 @@ pub struct UnaryRollup {
     pub sender_acc_info: AccountInformation,
     /// The sender's authentication path, *before* applying the transaction.
     pub sender_pre_path: AccPath,
-    /// The authentication path corresponding to the sender's account information *after* applying
-    /// the transactions.
-    pub sender_post_path: AccPath,
     /// The recipient's account information *before* applying the transaction.
     pub recv_acc_info: AccountInformation,
-    /// The recipient's authentication path, *before* applying the transaction.
-    pub recv_pre_path: AccPath,
-    /// The authentication path corresponding to the recipient's account information *after*
-    /// applying the transactions.
+    /// The recipient's authentication path *after* applying the transaction,
+    /// which is also its path after the sender's update.
     pub recv_post_path: AccPath,
+    /// The amount that the transaction is expected to transfer, if it is paying
+    /// a fixed invoice. When set, this is declared as a public input.
+    pub expected_amount: Option<Amount>,
+    /// The number of decimals of the asset being transferred, if the application
+    /// restricts amounts to whole units of it. When set, this is declared as a
+    /// public input.
+    pub decimals: Option<u32>,
+    /// The ledger epoch during which the transaction is applied.
+    pub epoch: u64,
+    /// The commitment to the block at which the transaction is applied.
+    pub block_commitment: BlockCommitment,
 }
 
 impl UnaryRollup {
@@ -204,20 +1149,18 @@ impl UnaryRollup {
         }
 
         let initial_root = state.root();
+        let epoch = state.epoch;
+        let block_commitment = state.block_commitment;
         let sender_id = transaction.sender;
         let recipient_id = transaction.recipient;
 
         let sender_acc_info = *state.id_to_account_info.get(&sender_id)?;
         let sender_pre_path = state
-            .account_merkle_tree
+            .account_merkle_tree()
             .generate_proof(sender_id.0 as usize)
             .unwrap();
 
         let recv_acc_info = *state.id_to_account_info.get(&recipient_id)?;
-        let recv_pre_path = state
-            .account_merkle_tree
-            .generate_proof(recipient_id.0 as usize)
-            .unwrap();
 
         if validate {
             state.apply_transaction(&ledger_params, &transaction)?;
@@ -226,12 +1169,8 @@ impl UnaryRollup {
         }
 
         let final_root = state.root();
-        let sender_post_path = state
-            .account_merkle_tree
-            .generate_proof(sender_id.0 as usize)
-            .unwrap();
         let recv_post_path = state
-            .account_merkle_tree
+            .account_merkle_tree()
             .generate_proof(recipient_id.0 as usize)
             .unwrap();
 
@@ -242,18 +1181,36 @@ impl UnaryRollup {
             transaction,
             sender_acc_info,
             sender_pre_path,
-            sender_post_path,
             recv_acc_info,
-            recv_pre_path,
             recv_post_path,
+            expected_amount: None,
+            decimals: None,
+            epoch,
+            block_commitment,
         })
     }
 }
 
-impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
-    fn generate_constraints(
+impl UnaryRollup {
+    /// Compute how many constraints are saved by validating a self-transfer with
+    /// `TransactionVar::validate_self_transfer`, which checks the membership of
+    /// the account once, rather than once as the sender and once more as the
+    /// recipient. This is zero if the transaction is not a self-transfer.
+    pub fn self_transfer_savings(&self) -> Result<usize, SynthesisError> {
+        let num_constraints = |merge_self_transfer| {
+            let cs = ConstraintSystem::new_ref();
+            self.clone().synthesize(cs.clone(), merge_self_transfer)?;
+            Ok::<_, SynthesisError>(cs.num_constraints())
+        };
+        Ok(num_constraints(false)? - num_constraints(true)?)
+    }
+
+    /// Generate the constraints of the rollup. If `merge_self_transfer` is set
+    /// and the transaction is a self-transfer, the account is only proven once.
+    fn synthesize(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
+        merge_self_transfer: bool,
     ) -> Result<(), SynthesisError> {
         // Declare the parameters as constants.
         let ledger_params = ParametersVar::new_constant(
@@ -267,57 +1224,104 @@ impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
         // Declare the final root as a public input.
         let final_root =
             AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || Ok(self.final_root))?;
+        // Declare the epoch as a public input.
+        let epoch = FpVar::new_input(ark_relations::ns!(cs, "Epoch"), || {
+            Ok(ConstraintF::from(self.epoch))
+        })?;
+        let epoch = ledger::epoch_to_uint64(&epoch)?;
+        // Declare the current block commitment as a public input.
+        let block_commitment =
+            FpVar::new_input(ark_relations::ns!(cs, "Block commitment"), || {
+                Ok(self.block_commitment)
+            })?;
 
         // Declare transaction as a witness.
         let tx = TransactionVar::new_witness(ark_relations::ns!(cs, "Transaction"), || {
             Ok(self.transaction.clone())
         })?;
+        tx.enforce_unexpired_at(&block_commitment)?;
 
         // Declare the sender's initial account balance...
         let sender_acc_info = AccountInformationVar::new_witness(
             ark_relations::ns!(cs, "Sender Account Info"),
             || Ok(self.sender_acc_info),
         )?;
-        // ..., corresponding authentication path, ...
+        // ... and corresponding authentication path.
         let sender_pre_path =
-            AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Pre-Path"), || {
+            PositionedPathVar::new_witness(ark_relations::ns!(cs, "Sender Pre-Path"), || {
                 Ok(self.sender_pre_path.clone())
             })?;
-        // ... and authentication path after the update.
-        let sender_post_path =
-            AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Post-Path"), || {
-                Ok(self.sender_post_path.clone())
+
+        // If we are paying an invoice, declare the expected amount as a public input
+        // and check that the transaction pays exactly that amount.
+        if let Some(expected_amount) = self.expected_amount {
+            let expected_amount =
+                AmountVar::new_input(ark_relations::ns!(cs, "Expected amount"), || {
+                    Ok(expected_amount)
+                })?;
+            tx.enforce_amount_equals(&expected_amount)?;
+        }
+        // If the asset has a fixed number of decimals, declare it as a public input
+        // and check that the transaction moves a whole number of units.
+        if let Some(decimals) = self.decimals {
+            let decimals = FpVar::new_input(ark_relations::ns!(cs, "Decimals"), || {
+                Ok(ConstraintF::from(decimals))
             })?;
+            tx.amount.enforce_precision(&decimals)?;
+        }
+
+        // A self-transfer only involves a single account, so it is only proven once.
+        if merge_self_transfer && self.transaction.sender == self.transaction.recipient {
+            let witness = AccountWitnessVar {
+                pre_info: PreState(&sender_acc_info),
+                path: &sender_pre_path,
+            };
+            return tx
+                .validate_self_transfer(
+                    &ledger_params,
+                    &witness,
+                    PreState(&initial_root),
+                    PostState(&final_root),
+                    &epoch,
+                )?
+                .enforce_equal(&Boolean::TRUE);
+        }
 
         // Declare the recipient's initial account balance...
         let recipient_acc_info = AccountInformationVar::new_witness(
             ark_relations::ns!(cs, "Recipient Account Info"),
             || Ok(self.recv_acc_info),
         )?;
-        // ..., corresponding authentication path, ...
-        let recipient_pre_path =
-            AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Pre-Path"), || {
-                Ok(self.recv_pre_path.clone())
-            })?;
         // ... and authentication path after the update.
         let recipient_post_path =
-            AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Post-Path"), || {
+            PositionedPathVar::new_witness(ark_relations::ns!(cs, "Recipient Post-Path"), || {
                 Ok(self.recv_post_path.clone())
             })?;
 
+        let witness = TransactionWitnessVar {
+            sender: AccountWitnessVar {
+                pre_info: PreState(&sender_acc_info),
+                path: &sender_pre_path,
+            },
+            recipient: AccountWitnessVar {
+                pre_info: PreState(&recipient_acc_info),
+                path: &recipient_post_path,
+            },
+            pre_root: PreState(&initial_root),
+            post_root: PostState(&final_root),
+        };
         // Validate that the transaction signature and amount is correct.
-        tx.validate(
-            &ledger_params,
-            &sender_acc_info,
-            &sender_pre_path,
-            &sender_post_path,
-            &recipient_acc_info,
-            &recipient_pre_path,
-            &recipient_post_path,
-            &initial_root,
-            &final_root,
-        )?
-        .enforce_equal(&Boolean::TRUE)
+        tx.validate(&ledger_params, &witness, &epoch)?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.synthesize(cs, true)
     }
 }
 
@@ -327,7 +1331,10 @@ mod test {
     use ark_relations::r1cs::{
         ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints,
     };
-    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::account::AccountId;
+    use ark_simple_payments::ledger::{
+        Amount, AssetId, MessageEncoding, Parameters, SignatureEncoding, State, MAX_DECIMALS,
+    };
     use ark_simple_payments::transaction::Transaction;
     use tracing_subscriber::layer::SubscriberExt;
 
@@ -370,19 +1377,1077 @@ mod test {
         assert!(test_cs(rollup));
 
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(
-            &pp,
-            alice_id,
-            bob_id,
-            Amount(5),
-            &bob_sk,
-            &mut rng,
-        );
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_none());
         let rollup =
             UnaryRollup::with_state_and_transaction(pp.clone(), bad_tx, &mut temp_state, false)
                 .unwrap();
         assert!(!test_cs(rollup));
     }
+
+    #[test]
+    fn from_parts_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut state, true)
+                .unwrap();
+
+        // Validate `tx` against the witnesses in `rollup`, after allocating it
+        // either from the native transaction or from its parts.
+        let validate = |tx: &Transaction, from_parts: bool| {
+            let cs = ConstraintSystem::new_ref();
+            let tx = if from_parts {
+                TransactionVar::from_parts(
+                    AccountIdVar::new_witness(cs.clone(), || Ok(tx.sender)).unwrap(),
+                    AccountIdVar::new_witness(cs.clone(), || Ok(tx.recipient)).unwrap(),
+                    AssetIdVar::new_witness(cs.clone(), || Ok(tx.asset_id)).unwrap(),
+                    AmountVar::new_witness(cs.clone(), || Ok(tx.amount)).unwrap(),
+                    AmountVar::new_witness(cs.clone(), || Ok(tx.fee)).unwrap(),
+                    FpVar::new_witness(cs.clone(), || Ok(tx.expiry_block_commitment)).unwrap(),
+                    FpVar::new_witness(cs.clone(), || Ok(tx.params_commitment)).unwrap(),
+                    L1AddressVar::new_witness(cs.clone(), || Ok(tx.l1_address)).unwrap(),
+                    UInt8::new_witness(cs.clone(), || Ok(tx.scale)).unwrap(),
+                    AccountSignatureVar::new_witness(cs.clone(), || Ok(&tx.signature)).unwrap(),
+                    AccountSignatureVar::new_witness(cs.clone(), || {
+                        Ok(tx.receipt.clone().unwrap_or_default())
+                    })
+                    .unwrap(),
+                    AccountSignatureVar::new_witness(cs.clone(), || {
+                        Ok(tx.consent.clone().unwrap_or_default())
+                    })
+                    .unwrap(),
+                    PositionedPathVar::new_witness(cs.clone(), || {
+                        Ok(AllowlistProof::placeholder().0)
+                    })
+                    .unwrap(),
+                    SpendingAuthorizationVar::new_witness(cs.clone(), || Ok(None)).unwrap(),
+                    UInt64::new_witness(cs.clone(), || Ok(tx.pow_nonce)).unwrap(),
+                )
+            } else {
+                TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap()
+            };
+            let params = ParametersVar::new_constant(cs.clone(), &rollup.ledger_params).unwrap();
+            let witness = |info| AccountInformationVar::new_witness(cs.clone(), || Ok(info));
+            let path = |path: &AccPath| PositionedPathVar::new_witness(cs.clone(), || Ok(path));
+            let root = |root| AccRootVar::new_input(cs.clone(), || Ok(root));
+            let (sender_info, recv_info) = (
+                witness(rollup.sender_acc_info).unwrap(),
+                witness(rollup.recv_acc_info).unwrap(),
+            );
+            let (sender_pre_path, recv_post_path) = (
+                path(&rollup.sender_pre_path).unwrap(),
+                path(&rollup.recv_post_path).unwrap(),
+            );
+            let (initial_root, final_root) = (
+                root(rollup.initial_root).unwrap(),
+                root(rollup.final_root).unwrap(),
+            );
+            let witness = TransactionWitnessVar {
+                sender: AccountWitnessVar {
+                    pre_info: PreState(&sender_info),
+                    path: &sender_pre_path,
+                },
+                recipient: AccountWitnessVar {
+                    pre_info: PreState(&recv_info),
+                    path: &recv_post_path,
+                },
+                pre_root: PreState(&initial_root),
+                post_root: PostState(&final_root),
+            };
+            let result = tx
+                .validate(&params, &witness, &UInt64::constant(rollup.epoch))
+                .unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            (result.value().unwrap(), cs.num_constraints())
+        };
+
+        assert_eq!(validate(&tx, true), validate(&tx, false));
+        assert!(validate(&tx, true).0);
+
+        // Tampering with the amount invalidates the signature either way.
+        let mut bad_tx = tx;
+        bad_tx.amount = Amount(6);
+        assert_eq!(validate(&bad_tx, true), validate(&bad_tx, false));
+        assert!(!validate(&bad_tx, true).0);
+    }
+
+    #[test]
+    fn grouped_witness_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Validate the transaction of `rollup` against its witnesses, grouped by
+        // the state they refer to, optionally mislabelling the roots.
+        let validate = |rollup: &UnaryRollup, swap_roots: bool| {
+            let cs = ConstraintSystem::new_ref();
+            let tx = TransactionVar::new_witness(cs.clone(), || Ok(&rollup.transaction)).unwrap();
+            let params = ParametersVar::new_constant(cs.clone(), &rollup.ledger_params).unwrap();
+            let info = |info| AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+            let path =
+                |path: &AccPath| PositionedPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+            let root = |root| AccRootVar::new_input(cs.clone(), || Ok(root)).unwrap();
+            let (sender_info, recv_info) =
+                (info(rollup.sender_acc_info), info(rollup.recv_acc_info));
+            let (sender_pre_path, recv_post_path) =
+                (path(&rollup.sender_pre_path), path(&rollup.recv_post_path));
+            let (mut initial_root, mut final_root) =
+                (root(rollup.initial_root), root(rollup.final_root));
+            if swap_roots {
+                std::mem::swap(&mut initial_root, &mut final_root);
+            }
+            let witness = TransactionWitnessVar {
+                sender: AccountWitnessVar {
+                    pre_info: PreState(&sender_info),
+                    path: &sender_pre_path,
+                },
+                recipient: AccountWitnessVar {
+                    pre_info: PreState(&recv_info),
+                    path: &recv_post_path,
+                },
+                pre_root: PreState(&initial_root),
+                post_root: PostState(&final_root),
+            };
+            tx.validate(&params, &witness, &UInt64::constant(rollup.epoch))
+                .unwrap()
+                .value()
+                .unwrap()
+        };
+        let rollup = |tx: Transaction| {
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), false)
+                .unwrap()
+        };
+
+        // The grouped witnesses lead to the same outcome as the whole circuit, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(21), &alice_sk, &mut rng);
+        for tx in [tx.clone(), bad_tx] {
+            assert_eq!(validate(&rollup(tx.clone()), false), test_cs(rollup(tx)));
+        }
+        // ... and values that refer to the wrong state are rejected.
+        assert!(validate(&rollup(tx.clone()), false));
+        assert!(!validate(&rollup(tx), true));
+    }
+
+    #[test]
+    fn self_transfer_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice sends some funds to herself, and only pays the fee.
+        let tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            alice_id,
+            AssetId::NATIVE,
+            Amount(5),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut post_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut post_state, true).unwrap();
+        let balance = post_state.id_to_account_info[&alice_id]
+            .balances
+            .get(AssetId::NATIVE);
+        assert_eq!(balance, Some(Amount(19)));
+
+        // The account is proven once, which is both satisfiable and cheaper ...
+        assert!(test_cs(rollup.clone()));
+        let savings = rollup.self_transfer_savings().unwrap();
+        assert!(savings > 0);
+        println!("Constraints saved by a self-transfer: {}", savings);
+        // ... than proving it twice, which can't even account for both roles.
+        let cs = ConstraintSystem::new_ref();
+        rollup.clone().synthesize(cs.clone(), false).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // Transfers between distinct accounts are unaffected.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), true)
+                .unwrap();
+        assert_eq!(rollup.self_transfer_savings().unwrap(), 0);
+    }
+
+    #[test]
+    fn key_rotation_test() {
+        use ark_simple_payments::ledger::IncrementalUpdate;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (_, _, carol_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let mut post_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut post_state, true).unwrap();
+
+        // Rotate the key of the recipient, or of the sender, after the transfer.
+        let rotate = |id: ark_simple_payments::account::AccountId| {
+            let mut info = post_state.id_to_account_info[&id];
+            info.public_key = carol_sk.public_key;
+            let mut tree = post_state.account_merkle_tree().clone();
+            let leaf = info.to_leaf(&pp);
+            let (_, root) = tree.update_with_path(id.0 as usize, &leaf).unwrap();
+            UnaryRollup {
+                final_root: root,
+                recv_post_path: tree.generate_proof(bob_id.0 as usize).unwrap(),
+                ..rollup.clone()
+            }
+        };
+
+        assert!(test_cs(rollup.clone()));
+        assert!(!test_cs(rotate(bob_id)));
+        assert!(!test_cs(rotate(alice_id)));
+    }
+
+    #[test]
+    fn fee_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays 15 units to Bob, and a fee of 5 units.
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(15),
+            Amount(5),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut temp_state, true).unwrap();
+        let balance = |id| {
+            temp_state.id_to_account_info[&id]
+                .balances
+                .get(AssetId::NATIVE)
+        };
+        assert_eq!(balance(alice_id), Some(Amount(0)));
+        assert_eq!(balance(bob_id), Some(Amount(15)));
+        assert!(test_cs(rollup));
+
+        // Alice can't afford a fee of 6 units.
+        let bad_tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(15),
+            Amount(6),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_tx.validate(&pp, &state));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), bad_tx, &mut state.clone(), false)
+                .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn invoice_amount_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays Bob's invoice of 5 units, and pays exactly 5 units.
+        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let mut rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            tx1.clone(),
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        rollup.expected_amount = Some(Amount(5));
+        assert!(test_cs(rollup));
+
+        // The same transaction must not be accepted as payment for an invoice of 6 units.
+        let mut rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx1, &mut state.clone(), true)
+                .unwrap();
+        rollup.expected_amount = Some(Amount(6));
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn precision_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(u64::MAX))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut pays = |amount, decimals| {
+            let tx = Transaction::create(&pp, alice_id, bob_id, amount, &alice_sk, &mut rng);
+            let mut rollup =
+                UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), true)
+                    .unwrap();
+            rollup.decimals = Some(decimals);
+            test_cs(rollup)
+        };
+
+        // With 2 decimals, amounts are whole multiples of 0.01, ...
+        let unit = Amount::unit(2).unwrap();
+        assert_eq!(unit, Amount(1_000_000));
+        assert!(pays(Amount(3 * unit.0), 2));
+        assert!(!Amount(3 * unit.0 + 1).has_precision(2));
+        assert!(!pays(Amount(3 * unit.0 + 1), 2));
+        // ... while finer amounts need more decimals, ...
+        assert!(pays(Amount(3 * unit.0 + 1), MAX_DECIMALS));
+        // ... and no more than the ledger's own are supported.
+        assert!(!pays(Amount(3 * unit.0), MAX_DECIMALS + 1));
+    }
+
+    #[test]
+    fn multi_asset_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(alice_id, AssetId(1), Amount(10))
+            .unwrap();
+        state
+            .update_asset_balance(alice_id, AssetId(2), Amount(7))
+            .unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice transfers 4 units of asset 1 to Bob, which leaves her balance of
+        // asset 2 untouched.
+        let tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(4),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut temp_state, true)
+                .unwrap();
+        let alice_balances = temp_state.id_to_account_info[&alice_id].balances;
+        assert_eq!(alice_balances.get(AssetId(1)), Some(Amount(5)));
+        assert_eq!(alice_balances.get(AssetId(2)), Some(Amount(7)));
+        assert!(test_cs(rollup));
+
+        // The rollup can't also change Alice's balance of asset 2.
+        let mut rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut temp_state.clone(), true)
+                .unwrap();
+        temp_state
+            .update_asset_balance(alice_id, AssetId(2), Amount(8))
+            .unwrap();
+        rollup.final_root = temp_state.root();
+        rollup.recv_post_path = temp_state.prove_account(bob_id).unwrap().path;
+        assert!(!test_cs(rollup));
+
+        // Alice can't spend her balance of asset 2 as asset 1.
+        let bad_tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(11),
+            Amount(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_tx.validate(&pp, &state));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp, bad_tx, &mut state.clone(), false).unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn post_balance_binding_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut post_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut post_state, true)
+                .unwrap();
+        assert_eq!(
+            post_state.id_to_account_info[&alice_id]
+                .balances
+                .get(AssetId::NATIVE),
+            Some(Amount(14))
+        );
+        assert!(test_cs(rollup));
+
+        // The prover can't claim any other post balance for either party, even
+        // with a root and paths that are consistent with that balance.
+        for (id, balance) in [
+            (alice_id, Amount(15)),
+            (alice_id, Amount(13)),
+            (alice_id, Amount(20)),
+            (bob_id, Amount(6)),
+        ] {
+            let mut rollup = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut state.clone(),
+                true,
+            )
+            .unwrap();
+            let mut bad_post_state = post_state.clone();
+            bad_post_state.update_balance(id, balance).unwrap();
+            rollup.final_root = bad_post_state.root();
+            rollup.recv_post_path = bad_post_state.prove_account(bob_id).unwrap().path;
+            assert!(!test_cs(rollup));
+        }
+    }
+
+    #[test]
+    fn amount_commitment_test() {
+        use ark_simple_payments::ledger::AmountBlinding;
+        use ark_std::UniformRand;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+
+        // Check whether committing to `amount` with `blinding` leads to a commitment
+        // that opens to the amount of `tx` with `opening`.
+        let opens = |amount: Amount, blinding: &AmountBlinding, opening: &AmountBlinding| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(&tx)).unwrap();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let commitment = amount.commit(&pp, blinding);
+            let commitment = AmountCommitmentVar::new_input(cs.clone(), || Ok(commitment)).unwrap();
+            let opening = AmountBlindingVar::new_witness(cs.clone(), || Ok(opening)).unwrap();
+            tx_var
+                .verify_amount_commitment(&params, &commitment, &opening)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        let blinding = AmountBlinding::rand(&mut rng);
+        assert!(opens(Amount(5), &blinding, &blinding));
+        // A commitment to a different amount doesn't open to the amount of `tx`, ...
+        assert!(!opens(Amount(6), &blinding, &blinding));
+        // ... and neither does it with a different blinding factor.
+        let other_blinding = AmountBlinding::rand(&mut rng);
+        assert!(!opens(Amount(5), &blinding, &other_blinding));
+    }
+
+    #[test]
+    fn noop_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let mut post_state = state.clone();
+        post_state.update_balance(alice_id, Amount(10)).unwrap();
+
+        // Check a padding slot of a batch: the transaction in the slot must either
+        // be valid, or be a no-op that leaves the root unchanged. No account is
+        // involved in a no-op, so the witnesses are placeholders.
+        let check_padding = |tx: &Transaction, post_root| {
+            let cs = ConstraintSystem::new_ref();
+            let tx = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            let params = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+            let info = AccountInformation::new(Default::default());
+            let info = AccountInformationVar::new_witness(cs.clone(), || Ok(info)).unwrap();
+            let path = state.account_merkle_tree().generate_proof(0).unwrap();
+            let path = PositionedPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+            let pre_root = AccRootVar::new_input(cs.clone(), || Ok(state.root())).unwrap();
+            let post_root = AccRootVar::new_input(cs.clone(), || Ok(post_root)).unwrap();
+            let account = || AccountWitnessVar {
+                pre_info: PreState(&info),
+                path: &path,
+            };
+            let witness = TransactionWitnessVar {
+                sender: account(),
+                recipient: account(),
+                pre_root: PreState(&pre_root),
+                post_root: PostState(&post_root),
+            };
+            let is_valid = tx
+                .validate(&params, &witness, &UInt64::constant(state.epoch))
+                .unwrap();
+            assert!(!is_valid.value().unwrap());
+            is_valid
+                .or(&tx.is_noop().unwrap())
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            tx.enforce_noop_keeps_root(&pre_root, &post_root).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // The constant no-op is recognized without any constraints.
+        let is_noop = TransactionVar::noop().unwrap().is_noop().unwrap();
+        assert!(is_noop.is_constant() && is_noop.value().unwrap());
+
+        // A no-op keeps the root fixed, ...
+        let noop = Transaction::NOOP;
+        assert!(noop.is_noop());
+        assert!(!noop.validate(&pp, &state));
+        assert!(check_padding(&noop, state.root()));
+        // ... and can't be used to change it.
+        assert!(!check_padding(&noop, post_state.root()));
+
+        // A transaction that is not a no-op can't masquerade as padding.
+        let mut fake_noop = Transaction::NOOP;
+        fake_noop.amount = Amount(10);
+        assert!(!fake_noop.is_noop());
+        assert!(!check_padding(&fake_noop, post_state.root()));
+        let tx = Transaction::create(&pp, alice_id, alice_id, Amount(0), &alice_sk, &mut rng);
+        assert!(!tx.is_noop());
+        assert!(!check_padding(&tx, state.root()));
+    }
+
+    #[test]
+    fn amount_rules_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Check that the amount rules hold both natively and in the circuit.
+        let follows_rules = |tx: &Transaction| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            let flag = tx_var.follows_amount_rules().unwrap();
+            assert_eq!(tx.follows_amount_rules(), flag.value().unwrap());
+            tx_var.enforce_amount_rules().unwrap();
+            assert_eq!(tx.follows_amount_rules(), cs.is_satisfied().unwrap());
+            tx.follows_amount_rules()
+        };
+
+        // Transfers must move a nonzero amount, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(follows_rules(&tx));
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(0), &alice_sk, &mut rng);
+        assert!(!follows_rules(&tx));
+        // ... and so must withdrawals, ...
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            WITHDRAWAL_ACCOUNT,
+            Amount(5),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(follows_rules(&tx));
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            WITHDRAWAL_ACCOUNT,
+            Amount(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!follows_rules(&tx));
+        // ... while control operations must not move anything.
+        let mut control_op = Transaction::NOOP;
+        assert!(control_op.is_control_op());
+        assert!(follows_rules(&control_op));
+        control_op.amount = Amount(3);
+        assert!(!follows_rules(&control_op));
+    }
+
+    #[test]
+    fn sha256_message_encoding_test() {
+        let mut rng = ark_std::test_rng();
+        let raw_pp = Parameters::sample(&mut rng);
+        let mut sha256_pp = raw_pp.clone();
+        sha256_pp.message_encoding = MessageEncoding::Sha256;
+        let mut state = State::new(32, &raw_pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&raw_pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&raw_pp, &mut rng).unwrap();
+
+        // Alice's wallet signs the SHA-256 digest of the transaction message.
+        let tx = Transaction::create(&sha256_pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(tx.validate(&sha256_pp, &state));
+        assert!(!tx.validate(&raw_pp, &state));
+
+        // The signature verifies only when the circuit hashes the message first.
+        let rollup = UnaryRollup::with_state_and_transaction(
+            sha256_pp,
+            tx.clone(),
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+
+        let rollup =
+            UnaryRollup::with_state_and_transaction(raw_pp, tx, &mut state.clone(), false).unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn structured_message_encoding_test() {
+        let mut rng = ark_std::test_rng();
+        let mut structured_pp = Parameters::sample(&mut rng);
+        structured_pp.message_encoding = MessageEncoding::Structured;
+        let mut state = State::new(32, &structured_pp);
+        let (alice_id, _alice_pk, alice_sk) = state
+            .sample_keys_and_register(&structured_pp, &mut rng)
+            .unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state
+            .sample_keys_and_register(&structured_pp, &mut rng)
+            .unwrap();
+        let tx = Transaction::create_with_asset(
+            &structured_pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(0),
+            Amount(0),
+            &alice_sk,
+            &mut rng,
+        );
+
+        // The circuit computes the same digest as the wallet, ...
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(&tx)).unwrap();
+        let digest = tx_var.structured_message().unwrap();
+        assert_eq!(digest.value().unwrap(), tx.structured_message());
+        assert!(cs.is_satisfied().unwrap());
+
+        // ... and so the signature of a transfer verifies in the circuit.
+        let tx = Transaction::create(
+            &structured_pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            &alice_sk,
+            &mut rng,
+        );
+        let rollup =
+            UnaryRollup::with_state_and_transaction(structured_pp, tx, &mut state, true).unwrap();
+        assert!(test_cs(rollup));
+    }
+
+    #[test]
+    fn signature_encoding_test() {
+        let mut rng = ark_std::test_rng();
+        let challenge_pp = Parameters::sample(&mut rng);
+        let mut point_pp = challenge_pp.clone();
+        point_pp.signature_encoding = SignatureEncoding::Point;
+        let mut state = State::new(32, &challenge_pp);
+        let (alice_id, _alice_pk, alice_sk) = state
+            .sample_keys_and_register(&challenge_pp, &mut rng)
+            .unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state
+            .sample_keys_and_register(&challenge_pp, &mut rng)
+            .unwrap();
+
+        // Each encoding verifies against its own signer, and only against it.
+        for (pp, other_pp) in [(&challenge_pp, &point_pp), (&point_pp, &challenge_pp)] {
+            let tx = Transaction::create(pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            assert!(tx.validate(pp, &state));
+            assert!(!tx.validate(other_pp, &state));
+
+            let rollup = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut state.clone(),
+                true,
+            )
+            .unwrap();
+            assert!(test_cs(rollup));
+
+            let rollup = UnaryRollup::with_state_and_transaction(
+                other_pp.clone(),
+                tx,
+                &mut state.clone(),
+                false,
+            )
+            .unwrap();
+            // Signatures in the wrong encoding are rejected statically, and so
+            // the constraints can't even be generated.
+            let cs = ConstraintSystem::new_ref();
+            assert!(rollup.generate_constraints(cs).is_err());
+        }
+    }
+
+    #[test]
+    fn joint_account_test() {
+        use ark_ec::{AffineCurve, ProjectiveCurve};
+        use ark_ed_on_bls12_381::EdwardsProjective;
+        use ark_simple_payments::account::{AccountSecretKey, AccountSignature};
+        use ark_simple_payments::signature::{
+            schnorr::{musig, Schnorr},
+            SignatureScheme,
+        };
+
+        /// Sign `message` jointly with the secret keys `sks`.
+        fn sign_jointly(
+            pp: &Parameters,
+            sks: &[&AccountSecretKey],
+            message: &[u8],
+        ) -> AccountSignature {
+            let mut rng = ark_std::test_rng();
+            let keys = sks.iter().map(|sk| sk.public_key).collect::<Vec<_>>();
+            let (nonces, commitments): (Vec<_>, Vec<_>) = sks
+                .iter()
+                .map(|_| musig::commit_nonce(&pp.sig_params, &mut rng))
+                .unzip();
+            let shares = sks
+                .iter()
+                .zip(nonces)
+                .map(|(sk, nonce)| {
+                    musig::sign_share(&pp.sig_params, &keys, &commitments, sk, nonce, message)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            let signature =
+                musig::combine_shares(&pp.sig_params, &keys, &commitments, &shares, message);
+            AccountSignature::Challenge(signature.unwrap())
+        }
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_pk, alice_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (bob_pk, bob_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice and Bob control a joint account, with their aggregated key.
+        let joint_pk =
+            musig::aggregate_public_keys::<EdwardsProjective>(&[alice_pk, bob_pk]).unwrap();
+        let joint_id = state.register(joint_pk).unwrap();
+        state.update_balance(joint_id, Amount(20)).unwrap();
+        // An account whose key is the plain sum of their keys is controlled by
+        // neither of them, and so can't be spent from by the same signature.
+        let sum_pk = (alice_pk.into_projective() + bob_pk.into_projective()).into_affine();
+        let sum_id = state.register(sum_pk).unwrap();
+        state.update_balance(sum_id, Amount(20)).unwrap();
+
+        // A single signature authorizes a transaction from the joint account.
+        let mut tx = Transaction::create(&pp, joint_id, carol_id, Amount(5), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        tx.signature = sign_jointly(&pp, &[&alice_sk, &bob_sk], &tx.signed_message(&pp));
+        assert!(tx.validate(&pp, &state));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // The signature doesn't verify against any other aggregate.
+        let mut tx = Transaction::create(&pp, sum_id, carol_id, Amount(5), &alice_sk, &mut rng);
+        tx.signature = sign_jointly(&pp, &[&alice_sk, &bob_sk], &tx.signed_message(&pp));
+        assert!(!tx.validate(&pp, &state));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), false)
+                .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn expiry_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.block_commitment = BlockCommitment::from(7u64);
+        let create = |expiry_block_commitment: u64, rng: &mut _| {
+            Transaction::create_with_expiry(
+                &pp,
+                alice_id,
+                bob_id,
+                AssetId::NATIVE,
+                Amount(5),
+                Amount(0),
+                BlockCommitment::from(expiry_block_commitment),
+                &alice_sk,
+                rng,
+            )
+        };
+        let is_valid = |tx: &Transaction, validate| {
+            let rollup = UnaryRollup::with_state_and_transaction(
+                pp.clone(),
+                tx.clone(),
+                &mut state.clone(),
+                validate,
+            )
+            .unwrap();
+            test_cs(rollup)
+        };
+
+        // Transactions bound to the current block, or to no block, are accepted, ...
+        for tx in [create(7, &mut rng), create(0, &mut rng)] {
+            assert!(tx.validate(&pp, &state));
+            assert!(is_valid(&tx, true));
+        }
+        // ... while those bound to any other block have expired.
+        let expired_tx = create(8, &mut rng);
+        assert!(!expired_tx.validate(&pp, &state));
+        assert!(!is_valid(&expired_tx, false));
+        // The expiry is signed, so it can't be rebound to the current block.
+        let mut rebound_tx = expired_tx;
+        rebound_tx.expiry_block_commitment = state.block_commitment;
+        assert!(!rebound_tx.validate(&pp, &state));
+        assert!(!is_valid(&rebound_tx, false));
+    }
+
+    #[test]
+    fn transaction_id_test() {
+        let mut rng = ark_std::test_rng();
+        let challenge_pp = Parameters::sample(&mut rng);
+        let mut point_pp = challenge_pp.clone();
+        point_pp.signature_encoding = SignatureEncoding::Point;
+        let mut state = State::new(32, &challenge_pp);
+        let (alice_id, _alice_pk, alice_sk) = state
+            .sample_keys_and_register(&challenge_pp, &mut rng)
+            .unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state
+            .sample_keys_and_register(&challenge_pp, &mut rng)
+            .unwrap();
+
+        // Compute the identifier of `tx` in a fresh constraint system, and check
+        // that it agrees with the native one.
+        let circuit_id = |pp: &Parameters, tx: &Transaction| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let params = ledger::ParametersVar::new_constant(cs.clone(), pp).unwrap();
+            let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            let id = tx_var.id(&params).unwrap().value().unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            assert_eq!(id, tx.id(pp));
+            id
+        };
+
+        for pp in [&challenge_pp, &point_pp] {
+            let tx = Transaction::create(pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            let id = circuit_id(pp, &tx);
+
+            // Changing any of the fields changes the identifier, ...
+            let mut changed = vec![tx.clone(); 10];
+            changed[0].sender = bob_id;
+            changed[1].recipient = alice_id;
+            changed[2].asset_id = AssetId(1);
+            changed[3].amount = Amount(6);
+            changed[4].fee = Amount(1);
+            changed[5].expiry_block_commitment = state.block_commitment + ConstraintF::from(1u8);
+            changed[6].params_commitment += ConstraintF::from(1u8);
+            changed[7].l1_address = [1u8; 20];
+            changed[8].scale = 1;
+            // ... including the signature.
+            changed[9].signature =
+                Transaction::create(pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng).signature;
+            for changed_tx in &changed {
+                assert_ne!(circuit_id(pp, changed_tx), id);
+            }
+        }
+    }
+
+    #[test]
+    fn signed_leaf_indices_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.bind_leaf_indices = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // The circuit signs the leaf indices as the native signer does.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            tx.clone(),
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+
+        // The accounts must be proven at the signed indices, even though the path
+        // of another account proves the membership of that account's information.
+        let verifies = |sender, recipient| {
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(&tx)).unwrap();
+            let path = |id: AccountId| {
+                let proof = state.prove_account(id).unwrap();
+                PositionedPathVar::new_witness(cs.clone(), || Ok(proof.path)).unwrap()
+            };
+            tx_var
+                .is_at_signed_indices(&path(sender), &path(recipient))
+                .unwrap()
+                .value()
+                .unwrap()
+        };
+        assert!(verifies(alice_id, bob_id));
+        assert!(!verifies(alice_id, carol_id));
+        assert!(!verifies(bob_id, alice_id));
+    }
+
+    #[test]
+    fn signed_pre_root_test() {
+        use ark_simple_payments::ledger::MessageEncoding;
+
+        let mut rng = ark_std::test_rng();
+        for encoding in [MessageEncoding::Raw, MessageEncoding::Structured] {
+            let mut pp = Parameters::sample(&mut rng);
+            pp.message_encoding = encoding;
+            pp.bind_pre_root = true;
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            let is_satisfied = |state: &State, tx: &Transaction| {
+                let rollup = UnaryRollup::with_state_and_transaction(
+                    pp.clone(),
+                    tx.clone(),
+                    &mut state.clone(),
+                    false,
+                )
+                .unwrap();
+                test_cs(rollup)
+            };
+
+            // The circuit checks the signature against the public pre-root, ...
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng)
+                .sign_at(&pp, &bob_pk, &state.root(), &alice_sk, &mut rng);
+            assert!(is_satisfied(&state, &tx));
+            // ... and so a transaction signed against another root, e.g., that of
+            // a fork, is rejected against the current one.
+            let mut fork = state.clone();
+            fork.update_balance(alice_id, Amount(30)).unwrap();
+            let forked = tx
+                .clone()
+                .sign_at(&pp, &bob_pk, &fork.root(), &alice_sk, &mut rng);
+            assert!(is_satisfied(&fork, &forked));
+            assert!(!forked.validate(&pp, &state));
+            assert!(!is_satisfied(&state, &forked));
+            assert!(!is_satisfied(&fork, &tx));
+        }
+    }
+
+    #[test]
+    fn balance_cap_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.balance_caps = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, Amount(3)).unwrap();
+        state.set_balance_cap(bob_id, Amount(10)).unwrap();
+
+        let is_satisfied = |state: &State, tx: Transaction| {
+            let rollup =
+                UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), false)
+                    .unwrap();
+            let cs = ConstraintSystem::<ConstraintF>::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.is_satisfied().unwrap()
+        };
+
+        // A credit that pushes Bob over his cap is rejected, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(8), &alice_sk, &mut rng);
+        assert!(!is_satisfied(&state, tx));
+        // ... while one that lands exactly at it is accepted.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(7), &alice_sk, &mut rng);
+        assert!(is_satisfied(&state, tx));
+        // Alice is uncapped, so she can be credited beyond Bob's cap.
+        let tx = Transaction::create(&pp, bob_id, alice_id, Amount(3), &bob_sk, &mut rng);
+        assert!(is_satisfied(&state, tx));
+    }
 }
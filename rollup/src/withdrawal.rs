@@ -0,0 +1,176 @@
+use crate::account::AccountIdVar;
+use crate::ledger::{
+    merkle_root, AccRootVar, AccountHashGadget, AmountVar, AssetIdVar, ParametersVar,
+};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_r1cs_std::bits::{uint32::UInt32, uint64::UInt64};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::ledger::LeafDomain;
+use ark_simple_payments::withdrawal::{L1Address, Withdrawal, WithdrawalTree, WITHDRAWAL_TAG};
+use std::borrow::Borrow;
+use std::convert::TryInto;
+
+/// The root of a withdrawal Merkle tree.
+pub type WithdrawalRootVar = AccRootVar;
+
+/// An address on L1 that withdrawn funds are paid out to. Like `L1Address`, it
+/// has exactly 20 bytes.
+#[derive(Clone, Debug)]
+pub struct L1AddressVar(pub [UInt8<ConstraintF>; 20]);
+
+impl L1AddressVar {
+    /// The address `address`, as a constant.
+    pub fn constant(address: L1Address) -> Self {
+        Self(address.map(UInt8::constant))
+    }
+}
+
+impl AllocVar<L1Address, ConstraintF> for L1AddressVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<L1Address>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into().cs();
+        let address = f().map(|address| *address.borrow());
+        let mut bytes = Vec::with_capacity(20);
+        for i in 0..20 {
+            bytes.push(UInt8::new_variable(
+                cs.clone(),
+                || address.map(|address| address[i]),
+                mode,
+            )?);
+        }
+        Ok(Self(bytes.try_into().expect("an address has 20 bytes")))
+    }
+}
+
+/// A withdrawal of `amount` of the asset `asset_id` from the ledger, which can
+/// later be claimed by the owner of the account `recipient`.
+#[derive(Clone, Debug)]
+pub struct WithdrawalVar {
+    /// The account that made the withdrawal.
+    pub recipient: AccountIdVar,
+    /// The asset being withdrawn.
+    pub asset_id: AssetIdVar,
+    /// The amount being withdrawn.
+    pub amount: AmountVar,
+    /// The address on L1 that the amount is paid out to.
+    pub l1_address: L1AddressVar,
+}
+
+impl WithdrawalVar {
+    /// Return the withdrawal tree leaf for `tx`: the withdrawal made by `tx` if
+    /// `tx` is a withdrawal, and `Withdrawal::EMPTY` otherwise.
+    pub fn from_transaction(tx: &TransactionVar) -> Result<Self, SynthesisError> {
+        Self::from_transaction_if(tx, &Boolean::TRUE)
+    }
+
+    /// Return the withdrawal tree leaf for `tx` if `applied` is set, as in
+    /// `from_transaction`, and `Withdrawal::EMPTY` otherwise, since skipped
+    /// transactions don't withdraw anything.
+    #[tracing::instrument(target = "r1cs", skip(tx, applied))]
+    pub fn from_transaction_if(
+        tx: &TransactionVar,
+        applied: &Boolean<ConstraintF>,
+    ) -> Result<Self, SynthesisError> {
+        let is_withdrawal = tx.is_withdrawal()?.and(applied)?;
+        let recipient = is_withdrawal.select(
+            &tx.sender.0,
+            &UInt8::constant(Withdrawal::EMPTY.recipient.0),
+        )?;
+        let asset_id = is_withdrawal.select(
+            &tx.asset_id.0,
+            &UInt32::constant(Withdrawal::EMPTY.asset_id.0),
+        )?;
+        let amount =
+            is_withdrawal.select(&tx.amount.0, &UInt64::constant(Withdrawal::EMPTY.amount.0))?;
+        let l1_address = is_withdrawal.select(
+            &tx.l1_address,
+            &L1AddressVar::constant(Withdrawal::EMPTY.l1_address),
+        )?;
+        Ok(Self {
+            recipient: AccountIdVar(recipient),
+            asset_id: AssetIdVar(asset_id),
+            amount: AmountVar(amount),
+            l1_address,
+        })
+    }
+
+    /// The withdrawal tree leaf for transactions that are not withdrawals, as a
+    /// constant.
+    pub fn empty() -> Self {
+        let empty = Withdrawal::EMPTY;
+        Self {
+            recipient: AccountIdVar(UInt8::constant(empty.recipient.0)),
+            asset_id: AssetIdVar(UInt32::constant(empty.asset_id.0)),
+            amount: AmountVar(UInt64::constant(empty.amount.0)),
+            l1_address: L1AddressVar::constant(empty.l1_address),
+        }
+    }
+
+    /// Convert the withdrawal to bytes.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        let mut bytes = self.recipient.to_bytes_le();
+        bytes.extend(self.asset_id.to_bytes_le());
+        bytes.extend(self.amount.to_bytes_le());
+        bytes.extend(self.l1_address.0.iter().cloned());
+        bytes
+    }
+
+    /// Return the leaf of the withdrawal Merkle tree that stores this withdrawal,
+    /// as computed by `Withdrawal::to_leaf`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters))]
+    pub fn to_leaf(
+        &self,
+        parameters: &ParametersVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        // The committed input consists of
+        // (Tag || Withdrawal)
+        let mut input = UInt8::constant_vec(WITHDRAWAL_TAG);
+        input.extend(self.to_bytes_le());
+        let commitment = <AccountHashGadget as CRHGadget<_, ConstraintF>>::evaluate(
+            &parameters.account_crh_params,
+            &input,
+        )?;
+        Ok(parameters.personalize_leaf(LeafDomain::Withdrawal, commitment.to_bytes()?))
+    }
+}
+
+impl CondSelectGadget<ConstraintF> for L1AddressVar {
+    fn conditionally_select(
+        cond: &Boolean<ConstraintF>,
+        true_value: &Self,
+        false_value: &Self,
+    ) -> Result<Self, SynthesisError> {
+        let mut bytes = Vec::with_capacity(20);
+        for (t, f) in true_value.0.iter().zip(&false_value.0) {
+            bytes.push(cond.select(t, f)?);
+        }
+        Ok(Self(bytes.try_into().expect("an address has 20 bytes")))
+    }
+}
+
+/// Compute the root of the withdrawal tree of a batch, given the leaf of each
+/// transaction in the batch. This is the circuit equivalent of
+/// `WithdrawalTree::from_transactions(..).root()`.
+#[tracing::instrument(target = "r1cs", skip(parameters, withdrawals))]
+pub fn withdrawal_root(
+    parameters: &ParametersVar,
+    withdrawals: &[WithdrawalVar],
+) -> Result<WithdrawalRootVar, SynthesisError> {
+    let empty = WithdrawalVar::empty().to_leaf(parameters)?;
+    let leaves = (0..WithdrawalTree::num_leaves(withdrawals.len()))
+        .map(|i| {
+            withdrawals
+                .get(i)
+                .map_or(Ok(empty.clone()), |w| w.to_leaf(parameters))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    merkle_root(parameters, &leaves)
+}
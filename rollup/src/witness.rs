@@ -0,0 +1,473 @@
+//! Deduplication of the Merkle paths supplied with a batch. Each transaction
+//! proves the paths of its sender and recipient both before and after it, but
+//! the path of an account is the same whenever it is taken at the same root. For
+//! example, when consecutive transactions touch the same account, the path after
+//! the first one is the path before the second one.
+//!
+//! This only shrinks the witness data that is stored or sent to the prover. The
+//! circuit still allocates four paths per transaction, and so `BatchWitness::fill`
+//! expands the shared paths again.
+//!
+//! It also assembles the full witnesses of a batch, see `RollupWitness::build`.
+
+use crate::rollup::Rollup;
+use ark_simple_payments::account::{AccountId, AccountInformation};
+use ark_simple_payments::ledger::{
+    AccPath, AccRoot, Amount, BlockCommitment, Parameters, State, TxCounter,
+};
+use ark_simple_payments::transaction::{self, SimulationError, Transaction};
+use ark_simple_payments::withdrawal::{WithdrawalRoot, WithdrawalTree};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The indices of the paths of an account before and after a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathIndices {
+    /// The index of the path before the transaction.
+    pub pre: usize,
+    /// The index of the path after the transaction.
+    pub post: usize,
+}
+
+/// The witnesses of a batch, with each distinct path supplied only once.
+#[derive(Clone)]
+pub struct BatchWitness {
+    /// The distinct paths needed by the batch.
+    pub paths: Vec<AccPath>,
+    /// The sender's account information before each transaction.
+    pub sender_pre_infos: Vec<AccountInformation>,
+    /// The recipient's account information before each transaction.
+    pub recv_pre_infos: Vec<AccountInformation>,
+    /// The indices in `paths` of the sender's paths of each transaction.
+    pub sender_paths: Vec<PathIndices>,
+    /// The indices in `paths` of the recipient's paths of each transaction.
+    pub recv_paths: Vec<PathIndices>,
+}
+
+/// Compute the witnesses of the batch `transactions`, applied to `state` in
+/// order, sharing the paths that are taken for the same account at the same root.
+/// As in `Rollup::with_state_and_transactions`, accounts that don't exist, such as
+/// the recipient of a withdrawal, are witnessed with placeholder information.
+/// Like `RollupWitness::build`, this reports the first transaction that can't be
+/// applied to the state left by the previous ones.
+pub fn minimal_witnesses(
+    parameters: &Parameters,
+    state: &State,
+    transactions: &[Transaction],
+) -> Result<BatchWitness, WitnessError> {
+    let mut state = state.clone();
+    let mut paths = Vec::new();
+    // The index of the path of each account at each root, which is identified by
+    // the number of transactions applied before it.
+    let mut indices = HashMap::<(AccountId, usize), usize>::new();
+    let mut path_index = |state: &State, id: AccountId, step: usize| {
+        *indices.entry((id, step)).or_insert_with(|| {
+            let path = state
+                .account_merkle_tree()
+                .generate_proof(id.0 as usize)
+                .unwrap();
+            paths.push(path);
+            paths.len() - 1
+        })
+    };
+    let info = |state: &State, id| match state.id_to_account_info.get(&id) {
+        Some(info) => *info,
+        None => AccountInformation::new(Default::default()),
+    };
+
+    let mut sender_pre_infos = Vec::with_capacity(transactions.len());
+    let mut recv_pre_infos = Vec::with_capacity(transactions.len());
+    let mut sender_paths = Vec::with_capacity(transactions.len());
+    let mut recv_paths = Vec::with_capacity(transactions.len());
+    for (step, tx) in transactions.iter().enumerate() {
+        let invalid = |reason| WitnessError::InvalidTransaction {
+            index: step,
+            reason,
+        };
+        tx.simulate(parameters, &state).map_err(invalid)?;
+        sender_pre_infos.push(info(&state, tx.sender));
+        recv_pre_infos.push(info(&state, tx.recipient));
+        let sender_pre = path_index(&state, tx.sender, step);
+        let recv_pre = path_index(&state, tx.recipient, step);
+        state
+            .apply_transaction(parameters, tx)
+            .ok_or_else(|| invalid(SimulationError::Invalid))?;
+        sender_paths.push(PathIndices {
+            pre: sender_pre,
+            post: path_index(&state, tx.sender, step + 1),
+        });
+        recv_paths.push(PathIndices {
+            pre: recv_pre,
+            post: path_index(&state, tx.recipient, step + 1),
+        });
+    }
+    Ok(BatchWitness {
+        paths,
+        sender_pre_infos,
+        recv_pre_infos,
+        sender_paths,
+        recv_paths,
+    })
+}
+
+impl BatchWitness {
+    /// Set the witnesses of `rollup` to these, expanding the shared paths into
+    /// the paths of each transaction, which the circuit allocates separately.
+    pub fn fill<const NUM_TX: usize>(&self, rollup: &mut Rollup<NUM_TX>) {
+        let with_paths = |infos: &[AccountInformation], indices: &[PathIndices]| {
+            infos
+                .iter()
+                .zip(indices)
+                .map(|(info, indices)| (*info, self.paths[indices.pre].clone()))
+                .collect()
+        };
+        let post_paths = |indices: &[PathIndices]| {
+            indices
+                .iter()
+                .map(|indices| self.paths[indices.post].clone())
+                .collect()
+        };
+        rollup.sender_pre_tx_info_and_paths =
+            Some(with_paths(&self.sender_pre_infos, &self.sender_paths));
+        rollup.recv_pre_tx_info_and_paths =
+            Some(with_paths(&self.recv_pre_infos, &self.recv_paths));
+        rollup.sender_post_paths = Some(post_paths(&self.sender_paths));
+        rollup.recv_post_paths = Some(post_paths(&self.recv_paths));
+    }
+}
+
+/// The reason why `RollupWitness::build` can't assemble the witnesses of a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WitnessError {
+    /// The batch doesn't consist of `expected` transactions.
+    WrongBatchSize { expected: usize, actual: usize },
+    /// The ledger requires batches in canonical order, and the batch isn't.
+    NotCanonicallyOrdered,
+    /// The ledger requires the transactions of a batch to have distinct
+    /// identifiers, and the batch includes a transaction twice.
+    DuplicateTransaction,
+    /// The transaction at `index` can't be applied to the state left by the
+    /// previous ones, for the reason `reason`.
+    InvalidTransaction {
+        index: usize,
+        reason: SimulationError,
+    },
+    /// The batch exceeds the number of transactions allowed during the epoch.
+    EpochLimitExceeded,
+    /// The fee collector doesn't exist, or crediting it the priority fees of the
+    /// batch overflows one of its balances.
+    FeeCollectionFailed,
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongBatchSize { expected, actual } => {
+                write!(f, "expected {} transactions, got {}", expected, actual)
+            }
+            Self::NotCanonicallyOrdered => f.write_str("the batch is not canonically ordered"),
+            Self::DuplicateTransaction => f.write_str("the batch includes a transaction twice"),
+            Self::InvalidTransaction { index, reason } => {
+                write!(f, "transaction {} can't be applied: {}", index, reason)
+            }
+            Self::EpochLimitExceeded => f.write_str("the batch exceeds the limit of the epoch"),
+            Self::FeeCollectionFailed => f.write_str("the fees of the batch can't be collected"),
+        }
+    }
+}
+
+impl std::error::Error for WitnessError {}
+
+/// All the witnesses that the rollup circuit needs to prove that a batch of
+/// `NUM_TX` transactions is applied to a state. The roots are threaded through
+/// the batch, so that each transaction starts from the root left by the
+/// previous one.
+#[derive(Clone)]
+pub struct RollupWitness<const NUM_TX: usize> {
+    /// The root of the account tree before the batch.
+    pub initial_root: AccRoot,
+    /// The root of the account tree after the batch.
+    pub final_root: AccRoot,
+    /// The root of the withdrawal tree of the batch.
+    pub withdrawal_root: WithdrawalRoot,
+    /// The ledger epoch before the batch.
+    pub pre_epoch: u64,
+    /// The ledger epoch after the batch.
+    pub post_epoch: u64,
+    /// The commitment to the block at which the batch is applied.
+    pub block_commitment: BlockCommitment,
+    /// The transactions of the batch.
+    pub transactions: Vec<Transaction>,
+    /// The sender's account information and path before each transaction.
+    pub sender_pre_tx_info_and_paths: Vec<(AccountInformation, AccPath)>,
+    /// The sender's path after each transaction.
+    pub sender_post_paths: Vec<AccPath>,
+    /// The recipient's account information and path before each transaction.
+    pub recv_pre_tx_info_and_paths: Vec<(AccountInformation, AccPath)>,
+    /// The recipient's path after each transaction.
+    pub recv_post_paths: Vec<AccPath>,
+    /// The root before each transaction.
+    pub pre_tx_roots: Vec<AccRoot>,
+    /// The root after each transaction.
+    pub post_tx_roots: Vec<AccRoot>,
+    /// The transaction counter before the batch and its path, if the ledger
+    /// limits the number of transactions per epoch.
+    pub tx_counter: Option<(TxCounter, AccPath)>,
+    /// The base fee of the batch, along with the information and the path of
+    /// the fee collector after the transactions, if the ledger has a fee market.
+    pub fee_collection: Option<(Amount, AccountInformation, AccPath)>,
+}
+
+impl<const NUM_TX: usize> RollupWitness<NUM_TX> {
+    /// Assemble the witnesses of the batch `transactions`, applied in order to
+    /// a copy of `state`, which is left unchanged. Unlike
+    /// `Rollup::with_state_and_transactions`, this validates each transaction
+    /// against the state left by the previous ones, and reports why the batch
+    /// can't be applied.
+    pub fn build(
+        parameters: &Parameters,
+        state: &State,
+        transactions: &[Transaction],
+    ) -> Result<Self, WitnessError> {
+        if transactions.len() != NUM_TX {
+            return Err(WitnessError::WrongBatchSize {
+                expected: NUM_TX,
+                actual: transactions.len(),
+            });
+        }
+        if parameters.canonical_order
+            && !transaction::is_canonically_ordered(parameters, transactions)
+        {
+            return Err(WitnessError::NotCanonicallyOrdered);
+        }
+        if parameters.unique_tx_ids && !transaction::has_unique_ids(parameters, transactions) {
+            return Err(WitnessError::DuplicateTransaction);
+        }
+        let mut state = state.clone();
+        let initial_root = state.root();
+        let pre_epoch = state.epoch;
+        let path = |state: &State, id: AccountId| {
+            state
+                .account_merkle_tree()
+                .generate_proof(id.0 as usize)
+                .unwrap()
+        };
+        let mut sender_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
+        let mut recv_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
+        let mut sender_post_paths = Vec::with_capacity(NUM_TX);
+        let mut recv_post_paths = Vec::with_capacity(NUM_TX);
+        let mut pre_tx_roots = Vec::with_capacity(NUM_TX);
+        let mut post_tx_roots = Vec::with_capacity(NUM_TX);
+        let mut withdrawal_tree = WithdrawalTree::new(parameters, NUM_TX);
+        for (index, tx) in transactions.iter().enumerate() {
+            let invalid = |reason| WitnessError::InvalidTransaction { index, reason };
+            tx.simulate(parameters, &state).map_err(invalid)?;
+            let sender_info = state.id_to_account_info[&tx.sender];
+            // Withdrawals have no recipient account, so we use a placeholder instead.
+            let recipient_info = match state.id_to_account_info.get(&tx.recipient) {
+                Some(info) => *info,
+                None => AccountInformation::new(Default::default()),
+            };
+            pre_tx_roots.push(state.root());
+            sender_pre_tx_info_and_paths.push((sender_info, path(&state, tx.sender)));
+            recv_pre_tx_info_and_paths.push((recipient_info, path(&state, tx.recipient)));
+            state
+                .apply_transaction(parameters, tx)
+                .ok_or_else(|| invalid(SimulationError::Invalid))?;
+            withdrawal_tree.append(tx);
+            post_tx_roots.push(state.root());
+            sender_post_paths.push(path(&state, tx.sender));
+            recv_post_paths.push(path(&state, tx.recipient));
+        }
+
+        // Credit the priority fees to the fee collector, if there is one.
+        let fee_collection = match parameters.fee_collector {
+            Some(collector) => {
+                let collector_info = *state
+                    .id_to_account_info
+                    .get(&collector)
+                    .ok_or(WitnessError::FeeCollectionFailed)?;
+                let collection = (state.base_fee, collector_info, path(&state, collector));
+                state
+                    .collect_fees(parameters, transactions)
+                    .ok_or(WitnessError::FeeCollectionFailed)?;
+                Some(collection)
+            }
+            None => None,
+        };
+
+        // Record the batch in the transaction counter, if it is tracked.
+        let tx_counter = match parameters.max_per_epoch {
+            Some(_) => {
+                let tx_counter = (state.tx_counter, state.prove_tx_counter());
+                state
+                    .record_batch(parameters, NUM_TX as u64)
+                    .ok_or(WitnessError::EpochLimitExceeded)?;
+                Some(tx_counter)
+            }
+            None => None,
+        };
+        state.epoch += 1;
+
+        Ok(Self {
+            initial_root,
+            final_root: state.root(),
+            withdrawal_root: withdrawal_tree.root(),
+            pre_epoch,
+            post_epoch: state.epoch,
+            block_commitment: state.block_commitment,
+            transactions: transactions.to_vec(),
+            sender_pre_tx_info_and_paths,
+            sender_post_paths,
+            recv_pre_tx_info_and_paths,
+            recv_post_paths,
+            pre_tx_roots,
+            post_tx_roots,
+            tx_counter,
+            fee_collection,
+        })
+    }
+
+    /// The rollup that proves the batch with these witnesses, under the ledger
+    /// parameters `ledger_params`. None of the optional features of the circuit
+    /// is set.
+    pub fn into_rollup(self, ledger_params: Parameters) -> Rollup<NUM_TX> {
+        let (tx_counter, tx_counter_path) = self.tx_counter.unzip();
+        let (base_fee, fee_collector_info_and_path) = self
+            .fee_collection
+            .map(|(base_fee, info, path)| (base_fee, (info, path)))
+            .unzip();
+        Rollup {
+            initial_root: Some(self.initial_root),
+            final_root: Some(self.final_root),
+            withdrawal_root: Some(self.withdrawal_root),
+            pre_epoch: Some(self.pre_epoch),
+            post_epoch: Some(self.post_epoch),
+            block_commitment: Some(self.block_commitment),
+            transactions: Some(self.transactions),
+            sender_pre_tx_info_and_paths: Some(self.sender_pre_tx_info_and_paths),
+            sender_post_paths: Some(self.sender_post_paths),
+            recv_pre_tx_info_and_paths: Some(self.recv_pre_tx_info_and_paths),
+            recv_post_paths: Some(self.recv_post_paths),
+            pre_tx_roots: Some(self.pre_tx_roots),
+            post_tx_roots: Some(self.post_tx_roots),
+            tx_counter,
+            tx_counter_path,
+            base_fee,
+            fee_collector_info_and_path,
+            ..Rollup::new_empty(ledger_params)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments::ledger::Amount;
+
+    #[test]
+    fn minimal_witnesses_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, Amount(10)).unwrap();
+
+        // Alice and Bob pay each other, so both transactions touch the same accounts.
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+        ];
+        let witness = minimal_witnesses(&pp, &state, &transactions).unwrap();
+        let mut rollup =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &transactions, &mut state, true)
+                .unwrap();
+        // Each transaction needs four paths, but the paths after the first one are
+        // those before the second one.
+        assert_eq!(witness.paths.len(), 6);
+        assert!(witness.paths.len() < 4 * transactions.len());
+        assert_eq!(witness.sender_paths[0].post, witness.recv_paths[1].pre);
+        assert_eq!(witness.recv_paths[0].post, witness.sender_paths[1].pre);
+
+        // The shared paths still satisfy the circuit.
+        rollup.sender_pre_tx_info_and_paths = None;
+        rollup.recv_pre_tx_info_and_paths = None;
+        rollup.sender_post_paths = None;
+        rollup.recv_post_paths = None;
+        witness.fill(&mut rollup);
+        assert_eq!(rollup.check_satisfied(), Ok(()));
+
+        // A transaction that can't be applied is reported, rather than witnessed
+        // against a state that it doesn't update.
+        let overdraft =
+            Transaction::create(&pp, alice_id, bob_id, Amount(100), &alice_sk, &mut rng);
+        assert_eq!(
+            minimal_witnesses(&pp, &state, &[overdraft]).err(),
+            Some(WitnessError::InvalidTransaction {
+                index: 0,
+                reason: SimulationError::InsufficientFunds
+            })
+        );
+    }
+
+    #[test]
+    fn rollup_witness_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Bob can only pay Alice back with what she sent him before.
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, alice_id, Amount(3), &bob_sk, &mut rng),
+        ];
+        let pre_root = state.root();
+        let witness = RollupWitness::<2>::build(&pp, &state, &transactions).unwrap();
+        assert_eq!(state.root(), pre_root);
+        assert_eq!(witness.pre_tx_roots[0], witness.initial_root);
+        assert_eq!(witness.pre_tx_roots[1], witness.post_tx_roots[0]);
+        assert_eq!(witness.post_tx_roots[1], witness.final_root);
+        let rollup = witness.into_rollup(pp.clone());
+        assert_eq!(rollup.check_satisfied(), Ok(()));
+        // Bob's payment is only valid after Alice's, and so the whole batch can't
+        // be validated against the initial state.
+        assert!(Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &transactions,
+            &mut state.clone(),
+            true
+        )
+        .is_none());
+        for tx in &transactions {
+            state.apply_transaction(&pp, tx).unwrap();
+        }
+        assert_eq!(rollup.final_root, Some(state.root()));
+
+        // A transaction that the previous ones make invalid is reported.
+        let overdraft = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng),
+        ];
+        assert_eq!(
+            RollupWitness::<2>::build(&pp, &state, &overdraft).err(),
+            Some(WitnessError::InvalidTransaction {
+                index: 1,
+                reason: SimulationError::InsufficientFunds
+            })
+        );
+        assert_eq!(
+            RollupWitness::<1>::build(&pp, &state, &transactions).err(),
+            Some(WitnessError::WrongBatchSize {
+                expected: 1,
+                actual: 2
+            })
+        );
+    }
+}
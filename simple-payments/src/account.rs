@@ -1,12 +1,109 @@
+use crate::allowlist::AllowlistCommitment;
 use crate::ledger::*;
-use crate::signature::schnorr;
+use crate::metadata::MetadataHash;
+use crate::multisig::SignerSetCommitment;
+use crate::signature::{
+    schnorr::{self, Schnorr, SchnorrPoint},
+    SignatureScheme,
+};
+use ark_crypto_primitives::crh::CRH;
 use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use ark_std::rand::Rng;
 
 /// Account public key used to verify transaction signatures.
 pub type AccountPublicKey = schnorr::PublicKey<EdwardsProjective>;
 /// Account secret key used to create transaction signatures.
 pub type AccountSecretKey = schnorr::SecretKey<EdwardsProjective>;
 
+/// The length of the packed encoding of account information, which fills the
+/// whole input of `AccountHash`. See `AccountInformation::to_bytes_le`.
+pub const PACKED_ACCOUNT_INFO_LEN: usize = <AccountHash as CRH>::INPUT_SIZE_BITS / 8;
+
+/// The last byte of the packed encoding of account information, which marks
+/// where it ends.
+pub const PACKED_ACCOUNT_INFO_END: u8 = 1;
+
+/// The bit that marks the last byte of an account commitment in the leaf of the
+/// account, see `mark_commitment`.
+pub const ACCOUNT_LEAF_MARKER: u8 = 0x80;
+
+/// Return the bytes of the account commitment `commitment`, as stored in the
+/// leaf of the account, with `ACCOUNT_LEAF_MARKER` set in the last byte.
+/// Commitments are elements of a 255-bit field, and so that bit is otherwise
+/// unset, and the marked bytes are never all zero. Hence, the leaf of an account
+/// is never the blank leaf of an empty slot, even if its information commits to
+/// zero.
+pub fn mark_commitment(commitment: &AccountCommitment) -> Vec<u8> {
+    let mut bytes = ark_ff::to_bytes![commitment].unwrap();
+    *bytes.last_mut().unwrap() |= ACCOUNT_LEAF_MARKER;
+    bytes
+}
+
+/// Account signature, encoded as specified by `Parameters::signature_encoding`.
+#[derive(Clone, Debug)]
+pub enum AccountSignature {
+    /// A signature in the `SignatureEncoding::Challenge` encoding.
+    Challenge(schnorr::Signature<EdwardsProjective>),
+    /// A signature in the `SignatureEncoding::Point` encoding.
+    Point(schnorr::PointSignature<EdwardsProjective>),
+}
+
+impl Default for AccountSignature {
+    fn default() -> Self {
+        Self::Challenge(Default::default())
+    }
+}
+
+impl AccountSignature {
+    /// Sign `message` with `sk`, in the encoding specified by `parameters`.
+    pub fn sign<R: Rng>(
+        parameters: &Parameters,
+        sk: &AccountSecretKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Self {
+        let pp = &parameters.sig_params;
+        match parameters.signature_encoding {
+            SignatureEncoding::Challenge => {
+                Self::Challenge(Schnorr::sign(pp, sk, message, rng).unwrap())
+            }
+            SignatureEncoding::Point => {
+                Self::Point(SchnorrPoint::sign(pp, sk, message, rng).unwrap())
+            }
+        }
+    }
+
+    /// Verify the signature over `message` against `pub_key`. Signatures that are
+    /// not in the encoding specified by `parameters` are rejected.
+    pub fn verify(
+        &self,
+        parameters: &Parameters,
+        pub_key: &AccountPublicKey,
+        message: &[u8],
+    ) -> bool {
+        let pp = &parameters.sig_params;
+        match (parameters.signature_encoding, self) {
+            (SignatureEncoding::Challenge, Self::Challenge(signature)) => {
+                Schnorr::verify(pp, pub_key, message, signature).unwrap()
+            }
+            (SignatureEncoding::Point, Self::Point(signature)) => {
+                SchnorrPoint::verify(pp, pub_key, message, signature).unwrap()
+            }
+            _ => false,
+        }
+    }
+
+    /// Convert the signature to bytes. The encoding isn't included, since it is
+    /// fixed by the ledger parameters.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        match self {
+            Self::Challenge(signature) => ark_ff::to_bytes![signature].unwrap(),
+            Self::Point(signature) => ark_ff::to_bytes![signature].unwrap(),
+        }
+    }
+}
+
 /// Account identifier. This prototype supports only 256 accounts at a time.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Debug)]
 pub struct AccountId(pub u8);
@@ -18,6 +115,40 @@ impl AccountId {
     }
 }
 
+/// Prefix of the input hashed to map a handle to an account identifier. This
+/// separates handles from account commitments, which are computed with the same
+/// hash.
+pub const HANDLE_TAG: &[u8] = b"handle";
+
+/// The maximum length in bytes of a handle that can be mapped to an account
+/// identifier.
+pub const MAX_HANDLE_LEN: usize = 128;
+
+impl AccountId {
+    /// Map the human-friendly handle `handle`, e.g., an email address, to an
+    /// account identifier, by hashing its UTF-8 encoding into the identifier
+    /// space. Returns `None` if the normalized handle is longer than
+    /// `MAX_HANDLE_LEN` bytes.
+    ///
+    /// The handle is normalized first, by trimming the surrounding whitespace and
+    /// lowercasing it, so that `"Alice@Example.com "` and `"alice@example.com"`
+    /// map to the same account. Since there are only 256 identifiers, distinct
+    /// handles may still collide.
+    pub fn from_handle(handle: &str, parameters: &Parameters) -> Option<Self> {
+        let handle = handle.trim().to_lowercase();
+        if handle.len() > MAX_HANDLE_LEN {
+            return None;
+        }
+        // The hashed input consists of
+        // (Tag || HandleLength || Handle)
+        let mut input = HANDLE_TAG.to_vec();
+        input.push(handle.len() as u8);
+        input.extend(handle.as_bytes());
+        let hash = AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap();
+        Some(Self(ark_ff::to_bytes![hash].unwrap()[0]))
+    }
+}
+
 impl AccountId {
     /// Increment the identifier in place.
     pub(crate) fn checked_increment(&mut self) -> Option<()> {
@@ -25,18 +156,220 @@ impl AccountId {
     }
 }
 
-/// Information about the account, such as the balance and the associated public key.
-#[derive(Hash, Eq, PartialEq, Copy, Clone)]
+/// A commitment to the information of an account. The account Merkle tree stores
+/// this commitment, rather than the full information, in the leaf of the account.
+pub type AccountCommitment = <AccountHash as CRH>::Output;
+
+/// Information about the account, such as the balances and the associated public key.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
 pub struct AccountInformation {
     /// The account public key.
     pub public_key: AccountPublicKey,
-    /// The balance associated with this this account.
-    pub balance: Amount,
+    /// The key that may replace `public_key` if it is lost, or the identity if
+    /// the account has none. See `rotation::RotateKey`.
+    pub recovery_key: AccountPublicKey,
+    /// The balance of each asset associated with this this account.
+    pub balances: Balances,
+    /// The number of transactions and swaps authorized by this account.
+    pub nonce: u64,
+    /// The amount sent by this account during the epoch `limit_epoch`. This is
+    /// only tracked if the ledger limits how much each account can send per epoch.
+    pub spent_today: Amount,
+    /// The epoch during which `spent_today` was sent.
+    pub limit_epoch: u64,
+    /// The amount of the native asset that is locked in escrow. Held funds are
+    /// still part of `balances`, but they can't be spent until they are released.
+    pub held: Amount,
+    /// The total amount spent from this account by its delegates. This is only
+    /// tracked if the ledger enables delegation. See `delegation`.
+    pub delegated_spent: Amount,
+    /// The highest balance of any asset that a transfer may credit this account
+    /// up to, or zero if it is uncapped. This is only checked if the ledger
+    /// enables balance caps.
+    pub balance_cap: Amount,
+    /// The index of the key derived from `public_key` that authorized the last
+    /// transaction of this account. This is only tracked if the ledger derives a
+    /// fresh key per transaction.
+    pub key_index: u64,
+    /// The epoch during which this account was last credited by a transfer. This
+    /// is only tracked if the ledger delays withdrawals after credits.
+    pub last_credited_epoch: u64,
+    /// The commitment to the allowlist of the recipients that the account may
+    /// pay, or zero if it may pay anybody. This is only checked if the ledger
+    /// enables allowlists. See `allowlist`.
+    pub allowlist: AllowlistCommitment,
+    /// The hash of the metadata that applications attach to the account, or zero
+    /// if it has none. See `metadata`.
+    pub metadata_hash: MetadataHash,
+    /// The commitment to the signers of the account if it is a multisig account,
+    /// and zero otherwise. See `multisig::SignerSet`.
+    pub signers: SignerSetCommitment,
+    /// The number of signers that must authorize a transaction of a multisig
+    /// account, or zero if the account is not a multisig account.
+    pub threshold: u8,
+    /// Whether the account accepts transfers without the consent of its owner.
+    /// This is only checked if the ledger requires consent.
+    pub accepts_transfers: bool,
 }
 
 impl AccountInformation {
-    /// Convert the account information to bytes.
+    /// Create the information of a fresh account with public key `public_key`,
+    /// which holds no funds.
+    pub fn new(public_key: AccountPublicKey) -> Self {
+        Self {
+            public_key,
+            recovery_key: AccountPublicKey::default(),
+            balances: Balances::default(),
+            nonce: 0,
+            spent_today: Amount(0),
+            limit_epoch: 0,
+            held: Amount(0),
+            delegated_spent: Amount(0),
+            balance_cap: Amount(0),
+            key_index: 0,
+            last_credited_epoch: 0,
+            allowlist: AllowlistCommitment::default(),
+            metadata_hash: MetadataHash::default(),
+            signers: SignerSetCommitment::default(),
+            threshold: 0,
+            accepts_transfers: false,
+        }
+    }
+
+    /// Return the balance of `asset` that can be spent, i.e., the balance
+    /// excluding the held funds, or `None` if `asset` is not supported.
+    pub fn available_balance(&self, asset: AssetId) -> Option<Amount> {
+        let balance = self.balances.get(asset)?;
+        if asset == AssetId::NATIVE {
+            balance.checked_sub(self.held)
+        } else {
+            Some(balance)
+        }
+    }
+
+    /// Return the amount sent by this account so far during the epoch `epoch`.
+    pub fn spent_in(&self, epoch: u64) -> Amount {
+        if self.limit_epoch == epoch {
+            self.spent_today
+        } else {
+            Amount(0)
+        }
+    }
+
+    /// Have at least `grace` epochs passed since this account was last credited,
+    /// as of the epoch `epoch`?
+    pub fn is_past_grace(&self, epoch: u64, grace: u64) -> bool {
+        epoch
+            .checked_sub(self.last_credited_epoch)
+            .is_some_and(|elapsed| elapsed >= grace)
+    }
+
+    /// Convert the account information to bytes, in its canonical packed form.
+    /// Every field has a fixed width, and the fields are followed by zero bytes
+    /// and then by `PACKED_ACCOUNT_INFO_END`, so that the encoding fills the
+    /// whole input of `AccountHash`. Since `AccountHash` pads shorter inputs with
+    /// zeros, any other byte string commits to a different account.
     pub fn to_bytes_le(&self) -> Vec<u8> {
-        ark_ff::to_bytes![self.public_key, self.balance.to_bytes_le()].unwrap()
+        let mut bytes = ark_ff::to_bytes![
+            self.balances.to_bytes_le(),
+            self.nonce.to_le_bytes(),
+            self.public_key,
+            self.recovery_key,
+            self.spent_today.to_bytes_le(),
+            self.limit_epoch.to_le_bytes(),
+            self.held.to_bytes_le(),
+            self.delegated_spent.to_bytes_le(),
+            self.balance_cap.to_bytes_le(),
+            self.key_index.to_le_bytes(),
+            self.last_credited_epoch.to_le_bytes(),
+            self.allowlist,
+            self.metadata_hash,
+            self.signers,
+            self.threshold,
+            self.accepts_transfers as u8
+        ]
+        .unwrap();
+        assert!(bytes.len() < PACKED_ACCOUNT_INFO_LEN);
+        bytes.resize(PACKED_ACCOUNT_INFO_LEN - 1, 0);
+        bytes.push(PACKED_ACCOUNT_INFO_END);
+        bytes
+    }
+
+    /// Commit to the account information.
+    pub fn commitment(&self, crh_params: &<AccountHash as CRH>::Parameters) -> AccountCommitment {
+        AccountHash::evaluate(crh_params, &self.to_bytes_le()).unwrap()
+    }
+
+    /// Return the leaf of the account Merkle tree that stores this account
+    /// information, i.e., the marked bytes of its commitment, see
+    /// `mark_commitment`, personalized as `ledger::personalize_leaf` does.
+    pub fn to_leaf(&self, parameters: &Parameters) -> Vec<u8> {
+        personalize_leaf(
+            parameters.leaf_personalization,
+            LeafDomain::Account,
+            mark_commitment(&self.commitment(&parameters.account_crh_params)),
+        )
+    }
+}
+
+// The serialization of account information, which persists it along with the rest
+// of the `State`, see `State::save`. Unlike `to_bytes_le`, this is not what the
+// account tree commits to, and so it can change without changing the roots.
+impl CanonicalSerialize for AccountInformation {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.public_key.serialize(&mut writer)?;
+        self.recovery_key.serialize(&mut writer)?;
+        for balance in &self.balances.0 {
+            balance.0.serialize(&mut writer)?;
+        }
+        for value in [
+            self.nonce,
+            self.spent_today.0,
+            self.limit_epoch,
+            self.held.0,
+            self.delegated_spent.0,
+            self.balance_cap.0,
+            self.key_index,
+            self.last_credited_epoch,
+        ] {
+            value.serialize(&mut writer)?;
+        }
+        for hash in [self.allowlist, self.metadata_hash, self.signers] {
+            hash.serialize(&mut writer)?;
+        }
+        self.threshold.serialize(&mut writer)?;
+        self.accepts_transfers.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        2 * self.public_key.serialized_size()
+            + (NUM_ASSETS + 8) * 0u64.serialized_size()
+            + 3 * self.allowlist.serialized_size()
+            + self.threshold.serialized_size()
+            + self.accepts_transfers.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for AccountInformation {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let mut info = Self::new(AccountPublicKey::deserialize(&mut reader)?);
+        info.recovery_key = AccountPublicKey::deserialize(&mut reader)?;
+        for balance in &mut info.balances.0 {
+            *balance = Amount(u64::deserialize(&mut reader)?);
+        }
+        info.nonce = u64::deserialize(&mut reader)?;
+        info.spent_today = Amount(u64::deserialize(&mut reader)?);
+        info.limit_epoch = u64::deserialize(&mut reader)?;
+        info.held = Amount(u64::deserialize(&mut reader)?);
+        info.delegated_spent = Amount(u64::deserialize(&mut reader)?);
+        info.balance_cap = Amount(u64::deserialize(&mut reader)?);
+        info.key_index = u64::deserialize(&mut reader)?;
+        info.last_credited_epoch = u64::deserialize(&mut reader)?;
+        info.allowlist = AllowlistCommitment::deserialize(&mut reader)?;
+        info.metadata_hash = MetadataHash::deserialize(&mut reader)?;
+        info.signers = SignerSetCommitment::deserialize(&mut reader)?;
+        info.threshold = u8::deserialize(&mut reader)?;
+        info.accepts_transfers = bool::deserialize(&mut reader)?;
+        Ok(info)
     }
 }
@@ -0,0 +1,127 @@
+//! Allowlists of controlled-payment accounts, which may only pay the recipients
+//! that their owner allowed beforehand.
+//!
+//! An allowlist is a `SortedIdTree` of height `ALLOWLIST_HEIGHT` storing the
+//! identifiers of the allowed recipients, and an account commits to it by
+//! storing its root. Accounts whose commitment is zero have no allowlist, and
+//! allowlists are only checked if the ledger enables them.
+
+use crate::account::{AccountId, AccountInformation};
+use crate::ledger::{self, sorted_leaf, AccPath, AccRoot, SortedIdTree};
+use crate::transaction::Transaction;
+use std::fmt;
+
+/// The height of allowlists, which is enough to allow every account.
+pub const ALLOWLIST_HEIGHT: usize = 9;
+
+/// A commitment to an allowlist, i.e., its root, or zero for no allowlist.
+pub type AllowlistCommitment = AccRoot;
+
+/// A proof that the recipient of a transaction is on the allowlist of its
+/// sender, i.e., the path of the leaf that stores the recipient's identifier.
+#[derive(Clone)]
+pub struct AllowlistProof(pub AccPath);
+
+impl fmt::Debug for AllowlistProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AllowlistProof")
+            .field("leaf_index", &self.0.leaf_index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AllowlistProof {
+    /// The stand-in for the proof of transactions whose sender has no
+    /// allowlist, which has the shape of a real proof.
+    pub fn placeholder() -> Self {
+        Self(AccPath {
+            leaf_sibling_hash: Default::default(),
+            auth_path: vec![Default::default(); ALLOWLIST_HEIGHT - 2],
+            leaf_index: 0,
+        })
+    }
+}
+
+/// Create the allowlist of the recipients `recipients`, in any order.
+pub fn allowlist(parameters: &ledger::Parameters, recipients: &[AccountId]) -> SortedIdTree {
+    SortedIdTree::new(parameters, ALLOWLIST_HEIGHT, recipients)
+        .expect("every account fits in an allowlist")
+}
+
+impl Transaction {
+    /// Attach the proof that the recipient is on the allowlist `allowlist` of
+    /// the sender. Like the receipt, the proof is not signed by the sender.
+    /// Returns `None` if the recipient is not on the allowlist.
+    pub fn with_allowlist_proof(mut self, allowlist: &SortedIdTree) -> Option<Self> {
+        let path = allowlist.prove_membership(self.recipient)?;
+        self.allowlist_proof = Some(AllowlistProof(path));
+        Some(self)
+    }
+
+    /// Is the recipient allowed by the sender, whose information before the
+    /// transaction is `sender_info`? Either the sender has no allowlist, or the
+    /// transaction proves that the recipient is on it. This always holds if the
+    /// ledger doesn't enable allowlists. Withdrawals are only allowed if
+    /// `WITHDRAWAL_ACCOUNT` is on the allowlist.
+    pub(crate) fn is_allowed_by(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+    ) -> bool {
+        if !parameters.allowlists || sender_info.allowlist == AllowlistCommitment::default() {
+            return true;
+        }
+        self.allowlist_proof.as_ref().is_some_and(|proof| {
+            proof
+                .0
+                .verify(
+                    &parameters.leaf_crh_params,
+                    &parameters.two_to_one_crh_params,
+                    &sender_info.allowlist,
+                    &sorted_leaf(self.recipient.0 as u64),
+                )
+                .unwrap_or(false)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, Parameters, State};
+
+    #[test]
+    fn allowlist_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.allowlists = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Without an allowlist, Alice may pay anybody.
+        let to_carol = Transaction::create(&pp, alice_id, carol_id, Amount(5), &alice_sk, &mut rng);
+        assert!(to_carol.validate(&pp, &state));
+
+        // Once Alice only allows Bob, she may only pay Bob, ...
+        let list = allowlist(&pp, &[bob_id]);
+        state.set_allowlist(alice_id, list.root()).unwrap();
+        let to_bob = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(!to_bob.validate(&pp, &state));
+        let to_bob = to_bob.with_allowlist_proof(&list).unwrap();
+        assert!(to_bob.validate(&pp, &state));
+        // ... and Carol's payment can't be proven allowed, ...
+        assert!(to_carol.clone().with_allowlist_proof(&list).is_none());
+        let mut forged = to_carol.clone();
+        forged.allowlist_proof = to_bob.allowlist_proof.clone();
+        assert!(!forged.validate(&pp, &state));
+        // ... unless the ledger doesn't enforce allowlists.
+        let mut lax_pp = pp.clone();
+        lax_pp.allowlists = false;
+        assert!(to_carol.is_allowed_by(&lax_pp, &state.id_to_account_info[&alice_id]));
+    }
+}
@@ -0,0 +1,47 @@
+//! Ledgers that keep each asset in an account tree of its own, rather than the
+//! balances of all the assets in a single tree. Each tree is a `State` of its
+//! own, whose accounts only hold the balance of its asset, and a transaction
+//! only touches the tree of the asset that it transfers.
+//!
+//! The trees are committed to as a whole by the digest of their roots, see
+//! `asset_roots_digest`, which takes the place of the root of the single tree.
+
+use crate::ledger::{AccRoot, AccountHash, Parameters};
+use ark_crypto_primitives::crh::CRH;
+
+/// The tag that separates the digests of the roots of the asset trees from
+/// other uses of `AccountHash`.
+pub const ASSET_ROOTS_TAG: &[u8] = b"asset-roots";
+
+/// Commit to the roots `roots` of the account trees of the assets, in order of
+/// asset identifier.
+pub fn asset_roots_digest(parameters: &Parameters, roots: &[AccRoot]) -> AccRoot {
+    // The hashed input consists of (Tag || Root_0 || ... || Root_n)
+    let mut input = ASSET_ROOTS_TAG.to_vec();
+    input.extend(ark_ff::to_bytes![roots.to_vec()].unwrap());
+    AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, State};
+
+    #[test]
+    fn asset_roots_digest_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let empty_root = state.root();
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let root = state.root();
+
+        // The digest commits to the root of each tree, and to its asset.
+        let digest = asset_roots_digest(&pp, &[root, empty_root]);
+        assert_ne!(digest, asset_roots_digest(&pp, &[empty_root, root]));
+        assert_ne!(digest, asset_roots_digest(&pp, &[root, root]));
+        assert_eq!(digest, asset_roots_digest(&pp, &[root, empty_root]));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::account::{AccountPublicKey, AccountSecretKey, AccountSignature};
+use crate::ledger::{
+    personalize_leaf, AccPath, AccRoot, AccountHash, LeafDomain, MerkleConfig, Parameters,
+};
+use crate::transaction::{Transaction, TransactionHash};
+use ark_crypto_primitives::crh::CRH;
+use ark_crypto_primitives::merkle_tree::MerkleTree;
+use ark_std::rand::Rng;
+
+/// The root of a batch Merkle tree, which commits to the transactions of a batch.
+pub type BatchRoot = AccRoot;
+/// A membership proof for a given transaction in a batch.
+pub type BatchPath = AccPath;
+
+/// Convert the hash of a transaction to the leaf that stores it.
+fn leaf(parameters: &Parameters, hash: &TransactionHash) -> Vec<u8> {
+    personalize_leaf(
+        parameters.leaf_personalization,
+        LeafDomain::Transaction,
+        ark_ff::to_bytes![hash].unwrap(),
+    )
+}
+
+/// A Merkle tree of the hashes of the transactions in a batch, so that each
+/// sender can prove that their transaction was included in the batch.
+///
+/// The tree has one leaf per transaction in the batch: the `i`-th leaf stores the
+/// hash of the `i`-th transaction, and the leaves past the end of the batch store
+/// the zero hash.
+#[derive(Clone)]
+pub struct BatchTree {
+    tree: MerkleTree<MerkleConfig>,
+    num_transactions: usize,
+}
+
+impl BatchTree {
+    /// Return the number of leaves in the tree of a batch of `num_transactions`
+    /// transactions. Merkle trees need a power of two number of leaves, and at
+    /// least two of them.
+    pub fn num_leaves(num_transactions: usize) -> usize {
+        num_transactions.next_power_of_two().max(2)
+    }
+
+    /// Create the tree of the batch `transactions`.
+    pub fn from_transactions(parameters: &Parameters, transactions: &[Transaction]) -> Self {
+        let mut leaves = transactions
+            .iter()
+            .map(|tx| leaf(parameters, &tx.hash(parameters)))
+            .collect::<Vec<_>>();
+        leaves.resize(
+            Self::num_leaves(transactions.len()),
+            leaf(parameters, &TransactionHash::default()),
+        );
+        let tree = MerkleTree::new(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &leaves,
+        )
+        .unwrap();
+        Self {
+            tree,
+            num_transactions: transactions.len(),
+        }
+    }
+
+    /// Return the root of the batch Merkle tree.
+    pub fn root(&self) -> BatchRoot {
+        self.tree.root()
+    }
+
+    /// Produce a membership proof for the transaction at `index` in the batch.
+    /// Returns `None` if the batch has no transaction at `index`.
+    pub fn generate_proof(&self, index: usize) -> Option<BatchPath> {
+        (index < self.num_transactions).then(|| self.tree.generate_proof(index).unwrap())
+    }
+}
+
+/// Check that `path` proves that `tx` is in the batch with root `root`.
+pub fn verify_tx_in_batch(
+    parameters: &Parameters,
+    root: &BatchRoot,
+    tx: &Transaction,
+    path: &BatchPath,
+) -> bool {
+    path.verify(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        root,
+        &leaf(parameters, &tx.hash(parameters)),
+    )
+    .unwrap()
+}
+
+/// The tag that separates the output commitments of batches from other uses of
+/// `AccountHash`.
+pub const BATCH_OUTPUT_TAG: &[u8] = b"batch-output";
+
+/// A commitment to the output of a batch, which chains the batch to the ones
+/// before it.
+pub type BatchCommitment = <AccountHash as CRH>::Output;
+
+/// Commit to the output of a batch that leaves the account tree with root
+/// `root` at the epoch `epoch`, and extends the chain of batches whose last
+/// output commitment is `prev`. The chain starts from a commitment to the
+/// initial state of the ledger, which extends zero:
+/// C_i = H(Tag || C_{i-1} || Root_i || Epoch_i)
+/// Each batch must then start from the root and the epoch committed to by the
+/// previous one, so that the chain can't skip or fork.
+pub fn batch_output_commitment(
+    parameters: &Parameters,
+    prev: &BatchCommitment,
+    root: &AccRoot,
+    epoch: u64,
+) -> BatchCommitment {
+    // The committed input consists of
+    // (Tag || PreviousCommitment || Root || Epoch)
+    let mut input = BATCH_OUTPUT_TAG.to_vec();
+    input.extend(ark_ff::to_bytes![prev, root].unwrap());
+    input.extend(epoch.to_le_bytes());
+    AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+}
+
+/// Prefix of the message that a sequencer signs to authorize a batch. This
+/// separates authorizations from the messages of other operations, so that their
+/// signatures can't be reused for one another.
+pub const BATCH_AUTHORIZATION_TAG: &[u8] = b"batch-authorization";
+
+/// Construct the message that a sequencer signs to authorize the batch of
+/// `transactions` that takes the account tree from the root `initial_root` to
+/// the root `final_root`.
+fn authorization_message(
+    parameters: &Parameters,
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    transactions: &[Transaction],
+) -> Vec<u8> {
+    // The signed message consists of
+    // (Tag || InitialRoot || FinalRoot || TxHash_1 || ... || TxHash_n)
+    let mut message = BATCH_AUTHORIZATION_TAG.to_vec();
+    message.extend(ark_ff::to_bytes![initial_root, final_root].unwrap());
+    for tx in transactions {
+        message.extend(ark_ff::to_bytes![tx.hash(parameters)].unwrap());
+    }
+    parameters.message_encoding.encode(message)
+}
+
+/// Sign the authorization of the batch of `transactions` that takes the account
+/// tree from the root `initial_root` to the root `final_root` with the
+/// sequencer's key `sequencer_sk`, so that only batches submitted by the
+/// sequencer are accepted.
+pub fn sign_batch<R: Rng>(
+    parameters: &Parameters,
+    sequencer_sk: &AccountSecretKey,
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    transactions: &[Transaction],
+    rng: &mut R,
+) -> AccountSignature {
+    let message = authorization_message(parameters, initial_root, final_root, transactions);
+    AccountSignature::sign(parameters, sequencer_sk, &message, rng)
+}
+
+/// Verify that `signature` is signed by `sequencer_key`, and authorizes the
+/// batch of `transactions` that takes the account tree from the root
+/// `initial_root` to the root `final_root`.
+pub fn verify_batch_authorization(
+    parameters: &Parameters,
+    sequencer_key: &AccountPublicKey,
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    transactions: &[Transaction],
+    signature: &AccountSignature,
+) -> bool {
+    let message = authorization_message(parameters, initial_root, final_root, transactions);
+    signature.verify(parameters, sequencer_key, &message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, State};
+
+    #[test]
+    fn batch_proof_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let txs = (1..=3)
+            .map(|amount| {
+                Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng)
+            })
+            .collect::<Vec<_>>();
+
+        let tree = BatchTree::from_transactions(&pp, &txs);
+        for (i, tx) in txs.iter().enumerate() {
+            let path = tree.generate_proof(i).unwrap();
+            assert!(verify_tx_in_batch(&pp, &tree.root(), tx, &path));
+        }
+        // There is no proof for the padding at the end of the batch.
+        assert!(tree.generate_proof(3).is_none());
+        // A proof only works for the transaction at its own position.
+        let path = tree.generate_proof(0).unwrap();
+        assert!(!verify_tx_in_batch(&pp, &tree.root(), &txs[1], &path));
+        // A transaction that is not in the batch has no valid proof.
+        let absent = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(!(0..3).any(|i| {
+            verify_tx_in_batch(&pp, &tree.root(), &absent, &tree.generate_proof(i).unwrap())
+        }));
+    }
+
+    #[test]
+    fn batch_authorization_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (_sequencer_id, sequencer_pk, sequencer_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let txs = vec![Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            &alice_sk,
+            &mut rng,
+        )];
+        let initial_root = state.root();
+        state.apply_transaction(&pp, &txs[0]).unwrap();
+        let final_root = state.root();
+
+        let signature = sign_batch(
+            &pp,
+            &sequencer_sk,
+            &initial_root,
+            &final_root,
+            &txs,
+            &mut rng,
+        );
+        assert!(verify_batch_authorization(
+            &pp,
+            &sequencer_pk,
+            &initial_root,
+            &final_root,
+            &txs,
+            &signature
+        ));
+        // The authorization only holds for the batch that it was signed for, ...
+        assert!(!verify_batch_authorization(
+            &pp,
+            &sequencer_pk,
+            &initial_root,
+            &initial_root,
+            &txs,
+            &signature
+        ));
+        assert!(!verify_batch_authorization(
+            &pp,
+            &sequencer_pk,
+            &initial_root,
+            &final_root,
+            &[],
+            &signature
+        ));
+        // ... and only if the sequencer signed it.
+        let forged = sign_batch(&pp, &bob_sk, &initial_root, &final_root, &txs, &mut rng);
+        assert!(!verify_batch_authorization(
+            &pp,
+            &sequencer_pk,
+            &initial_root,
+            &final_root,
+            &txs,
+            &forged
+        ));
+        assert!(verify_batch_authorization(
+            &pp,
+            &bob_pk,
+            &initial_root,
+            &final_root,
+            &txs,
+            &forged
+        ));
+    }
+}
@@ -0,0 +1,93 @@
+//! Consent, which the recipient of a transfer signs to accept it, so that spam
+//! can't be sent to accounts that haven't opted in to receiving transfers.
+//! Consent is only required if the ledger requires it, and only for recipients
+//! that don't accept transfers.
+
+use crate::account::{AccountInformation, AccountSecretKey, AccountSignature};
+use crate::ledger;
+use crate::transaction::Transaction;
+use ark_std::rand::Rng;
+
+/// Prefix of the message signed to consent to a transfer. This separates consent
+/// from the messages of other operations, and in particular from the signatures
+/// of senders over the same transaction.
+pub const CONSENT_TAG: &[u8] = b"consent";
+
+impl Transaction {
+    /// Construct the message that the recipient signs to consent to the transfer.
+    fn consent_message(&self, parameters: &ledger::Parameters) -> Vec<u8> {
+        // The signed message consists of
+        // (Tag || TransactionMessage), where the transaction message is the one
+        // signed by the sender, before it is encoded.
+        let mut message = CONSENT_TAG.to_vec();
+        message.extend(self.raw_message());
+        parameters.message_encoding.encode(message)
+    }
+
+    /// Sign the consent to the transfer with the recipient's key `recipient_sk`.
+    pub fn sign_consent<R: Rng>(
+        &self,
+        parameters: &ledger::Parameters,
+        recipient_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> AccountSignature {
+        let message = self.consent_message(parameters);
+        AccountSignature::sign(parameters, recipient_sk, &message, rng)
+    }
+
+    /// Is the transfer accepted by the recipient, whose information before the
+    /// transaction is `recipient_info`? Either the recipient accepts transfers,
+    /// or the transaction carries its consent, signed under its public key. This
+    /// always holds if the ledger doesn't require consent, and for withdrawals
+    /// and self-transfers.
+    pub(crate) fn has_valid_consent(
+        &self,
+        parameters: &ledger::Parameters,
+        recipient_info: Option<&AccountInformation>,
+    ) -> bool {
+        if !parameters.require_consent || self.is_withdrawal() || self.sender == self.recipient {
+            return true;
+        }
+        match (recipient_info, &self.consent) {
+            (Some(info), _) if info.accepts_transfers => true,
+            (Some(info), Some(consent)) => consent.verify(
+                parameters,
+                &info.public_key,
+                &self.consent_message(parameters),
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ledger::{Amount, Parameters, State};
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn consent_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.require_consent = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Bob hasn't opted in, so he must consent to the transfer, ...
+        let mut tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        // ... with his own key, ...
+        tx.consent = Some(tx.sign_consent(&pp, &alice_sk, &mut rng));
+        assert!(!tx.validate(&pp, &state));
+        tx.consent = Some(tx.sign_consent(&pp, &bob_sk, &mut rng));
+        assert!(tx.validate(&pp, &state));
+        // ... unless he accepts transfers.
+        tx.consent = None;
+        state.set_accepts_transfers(bob_id, true).unwrap();
+        assert!(tx.validate(&pp, &state));
+        state.apply_transaction(&pp, &tx).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Delegated spending, with which the owner of an account lets another key, the
+//! delegate, send transactions from the account, up to a cap that the owner
+//! signs.
+//!
+//! The cap bounds the total amount, fees included, that delegates spend from the
+//! account over its lifetime, which the account tracks in
+//! `AccountInformation::delegated_spent`. The owner raises it by signing a new
+//! authorization with a higher cap. Delegates may only spend the native asset,
+//! and delegation is only allowed if the ledger enables it.
+
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+};
+use crate::ledger::{self, Amount, AssetId};
+use crate::transaction::Transaction;
+use ark_std::rand::Rng;
+
+/// Prefix of the message that the owner of an account signs to authorize a
+/// delegate. This separates authorizations from the messages of other
+/// operations, and in particular from the transactions of the owner.
+pub const DELEGATION_TAG: &[u8] = b"delegation";
+
+/// The authorization of a delegate to spend from an account, signed by its owner.
+#[derive(Clone, Debug)]
+pub struct SpendingAuthorization {
+    /// The key that signs the transactions of the delegate.
+    pub delegate: AccountPublicKey,
+    /// The maximum total amount that delegates may spend from the account.
+    pub cap: Amount,
+    /// The signature of the owner over the account, the delegate, and the cap.
+    pub signature: AccountSignature,
+}
+
+impl SpendingAuthorization {
+    /// Construct the message that the owner of the account `owner` signs to let
+    /// `delegate` spend up to `cap` from it.
+    fn message(
+        parameters: &ledger::Parameters,
+        owner: AccountId,
+        delegate: &AccountPublicKey,
+        cap: Amount,
+    ) -> Vec<u8> {
+        // The signed message consists of (Tag || OwnerAccId || Delegate || Cap).
+        let mut message = DELEGATION_TAG.to_vec();
+        message.extend(owner.to_bytes_le());
+        message.extend(ark_ff::to_bytes![delegate].unwrap());
+        message.extend(cap.to_bytes_le());
+        parameters.message_encoding.encode(message)
+    }
+
+    /// Let `delegate` spend up to `cap` from the account `owner`, whose owner
+    /// has the key `owner_sk`.
+    pub fn sign<R: Rng>(
+        parameters: &ledger::Parameters,
+        owner: AccountId,
+        delegate: AccountPublicKey,
+        cap: Amount,
+        owner_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = Self::message(parameters, owner, &delegate, cap);
+        Self {
+            delegate,
+            cap,
+            signature: AccountSignature::sign(parameters, owner_sk, &message, rng),
+        }
+    }
+
+    /// Is this authorization signed by the owner of the account `owner`, whose
+    /// public key is `owner_key`?
+    pub fn verify(
+        &self,
+        parameters: &ledger::Parameters,
+        owner: AccountId,
+        owner_key: &AccountPublicKey,
+    ) -> bool {
+        let message = Self::message(parameters, owner, &self.delegate, self.cap);
+        self.signature.verify(parameters, owner_key, &message)
+    }
+
+    /// The stand-in for the authorization of transactions that are not
+    /// delegated, which has the shape of a real authorization.
+    pub fn placeholder() -> Self {
+        Self {
+            delegate: AccountPublicKey::default(),
+            cap: Amount(0),
+            signature: AccountSignature::default(),
+        }
+    }
+}
+
+impl Transaction {
+    /// Attach the authorization `authorization` of the delegate that signed the
+    /// transaction. Like the receipt, the authorization is not signed by the
+    /// delegate.
+    pub fn with_authorization(mut self, authorization: SpendingAuthorization) -> Self {
+        self.authorization = Some(authorization);
+        self
+    }
+
+    /// Is the transaction sent by a delegate? This only holds if the ledger
+    /// enables delegation and the transaction carries an authorization.
+    pub fn is_delegated(&self, parameters: &ledger::Parameters) -> bool {
+        parameters.delegation && self.authorization.is_some()
+    }
+
+    /// The key that must sign the transaction, whose sender has the information
+    /// `sender_info`: the delegate of a delegated transaction, and the signing
+    /// key of the sender otherwise.
+    pub fn signer_key(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+    ) -> AccountPublicKey {
+        match &self.authorization {
+            Some(authorization) if parameters.delegation => authorization.delegate,
+            _ => sender_info.signing_key(parameters),
+        }
+    }
+
+    /// Does a delegated transaction stay within its authorization, given the
+    /// information `sender_info` of its sender? The authorization must be
+    /// signed by the sender, and the spending of the delegates, including this
+    /// transaction and its fee, must stay within its cap. This always holds for
+    /// transactions that are not delegated.
+    pub(crate) fn is_within_authorization(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+    ) -> bool {
+        let authorization = match &self.authorization {
+            Some(authorization) if parameters.delegation => authorization,
+            _ => return true,
+        };
+        let spent = self
+            .total_cost()
+            .and_then(|cost| sender_info.delegated_spent.checked_add(cost));
+        authorization.verify(parameters, self.sender, &sender_info.public_key)
+            && self.asset_id == AssetId::NATIVE
+            && spent.is_some_and(|spent| spent <= authorization.cap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+
+    #[test]
+    fn delegation_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.delegation = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Dave has no account, but Alice lets him spend up to 10 from hers.
+        let (dave_pk, dave_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let authorization =
+            SpendingAuthorization::sign(&pp, alice_id, dave_pk, Amount(10), &alice_sk, &mut rng);
+        let delegated = |amount, rng: &mut _| {
+            Transaction::create(&pp, alice_id, bob_id, Amount(amount), &dave_sk, rng)
+                .with_authorization(authorization.clone())
+        };
+
+        // Dave's transactions are accepted as long as they stay within the cap, ...
+        for amount in [4, 6] {
+            let tx = delegated(amount, &mut rng);
+            assert!(tx.validate(&pp, &state));
+            state.apply_transaction(&pp, &tx).unwrap();
+        }
+        assert_eq!(
+            state.id_to_account_info[&alice_id].delegated_spent,
+            Amount(10)
+        );
+        // ... but once it is used up, they are rejected, although Alice can
+        // still afford them, ...
+        let tx = delegated(1, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        assert!(state.apply_transaction(&pp, &tx).is_none());
+        // ... until Alice raises the cap.
+        let raised =
+            SpendingAuthorization::sign(&pp, alice_id, dave_pk, Amount(11), &alice_sk, &mut rng);
+        assert!(tx.clone().with_authorization(raised).validate(&pp, &state));
+
+        // Dave can neither authorize himself, nor use Alice's authorization
+        // without signing with his key.
+        let forged =
+            SpendingAuthorization::sign(&pp, alice_id, dave_pk, Amount(100), &dave_sk, &mut rng);
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(1), &dave_sk, &mut rng);
+        assert!(!tx.clone().with_authorization(forged).validate(&pp, &state));
+        let (_mallory_pk, mallory_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(1), &mallory_sk, &mut rng);
+        let raised =
+            SpendingAuthorization::sign(&pp, alice_id, dave_pk, Amount(11), &alice_sk, &mut rng);
+        assert!(!tx.with_authorization(raised).validate(&pp, &state));
+
+        // Alice's own transactions don't count towards the cap.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+    }
+}
@@ -0,0 +1,162 @@
+//! Commitments to the changes that a batch makes to the account tree, for
+//! on-chain contracts that only track what changed, rather than the whole state.
+//!
+//! Each applied transfer updates the leaves of its sender and of its recipient.
+//! The diff commitment is a hash chain over these updates, in the order of the
+//! transactions, which starts from zero:
+//! D_i = H(Tag || D_{i-1} || Sender || Recipient || OldLeaves || NewLeaves)
+
+use crate::account::{mark_commitment, AccountId, AccountInformation};
+use crate::ledger::{self, AccountHash};
+use crate::transaction::Transaction;
+use ark_crypto_primitives::crh::CRH;
+use ark_ff::Zero;
+
+/// The tag that separates diff commitments from other uses of `AccountHash`.
+pub const DIFF_TAG: &[u8] = b"diff";
+
+/// A commitment to the updates that a batch makes to the account tree.
+pub type DiffCommitment = <AccountHash as CRH>::Output;
+
+/// The updates that a transaction makes to the leaves of its sender and of its
+/// recipient. The leaves are listed sender first, and are the marked bytes of
+/// the account commitments, see `account::mark_commitment`, whatever the
+/// personalization of the account tree. See `ledger::personalize_leaf`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeafUpdate {
+    /// The sender, whose identifier is the position of its leaf.
+    pub sender: AccountId,
+    /// The recipient, whose identifier is the position of its leaf.
+    pub recipient: AccountId,
+    /// The leaves of the sender and of the recipient before the transaction.
+    pub old_leaves: [Vec<u8>; 2],
+    /// The leaves of the sender and of the recipient after the transaction.
+    pub new_leaves: [Vec<u8>; 2],
+}
+
+/// The stand-in for the leaves of the recipient of a withdrawal, which has no
+/// account to update.
+pub fn blank_diff_leaf() -> Vec<u8> {
+    vec![0u8; 32]
+}
+
+impl Transaction {
+    /// The updates that the transaction makes to the leaves of accounts with
+    /// information `sender_info` and `recipient_info` before it, during the
+    /// epoch `epoch`. Withdrawals credit nobody, and so both leaves of their
+    /// recipient are `blank_diff_leaf()`. Returns `None` if either balance
+    /// underflows or overflows.
+    pub fn leaf_update(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+        recipient_info: &AccountInformation,
+        epoch: u64,
+    ) -> Option<LeafUpdate> {
+        let (new_sender_info, new_recipient_info) =
+            self.new_account_infos(parameters, sender_info, Some(recipient_info), epoch)?;
+        let leaf = |info: Option<&AccountInformation>| {
+            info.map_or_else(blank_diff_leaf, |info| {
+                mark_commitment(&info.commitment(&parameters.account_crh_params))
+            })
+        };
+        let old_recipient = (!self.is_withdrawal()).then_some(recipient_info);
+        Some(LeafUpdate {
+            sender: self.sender,
+            recipient: self.recipient,
+            old_leaves: [leaf(Some(sender_info)), leaf(old_recipient)],
+            new_leaves: [
+                leaf(Some(&new_sender_info)),
+                leaf(new_recipient_info.as_ref()),
+            ],
+        })
+    }
+}
+
+/// Commit to the leaf updates `updates` of a batch, in their order.
+pub fn diff_commitment(parameters: &ledger::Parameters, updates: &[LeafUpdate]) -> DiffCommitment {
+    updates
+        .iter()
+        .fold(DiffCommitment::zero(), |commitment, update| {
+            // The committed input consists of
+            // (Tag || PreviousCommitment || Sender || Recipient || OldLeaves || NewLeaves)
+            let mut input = DIFF_TAG.to_vec();
+            input.extend(ark_ff::to_bytes![commitment].unwrap());
+            input.extend([update.sender.0, update.recipient.0]);
+            input.extend(update.old_leaves.concat());
+            input.extend(update.new_leaves.concat());
+            AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, AssetId, Parameters, State};
+
+    #[test]
+    fn leaf_update_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let leaf = |state: &State, id| {
+            mark_commitment(&state.id_to_account_info[&id].commitment(&pp.account_crh_params))
+        };
+        let transfer = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            AssetId::NATIVE,
+            Amount(3),
+            Amount(0),
+            Default::default(),
+            &alice_sk,
+            &mut rng,
+        );
+
+        // A transfer updates the leaves of both of its accounts, ...
+        let pre_state = state.clone();
+        let update = transfer
+            .leaf_update(
+                &pp,
+                &state.id_to_account_info[&alice_id],
+                &state.id_to_account_info[&bob_id],
+                state.epoch,
+            )
+            .unwrap();
+        state.apply_transaction(&pp, &transfer).unwrap();
+        assert_eq!(
+            update.old_leaves,
+            [leaf(&pre_state, alice_id), leaf(&pre_state, bob_id)]
+        );
+        assert_eq!(
+            update.new_leaves,
+            [leaf(&state, alice_id), leaf(&state, bob_id)]
+        );
+        // ... while a withdrawal only updates that of its sender.
+        let info = state.id_to_account_info[&alice_id];
+        let placeholder = AccountInformation::new(Default::default());
+        let withdrawal_update = withdrawal
+            .leaf_update(&pp, &info, &placeholder, state.epoch)
+            .unwrap();
+        state.apply_transaction(&pp, &withdrawal).unwrap();
+        assert_eq!(withdrawal_update.new_leaves[0], leaf(&state, alice_id));
+        assert_eq!(withdrawal_update.old_leaves[1], blank_diff_leaf());
+        assert_eq!(withdrawal_update.new_leaves[1], blank_diff_leaf());
+
+        // The commitment depends on every update, and on their order.
+        let commitment = diff_commitment(&pp, &[update.clone(), withdrawal_update.clone()]);
+        assert_ne!(
+            commitment,
+            diff_commitment(&pp, std::slice::from_ref(&update))
+        );
+        assert_ne!(
+            commitment,
+            diff_commitment(&pp, &[withdrawal_update, update])
+        );
+    }
+}
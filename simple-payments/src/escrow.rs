@@ -0,0 +1,229 @@
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+};
+use crate::ledger::{self, Amount, AssetId};
+use ark_std::rand::Rng;
+
+/// Prefix of the message signed to authorize an escrow operation. This separates
+/// escrow messages from transaction and swap messages, so that their signatures
+/// can't be reused for one another.
+pub const ESCROW_TAG: &[u8] = b"escrow";
+
+/// The kind of an escrow operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscrowKind {
+    /// Move funds from the available balance of the account to its held funds.
+    Lock,
+    /// Move held funds back to the available balance of the account, e.g., to
+    /// cancel a conditional payment, or before settling it.
+    Release,
+}
+
+impl EscrowKind {
+    /// Convert the kind to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+}
+
+/// An escrow operation, which locks or releases `amount` of the native asset
+/// held by `account`. Held funds stay in the account, but can't be spent.
+#[derive(Clone, Debug)]
+pub struct Escrow {
+    /// The account whose funds are locked or released.
+    pub account: AccountId,
+    /// Whether the funds are locked or released.
+    pub kind: EscrowKind,
+    /// The amount of the native asset being locked or released.
+    pub amount: Amount,
+    /// The signature of `account` over the operation.
+    pub signature: AccountSignature,
+}
+
+/// Construct the message that is signed to authorize an escrow operation.
+fn message(
+    parameters: &ledger::Parameters,
+    account: AccountId,
+    kind: EscrowKind,
+    amount: Amount,
+) -> Vec<u8> {
+    // The authorized message consists of
+    // (Tag || AccountId || Kind || Amount)
+    let mut message = ESCROW_TAG.to_vec();
+    message.extend(account.to_bytes_le());
+    message.extend(kind.to_bytes_le());
+    message.extend(amount.to_bytes_le());
+    parameters.message_encoding.encode(message)
+}
+
+impl Escrow {
+    /// Verify just the signature of the operation.
+    fn verify_signature(&self, pp: &ledger::Parameters, pub_key: &AccountPublicKey) -> bool {
+        let message = message(pp, self.account, self.kind, self.amount);
+        self.signature.verify(pp, pub_key, &message)
+    }
+
+    /// Compute the held funds of the account after the operation, given its
+    /// information `info` before it. Returns `None` if more than the native
+    /// balance would be held, or if more than the held funds would be released.
+    pub(crate) fn new_held(&self, info: &AccountInformation) -> Option<Amount> {
+        match self.kind {
+            EscrowKind::Lock => {
+                let held = info.held.checked_add(self.amount)?;
+                (held <= info.balances.get(AssetId::NATIVE)?).then_some(held)
+            }
+            EscrowKind::Release => info.held.checked_sub(self.amount),
+        }
+    }
+
+    /// Check that the operation is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the account exists, and that the signature is valid with
+    ///    respect to its public key.
+    /// 2. Verify that a lock doesn't hold more than the native balance of the
+    ///    account, and that a release doesn't release more than is held.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        match state.id_to_account_info.get(&self.account) {
+            Some(info) => {
+                self.verify_signature(parameters, &info.public_key) && self.new_held(info).is_some()
+            }
+            None => false,
+        }
+    }
+
+    /// Create a (possibly invalid) escrow operation, signed by `account`.
+    pub fn create<R: Rng>(
+        parameters: &ledger::Parameters,
+        account: AccountId,
+        kind: EscrowKind,
+        amount: Amount,
+        account_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = message(parameters, account, kind, amount);
+        let signature = AccountSignature::sign(parameters, account_sk, &message, rng);
+        Self {
+            account,
+            kind,
+            amount,
+            signature,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+    use crate::swap::Swap;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn escrow_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(bob_id, AssetId(1), Amount(5))
+            .unwrap();
+
+        // Alice can't hold more than she has, or release what she doesn't hold.
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(11),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!lock.validate(&pp, &state));
+        let release = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Release,
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!release.validate(&pp, &state));
+        // Only Alice can hold her funds.
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(6),
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!lock.validate(&pp, &state));
+
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(6),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_escrow(&pp, &lock).unwrap();
+        let info = state.id_to_account_info[&alice_id];
+        assert_eq!(info.held, Amount(6));
+        assert_eq!(info.balances.get(AssetId::NATIVE), Some(Amount(10)));
+        assert_eq!(info.available_balance(AssetId::NATIVE), Some(Amount(4)));
+        assert_eq!(info.nonce, 1);
+
+        // Held funds can't be spent, neither by a transaction, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+        // ... nor by a swap.
+        let swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId::NATIVE,
+            Amount(5),
+            AssetId(1),
+            Amount(5),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!swap.validate(&pp, &state));
+        // Neither can they be held twice.
+        let lock = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Lock,
+            Amount(5),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!lock.validate(&pp, &state));
+
+        // Releasing the funds makes them spendable again.
+        let release = Escrow::create(
+            &pp,
+            alice_id,
+            EscrowKind::Release,
+            Amount(6),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_escrow(&pp, &release).unwrap();
+        assert_eq!(state.id_to_account_info[&alice_id].held, Amount(0));
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(
+            state.id_to_account_info[&bob_id]
+                .balances
+                .get(AssetId::NATIVE),
+            Some(Amount(10))
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! A fee market in the style of EIP-1559, for ledgers with a fee collector.
+//!
+//! Each transaction must pay at least the base fee of the ledger, see
+//! `State::base_fee`, which is burned. The rest of its fee is its priority fee,
+//! which is credited to the fee collector. The priority fees of a batch are only
+//! credited once all of its transactions are applied, in the asset of each
+//! transaction, so that they can't be spent within the batch that pays them.
+
+use crate::ledger::{self, Amount, State};
+use crate::transaction::Transaction;
+
+/// How the fee of a transaction is split between the base fee and the priority
+/// fee.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeeSplit {
+    /// The part of the fee that is burned, i.e., the base fee.
+    pub burned: Amount,
+    /// The part of the fee that is credited to the fee collector.
+    pub priority: Amount,
+}
+
+impl Transaction {
+    /// Split the fee of the transaction into the base fee `base_fee` and the
+    /// priority fee, i.e., whatever it pays beyond the base fee. Returns `None`
+    /// if the fee doesn't cover the base fee.
+    pub fn split_fee(&self, base_fee: Amount) -> Option<FeeSplit> {
+        Some(FeeSplit {
+            burned: base_fee,
+            priority: self.fee.checked_sub(base_fee)?,
+        })
+    }
+
+    /// Does the transaction pay the base fee `base_fee`? This always holds if the
+    /// ledger has no fee market.
+    pub(crate) fn pays_base_fee(&self, parameters: &ledger::Parameters, base_fee: Amount) -> bool {
+        parameters.fee_collector.is_none() || self.split_fee(base_fee).is_some()
+    }
+}
+
+impl State {
+    /// Credit the priority fees of `transactions`, which must all have been
+    /// applied during the current batch, to the fee collector, each in the asset
+    /// of its transaction. This does nothing if the ledger has no fee market.
+    ///
+    /// Returns `None`, leaving the state unchanged, if the fee collector doesn't
+    /// exist, if a transaction doesn't pay the base fee, or if a balance of the
+    /// fee collector overflows.
+    pub fn collect_fees(
+        &mut self,
+        pp: &ledger::Parameters,
+        transactions: &[Transaction],
+    ) -> Option<()> {
+        let collector = match pp.fee_collector {
+            Some(collector) => collector,
+            None => return Some(()),
+        };
+        let mut balances = self.id_to_account_info.get(&collector)?.balances;
+        for tx in transactions {
+            let priority = tx.split_fee(self.base_fee)?.priority;
+            let balance = balances.get(tx.asset_id)?.checked_add(priority)?;
+            balances.set(tx.asset_id, balance)?;
+        }
+        self.update_balances(collector, balances)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{AssetId, Parameters};
+
+    #[test]
+    fn fee_market_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (sequencer_id, _sequencer_pk, _sequencer_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        pp.fee_collector = Some(sequencer_id);
+        state.base_fee = Amount(3);
+        let pay = |fee, rng: &mut _| {
+            Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(5),
+                Amount(fee),
+                &alice_sk,
+                rng,
+            )
+        };
+
+        // A transaction underpaying the base fee is rejected, ...
+        let underpaying = pay(2, &mut rng);
+        assert_eq!(underpaying.split_fee(state.base_fee), None);
+        assert!(!underpaying.validate(&pp, &state));
+        // ... unless the ledger has no fee market.
+        let mut lax_pp = pp.clone();
+        lax_pp.fee_collector = None;
+        assert!(underpaying.pays_base_fee(&lax_pp, state.base_fee));
+
+        // Otherwise, the base fee is burned, and the priority fee is credited to
+        // the fee collector at the end of the batch.
+        let tx = pay(4, &mut rng);
+        assert_eq!(
+            tx.split_fee(state.base_fee),
+            Some(FeeSplit {
+                burned: Amount(3),
+                priority: Amount(1),
+            })
+        );
+        state.apply_transaction(&pp, &tx).unwrap();
+        state.collect_fees(&pp, &[tx]).unwrap();
+        let balance = |id| state.id_to_account_info[&id].balances.get(AssetId::NATIVE);
+        assert_eq!(balance(alice_id), Some(Amount(11)));
+        assert_eq!(balance(bob_id), Some(Amount(5)));
+        assert_eq!(balance(sequencer_id), Some(Amount(1)));
+    }
+}
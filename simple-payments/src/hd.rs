@@ -0,0 +1,102 @@
+//! Hierarchical deterministic keys, which let an account authorize each of its
+//! transactions with a fresh key. The public key of the account acts as a master
+//! key, from which the key at each index is derived by offsetting it by a hash of
+//! the master key and of the index. Anyone who knows the master public key can
+//! thus derive the public key at any index, while only the owner of the master
+//! secret key can derive the matching secret keys.
+
+use crate::account::{AccountInformation, AccountPublicKey, AccountSecretKey};
+use crate::ledger::{self, AccountHash};
+use ark_crypto_primitives::crh::CRH;
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ed_on_bls12_381::Fr;
+use ark_ff::PrimeField;
+
+/// Prefix of the input hashed to derive a key. This separates key derivations
+/// from account commitments, which are computed with the same hash.
+pub const DERIVE_KEY_TAG: &[u8] = b"derive-key";
+
+/// Compute the offset of the key at `index` from the master key `master`.
+fn key_tweak(parameters: &ledger::Parameters, master: &AccountPublicKey, index: u64) -> Fr {
+    // The hashed input consists of
+    // (Tag || MasterPublicKey || Index)
+    let mut input = DERIVE_KEY_TAG.to_vec();
+    input.extend(ark_ff::to_bytes![master].unwrap());
+    input.extend(index.to_le_bytes());
+    let hash = AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap();
+    Fr::from_le_bytes_mod_order(&ark_ff::to_bytes![hash].unwrap())
+}
+
+/// Derive the public key at `index` from the master public key `master`.
+pub fn derive_public_key(
+    parameters: &ledger::Parameters,
+    master: &AccountPublicKey,
+    index: u64,
+) -> AccountPublicKey {
+    let tweak = key_tweak(parameters, master, index);
+    let offset = parameters.sig_params.generator.mul(tweak);
+    (master.into_projective() + offset).into_affine()
+}
+
+/// Derive the secret key at `index` from the master secret key `master`.
+pub fn derive_secret_key(
+    parameters: &ledger::Parameters,
+    master: &AccountSecretKey,
+    index: u64,
+) -> AccountSecretKey {
+    AccountSecretKey {
+        secret_key: master.secret_key + key_tweak(parameters, &master.public_key, index),
+        public_key: derive_public_key(parameters, &master.public_key, index),
+    }
+}
+
+impl AccountInformation {
+    /// Return the key that must authorize the next transaction of this account:
+    /// the key derived at the index after `key_index` if the ledger derives a
+    /// fresh key per transaction, and `public_key` otherwise.
+    pub fn signing_key(&self, parameters: &ledger::Parameters) -> AccountPublicKey {
+        match parameters.hd_keys {
+            true => derive_public_key(parameters, &self.public_key, self.key_index.wrapping_add(1)),
+            false => self.public_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, Parameters, State};
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn hd_key_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.hd_keys = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let pay = |index, rng: &mut _| {
+            let sk = derive_secret_key(&pp, &alice_sk, index);
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &sk, rng)
+        };
+
+        // The derived secret key matches the derived public key.
+        let first_sk = derive_secret_key(&pp, &alice_sk, 1);
+        assert_eq!(first_sk.public_key, derive_public_key(&pp, &alice_pk, 1));
+        assert_ne!(first_sk.public_key, alice_pk);
+        // The master key doesn't authorize transactions itself.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+
+        let tx = pay(1, &mut rng);
+        assert!(tx.validate(&pp, &state));
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(state.id_to_account_info[&alice_id].key_index, 1);
+        // Keys can't be reused, nor skipped.
+        assert!(!pay(1, &mut rng).validate(&pp, &state));
+        assert!(!pay(3, &mut rng).validate(&pp, &state));
+        assert!(pay(2, &mut rng).validate(&pp, &state));
+    }
+}
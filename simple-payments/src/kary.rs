@@ -0,0 +1,199 @@
+use crate::ledger::{AccRoot, LeafHash};
+use ark_crypto_primitives::crh::{
+    injective_map::{PedersenCRHCompressor, TECompressor},
+    pedersen, CRH,
+};
+use ark_crypto_primitives::Error;
+use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_ff::to_bytes;
+use ark_std::rand::Rng;
+
+/// The hash of the `ARITY` children of a node in a `KaryMerkleTree`.
+pub type KaryHash<const ARITY: usize> =
+    PedersenCRHCompressor<EdwardsProjective, TECompressor, KaryWindow<ARITY>>;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct KaryWindow<const ARITY: usize>;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = `ARITY` * 256 bits = enough for hashing `ARITY`
+// outputs. For `ARITY == 2`, this is the same as `TwoToOneWindow`.
+impl<const ARITY: usize> pedersen::Window for KaryWindow<ARITY> {
+    const WINDOW_SIZE: usize = 128;
+    const NUM_WINDOWS: usize = 2 * ARITY;
+}
+
+/// The parameters of `KaryHash`, which don't depend on the arity.
+pub type KaryHashParams = pedersen::Parameters<EdwardsProjective>;
+
+/// Sample the parameters of the hash of the nodes of a `KaryMerkleTree`.
+pub fn sample_kary_hash_params<const ARITY: usize, R: Rng>(rng: &mut R) -> KaryHashParams {
+    <KaryHash<ARITY> as CRH>::setup(rng).unwrap()
+}
+
+/// Hash the `ARITY` children `children` of a node, from the leftmost one.
+fn hash_children<const ARITY: usize>(
+    node_crh_params: &KaryHashParams,
+    children: &[AccRoot],
+) -> Result<AccRoot, Error> {
+    assert_eq!(
+        children.len(),
+        ARITY,
+        "a node must have exactly ARITY children"
+    );
+    let mut bytes = Vec::new();
+    for child in children {
+        bytes.extend(to_bytes![child]?);
+    }
+    <KaryHash<ARITY> as CRH>::evaluate(node_crh_params, &bytes)
+}
+
+/// A Merkle tree in which every node has `ARITY` children, rather than two, so
+/// that the tree is shallower for the same number of leaves. Leaves are hashed
+/// with `LeafHash`, as in an `AccMerkleTree`, and each node is the `KaryHash`
+/// of its children.
+///
+/// A tree of depth `depth` has `ARITY^depth` leaves, and the path of each of
+/// them consists of `depth` levels of `ARITY - 1` siblings.
+#[derive(Clone)]
+pub struct KaryMerkleTree<const ARITY: usize> {
+    /// The nodes of each level of the tree, from the hashes of the leaves up to
+    /// the root.
+    levels: Vec<Vec<AccRoot>>,
+}
+
+/// A membership proof in a `KaryMerkleTree`.
+#[derive(Clone, Debug)]
+pub struct KaryPath<const ARITY: usize> {
+    /// The index of the leaf.
+    pub leaf_index: usize,
+    /// The `ARITY - 1` siblings of the node on the path at each level, from the
+    /// leaf up, and from the leftmost one.
+    pub siblings: Vec<Vec<AccRoot>>,
+}
+
+impl<const ARITY: usize> KaryMerkleTree<ARITY> {
+    /// Create a tree with the leaves `leaves`, whose number must be a power of
+    /// `ARITY`.
+    pub fn new<L: AsRef<[u8]>>(
+        leaf_crh_params: &<LeafHash as CRH>::Parameters,
+        node_crh_params: &KaryHashParams,
+        leaves: &[L],
+    ) -> Result<Self, Error> {
+        assert!(ARITY >= 2, "a Merkle tree must have an arity of at least 2");
+        let mut level = leaves
+            .iter()
+            .map(|leaf| <LeafHash as CRH>::evaluate(leaf_crh_params, leaf.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut levels = Vec::new();
+        while level.len() > 1 {
+            if level.len() % ARITY != 0 {
+                return Err("the number of leaves must be a power of the arity".into());
+            }
+            let parents = level
+                .chunks(ARITY)
+                .map(|children| hash_children::<ARITY>(node_crh_params, children))
+                .collect::<Result<Vec<_>, _>>()?;
+            levels.push(level);
+            level = parents;
+        }
+        if level.is_empty() {
+            return Err("a Merkle tree must have at least one leaf".into());
+        }
+        levels.push(level);
+        Ok(Self { levels })
+    }
+
+    /// The root of the tree.
+    pub fn root(&self) -> AccRoot {
+        self.levels.last().expect("the tree has a root")[0]
+    }
+
+    /// The number of levels of siblings on the path of each leaf.
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// Generate the membership proof of the leaf at `index`.
+    pub fn generate_proof(&self, index: usize) -> Result<KaryPath<ARITY>, Error> {
+        if index >= self.levels[0].len() {
+            return Err("leaf index out of bounds".into());
+        }
+        let mut position = index;
+        let siblings = self.levels[..self.depth()]
+            .iter()
+            .map(|level| {
+                let first = position - position % ARITY;
+                let siblings = (first..first + ARITY)
+                    .filter(|&i| i != position)
+                    .map(|i| level[i])
+                    .collect();
+                position /= ARITY;
+                siblings
+            })
+            .collect();
+        Ok(KaryPath {
+            leaf_index: index,
+            siblings,
+        })
+    }
+}
+
+impl<const ARITY: usize> KaryPath<ARITY> {
+    /// Check that this path proves the membership of `leaf` in the tree with
+    /// root `root`.
+    pub fn verify(
+        &self,
+        leaf_crh_params: &<LeafHash as CRH>::Parameters,
+        node_crh_params: &KaryHashParams,
+        root: &AccRoot,
+        leaf: &[u8],
+    ) -> Result<bool, Error> {
+        let mut node = <LeafHash as CRH>::evaluate(leaf_crh_params, leaf)?;
+        let mut position = self.leaf_index;
+        for siblings in &self.siblings {
+            if siblings.len() != ARITY - 1 {
+                return Ok(false);
+            }
+            // The node takes its place among its siblings.
+            let mut children = siblings.clone();
+            children.insert(position % ARITY, node);
+            node = hash_children::<ARITY>(node_crh_params, &children)?;
+            position /= ARITY;
+        }
+        Ok(position == 0 && node == *root)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::Parameters;
+
+    #[test]
+    fn kary_tree_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let node_crh_params = sample_kary_hash_params::<4, _>(&mut rng);
+        let leaves = (0u8..16).map(|i| vec![i; 4]).collect::<Vec<_>>();
+        let tree =
+            KaryMerkleTree::<4>::new(&pp.leaf_crh_params, &node_crh_params, &leaves).unwrap();
+        assert_eq!(tree.depth(), 2);
+
+        let root = tree.root();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let path = tree.generate_proof(i).unwrap();
+            assert!(path
+                .verify(&pp.leaf_crh_params, &node_crh_params, &root, leaf)
+                .unwrap());
+        }
+        // A path only proves the leaf at its own position.
+        let path = tree.generate_proof(6).unwrap();
+        assert!(!path
+            .verify(&pp.leaf_crh_params, &node_crh_params, &root, &leaves[7])
+            .unwrap());
+        // The number of leaves must be a power of the arity.
+        assert!(
+            KaryMerkleTree::<4>::new(&pp.leaf_crh_params, &node_crh_params, &leaves[..8]).is_err()
+        );
+    }
+}
@@ -1,14 +1,33 @@
-use crate::account::{AccountId, AccountInformation, AccountPublicKey, AccountSecretKey};
+use crate::account::{
+    mark_commitment, AccountId, AccountInformation, AccountPublicKey, AccountSecretKey,
+};
+use crate::allowlist::AllowlistCommitment;
+use crate::escrow::Escrow;
+use crate::metadata::MetadataHash;
+use crate::multisig::SignerSet;
+use crate::random_oracle::sha256;
+use crate::rotation::RotateKey;
 use crate::signature::{schnorr, SignatureScheme};
-use crate::transaction::Transaction;
+use crate::swap::Swap;
+use crate::transaction::{SimulationError, Transaction};
+use crate::withdrawal::WITHDRAWAL_ACCOUNT;
+use ark_crypto_primitives::commitment::{self, CommitmentScheme};
 use ark_crypto_primitives::crh::{
     injective_map::{PedersenCRHCompressor, TECompressor},
     pedersen, TwoToOneCRH, CRH,
 };
 use ark_crypto_primitives::merkle_tree::{self, MerkleTree, Path};
+use ark_ec::ProjectiveCurve;
 use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_ff::{to_bytes, PrimeField};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_std::rand::Rng;
-use std::collections::HashMap;
+use blake2::Blake2s;
+use digest::Digest;
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{Error, ErrorKind};
 
 /// Represents transaction amounts and account balances.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug)]
@@ -26,6 +45,127 @@ impl Amount {
     pub fn checked_sub(self, other: Self) -> Option<Self> {
         self.0.checked_sub(other.0).map(Self)
     }
+
+    /// The smallest amount of a token with `decimals` decimals, or `None` if
+    /// `decimals` exceeds `MAX_DECIMALS`.
+    pub fn unit(decimals: u32) -> Option<Self> {
+        10u64
+            .checked_pow(MAX_DECIMALS.checked_sub(decimals)?)
+            .map(Self)
+    }
+
+    /// Is this a whole number of units of a token with `decimals` decimals?
+    pub fn has_precision(self, decimals: u32) -> bool {
+        Self::unit(decimals).is_some_and(|unit| self.0.is_multiple_of(unit.0))
+    }
+
+    /// Commit to the amount, with the blinding factor `blinding`.
+    pub fn commit(self, parameters: &Parameters, blinding: &AmountBlinding) -> AmountCommitment {
+        AmountCommitmentScheme::commit(
+            &parameters.amount_commitment_params,
+            &self.to_bytes_le(),
+            blinding,
+        )
+        .expect("an amount fits in the commitment window")
+    }
+}
+
+/// The precision of amounts: an amount counts units of `10^-MAX_DECIMALS`. Tokens
+/// with fewer decimals only use multiples of their own unit, see `Amount::unit`.
+pub const MAX_DECIMALS: u32 = 8;
+
+/// The number of assets tracked by the ledger. Every account holds a balance of
+/// each asset.
+pub const NUM_ASSETS: usize = 4;
+
+/// Asset identifier. The ledger tracks the assets `0..NUM_ASSETS`.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug)]
+pub struct AssetId(pub u32);
+
+impl AssetId {
+    /// The native asset of the ledger. Transactions that don't specify an asset
+    /// transfer the native asset.
+    pub const NATIVE: Self = Self(0);
+
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+
+    /// Is this asset tracked by the ledger?
+    pub fn is_supported(&self) -> bool {
+        (self.0 as usize) < NUM_ASSETS
+    }
+}
+
+/// The balances of an account, indexed by asset identifier.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub struct Balances(pub [Amount; NUM_ASSETS]);
+
+impl Balances {
+    /// Return the balance of `asset`, or `None` if `asset` is not supported.
+    pub fn get(&self, asset: AssetId) -> Option<Amount> {
+        self.0.get(asset.0 as usize).copied()
+    }
+
+    /// Set the balance of `asset` to `amount`. Returns `None` if `asset` is not
+    /// supported.
+    pub fn set(&mut self, asset: AssetId, amount: Amount) -> Option<()> {
+        self.0
+            .get_mut(asset.0 as usize)
+            .map(|balance| *balance = amount)
+    }
+
+    /// Convert the balances to bytes, in order of asset identifier.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        self.0.iter().flat_map(Amount::to_bytes_le).collect()
+    }
+}
+
+impl Default for Balances {
+    fn default() -> Self {
+        Self([Amount(0); NUM_ASSETS])
+    }
+}
+
+/// How the transaction message is encoded before it is signed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MessageEncoding {
+    /// The signature is over the raw message bytes.
+    #[default]
+    Raw,
+    /// The signature is over the SHA-256 digest of the message bytes. This is
+    /// useful for wallets that can only sign message hashes.
+    Sha256,
+    /// Transactions are signed over their structured digest, which resembles
+    /// EIP-712, see `Transaction::structured_message`. Other messages have no
+    /// structure, and so they are signed over their SHA-256 digest, as with
+    /// `Sha256`.
+    Structured,
+}
+
+impl MessageEncoding {
+    /// Encode `message` before signing or verifying it.
+    pub fn encode(&self, message: Vec<u8>) -> Vec<u8> {
+        match self {
+            MessageEncoding::Raw => message,
+            MessageEncoding::Sha256 | MessageEncoding::Structured => {
+                sha256::digest(&message).to_vec()
+            }
+        }
+    }
+}
+
+/// How account signatures are encoded. Schnorr signatures either carry the
+/// verifier challenge, from which the verifier recomputes the prover's
+/// commitment, or carry the prover's commitment as a curve point, from which the
+/// verifier recomputes the challenge.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SignatureEncoding {
+    /// Signatures are `schnorr::Signature`s, i.e., pairs `(e, s)`.
+    #[default]
+    Challenge,
+    /// Signatures are `schnorr::PointSignature`s, i.e., pairs `(R, s)`.
+    Point,
 }
 
 /// The parameters that are used in transaction creation and validation.
@@ -33,20 +173,159 @@ impl Amount {
 pub struct Parameters {
     pub sig_params: schnorr::Parameters<EdwardsProjective>,
     pub leaf_crh_params: <TwoToOneHash as CRH>::Parameters,
+    /// The parameters used to commit to the information of each account.
+    pub account_crh_params: <AccountHash as CRH>::Parameters,
     pub two_to_one_crh_params: <TwoToOneHash as TwoToOneCRH>::Parameters,
+    /// The parameters used to commit to transaction amounts.
+    pub amount_commitment_params: <AmountCommitmentScheme as CommitmentScheme>::Parameters,
+    pub message_encoding: MessageEncoding,
+    /// How accounts encode their signatures.
+    pub signature_encoding: SignatureEncoding,
+    /// The maximum amount that each account can send per epoch, or `None` if
+    /// spending is unlimited.
+    pub daily_limit: Option<Amount>,
+    /// The maximum number of transactions that can be applied per epoch across
+    /// all accounts, or `None` if the number of transactions is unlimited.
+    pub max_per_epoch: Option<u64>,
+    /// Whether each transaction is authorized by a fresh key derived from the
+    /// public key of its sender, which acts as a master key. See `hd`.
+    pub hd_keys: bool,
+    /// The key of the sequencer, which must sign a receipt for the balance change
+    /// of the sender of each transaction, or `None` if no receipts are required.
+    /// See `receipt`.
+    pub sequencer_key: Option<AccountPublicKey>,
+    /// Whether the transactions of each batch must be sorted by their hashes, so
+    /// that a set of transactions can only be batched in one order. See
+    /// `transaction::is_canonically_ordered`.
+    pub canonical_order: bool,
+    /// The number of epochs that must pass after an account is credited by a
+    /// transfer before it can withdraw, or `None` if withdrawals are not delayed.
+    pub withdrawal_grace: Option<u64>,
+    /// Whether transfers to accounts that don't accept transfers need the
+    /// consent of their recipient. See `consent`.
+    pub require_consent: bool,
+    /// Whether transfers sign the public key of their recipient besides its
+    /// identifier, so that a transfer can't be redirected by pointing the
+    /// identifier to another key. See `Transaction::signed_message_to`.
+    pub bind_recipient_key: bool,
+    /// Whether transactions sign the leaf indices of their sender and of their
+    /// recipient, so that their accounts must be proven at exactly these
+    /// positions of the account tree. See `Transaction::leaf_indices`.
+    pub bind_leaf_indices: bool,
+    /// Whether accounts may restrict the recipients that they pay to an
+    /// allowlist. See `allowlist`.
+    pub allowlists: bool,
+    /// The account that collects the priority fees of the transactions, or
+    /// `None` if the ledger has no fee market and burns all fees. See
+    /// `fee_market`.
+    pub fee_collector: Option<AccountId>,
+    /// Whether the owners of accounts may let delegates spend from them, up to
+    /// a cap. See `delegation`.
+    pub delegation: bool,
+    /// The personalization that the leaves of the account, withdrawal and batch
+    /// trees are prefixed with, along with their `LeafDomain`, so that leaves of
+    /// different trees never hash alike, or `None` if leaves are hashed as is.
+    /// See `personalize_leaf`.
+    pub leaf_personalization: Option<[u8; LEAF_PERSONALIZATION_LEN]>,
+    /// Whether transfers may not credit an account beyond the cap on its
+    /// balance, see `AccountInformation::balance_cap`.
+    pub balance_caps: bool,
+    /// Whether the transactions of each batch must have distinct identifiers, so
+    /// that a transaction can't be included twice in a batch. See
+    /// `transaction::has_unique_ids`.
+    pub unique_tx_ids: bool,
+    /// Whether transactions sign the root of the account tree that they are
+    /// applied to, so that they can't be replayed on another state of the
+    /// ledger, e.g., on a fork. See `Transaction::message_at`.
+    pub bind_pre_root: bool,
 }
 
 impl Parameters {
     pub fn sample<R: Rng>(rng: &mut R) -> Self {
         let sig_params = schnorr::Schnorr::setup(rng).unwrap();
         let leaf_crh_params = <LeafHash as CRH>::setup(rng).unwrap();
+        let account_crh_params = <AccountHash as CRH>::setup(rng).unwrap();
         let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(rng).unwrap();
+        let amount_commitment_params = AmountCommitmentScheme::setup(rng).unwrap();
         Self {
             sig_params,
             leaf_crh_params,
+            account_crh_params,
             two_to_one_crh_params,
+            amount_commitment_params,
+            message_encoding: MessageEncoding::default(),
+            signature_encoding: SignatureEncoding::default(),
+            daily_limit: None,
+            max_per_epoch: None,
+            hd_keys: false,
+            sequencer_key: None,
+            canonical_order: false,
+            withdrawal_grace: None,
+            require_consent: false,
+            bind_recipient_key: false,
+            bind_leaf_indices: false,
+            allowlists: false,
+            fee_collector: None,
+            delegation: false,
+            leaf_personalization: None,
+            balance_caps: false,
+            unique_tx_ids: false,
+            bind_pre_root: false,
         }
     }
+
+    /// Commit to the parameters, so that transactions can refer to the parameters
+    /// that they are signed under. This is a hash of all the parameters.
+    pub fn commitment(&self) -> ParamsCommitment {
+        // The generators are normalized first, since projective points have many
+        // representations.
+        let mut points = [
+            &self.leaf_crh_params.generators,
+            &self.account_crh_params.generators,
+            &self.two_to_one_crh_params.generators,
+            &self.amount_commitment_params.generators,
+        ]
+        .iter()
+        .flat_map(|generators| generators.iter().flatten().copied())
+        .collect::<Vec<_>>();
+        points.extend(&self.amount_commitment_params.randomness_generator);
+        let points = EdwardsProjective::batch_normalization_into_affine(&points);
+        let mut bytes = to_bytes![self.sig_params.generator, points].unwrap();
+        let optional = |value: Option<Vec<u8>>| match value {
+            Some(value) => [vec![1], value].concat(),
+            None => vec![0],
+        };
+        bytes.extend(optional(self.sig_params.salt.map(|salt| salt.to_vec())));
+        bytes.push(self.message_encoding as u8);
+        bytes.push(self.signature_encoding as u8);
+        bytes.extend(optional(self.daily_limit.map(|limit| limit.to_bytes_le())));
+        bytes.extend(optional(
+            self.max_per_epoch.map(|max| max.to_le_bytes().to_vec()),
+        ));
+        bytes.push(self.hd_keys as u8);
+        bytes.extend(optional(
+            self.sequencer_key.map(|key| to_bytes![key].unwrap()),
+        ));
+        bytes.push(self.canonical_order as u8);
+        bytes.extend(optional(
+            self.withdrawal_grace
+                .map(|grace| grace.to_le_bytes().to_vec()),
+        ));
+        bytes.push(self.require_consent as u8);
+        bytes.push(self.bind_recipient_key as u8);
+        bytes.push(self.bind_leaf_indices as u8);
+        bytes.push(self.allowlists as u8);
+        bytes.extend(optional(self.fee_collector.map(|id| id.to_bytes_le())));
+        bytes.push(self.delegation as u8);
+        bytes.extend(optional(
+            self.leaf_personalization
+                .map(|personalization| personalization.to_vec()),
+        ));
+        bytes.push(self.balance_caps as u8);
+        bytes.push(self.unique_tx_ids as u8);
+        bytes.push(self.bind_pre_root as u8);
+        ParamsCommitment::from_le_bytes_mod_order(&Blake2s::digest(&bytes))
+    }
 }
 
 pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
@@ -65,12 +344,83 @@ pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafW
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct LeafWindow;
 
-// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 bits = enough for hashing two outputs.
+// `WINDOW_SIZE * NUM_WINDOWS` = 320 bits = enough for hashing an account commitment,
+// prefixed with the personalization and the domain of its tree (8 bytes).
 impl pedersen::Window for LeafWindow {
-    const WINDOW_SIZE: usize = 144;
+    const WINDOW_SIZE: usize = 64;
+    const NUM_WINDOWS: usize = 5;
+}
+
+/// The length of `Parameters::leaf_personalization`. Along with the byte of the
+/// `LeafDomain`, the personalization fills one window of `LeafHash`.
+pub const LEAF_PERSONALIZATION_LEN: usize = 7;
+
+/// The trees whose leaves are separated by `Parameters::leaf_personalization`.
+/// Their leaves are all 32-byte commitments or hashes, and so without it, the
+/// same bytes would hash to the same node in each of them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LeafDomain {
+    /// The account tree, whose leaves store account commitments.
+    Account = 0,
+    /// The withdrawal tree of a batch, see `withdrawal::WithdrawalTree`.
+    Withdrawal = 1,
+    /// The tree of the transactions of a batch, see `batch::BatchTree`.
+    Transaction = 2,
+}
+
+/// Convert `leaf` to the leaf of a tree of the domain `domain`, under the
+/// personalization `personalization`. The leaf is unchanged if `personalization`
+/// is `None`.
+pub fn personalize_leaf(
+    personalization: Option<[u8; LEAF_PERSONALIZATION_LEN]>,
+    domain: LeafDomain,
+    leaf: Vec<u8>,
+) -> Vec<u8> {
+    match personalization {
+        // The personalized leaf consists of
+        // (Personalization || Domain || Leaf)
+        Some(personalization) => [personalization.to_vec(), vec![domain as u8], leaf].concat(),
+        None => leaf,
+    }
+}
+
+pub type AccountHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, AccountWindow>;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct AccountWindow;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = 2816 bits = enough for hashing `NUM_ASSETS` balances
+// (8 bytes each), a nonce (8 bytes), a public key and a recovery key (64 bytes
+// each), the spending of the account (16 bytes), its held funds (8 bytes), the
+// spending of its delegates (8 bytes), its balance cap (8 bytes), its key index
+// (8 bytes), the epoch of its last credit (8 bytes), its allowlist (32 bytes), its
+// metadata hash (32 bytes), its signer set and threshold (33 bytes), and whether
+// it accepts transfers (1 byte), followed by the end marker of the packed
+// encoding (see `account::PACKED_ACCOUNT_INFO_END`).
+impl pedersen::Window for AccountWindow {
+    const WINDOW_SIZE: usize = 256;
+    const NUM_WINDOWS: usize = 11;
+}
+
+/// A Pedersen commitment scheme for amounts. Its commitments hide the amount
+/// they commit to, as long as the blinding factor is kept secret.
+pub type AmountCommitmentScheme =
+    commitment::pedersen::Commitment<EdwardsProjective, AmountCommitmentWindow>;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct AmountCommitmentWindow;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = 64 bits = enough for committing to an amount.
+impl pedersen::Window for AmountCommitmentWindow {
+    const WINDOW_SIZE: usize = 16;
     const NUM_WINDOWS: usize = 4;
 }
 
+/// A commitment to an amount.
+pub type AmountCommitment = <AmountCommitmentScheme as CommitmentScheme>::Output;
+/// The blinding factor of a commitment to an amount.
+pub type AmountBlinding = <AmountCommitmentScheme as CommitmentScheme>::Randomness;
+
 #[derive(Clone)]
 pub struct MerkleConfig;
 impl merkle_tree::Config for MerkleConfig {
@@ -84,6 +434,480 @@ pub type AccMerkleTree = MerkleTree<MerkleConfig>;
 pub type AccRoot = <TwoToOneHash as TwoToOneCRH>::Output;
 /// A membership proof for a given account.
 pub type AccPath = Path<MerkleConfig>;
+/// A commitment to a block of the chain that the ledger is anchored to, e.g.,
+/// its hash. The zero commitment stands for no block at all.
+pub type BlockCommitment = ark_ed_on_bls12_381::Fq;
+/// A commitment to the ledger parameters, see `Parameters::commitment`.
+pub type ParamsCommitment = ark_ed_on_bls12_381::Fq;
+
+/// Incremental updates of account Merkle trees, which produce the witnesses
+/// needed to prove the update in a circuit.
+pub trait IncrementalUpdate {
+    /// Update the leaf at `index` to `new_leaf`. Only the nodes on the path from
+    /// the leaf to the root are recomputed, so this takes `O(log n)` hashes.
+    ///
+    /// Returns the path of the leaf *before* the update, along with the new root.
+    /// The siblings on the path are not affected by the update, so the returned
+    /// path also proves the membership of `new_leaf` with respect to the new root.
+    fn update_with_path(
+        &mut self,
+        index: usize,
+        new_leaf: &[u8],
+    ) -> Result<(AccPath, AccRoot), ark_crypto_primitives::Error>;
+}
+
+impl IncrementalUpdate for AccMerkleTree {
+    fn update_with_path(
+        &mut self,
+        index: usize,
+        new_leaf: &[u8],
+    ) -> Result<(AccPath, AccRoot), ark_crypto_primitives::Error> {
+        let pre_path = self.generate_proof(index)?;
+        self.update(index, &new_leaf)?;
+        Ok((pre_path, self.root()))
+    }
+}
+
+/// Conversions of account Merkle tree paths to and from flat vectors of field
+/// elements, e.g., to transport them as witnesses.
+pub trait PathFieldElements: Sized {
+    /// Convert the path to field elements: its leaf index, followed by the
+    /// sibling of the leaf, and then by the rest of its siblings, from the top
+    /// of the tree down. A path in a tree of height `height` takes `height`
+    /// elements.
+    fn to_field_elements(&self) -> Vec<ark_ed_on_bls12_381::Fq>;
+
+    /// Convert the field elements `elements`, as produced by `to_field_elements`,
+    /// back to a path in a tree of height `height`. Returns `None` if there
+    /// are not exactly `height` elements, or if the leaf index does not fit
+    /// in the tree.
+    fn from_field_elements(elements: &[ark_ed_on_bls12_381::Fq], height: usize) -> Option<Self>;
+}
+
+impl PathFieldElements for AccPath {
+    fn to_field_elements(&self) -> Vec<ark_ed_on_bls12_381::Fq> {
+        let mut elements = vec![(self.leaf_index as u64).into(), self.leaf_sibling_hash];
+        elements.extend(&self.auth_path);
+        elements
+    }
+
+    fn from_field_elements(elements: &[ark_ed_on_bls12_381::Fq], height: usize) -> Option<Self> {
+        if height < 2 || elements.len() != height {
+            return None;
+        }
+        let index = elements[0].into_repr();
+        let (low, high) = index.as_ref().split_first()?;
+        if high.iter().any(|&limb| limb != 0) || *low >= 1 << (height - 1) {
+            return None;
+        }
+        Some(Self {
+            leaf_index: *low as usize,
+            leaf_sibling_hash: elements[1],
+            auth_path: elements[2..].to_vec(),
+        })
+    }
+}
+
+/// Return the index of the leaf storing the account `id`'s information, in an
+/// account Merkle tree of height `height`.
+///
+/// Account identifiers map directly to leaf indices (i.e., they are not hashed),
+/// so the `i`-th account is stored in the `i`-th leaf. A tree of height `height`
+/// has `2^(height - 1)` leaves, so `id` must be smaller than that. Returns `None`
+/// if it isn't, or if `height` is 0, in which case the tree has no leaves.
+pub fn leaf_index(id: &AccountId, height: usize) -> Option<u64> {
+    let depth = height.checked_sub(1)?;
+    let index = id.0 as u64;
+    (depth >= 64 || index < (1 << depth)).then_some(index)
+}
+
+/// A two-level account tree, for ledgers with many accounts. Accounts are stored
+/// in the leaves of `2^(top_height - 1)` bottom trees of height `bottom_height`,
+/// and the `i`-th leaf of the top tree holds the root of the `i`-th bottom tree.
+/// Updating an account only rehashes its path in its bottom tree, and the path of
+/// that bottom tree in the top tree, so it takes `O(top_height + bottom_height)`
+/// hashes, however the heights are split.
+///
+/// The `i`-th leaf is stored in the bottom tree `i / 2^(bottom_height - 1)`, at
+/// the position `i % 2^(bottom_height - 1)`.
+#[derive(Clone)]
+pub struct LayeredLedger {
+    top: AccMerkleTree,
+    bottoms: Vec<AccMerkleTree>,
+}
+
+/// A membership proof in a `LayeredLedger`.
+#[derive(Clone)]
+pub struct LayeredPath {
+    /// The root of the bottom tree containing the leaf.
+    pub bottom_root: AccRoot,
+    /// The membership proof of the leaf in its bottom tree.
+    pub bottom: AccPath,
+    /// The membership proof of `bottom_root` in the top tree.
+    pub top: AccPath,
+}
+
+/// Convert the root of a bottom tree to a leaf of the top tree.
+fn bottom_root_to_leaf(root: &AccRoot) -> Vec<u8> {
+    ark_ff::to_bytes![root].unwrap()
+}
+
+impl LayeredLedger {
+    /// Create a layered ledger with blank leaves, whose top and bottom trees have
+    /// height `top_height` and `bottom_height` respectively.
+    pub fn blank(parameters: &Parameters, top_height: usize, bottom_height: usize) -> Self {
+        let bottom = AccMerkleTree::blank(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            bottom_height,
+        )
+        .unwrap();
+        let bottoms = vec![bottom; 1 << (top_height - 1)];
+        let top_leaves = bottoms
+            .iter()
+            .map(|bottom| bottom_root_to_leaf(&bottom.root()))
+            .collect::<Vec<_>>();
+        let top = AccMerkleTree::new(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &top_leaves,
+        )
+        .unwrap();
+        Self { top, bottoms }
+    }
+
+    /// The root of the top tree, which commits to every leaf.
+    pub fn root(&self) -> AccRoot {
+        self.top.root()
+    }
+
+    /// Return the bottom tree storing the `index`-th leaf, and the position of
+    /// the leaf in it.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let bottom_size = 1 << (self.bottoms[0].height() - 1);
+        (index / bottom_size, index % bottom_size)
+    }
+
+    /// Update the `index`-th leaf to `new_leaf`.
+    pub fn update(
+        &mut self,
+        index: usize,
+        new_leaf: &[u8],
+    ) -> Result<(), ark_crypto_primitives::Error> {
+        let (subtree, position) = self.locate(index);
+        let bottom = self.bottoms.get_mut(subtree).ok_or("index out of range")?;
+        bottom.update(position, &new_leaf)?;
+        self.top
+            .update(subtree, &bottom_root_to_leaf(&bottom.root()))
+    }
+
+    /// Return a proof of the membership of the `index`-th leaf.
+    pub fn prove(&self, index: usize) -> Result<LayeredPath, ark_crypto_primitives::Error> {
+        let (subtree, position) = self.locate(index);
+        let bottom = self.bottoms.get(subtree).ok_or("index out of range")?;
+        Ok(LayeredPath {
+            bottom_root: bottom.root(),
+            bottom: bottom.generate_proof(position)?,
+            top: self.top.generate_proof(subtree)?,
+        })
+    }
+}
+
+impl LayeredPath {
+    /// Check that this proves that `leaf` is the `index`-th leaf of the layered
+    /// ledger with root `root`.
+    pub fn verify(
+        &self,
+        parameters: &Parameters,
+        index: usize,
+        root: &AccRoot,
+        leaf: &[u8],
+    ) -> Result<bool, ark_crypto_primitives::Error> {
+        // The paths must lead to the `index`-th leaf, and not to some other one.
+        let bottom_size = 1 << (self.bottom.auth_path.len() + 1);
+        if self.top.leaf_index != index / bottom_size
+            || self.bottom.leaf_index != index % bottom_size
+        {
+            return Ok(false);
+        }
+        let in_bottom = self.bottom.verify(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &self.bottom_root,
+            &leaf,
+        )?;
+        let in_top = self.top.verify(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            root,
+            &bottom_root_to_leaf(&self.bottom_root),
+        )?;
+        Ok(in_bottom && in_top)
+    }
+}
+
+/// The key stored in the unused leaves of a `SortedIdTree`. It is larger than any
+/// account identifier, so that the identifiers above the largest one in the tree
+/// can be proven absent as well.
+pub const SORTED_PADDING_KEY: u64 = u64::MAX;
+
+/// Convert a key of a `SortedIdTree` to a leaf.
+pub fn sorted_leaf(key: u64) -> Vec<u8> {
+    key.to_le_bytes().to_vec()
+}
+
+/// A Merkle tree storing the identifiers of a set of accounts as keys, in
+/// increasing order, followed by `SORTED_PADDING_KEY` in the unused leaves.
+///
+/// Unlike an account tree, whose `i`-th leaf is reserved for account `i`, the
+/// size of this tree only depends on the number of accounts in the set, rather
+/// than on the range of identifiers. An account is absent from the set iff its
+/// identifier falls strictly between the keys of two adjacent leaves.
+#[derive(Clone)]
+pub struct SortedIdTree {
+    tree: AccMerkleTree,
+    keys: Vec<u64>,
+}
+
+/// A proof that an identifier is absent from a `SortedIdTree`, consisting of the
+/// adjacent leaves whose keys surround it.
+#[derive(Clone)]
+pub struct NonMembershipProof {
+    /// The largest key that is smaller than the identifier.
+    pub low_key: u64,
+    /// The membership proof of `low_key`.
+    pub low_path: AccPath,
+    /// The smallest key that is larger than the identifier.
+    pub high_key: u64,
+    /// The membership proof of `high_key`, in the leaf right after `low_key`.
+    pub high_path: AccPath,
+}
+
+impl SortedIdTree {
+    /// Create a tree of height `height` storing the identifiers `ids`, in any
+    /// order. Returns an error if there are more identifiers than leaves.
+    pub fn new(
+        parameters: &Parameters,
+        height: usize,
+        ids: &[AccountId],
+    ) -> Result<Self, ark_crypto_primitives::Error> {
+        let mut keys = ids.iter().map(|id| id.0 as u64).collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        let num_leaves = 1 << (height - 1);
+        if keys.len() > num_leaves {
+            return Err("too many identifiers for the tree".into());
+        }
+        keys.resize(num_leaves, SORTED_PADDING_KEY);
+        let leaves = keys.iter().map(|key| sorted_leaf(*key)).collect::<Vec<_>>();
+        let tree = AccMerkleTree::new(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &leaves,
+        )?;
+        Ok(Self { tree, keys })
+    }
+
+    /// The root of the tree, which commits to the whole set.
+    pub fn root(&self) -> AccRoot {
+        self.tree.root()
+    }
+
+    /// Return the path of the leaf that stores `id`, which proves that `id` is in
+    /// the tree, or `None` if it isn't.
+    pub fn prove_membership(&self, id: AccountId) -> Option<AccPath> {
+        let index = self.keys.binary_search(&(id.0 as u64)).ok()?;
+        self.tree.generate_proof(index).ok()
+    }
+
+    /// Return a proof that `id` is absent from the tree, or `None` if `id` is in
+    /// the tree, or if it is smaller than every identifier in it.
+    pub fn prove_non_membership(&self, id: AccountId) -> Option<NonMembershipProof> {
+        let key = id.0 as u64;
+        // The padding is larger than any identifier, so there is always a key
+        // larger than `key`.
+        let high = self.keys.partition_point(|k| *k < key);
+        if high == 0 || self.keys[high] == key {
+            return None;
+        }
+        Some(NonMembershipProof {
+            low_key: self.keys[high - 1],
+            low_path: self.tree.generate_proof(high - 1).ok()?,
+            high_key: self.keys[high],
+            high_path: self.tree.generate_proof(high).ok()?,
+        })
+    }
+}
+
+impl NonMembershipProof {
+    /// Check that this proves that `id` is absent from the `SortedIdTree` with
+    /// root `root`.
+    pub fn verify(
+        &self,
+        parameters: &Parameters,
+        root: &AccRoot,
+        id: AccountId,
+    ) -> Result<bool, ark_crypto_primitives::Error> {
+        let key = id.0 as u64;
+        if self.high_path.leaf_index != self.low_path.leaf_index + 1
+            || !(self.low_key < key && key < self.high_key)
+        {
+            return Ok(false);
+        }
+        let is_member = |path: &AccPath, key| {
+            path.verify(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                root,
+                &sorted_leaf(key),
+            )
+        };
+        Ok(is_member(&self.low_path, self.low_key)? && is_member(&self.high_path, self.high_key)?)
+    }
+}
+
+/// The number of transactions applied to the ledger during the epoch `epoch`.
+///
+/// If the ledger limits the number of transactions per epoch, the counter is
+/// stored in the leaf of `WITHDRAWAL_ACCOUNT`, under which no account is ever
+/// registered. The counter of a fresh ledger hashes to the same leaf as a blank one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TxCounter {
+    /// The number of transactions applied during `epoch`.
+    pub count: u64,
+    /// The epoch during which `count` transactions were applied.
+    pub epoch: u64,
+}
+
+impl TxCounter {
+    /// Return the number of transactions applied so far during the epoch `epoch`.
+    pub fn count_in(&self, epoch: u64) -> u64 {
+        if self.epoch == epoch {
+            self.count
+        } else {
+            0
+        }
+    }
+
+    /// Convert the counter to bytes. These bytes are the leaf storing the counter.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = self.count.to_le_bytes().to_vec();
+        bytes.extend(self.epoch.to_le_bytes());
+        bytes
+    }
+}
+
+/// An account's information, along with a proof that it is stored in the
+/// account Merkle tree.
+#[derive(Clone)]
+pub struct AccountProof {
+    /// The account information.
+    pub info: AccountInformation,
+    /// The membership proof for `info`.
+    pub path: AccPath,
+}
+
+impl AccountProof {
+    /// Check that this proves that `self.info` is the information of the account
+    /// `id` in the account tree with root `root`.
+    pub fn verify(
+        &self,
+        parameters: &Parameters,
+        id: AccountId,
+        root: &AccRoot,
+    ) -> Result<bool, ark_crypto_primitives::Error> {
+        // The path must lead to the leaf of `id`, and not to some other account.
+        if self.path.leaf_index != id.0 as usize {
+            return Ok(false);
+        }
+        self.path.verify(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            root,
+            &self.info.to_leaf(parameters),
+        )
+    }
+}
+
+/// Check that `path` proves that `info` is the information of the account `id`
+/// in the account tree with root `root`. Only the root is needed, and so a light
+/// client that follows the published roots can check the balances of an
+/// account without holding the ledger.
+pub fn verify_inclusion(
+    parameters: &Parameters,
+    root: &AccRoot,
+    id: AccountId,
+    info: &AccountInformation,
+    path: &AccPath,
+) -> bool {
+    // The path must lead to the leaf of `id`, and not to some other account.
+    path.leaf_index == id.0 as usize
+        && path
+            .verify(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                root,
+                &info.to_leaf(parameters),
+            )
+            .unwrap_or(false)
+}
+
+/// An entry of the audit trail of a batch, see `State::apply_batch_with_trail`,
+/// which records what a transaction of the batch did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchEvent {
+    /// The index of the transaction in the batch.
+    pub index: usize,
+    /// The sender of the transaction.
+    pub sender: AccountId,
+    /// The recipient of the transaction.
+    pub recipient: AccountId,
+    /// The asset that the transaction transfers.
+    pub asset_id: AssetId,
+    /// The amount that the transaction transfers.
+    pub amount: Amount,
+    /// Whether the transaction was applied, or why it wasn't.
+    pub outcome: Result<(), SimulationError>,
+    /// The balance of the asset of the sender after the transaction, or `None`
+    /// if the sender doesn't exist or the asset isn't supported.
+    pub sender_balance: Option<Amount>,
+    /// The balance of the asset of the recipient after the transaction, or
+    /// `None` if the recipient doesn't exist, e.g., for withdrawals, or the asset
+    /// isn't supported.
+    pub recipient_balance: Option<Amount>,
+}
+
+impl fmt::Display for BatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let balance = |balance: Option<Amount>| match balance {
+            Some(balance) => balance.0.to_string(),
+            None => "none".to_string(),
+        };
+        write!(
+            f,
+            "#{}: {} of asset {} from account {} to account {}: ",
+            self.index, self.amount.0, self.asset_id.0, self.sender.0, self.recipient.0
+        )?;
+        match self.outcome {
+            Ok(()) => f.write_str("applied")?,
+            Err(error) => write!(f, "rejected, since {}", error)?,
+        }
+        write!(
+            f,
+            "; balances {} and {}",
+            balance(self.sender_balance),
+            balance(self.recipient_balance)
+        )
+    }
+}
+
+/// The account Merkle tree, together with the accounts whose leaves are out of
+/// date.
+#[derive(Clone)]
+struct AccountTree {
+    tree: AccMerkleTree,
+    dirty: BTreeSet<AccountId>,
+}
 
 #[derive(Clone)]
 pub struct State {
@@ -91,11 +915,34 @@ pub struct State {
     pub next_available_account: Option<AccountId>,
     /// A merkle tree mapping where the i-th leaf corresponds to the i-th account's
     /// information (= balance and public key).
-    pub account_merkle_tree: AccMerkleTree,
+    ///
+    /// Updating a leaf requires committing to the account information and
+    /// rehashing its path, so updates are deferred: updated accounts are only
+    /// marked as dirty, and their leaves are recomputed the next time the tree
+    /// is read. An account that is updated several times in between is only
+    /// rehashed once.
+    account_tree: RefCell<AccountTree>,
+    /// The parameters used to commit to the account information stored in the
+    /// leaves of the account Merkle tree.
+    pub account_crh_params: <AccountHash as CRH>::Parameters,
+    /// The personalization of the leaves of the account Merkle tree, see
+    /// `Parameters::leaf_personalization`.
+    pub leaf_personalization: Option<[u8; LEAF_PERSONALIZATION_LEN]>,
     /// A mapping from an account's identifier to its information (= balance and public key).
     pub id_to_account_info: HashMap<AccountId, AccountInformation>,
     /// A mapping from a public key to an account's identifier.
     pub pub_key_to_id: HashMap<schnorr::PublicKey<EdwardsProjective>, AccountId>,
+    /// The number of batches of transactions that have been applied to the ledger.
+    pub epoch: u64,
+    /// The number of transactions applied during the current epoch. This is only
+    /// tracked if the ledger limits the number of transactions per epoch.
+    pub tx_counter: TxCounter,
+    /// The commitment to the current block. Transactions bound to any other
+    /// block have expired.
+    pub block_commitment: BlockCommitment,
+    /// The base fee that every transaction must pay, and which is burned. This is
+    /// only enforced if the ledger has a fee market.
+    pub base_fee: Amount,
 }
 
 impl State {
@@ -112,33 +959,333 @@ impl State {
         let id_to_account_info = HashMap::with_capacity(num_accounts);
         Self {
             next_available_account: Some(AccountId(1)),
-            account_merkle_tree,
+            account_tree: RefCell::new(AccountTree {
+                tree: account_merkle_tree,
+                dirty: BTreeSet::new(),
+            }),
+            account_crh_params: parameters.account_crh_params.clone(),
+            leaf_personalization: parameters.leaf_personalization,
             id_to_account_info,
             pub_key_to_id,
+            epoch: 0,
+            tx_counter: TxCounter::default(),
+            block_commitment: BlockCommitment::default(),
+            base_fee: Amount(0),
+        }
+    }
+
+    /// Create a ledger that supports `num_accounts` accounts, and in which each
+    /// `(id, balance, public_key)` of `accounts` is registered under `id`, with a
+    /// native balance of `balance`. Identifiers need not be contiguous, and
+    /// later registrations continue after the largest of them.
+    ///
+    /// Returns `None` if an identifier or a public key is used twice, if `id` is
+    /// `WITHDRAWAL_ACCOUNT`, or if it does not fit in the tree.
+    pub fn genesis(
+        num_accounts: usize,
+        accounts: &[(AccountId, Amount, AccountPublicKey)],
+        parameters: &Parameters,
+    ) -> Option<Self> {
+        let mut state = Self::new(num_accounts, parameters);
+        let num_leaves = 1 << (state.account_tree.get_mut().tree.height() - 1);
+        for &(id, balance, public_key) in accounts {
+            if id == WITHDRAWAL_ACCOUNT
+                || id.0 as usize >= num_leaves
+                || state.id_to_account_info.contains_key(&id)
+                || state.pub_key_to_id.contains_key(&public_key)
+            {
+                return None;
+            }
+            let mut account_info = AccountInformation::new(public_key);
+            account_info.balances.set(AssetId::NATIVE, balance)?;
+            state.pub_key_to_id.insert(public_key, id);
+            state.id_to_account_info.insert(id, account_info);
+            // The leaves are only computed once all accounts are inserted.
+            state.account_tree.get_mut().dirty.insert(id);
+            if let Some(next) = state.next_available_account {
+                if id.0 >= next.0 {
+                    // There is no identifier left after the largest one.
+                    let mut next = id;
+                    state.next_available_account = next.checked_increment().map(|()| next);
+                }
+            }
+        }
+        Some(state)
+    }
+
+    /// Merge the ledgers `a` and `b`, whose sets of accounts must be disjoint,
+    /// into a single ledger holding the accounts of both. Registrations continue
+    /// after the largest identifier of either ledger.
+    ///
+    /// Both ledgers must have been created with the same parameters, and be at
+    /// the same epoch and block, with the same base fee. The transactions counted by either of them
+    /// during the epoch are counted by the merged ledger.
+    ///
+    /// Returns an error if the trees have different heights, if the ledgers are
+    /// at different epochs or blocks or have different base fees, or if an identifier or a public key is used
+    /// by both.
+    pub fn merge(a: Self, b: Self) -> Result<Self, ark_crypto_primitives::Error> {
+        let mut merged = a;
+        let mut b = b;
+        if merged.account_tree.get_mut().tree.height() != b.account_tree.get_mut().tree.height() {
+            return Err("the account trees have different heights".into());
+        }
+        if merged.epoch != b.epoch || merged.block_commitment != b.block_commitment {
+            return Err("the ledgers are at different epochs or blocks".into());
+        }
+        if merged.base_fee != b.base_fee {
+            return Err("the ledgers have different base fees".into());
+        }
+        if let Some(id) = b
+            .id_to_account_info
+            .keys()
+            .find(|id| merged.id_to_account_info.contains_key(id))
+        {
+            return Err(format!("account {} exists in both ledgers", id.0).into());
+        }
+        if b.pub_key_to_id
+            .keys()
+            .any(|public_key| merged.pub_key_to_id.contains_key(public_key))
+        {
+            return Err("a public key is registered in both ledgers".into());
+        }
+
+        for (id, info) in b.id_to_account_info {
+            merged.pub_key_to_id.insert(info.public_key, id);
+            merged.id_to_account_info.insert(id, info);
+            merged.account_tree.get_mut().dirty.insert(id);
+        }
+        merged.next_available_account = merged
+            .next_available_account
+            .zip(b.next_available_account)
+            .map(|(a, b)| a.max(b));
+        let count = merged.tx_counter.count_in(merged.epoch) + b.tx_counter.count_in(b.epoch);
+        if count > 0 {
+            merged.tx_counter = TxCounter {
+                count,
+                epoch: merged.epoch,
+            };
+            merged
+                .account_tree
+                .get_mut()
+                .tree
+                .update(
+                    WITHDRAWAL_ACCOUNT.0 as usize,
+                    &merged.tx_counter.to_bytes_le(),
+                )
+                .expect("should exist");
+        }
+        Ok(merged)
+    }
+
+    /// Write the ledger to the file at `path`, so that it can be reloaded with
+    /// `load`, e.g., when the sequencer restarts. The file holds the information
+    /// of every account and the counters of the ledger, along with the root of
+    /// the account tree, but not the ledger parameters nor the tree itself: the
+    /// leaves are recomputed from the accounts when the ledger is loaded.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let accounts = self
+            .iter_accounts()
+            .map(|(id, info)| (id.0, *info))
+            .collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        (
+            self.root(),
+            self.account_tree.borrow().tree.height() as u64,
+            self.next_available_account.map(|id| id.0),
+            accounts,
+        )
+            .serialize(&mut bytes)
+            .and_then(|()| {
+                (
+                    self.epoch,
+                    (self.tx_counter.count, self.tx_counter.epoch),
+                    self.block_commitment,
+                    self.base_fee.0,
+                )
+                    .serialize(&mut bytes)
+            })
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        std::fs::write(path, bytes)
+    }
+
+    /// Load the ledger written by `save` from the file at `path`, under the
+    /// parameters `parameters`. Returns an error if the file doesn't hold a valid
+    /// ledger, or if its accounts don't hash to the saved root, e.g., because
+    /// the ledger was saved under other parameters.
+    pub fn load(
+        path: impl AsRef<std::path::Path>,
+        parameters: &Parameters,
+    ) -> std::io::Result<Self> {
+        let invalid = |e| Error::new(ErrorKind::InvalidData, e);
+        let bytes = std::fs::read(path)?;
+        let mut reader = &bytes[..];
+        let (root, height, next_available_account, accounts) =
+            <(AccRoot, u64, Option<u8>, Vec<(u8, AccountInformation)>)>::deserialize(&mut reader)
+                .map_err(invalid)?;
+        let (epoch, (count, counter_epoch), block_commitment, base_fee) =
+            <(u64, (u64, u64), BlockCommitment, u64)>::deserialize(&mut reader).map_err(invalid)?;
+        if !(2..=9).contains(&height) || !reader.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the ledger is malformed",
+            ));
+        }
+
+        let mut state = Self::new(1 << height, parameters);
+        let num_leaves = 1 << (height - 1);
+        for (id, info) in accounts {
+            let id = AccountId(id);
+            if id == WITHDRAWAL_ACCOUNT
+                || id.0 as usize >= num_leaves
+                || state.id_to_account_info.contains_key(&id)
+                || state.pub_key_to_id.contains_key(&info.public_key)
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("account {} is malformed", id.0),
+                ));
+            }
+            state.pub_key_to_id.insert(info.public_key, id);
+            state.id_to_account_info.insert(id, info);
+            state.account_tree.get_mut().dirty.insert(id);
+        }
+        state.next_available_account = next_available_account.map(AccountId);
+        state.epoch = epoch;
+        state.tx_counter = TxCounter {
+            count,
+            epoch: counter_epoch,
+        };
+        state.block_commitment = block_commitment;
+        state.base_fee = Amount(base_fee);
+        // The counter of a fresh ledger hashes to the same leaf as a blank one,
+        // and so the leaf can be written either way.
+        state
+            .account_tree
+            .get_mut()
+            .tree
+            .update(
+                WITHDRAWAL_ACCOUNT.0 as usize,
+                &state.tx_counter.to_bytes_le(),
+            )
+            .expect("should exist");
+        if state.root() != root {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the accounts don't match the saved root",
+            ));
+        }
+        Ok(state)
+    }
+
+    /// Return the account Merkle tree, after recomputing the leaves of the
+    /// accounts that were updated since it was last read.
+    pub fn account_merkle_tree(&self) -> Ref<'_, AccMerkleTree> {
+        {
+            let mut account_tree = self.account_tree.borrow_mut();
+            let AccountTree { tree, dirty } = &mut *account_tree;
+            for id in std::mem::take(dirty) {
+                let commitment = self.id_to_account_info[&id].commitment(&self.account_crh_params);
+                let leaf = personalize_leaf(
+                    self.leaf_personalization,
+                    LeafDomain::Account,
+                    mark_commitment(&commitment),
+                );
+                tree.update(id.0 as usize, &leaf).expect("should exist");
+            }
         }
+        Ref::map(self.account_tree.borrow(), |account_tree| {
+            &account_tree.tree
+        })
     }
 
-    /// Return the root of the account Merkle tree.
+    /// Return the root of the account Merkle tree. The root is cached, and is
+    /// only recomputed if an account was updated since it was last read.
     pub fn root(&self) -> AccRoot {
-        self.account_merkle_tree.root()
+        self.account_merkle_tree().root()
+    }
+
+    /// Iterate over the accounts of the ledger, in the order of their leaves in
+    /// the account Merkle tree, i.e., by identifier. Only occupied leaves are
+    /// visited, so the leaf of the transaction counter is skipped.
+    pub fn iter_accounts(&self) -> impl Iterator<Item = (AccountId, &AccountInformation)> {
+        let mut ids = self.id_to_account_info.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(move |id| (id, &self.id_to_account_info[&id]))
+    }
+
+    /// Return the sum of the balances of all accounts, for each asset, or `None`
+    /// if this overflows.
+    pub fn total_supply(&self) -> Option<Balances> {
+        let mut supply = Balances::default();
+        for (_, info) in self.iter_accounts() {
+            for (total, balance) in supply.0.iter_mut().zip(&info.balances.0) {
+                *total = total.checked_add(*balance)?;
+            }
+        }
+        Some(supply)
+    }
+
+    /// Return a proof of the information of the account `id`, if `id` exists.
+    pub fn prove_account(&self, id: AccountId) -> Option<AccountProof> {
+        let info = *self.id_to_account_info.get(&id)?;
+        let path = self
+            .account_merkle_tree()
+            .generate_proof(id.0 as usize)
+            .expect("path should exist");
+        Some(AccountProof { info, path })
+    }
+
+    /// Return the path of the leaf storing the transaction counter.
+    pub fn prove_tx_counter(&self) -> AccPath {
+        self.account_merkle_tree()
+            .generate_proof(WITHDRAWAL_ACCOUNT.0 as usize)
+            .expect("path should exist")
+    }
+
+    /// Record that a batch of `num_transactions` transactions was applied during
+    /// the current epoch, if the ledger limits the number of transactions per
+    /// epoch. Returns `None`, and leaves the counter unchanged, if this exceeds
+    /// the limit.
+    pub fn record_batch(&mut self, pp: &Parameters, num_transactions: u64) -> Option<()> {
+        if let Some(max_per_epoch) = pp.max_per_epoch {
+            let count = self
+                .tx_counter
+                .count_in(self.epoch)
+                .checked_add(num_transactions)?;
+            if count > max_per_epoch {
+                return None;
+            }
+            self.tx_counter = TxCounter {
+                count,
+                epoch: self.epoch,
+            };
+            // Leaves are independent, so the counter can be updated before the
+            // dirty accounts are.
+            self.account_tree
+                .get_mut()
+                .tree
+                .update(
+                    WITHDRAWAL_ACCOUNT.0 as usize,
+                    &self.tx_counter.to_bytes_le(),
+                )
+                .expect("should exist");
+        }
+        Some(())
     }
 
     /// Create a new account with public key `pub_key`. Returns a fresh account identifier
     /// if there is space for a new account, and returns `None` otherwise.
-    /// The initial balance of the new account is 0.
+    /// The initial balance of the new account is 0, for every asset.
     pub fn register(&mut self, public_key: AccountPublicKey) -> Option<AccountId> {
         self.next_available_account.and_then(|id| {
             // Construct account information for the new account.
-            let account_info = AccountInformation {
-                public_key,
-                balance: Amount(0),
-            };
+            let account_info = AccountInformation::new(public_key);
             // Insert information into the relevant accounts.
             self.pub_key_to_id.insert(public_key, id);
-            self.account_merkle_tree
-                .update(id.0 as usize, &account_info.to_bytes_le())
-                .expect("should exist");
             self.id_to_account_info.insert(id, account_info);
+            self.account_tree.get_mut().dirty.insert(id);
             // Increment the next account identifier.
             self.next_available_account
                 .as_mut()
@@ -158,38 +1305,286 @@ impl State {
         self.register(pub_key).map(|id| (id, pub_key, secret_key))
     }
 
-    /// Update the balance of `id` to `new_amount`.
+    /// Update the information of `id` by applying `f` to it.
+    /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
+    /// otherwise.
+    fn update_account_info(
+        &mut self,
+        id: AccountId,
+        f: impl FnOnce(&mut AccountInformation),
+    ) -> Option<()> {
+        let account_info = self.id_to_account_info.get_mut(&id)?;
+        f(account_info);
+        self.account_tree.get_mut().dirty.insert(id);
+        Some(())
+    }
+
+    /// Update the balances of `id` to `new_balances`.
+    /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
+    /// otherwise.
+    pub fn update_balances(&mut self, id: AccountId, new_balances: Balances) -> Option<()> {
+        self.update_account_info(id, |account_info| account_info.balances = new_balances)
+    }
+
+    /// Update the balance of `id` in the asset `asset` to `new_amount`.
+    /// Returns `Some(())` if an account with identifier `id` exists already and
+    /// `asset` is supported, and `None` otherwise.
+    pub fn update_asset_balance(
+        &mut self,
+        id: AccountId,
+        asset: AssetId,
+        new_amount: Amount,
+    ) -> Option<()> {
+        let mut balances = self.id_to_account_info.get(&id)?.balances;
+        balances.set(asset, new_amount)?;
+        self.update_balances(id, balances)
+    }
+
+    /// Update the balance of `id` in the native asset to `new_amount`.
     /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
     /// otherwise.
     pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Option<()> {
-        let tree = &mut self.account_merkle_tree;
-        self.id_to_account_info.get_mut(&id).map(|account_info| {
-            account_info.balance = new_amount;
-            tree.update(id.0 as usize, &account_info.to_bytes_le())
-                .expect("should exist");
+        self.update_asset_balance(id, AssetId::NATIVE, new_amount)
+    }
+
+    /// Check that applying the balance updates `updates` to `pre_state`, and
+    /// nothing else, leads to the root `claimed_post_root`. Each update sets the
+    /// balance of an account in the native asset, in order, so that an auditor
+    /// can check a claimed list of updates against the published roots. Returns
+    /// `false` if any updated account doesn't exist in `pre_state`.
+    pub fn verify_updates(
+        pre_state: &Self,
+        updates: &[(AccountId, Amount)],
+        claimed_post_root: &AccRoot,
+    ) -> bool {
+        let mut post_state = pre_state.clone();
+        for &(id, new_balance) in updates {
+            if post_state.update_balance(id, new_balance).is_none() {
+                return false;
+            }
+        }
+        post_state.root() == *claimed_post_root
+    }
+
+    /// Turn the account `id` into a multisig account controlled by `signers`.
+    /// Returns `Some(())` if an account with identifier `id` exists already, and
+    /// `None` otherwise.
+    pub fn set_signers(
+        &mut self,
+        pp: &Parameters,
+        id: AccountId,
+        signers: &SignerSet,
+    ) -> Option<()> {
+        let commitment = signers.commitment(pp);
+        self.update_account_info(id, |account_info| {
+            account_info.signers = commitment;
+            account_info.threshold = signers.threshold;
+        })
+    }
+
+    /// Set whether the account `id` accepts transfers without the consent of its
+    /// owner. Returns `Some(())` if an account with identifier `id` exists
+    /// already, and `None` otherwise.
+    pub fn set_accepts_transfers(&mut self, id: AccountId, accepts_transfers: bool) -> Option<()> {
+        self.update_account_info(id, |account_info| {
+            account_info.accepts_transfers = accepts_transfers
+        })
+    }
+
+    /// Cap the balance that transfers may credit the account `id` up to at
+    /// `balance_cap`, or lift the cap if it is zero. Returns `Some(())` if an
+    /// account with identifier `id` exists already, and `None` otherwise.
+    pub fn set_balance_cap(&mut self, id: AccountId, balance_cap: Amount) -> Option<()> {
+        self.update_account_info(id, |account_info| account_info.balance_cap = balance_cap)
+    }
+
+    /// Let the key `recovery_key` rotate the key of the account `id` if it is
+    /// lost, or remove its recovery key if `recovery_key` is the identity.
+    /// Returns `Some(())` if an account with identifier `id` exists already, and
+    /// `None` otherwise.
+    pub fn set_recovery_key(
+        &mut self,
+        id: AccountId,
+        recovery_key: AccountPublicKey,
+    ) -> Option<()> {
+        self.update_account_info(id, |account_info| account_info.recovery_key = recovery_key)
+    }
+
+    /// Restrict the recipients that the account `id` may pay to those on the
+    /// allowlist with commitment `allowlist`, or lift the restriction if it is
+    /// zero. Returns `Some(())` if an account with identifier `id` exists
+    /// already, and `None` otherwise.
+    pub fn set_allowlist(&mut self, id: AccountId, allowlist: AllowlistCommitment) -> Option<()> {
+        self.update_account_info(id, |account_info| account_info.allowlist = allowlist)
+    }
+
+    /// Attach the metadata with hash `metadata_hash` to the account `id`, or
+    /// detach its metadata if it is zero. Returns `Some(())` if an account with
+    /// identifier `id` exists already, and `None` otherwise.
+    pub fn set_metadata_hash(&mut self, id: AccountId, metadata_hash: MetadataHash) -> Option<()> {
+        self.update_account_info(id, |account_info| {
+            account_info.metadata_hash = metadata_hash
         })
     }
 
     /// Update the state by applying the transaction `tx`, if `tx` is valid.
+    /// Only the balances of the asset `tx.asset_id` change, and the nonce of the
+    /// sender is incremented.
     pub fn apply_transaction(&mut self, pp: &Parameters, tx: &Transaction) -> Option<()> {
         if tx.validate(pp, self) {
-            let old_sender_bal = self.id_to_account_info.get(&tx.sender)?.balance;
-            let old_receiver_bal = self.id_to_account_info.get(&tx.recipient)?.balance;
-            let new_sender_bal = old_sender_bal.checked_sub(tx.amount)?;
-            let new_receiver_bal = old_receiver_bal.checked_add(tx.amount)?;
-            self.update_balance(tx.sender, new_sender_bal);
-            self.update_balance(tx.recipient, new_receiver_bal);
+            let (new_sender_info, new_recipient_info) = tx.new_account_infos(
+                pp,
+                self.id_to_account_info.get(&tx.sender)?,
+                self.id_to_account_info.get(&tx.recipient),
+                self.epoch,
+            )?;
+            if let Some(new_recipient_info) = new_recipient_info {
+                self.update_account_info(tx.recipient, |info| *info = new_recipient_info)?;
+            }
+            self.update_account_info(tx.sender, |info| *info = new_sender_info)
+        } else {
+            None
+        }
+    }
+
+    /// Split the batch `transactions` into the transactions that are valid when
+    /// applied in order, which are applied to the state, and the indices of the
+    /// invalid ones along with why they can't be applied. Proving a batch fails
+    /// if any of its transactions is invalid, whereas the valid ones can be
+    /// proven on their own.
+    pub fn partition_batch(
+        &mut self,
+        pp: &Parameters,
+        transactions: &[Transaction],
+    ) -> (Vec<Transaction>, Vec<(usize, SimulationError)>) {
+        let mut valid = Vec::new();
+        let mut invalid = Vec::new();
+        for (i, tx) in transactions.iter().enumerate() {
+            let applied = tx.simulate(pp, self).and_then(|_| {
+                self.apply_transaction(pp, tx)
+                    .ok_or(SimulationError::Invalid)
+            });
+            match applied {
+                Ok(()) => valid.push(tx.clone()),
+                Err(error) => invalid.push((i, error)),
+            }
+        }
+        (valid, invalid)
+    }
+
+    /// Apply the batch `transactions` in order, like `apply_transaction`, and
+    /// return the roots of the account tree along the way: the `i`-th
+    /// intermediate root is the root before the `i`-th transaction, i.e., after
+    /// the first `i` ones, and the final root is the root after all of them.
+    /// Returns `None` if any of the transactions is invalid, in which case the
+    /// transactions before it remain applied.
+    pub fn apply_batch_with_roots(
+        &mut self,
+        pp: &Parameters,
+        transactions: &[Transaction],
+    ) -> Option<(Vec<AccRoot>, AccRoot)> {
+        let mut roots = Vec::with_capacity(transactions.len());
+        for tx in transactions {
+            roots.push(self.root());
+            self.apply_transaction(pp, tx)?;
+        }
+        Some((roots, self.root()))
+    }
+
+    /// Apply the batch `transactions` in order, skipping the invalid ones as
+    /// `partition_batch` does, and return the final root of the account tree
+    /// along with the audit trail of the batch, which has an event for each of
+    /// the transactions, in order.
+    pub fn apply_batch_with_trail(
+        &mut self,
+        pp: &Parameters,
+        transactions: &[Transaction],
+    ) -> (AccRoot, Vec<BatchEvent>) {
+        let events = transactions
+            .iter()
+            .enumerate()
+            .map(|(index, tx)| {
+                let outcome = tx.simulate(pp, self).and_then(|_| {
+                    self.apply_transaction(pp, tx)
+                        .ok_or(SimulationError::Invalid)
+                });
+                let balance = |id| self.id_to_account_info.get(&id)?.balances.get(tx.asset_id);
+                BatchEvent {
+                    index,
+                    sender: tx.sender,
+                    recipient: tx.recipient,
+                    asset_id: tx.asset_id,
+                    amount: tx.amount,
+                    outcome,
+                    sender_balance: balance(tx.sender),
+                    recipient_balance: balance(tx.recipient),
+                }
+            })
+            .collect();
+        (self.root(), events)
+    }
+
+    /// Update the state by applying the swap `swap`, if `swap` is valid. The
+    /// nonces of both parties are incremented.
+    pub fn apply_swap(&mut self, pp: &Parameters, swap: &Swap) -> Option<()> {
+        if swap.validate(pp, self) {
+            let (new_bal_a, new_bal_b) = swap.new_balances(
+                self.id_to_account_info.get(&swap.party_a)?,
+                self.id_to_account_info.get(&swap.party_b)?,
+            )?;
+            for (id, new_balances) in [(swap.party_a, new_bal_a), (swap.party_b, new_bal_b)] {
+                self.update_account_info(id, |info| {
+                    info.balances = new_balances;
+                    info.nonce = info.nonce.wrapping_add(1);
+                });
+            }
             Some(())
         } else {
             None
         }
     }
+
+    /// Update the state by applying the escrow operation `escrow`, if `escrow`
+    /// is valid. The nonce of the account is incremented.
+    pub fn apply_escrow(&mut self, pp: &Parameters, escrow: &Escrow) -> Option<()> {
+        if escrow.validate(pp, self) {
+            let new_held = escrow.new_held(self.id_to_account_info.get(&escrow.account)?)?;
+            self.update_account_info(escrow.account, |info| {
+                info.held = new_held;
+                info.nonce = info.nonce.wrapping_add(1);
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Update the state by applying the key rotation `rotation`, if `rotation`
+    /// is valid. The nonce of the account is incremented.
+    pub fn apply_rotation(&mut self, pp: &Parameters, rotation: &RotateKey) -> Option<()> {
+        if rotation.validate(pp, self) {
+            let old_public_key = self.id_to_account_info.get(&rotation.account)?.public_key;
+            self.pub_key_to_id.remove(&old_public_key);
+            self.pub_key_to_id
+                .insert(rotation.new_public_key, rotation.account);
+            self.update_account_info(rotation.account, |info| {
+                info.public_key = rotation.new_public_key;
+                info.nonce = info.nonce.wrapping_add(1);
+            })
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{AccountId, Amount, Parameters, State};
-    use crate::transaction::Transaction;
+    use super::{
+        leaf_index, personalize_leaf, verify_inclusion, AccMerkleTree, AccountId, Amount, AssetId,
+        BatchEvent, IncrementalUpdate, LayeredLedger, LeafDomain, LeafHash, Parameters,
+        SortedIdTree, State, TxCounter, NUM_ASSETS,
+    };
+    use crate::transaction::{SimulationError, Transaction};
+    use ark_crypto_primitives::crh::CRH;
 
     #[test]
     fn end_to_end() {
@@ -210,20 +1605,807 @@ mod test {
         let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
         assert!(tx1.validate(&pp, &state));
         state.apply_transaction(&pp, &tx1).expect("should work");
+        // Only the sender's nonce is incremented.
+        assert_eq!(state.id_to_account_info[&alice_id].nonce, 1);
+        assert_eq!(state.id_to_account_info[&bob_id].nonce, 0);
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
         let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        assert!(state.apply_transaction(&pp, &bad_tx).is_none());
         // Next, let's try a transaction where the signature is incorrect:
         let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        assert!(state.apply_transaction(&pp, &bad_tx).is_none());
 
         // Finally, let's try a transaction to an non-existant account:
         let bad_tx =
             Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        assert!(state.apply_transaction(&pp, &bad_tx).is_none());
+    }
+
+    #[test]
+    fn partition_batch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng),
+            // Alice can't afford this after the first transaction, ...
+            Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng),
+            // ... nor can anyone pay an account that doesn't exist, ...
+            Transaction::create(&pp, bob_id, AccountId(7), Amount(1), &bob_sk, &mut rng),
+            // ... but Bob can pay with what he received.
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+        ];
+        let mut replayed = state.clone();
+
+        let (valid, invalid) = state.partition_batch(&pp, &transactions);
+        let ids = |txs: &[Transaction]| txs.iter().map(|tx| tx.id(&pp)).collect::<Vec<_>>();
+        assert_eq!(
+            ids(&valid),
+            ids(&[transactions[0].clone(), transactions[3].clone()])
+        );
+        assert_eq!(
+            invalid,
+            vec![
+                (1, SimulationError::InsufficientFunds),
+                (2, SimulationError::UnknownRecipient)
+            ]
+        );
+        // The valid transactions lead to the same root on their own.
+        for tx in &valid {
+            replayed.apply_transaction(&pp, tx).unwrap();
+        }
+        assert_eq!(replayed.root(), state.root());
+        let balance = state.id_to_account_info[&alice_id]
+            .balances
+            .get(AssetId::NATIVE);
+        assert_eq!(balance, Some(Amount(6)));
+    }
+
+    #[test]
+    fn apply_batch_with_trail_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng),
+            // Alice can't afford this after the first transaction, ...
+            Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng),
+            // ... but Bob can pay with what he received.
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+        ];
+        let mut replayed = state.clone();
+
+        let (root, trail) = state.apply_batch_with_trail(&pp, &transactions);
+        let event = |index, sender, recipient, amount, outcome, balances: [u64; 2]| BatchEvent {
+            index,
+            sender,
+            recipient,
+            asset_id: AssetId::NATIVE,
+            amount: Amount(amount),
+            outcome,
+            sender_balance: Some(Amount(balances[0])),
+            recipient_balance: Some(Amount(balances[1])),
+        };
+        assert_eq!(
+            trail,
+            vec![
+                event(0, alice_id, bob_id, 6, Ok(()), [4, 6]),
+                event(
+                    1,
+                    alice_id,
+                    bob_id,
+                    6,
+                    Err(SimulationError::InsufficientFunds),
+                    [4, 6]
+                ),
+                event(2, bob_id, alice_id, 2, Ok(()), [4, 6]),
+            ]
+        );
+        assert_eq!(
+            trail[1].to_string(),
+            format!(
+                "#1: 6 of asset 0 from account {} to account {}: rejected, since the sender \
+                 has insufficient funds; balances 4 and 6",
+                alice_id.0, bob_id.0
+            )
+        );
+        // The trail is taken along the same root as the applied transactions.
+        assert_eq!(root, state.root());
+        replayed.partition_batch(&pp, &transactions);
+        assert_eq!(replayed.root(), root);
+    }
+
+    #[test]
+    fn apply_batch_with_roots_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(3), &alice_sk, &mut rng),
+        ];
+
+        let initial_state = state.clone();
+        let (roots, final_root) = state.apply_batch_with_roots(&pp, &transactions).unwrap();
+        assert_eq!(roots.len(), transactions.len());
+        assert_eq!(final_root, state.root());
+        // The `i`-th intermediate root is the root after the first `i`
+        // transactions, ...
+        for (i, root) in roots.iter().enumerate() {
+            let mut expected = initial_state.clone();
+            for tx in &transactions[..i] {
+                expected.apply_transaction(&pp, tx).unwrap();
+            }
+            assert_eq!(*root, expected.root());
+        }
+        // ... and there are no roots if a transaction is invalid.
+        let overdraft = Transaction::create(&pp, bob_id, alice_id, Amount(100), &bob_sk, &mut rng);
+        assert!(state.apply_batch_with_roots(&pp, &[overdraft]).is_none());
+    }
+
+    #[test]
+    fn iter_accounts_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let mut total = 0;
+        let mut accounts = Vec::new();
+        for balance in [7, 0, 12] {
+            let (id, _pk, sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(id, Amount(balance)).unwrap();
+            total += balance;
+            accounts.push((id, sk));
+        }
+        // A transfer moves funds between accounts, but doesn't change the total.
+        let (alice_id, alice_sk) = &accounts[0];
+        let tx = Transaction::create(&pp, *alice_id, accounts[1].0, Amount(3), alice_sk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+
+        let ids = state.iter_accounts().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, accounts.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+        let sum = state
+            .iter_accounts()
+            .map(|(_, info)| info.balances.get(AssetId::NATIVE).unwrap().0)
+            .sum::<u64>();
+        assert_eq!(sum, total);
+    }
+
+    #[test]
+    fn multi_asset_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(alice_id, AssetId(1), Amount(10))
+            .unwrap();
+        state
+            .update_asset_balance(alice_id, AssetId(2), Amount(7))
+            .unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice transfers 4 units of asset 1 to Bob, and pays a fee of 1 unit.
+        let tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(4),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).expect("should work");
+        let alice_balances = state.id_to_account_info[&alice_id].balances;
+        let bob_balances = state.id_to_account_info[&bob_id].balances;
+        assert_eq!(alice_balances.get(AssetId(1)), Some(Amount(5)));
+        assert_eq!(bob_balances.get(AssetId(1)), Some(Amount(4)));
+        // The balances of the other assets are unaffected.
+        assert_eq!(alice_balances.get(AssetId(2)), Some(Amount(7)));
+        assert_eq!(bob_balances.get(AssetId(2)), Some(Amount(0)));
+        assert_eq!(alice_balances.get(AssetId::NATIVE), Some(Amount(0)));
+
+        // Alice can't spend her balance of asset 2 as asset 1, ...
+        let bad_tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(6),
+            Amount(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_tx.validate(&pp, &state));
+        // ... and assets that the ledger doesn't track can't be transferred.
+        let bad_tx = Transaction::create_with_asset(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(NUM_ASSETS as u32),
+            Amount(1),
+            Amount(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_tx.validate(&pp, &state));
+        assert!(state
+            .update_asset_balance(alice_id, AssetId(NUM_ASSETS as u32), Amount(1))
+            .is_none());
+    }
+
+    #[test]
+    fn daily_limit_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.daily_limit = Some(Amount(10));
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(100)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut transfer = |amount| {
+            Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng)
+        };
+
+        // Alice can send up to 10 units per epoch, ...
+        let (tx1, tx2, tx3) = (transfer(6), transfer(6), transfer(4));
+        state.apply_transaction(&pp, &tx1).unwrap();
+        assert!(state.apply_transaction(&pp, &tx2).is_none());
+        state.apply_transaction(&pp, &tx3).unwrap();
+        assert_eq!(state.id_to_account_info[&alice_id].spent_today, Amount(10));
+
+        // ... and her spending is reset in the next epoch.
+        state.epoch += 1;
+        state.apply_transaction(&pp, &tx2).unwrap();
+        let alice_info = state.id_to_account_info[&alice_id];
+        assert_eq!(alice_info.spent_in(state.epoch), Amount(6));
+        assert_eq!(alice_info.balances.get(AssetId::NATIVE), Some(Amount(84)));
+    }
+
+    #[test]
+    fn withdrawal_grace_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.withdrawal_grace = Some(2);
+        let mut state = State::new(32, &pp);
+        state.epoch = 5;
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(100)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut withdrawal = |id, sk| {
+            Transaction::create_withdrawal(
+                &pp,
+                id,
+                AssetId::NATIVE,
+                Amount(5),
+                Amount(0),
+                Default::default(),
+                sk,
+                &mut rng,
+            )
+        };
+        let (alice_withdrawal, bob_withdrawal) =
+            (withdrawal(alice_id, &alice_sk), withdrawal(bob_id, &bob_sk));
+
+        // Alice was never credited by a transfer, so she can withdraw, ...
+        state.apply_transaction(&pp, &alice_withdrawal).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(10), &alice_sk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(state.id_to_account_info[&bob_id].last_credited_epoch, 5);
+
+        // ... while Bob, who was just credited, has to wait for two epochs.
+        assert!(state.apply_transaction(&pp, &bob_withdrawal).is_none());
+        state.epoch += 1;
+        assert!(state.apply_transaction(&pp, &bob_withdrawal).is_none());
+        state.epoch += 1;
+        state.apply_transaction(&pp, &bob_withdrawal).unwrap();
+        let bob_info = state.id_to_account_info[&bob_id];
+        assert_eq!(bob_info.balances.get(AssetId::NATIVE), Some(Amount(5)));
+    }
+
+    #[test]
+    fn incremental_update_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut leaves = vec![vec![0u8; 32]; 8];
+        let new_tree = |leaves: &[Vec<u8>]| {
+            AccMerkleTree::new(&pp.leaf_crh_params, &pp.two_to_one_crh_params, leaves).unwrap()
+        };
+        let mut tree = new_tree(&leaves);
+        for (index, byte) in [(3, 1u8), (0, 2), (3, 3), (7, 4)] {
+            let pre_root = tree.root();
+            let new_leaf = vec![byte; 32];
+            let (pre_path, new_root) = tree.update_with_path(index, &new_leaf).unwrap();
+            let old_leaf = std::mem::replace(&mut leaves[index], new_leaf);
+            // The incrementally updated root matches a full recomputation, ...
+            assert_eq!(new_root, tree.root());
+            assert_eq!(new_root, new_tree(&leaves).root());
+            // ... and the returned path proves both the old and the new leaf.
+            assert_eq!(pre_path.leaf_index, index);
+            assert!(pre_path
+                .verify(
+                    &pp.leaf_crh_params,
+                    &pp.two_to_one_crh_params,
+                    &pre_root,
+                    &old_leaf,
+                )
+                .unwrap());
+            assert!(pre_path
+                .verify(
+                    &pp.leaf_crh_params,
+                    &pp.two_to_one_crh_params,
+                    &new_root,
+                    &leaves[index],
+                )
+                .unwrap());
+            assert_ne!(pre_root, new_root);
+        }
+    }
+
+    #[test]
+    fn cached_root_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(16, &pp);
+        // Recompute the root from scratch, from the information of each account.
+        let fresh_root = |state: &State| {
+            let mut leaves = vec![vec![0u8; 32]; 8];
+            for (id, info) in &state.id_to_account_info {
+                leaves[id.0 as usize] = info.to_leaf(&pp);
+            }
+            AccMerkleTree::new(&pp.leaf_crh_params, &pp.two_to_one_crh_params, &leaves)
+                .unwrap()
+                .root()
+        };
+        assert_eq!(state.root(), fresh_root(&state));
+
+        let mut accounts = Vec::new();
+        for _ in 0..3 {
+            let (id, _pk, sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            accounts.push((id, sk));
+        }
+        assert_eq!(state.root(), fresh_root(&state));
+
+        // Interleave updates of the same and of different accounts with reads,
+        // sometimes updating several times in between two reads.
+        for step in 0..12u64 {
+            let (id, sk) = &accounts[step as usize % accounts.len()];
+            state.update_balance(*id, Amount(10 + step)).unwrap();
+            if step % 3 == 0 {
+                let (recipient, _) = &accounts[(step as usize + 1) % accounts.len()];
+                let tx = Transaction::create(&pp, *id, *recipient, Amount(step + 1), sk, &mut rng);
+                state.apply_transaction(&pp, &tx).unwrap();
+            }
+            if step % 2 == 0 {
+                assert_eq!(state.root(), fresh_root(&state));
+                // Reading the root again doesn't change it.
+                assert_eq!(state.root(), fresh_root(&state));
+            }
+        }
+        assert_eq!(state.root(), fresh_root(&state));
+
+        // A clone shares no cache with the original.
+        let mut clone = state.clone();
+        clone.update_balance(accounts[0].0, Amount(1)).unwrap();
+        assert_ne!(clone.root(), state.root());
+        assert_eq!(clone.root(), fresh_root(&clone));
+        assert_eq!(state.root(), fresh_root(&state));
+    }
+
+    #[test]
+    fn tx_counter_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        // Without a limit, batches are not counted.
+        assert!(state.record_batch(&pp, 100).is_some());
+        assert_eq!(state.tx_counter, TxCounter::default());
+
+        pp.max_per_epoch = Some(3);
+        let blank_root = state.root();
+        assert!(state.record_batch(&pp, 2).is_some());
+        assert_eq!(state.tx_counter.count_in(0), 2);
+        assert_ne!(state.root(), blank_root);
+        // The counter is stored in its leaf.
+        assert!(state
+            .prove_tx_counter()
+            .verify(
+                &pp.leaf_crh_params,
+                &pp.two_to_one_crh_params,
+                &state.root(),
+                &state.tx_counter.to_bytes_le(),
+            )
+            .unwrap());
+
+        // A batch exceeding the limit is rejected, ...
+        let root = state.root();
+        assert!(state.record_batch(&pp, 2).is_none());
+        assert_eq!(state.tx_counter.count_in(0), 2);
+        assert_eq!(state.root(), root);
+        assert!(state.record_batch(&pp, 1).is_some());
+        // ... until the next epoch starts.
+        state.epoch += 1;
+        assert_eq!(state.tx_counter.count_in(1), 0);
+        assert!(state.record_batch(&pp, 3).is_some());
+        assert_eq!(state.tx_counter, TxCounter { count: 3, epoch: 1 });
+    }
+
+    #[test]
+    fn layered_ledger_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        // Four bottom trees of four leaves each.
+        let mut ledger = LayeredLedger::blank(&pp, 3, 3);
+        let blank_root = ledger.root();
+        ledger.update(5, &[1u8; 32]).unwrap();
+        ledger.update(10, &[2u8; 32]).unwrap();
+        assert_ne!(ledger.root(), blank_root);
+
+        let root = ledger.root();
+        let path = ledger.prove(5).unwrap();
+        assert!(path.verify(&pp, 5, &root, &[1u8; 32]).unwrap());
+        assert!(!path.verify(&pp, 5, &root, &[2u8; 32]).unwrap());
+        // The leaf is at the same position in its bottom tree as leaf 9 is in
+        // the next one, but the proof only holds for the right bottom tree.
+        assert!(!path.verify(&pp, 9, &root, &[1u8; 32]).unwrap());
+        let mut wrong_path = path.clone();
+        wrong_path.top = ledger.prove(9).unwrap().top;
+        assert!(!wrong_path.verify(&pp, 9, &root, &[1u8; 32]).unwrap());
+        // Updating another bottom tree changes the top root.
+        assert!(ledger
+            .prove(10)
+            .unwrap()
+            .verify(&pp, 10, &root, &[2u8; 32])
+            .unwrap());
+        ledger.update(10, &[3u8; 32]).unwrap();
+        assert!(!path.verify(&pp, 5, &ledger.root(), &[1u8; 32]).unwrap());
+    }
+
+    #[test]
+    fn sorted_id_tree_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let ids = [AccountId(9), AccountId(3), AccountId(200), AccountId(42)];
+        let tree = SortedIdTree::new(&pp, 4, &ids).unwrap();
+        let root = tree.root();
+
+        // Absent identifiers fall between two adjacent keys, including the padding.
+        for id in [4, 41, 100, 201, 255] {
+            let proof = tree.prove_non_membership(AccountId(id)).unwrap();
+            assert!(proof.verify(&pp, &root, AccountId(id)).unwrap());
+        }
+        // Identifiers in the tree can't be proven absent, ...
+        for id in ids {
+            assert!(tree.prove_non_membership(id).is_none());
+            let proof = tree.prove_non_membership(AccountId(id.0 + 1)).unwrap();
+            assert!(!proof.verify(&pp, &root, id).unwrap());
+        }
+        // ... and neither can those below the smallest key.
+        assert!(tree.prove_non_membership(AccountId(2)).is_none());
+        // The leaves of a proof must be adjacent.
+        let mut proof = tree.prove_non_membership(AccountId(41)).unwrap();
+        let far_proof = tree.prove_non_membership(AccountId(100)).unwrap();
+        proof.high_key = far_proof.high_key;
+        proof.high_path = far_proof.high_path;
+        assert!(!proof.verify(&pp, &root, AccountId(41)).unwrap());
+        // The tree must fit all identifiers.
+        assert!(SortedIdTree::new(&pp, 2, &ids).is_err());
+    }
+
+    #[test]
+    fn leaf_index_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let state = State::new(32, &pp);
+        let height = state.account_merkle_tree().height();
+        for id in [0u8, 1, 7, 15] {
+            let index = leaf_index(&AccountId(id), height).unwrap();
+            assert_eq!(index, id as u64);
+            let path = state
+                .account_merkle_tree()
+                .generate_proof(index as usize)
+                .unwrap();
+            assert_eq!(path.leaf_index as u64, index);
+        }
+        // Identifiers beyond the tree have no leaf, and neither does any
+        // identifier in a tree without leaves.
+        assert_eq!(leaf_index(&AccountId(32), height), None);
+        assert_eq!(leaf_index(&AccountId(0), 0), None);
+    }
+
+    #[test]
+    fn leaf_personalization_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        let bytes = vec![7u8; 32];
+        let hash = |pp: &Parameters, domain| {
+            let leaf = personalize_leaf(pp.leaf_personalization, domain, bytes.clone());
+            LeafHash::evaluate(&pp.leaf_crh_params, &leaf).unwrap()
+        };
+
+        // Without a personalization, an account leaf and a withdrawal leaf with
+        // the same bytes hash alike, ...
+        assert_eq!(
+            hash(&pp, LeafDomain::Account),
+            hash(&pp, LeafDomain::Withdrawal)
+        );
+        // ... but with one, they don't, and neither do leaves under another
+        // personalization.
+        let unpersonalized = hash(&pp, LeafDomain::Account);
+        pp.leaf_personalization = Some(*b"rollup1");
+        assert_ne!(
+            hash(&pp, LeafDomain::Account),
+            hash(&pp, LeafDomain::Withdrawal)
+        );
+        assert_ne!(
+            hash(&pp, LeafDomain::Withdrawal),
+            hash(&pp, LeafDomain::Transaction)
+        );
+        assert_ne!(hash(&pp, LeafDomain::Account), unpersonalized);
+        let mut other = pp.clone();
+        other.leaf_personalization = Some(*b"rollup2");
+        assert_ne!(
+            hash(&pp, LeafDomain::Account),
+            hash(&other, LeafDomain::Account)
+        );
+        assert_ne!(pp.commitment(), other.commitment());
+
+        // The account tree is built from personalized leaves, and so its proofs
+        // only verify under the same personalization.
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let proof = state.prove_account(alice_id).unwrap();
+        let root = state.root();
+        assert!(proof.verify(&pp, alice_id, &root).unwrap());
+        assert!(!proof.verify(&other, alice_id, &root).unwrap());
+        assert!(verify_inclusion(
+            &pp,
+            &root,
+            alice_id,
+            &proof.info,
+            &proof.path
+        ));
+    }
+
+    #[test]
+    fn genesis_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        // Register some accounts one by one, and fund them.
+        let mut state = State::new(32, &pp);
+        let mut accounts = Vec::new();
+        for balance in [10, 0, 25] {
+            let (id, pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(id, Amount(balance)).unwrap();
+            accounts.push((id, Amount(balance), pk));
+        }
+        let genesis = State::genesis(32, &accounts, &pp).unwrap();
+        assert_eq!(genesis.root(), state.root());
+        assert_eq!(genesis.next_available_account, state.next_available_account);
+
+        // Identifiers may have gaps, and registrations continue after the last one.
+        let (_, _, pk) = accounts[2];
+        let mut sparse = State::genesis(32, &[(AccountId(9), Amount(3), pk)], &pp).unwrap();
+        assert_eq!(sparse.next_available_account, Some(AccountId(10)));
+        assert_eq!(
+            sparse.prove_account(AccountId(9)).unwrap().info.public_key,
+            pk
+        );
+        let (new_id, _, _) = sparse.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(new_id, AccountId(10));
+
+        // Identifiers and keys can't be used twice, ...
+        let (id, _, pk) = accounts[0];
+        let mut duplicate = accounts.clone();
+        duplicate.push((id, Amount(1), accounts[1].2));
+        assert!(State::genesis(32, &duplicate, &pp).is_none());
+        assert!(State::genesis(32, &[(AccountId(4), Amount(1), pk), accounts[0]], &pp).is_none());
+        // ... and the withdrawal account and identifiers beyond the tree are reserved.
+        assert!(State::genesis(32, &[(AccountId(0), Amount(1), pk)], &pp).is_none());
+        assert!(State::genesis(32, &[(AccountId(16), Amount(1), pk)], &pp).is_none());
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        // Sample some accounts, and split them between two shards.
+        let mut state = State::new(32, &pp);
+        let mut accounts = Vec::new();
+        for balance in [10, 0, 25, 7] {
+            let (id, pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            accounts.push((id, Amount(balance), pk));
+        }
+        let shard = |accounts: &[_]| State::genesis(32, accounts, &pp).unwrap();
+        let (even, odd): (Vec<_>, Vec<_>) = accounts.iter().partition(|(id, _, _)| id.0 % 2 == 0);
+        let merged = State::merge(shard(&even), shard(&odd)).unwrap();
+        // The merged ledger is the one holding all accounts from the start.
+        let all = shard(&accounts);
+        assert_eq!(merged.root(), all.root());
+        assert_ne!(merged.root(), shard(&even).root());
+        assert_eq!(merged.next_available_account, all.next_available_account);
+        for (id, balance, pk) in &accounts {
+            let info = merged.prove_account(*id).unwrap().info;
+            assert_eq!(info.public_key, *pk);
+            assert_eq!(info.balances.get(AssetId::NATIVE), Some(*balance));
+        }
+
+        // Ledgers that share an account can't be merged, ...
+        assert!(State::merge(shard(&accounts[..2]), shard(&accounts[1..])).is_err());
+        // ... and neither can ledgers that register the same key under
+        // different identifiers.
+        let (id, balance, _) = accounts[0];
+        let (_, _, pk) = accounts[1];
+        assert!(State::merge(shard(&[(id, balance, pk)]), shard(&accounts[1..2])).is_err());
+        // The trees must also have the same height.
+        let small = State::genesis(8, &odd, &pp).unwrap();
+        assert!(State::merge(shard(&even), small).is_err());
+    }
+
+    #[test]
+    fn verify_inclusion_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // A light client only holds the published root.
+        let root = state.root();
+        let proof = state.prove_account(alice_id).unwrap();
+
+        // The account is included with its actual balance, ...
+        assert!(verify_inclusion(
+            &pp,
+            &root,
+            alice_id,
+            &proof.info,
+            &proof.path
+        ));
+        // ... but not with a tampered one, ...
+        let mut tampered = proof.info;
+        tampered.balances.set(AssetId::NATIVE, Amount(21)).unwrap();
+        assert!(!verify_inclusion(
+            &pp,
+            &root,
+            alice_id,
+            &tampered,
+            &proof.path
+        ));
+        // ... nor as another account, ...
+        assert!(!verify_inclusion(
+            &pp,
+            &root,
+            bob_id,
+            &proof.info,
+            &proof.path
+        ));
+        // ... nor once the root moves on.
+        state.update_balance(alice_id, Amount(15)).unwrap();
+        assert!(!verify_inclusion(
+            &pp,
+            &state.root(),
+            alice_id,
+            &proof.info,
+            &proof.path
+        ));
+    }
+
+    #[test]
+    fn verify_updates_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let pre_state = state.clone();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        let post_root = state.root();
+
+        // The updates of the balances alone don't account for the transfer, which
+        // also increments the nonce of Alice, ...
+        let updates = [(alice_id, Amount(15)), (bob_id, Amount(5))];
+        assert!(!State::verify_updates(&pre_state, &updates, &post_root));
+        // ... while they do account for a deposit to both accounts.
+        let mut deposited = pre_state.clone();
+        deposited.update_balance(alice_id, Amount(15)).unwrap();
+        deposited.update_balance(bob_id, Amount(5)).unwrap();
+        assert!(State::verify_updates(
+            &pre_state,
+            &updates,
+            &deposited.root()
+        ));
+        // A claimed root that is inconsistent with the updates is rejected, ...
+        let wrong_updates = [(alice_id, Amount(15)), (bob_id, Amount(6))];
+        assert!(!State::verify_updates(
+            &pre_state,
+            &wrong_updates,
+            &deposited.root()
+        ));
+        assert!(!State::verify_updates(
+            &pre_state,
+            &updates[..1],
+            &deposited.root()
+        ));
+        // ... as are updates to accounts that don't exist.
+        assert!(!State::verify_updates(
+            &pre_state,
+            &[(AccountId(7), Amount(1))],
+            &pre_state.root()
+        ));
+    }
+
+    #[test]
+    fn save_and_load_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.max_per_epoch = Some(10);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        state.record_batch(&pp, 1).unwrap();
+        state.base_fee = Amount(2);
+        let path = std::env::temp_dir().join(format!("ledger-{}.bin", std::process::id()));
+
+        // The reloaded ledger has the same root and accounts, and it goes on
+        // registering accounts where the saved one left off.
+        state.save(&path).unwrap();
+        let mut loaded = State::load(&path, &pp).unwrap();
+        assert_eq!(loaded.root(), state.root());
+        for id in [alice_id, bob_id] {
+            assert_eq!(
+                loaded.id_to_account_info[&id],
+                state.id_to_account_info[&id]
+            );
+        }
+        let balance = |id| loaded.id_to_account_info[&id].balances.get(AssetId::NATIVE);
+        assert_eq!(balance(alice_id), Some(Amount(6)));
+        assert_eq!(balance(bob_id), Some(Amount(4)));
+        assert_eq!(loaded.pub_key_to_id, state.pub_key_to_id);
+        assert_eq!(loaded.tx_counter, state.tx_counter);
+        assert_eq!(loaded.base_fee, Amount(2));
+        let (carol_id, _carol_pk, _carol_sk) =
+            loaded.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (other_id, _other_pk, _other_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(carol_id, other_id);
+
+        // The ledger can't be loaded under other parameters, or from a
+        // truncated file.
+        let other_pp = Parameters::sample(&mut rng);
+        assert!(State::load(&path, &other_pp).is_err());
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(State::load(&path, &pp).is_err());
+        std::fs::remove_file(&path).unwrap();
     }
 }
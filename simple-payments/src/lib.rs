@@ -1,6 +1,30 @@
 pub mod account;
+pub mod allowlist;
+pub mod asset_trees;
+pub mod batch;
+pub mod consent;
+pub mod delegation;
+pub mod diff;
+pub mod escrow;
+pub mod fee_market;
+pub mod hd;
+pub mod kary;
 pub mod ledger;
+pub mod mempool;
+pub mod metadata;
+pub mod multisig;
+pub mod oracle;
+pub mod ownership;
+pub mod pow;
+pub mod processed;
+pub mod receipt;
+pub mod replay;
+pub mod rotation;
+pub mod schedule;
+pub mod shard;
+pub mod swap;
 pub mod transaction;
+pub mod withdrawal;
 
 pub mod random_oracle;
 pub mod signature;
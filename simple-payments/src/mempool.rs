@@ -0,0 +1,166 @@
+use crate::ledger::{Parameters, State};
+use crate::transaction::Transaction;
+
+/// A pool of pending transactions, from which a sequencer builds batches.
+///
+/// Transactions paying higher fees are included first, and transactions paying
+/// the same fee are included in the order in which they were inserted.
+#[derive(Clone, Default)]
+pub struct Mempool {
+    /// The pending transactions. Transactions paying the same fee are kept in
+    /// insertion order.
+    pending: Vec<Transaction>,
+}
+
+impl Mempool {
+    /// Create an empty mempool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of pending transactions.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Is the mempool empty?
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Add `tx` to the mempool if it is valid with respect to `state`.
+    /// Returns whether `tx` was added.
+    pub fn insert(&mut self, parameters: &Parameters, state: &State, tx: Transaction) -> bool {
+        let valid = tx.validate(parameters, state);
+        if valid {
+            self.pending.push(tx);
+        }
+        valid
+    }
+
+    /// Remove the transactions that are not valid with respect to `state`, for
+    /// example because their sender no longer has enough funds. Returns the
+    /// number of evicted transactions.
+    pub fn evict_invalid(&mut self, parameters: &Parameters, state: &State) -> usize {
+        let len = self.pending.len();
+        self.pending.retain(|tx| tx.validate(parameters, state));
+        len - self.pending.len()
+    }
+
+    /// Remove and return a batch of at most `batch_size` transactions, picking
+    /// the highest-fee transactions first.
+    ///
+    /// The batch is valid when applied to `state` in order: a transaction that
+    /// becomes invalid after applying the previous transactions of the batch is
+    /// skipped and stays in the mempool. `state` itself is not modified.
+    pub fn pop_batch(
+        &mut self,
+        parameters: &Parameters,
+        state: &State,
+        batch_size: usize,
+    ) -> Vec<Transaction> {
+        self.evict_invalid(parameters, state);
+        // The sort is stable, so ties are broken by insertion order.
+        let mut by_fee = std::mem::take(&mut self.pending);
+        by_fee.sort_by_key(|tx| std::cmp::Reverse(tx.fee));
+
+        let mut state = state.clone();
+        let mut batch = Vec::with_capacity(batch_size);
+        for tx in by_fee {
+            if batch.len() < batch_size && state.apply_transaction(parameters, &tx).is_some() {
+                batch.push(tx);
+            } else {
+                self.pending.push(tx);
+            }
+        }
+        batch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::Amount;
+
+    #[test]
+    fn fee_ordering_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(100)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let mut mempool = Mempool::new();
+        for fee in [1, 3, 2, 3] {
+            let tx = Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(fee),
+                Amount(fee),
+                &alice_sk,
+                &mut rng,
+            );
+            assert!(mempool.insert(&pp, &state, tx));
+        }
+        // Invalid transactions are not accepted.
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(101), &alice_sk, &mut rng);
+        assert!(!mempool.insert(&pp, &state, bad_tx));
+        assert_eq!(mempool.len(), 4);
+
+        let batch = mempool.pop_batch(&pp, &state, 3);
+        let fees = batch.iter().map(|tx| tx.fee.0).collect::<Vec<_>>();
+        assert_eq!(fees, [3, 3, 2]);
+        let batch = mempool.pop_batch(&pp, &state, 3);
+        let fees = batch.iter().map(|tx| tx.fee.0).collect::<Vec<_>>();
+        assert_eq!(fees, [1]);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn eviction_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice can afford either transaction, but not both.
+        let tx1 = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(6),
+            Amount(2),
+            &alice_sk,
+            &mut rng,
+        );
+        let tx2 = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut mempool = Mempool::new();
+        assert!(mempool.insert(&pp, &state, tx1.clone()));
+        assert!(mempool.insert(&pp, &state, tx2));
+
+        // The higher-fee transaction is picked, and the other one is left over.
+        let batch = mempool.pop_batch(&pp, &state, 2);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].fee, tx1.fee);
+        assert_eq!(mempool.len(), 1);
+
+        // Once the batch is applied, Alice's remaining transaction is underfunded.
+        state.apply_transaction(&pp, &batch[0]).unwrap();
+        assert_eq!(mempool.evict_invalid(&pp, &state), 1);
+        assert!(mempool.is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+//! Metadata that applications attach to accounts, such as their KYC status or
+//! their tier. An account commits to its metadata by storing its hash, see
+//! `metadata_hash`, or zero if it has no metadata. The metadata itself is kept
+//! off the ledger, and it is opened by its preimage when it is needed.
+
+use crate::account::PACKED_ACCOUNT_INFO_LEN;
+use crate::ledger::{self, AccountHash};
+use ark_crypto_primitives::crh::CRH;
+use std::convert::TryFrom;
+
+/// The tag that separates the hashes of metadata from other uses of
+/// `AccountHash`.
+pub const METADATA_TAG: &[u8] = b"metadata";
+
+/// The maximum length of the metadata of an account, so that it fits in the
+/// input of `AccountHash` together with its tag and its length, and so that its
+/// length fits in a byte.
+pub const MAX_METADATA_LEN: usize = {
+    let fits = PACKED_ACCOUNT_INFO_LEN - METADATA_TAG.len() - 1;
+    if fits < u8::MAX as usize {
+        fits
+    } else {
+        u8::MAX as usize
+    }
+};
+
+/// The hash of the metadata of an account, see `metadata_hash`.
+pub type MetadataHash = ark_ed_on_bls12_381::Fq;
+
+/// Hash the metadata `preimage`. Returns `None` if it is longer than
+/// `MAX_METADATA_LEN`.
+pub fn metadata_hash(parameters: &ledger::Parameters, preimage: &[u8]) -> Option<MetadataHash> {
+    // The hashed input consists of (Tag || Length || Preimage). `AccountHash`
+    // pads its input with zeros, and so the length keeps metadata that only
+    // differ in trailing zeros apart.
+    if preimage.len() > MAX_METADATA_LEN {
+        return None;
+    }
+    let mut input = METADATA_TAG.to_vec();
+    input.push(u8::try_from(preimage.len()).ok()?);
+    input.extend(preimage);
+    Some(AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+
+    #[test]
+    fn metadata_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let root = state.root();
+
+        // Metadata that only differ in trailing zeros have different hashes, ...
+        let hash = metadata_hash(&pp, b"tier:gold").unwrap();
+        assert_ne!(Some(hash), metadata_hash(&pp, b"tier:gold\0"));
+        // ... and metadata that don't fit in the hash have none.
+        assert!(metadata_hash(&pp, &[1; MAX_METADATA_LEN]).is_some());
+        assert_eq!(metadata_hash(&pp, &[1; MAX_METADATA_LEN + 1]), None);
+
+        // Attaching metadata changes the root, and detaching it restores it.
+        state.set_metadata_hash(alice_id, hash).unwrap();
+        assert_eq!(state.id_to_account_info[&alice_id].metadata_hash, hash);
+        assert_ne!(state.root(), root);
+        state
+            .set_metadata_hash(alice_id, MetadataHash::default())
+            .unwrap();
+        assert_eq!(state.root(), root);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::account::{AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature};
+use crate::ledger::{self, AccountHash};
+use crate::transaction::Transaction;
+use ark_crypto_primitives::crh::CRH;
+use ark_std::rand::Rng;
+
+/// The tag that separates the commitments to signer sets from other uses of
+/// `AccountHash`.
+pub const SIGNER_SET_TAG: &[u8] = b"signer-set";
+
+/// A commitment to the signers of a multisig account, see `SignerSet::commitment`.
+pub type SignerSetCommitment = ark_ed_on_bls12_381::Fq;
+
+/// The signers of a k-of-n multisig account, any `threshold` of whom can
+/// authorize a transaction of the account together.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignerSet {
+    /// The number of signers that must sign a transaction.
+    pub threshold: u8,
+    /// The public keys of the signers.
+    pub signers: Vec<AccountPublicKey>,
+}
+
+impl SignerSet {
+    /// Create the set of `signers`, of whom `threshold` must sign. Returns `None`
+    /// unless `threshold` is between 1 and the number of signers, and there are
+    /// fewer than 256 signers.
+    pub fn new(threshold: u8, signers: Vec<AccountPublicKey>) -> Option<Self> {
+        (threshold >= 1 && signers.len() < 256 && threshold as usize <= signers.len())
+            .then_some(Self { threshold, signers })
+    }
+
+    /// Commit to the signers, in order. The threshold is stored next to the
+    /// commitment in the account information, so it isn't committed to.
+    pub fn commitment(&self, parameters: &ledger::Parameters) -> SignerSetCommitment {
+        // The commitment is a hash chain over the signers, which starts from zero:
+        // C_i = H(Tag || C_{i-1} || PublicKey_i)
+        self.signers
+            .iter()
+            .fold(SignerSetCommitment::default(), |commitment, key| {
+                let mut input = SIGNER_SET_TAG.to_vec();
+                input.extend(ark_ff::to_bytes![commitment, key].unwrap());
+                AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+            })
+    }
+}
+
+/// The signatures of the signers of a multisig account over a transaction:
+/// `signatures[i]` is the signature of `signers.signers[i]`, if they signed.
+///
+/// This authorization is checked separately from `Transaction::validate`, which
+/// still checks the signature of the transaction under the key of the account.
+#[derive(Clone, Debug)]
+pub struct MultisigAuthorization {
+    /// The signers of the account.
+    pub signers: SignerSet,
+    /// The signature of each signer, or `None` if they haven't signed.
+    pub signatures: Vec<Option<AccountSignature>>,
+}
+
+impl MultisigAuthorization {
+    /// Start collecting the signatures of `signers`, none of whom has signed yet.
+    pub fn new(signers: SignerSet) -> Self {
+        let signatures = vec![None; signers.signers.len()];
+        Self {
+            signers,
+            signatures,
+        }
+    }
+
+    /// Sign `tx` as the `index`-th signer, whose secret key is `sk`.
+    pub fn sign<R: Rng>(
+        &mut self,
+        parameters: &ledger::Parameters,
+        tx: &Transaction,
+        index: usize,
+        sk: &AccountSecretKey,
+        rng: &mut R,
+    ) {
+        let message = tx.signed_message(parameters);
+        self.signatures[index] = Some(AccountSignature::sign(parameters, sk, &message, rng));
+    }
+
+    /// The number of signers that have signed.
+    pub fn num_signed(&self) -> usize {
+        self.signatures.iter().flatten().count()
+    }
+
+    /// Check that `tx` is authorized by the multisig account with information
+    /// `sender_info`. This checks the following conditions:
+    /// 1. Verify that the account is a multisig account, whose signer set is
+    ///    `self.signers`.
+    /// 2. Verify that each signature is valid under the key of its signer.
+    /// 3. Verify that at least as many signers as the threshold of the account
+    ///    have signed.
+    pub fn validate(
+        &self,
+        parameters: &ledger::Parameters,
+        tx: &Transaction,
+        sender_info: &AccountInformation,
+    ) -> bool {
+        let message = tx.signed_message(parameters);
+        let mut result = sender_info.threshold != 0;
+        result &= sender_info.signers == self.signers.commitment(parameters);
+        result &= self.signatures.len() == self.signers.signers.len();
+        result &= self
+            .signatures
+            .iter()
+            .zip(&self.signers.signers)
+            .all(|(signature, key)| {
+                signature
+                    .as_ref()
+                    .is_none_or(|signature| signature.verify(parameters, key, &message))
+            });
+        result &= self.num_signed() >= sender_info.threshold as usize;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, Parameters, State};
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+
+    #[test]
+    fn threshold_authorization_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let keys = (0..3)
+            .map(|_| Schnorr::keygen(&pp.sig_params, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let signers = SignerSet::new(2, keys.iter().map(|(pk, _)| *pk).collect()).unwrap();
+        state.set_signers(&pp, alice_id, &signers).unwrap();
+        let alice_info = state.id_to_account_info[&alice_id];
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(0), &alice_sk, &mut rng);
+
+        // One signature is not enough, ...
+        let mut auth = MultisigAuthorization::new(signers.clone());
+        auth.sign(&pp, &tx, 0, &keys[0].1, &mut rng);
+        assert!(!auth.validate(&pp, &tx, &alice_info));
+        // ... but two are.
+        auth.sign(&pp, &tx, 2, &keys[2].1, &mut rng);
+        assert!(auth.validate(&pp, &tx, &alice_info));
+        // Signatures only count under the key of their own signer.
+        auth.signatures.swap(0, 2);
+        assert!(!auth.validate(&pp, &tx, &alice_info));
+        // The threshold must be between 1 and the number of signers.
+        assert!(SignerSet::new(0, signers.signers.clone()).is_none());
+        assert!(SignerSet::new(4, signers.signers).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+//! Prices signed by an oracle, so that a swap can be settled at the oracle price
+//! rather than at amounts that both parties agree on.
+//!
+//! A price counts units of `10^-PRICE_DECIMALS` of the output asset per unit of
+//! the input asset. The output amount is rounded down, so that rounding never
+//! favors the party that receives it.
+
+use crate::account::{AccountPublicKey, AccountSecretKey, AccountSignature};
+use crate::ledger::{self, Amount, AssetId};
+use crate::swap::Swap;
+use ark_std::rand::Rng;
+use std::convert::TryFrom;
+
+/// Prefix of the message signed by the oracle. This separates price quotes from
+/// the messages of other operations, so that their signatures can't be reused
+/// for one another.
+pub const ORACLE_TAG: &[u8] = b"oracle";
+
+/// The number of decimals of prices.
+pub const PRICE_DECIMALS: u32 = 6;
+
+/// The price of `asset_in` in units of `asset_out` during `epoch`, as signed by
+/// the oracle.
+#[derive(Clone, Debug)]
+pub struct PriceQuote {
+    /// The asset being sold at this price.
+    pub asset_in: AssetId,
+    /// The asset being bought at this price.
+    pub asset_out: AssetId,
+    /// The price, with `PRICE_DECIMALS` decimals.
+    pub price: Amount,
+    /// The epoch during which the price holds.
+    pub epoch: u64,
+    /// The signature of the oracle over the whole quote.
+    pub signature: AccountSignature,
+}
+
+/// Construct the message that the oracle signs to quote `price`.
+fn message(
+    parameters: &ledger::Parameters,
+    asset_in: AssetId,
+    asset_out: AssetId,
+    price: Amount,
+    epoch: u64,
+) -> Vec<u8> {
+    // The signed message consists of
+    // (Tag || AssetIn || AssetOut || Price || Epoch)
+    let mut message = ORACLE_TAG.to_vec();
+    message.extend(asset_in.to_bytes_le());
+    message.extend(asset_out.to_bytes_le());
+    message.extend(price.to_bytes_le());
+    message.extend(epoch.to_le_bytes());
+    parameters.message_encoding.encode(message)
+}
+
+impl PriceQuote {
+    /// Create a price quote, signed by the oracle with `oracle_sk`.
+    pub fn create<R: Rng>(
+        parameters: &ledger::Parameters,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        price: Amount,
+        epoch: u64,
+        oracle_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = message(parameters, asset_in, asset_out, price, epoch);
+        let signature = AccountSignature::sign(parameters, oracle_sk, &message, rng);
+        Self {
+            asset_in,
+            asset_out,
+            price,
+            epoch,
+            signature,
+        }
+    }
+
+    /// Verify that the quote is signed by the oracle with key `oracle_key`.
+    pub fn verify(&self, parameters: &ledger::Parameters, oracle_key: &AccountPublicKey) -> bool {
+        let message = message(
+            parameters,
+            self.asset_in,
+            self.asset_out,
+            self.price,
+            self.epoch,
+        );
+        self.signature.verify(parameters, oracle_key, &message)
+    }
+
+    /// The amount of `asset_out` that `amount_in` of `asset_in` is worth at this
+    /// price, rounded down. Returns `None` if it doesn't fit in an amount.
+    pub fn amount_out(&self, amount_in: Amount) -> Option<Amount> {
+        let scale = 10u128.pow(PRICE_DECIMALS);
+        let amount_out = u128::from(amount_in.0) * u128::from(self.price.0) / scale;
+        u64::try_from(amount_out).ok().map(Amount)
+    }
+}
+
+impl Swap {
+    /// Check that the swap trades `amount_a` of `asset_a` for `amount_b` of
+    /// `asset_b` at the price of `quote`, during the epoch `epoch`. This checks
+    /// the following conditions:
+    /// 1. Verify that `quote` is signed by the oracle with key `oracle_key`.
+    /// 2. Verify that `quote` prices `asset_a` in `asset_b` during `epoch`.
+    /// 3. Verify that `amount_b` is `amount_a` times the price, rounded down.
+    pub fn is_at_price(
+        &self,
+        parameters: &ledger::Parameters,
+        quote: &PriceQuote,
+        oracle_key: &AccountPublicKey,
+        epoch: u64,
+    ) -> bool {
+        quote.verify(parameters, oracle_key)
+            && quote.asset_in == self.asset_a
+            && quote.asset_out == self.asset_b
+            && quote.epoch == epoch
+            && quote.amount_out(self.amount_a) == Some(self.amount_b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+
+    #[test]
+    fn price_quote_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (oracle_pk, oracle_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        // One unit of asset 1 is worth 2.5 units of asset 2.
+        let quote = PriceQuote::create(
+            &pp,
+            AssetId(1),
+            AssetId(2),
+            Amount(2_500_000),
+            0,
+            &oracle_sk,
+            &mut rng,
+        );
+        let swap = |amount_a, amount_b, rng: &mut _| {
+            Swap::create(
+                &pp,
+                alice_id,
+                bob_id,
+                AssetId(1),
+                Amount(amount_a),
+                AssetId(2),
+                Amount(amount_b),
+                &alice_sk,
+                &bob_sk,
+                rng,
+            )
+        };
+
+        // The output is rounded down, ...
+        assert_eq!(quote.amount_out(Amount(3)), Some(Amount(7)));
+        assert!(swap(3, 7, &mut rng).is_at_price(&pp, &quote, &oracle_pk, 0));
+        // ... and any other output is rejected.
+        assert!(!swap(3, 8, &mut rng).is_at_price(&pp, &quote, &oracle_pk, 0));
+        assert!(!swap(3, 6, &mut rng).is_at_price(&pp, &quote, &oracle_pk, 0));
+        // The price only holds during its epoch, ...
+        assert!(!swap(3, 7, &mut rng).is_at_price(&pp, &quote, &oracle_pk, 1));
+        // ... and only if the oracle signed it.
+        let forged = PriceQuote {
+            price: Amount(3_000_000),
+            ..quote.clone()
+        };
+        assert!(!swap(3, 9, &mut rng).is_at_price(&pp, &forged, &oracle_pk, 0));
+    }
+}
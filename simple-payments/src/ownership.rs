@@ -0,0 +1,119 @@
+//! Ownership proofs, with which a user proves that they control some account in
+//! the account tree, e.g., to log in to an application, by signing a challenge of
+//! the verifier with the key of the account.
+//!
+//! Checking the proof natively reveals the account. The gadget
+//! `prove_ownership_anonymous` of the rollup checks the same statement in zero
+//! knowledge instead, and so it hides which member of the tree signed.
+
+use crate::account::{AccountSecretKey, AccountSignature};
+use crate::ledger::{AccRoot, AccountProof, Parameters};
+use ark_std::rand::Rng;
+
+/// Prefix of the message signed to prove the ownership of an account. This
+/// separates ownership proofs from the messages of other operations, so that a
+/// challenge can't trick a user into signing a transaction.
+pub const OWNERSHIP_TAG: &[u8] = b"ownership";
+
+/// Construct the message that the owner of an account in the tree with root
+/// `set_root` signs to answer the challenge `challenge`.
+fn ownership_message(parameters: &Parameters, set_root: &AccRoot, challenge: &[u8]) -> Vec<u8> {
+    // The signed message consists of (Tag || Root || Challenge). The root keeps
+    // answers from being replayed against other sets of accounts.
+    let mut message = OWNERSHIP_TAG.to_vec();
+    message.extend(ark_ff::to_bytes![set_root].unwrap());
+    message.extend(challenge);
+    parameters.message_encoding.encode(message)
+}
+
+/// Answer the challenge `challenge` with the key `sk` of an account in the tree
+/// with root `set_root`.
+pub fn sign_ownership<R: Rng>(
+    parameters: &Parameters,
+    sk: &AccountSecretKey,
+    set_root: &AccRoot,
+    challenge: &[u8],
+    rng: &mut R,
+) -> AccountSignature {
+    let message = ownership_message(parameters, set_root, challenge);
+    AccountSignature::sign(parameters, sk, &message, rng)
+}
+
+/// Verify that `signature` answers the challenge `challenge` under the key of
+/// the account that `proof` proves to be in the tree with root `set_root`, at
+/// whichever index.
+pub fn verify_ownership(
+    parameters: &Parameters,
+    set_root: &AccRoot,
+    challenge: &[u8],
+    proof: &AccountProof,
+    signature: &AccountSignature,
+) -> bool {
+    let is_member = proof
+        .path
+        .verify(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            set_root,
+            &proof.info.to_leaf(parameters),
+        )
+        .unwrap();
+    let message = ownership_message(parameters, set_root, challenge);
+    is_member && signature.verify(parameters, &proof.info.public_key, &message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::State;
+
+    #[test]
+    fn ownership_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (_bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let root = state.root();
+        let proof = state.prove_account(alice_id).unwrap();
+
+        // Alice answers the challenge with the key of her account, ...
+        let signature = sign_ownership(&pp, &alice_sk, &root, b"login:42", &mut rng);
+        assert!(verify_ownership(
+            &pp,
+            &root,
+            b"login:42",
+            &proof,
+            &signature
+        ));
+        // ... but her answer doesn't hold for another challenge, or for another
+        // set of accounts, ...
+        assert!(!verify_ownership(
+            &pp,
+            &root,
+            b"login:43",
+            &proof,
+            &signature
+        ));
+        let mut other = state.clone();
+        let (_carol_id, _carol_pk, _carol_sk) =
+            other.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert!(!verify_ownership(
+            &pp,
+            &other.root(),
+            b"login:42",
+            &proof,
+            &signature
+        ));
+        // ... and Bob can't answer for her account.
+        let signature = sign_ownership(&pp, &bob_sk, &root, b"login:42", &mut rng);
+        assert!(!verify_ownership(
+            &pp,
+            &root,
+            b"login:42",
+            &proof,
+            &signature
+        ));
+    }
+}
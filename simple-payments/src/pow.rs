@@ -0,0 +1,94 @@
+//! Proofs of work that transactions carry to resist spam, so that a mempool can
+//! be open to anybody. The proof of work of a transaction is a nonce such that
+//! the digest of the transaction and the nonce starts with enough zero bits,
+//! see `Transaction::meets_difficulty`. The digest covers the hash of the
+//! transaction, and so the work can't be reused for another transaction.
+
+use crate::ledger::{AccountHash, Parameters};
+use crate::transaction::{Transaction, TransactionHash};
+use ark_crypto_primitives::crh::CRH;
+use ark_ff::{BigInteger, FpParameters, PrimeField};
+
+/// The tag that separates the digests of proofs of work from other uses of
+/// `AccountHash`.
+pub const POW_TAG: &[u8] = b"pow";
+
+/// The number of bits of a digest, which bounds the difficulty of a proof of
+/// work.
+pub const POW_DIGEST_BITS: u32 =
+    <<TransactionHash as PrimeField>::Params as FpParameters>::MODULUS_BITS;
+
+/// Return the number of leading zero bits of `digest`, out of its
+/// `POW_DIGEST_BITS` bits.
+pub fn leading_zero_bits(digest: &TransactionHash) -> u32 {
+    let bits = digest.into_repr().to_bits_le();
+    bits[..POW_DIGEST_BITS as usize]
+        .iter()
+        .rev()
+        .take_while(|bit| !**bit)
+        .count() as u32
+}
+
+impl Transaction {
+    /// The digest of the proof of work of the transaction, under its nonce
+    /// `pow_nonce`.
+    pub fn pow_digest(&self, parameters: &Parameters) -> TransactionHash {
+        // The hashed input consists of
+        // (Tag || TransactionHash || Nonce)
+        let mut input = POW_TAG.to_vec();
+        input.extend(ark_ff::to_bytes![self.hash(parameters)].unwrap());
+        input.extend(self.pow_nonce.to_le_bytes());
+        AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+    }
+
+    /// Does the proof of work of the transaction meet the difficulty
+    /// `difficulty`, i.e., does its digest start with at least `difficulty` zero
+    /// bits?
+    pub fn meets_difficulty(&self, parameters: &Parameters, difficulty: u8) -> bool {
+        leading_zero_bits(&self.pow_digest(parameters)) >= difficulty as u32
+    }
+
+    /// Search for the first nonce whose proof of work meets the difficulty
+    /// `difficulty`, and attach it to the transaction. This takes about
+    /// `2^difficulty` hashes.
+    pub fn with_pow(mut self, parameters: &Parameters, difficulty: u8) -> Self {
+        self.pow_nonce = 0;
+        while !self.meets_difficulty(parameters, difficulty) {
+            self.pow_nonce += 1;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, State};
+
+    #[test]
+    fn pow_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+
+        // Any nonce meets no difficulty, ...
+        assert!(tx.meets_difficulty(&pp, 0));
+        // ... while the nonce that is found meets the difficulty it is found for.
+        let tx = tx.with_pow(&pp, 6);
+        let zeros = leading_zero_bits(&tx.pow_digest(&pp));
+        assert!(zeros >= 6);
+        assert!(tx.meets_difficulty(&pp, 6));
+        assert!(!tx.meets_difficulty(&pp, zeros as u8 + 1));
+        // The work is bound to the transaction.
+        let other = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng);
+        let other = Transaction {
+            pow_nonce: tx.pow_nonce,
+            ..other
+        };
+        assert_ne!(other.pow_digest(&pp), tx.pow_digest(&pp));
+    }
+}
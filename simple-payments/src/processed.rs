@@ -0,0 +1,235 @@
+//! The set of processed transactions, in which the identifiers of transactions
+//! are recorded as they are applied, so that each transaction is applied at most
+//! once, even across batches.
+//!
+//! The set is stored in an indexed Merkle tree: each occupied leaf commits to an
+//! identifier in the set along with the next larger identifier in the set, so
+//! that an identifier is absent from the set iff it falls strictly between the
+//! two identifiers of some leaf. Inserting an identifier updates that leaf, and
+//! fills a blank leaf with the new identifier.
+
+use crate::ledger::{AccMerkleTree, AccPath, AccRoot, AccountHash, IncrementalUpdate, Parameters};
+use crate::transaction::TransactionId;
+use ark_crypto_primitives::crh::CRH;
+use ark_ff::{One, Zero};
+
+/// The tag that separates the leaves of the set of processed transactions from
+/// other uses of `AccountHash`.
+pub const PROCESSED_LEAF_TAG: &[u8] = b"processed";
+
+/// A leaf of the set of processed transactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessedLeaf {
+    /// The identifier stored in the leaf.
+    pub key: TransactionId,
+    /// The smallest identifier in the set that is larger than `key`, or the
+    /// largest field element if there is none.
+    pub next: TransactionId,
+}
+
+impl ProcessedLeaf {
+    /// Convert the leaf to the bytes stored in the tree, which commit to both of
+    /// its identifiers. The commitment fits in a leaf, unlike the identifiers.
+    pub fn to_leaf(&self, parameters: &Parameters) -> Vec<u8> {
+        // The committed input consists of
+        // (Tag || Key || Next)
+        let mut input = PROCESSED_LEAF_TAG.to_vec();
+        input.extend(ark_ff::to_bytes![self.key, self.next].unwrap());
+        let commitment = AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap();
+        ark_ff::to_bytes![commitment].unwrap()
+    }
+
+    /// Does `id` fall strictly between the identifiers of this leaf, which proves
+    /// that it is absent from the set?
+    pub fn brackets(&self, id: TransactionId) -> bool {
+        self.key < id && id < self.next
+    }
+}
+
+/// The blank leaf of the set of processed transactions, which fills the unused
+/// leaves of the tree.
+pub fn blank_processed_leaf() -> Vec<u8> {
+    vec![0u8; 32]
+}
+
+/// A proof that an identifier was inserted into the set of processed
+/// transactions, which shows that it was absent from the set before.
+#[derive(Clone)]
+pub struct InsertionProof {
+    /// The leaf that brackets the identifier before the insertion.
+    pub low_leaf: ProcessedLeaf,
+    /// The membership proof of `low_leaf` before the insertion. The siblings on
+    /// the path don't change when `low_leaf` is updated to point to the
+    /// identifier, and so it also proves the updated leaf.
+    pub low_path: AccPath,
+    /// The root of the tree after updating `low_leaf`, but before filling the
+    /// blank leaf with the identifier.
+    pub mid_root: AccRoot,
+    /// The membership proof of the blank leaf that the identifier fills, with
+    /// respect to `mid_root`. It also proves the filled leaf after the insertion.
+    pub new_path: AccPath,
+}
+
+impl InsertionProof {
+    /// Check that this proves that `id` was inserted into the set with root
+    /// `pre_root`, which has the root `post_root` after the insertion.
+    pub fn verify(
+        &self,
+        parameters: &Parameters,
+        pre_root: &AccRoot,
+        post_root: &AccRoot,
+        id: TransactionId,
+    ) -> bool {
+        let updated_low_leaf = ProcessedLeaf {
+            key: self.low_leaf.key,
+            next: id,
+        };
+        let new_leaf = ProcessedLeaf {
+            key: id,
+            next: self.low_leaf.next,
+        };
+        let is_member = |path: &AccPath, root, leaf: &[u8]| {
+            path.verify(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                root,
+                &leaf,
+            )
+            .unwrap_or(false)
+        };
+        self.low_leaf.brackets(id)
+            && is_member(&self.low_path, pre_root, &self.low_leaf.to_leaf(parameters))
+            && is_member(
+                &self.low_path,
+                &self.mid_root,
+                &updated_low_leaf.to_leaf(parameters),
+            )
+            && is_member(&self.new_path, &self.mid_root, &blank_processed_leaf())
+            && is_member(&self.new_path, post_root, &new_leaf.to_leaf(parameters))
+    }
+}
+
+/// The set of processed transactions.
+#[derive(Clone)]
+pub struct ProcessedSet {
+    tree: AccMerkleTree,
+    /// The occupied leaves, in the order of the tree.
+    leaves: Vec<ProcessedLeaf>,
+}
+
+impl ProcessedSet {
+    /// Create an empty set in a tree of height `height`, which can hold up to
+    /// `2^(height - 1) - 1` identifiers. The first leaf brackets all the
+    /// identifiers but zero, which is never the identifier of a transaction.
+    pub fn new(parameters: &Parameters, height: usize) -> Self {
+        let mut tree = AccMerkleTree::blank(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            height,
+        )
+        .unwrap();
+        let first = ProcessedLeaf {
+            key: TransactionId::zero(),
+            next: -TransactionId::one(),
+        };
+        tree.update(0, &first.to_leaf(parameters)).unwrap();
+        Self {
+            tree,
+            leaves: vec![first],
+        }
+    }
+
+    /// The root of the tree, which commits to the whole set.
+    pub fn root(&self) -> AccRoot {
+        self.tree.root()
+    }
+
+    /// Is `id` in the set?
+    pub fn contains(&self, id: TransactionId) -> bool {
+        self.leaves.iter().any(|leaf| leaf.key == id)
+    }
+
+    /// Insert `id` into the set, and return the proof of the insertion. Returns
+    /// `None` if `id` is already in the set, or if the set is full.
+    pub fn insert(&mut self, parameters: &Parameters, id: TransactionId) -> Option<InsertionProof> {
+        let low_index = self.leaves.iter().position(|leaf| leaf.brackets(id))?;
+        let new_index = self.leaves.len();
+        if new_index >= 1 << (self.tree.height() - 1) {
+            return None;
+        }
+        let low_leaf = self.leaves[low_index];
+        let updated_low_leaf = ProcessedLeaf {
+            key: low_leaf.key,
+            next: id,
+        };
+        let new_leaf = ProcessedLeaf {
+            key: id,
+            next: low_leaf.next,
+        };
+        let (low_path, mid_root) = self
+            .tree
+            .update_with_path(low_index, &updated_low_leaf.to_leaf(parameters))
+            .ok()?;
+        let (new_path, _) = self
+            .tree
+            .update_with_path(new_index, &new_leaf.to_leaf(parameters))
+            .ok()?;
+        self.leaves[low_index] = updated_low_leaf;
+        self.leaves.push(new_leaf);
+        Some(InsertionProof {
+            low_leaf,
+            low_path,
+            mid_root,
+            new_path,
+        })
+    }
+
+    /// A proof of the right shape for this set that proves nothing, to stand in
+    /// for the insertions of transactions that are not recorded in the set.
+    pub fn placeholder_proof(&self) -> InsertionProof {
+        let path = self.tree.generate_proof(0).unwrap();
+        InsertionProof {
+            low_leaf: self.leaves[0],
+            low_path: path.clone(),
+            mid_root: self.root(),
+            new_path: path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, State};
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn insertion_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let ids = (1..=3)
+            .map(|amount| {
+                Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng)
+                    .id(&pp)
+            })
+            .collect::<Vec<_>>();
+
+        let mut set = ProcessedSet::new(&pp, 3);
+        for id in &ids {
+            let pre_root = set.root();
+            let proof = set.insert(&pp, *id).unwrap();
+            assert!(proof.verify(&pp, &pre_root, &set.root(), *id));
+            assert!(set.contains(*id));
+            // The proof doesn't show the insertion of another identifier.
+            assert!(!proof.verify(&pp, &pre_root, &set.root(), *id + TransactionId::one()));
+        }
+        // An identifier can only be inserted once, ...
+        assert!(set.insert(&pp, ids[0]).is_none());
+        // ... and the set can only hold as many identifiers as it has leaves.
+        assert!(set.insert(&pp, TransactionId::one()).is_none());
+    }
+}
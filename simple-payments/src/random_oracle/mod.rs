@@ -3,6 +3,7 @@ use ark_std::hash::Hash;
 use ark_std::rand::Rng;
 
 pub mod blake2s;
+pub mod sha256;
 
 use ark_crypto_primitives::Error;
 
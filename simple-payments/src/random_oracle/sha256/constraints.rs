@@ -0,0 +1,191 @@
+use crate::random_oracle::sha256::{self, padding, INITIAL_STATE, ROUND_CONSTANTS};
+use crate::random_oracle::RandomOracleGadget;
+// The SHA-256 output is 32 bytes, just like that of Blake2s, so we reuse its output gadget.
+use ark_crypto_primitives::prf::blake2s::constraints::OutputVar;
+use ark_ff::{Field, PrimeField};
+use ark_r1cs_std::bits::uint32::UInt32;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_std::vec::Vec;
+
+use core::borrow::Borrow;
+
+#[derive(Clone)]
+pub struct ParametersVar;
+
+pub struct ROGadget;
+
+/// Interpret four bytes as a big-endian 32-bit word.
+fn word_from_be_bytes<F: Field>(bytes: &[UInt8<F>]) -> Result<UInt32<F>, SynthesisError> {
+    let mut bits = Vec::with_capacity(32);
+    for byte in bytes.iter().rev() {
+        bits.extend(byte.to_bits_le()?);
+    }
+    Ok(UInt32::from_bits_le(&bits))
+}
+
+/// Convert a 32-bit word into four big-endian bytes.
+fn word_to_be_bytes<F: Field>(word: &UInt32<F>) -> Vec<UInt8<F>> {
+    let mut bytes: Vec<_> = word
+        .to_bits_le()
+        .chunks(8)
+        .map(UInt8::from_bits_le)
+        .collect();
+    bytes.reverse();
+    bytes
+}
+
+fn shr<F: Field>(word: &UInt32<F>, by: usize) -> UInt32<F> {
+    let mut bits: Vec<_> = word.to_bits_le().into_iter().skip(by).collect();
+    bits.resize(32, Boolean::FALSE);
+    UInt32::from_bits_le(&bits)
+}
+
+/// Compute `(e & f) ^ (!e & g)`, i.e., choose the bit of `f` when the bit of `e` is set,
+/// and the bit of `g` otherwise.
+fn ch<F: Field>(e: &UInt32<F>, f: &UInt32<F>, g: &UInt32<F>) -> Result<UInt32<F>, SynthesisError> {
+    let mut bits = Vec::with_capacity(32);
+    for ((e, f), g) in e
+        .to_bits_le()
+        .iter()
+        .zip(f.to_bits_le())
+        .zip(g.to_bits_le())
+    {
+        bits.push(Boolean::conditionally_select(e, &f, &g)?);
+    }
+    Ok(UInt32::from_bits_le(&bits))
+}
+
+/// Compute `(a & b) ^ (a & c) ^ (b & c)`, i.e., the majority of the bits. When the bits
+/// of `b` and `c` differ, `a` decides, and otherwise the majority is the bit of `b`.
+fn maj<F: Field>(a: &UInt32<F>, b: &UInt32<F>, c: &UInt32<F>) -> Result<UInt32<F>, SynthesisError> {
+    let mut bits = Vec::with_capacity(32);
+    for ((a, b), c) in a
+        .to_bits_le()
+        .iter()
+        .zip(b.to_bits_le())
+        .zip(c.to_bits_le())
+    {
+        bits.push(Boolean::conditionally_select(&b.xor(&c)?, a, &b)?);
+    }
+    Ok(UInt32::from_bits_le(&bits))
+}
+
+fn compress<F: PrimeField>(
+    state: &[UInt32<F>],
+    block: &[UInt8<F>],
+) -> Result<Vec<UInt32<F>>, SynthesisError> {
+    let mut w = Vec::with_capacity(64);
+    for word in block.chunks(4) {
+        w.push(word_from_be_bytes(word)?);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15]
+            .rotr(7)
+            .xor(&w[i - 15].rotr(18))?
+            .xor(&shr(&w[i - 15], 3))?;
+        let s1 = w[i - 2]
+            .rotr(17)
+            .xor(&w[i - 2].rotr(19))?
+            .xor(&shr(&w[i - 2], 10))?;
+        let word = UInt32::addmany(&[w[i - 16].clone(), s0, w[i - 7].clone(), s1])?;
+        w.push(word);
+    }
+
+    let mut a = state[0].clone();
+    let mut b = state[1].clone();
+    let mut c = state[2].clone();
+    let mut d = state[3].clone();
+    let mut e = state[4].clone();
+    let mut f = state[5].clone();
+    let mut g = state[6].clone();
+    let mut h = state[7].clone();
+    for (w, k) in w.iter().zip(ROUND_CONSTANTS.iter()) {
+        let s1 = e.rotr(6).xor(&e.rotr(11))?.xor(&e.rotr(25))?;
+        let ch = ch(&e, &f, &g)?;
+        let temp1 = UInt32::addmany(&[h, s1, ch, UInt32::constant(*k), w.clone()])?;
+        let s0 = a.rotr(2).xor(&a.rotr(13))?.xor(&a.rotr(22))?;
+        let maj = maj(&a, &b, &c)?;
+        let temp2 = UInt32::addmany(&[s0, maj])?;
+        h = g;
+        g = f;
+        f = e;
+        e = UInt32::addmany(&[d, temp1.clone()])?;
+        d = c;
+        c = b;
+        b = a;
+        a = UInt32::addmany(&[temp1, temp2])?;
+    }
+
+    state
+        .iter()
+        .zip([a, b, c, d, e, f, g, h])
+        .map(|(s, v)| UInt32::addmany(&[s.clone(), v]))
+        .collect()
+}
+
+impl<F: PrimeField> RandomOracleGadget<sha256::RO, F> for ROGadget {
+    type OutputVar = OutputVar<F>;
+    type ParametersVar = ParametersVar;
+
+    fn evaluate(
+        _: &Self::ParametersVar,
+        input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        // The length of the input is known when generating constraints,
+        // so the padding is a constant.
+        let mut padded = input.to_vec();
+        padded.extend(padding(input.len()).into_iter().map(UInt8::constant));
+
+        let mut state: Vec<_> = INITIAL_STATE.iter().map(|h| UInt32::constant(*h)).collect();
+        for block in padded.chunks(64) {
+            state = compress(&state, block)?;
+        }
+        Ok(OutputVar(state.iter().flat_map(word_to_be_bytes).collect()))
+    }
+}
+
+impl<ConstraintF: Field> AllocVar<(), ConstraintF> for ParametersVar {
+    fn new_variable<T: Borrow<()>>(
+        _cs: impl Into<Namespace<ConstraintF>>,
+        _f: impl FnOnce() -> Result<T, SynthesisError>,
+        _mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        Ok(ParametersVar)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::random_oracle::{
+        sha256::{constraints::ROGadget, RO},
+        RandomOracle, RandomOracleGadget,
+    };
+    use ark_ed_on_bls12_381::Fq as Fr;
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    #[test]
+    fn random_oracle_gadget_test() {
+        // Check both a single-block and a two-block input.
+        for len in [10, 70] {
+            let cs = ConstraintSystem::<Fr>::new_ref();
+            let input: Vec<u8> = (0..len).collect();
+            let primitive_result = RO::evaluate(&(), &input).unwrap();
+
+            let input_var = UInt8::new_witness_vec(cs.clone(), &input).unwrap();
+            let parameters_var =
+                <ROGadget as RandomOracleGadget<RO, Fr>>::ParametersVar::new_witness(
+                    ark_relations::ns!(cs, "gadget_parameters"),
+                    || Ok(()),
+                )
+                .unwrap();
+            let result_var =
+                <ROGadget as RandomOracleGadget<RO, Fr>>::evaluate(&parameters_var, &input_var)
+                    .unwrap();
+
+            assert_eq!(result_var.value().unwrap(), primitive_result);
+            assert!(cs.is_satisfied().unwrap());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Receipts, which the sequencer signs for the balance change of the sender of
+//! each transaction, so that disputes about how much an account was debited can
+//! be settled with the sequencer's signature. Receipts are only required if the
+//! ledger has a sequencer key.
+
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+};
+use crate::ledger::{self, Amount};
+use crate::transaction::Transaction;
+use ark_std::rand::Rng;
+
+/// Prefix of the message signed by a receipt. This separates receipts from the
+/// messages of other operations, so that their signatures can't be reused for
+/// one another.
+pub const RECEIPT_TAG: &[u8] = b"receipt";
+
+/// Construct the message that the sequencer signs to certify that the balance of
+/// `account` changed from `pre_balance` to `post_balance`.
+fn message(
+    parameters: &ledger::Parameters,
+    account: AccountId,
+    pre_balance: Amount,
+    post_balance: Amount,
+) -> Vec<u8> {
+    // The signed message consists of
+    // (Tag || AccountId || PreBalance || PostBalance)
+    let mut message = RECEIPT_TAG.to_vec();
+    message.extend(account.to_bytes_le());
+    message.extend(pre_balance.to_bytes_le());
+    message.extend(post_balance.to_bytes_le());
+    parameters.message_encoding.encode(message)
+}
+
+/// Sign a receipt with the sequencer's key `sequencer_sk`, certifying that the
+/// balance of `account` changed from `pre_balance` to `post_balance`.
+pub fn sign_receipt<R: Rng>(
+    parameters: &ledger::Parameters,
+    sequencer_sk: &AccountSecretKey,
+    account: AccountId,
+    pre_balance: Amount,
+    post_balance: Amount,
+    rng: &mut R,
+) -> AccountSignature {
+    let message = message(parameters, account, pre_balance, post_balance);
+    AccountSignature::sign(parameters, sequencer_sk, &message, rng)
+}
+
+/// Verify that `receipt` is signed by `sequencer_key`, and certifies that the
+/// balance of `account` changed from `pre_balance` to `post_balance`.
+pub fn verify_receipt(
+    parameters: &ledger::Parameters,
+    sequencer_key: &AccountPublicKey,
+    account: AccountId,
+    pre_balance: Amount,
+    post_balance: Amount,
+    receipt: &AccountSignature,
+) -> bool {
+    let message = message(parameters, account, pre_balance, post_balance);
+    receipt.verify(parameters, sequencer_key, &message)
+}
+
+impl Transaction {
+    /// Return the balances of the asset `self.asset_id` of a sender with
+    /// information `sender_info` before and after the transaction, or `None` if
+    /// the sender can't afford it.
+    fn sender_balance_change(&self, sender_info: &AccountInformation) -> Option<(Amount, Amount)> {
+        let pre_balance = sender_info.balances.get(self.asset_id)?;
+        let mut post_balance = pre_balance.checked_sub(self.total_cost()?)?;
+        // The amount of a self-transfer returns to the sender.
+        if self.sender == self.recipient && !self.is_withdrawal() {
+            post_balance = post_balance.checked_add(self.amount)?;
+        }
+        Some((pre_balance, post_balance))
+    }
+
+    /// Sign the receipt for the balance change of the sender, whose information
+    /// before the transaction is `sender_info`, with the sequencer's key
+    /// `sequencer_sk`. Returns `None` if the sender can't afford the transaction.
+    pub fn sign_receipt<R: Rng>(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+        sequencer_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Option<AccountSignature> {
+        let (pre_balance, post_balance) = self.sender_balance_change(sender_info)?;
+        Some(sign_receipt(
+            parameters,
+            sequencer_sk,
+            self.sender,
+            pre_balance,
+            post_balance,
+            rng,
+        ))
+    }
+
+    /// Does the transaction carry a valid receipt for the balance change of the
+    /// sender, whose information before the transaction is `sender_info`? This
+    /// always holds if the ledger doesn't require receipts.
+    pub(crate) fn has_valid_receipt(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+    ) -> bool {
+        let sequencer_key = match &parameters.sequencer_key {
+            Some(key) => key,
+            None => return true,
+        };
+        match (&self.receipt, self.sender_balance_change(sender_info)) {
+            (Some(receipt), Some((pre_balance, post_balance))) => verify_receipt(
+                parameters,
+                sequencer_key,
+                self.sender,
+                pre_balance,
+                post_balance,
+                receipt,
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+
+    #[test]
+    fn receipt_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        let (sequencer_pk, sequencer_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        pp.sequencer_key = Some(sequencer_pk);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let alice_info = state.id_to_account_info[&alice_id];
+
+        // Transactions need a receipt, ...
+        let mut tx = Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        // ... for the balance change that they actually make, ...
+        let wrong_receipt = sign_receipt(
+            &pp,
+            &sequencer_sk,
+            alice_id,
+            Amount(10),
+            Amount(7),
+            &mut rng,
+        );
+        tx.receipt = Some(wrong_receipt);
+        assert!(!tx.validate(&pp, &state));
+        // ... signed by the sequencer.
+        tx.receipt = tx.sign_receipt(&pp, &alice_info, &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        tx.receipt = tx.sign_receipt(&pp, &alice_info, &sequencer_sk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(
+            state.id_to_account_info[&alice_id]
+                .balances
+                .get(tx.asset_id),
+            Some(Amount(6))
+        );
+    }
+}
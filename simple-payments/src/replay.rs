@@ -0,0 +1,323 @@
+use crate::account::AccountInformation;
+use crate::ledger::{self, AccRoot, AccountProof, BlockCommitment, LeafHash, TwoToOneHash};
+use crate::transaction::Transaction;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::Error;
+use ark_ff::to_bytes;
+
+/// The witnesses needed to replay a transaction, i.e., the proofs of the
+/// sender's and recipient's account information *before* the transaction.
+#[derive(Clone)]
+pub struct TransactionWitness {
+    /// The proof of the sender's account information. This is `None` for
+    /// `Transaction::NOOP`, which has no sender account.
+    pub sender: Option<AccountProof>,
+    /// The proof of the recipient's account information. This is `None` for
+    /// withdrawals, which have no recipient account.
+    pub recipient: Option<AccountProof>,
+}
+
+impl TransactionWitness {
+    /// Collect the witnesses of `tx` from the ledger state `state` before `tx`.
+    pub fn new(state: &ledger::State, tx: &Transaction) -> Self {
+        Self {
+            sender: state.prove_account(tx.sender),
+            recipient: state.prove_account(tx.recipient),
+        }
+    }
+}
+
+/// Compute the hashes of the nodes on the path proven by `proof`, from the leaf
+/// up to the root, once the account information in its leaf is replaced by
+/// `new_info`. The i-th node is returned along with its index in the i-th level
+/// of the tree, counting from the leaves.
+///
+/// `updated` contains the nodes of another path that was updated before this
+/// one, which replace the corresponding siblings of `proof`, since these are
+/// only valid before that update.
+fn updated_path_nodes(
+    parameters: &ledger::Parameters,
+    proof: &AccountProof,
+    new_info: &AccountInformation,
+    updated: &[(usize, AccRoot)],
+) -> Result<Vec<(usize, AccRoot)>, Error> {
+    let path = &proof.path;
+    let leaf = new_info.to_leaf(parameters);
+    let mut index = path.leaf_index;
+    let mut node = <LeafHash as CRH>::evaluate(&parameters.leaf_crh_params, &to_bytes![leaf]?)?;
+    let mut nodes = vec![(index, node)];
+    // The siblings are ordered from the leaf up, starting with the leaf's own.
+    let siblings = std::iter::once(&path.leaf_sibling_hash).chain(path.auth_path.iter().rev());
+    for (level, sibling) in siblings.enumerate() {
+        let sibling = match updated.get(level) {
+            Some((updated_index, updated_node)) if *updated_index == index ^ 1 => updated_node,
+            _ => sibling,
+        };
+        let (left, right) = match index & 1 {
+            0 => (&node, sibling),
+            _ => (sibling, &node),
+        };
+        node = <TwoToOneHash as TwoToOneCRH>::evaluate(
+            &parameters.two_to_one_crh_params,
+            &to_bytes![left]?,
+            &to_bytes![right]?,
+        )?;
+        index >>= 1;
+        nodes.push((index, node));
+    }
+    Ok(nodes)
+}
+
+/// A checkpoint of a `BatchVerifier`, from which verification can be resumed,
+/// e.g., after persisting it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchCheckpoint {
+    /// The root of the account tree after the transactions verified so far.
+    pub root: AccRoot,
+    /// The number of transactions verified so far, including padding.
+    pub num_verified: usize,
+}
+
+/// Verifies a batch of transactions one at a time, starting from the root of
+/// the account tree before the batch, during the epoch `epoch` and at the block
+/// with the commitment `block_commitment`. See `replay_batch`.
+///
+/// Verification can be paused by taking a `checkpoint`, and resumed later by
+/// `restore`-ing it into a verifier for the same batch.
+pub struct BatchVerifier<'a> {
+    parameters: &'a ledger::Parameters,
+    epoch: u64,
+    block_commitment: BlockCommitment,
+    checkpoint: BatchCheckpoint,
+}
+
+impl<'a> BatchVerifier<'a> {
+    /// Create a verifier for a batch applied to the tree with root `initial_root`.
+    pub fn new(
+        parameters: &'a ledger::Parameters,
+        initial_root: &AccRoot,
+        epoch: u64,
+        block_commitment: &BlockCommitment,
+    ) -> Self {
+        Self {
+            parameters,
+            epoch,
+            block_commitment: *block_commitment,
+            checkpoint: BatchCheckpoint {
+                root: *initial_root,
+                num_verified: 0,
+            },
+        }
+    }
+
+    /// Verify the next transaction `tx` of the batch, whose witness `witness` is
+    /// with respect to the current root, and return the root after it.
+    ///
+    /// An error is returned if the witness doesn't match the current root, or if
+    /// the transaction isn't valid, in which case the verifier is left unchanged.
+    pub fn step(
+        &mut self,
+        tx: &Transaction,
+        witness: &TransactionWitness,
+    ) -> Result<AccRoot, Error> {
+        // Padding doesn't change the root.
+        if !tx.is_noop() {
+            self.checkpoint.root = self.apply(tx, witness)?;
+        }
+        self.checkpoint.num_verified += 1;
+        Ok(self.checkpoint.root)
+    }
+
+    /// Compute the root after the valid transaction `tx`, which isn't padding.
+    fn apply(&self, tx: &Transaction, witness: &TransactionWitness) -> Result<AccRoot, Error> {
+        let parameters = self.parameters;
+        let sender = witness.sender.as_ref().ok_or("missing sender witness")?;
+        let recipient = witness.recipient.as_ref().filter(|_| !tx.is_withdrawal());
+        if !tx.validate_with_proofs(
+            parameters,
+            sender,
+            recipient,
+            &self.checkpoint.root,
+            self.epoch,
+            &self.block_commitment,
+        )? {
+            return Err("invalid transaction".into());
+        }
+        let (new_sender_info, new_recipient_info) = tx
+            .new_account_infos(
+                parameters,
+                &sender.info,
+                recipient.map(|r| &r.info),
+                self.epoch,
+            )
+            .ok_or("invalid transaction")?;
+        let mut nodes = updated_path_nodes(parameters, sender, &new_sender_info, &[])?;
+        if let (Some(recipient), Some(new_recipient_info)) = (recipient, new_recipient_info) {
+            nodes = updated_path_nodes(parameters, recipient, &new_recipient_info, &nodes)?;
+        }
+        Ok(nodes.last().expect("path has a root").1)
+    }
+
+    /// Return the root of the account tree after the transactions verified so far.
+    pub fn root(&self) -> AccRoot {
+        self.checkpoint.root
+    }
+
+    /// Return a checkpoint of the verification so far.
+    pub fn checkpoint(&self) -> BatchCheckpoint {
+        self.checkpoint
+    }
+
+    /// Resume verification from `checkpoint`. The next transaction to verify is
+    /// the one after the `checkpoint.num_verified` transactions verified before it.
+    pub fn restore(&mut self, checkpoint: BatchCheckpoint) {
+        self.checkpoint = checkpoint;
+    }
+}
+
+/// Re-derive the root of the account tree after applying the batch of
+/// transactions `transactions` to the tree with root `initial_root`, during the
+/// epoch `epoch` and at the block with the commitment `block_commitment`. `witnesses[i]` are the witnesses of `transactions[i]`, with
+/// respect to the root before it.
+///
+/// The final root is computed purely from the initial root, the transactions,
+/// and the account information before each of them, as the rollup circuit does:
+/// the account information after each transaction is derived rather than
+/// supplied, and the paths are only used to recompute the root. An error is
+/// returned if any witness doesn't match the current root, or if any transaction
+/// isn't valid. Transactions are not recorded in the transaction counter.
+pub fn replay_batch(
+    parameters: &ledger::Parameters,
+    initial_root: &AccRoot,
+    epoch: u64,
+    block_commitment: &BlockCommitment,
+    transactions: &[Transaction],
+    witnesses: &[TransactionWitness],
+) -> Result<AccRoot, Error> {
+    if transactions.len() != witnesses.len() {
+        return Err("each transaction must have exactly one witness".into());
+    }
+    let mut verifier = BatchVerifier::new(parameters, initial_root, epoch, block_commitment);
+    for (tx, witness) in transactions.iter().zip(witnesses) {
+        verifier.step(tx, witness)?;
+    }
+    Ok(verifier.root())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, Parameters, State};
+    use crate::withdrawal::WITHDRAWAL_ACCOUNT;
+
+    #[test]
+    fn replay_batch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays Bob, who pays some of it back, and then withdraws the rest.
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+            Transaction::NOOP,
+            Transaction::create(
+                &pp,
+                bob_id,
+                WITHDRAWAL_ACCOUNT,
+                Amount(3),
+                &bob_sk,
+                &mut rng,
+            ),
+        ];
+        let initial_root = state.root();
+        let mut final_state = state.clone();
+        let mut witnesses = Vec::new();
+        for tx in &transactions {
+            witnesses.push(TransactionWitness::new(&final_state, tx));
+            let _ = final_state.apply_transaction(&pp, tx);
+        }
+        let replay = |transactions: &[Transaction], witnesses: &[TransactionWitness]| {
+            replay_batch(
+                &pp,
+                &initial_root,
+                state.epoch,
+                &state.block_commitment,
+                transactions,
+                witnesses,
+            )
+        };
+        assert_eq!(
+            replay(&transactions, &witnesses).unwrap(),
+            final_state.root()
+        );
+
+        // Witnesses are only valid with respect to the root before their transaction.
+        witnesses.swap(0, 1);
+        assert!(replay(&transactions, &witnesses).is_err());
+        witnesses.swap(0, 1);
+        // Invalid transactions are rejected, even with matching witnesses.
+        let mut bad_transactions = transactions.clone();
+        bad_transactions[0] =
+            Transaction::create(&pp, alice_id, bob_id, Amount(21), &alice_sk, &mut rng);
+        assert!(replay(&bad_transactions, &witnesses).is_err());
+    }
+
+    #[test]
+    fn resumed_verification_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let transactions = [
+            Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng),
+            Transaction::create(&pp, bob_id, alice_id, Amount(2), &bob_sk, &mut rng),
+            Transaction::create(&pp, alice_id, bob_id, Amount(4), &alice_sk, &mut rng),
+            Transaction::NOOP,
+        ];
+        let initial_root = state.root();
+        let mut final_state = state.clone();
+        let mut witnesses = Vec::new();
+        for tx in &transactions {
+            witnesses.push(TransactionWitness::new(&final_state, tx));
+            let _ = final_state.apply_transaction(&pp, tx);
+        }
+        let new_verifier =
+            || BatchVerifier::new(&pp, &initial_root, state.epoch, &state.block_commitment);
+
+        // Verify the batch in one pass.
+        let mut verifier = new_verifier();
+        for (tx, witness) in transactions.iter().zip(&witnesses) {
+            verifier.step(tx, witness).unwrap();
+        }
+        assert_eq!(verifier.root(), final_state.root());
+
+        // Verify the first half, and resume from its checkpoint in a new verifier.
+        let mut verifier = new_verifier();
+        for (tx, witness) in transactions[..2].iter().zip(&witnesses) {
+            verifier.step(tx, witness).unwrap();
+        }
+        let checkpoint = verifier.checkpoint();
+        assert_eq!(checkpoint.num_verified, 2);
+        let mut resumed = new_verifier();
+        resumed.restore(checkpoint);
+        for (tx, witness) in transactions[2..].iter().zip(&witnesses[2..]) {
+            resumed.step(tx, witness).unwrap();
+        }
+        assert_eq!(resumed.root(), final_state.root());
+        assert_eq!(resumed.checkpoint().num_verified, transactions.len());
+
+        // A failed step leaves the verifier unchanged.
+        let mut verifier = new_verifier();
+        assert!(verifier.step(&transactions[1], &witnesses[1]).is_err());
+        assert_eq!(verifier.checkpoint(), new_verifier().checkpoint());
+    }
+}
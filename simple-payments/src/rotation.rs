@@ -0,0 +1,188 @@
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+};
+use crate::ledger;
+use ark_std::rand::Rng;
+
+/// Prefix of the message signed to authorize a key rotation. This separates
+/// rotation messages from the messages of other operations, so that their
+/// signatures can't be reused for one another.
+pub const ROTATE_KEY_TAG: &[u8] = b"rotate-key";
+
+/// A key rotation, which replaces the public key of `account` by
+/// `new_public_key`. Only the key and the nonce of the account change.
+#[derive(Clone, Debug)]
+pub struct RotateKey {
+    /// The account whose key is rotated.
+    pub account: AccountId,
+    /// The public key of the account after the rotation.
+    pub new_public_key: AccountPublicKey,
+    /// The signature over the rotation, under the key *before* the rotation, or
+    /// under the recovery key of the account if `by_recovery_key` is set.
+    pub signature: AccountSignature,
+    /// Whether the rotation is authorized by the recovery key of the account
+    /// instead of its current key, e.g., because the current key is lost. See
+    /// `AccountInformation::recovery_key`.
+    pub by_recovery_key: bool,
+}
+
+/// Construct the message that is signed to authorize a key rotation.
+fn message(
+    parameters: &ledger::Parameters,
+    account: AccountId,
+    new_public_key: &AccountPublicKey,
+) -> Vec<u8> {
+    // The authorized message consists of
+    // (Tag || AccountId || NewPublicKey)
+    let mut message = ROTATE_KEY_TAG.to_vec();
+    message.extend(account.to_bytes_le());
+    message.extend(ark_ff::to_bytes![new_public_key].unwrap());
+    parameters.message_encoding.encode(message)
+}
+
+impl RotateKey {
+    /// Verify just the signature of the rotation.
+    fn verify_signature(&self, pp: &ledger::Parameters, pub_key: &AccountPublicKey) -> bool {
+        let message = message(pp, self.account, &self.new_public_key);
+        self.signature.verify(pp, pub_key, &message)
+    }
+
+    /// The key that must sign the rotation of the account with information
+    /// `info`: its recovery key if the rotation is authorized by it, and its
+    /// current key otherwise. Returns `None` if the rotation is authorized by the
+    /// recovery key, but the account has none.
+    pub fn signer_key(&self, info: &AccountInformation) -> Option<AccountPublicKey> {
+        match self.by_recovery_key {
+            true if info.recovery_key == AccountPublicKey::default() => None,
+            true => Some(info.recovery_key),
+            false => Some(info.public_key),
+        }
+    }
+
+    /// Check that the rotation is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the account exists, and that the signature is valid with
+    ///    respect to its current public key, or with respect to its recovery key
+    ///    if the rotation is authorized by it, in which case the account must
+    ///    have one.
+    /// 2. Verify that the new public key doesn't belong to any account yet.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        match state
+            .id_to_account_info
+            .get(&self.account)
+            .and_then(|info| self.signer_key(info))
+        {
+            Some(signer_key) => {
+                self.verify_signature(parameters, &signer_key)
+                    && !state.pub_key_to_id.contains_key(&self.new_public_key)
+            }
+            None => false,
+        }
+    }
+
+    /// Create a (possibly invalid) key rotation, signed by `account_sk`.
+    pub fn create<R: Rng>(
+        parameters: &ledger::Parameters,
+        account: AccountId,
+        new_public_key: AccountPublicKey,
+        account_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = message(parameters, account, &new_public_key);
+        let signature = AccountSignature::sign(parameters, account_sk, &message, rng);
+        Self {
+            account,
+            new_public_key,
+            signature,
+            by_recovery_key: false,
+        }
+    }
+
+    /// Create a (possibly invalid) key rotation, signed by the recovery key
+    /// `recovery_sk` of the account.
+    pub fn create_with_recovery_key<R: Rng>(
+        parameters: &ledger::Parameters,
+        account: AccountId,
+        new_public_key: AccountPublicKey,
+        recovery_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        Self {
+            by_recovery_key: true,
+            ..Self::create(parameters, account, new_public_key, recovery_sk, rng)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, AssetId, Parameters, State};
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn rotate_key_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (new_pk, new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        // The rotation must be signed by the old key, ...
+        let rotation = RotateKey::create(&pp, alice_id, new_pk, &new_sk, &mut rng);
+        assert!(!rotation.validate(&pp, &state));
+        // ... and can't take over the key of another account.
+        let rotation = RotateKey::create(&pp, alice_id, bob_pk, &alice_sk, &mut rng);
+        assert!(!rotation.validate(&pp, &state));
+
+        let rotation = RotateKey::create(&pp, alice_id, new_pk, &alice_sk, &mut rng);
+        assert!(rotation.validate(&pp, &state));
+        state.apply_rotation(&pp, &rotation).unwrap();
+        let info = state.id_to_account_info[&alice_id];
+        assert_eq!(info.public_key, new_pk);
+        assert_eq!(info.balances.get(AssetId::NATIVE), Some(Amount(10)));
+        assert_eq!(info.nonce, 1);
+        assert_eq!(state.pub_key_to_id.get(&new_pk), Some(&alice_id));
+        assert!(!state.pub_key_to_id.contains_key(&alice_pk));
+
+        // Only the new key authorizes transactions from then on.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &new_sk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+    }
+
+    #[test]
+    fn recovery_key_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (recovery_pk, recovery_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (new_pk, _new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        // An account without a recovery key can't be recovered, ...
+        let rotation =
+            RotateKey::create_with_recovery_key(&pp, alice_id, new_pk, &recovery_sk, &mut rng);
+        assert!(!rotation.validate(&pp, &state));
+        state.set_recovery_key(alice_id, recovery_pk).unwrap();
+        // ... and the recovery key must authorize the recovery, ...
+        let bad_rotation =
+            RotateKey::create_with_recovery_key(&pp, alice_id, new_pk, &alice_sk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+        // ... rather than just sign a rotation of the current key.
+        let bad_rotation = RotateKey::create(&pp, alice_id, new_pk, &recovery_sk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+
+        assert!(rotation.validate(&pp, &state));
+        state.apply_rotation(&pp, &rotation).unwrap();
+        let info = state.id_to_account_info[&alice_id];
+        assert_eq!(info.public_key, new_pk);
+        assert_eq!(info.recovery_key, recovery_pk);
+        assert_eq!(info.nonce, 1);
+    }
+}
@@ -0,0 +1,183 @@
+//! Scheduled executions, with which the sender of a recurring payment signs a
+//! single authorization that lets a transaction be executed once on each of the
+//! epochs of a schedule, rather than signing it anew every time.
+//!
+//! The executions that were made are recorded in a set of used executions, in
+//! the same indexed Merkle tree as the set of processed transactions, so that an
+//! execution is made at most once. An execution is identified by the hash of the
+//! transaction and the epoch it's made on, see `execution_id`.
+
+use crate::account::{AccountPublicKey, AccountSecretKey, AccountSignature};
+use crate::ledger::{AccountHash, Parameters};
+use crate::processed::{InsertionProof, ProcessedSet};
+use crate::transaction::{Transaction, TransactionHash, TransactionId};
+use ark_crypto_primitives::crh::CRH;
+use ark_std::rand::Rng;
+
+/// Prefix of the message that the sender signs to schedule a transaction. This
+/// separates schedules from the messages of other operations, and in particular
+/// from the transactions of the sender.
+pub const SCHEDULE_TAG: &[u8] = b"schedule";
+
+/// The tag that separates the identifiers of executions from other uses of
+/// `AccountHash`.
+pub const EXECUTION_TAG: &[u8] = b"execution";
+
+/// The number of epochs in a schedule. Shorter schedules repeat their last
+/// epoch, so that every schedule has the same shape in the circuit.
+pub const SCHEDULE_LEN: usize = 4;
+
+/// The authorization of a transaction to be executed on each of the epochs of a
+/// schedule, signed by its sender.
+#[derive(Clone, Debug)]
+pub struct ScheduledAuthorization {
+    /// The epochs on which the transaction may be executed.
+    pub epochs: [u64; SCHEDULE_LEN],
+    /// The signature of the sender over the hash of the transaction and the
+    /// epochs.
+    pub signature: AccountSignature,
+}
+
+impl ScheduledAuthorization {
+    /// Construct the message that the sender signs to let the transaction with
+    /// hash `tx_hash` be executed on `epochs`.
+    fn message(
+        parameters: &Parameters,
+        tx_hash: &TransactionHash,
+        epochs: &[u64; SCHEDULE_LEN],
+    ) -> Vec<u8> {
+        // The signed message consists of
+        // (Tag || TransactionHash || Epoch_1 || ... || Epoch_n).
+        let mut message = SCHEDULE_TAG.to_vec();
+        message.extend(ark_ff::to_bytes![tx_hash].unwrap());
+        for epoch in epochs {
+            message.extend(epoch.to_le_bytes());
+        }
+        parameters.message_encoding.encode(message)
+    }
+
+    /// Let `transaction` be executed on each of `epochs`, with the key
+    /// `sender_sk` of its sender. Returns `None` if there are no epochs, or more
+    /// than `SCHEDULE_LEN` of them.
+    pub fn sign<R: Rng>(
+        parameters: &Parameters,
+        transaction: &Transaction,
+        epochs: &[u64],
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Option<Self> {
+        let last = *epochs.last()?;
+        if epochs.len() > SCHEDULE_LEN {
+            return None;
+        }
+        let mut padded = [last; SCHEDULE_LEN];
+        padded[..epochs.len()].copy_from_slice(epochs);
+        let message = Self::message(parameters, &transaction.hash(parameters), &padded);
+        Some(Self {
+            epochs: padded,
+            signature: AccountSignature::sign(parameters, sender_sk, &message, rng),
+        })
+    }
+
+    /// Is this authorization signed for `transaction` by its sender, whose public
+    /// key is `sender_key`?
+    pub fn verify(
+        &self,
+        parameters: &Parameters,
+        transaction: &Transaction,
+        sender_key: &AccountPublicKey,
+    ) -> bool {
+        let message = Self::message(parameters, &transaction.hash(parameters), &self.epochs);
+        self.signature.verify(parameters, sender_key, &message)
+    }
+
+    /// Is `epoch` in the schedule?
+    pub fn includes(&self, epoch: u64) -> bool {
+        self.epochs.contains(&epoch)
+    }
+
+    /// Execute `transaction` on `epoch`, if this authorization is signed by its
+    /// sender, whose public key is `sender_key`, and `epoch` is in the schedule.
+    /// The execution is recorded in the set of used executions `used`, and the
+    /// proof of its insertion is returned. Returns `None` if the execution is
+    /// not authorized, or if it was already made.
+    pub fn execute(
+        &self,
+        parameters: &Parameters,
+        transaction: &Transaction,
+        sender_key: &AccountPublicKey,
+        epoch: u64,
+        used: &mut ProcessedSet,
+    ) -> Option<InsertionProof> {
+        if !self.verify(parameters, transaction, sender_key) || !self.includes(epoch) {
+            return None;
+        }
+        used.insert(parameters, execution_id(parameters, transaction, epoch))
+    }
+}
+
+/// The identifier of the execution of `transaction` on `epoch`, under which it
+/// is recorded in the set of used executions. It doesn't cover the
+/// authorization, and so re-signing a schedule doesn't allow executing the
+/// transaction twice on the same epoch.
+pub fn execution_id(
+    parameters: &Parameters,
+    transaction: &Transaction,
+    epoch: u64,
+) -> TransactionId {
+    // The hashed input consists of
+    // (Tag || TransactionHash || Epoch)
+    let mut input = EXECUTION_TAG.to_vec();
+    input.extend(ark_ff::to_bytes![transaction.hash(parameters)].unwrap());
+    input.extend(epoch.to_le_bytes());
+    AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Amount, State};
+
+    #[test]
+    fn schedule_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let schedule =
+            ScheduledAuthorization::sign(&pp, &tx, &[3, 5, 7], &alice_sk, &mut rng).unwrap();
+        assert_eq!(schedule.epochs, [3, 5, 7, 7]);
+        let mut used = ProcessedSet::new(&pp, 4);
+
+        // Alice's payment is executed on each of the scheduled epochs, ...
+        for epoch in [3, 5, 7] {
+            let pre_root = used.root();
+            let proof = schedule
+                .execute(&pp, &tx, &alice_pk, epoch, &mut used)
+                .unwrap();
+            let id = execution_id(&pp, &tx, epoch);
+            assert!(proof.verify(&pp, &pre_root, &used.root(), id));
+        }
+        // ... but neither on another epoch, nor twice on the same epoch.
+        assert!(schedule
+            .execute(&pp, &tx, &alice_pk, 4, &mut used)
+            .is_none());
+        assert!(schedule
+            .execute(&pp, &tx, &alice_pk, 5, &mut used)
+            .is_none());
+
+        // Bob can't schedule Alice's payments, nor use her schedule for another
+        // payment.
+        let forged = ScheduledAuthorization::sign(&pp, &tx, &[9], &bob_sk, &mut rng).unwrap();
+        assert!(!forged.verify(&pp, &tx, &alice_pk));
+        assert!(forged.verify(&pp, &tx, &bob_pk));
+        let other = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng);
+        assert!(!schedule.verify(&pp, &other, &alice_pk));
+
+        // A schedule has at least one and at most `SCHEDULE_LEN` epochs.
+        assert!(ScheduledAuthorization::sign(&pp, &tx, &[], &alice_sk, &mut rng).is_none());
+        assert!(ScheduledAuthorization::sign(&pp, &tx, &[1; 5], &alice_sk, &mut rng).is_none());
+    }
+}
@@ -0,0 +1,80 @@
+//! Shards of a sharded ledger, which split the account tree into ranges of
+//! consecutive leaves, so that batches of different shards touch disjoint
+//! accounts.
+//!
+//! The shard `id` of size `2^log_size` holds the leaves from `id * 2^log_size`
+//! up to, but excluding, `(id + 1) * 2^log_size`. Transactions must stay within
+//! a single shard, except for the recipient of withdrawals, which has no
+//! account.
+
+use crate::account::AccountId;
+use crate::transaction::Transaction;
+
+/// A shard of the account tree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Shard {
+    /// The index of the shard among the shards of its size.
+    pub id: u8,
+    /// The logarithm of the number of leaves in the shard.
+    pub log_size: u8,
+}
+
+impl Shard {
+    /// Create the shard `id` of size `2^log_size`. Returns `None` if the shard
+    /// holds no account identifier.
+    pub fn new(id: u8, log_size: u8) -> Option<Self> {
+        let first = u32::from(id).checked_shl(log_size.into())?;
+        if log_size > 8 || first > u32::from(u8::MAX) {
+            return None;
+        }
+        Some(Self { id, log_size })
+    }
+
+    /// Is the account `account` in this shard?
+    pub fn contains(&self, account: AccountId) -> bool {
+        u32::from(account.0) >> self.log_size == u32::from(self.id)
+    }
+}
+
+impl Transaction {
+    /// Does the transaction stay within the shard `shard`? This holds if both
+    /// its sender and its recipient are in the shard, where the recipient of a
+    /// withdrawal is exempt.
+    pub fn is_in_shard(&self, shard: &Shard) -> bool {
+        shard.contains(self.sender) && (self.is_withdrawal() || shard.contains(self.recipient))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::Amount;
+    use crate::withdrawal::WITHDRAWAL_ACCOUNT;
+
+    #[test]
+    fn shard_test() {
+        // The shards of size 4 split the identifiers into consecutive ranges.
+        let shard = Shard::new(1, 2).unwrap();
+        assert!((4..8).all(|id| shard.contains(AccountId(id))));
+        assert!(!shard.contains(AccountId(3)) && !shard.contains(AccountId(8)));
+        let whole = Shard::new(0, 8).unwrap();
+        assert!((0..=u8::MAX).all(|id| whole.contains(AccountId(id))));
+        // Shards past the last identifier don't exist.
+        assert_eq!(Shard::new(64, 2), None);
+        assert_eq!(Shard::new(1, 8), None);
+        assert_eq!(Shard::new(0, 9), None);
+
+        // Transactions within the shard stay in it, and so do withdrawals, ...
+        let tx = |sender, recipient| Transaction {
+            sender: AccountId(sender),
+            recipient: AccountId(recipient),
+            amount: Amount(5),
+            ..Transaction::NOOP
+        };
+        assert!(tx(4, 7).is_in_shard(&shard));
+        assert!(tx(5, WITHDRAWAL_ACCOUNT.0).is_in_shard(&shard));
+        // ... but cross-shard transactions don't, whichever side leaves it.
+        assert!(!tx(4, 8).is_in_shard(&shard));
+        assert!(!tx(3, 4).is_in_shard(&shard));
+    }
+}
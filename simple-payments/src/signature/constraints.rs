@@ -52,8 +52,8 @@ mod test {
         let rng = &mut test_rng();
         let parameters = S::setup::<_>(rng).unwrap();
         let (pk, sk) = S::keygen(&parameters, rng).unwrap();
-        let sig = S::sign(&parameters, &sk, &message, rng).unwrap();
-        assert!(S::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
 
         let cs = ConstraintSystem::<F>::new_ref();
 
@@ -61,8 +61,8 @@ mod test {
         let signature_var = SG::SignatureVar::new_witness(cs.clone(), || Ok(&sig)).unwrap();
         let pk_var = SG::PublicKeyVar::new_witness(cs.clone(), || Ok(&pk)).unwrap();
         let mut msg_var = Vec::new();
-        for i in 0..message.len() {
-            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(&message[i])).unwrap())
+        for byte in message {
+            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(byte)).unwrap())
         }
         let valid_sig_var = SG::verify(&parameters_var, &pk_var, &msg_var, &signature_var).unwrap();
 
@@ -92,4 +92,19 @@ mod test {
             "Bad message".as_bytes(),
         );
     }
+
+    #[test]
+    fn schnorr_point_signature_test() {
+        type F = <JubJub as ProjectiveCurve>::BaseField;
+        let message = "Hi, I am a Schnorr signature with a point!";
+        sign_and_verify::<
+            F,
+            schnorr::SchnorrPoint<JubJub>,
+            SchnorrPointSignatureVerifyGadget<JubJub, JubJubVar>,
+        >(message.as_bytes());
+        failed_verification::<schnorr::SchnorrPoint<JubJub>>(
+            message.as_bytes(),
+            "Bad message".as_bytes(),
+        );
+    }
 }
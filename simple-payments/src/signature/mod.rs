@@ -48,8 +48,8 @@ mod test {
         let rng = &mut test_rng();
         let parameters = S::setup::<_>(rng).unwrap();
         let (pk, sk) = S::keygen(&parameters, rng).unwrap();
-        let sig = S::sign(&parameters, &sk, &message, rng).unwrap();
-        assert!(S::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
     }
 
     fn failed_verification<S: SignatureScheme>(message: &[u8], bad_message: &[u8]) {
@@ -69,4 +69,14 @@ mod test {
             "Bad message".as_bytes(),
         );
     }
+
+    #[test]
+    fn schnorr_point_signature_test() {
+        let message = "Hi, I am a Schnorr signature with a point!";
+        sign_and_verify::<schnorr::SchnorrPoint<JubJub>>(message.as_bytes());
+        failed_verification::<schnorr::SchnorrPoint<JubJub>>(
+            message.as_bytes(),
+            "Bad message".as_bytes(),
+        );
+    }
 }
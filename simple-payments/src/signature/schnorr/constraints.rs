@@ -1,6 +1,6 @@
 use ark_ec::ProjectiveCurve;
-use ark_ff::{to_bytes, Field};
-use ark_r1cs_std::{bits::uint8::UInt8, prelude::*};
+use ark_ff::{to_bytes, BigInteger, Field, FpParameters, PrimeField};
+use ark_r1cs_std::{bits::uint8::UInt8, fields::fp::FpVar, prelude::*, ToConstraintFieldGadget};
 use ark_relations::r1cs::ConstraintSystemRef;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_std::vec::Vec;
@@ -13,10 +13,23 @@ use derivative::Derivative;
 
 use core::{borrow::Borrow, marker::PhantomData};
 
-use crate::signature::schnorr::{Parameters, PublicKey, Schnorr, Signature};
+use crate::signature::schnorr::{
+    Parameters, PointSignature, PublicKey, Schnorr, SchnorrPoint, Signature,
+};
 
 type ConstraintF<C> = <<C as ProjectiveCurve>::BaseField as Field>::BasePrimeField;
 
+/// A public key, a message, and a point signature of it.
+pub type SignedMessageVar<C, GC> = (
+    PublicKeyVar<C, GC>,
+    Vec<UInt8<ConstraintF<C>>>,
+    PointSignatureVar<C, GC>,
+);
+
+/// The default number of bits of the windows of the multiplications of the
+/// generator, which multiplies it bit by bit. See `ParametersVar::with_window_size`.
+pub const DEFAULT_WINDOW_SIZE: usize = 1;
+
 #[derive(Clone)]
 pub struct ParametersVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
 where
@@ -24,9 +37,67 @@ where
 {
     generator: GC,
     salt: Option<Vec<UInt8<ConstraintF<C>>>>,
+    window_size: usize,
     _curve: PhantomData<C>,
 }
 
+impl<C, GC> ParametersVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    /// Multiply the generator by scalars in windows of `window_size` bits, each
+    /// of which selects one of `2^window_size` precomputed multiples of the
+    /// generator. Larger windows take fewer additions, but the tables, and the
+    /// selections from them, grow exponentially. This only applies if the
+    /// generator is a constant: otherwise, it is multiplied bit by bit.
+    pub fn with_window_size(self, window_size: usize) -> Self {
+        assert!(
+            (1..=16).contains(&window_size),
+            "the window size must be between 1 and 16"
+        );
+        Self {
+            window_size,
+            ..self
+        }
+    }
+
+    /// The number of bits of the windows of the multiplications of the generator.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// Multiply the generator by the scalar with little-endian bits `bits`.
+    fn generator_mul(&self, bits: &[Boolean<ConstraintF<C>>]) -> Result<GC, SynthesisError> {
+        if self.window_size == 1 || !self.generator.is_constant() {
+            return self.generator.scalar_mul_le(bits.iter());
+        }
+        let mut base = self.generator.value()?;
+        let mut result = GC::zero();
+        for window in bits.chunks(self.window_size) {
+            // Select `j * base`, where `j` is the value of the bits of the window,
+            // by halving the table of all its multiples once per bit. The first
+            // selections are between constants, and so they are free.
+            let mut table = Vec::with_capacity(1 << window.len());
+            let mut multiple = C::zero();
+            for _ in 0..(1 << window.len()) {
+                table.push(GC::constant(multiple));
+                multiple += &base;
+            }
+            for bit in window {
+                table = table
+                    .chunks(2)
+                    .map(|pair| bit.select(&pair[1], &pair[0]))
+                    .collect::<Result<_, _>>()?;
+            }
+            result += &table[0];
+            base = multiple;
+        }
+        Ok(result)
+    }
+}
+
 #[derive(Derivative)]
 #[derivative(
     Debug(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>"),
@@ -82,19 +153,17 @@ where
         message: &[UInt8<ConstraintF<C>>],
         signature: &Self::SignatureVar,
     ) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
-        let prover_response = signature.prover_response.clone();
+        let prover_response = signature.prover_response.to_bits_le()?;
         let verifier_challenge = signature.verifier_challenge.clone();
-        let mut claimed_prover_commitment = parameters
-            .generator
-            .scalar_mul_le(prover_response.to_bits_le()?.iter())?;
+        let mut claimed_prover_commitment = parameters.generator_mul(&prover_response)?;
         let public_key_times_verifier_challenge = public_key
             .pub_key
             .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
         claimed_prover_commitment += &public_key_times_verifier_challenge;
 
         let mut hash_input = Vec::new();
-        if parameters.salt.is_some() {
-            hash_input.extend_from_slice(parameters.salt.as_ref().unwrap());
+        if let Some(salt) = &parameters.salt {
+            hash_input.extend_from_slice(salt);
         }
         hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
         hash_input.extend_from_slice(&claimed_prover_commitment.to_bytes()?);
@@ -106,10 +175,253 @@ where
         )?;
         let obtained_verifier_challenge = ROGadget::evaluate(&b2s_params, &hash_input)?.0;
 
-        obtained_verifier_challenge.is_eq(&verifier_challenge.to_vec())
+        obtained_verifier_challenge
+            .is_eq(&verifier_challenge.to_vec())?
+            .and(&is_canonical_scalar::<C>(&prover_response)?)
     }
 }
 
+#[derive(Derivative)]
+#[derivative(
+    Debug(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>"),
+    Clone(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>")
+)]
+pub struct PointSignatureVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
+where
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    prover_commitment: GC,
+    prover_response: Vec<UInt8<ConstraintF<C>>>,
+    #[doc(hidden)]
+    _group: PhantomData<C>,
+}
+
+pub struct SchnorrPointSignatureVerifyGadget<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
+where
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+    #[doc(hidden)]
+    _group_gadget: PhantomData<*const GC>,
+}
+
+impl<C, GC> SigVerifyGadget<SchnorrPoint<C>, ConstraintF<C>>
+    for SchnorrPointSignatureVerifyGadget<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    type ParametersVar = ParametersVar<C, GC>;
+    type PublicKeyVar = PublicKeyVar<C, GC>;
+    type SignatureVar = PointSignatureVar<C, GC>;
+
+    fn verify(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        message: &[UInt8<ConstraintF<C>>],
+        signature: &Self::SignatureVar,
+    ) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
+        let verifier_challenge = point_verifier_challenge(
+            parameters,
+            public_key,
+            &signature.prover_commitment,
+            message,
+        )?;
+
+        // The signature is valid iff sG + eY = kG.
+        let prover_response = signature.prover_response.to_bits_le()?;
+        let mut claimed_prover_commitment = parameters.generator_mul(&prover_response)?;
+        let public_key_times_verifier_challenge = public_key
+            .pub_key
+            .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
+        claimed_prover_commitment += &public_key_times_verifier_challenge;
+
+        claimed_prover_commitment
+            .is_eq(&signature.prover_commitment)?
+            .and(&is_canonical_scalar::<C>(&prover_response)?)
+    }
+}
+
+/// Is the scalar with the little-endian bits `bits` less than the order of the
+/// group, i.e., are the bits its canonical encoding? The prover response of a
+/// signature only matters modulo the order, and so adding the order to the
+/// response of a valid signature would otherwise yield another valid signature
+/// of the same message.
+fn is_canonical_scalar<C: ProjectiveCurve>(
+    bits: &[Boolean<ConstraintF<C>>],
+) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
+    let order = <C::ScalarField as PrimeField>::Params::MODULUS.to_bits_le();
+    // Compare the bits with those of the order from the most significant one
+    // down, tracking whether they are equal so far, and whether they are less.
+    let mut is_less = Boolean::FALSE;
+    let mut is_equal = Boolean::TRUE;
+    for (i, bit) in bits.iter().enumerate().rev() {
+        if order.get(i).copied().unwrap_or(false) {
+            is_less = is_less.or(&is_equal.and(&bit.not())?)?;
+            is_equal = is_equal.and(bit)?;
+        } else {
+            is_equal = is_equal.and(&bit.not())?;
+        }
+    }
+    Ok(is_less)
+}
+
+/// Compute the verifier challenge e := H(salt || pubkey || r || msg) of a point
+/// signature with the prover commitment `prover_commitment`.
+fn point_verifier_challenge<C, GC>(
+    parameters: &ParametersVar<C, GC>,
+    public_key: &PublicKeyVar<C, GC>,
+    prover_commitment: &GC,
+    message: &[UInt8<ConstraintF<C>>],
+) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    let mut hash_input = Vec::new();
+    if let Some(salt) = &parameters.salt {
+        hash_input.extend_from_slice(salt);
+    }
+    hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
+    hash_input.extend_from_slice(&prover_commitment.to_bytes()?);
+    hash_input.extend_from_slice(message);
+
+    let b2s_params =
+        <B2SParamsVar as AllocVar<_, ConstraintF<C>>>::new_constant(ConstraintSystemRef::None, ())?;
+    Ok(ROGadget::evaluate(&b2s_params, &hash_input)?.0)
+}
+
+/// Decompose `value`, which must be less than `2^num_bits`, into its `num_bits`
+/// bits, in little-endian order. This is cheaper than `FpVar::to_bits_le` for
+/// small values, since only `num_bits` bits are allocated.
+fn to_bounded_bits_le<F: PrimeField>(
+    value: &FpVar<F>,
+    num_bits: usize,
+) -> Result<Vec<Boolean<F>>, SynthesisError> {
+    let cs = value.cs();
+    let mode = if cs.is_none() {
+        AllocationMode::Constant
+    } else {
+        AllocationMode::Witness
+    };
+    let native = value
+        .value()
+        .ok()
+        .map(|value| value.into_repr().to_bits_le());
+    let bits = (0..num_bits)
+        .map(|i| {
+            Boolean::new_variable(
+                cs.clone(),
+                || {
+                    native
+                        .as_ref()
+                        .map(|bits| bits[i])
+                        .ok_or(SynthesisError::AssignmentMissing)
+                },
+                mode,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Boolean::le_bits_to_fp_var(&bits)?.enforce_equal(value)?;
+    Ok(bits)
+}
+
+/// The number of bits of each limb of the prover responses in
+/// `verify_signatures_randomized`.
+const LIMB_BITS: usize = 128;
+/// The maximum number of bits of each randomizer in
+/// `verify_signatures_randomized`, so that the sums of the products of the
+/// randomizers and the limbs fit in a field element.
+const MAX_RANDOMIZER_BITS: usize = 64;
+
+/// Verify the point signatures `items`, each of which consists of a public key,
+/// a message and a signature over the message, with a single random linear
+/// combination of their verification equations.
+///
+/// Each signature `(R_i, s_i)` is valid iff `s_i G = R_i - e_i Y_i`. Rather than
+/// checking each of these equations, we check that `(Σ z_i s_i) G = Σ z_i (R_i -
+/// e_i Y_i)`, where the randomizers `z_i` are consecutive chunks of the bits of
+/// `challenge`. This replaces the multiplication of the generator for each
+/// signature by a single one, at the cost of a multiplication by `z_i`, which is
+/// short. The sum `Σ z_i s_i` is computed over the integers, from limbs of the
+/// responses, and so it never needs to be reduced modulo the group order.
+///
+/// `challenge` must be chosen after the signatures are fixed, e.g. derived from a
+/// public transcript of them. If any of the signatures is invalid, the check then
+/// passes with probability at most `2^-k`, where `k` is the number of bits of
+/// each randomizer, which must be at most 64. Like other batch verification
+/// equations, this one also accepts signatures whose commitments are offset by
+/// points of small order, which the individual check rejects.
+pub fn verify_signatures_randomized<C, GC>(
+    parameters: &ParametersVar<C, GC>,
+    items: &[SignedMessageVar<C, GC>],
+    challenge: &[Boolean<ConstraintF<C>>],
+) -> Result<Boolean<ConstraintF<C>>, SynthesisError>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    assert!(
+        !items.is_empty() && challenge.len().is_multiple_of(items.len()),
+        "the challenge must be split evenly among the signatures"
+    );
+    let randomizer_bits = challenge.len() / items.len();
+    assert!(
+        randomizer_bits <= MAX_RANDOMIZER_BITS,
+        "randomizers must have at most 64 bits"
+    );
+    let mut combined_commitment = GC::zero();
+    let mut combined_response = Vec::<FpVar<ConstraintF<C>>>::new();
+    for ((public_key, message, signature), randomizer) in
+        items.iter().zip(challenge.chunks(randomizer_bits))
+    {
+        let verifier_challenge = point_verifier_challenge(
+            parameters,
+            public_key,
+            &signature.prover_commitment,
+            message,
+        )?;
+        let public_key_times_verifier_challenge = public_key
+            .pub_key
+            .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
+        let commitment = signature.prover_commitment.clone() - &public_key_times_verifier_challenge;
+        combined_commitment += &commitment.scalar_mul_le(randomizer.iter())?;
+
+        let randomizer = Boolean::le_bits_to_fp_var(randomizer)?;
+        let response_bits = signature.prover_response.to_bits_le()?;
+        combined_response.resize(response_bits.len() / LIMB_BITS, FpVar::zero());
+        for (sum, limb) in combined_response
+            .iter_mut()
+            .zip(response_bits.chunks(LIMB_BITS))
+        {
+            *sum += &randomizer * Boolean::le_bits_to_fp_var(limb)?;
+        }
+    }
+
+    // Each limb of the sum, along with the carry from the one below it, is less
+    // than `2^limb_sum_bits`.
+    let limb_sum_bits = LIMB_BITS + randomizer_bits + ark_std::log2(items.len()) as usize + 1;
+    let mut scalar = Vec::new();
+    let mut carry = FpVar::zero();
+    let last = combined_response.len() - 1;
+    for (i, sum) in combined_response.iter().enumerate() {
+        let bits = to_bounded_bits_le(&(sum + &carry), limb_sum_bits)?;
+        if i == last {
+            scalar.extend(bits);
+        } else {
+            carry = Boolean::le_bits_to_fp_var(&bits[LIMB_BITS..])?;
+            scalar.extend_from_slice(&bits[..LIMB_BITS]);
+        }
+    }
+    parameters
+        .generator_mul(&scalar)?
+        .is_eq(&combined_commitment)
+}
+
 impl<C, GC> AllocVar<Parameters<C>, ConstraintF<C>> for ParametersVar<C, GC>
 where
     C: ProjectiveCurve,
@@ -138,12 +450,14 @@ where
                 return Ok(Self {
                     generator,
                     salt: Some(constraint_salt),
+                    window_size: DEFAULT_WINDOW_SIZE,
                     _curve: PhantomData,
                 });
             }
             Ok(Self {
                 generator,
                 salt: None,
+                window_size: DEFAULT_WINDOW_SIZE,
                 _curve: PhantomData,
             })
         })
@@ -169,6 +483,29 @@ where
     }
 }
 
+impl<C, GC> PublicKeyVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    /// Offset the public key by `tweak` times the generator, where `tweak` is
+    /// given by its little-endian bits. This is the public key of the secret key
+    /// offset by `tweak`.
+    pub fn tweak(
+        &self,
+        parameters: &ParametersVar<C, GC>,
+        tweak: &[Boolean<ConstraintF<C>>],
+    ) -> Result<Self, SynthesisError> {
+        let mut pub_key = self.pub_key.clone();
+        pub_key += &parameters.generator_mul(tweak)?;
+        Ok(Self {
+            pub_key,
+            _group: PhantomData,
+        })
+    }
+}
+
 impl<C, GC> AllocVar<Signature<C>, ConstraintF<C>> for SignatureVar<C, GC>
 where
     C: ProjectiveCurve,
@@ -209,6 +546,36 @@ where
     }
 }
 
+impl<C, GC> AllocVar<PointSignature<C>, ConstraintF<C>> for PointSignatureVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    fn new_variable<T: Borrow<PointSignature<C>>>(
+        cs: impl Into<Namespace<ConstraintF<C>>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|val| {
+            let cs = cs.into();
+            let val = val.borrow();
+            let prover_commitment =
+                GC::new_variable(cs.clone(), || Ok(val.prover_commitment), mode)?;
+            let response_bytes = to_bytes![val.prover_response].unwrap();
+            let prover_response = response_bytes
+                .iter()
+                .map(|byte| UInt8::new_variable(cs.clone(), || Ok(byte), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(PointSignatureVar {
+                prover_commitment,
+                prover_response,
+                _group: PhantomData,
+            })
+        })
+    }
+}
+
 impl<C, GC> EqGadget<ConstraintF<C>> for PublicKeyVar<C, GC>
 where
     C: ProjectiveCurve,
@@ -241,6 +608,42 @@ where
     }
 }
 
+impl<C, GC> CondSelectGadget<ConstraintF<C>> for PublicKeyVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    #[inline]
+    fn conditionally_select(
+        cond: &Boolean<ConstraintF<C>>,
+        true_value: &Self,
+        false_value: &Self,
+    ) -> Result<Self, SynthesisError> {
+        Ok(Self {
+            pub_key: cond.select(&true_value.pub_key, &false_value.pub_key)?,
+            _group: PhantomData,
+        })
+    }
+}
+
+impl<C, GC> R1CSVar<ConstraintF<C>> for PublicKeyVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    type Value = PublicKey<C>;
+
+    fn cs(&self) -> ConstraintSystemRef<ConstraintF<C>> {
+        self.pub_key.cs()
+    }
+
+    fn value(&self) -> Result<Self::Value, SynthesisError> {
+        self.pub_key.value().map(|pub_key| pub_key.into_affine())
+    }
+}
+
 impl<C, GC> ToBytesGadget<ConstraintF<C>> for PublicKeyVar<C, GC>
 where
     C: ProjectiveCurve,
@@ -251,3 +654,208 @@ where
         self.pub_key.to_bytes()
     }
 }
+
+impl<C, GC> ToConstraintFieldGadget<ConstraintF<C>> for PublicKeyVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>> + ToConstraintFieldGadget<ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    fn to_constraint_field(&self) -> Result<Vec<FpVar<ConstraintF<C>>>, SynthesisError> {
+        self.pub_key.to_constraint_field()
+    }
+}
+
+impl<C, GC> ToBytesGadget<ConstraintF<C>> for SignatureVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    fn to_bytes(&self) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError> {
+        let mut bytes = self.prover_response.clone();
+        bytes.extend_from_slice(&self.verifier_challenge);
+        Ok(bytes)
+    }
+}
+
+impl<C, GC> ToBytesGadget<ConstraintF<C>> for PointSignatureVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    fn to_bytes(&self) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError> {
+        let mut bytes = self.prover_commitment.to_bytes()?;
+        bytes.extend_from_slice(&self.prover_response);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signature::{SigVerifyGadget, SignatureScheme};
+    use ark_ec::AffineCurve;
+    use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective as JubJub, Fq, Fr};
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+
+    type TestParamsVar = ParametersVar<JubJub, EdwardsVar>;
+    type Items = Vec<(
+        PublicKeyVar<JubJub, EdwardsVar>,
+        Vec<UInt8<Fq>>,
+        PointSignatureVar<JubJub, EdwardsVar>,
+    )>;
+
+    #[test]
+    fn verify_signatures_randomized_test() {
+        let mut rng = ark_std::test_rng();
+        let parameters = SchnorrPoint::<JubJub>::setup(&mut rng).unwrap();
+        let messages = (0u8..8).map(|i| vec![i; 8]).collect::<Vec<_>>();
+        let signed = messages
+            .iter()
+            .map(|message| {
+                let (pk, sk) = SchnorrPoint::<JubJub>::keygen(&parameters, &mut rng).unwrap();
+                let signature =
+                    SchnorrPoint::<JubJub>::sign(&parameters, &sk, message, &mut rng).unwrap();
+                (pk, signature)
+            })
+            .collect::<Vec<_>>();
+
+        // Allocate the signatures over `messages`, along with the parameters.
+        let allocate = |cs: ConstraintSystemRef<Fq>, messages: &[Vec<u8>]| {
+            let params = TestParamsVar::new_constant(cs.clone(), &parameters).unwrap();
+            let items: Items = signed
+                .iter()
+                .zip(messages)
+                .map(|((pk, signature), message)| {
+                    (
+                        PublicKeyVar::new_witness(cs.clone(), || Ok(pk)).unwrap(),
+                        UInt8::new_witness_vec(cs.clone(), message).unwrap(),
+                        PointSignatureVar::new_witness(cs.clone(), || Ok(signature)).unwrap(),
+                    )
+                })
+                .collect();
+            (params, items)
+        };
+        // Verify the signatures over `messages` with a random challenge, and return
+        // the result, along with the number of constraints of the verification.
+        let verify_randomized = |messages: &[Vec<u8>], rng: &mut ark_std::rand::rngs::StdRng| {
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let (params, items) = allocate(cs.clone(), messages);
+            let challenge = (0..8 * 64)
+                .map(|_| Boolean::new_input(cs.clone(), || Ok(rng.gen::<bool>())).unwrap())
+                .collect::<Vec<_>>();
+            let before = cs.num_constraints();
+            let result = verify_signatures_randomized(&params, &items, &challenge).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            (result.value().unwrap(), cs.num_constraints() - before)
+        };
+
+        let (is_valid, randomized_constraints) = verify_randomized(&messages, &mut rng);
+        assert!(is_valid);
+
+        // Verifying each signature on its own takes more constraints, although
+        // both are dominated by the hashing of the messages.
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let (params, items) = allocate(cs.clone(), &messages);
+        let before = cs.num_constraints();
+        let results = items
+            .iter()
+            .map(|(pk, message, signature)| {
+                SchnorrPointSignatureVerifyGadget::verify(&params, pk, message, signature)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(Boolean::kary_and(&results).unwrap().value().unwrap());
+        let individual_constraints = cs.num_constraints() - before;
+        assert!(randomized_constraints < individual_constraints);
+
+        // A single invalid signature makes the check fail, whatever the challenge.
+        let mut tampered = messages.clone();
+        tampered[2][0] ^= 1;
+        for _ in 0..2 {
+            assert!(!verify_randomized(&tampered, &mut rng).0);
+        }
+    }
+
+    #[test]
+    fn canonical_response_test() {
+        let mut rng = ark_std::test_rng();
+        let parameters = Schnorr::<JubJub>::setup(&mut rng).unwrap();
+        let (pk, sk) = Schnorr::<JubJub>::keygen(&parameters, &mut rng).unwrap();
+        let message = b"canonical response";
+        let signature = Schnorr::<JubJub>::sign(&parameters, &sk, message, &mut rng).unwrap();
+
+        // Verify `signature` with the bytes `response` as its prover response.
+        let verify = |response: &[u8]| {
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let params = TestParamsVar::new_constant(cs.clone(), &parameters).unwrap();
+            let pk = PublicKeyVar::new_witness(cs.clone(), || Ok(pk)).unwrap();
+            let message = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+            let signature = SignatureVar {
+                prover_response: UInt8::new_witness_vec(cs.clone(), response).unwrap(),
+                verifier_challenge: UInt8::new_witness_vec(
+                    cs.clone(),
+                    &signature.verifier_challenge,
+                )
+                .unwrap(),
+                _group: PhantomData,
+            };
+            let result =
+                SchnorrSignatureVerifyGadget::verify(&params, &pk, &message, &signature).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            result.value().unwrap()
+        };
+        assert!(verify(&to_bytes![signature.prover_response].unwrap()));
+
+        // Adding the group order to the response leaves `sG` unchanged, and so the
+        // signature would still verify, but the response is no longer canonical.
+        let mut response = signature.prover_response.into_repr();
+        response.add_nocarry(&<Fr as PrimeField>::Params::MODULUS);
+        assert_eq!(
+            parameters.generator.mul(response),
+            parameters.generator.mul(signature.prover_response)
+        );
+        assert!(!verify(&to_bytes![response].unwrap()));
+    }
+
+    #[test]
+    fn window_size_test() {
+        let mut rng = ark_std::test_rng();
+        let parameters = Schnorr::<JubJub>::setup(&mut rng).unwrap();
+        let (pk, sk) = Schnorr::<JubJub>::keygen(&parameters, &mut rng).unwrap();
+        let message = b"window size";
+        let signature = Schnorr::<JubJub>::sign(&parameters, &sk, message, &mut rng).unwrap();
+
+        // Verify `signature` with windows of `window_size` bits, and return the
+        // result, along with the number of constraints of the verification.
+        let verify = |window_size, message: &[u8]| {
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let params = TestParamsVar::new_constant(cs.clone(), &parameters)
+                .unwrap()
+                .with_window_size(window_size);
+            let pk = PublicKeyVar::new_witness(cs.clone(), || Ok(pk)).unwrap();
+            let message = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+            let signature = SignatureVar::new_witness(cs.clone(), || Ok(&signature)).unwrap();
+            let before = cs.num_constraints();
+            let result =
+                SchnorrSignatureVerifyGadget::verify(&params, &pk, &message, &signature).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            (result.value().unwrap(), cs.num_constraints() - before)
+        };
+
+        // All window sizes agree on which signatures are valid, ...
+        let (is_valid, bitwise_constraints) = verify(DEFAULT_WINDOW_SIZE, message);
+        let (is_valid_windowed, windowed_constraints) = verify(2, message);
+        assert!(is_valid && is_valid_windowed);
+        assert!(!verify(2, b"other message").0);
+        // ... but small windows save additions, ...
+        assert!(windowed_constraints < bitwise_constraints);
+        // ... while large ones spend more on selecting from their tables.
+        let (is_valid_wide, wide_constraints) = verify(6, message);
+        assert!(is_valid_wide);
+        assert!(wide_constraints > bitwise_constraints);
+    }
+}
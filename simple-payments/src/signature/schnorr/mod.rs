@@ -15,6 +15,7 @@ use digest::Digest;
 use derivative::Derivative;
 #[cfg(feature = "r1cs")]
 pub mod constraints;
+pub mod musig;
 
 pub struct Schnorr<C: ProjectiveCurve> {
     _group: PhantomData<C>,
@@ -48,6 +49,14 @@ pub struct Signature<C: ProjectiveCurve> {
     pub verifier_challenge: [u8; 32],
 }
 
+impl<C: ProjectiveCurve> ToBytes for Signature<C> {
+    #[inline]
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        self.prover_response.write(&mut writer)?;
+        self.verifier_challenge.write(&mut writer)
+    }
+}
+
 impl<C: ProjectiveCurve + Hash> SignatureScheme for Schnorr<C>
 where
     C::ScalarField: PrimeField,
@@ -106,8 +115,8 @@ where
             // Hash everything to get verifier challenge.
             // e := H(salt || pubkey || r || msg);
             let mut hash_input = Vec::new();
-            if parameters.salt != None {
-                hash_input.extend_from_slice(&parameters.salt.unwrap());
+            if let Some(salt) = parameters.salt {
+                hash_input.extend_from_slice(&salt);
             }
             hash_input.extend_from_slice(&to_bytes![sk.public_key]?);
             hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
@@ -157,8 +166,8 @@ where
 
         // e = H(salt, kG, msg)
         let mut hash_input = Vec::new();
-        if parameters.salt != None {
-            hash_input.extend_from_slice(&parameters.salt.unwrap());
+        if let Some(salt) = parameters.salt {
+            hash_input.extend_from_slice(&salt);
         }
         hash_input.extend_from_slice(&to_bytes![pk]?);
         hash_input.extend_from_slice(&to_bytes![claimed_prover_commitment]?);
@@ -173,6 +182,106 @@ where
     }
 }
 
+/// A variant of Schnorr signatures in which the signature contains the prover's
+/// commitment `R = k·G` as a curve point, rather than the verifier challenge.
+/// The challenge is recomputed by the verifier instead.
+pub struct SchnorrPoint<C: ProjectiveCurve> {
+    _group: PhantomData<C>,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct PointSignature<C: ProjectiveCurve> {
+    pub prover_commitment: C::Affine,
+    pub prover_response: C::ScalarField,
+}
+
+impl<C: ProjectiveCurve> ToBytes for PointSignature<C> {
+    #[inline]
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        self.prover_commitment.write(&mut writer)?;
+        self.prover_response.write(&mut writer)
+    }
+}
+
+/// Compute the verifier challenge e := H(salt || pubkey || r || msg).
+fn verifier_challenge<C: ProjectiveCurve>(
+    parameters: &Parameters<C>,
+    pk: &PublicKey<C>,
+    prover_commitment: &C::Affine,
+    message: &[u8],
+) -> Result<[u8; 32], Error> {
+    let mut hash_input = Vec::new();
+    if let Some(salt) = parameters.salt {
+        hash_input.extend_from_slice(&salt);
+    }
+    hash_input.extend_from_slice(&to_bytes![pk]?);
+    hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
+    hash_input.extend_from_slice(message);
+    let mut verifier_challenge = [0u8; 32];
+    verifier_challenge.copy_from_slice(&Blake2s::digest(&hash_input));
+    Ok(verifier_challenge)
+}
+
+impl<C: ProjectiveCurve + Hash> SignatureScheme for SchnorrPoint<C>
+where
+    C::ScalarField: PrimeField,
+{
+    type Parameters = Parameters<C>;
+    type PublicKey = PublicKey<C>;
+    type SecretKey = SecretKey<C>;
+    type Signature = PointSignature<C>;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error> {
+        Schnorr::<C>::setup(rng)
+    }
+
+    fn keygen<R: Rng>(
+        parameters: &Self::Parameters,
+        rng: &mut R,
+    ) -> Result<(Self::PublicKey, Self::SecretKey), Error> {
+        Schnorr::<C>::keygen(parameters, rng)
+    }
+
+    fn sign<R: Rng>(
+        parameters: &Self::Parameters,
+        sk: &Self::SecretKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<Self::Signature, Error> {
+        // r := k · G
+        let random_scalar = C::ScalarField::rand(rng);
+        let prover_commitment = parameters.generator.mul(random_scalar).into_affine();
+        let verifier_challenge =
+            verifier_challenge::<C>(parameters, &sk.public_key, &prover_commitment, message)?;
+        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
+        // k - xe;
+        let prover_response = random_scalar - (verifier_challenge_fe * sk.secret_key);
+        Ok(PointSignature {
+            prover_commitment,
+            prover_response,
+        })
+    }
+
+    fn verify(
+        parameters: &Self::Parameters,
+        pk: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<bool, Error> {
+        let PointSignature {
+            prover_commitment,
+            prover_response,
+        } = signature;
+        let verifier_challenge =
+            verifier_challenge::<C>(parameters, pk, prover_commitment, message)?;
+        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
+        // The signature is valid iff sG + eY = kG.
+        let mut claimed_prover_commitment = parameters.generator.mul(*prover_response);
+        claimed_prover_commitment += &pk.mul(verifier_challenge_fe);
+        Ok(claimed_prover_commitment.into_affine() == *prover_commitment)
+    }
+}
+
 pub fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
     let mut bits = Vec::with_capacity(bytes.len() * 8);
     for byte in bytes {
@@ -0,0 +1,181 @@
+//! MuSig-style aggregation of Schnorr public keys and signatures.
+//!
+//! The signers of a joint account aggregate their public keys into a single
+//! public key `Y = a_1·Y_1 + ... + a_n·Y_n`, where each coefficient `a_i` is a
+//! hash of all the public keys and of `Y_i`. The coefficients prevent a signer
+//! from choosing their key as a function of the others' keys, and so from
+//! controlling the aggregated key alone.
+//!
+//! To sign jointly, each signer commits to a random nonce `k_i` with
+//! `R_i = k_i·G`, and, once all commitments are known, sends the share
+//! `s_i = k_i - e·a_i·x_i` of the response, where `e` is the challenge for the
+//! aggregated key and `R = R_1 + ... + R_n`. The resulting signature `(s, e)`,
+//! with `s = s_1 + ... + s_n`, is an ordinary `Schnorr` signature under the
+//! aggregated key, and so it is verified like any other signature.
+
+use super::{verifier_challenge, Parameters, PublicKey, SecretKey, Signature};
+use ark_crypto_primitives::Error;
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::{to_bytes, PrimeField, UniformRand, Zero};
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+use blake2::Blake2s;
+use digest::Digest;
+
+/// Compute the coefficient of each key in `keys`: a_i := H(Y_1 || ... || Y_n || Y_i).
+fn key_coefficients<C: ProjectiveCurve>(keys: &[PublicKey<C>]) -> Result<Vec<C::ScalarField>, Error>
+where
+    C::ScalarField: PrimeField,
+{
+    let all_keys = to_bytes![keys]?;
+    keys.iter()
+        .map(|key| {
+            let mut hash_input = all_keys.clone();
+            hash_input.extend_from_slice(&to_bytes![key]?);
+            Ok(C::ScalarField::from_le_bytes_mod_order(&Blake2s::digest(
+                &hash_input,
+            )))
+        })
+        .collect()
+}
+
+/// Aggregate the public keys `keys` of the signers of a joint account into the
+/// public key of the account. The order of the keys matters.
+pub fn aggregate_public_keys<C: ProjectiveCurve>(
+    keys: &[PublicKey<C>],
+) -> Result<PublicKey<C>, Error>
+where
+    C::ScalarField: PrimeField,
+{
+    let mut aggregated_key = C::zero();
+    for (key, coefficient) in keys.iter().zip(key_coefficients::<C>(keys)?) {
+        aggregated_key += &key.mul(coefficient);
+    }
+    Ok(aggregated_key.into_affine())
+}
+
+/// Sample the secret nonce `k_i` of a signer, along with its commitment
+/// `R_i = k_i·G`, which is sent to the other signers.
+pub fn commit_nonce<C: ProjectiveCurve, R: Rng>(
+    parameters: &Parameters<C>,
+    rng: &mut R,
+) -> (C::ScalarField, C::Affine) {
+    let nonce = C::ScalarField::rand(rng);
+    (nonce, parameters.generator.mul(nonce).into_affine())
+}
+
+/// Compute the challenge of a joint signature, given the public keys of all
+/// signers and the commitments to their nonces.
+fn joint_challenge<C: ProjectiveCurve>(
+    parameters: &Parameters<C>,
+    keys: &[PublicKey<C>],
+    commitments: &[C::Affine],
+    message: &[u8],
+) -> Result<[u8; 32], Error>
+where
+    C::ScalarField: PrimeField,
+{
+    let aggregated_key = aggregate_public_keys::<C>(keys)?;
+    let prover_commitment = commitments
+        .iter()
+        .fold(C::zero(), |sum, commitment| {
+            sum + commitment.into_projective()
+        })
+        .into_affine();
+    verifier_challenge::<C>(parameters, &aggregated_key, &prover_commitment, message)
+}
+
+/// Compute the share `s_i = k_i - e·a_i·x_i` of the joint signature over
+/// `message` of the signer with secret key `sk` and nonce `nonce`. `commitments`
+/// contains the nonce commitment of each signer in `keys`, in the same order.
+pub fn sign_share<C: ProjectiveCurve>(
+    parameters: &Parameters<C>,
+    keys: &[PublicKey<C>],
+    commitments: &[C::Affine],
+    sk: &SecretKey<C>,
+    nonce: C::ScalarField,
+    message: &[u8],
+) -> Result<C::ScalarField, Error>
+where
+    C::ScalarField: PrimeField,
+{
+    let index = keys
+        .iter()
+        .position(|key| *key == sk.public_key)
+        .ok_or("the secret key doesn't belong to any of the signers")?;
+    let coefficient = key_coefficients::<C>(keys)?[index];
+    let challenge = joint_challenge::<C>(parameters, keys, commitments, message)?;
+    let challenge = C::ScalarField::from_le_bytes_mod_order(&challenge);
+    Ok(nonce - challenge * coefficient * sk.secret_key)
+}
+
+/// Combine the shares of all signers into a joint signature over `message`,
+/// which verifies under `aggregate_public_keys(keys)`.
+pub fn combine_shares<C: ProjectiveCurve>(
+    parameters: &Parameters<C>,
+    keys: &[PublicKey<C>],
+    commitments: &[C::Affine],
+    shares: &[C::ScalarField],
+    message: &[u8],
+) -> Result<Signature<C>, Error>
+where
+    C::ScalarField: PrimeField,
+{
+    Ok(Signature {
+        prover_response: shares.iter().fold(C::ScalarField::zero(), |sum, s| sum + s),
+        verifier_challenge: joint_challenge::<C>(parameters, keys, commitments, message)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+    use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
+
+    /// Jointly sign `message` with the secret keys `sks`.
+    fn sign_jointly(
+        parameters: &Parameters<JubJub>,
+        sks: &[SecretKey<JubJub>],
+        message: &[u8],
+    ) -> Signature<JubJub> {
+        let rng = &mut ark_std::test_rng();
+        let keys = sks.iter().map(|sk| sk.public_key).collect::<Vec<_>>();
+        let (nonces, commitments): (Vec<_>, Vec<_>) =
+            sks.iter().map(|_| commit_nonce(parameters, rng)).unzip();
+        let shares = sks
+            .iter()
+            .zip(nonces)
+            .map(|(sk, nonce)| {
+                sign_share(parameters, &keys, &commitments, sk, nonce, message).unwrap()
+            })
+            .collect::<Vec<_>>();
+        combine_shares(parameters, &keys, &commitments, &shares, message).unwrap()
+    }
+
+    #[test]
+    fn joint_signature_test() {
+        let rng = &mut ark_std::test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let (alice_pk, alice_sk) = Schnorr::keygen(&parameters, rng).unwrap();
+        let (bob_pk, bob_sk) = Schnorr::keygen(&parameters, rng).unwrap();
+        let message = b"Hi, we are a joint Schnorr signature!";
+
+        let aggregated_key = aggregate_public_keys::<JubJub>(&[alice_pk, bob_pk]).unwrap();
+        let signature = sign_jointly(&parameters, &[alice_sk.clone(), bob_sk.clone()], message);
+        assert!(Schnorr::verify(&parameters, &aggregated_key, message, &signature).unwrap());
+        assert!(
+            !Schnorr::verify(&parameters, &aggregated_key, b"Bad message", &signature).unwrap()
+        );
+
+        // The signature doesn't verify under any other aggregation of the keys, ...
+        let sum = (alice_pk.into_projective() + bob_pk.into_projective()).into_affine();
+        let swapped = aggregate_public_keys::<JubJub>(&[bob_pk, alice_pk]).unwrap();
+        for key in [sum, swapped, alice_pk, bob_pk] {
+            assert!(!Schnorr::verify(&parameters, &key, message, &signature).unwrap());
+        }
+        // ... and a single signer can't sign on behalf of both.
+        let signature = sign_jointly(&parameters, &[alice_sk.clone(), alice_sk], message);
+        assert!(!Schnorr::verify(&parameters, &aggregated_key, message, &signature).unwrap());
+    }
+}
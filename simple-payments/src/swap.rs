@@ -0,0 +1,260 @@
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+};
+use crate::ledger::{self, Amount, AssetId, Balances};
+use ark_std::rand::Rng;
+
+/// Prefix of the message signed to authorize a swap. This separates swap
+/// messages from transaction messages, so that a signature on a swap can't be
+/// reused as a signature on a transaction, and vice versa.
+pub const SWAP_TAG: &[u8] = b"swap";
+
+/// An atomic swap, in which `party_a` sends `amount_a` of the asset `asset_a` to
+/// `party_b`, and `party_b` sends `amount_b` of the asset `asset_b` to `party_a`.
+/// Either both legs of the swap are applied, or neither is.
+#[derive(Clone, Debug)]
+pub struct Swap {
+    /// The account sending `amount_a`, and receiving `amount_b`.
+    pub party_a: AccountId,
+    /// The account sending `amount_b`, and receiving `amount_a`.
+    pub party_b: AccountId,
+    /// The asset being transferred from `party_a` to `party_b`.
+    pub asset_a: AssetId,
+    /// The amount being transferred from `party_a` to `party_b`.
+    pub amount_a: Amount,
+    /// The asset being transferred from `party_b` to `party_a`.
+    pub asset_b: AssetId,
+    /// The amount being transferred from `party_b` to `party_a`.
+    pub amount_b: Amount,
+    /// The signature of `party_a` over the whole swap.
+    pub signature_a: AccountSignature,
+    /// The signature of `party_b` over the whole swap.
+    pub signature_b: AccountSignature,
+}
+
+/// Construct the message that is signed by both parties to authorize a swap.
+fn message(
+    parameters: &ledger::Parameters,
+    party_a: AccountId,
+    party_b: AccountId,
+    asset_a: AssetId,
+    amount_a: Amount,
+    asset_b: AssetId,
+    amount_b: Amount,
+) -> Vec<u8> {
+    // The authorized message consists of
+    // (Tag || PartyA || PartyB || AssetA || AssetB || AmountA || AmountB)
+    let mut message = SWAP_TAG.to_vec();
+    message.extend(party_a.to_bytes_le());
+    message.extend(party_b.to_bytes_le());
+    message.extend(asset_a.to_bytes_le());
+    message.extend(asset_b.to_bytes_le());
+    message.extend(amount_a.to_bytes_le());
+    message.extend(amount_b.to_bytes_le());
+    parameters.message_encoding.encode(message)
+}
+
+impl Swap {
+    /// Verify just the signature of one of the parties.
+    fn verify_signature(
+        &self,
+        pp: &ledger::Parameters,
+        pub_key: &AccountPublicKey,
+        signature: &AccountSignature,
+    ) -> bool {
+        let message = message(
+            pp,
+            self.party_a,
+            self.party_b,
+            self.asset_a,
+            self.amount_a,
+            self.asset_b,
+            self.amount_b,
+        );
+        signature.verify(pp, pub_key, &message)
+    }
+
+    /// Compute the balances of both parties after the swap, given their account
+    /// information `info_a` and `info_b` before it. Returns `None` if either leg
+    /// is underfunded or would spend held funds, if either balance overflows, or
+    /// if either asset is not supported.
+    pub(crate) fn new_balances(
+        &self,
+        info_a: &AccountInformation,
+        info_b: &AccountInformation,
+    ) -> Option<(Balances, Balances)> {
+        let mut balances_a = info_a.balances;
+        let mut balances_b = info_b.balances;
+        let debit = |balances: &mut Balances, asset, amount| {
+            balances.set(asset, balances.get(asset)?.checked_sub(amount)?)
+        };
+        let credit = |balances: &mut Balances, asset, amount| {
+            balances.set(asset, balances.get(asset)?.checked_add(amount)?)
+        };
+        // Both parties pay their leg before receiving the other one, so that
+        // neither leg can be funded by the proceeds of the swap.
+        debit(&mut balances_a, self.asset_a, self.amount_a)?;
+        debit(&mut balances_b, self.asset_b, self.amount_b)?;
+        let covers_held = |balances: &Balances, info: &AccountInformation| {
+            balances
+                .get(AssetId::NATIVE)
+                .is_some_and(|b| b >= info.held)
+        };
+        (covers_held(&balances_a, info_a) && covers_held(&balances_b, info_b)).then_some(())?;
+        credit(&mut balances_a, self.asset_b, self.amount_b)?;
+        credit(&mut balances_b, self.asset_a, self.amount_a)?;
+        Some((balances_a, balances_b))
+    }
+
+    /// Check that the swap is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the two parties are distinct, existing accounts.
+    /// 2. Verify that both signatures are valid with respect to the public keys
+    ///    of the respective parties.
+    /// 3. Verify that both assets are supported, that both parties have
+    ///    sufficient balance, excluding held funds, to finance their leg of the
+    ///    swap, and that neither balance overflows.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        match (
+            state.id_to_account_info.get(&self.party_a),
+            state.id_to_account_info.get(&self.party_b),
+        ) {
+            (Some(info_a), Some(info_b)) if self.party_a != self.party_b => {
+                let mut result = true;
+                // Verify the signatures of both parties.
+                result &= self.verify_signature(parameters, &info_a.public_key, &self.signature_a);
+                result &= self.verify_signature(parameters, &info_b.public_key, &self.signature_b);
+                // Verify that both legs can be applied.
+                result &= self.new_balances(info_a, info_b).is_some();
+                result
+            }
+            _ => false,
+        }
+    }
+
+    /// Create a (possibly invalid) swap, signed by both parties.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create<R: Rng>(
+        parameters: &ledger::Parameters,
+        party_a: AccountId,
+        party_b: AccountId,
+        asset_a: AssetId,
+        amount_a: Amount,
+        asset_b: AssetId,
+        amount_b: Amount,
+        party_a_sk: &AccountSecretKey,
+        party_b_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = message(
+            parameters, party_a, party_b, asset_a, amount_a, asset_b, amount_b,
+        );
+        let signature_a = AccountSignature::sign(parameters, party_a_sk, &message, rng);
+        let signature_b = AccountSignature::sign(parameters, party_b_sk, &message, rng);
+        Self {
+            party_a,
+            party_b,
+            asset_a,
+            amount_a,
+            asset_b,
+            amount_b,
+            signature_a,
+            signature_b,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+
+    #[test]
+    fn swap_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(alice_id, AssetId(1), Amount(10))
+            .unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_asset_balance(bob_id, AssetId(2), Amount(3))
+            .unwrap();
+
+        // Alice sends 10 units of asset 1 to Bob in exchange for 3 units of asset 2.
+        let swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(2),
+            Amount(3),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(swap.validate(&pp, &state));
+        let mut temp_state = state.clone();
+        temp_state.apply_swap(&pp, &swap).unwrap();
+        let alice_balances = temp_state.id_to_account_info[&alice_id].balances;
+        let bob_balances = temp_state.id_to_account_info[&bob_id].balances;
+        assert_eq!(alice_balances.get(AssetId(1)), Some(Amount(0)));
+        assert_eq!(alice_balances.get(AssetId(2)), Some(Amount(3)));
+        assert_eq!(bob_balances.get(AssetId(1)), Some(Amount(10)));
+        assert_eq!(bob_balances.get(AssetId(2)), Some(Amount(0)));
+        assert_eq!(temp_state.id_to_account_info[&alice_id].nonce, 1);
+        assert_eq!(temp_state.id_to_account_info[&bob_id].nonce, 1);
+
+        // If Bob's leg is underfunded, the whole swap is rejected.
+        let bad_swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(2),
+            Amount(4),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!bad_swap.validate(&pp, &state));
+        let mut temp_state = state.clone();
+        assert!(temp_state.apply_swap(&pp, &bad_swap).is_none());
+        assert_eq!(temp_state.root(), state.root());
+
+        // Bob can't pay with an asset he doesn't hold.
+        let bad_swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(1),
+            Amount(3),
+            &alice_sk,
+            &bob_sk,
+            &mut rng,
+        );
+        assert!(!bad_swap.validate(&pp, &state));
+
+        // Both parties must sign the swap.
+        let bad_swap = Swap::create(
+            &pp,
+            alice_id,
+            bob_id,
+            AssetId(1),
+            Amount(10),
+            AssetId(2),
+            Amount(3),
+            &alice_sk,
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_swap.validate(&pp, &state));
+    }
+}
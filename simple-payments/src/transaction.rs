@@ -1,11 +1,19 @@
-use crate::account::{AccountId, AccountPublicKey, AccountSecretKey};
-use crate::ledger::{self, Amount};
-use crate::signature::{
-    schnorr::{self, Schnorr},
-    SignatureScheme,
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
 };
-use ark_ed_on_bls12_381::EdwardsProjective;
+use crate::allowlist::AllowlistProof;
+use crate::delegation::SpendingAuthorization;
+use crate::ledger::{
+    self, AccRoot, AccountHash, AccountProof, Amount, AssetId, BlockCommitment, MessageEncoding,
+    ParamsCommitment,
+};
+use crate::random_oracle::sha256;
+use crate::signature::schnorr;
+use crate::withdrawal::{L1Address, WITHDRAWAL_ACCOUNT};
+use ark_crypto_primitives::crh::CRH;
+use ark_ff::{BigInteger256, PrimeField};
 use ark_std::rand::Rng;
+use std::fmt;
 
 /// Transaction transferring some amount from one account to another.
 #[derive(Clone, Debug)]
@@ -14,35 +22,449 @@ pub struct Transaction {
     pub sender: AccountId,
     /// The account information of the recipient.
     pub recipient: AccountId,
+    /// The asset being transferred. The fee is paid in the same asset.
+    pub asset_id: AssetId,
     /// The amount being transferred from the sender to the receiver.
     pub amount: Amount,
+    /// The fee paid by the sender on top of `amount` to have the transaction
+    /// included in a batch. Fees are burned.
+    pub fee: Amount,
+    /// The block that the transaction is bound to, or zero if it is not bound to
+    /// any block. A bound transaction expires once the ledger moves past that block.
+    pub expiry_block_commitment: BlockCommitment,
+    /// The commitment to the ledger parameters that the transaction is signed
+    /// under. The transaction is only valid under these parameters.
+    pub params_commitment: ParamsCommitment,
+    /// The address on L1 that the funds of a withdrawal are paid out to. This
+    /// is zero for other transactions.
+    pub l1_address: L1Address,
+    /// The number of decimals of the fixed-point scale that `amount` and `fee`
+    /// are expressed in, i.e., they count units of `10^-scale` of the asset.
+    /// The ledger stores amounts as they are, and so a batch may require that
+    /// all of its transactions use the same scale.
+    pub scale: u8,
     /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: schnorr::Signature<EdwardsProjective>,
+    /// the asset, the amount, the fee, the expiry block commitment, the
+    /// parameters commitment, the L1 address, and the scale, as well as over the
+    /// public key of the recipient if the ledger binds recipient keys, and over
+    /// the leaf indices of both accounts if the ledger binds leaf indices.
+    pub signature: AccountSignature,
+    /// The sequencer's receipt for the balance change of the sender, if the
+    /// ledger requires receipts. Unlike the other fields, this is not signed by the
+    /// sender. See `receipt`.
+    pub receipt: Option<AccountSignature>,
+    /// The recipient's consent to receive the transfer, if the ledger requires
+    /// consent and the recipient doesn't accept transfers. Like the receipt,
+    /// this is not signed by the sender. See `consent`.
+    pub consent: Option<AccountSignature>,
+    /// The proof that the recipient is on the allowlist of the sender, if the
+    /// ledger enables allowlists and the sender has one. Like the receipt, this
+    /// is not signed by the sender. See `allowlist`.
+    pub allowlist_proof: Option<AllowlistProof>,
+    /// The authorization of the delegate that signed the transaction instead of
+    /// the sender, if the ledger enables delegation. Like the receipt, this is
+    /// not signed by the delegate. See `delegation`.
+    pub authorization: Option<SpendingAuthorization>,
+    /// The nonce of the proof of work that the transaction carries, if the batch
+    /// requires proofs of work. Like the receipt, this is not signed by the
+    /// sender. See `pow`.
+    pub pow_nonce: u64,
+}
+
+/// The tag that separates the hashes of transactions from other uses of
+/// `AccountHash`.
+pub const TX_HASH_TAG: &[u8] = b"tx-hash";
+
+/// The hash of a transaction, see `Transaction::hash`.
+pub type TransactionHash = ark_ed_on_bls12_381::Fq;
+
+/// The tag that separates the identifiers of transactions from other uses of
+/// `AccountHash`.
+pub const TX_ID_TAG: &[u8] = b"tx-id";
+
+/// The identifier of a transaction, see `Transaction::id`.
+pub type TransactionId = ark_ed_on_bls12_381::Fq;
+
+/// The cost of verifying a signature in the cost model of
+/// `Transaction::compute_cost`, in thousands of constraints.
+pub const SIGNATURE_COST: u64 = 95;
+
+/// The cost of updating an account in the cost model of
+/// `Transaction::compute_cost`, in thousands of constraints. Each update checks
+/// the authentication path of the account both before and after it.
+pub const ACCOUNT_UPDATE_COST: u64 = 45;
+
+/// The prefix of structured digests, as in EIP-712.
+pub const STRUCTURED_PREFIX: &[u8] = &[0x19, 0x01];
+
+/// The type of the domain of structured digests, whose hash separates them from
+/// those of other domains. A ledger is identified by its parameters.
+pub const DOMAIN_TYPE: &[u8] = b"Domain(bytes32 paramsCommitment)";
+
+/// The type of transactions in structured digests.
+pub const TRANSACTION_TYPE: &[u8] = b"Transaction(uint8 sender,uint8 recipient,uint32 assetId,\
+uint64 amount,uint64 fee,bytes32 expiryBlockCommitment,address l1Address,uint8 scale)";
+
+/// The type of transactions in structured digests, if the ledger binds recipient
+/// keys. The key is encoded as dynamic bytes, i.e., as the hash of its bytes.
+pub const BOUND_TRANSACTION_TYPE: &[u8] = b"Transaction(uint8 sender,uint8 recipient,\
+uint32 assetId,uint64 amount,uint64 fee,bytes32 expiryBlockCommitment,address l1Address,\
+uint8 scale,bytes recipientKey)";
+
+/// The fields that are appended to the type of transactions in structured
+/// digests, if the ledger binds leaf indices.
+pub const LEAF_INDICES_TYPE_FIELDS: &[u8] = b",uint64 senderLeafIndex,uint64 recipientLeafIndex";
+
+/// The type of transactions in structured digests, which binds the recipient's
+/// key if `bound_key` is set, and the leaf indices of both accounts if
+/// `leaf_indices` is set.
+pub fn transaction_type(bound_key: bool, leaf_indices: bool) -> Vec<u8> {
+    let base = if bound_key {
+        BOUND_TRANSACTION_TYPE
+    } else {
+        TRANSACTION_TYPE
+    };
+    // The fields are listed between the parentheses that close the type.
+    let mut transaction_type = base[..base.len() - 1].to_vec();
+    if leaf_indices {
+        transaction_type.extend(LEAF_INDICES_TYPE_FIELDS);
+    }
+    transaction_type.push(b')');
+    transaction_type
+}
+
+/// Encode the unsigned integer whose little-endian bytes are `bytes_le` as a
+/// 32-byte word of a structured digest, i.e., in big-endian and left-padded.
+fn uint_word(bytes_le: &[u8]) -> Vec<u8> {
+    let mut word = vec![0u8; 32 - bytes_le.len()];
+    word.extend(bytes_le.iter().rev());
+    word
 }
 
 impl Transaction {
-    /// Verify just the signature in the transaction.
+    /// Verify just the signature in the transaction, given the public key of the
+    /// recipient `recipient_key` and the root `pre_root` of the account tree
+    /// that the transaction is applied to, see `message_at`.
     fn verify_signature(
         &self,
-        pp: &schnorr::Parameters<EdwardsProjective>,
+        pp: &ledger::Parameters,
         pub_key: &AccountPublicKey,
+        recipient_key: &AccountPublicKey,
+        pre_root: &AccRoot,
     ) -> bool {
+        self.signature
+            .verify(pp, pub_key, &self.message_at(pp, recipient_key, pre_root))
+    }
+
+    /// The public key that the signature binds for the recipient with
+    /// information `recipient_info`. Withdrawals credit nobody, and so they bind
+    /// the default key, as do transfers to accounts that don't exist.
+    pub fn recipient_key(&self, recipient_info: Option<&AccountInformation>) -> AccountPublicKey {
+        match recipient_info {
+            Some(info) if !self.is_withdrawal() => info.public_key,
+            _ => AccountPublicKey::default(),
+        }
+    }
+
+    /// The leaf indices of the sender and of the recipient in the account tree.
+    /// Account identifiers map directly to leaf indices, see `ledger::leaf_index`,
+    /// but signing the indices makes explicit the positions at which the
+    /// memberships of the accounts must be proven.
+    pub fn leaf_indices(&self) -> [u64; 2] {
+        [self.sender.0 as u64, self.recipient.0 as u64]
+    }
+
+    /// The message that the sender signs to authorize the transaction to the
+    /// recipient with public key `recipient_key`. This is `signed_message`,
+    /// unless the ledger binds recipient keys, in which case the key is signed
+    /// along with the other fields, so that the transaction is only valid while
+    /// the recipient's identifier points to that key.
+    pub fn signed_message_to(
+        &self,
+        pp: &ledger::Parameters,
+        recipient_key: &AccountPublicKey,
+    ) -> Vec<u8> {
+        self.signed_message_with(pp, pp.bind_recipient_key.then_some(recipient_key))
+    }
+
+    /// The message that the sender signs to authorize the transaction to the
+    /// recipient with public key `recipient_key`, when applied to the account
+    /// tree with root `pre_root`. This is `signed_message_to`, unless the ledger
+    /// binds pre-roots, in which case the root follows the encoded message, so
+    /// that the transaction is only valid on that state of the ledger.
+    pub fn message_at(
+        &self,
+        pp: &ledger::Parameters,
+        recipient_key: &AccountPublicKey,
+        pre_root: &AccRoot,
+    ) -> Vec<u8> {
+        let mut message = self.signed_message_to(pp, recipient_key);
+        if pp.bind_pre_root {
+            message.extend(ark_ff::to_bytes![pre_root].unwrap());
+        }
+        message
+    }
+
+    /// The message that the sender signs to authorize the transaction, e.g., to
+    /// sign it jointly on behalf of an account controlled by several keys. If
+    /// the ledger binds leaf indices, they are signed after the other fields.
+    pub fn signed_message(&self, pp: &ledger::Parameters) -> Vec<u8> {
+        self.signed_message_with(pp, None)
+    }
+
+    /// The message that the sender signs, which also binds the public key of the
+    /// recipient if `recipient_key` is given.
+    fn signed_message_with(
+        &self,
+        pp: &ledger::Parameters,
+        recipient_key: Option<&AccountPublicKey>,
+    ) -> Vec<u8> {
+        match pp.message_encoding {
+            MessageEncoding::Structured => {
+                self.structured_message_with(recipient_key, pp.bind_leaf_indices)
+            }
+            encoding => {
+                let mut message = self.raw_message();
+                if let Some(key) = recipient_key {
+                    message.extend(ark_ff::to_bytes![key].unwrap());
+                }
+                if pp.bind_leaf_indices {
+                    message.extend(self.leaf_indices().iter().flat_map(|i| i.to_le_bytes()));
+                }
+                encoding.encode(message)
+            }
+        }
+    }
+
+    /// The structured digest of the transaction, which resembles EIP-712 with
+    /// SHA-256 in place of Keccak-256, so that wallets can display the fields
+    /// that they sign. It binds the same fields as the raw message.
+    pub fn structured_message(&self) -> Vec<u8> {
+        self.structured_message_with(None, false)
+    }
+
+    /// The structured digest of the transaction, which also binds the public key
+    /// of the recipient if `recipient_key` is given, and the leaf indices of
+    /// both accounts if `leaf_indices` is set, see `transaction_type`.
+    fn structured_message_with(
+        &self,
+        recipient_key: Option<&AccountPublicKey>,
+        leaf_indices: bool,
+    ) -> Vec<u8> {
+        // The digest is
+        // SHA256(Prefix || DomainSeparator || StructHash), where
+        // DomainSeparator = SHA256(SHA256(DomainType) || ParamsCommitment) and
+        // StructHash = SHA256(SHA256(TransactionType) || EncodedFields), and where
+        // each field is encoded as a 32-byte word.
+        let mut domain = sha256::digest(DOMAIN_TYPE).to_vec();
+        domain.extend(ark_ff::to_bytes![self.params_commitment].unwrap());
+        let transaction_type = transaction_type(recipient_key.is_some(), leaf_indices);
+        let mut fields = sha256::digest(&transaction_type).to_vec();
+        fields.extend(uint_word(&self.sender.to_bytes_le()));
+        fields.extend(uint_word(&self.recipient.to_bytes_le()));
+        fields.extend(uint_word(&self.asset_id.to_bytes_le()));
+        fields.extend(uint_word(&self.amount.to_bytes_le()));
+        fields.extend(uint_word(&self.fee.to_bytes_le()));
+        fields.extend(ark_ff::to_bytes![self.expiry_block_commitment].unwrap());
+        // Addresses are left-padded, but not reversed.
+        fields.extend([0u8; 12]);
+        fields.extend(self.l1_address);
+        fields.extend(uint_word(&[self.scale]));
+        if let Some(key) = recipient_key {
+            fields.extend(sha256::digest(&ark_ff::to_bytes![key].unwrap()));
+        }
+        if leaf_indices {
+            for index in self.leaf_indices() {
+                fields.extend(uint_word(&index.to_le_bytes()));
+            }
+        }
+        let mut message = STRUCTURED_PREFIX.to_vec();
+        message.extend(sha256::digest(&domain));
+        message.extend(sha256::digest(&fields));
+        sha256::digest(&message).to_vec()
+    }
+
+    /// The message that is signed to authorize the transaction, before it is
+    /// encoded as specified by the ledger parameters.
+    pub(crate) fn raw_message(&self) -> Vec<u8> {
         // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
+        // (SenderAccId || RecipientAccId || AssetId || Amount || Fee || ExpiryBlockCommitment
+        //  || ParamsCommitment || L1Address || Scale)
         let mut message = self.sender.to_bytes_le();
         message.extend(self.recipient.to_bytes_le());
+        message.extend(self.asset_id.to_bytes_le());
         message.extend(self.amount.to_bytes_le());
-        Schnorr::verify(pp, pub_key, &message, &self.signature).unwrap()
+        message.extend(self.fee.to_bytes_le());
+        message.extend(ark_ff::to_bytes![self.expiry_block_commitment].unwrap());
+        message.extend(ark_ff::to_bytes![self.params_commitment].unwrap());
+        message.extend(self.l1_address);
+        message.push(self.scale);
+        message
+    }
+
+    /// The hash of the transaction, which identifies it within a batch. Like the
+    /// signature, it covers all the fields of the transaction but the signature
+    /// and the receipt, so that the hash can't be changed without the sender's
+    /// authorization.
+    pub fn hash(&self, pp: &ledger::Parameters) -> TransactionHash {
+        // The hashed input consists of
+        // (Tag || SignedMessage), where the message is not encoded.
+        let mut input = TX_HASH_TAG.to_vec();
+        input.extend(self.raw_message());
+        AccountHash::evaluate(&pp.account_crh_params, &input).unwrap()
+    }
+
+    /// The identifier of the transaction, which tells apart transactions in the
+    /// mempool. Unlike the hash, it also covers the signature, and so the same
+    /// transfer signed twice has two identifiers. The receipt and the consent are
+    /// attached by other parties later on, and so they aren't covered.
+    pub fn id(&self, pp: &ledger::Parameters) -> TransactionId {
+        // The hashed input consists of
+        // (Tag || TransactionHash || Signature), so that it fits in the input of
+        // the hash, whatever the signature encoding.
+        let mut input = TX_ID_TAG.to_vec();
+        input.extend(ark_ff::to_bytes![self.hash(pp)].unwrap());
+        input.extend(self.signature.to_bytes_le());
+        AccountHash::evaluate(&pp.account_crh_params, &input).unwrap()
+    }
+
+    /// The canonical transaction that does nothing, which is used to pad batches.
+    /// It is sent from and to `WITHDRAWAL_ACCOUNT`, which is never registered,
+    /// transfers nothing, and carries the all-zero placeholder signature. Such a
+    /// transaction is never valid, and so it can only be included in a batch as
+    /// padding, where it leaves the root unchanged.
+    pub const NOOP: Self = Self {
+        sender: WITHDRAWAL_ACCOUNT,
+        recipient: WITHDRAWAL_ACCOUNT,
+        asset_id: AssetId::NATIVE,
+        amount: Amount(0),
+        fee: Amount(0),
+        // Zero is all zeros in Montgomery form, too.
+        expiry_block_commitment: BlockCommitment::new(BigInteger256::new([0; 4])),
+        params_commitment: ParamsCommitment::new(BigInteger256::new([0; 4])),
+        l1_address: [0; 20],
+        scale: 0,
+        signature: AccountSignature::Challenge(schnorr::Signature {
+            prover_response: ark_ed_on_bls12_381::Fr::new(BigInteger256::new([0; 4])),
+            verifier_challenge: [0; 32],
+        }),
+        receipt: None,
+        consent: None,
+        allowlist_proof: None,
+        authorization: None,
+        pow_nonce: 0,
+    };
+
+    /// Is this the canonical no-op transaction, `Transaction::NOOP`? The
+    /// signature is ignored.
+    pub fn is_noop(&self) -> bool {
+        let noop = Self::NOOP;
+        self.sender == noop.sender
+            && self.recipient == noop.recipient
+            && self.asset_id == noop.asset_id
+            && self.amount == noop.amount
+            && self.fee == noop.fee
+            && self.expiry_block_commitment == noop.expiry_block_commitment
+            && self.params_commitment == noop.params_commitment
+            && self.l1_address == noop.l1_address
+            && self.scale == noop.scale
+    }
+
+    /// Is this transaction withdrawing funds from the ledger?
+    pub fn is_withdrawal(&self) -> bool {
+        self.recipient == WITHDRAWAL_ACCOUNT
+    }
+
+    /// Is this a control operation, i.e., a transaction that doesn't move funds?
+    /// Control operations are sent from and to `WITHDRAWAL_ACCOUNT`, like
+    /// `Transaction::NOOP`.
+    pub fn is_control_op(&self) -> bool {
+        self.sender == WITHDRAWAL_ACCOUNT && self.recipient == WITHDRAWAL_ACCOUNT
+    }
+
+    /// Does this transaction touch an account that `other` touches too, i.e., do
+    /// their senders and recipients overlap? `WITHDRAWAL_ACCOUNT` holds no funds,
+    /// and so it is never touched: withdrawals only touch their sender, and
+    /// control operations touch nothing.
+    pub fn shares_account_with(&self, other: &Transaction) -> bool {
+        let touched = |tx: &Transaction| {
+            [tx.sender, tx.recipient]
+                .iter()
+                .copied()
+                .filter(|&id| id != WITHDRAWAL_ACCOUNT)
+                .collect::<Vec<_>>()
+        };
+        let other_touched = touched(other);
+        touched(self).iter().any(|id| other_touched.contains(id))
+    }
+
+    /// Does the amount follow the rules of the kind of this transaction? Transfers
+    /// (including withdrawals) must move a nonzero amount, while control operations
+    /// must not move anything.
+    pub fn follows_amount_rules(&self) -> bool {
+        (self.amount == Amount(0)) == self.is_control_op()
+    }
+
+    /// Does the transaction leave no dust behind, for the dust threshold
+    /// `threshold`? A transfer must move at least `threshold`, and must leave the
+    /// balance of the sender, whose information before the transaction is
+    /// `sender_info`, either empty or at least `threshold`. Control operations
+    /// move nothing, and so they are exempt.
+    pub fn leaves_no_dust(&self, threshold: Amount, sender_info: &AccountInformation) -> bool {
+        if self.is_control_op() {
+            return true;
+        }
+        // The amount of a self-transfer returns to the sender.
+        let spent = if self.sender == self.recipient {
+            Some(self.fee)
+        } else {
+            self.total_cost()
+        };
+        let remaining =
+            spent.and_then(|spent| sender_info.balances.get(self.asset_id)?.checked_sub(spent));
+        self.amount >= threshold
+            && matches!(remaining, Some(remaining) if remaining == Amount(0) || remaining >= threshold)
+    }
+
+    /// Has this transaction not expired yet when the current block has the
+    /// commitment `block_commitment`? Transactions that are not bound to any
+    /// block never expire.
+    pub fn is_unexpired_at(&self, block_commitment: &BlockCommitment) -> bool {
+        self.expiry_block_commitment == BlockCommitment::default()
+            || self.expiry_block_commitment == *block_commitment
     }
 
     /// Check that the transaction is valid for the given ledger state. This checks
     /// the following conditions:
     /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
-    /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
-    /// 3. Verify that the recipient's account exists.
+    ///    corresponding to `self.sender`, or to the next key derived from it if
+    ///    the ledger derives a fresh key per transaction. If the ledger binds
+    ///    pre-roots, the signature must also cover the current root.
+    /// 2. Verify that the asset is supported, and that the sender's account has
+    ///    sufficient balance of it, excluding held funds, to finance the
+    ///    transaction and its fee.
+    /// 3. Verify that the recipient's account exists, unless the transaction
+    ///    is a withdrawal.
+    /// 4. Verify that the sender stays within the daily limit, if there is one.
+    /// 5. Verify that the amount follows the rules of the kind of transaction.
+    /// 6. Verify that the transaction has not expired at the current block.
+    /// 7. Verify that the transaction is signed under `parameters`.
+    /// 8. Verify that the sequencer signed a receipt for the balance change of
+    ///    the sender, if the ledger requires receipts.
+    /// 9. Verify that the grace period since the sender was last credited has
+    ///    elapsed, if the transaction is a withdrawal and the ledger delays them.
+    /// 10. Verify that the recipient accepts transfers or consented to this one,
+    ///     if the ledger requires consent.
+    /// 11. Verify that the recipient is on the allowlist of the sender, if the
+    ///     ledger enables allowlists and the sender has one.
+    /// 12. Verify that the fee covers the base fee of the ledger, if it has a fee
+    ///     market.
+    /// 13. Verify that a delegated transaction is authorized by the sender, and
+    ///     that it stays within the cap of its authorization, if the ledger
+    ///     enables delegation. The signature of condition 1 is then checked
+    ///     against the key of the delegate instead.
+    /// 14. Verify that the recipient's balance stays within its cap after the
+    ///     credit, if the ledger enables balance caps and the recipient has one.
     pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
         // Lookup public key corresponding to sender ID
         if let Some(sender_acc_info) = state.id_to_account_info.get(&self.sender) {
@@ -50,31 +472,294 @@ impl Transaction {
             // Check that the account_info exists in the Merkle tree.
             result &= {
                 let path = state
-                    .account_merkle_tree
+                    .account_merkle_tree()
                     .generate_proof(self.sender.0 as usize)
                     .expect("path should exist");
                 path.verify(
                     &parameters.leaf_crh_params,
                     &parameters.two_to_one_crh_params,
-                    &state.account_merkle_tree.root(),
-                    &sender_acc_info.to_bytes_le(),
+                    &state.account_merkle_tree().root(),
+                    &sender_acc_info.to_leaf(parameters),
                 )
                 .unwrap()
             };
             // Verify the signature against the sender pubkey.
-            result &= self.verify_signature(&parameters.sig_params, &sender_acc_info.public_key);
+            let recipient_info = state.id_to_account_info.get(&self.recipient);
+            result &= self.verify_signature(
+                parameters,
+                &self.signer_key(parameters, sender_acc_info),
+                &self.recipient_key(recipient_info),
+                &state.root(),
+            );
             // assert!(result, "signature verification failed");
-            // Verify the amount is available in the sender account.
-            result &= self.amount <= sender_acc_info.balance;
+            // Verify the amount and the fee are available in the sender account.
+            result &= self.is_funded_by(sender_acc_info);
+            result &= self.is_within_daily_limit(parameters, sender_acc_info, state.epoch);
             // Verify that recipient account exists.
-            result &= state.id_to_account_info.get(&self.recipient).is_some();
+            result &=
+                self.is_withdrawal() || state.id_to_account_info.contains_key(&self.recipient);
+            result &= self.follows_amount_rules();
+            result &= self.is_unexpired_at(&state.block_commitment);
+            result &= self.params_commitment == parameters.commitment();
+            result &= self.has_valid_receipt(parameters, sender_acc_info);
+            result &= self.is_past_withdrawal_grace(parameters, sender_acc_info, state.epoch);
+            result &=
+                self.has_valid_consent(parameters, state.id_to_account_info.get(&self.recipient));
+            result &= self.is_allowed_by(parameters, sender_acc_info);
+            result &= self.pays_base_fee(parameters, state.base_fee);
+            result &= self.is_within_authorization(parameters, sender_acc_info);
+            result &= self.is_within_balance_cap(parameters, recipient_info);
             result
         } else {
             false
         }
     }
 
-    /// Create a (possibly invalid) transaction.
+    /// Check that the transaction is valid with respect to the account tree with
+    /// root `root`, given only the proofs of the sender's and recipient's account
+    /// information. This checks the same conditions as `validate`, but doesn't
+    /// require the full ledger state, which makes it suitable for light clients.
+    /// The base fee is not checked either, see `Transaction::split_fee`.
+    ///
+    /// `recipient_proof` may be `None` if the transaction is a withdrawal, and
+    /// `epoch` and `block_commitment` are the current epoch and block of the ledger.
+    pub fn validate_with_proofs(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_proof: &AccountProof,
+        recipient_proof: Option<&AccountProof>,
+        root: &AccRoot,
+        epoch: u64,
+        block_commitment: &BlockCommitment,
+    ) -> Result<bool, ark_crypto_primitives::Error> {
+        // Check that the sender's account information is in the tree.
+        let mut result = sender_proof.verify(parameters, self.sender, root)?;
+        // Verify the signature against the sender pubkey.
+        result &= self.verify_signature(
+            parameters,
+            &self.signer_key(parameters, &sender_proof.info),
+            &self.recipient_key(recipient_proof.map(|proof| &proof.info)),
+            root,
+        );
+        // Verify the amount and the fee are available in the sender account.
+        result &= self.is_funded_by(&sender_proof.info);
+        result &= self.is_within_daily_limit(parameters, &sender_proof.info, epoch);
+        // Verify that recipient account exists.
+        result &= match recipient_proof {
+            Some(proof) => proof.verify(parameters, self.recipient, root)?,
+            None => self.is_withdrawal(),
+        };
+        result &= self.follows_amount_rules();
+        result &= self.is_unexpired_at(block_commitment);
+        result &= self.params_commitment == parameters.commitment();
+        result &= self.has_valid_receipt(parameters, &sender_proof.info);
+        result &= self.is_past_withdrawal_grace(parameters, &sender_proof.info, epoch);
+        result &= self.is_allowed_by(parameters, &sender_proof.info);
+        result &= self.has_valid_consent(parameters, recipient_proof.map(|proof| &proof.info));
+        result &= self.is_within_authorization(parameters, &sender_proof.info);
+        result &= self.is_within_balance_cap(parameters, recipient_proof.map(|proof| &proof.info));
+        Ok(result)
+    }
+
+    /// The total amount debited from the sender, i.e., the amount plus the fee.
+    /// Returns `None` if this overflows.
+    pub fn total_cost(&self) -> Option<Amount> {
+        self.amount.checked_add(self.fee)
+    }
+
+    /// The compute cost of the transaction, which approximates the number of
+    /// constraints, in thousands, that proving it takes: `SIGNATURE_COST` for each
+    /// signature to verify, including the receipt, the consent, and the
+    /// authorization of the delegate if there are any, and `ACCOUNT_UPDATE_COST`
+    /// for each account to update. No-op transactions cost nothing, since they
+    /// pad batches.
+    pub fn compute_cost(&self) -> u64 {
+        if self.is_noop() {
+            return 0;
+        }
+        let num_signatures = 1
+            + self.receipt.is_some() as u64
+            + self.consent.is_some() as u64
+            + self.authorization.is_some() as u64;
+        let mut accounts = vec![self.sender, self.recipient];
+        accounts.retain(|id| *id != WITHDRAWAL_ACCOUNT);
+        accounts.dedup();
+        num_signatures * SIGNATURE_COST + accounts.len() as u64 * ACCOUNT_UPDATE_COST
+    }
+
+    /// Can a sender with information `sender_info` afford the amount and the
+    /// fee, without spending held funds?
+    fn is_funded_by(&self, sender_info: &AccountInformation) -> bool {
+        match (
+            self.total_cost(),
+            sender_info.available_balance(self.asset_id),
+        ) {
+            (Some(cost), Some(balance)) => cost <= balance,
+            _ => false,
+        }
+    }
+
+    /// Can a sender with information `sender_info` send this transaction during
+    /// the epoch `epoch`, without exceeding the daily limit?
+    fn is_within_daily_limit(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+        epoch: u64,
+    ) -> bool {
+        match parameters.daily_limit {
+            Some(limit) => sender_info
+                .spent_in(epoch)
+                .checked_add(self.amount)
+                .is_some_and(|spent| spent <= limit),
+            None => true,
+        }
+    }
+
+    /// Can a sender with information `sender_info` send this transaction during
+    /// the epoch `epoch`, without withdrawing funds that it was credited too
+    /// recently? Only withdrawals are delayed.
+    fn is_past_withdrawal_grace(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+        epoch: u64,
+    ) -> bool {
+        match parameters.withdrawal_grace {
+            Some(grace) if self.is_withdrawal() => sender_info.is_past_grace(epoch, grace),
+            _ => true,
+        }
+    }
+
+    /// Does the balance of a recipient with information `recipient_info` stay
+    /// within its cap after the credit? This always holds if the ledger doesn't
+    /// enable balance caps, if the recipient is uncapped, and for withdrawals and
+    /// self-transfers, which credit no account.
+    fn is_within_balance_cap(
+        &self,
+        parameters: &ledger::Parameters,
+        recipient_info: Option<&AccountInformation>,
+    ) -> bool {
+        if !parameters.balance_caps || self.is_withdrawal() || self.sender == self.recipient {
+            return true;
+        }
+        match recipient_info {
+            Some(info) if info.balance_cap == Amount(0) => true,
+            Some(info) => info
+                .balances
+                .get(self.asset_id)
+                .and_then(|balance| balance.checked_add(self.amount))
+                .is_some_and(|balance| balance <= info.balance_cap),
+            None => false,
+        }
+    }
+
+    /// Compute the account information of the sender and of the recipient after
+    /// the transaction, given their information `sender_info` and `recipient_info`
+    /// before it, during the epoch `epoch`. `recipient_info` is ignored, and no
+    /// recipient information is returned, if the transaction is a withdrawal.
+    /// Returns `None` if either balance underflows or overflows.
+    ///
+    /// Only the balances of the asset `self.asset_id` change, and the nonce of
+    /// the sender is incremented. A self-transfer only costs its sender the fee,
+    /// and doesn't count as a credit.
+    pub fn new_account_infos(
+        &self,
+        parameters: &ledger::Parameters,
+        sender_info: &AccountInformation,
+        recipient_info: Option<&AccountInformation>,
+        epoch: u64,
+    ) -> Option<(AccountInformation, Option<AccountInformation>)> {
+        let asset = self.asset_id;
+        let mut new_sender_info = *sender_info;
+        let sender_balance = sender_info.balances.get(asset)?;
+        new_sender_info
+            .balances
+            .set(asset, sender_balance.checked_sub(self.total_cost()?)?)?;
+        new_sender_info.nonce = sender_info.nonce.wrapping_add(1);
+        // The sender's spending is only tracked if it is limited.
+        if parameters.daily_limit.is_some() {
+            new_sender_info.spent_today = sender_info.spent_in(epoch).checked_add(self.amount)?;
+            new_sender_info.limit_epoch = epoch;
+        }
+        // The spending of delegates is only tracked if delegation is enabled.
+        if self.is_delegated(parameters) {
+            new_sender_info.delegated_spent = sender_info
+                .delegated_spent
+                .checked_add(self.total_cost()?)?;
+        }
+        // The next transaction must be authorized by the next derived key.
+        if parameters.hd_keys {
+            new_sender_info.key_index = sender_info.key_index.wrapping_add(1);
+        }
+        // The amount of a self-transfer returns to the sender, who only pays the
+        // fee. Both roles are played by the same account, and so they end up with
+        // the same information.
+        if self.sender == self.recipient && !self.is_withdrawal() {
+            let balance = new_sender_info.balances.get(asset)?;
+            new_sender_info
+                .balances
+                .set(asset, balance.checked_add(self.amount)?)?;
+            return Some((new_sender_info, Some(new_sender_info)));
+        }
+        // Withdrawn funds leave the ledger, so nobody is credited.
+        let new_recipient_info = match self.is_withdrawal() {
+            true => None,
+            false => {
+                let mut new_recipient_info = *recipient_info?;
+                let recipient_balance = new_recipient_info.balances.get(asset)?;
+                new_recipient_info
+                    .balances
+                    .set(asset, recipient_balance.checked_add(self.amount)?)?;
+                // The recipient's credits are only tracked if withdrawals are
+                // delayed after them.
+                if parameters.withdrawal_grace.is_some() {
+                    new_recipient_info.last_credited_epoch = epoch;
+                }
+                Some(new_recipient_info)
+            }
+        };
+        Some((new_sender_info, new_recipient_info))
+    }
+
+    /// Compute the balances of the asset `self.asset_id` of the sender and of the
+    /// recipient after applying the transaction to `state`, without applying it.
+    /// Withdrawals credit no account, so the recipient's balance is zero for them.
+    /// Returns why the transaction can't be applied if it is invalid.
+    pub fn simulate(
+        &self,
+        parameters: &ledger::Parameters,
+        state: &ledger::State,
+    ) -> Result<(Amount, Amount), SimulationError> {
+        let sender_info = state
+            .id_to_account_info
+            .get(&self.sender)
+            .ok_or(SimulationError::UnknownSender)?;
+        let recipient_info = state.id_to_account_info.get(&self.recipient);
+        if recipient_info.is_none() && !self.is_withdrawal() {
+            return Err(SimulationError::UnknownRecipient);
+        }
+        if !self.is_funded_by(sender_info) {
+            return Err(SimulationError::InsufficientFunds);
+        }
+        if !self.validate(parameters, state) {
+            return Err(SimulationError::Invalid);
+        }
+        let (new_sender_info, new_recipient_info) = self
+            .new_account_infos(parameters, sender_info, recipient_info, state.epoch)
+            .ok_or(SimulationError::Overflow)?;
+        let balance = |info: &AccountInformation| {
+            info.balances
+                .get(self.asset_id)
+                .expect("the asset is supported")
+        };
+        Ok((
+            balance(&new_sender_info),
+            new_recipient_info.map_or(Amount(0), |info| balance(&info)),
+        ))
+    }
+
+    /// Create a (possibly invalid) transaction of the native asset that pays no fee.
     pub fn create<R: Rng>(
         parameters: &ledger::Parameters,
         sender: AccountId,
@@ -83,23 +768,695 @@ impl Transaction {
         sender_sk: &AccountSecretKey,
         rng: &mut R,
     ) -> Self {
-        // The authorized message consists of (SenderAccId || RecipientAccId || Amount)
-        let mut message = sender.to_bytes_le();
-        message.extend(recipient.to_bytes_le());
-        message.extend(amount.to_bytes_le());
-        let signature = Schnorr::sign(&parameters.sig_params, sender_sk, &message, rng).unwrap();
+        Self::create_with_fee(
+            parameters,
+            sender,
+            recipient,
+            amount,
+            Amount(0),
+            sender_sk,
+            rng,
+        )
+    }
+
+    /// Create a (possibly invalid) transaction of the native asset that pays
+    /// the fee `fee`.
+    pub fn create_with_fee<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        recipient: AccountId,
+        amount: Amount,
+        fee: Amount,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        Self::create_with_asset(
+            parameters,
+            sender,
+            recipient,
+            AssetId::NATIVE,
+            amount,
+            fee,
+            sender_sk,
+            rng,
+        )
+    }
+
+    /// Create a (possibly invalid) transaction of the asset `asset_id` that pays
+    /// the fee `fee`, in the same asset.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_asset<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        recipient: AccountId,
+        asset_id: AssetId,
+        amount: Amount,
+        fee: Amount,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        Self::create_with_expiry(
+            parameters,
+            sender,
+            recipient,
+            asset_id,
+            amount,
+            fee,
+            BlockCommitment::default(),
+            sender_sk,
+            rng,
+        )
+    }
+
+    /// Create a (possibly invalid) transaction of the asset `asset_id` that pays
+    /// the fee `fee`, and that expires once the ledger moves past the block with
+    /// the commitment `expiry_block_commitment`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_expiry<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        recipient: AccountId,
+        asset_id: AssetId,
+        amount: Amount,
+        fee: Amount,
+        expiry_block_commitment: BlockCommitment,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        Self {
+            sender,
+            recipient,
+            asset_id,
+            amount,
+            fee,
+            expiry_block_commitment,
+            params_commitment: parameters.commitment(),
+            l1_address: L1Address::default(),
+            scale: 0,
+            signature: Default::default(),
+            receipt: None,
+            consent: None,
+            allowlist_proof: None,
+            authorization: None,
+            pow_nonce: 0,
+        }
+        .sign(parameters, sender_sk, rng)
+    }
+
+    /// Create a (possibly invalid) transaction of the native asset that pays no
+    /// fee, and whose amount is expressed with `scale` decimals.
+    pub fn create_with_scale<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        recipient: AccountId,
+        amount: Amount,
+        scale: u8,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
         Self {
             sender,
             recipient,
+            asset_id: AssetId::NATIVE,
             amount,
-            signature,
+            fee: Amount(0),
+            expiry_block_commitment: BlockCommitment::default(),
+            params_commitment: parameters.commitment(),
+            l1_address: L1Address::default(),
+            scale,
+            signature: Default::default(),
+            receipt: None,
+            consent: None,
+            allowlist_proof: None,
+            authorization: None,
+            pow_nonce: 0,
         }
+        .sign(parameters, sender_sk, rng)
+    }
+
+    /// Create a (possibly invalid) transaction that withdraws `amount` of the
+    /// asset `asset_id` from the ledger, and pays it out to `l1_address` on L1.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_withdrawal<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        asset_id: AssetId,
+        amount: Amount,
+        fee: Amount,
+        l1_address: L1Address,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        Self {
+            sender,
+            recipient: WITHDRAWAL_ACCOUNT,
+            asset_id,
+            amount,
+            fee,
+            expiry_block_commitment: BlockCommitment::default(),
+            params_commitment: parameters.commitment(),
+            l1_address,
+            scale: 0,
+            signature: Default::default(),
+            receipt: None,
+            consent: None,
+            allowlist_proof: None,
+            authorization: None,
+            pow_nonce: 0,
+        }
+        .sign(parameters, sender_sk, rng)
+    }
+
+    /// Sign the transaction with the secret key of its sender.
+    fn sign<R: Rng>(
+        mut self,
+        parameters: &ledger::Parameters,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        self.signature =
+            AccountSignature::sign(parameters, sender_sk, &self.signed_message(parameters), rng);
+        self
+    }
+
+    /// Sign the transaction again with the secret key of its sender, binding the
+    /// public key of its recipient `recipient_key`. This is needed if the ledger
+    /// binds recipient keys, which the constructors don't know about.
+    pub fn sign_to<R: Rng>(
+        mut self,
+        parameters: &ledger::Parameters,
+        recipient_key: &AccountPublicKey,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = self.signed_message_to(parameters, recipient_key);
+        self.signature = AccountSignature::sign(parameters, sender_sk, &message, rng);
+        self
+    }
+
+    /// Sign the transaction again with the secret key of its sender, binding the
+    /// public key of its recipient `recipient_key` and the root `pre_root` of
+    /// the account tree that it is applied to. This is needed if the ledger
+    /// binds pre-roots, see `message_at`.
+    pub fn sign_at<R: Rng>(
+        mut self,
+        parameters: &ledger::Parameters,
+        recipient_key: &AccountPublicKey,
+        pre_root: &AccRoot,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = self.message_at(parameters, recipient_key, pre_root);
+        self.signature = AccountSignature::sign(parameters, sender_sk, &message, rng);
+        self
+    }
+}
+
+/// The reason why a transaction can't be applied, as found by
+/// `Transaction::simulate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationError {
+    /// The sender's account doesn't exist.
+    UnknownSender,
+    /// The recipient's account doesn't exist, and the transaction is not a
+    /// withdrawal.
+    UnknownRecipient,
+    /// The sender can't afford the amount and the fee, or the asset is not
+    /// supported.
+    InsufficientFunds,
+    /// The transaction is invalid for another reason, e.g., its signature is.
+    Invalid,
+    /// The recipient's balance would overflow.
+    Overflow,
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::UnknownSender => "the sender doesn't exist",
+            Self::UnknownRecipient => "the recipient doesn't exist",
+            Self::InsufficientFunds => "the sender has insufficient funds",
+            Self::Invalid => "the transaction is invalid",
+            Self::Overflow => "the recipient's balance overflows",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// Are the transactions `transactions` of a batch sorted by their hashes, as
+/// ledgers with `canonical_order` set require? Transactions with equal hashes
+/// may appear in any order, since they are interchangeable.
+pub fn is_canonically_ordered(
+    parameters: &ledger::Parameters,
+    transactions: &[Transaction],
+) -> bool {
+    let hashes = transactions
+        .iter()
+        .map(|tx| tx.hash(parameters).into_repr())
+        .collect::<Vec<_>>();
+    hashes.windows(2).all(|pair| pair[0] <= pair[1])
+}
+
+/// Sort the transactions `transactions` of a batch by their hashes, so that
+/// `is_canonically_ordered` holds.
+pub fn sort_canonically(parameters: &ledger::Parameters, transactions: &mut [Transaction]) {
+    transactions.sort_by_cached_key(|tx| tx.hash(parameters).into_repr());
+}
+
+/// Do the transactions `transactions` of a batch have distinct identifiers, as
+/// ledgers with `unique_tx_ids` set require? No-op transactions pad batches, and
+/// so they may repeat.
+pub fn has_unique_ids(parameters: &ledger::Parameters, transactions: &[Transaction]) -> bool {
+    let mut ids = transactions
+        .iter()
+        .filter(|tx| !tx.is_noop())
+        .map(|tx| tx.id(parameters).into_repr())
+        .collect::<Vec<_>>();
+    let len = ids.len();
+    ids.sort_unstable();
+    ids.dedup();
+    ids.len() == len
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+    use crate::withdrawal::WITHDRAWAL_ACCOUNT;
+
+    #[test]
+    fn validate_with_proofs_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // The client only knows the root and the proofs of Alice's and Bob's accounts.
+        let root = state.root();
+        let alice_proof = state.prove_account(alice_id).unwrap();
+        let bob_proof = state.prove_account(bob_id).unwrap();
+        drop(state);
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert!(tx
+            .validate_with_proofs(
+                &pp,
+                &alice_proof,
+                Some(&bob_proof),
+                &root,
+                0,
+                &Default::default()
+            )
+            .unwrap());
+        let withdrawal = Transaction::create(
+            &pp,
+            alice_id,
+            WITHDRAWAL_ACCOUNT,
+            Amount(5),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(withdrawal
+            .validate_with_proofs(&pp, &alice_proof, None, &root, 0, &Default::default())
+            .unwrap());
+
+        // The transaction is rejected when Alice can't afford it, ...
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(11), &alice_sk, &mut rng);
+        assert!(!bad_tx
+            .validate_with_proofs(
+                &pp,
+                &alice_proof,
+                Some(&bob_proof),
+                &root,
+                0,
+                &Default::default()
+            )
+            .unwrap());
+        // ... when the recipient's proof is missing, ...
+        assert!(!tx
+            .validate_with_proofs(&pp, &alice_proof, None, &root, 0, &Default::default())
+            .unwrap());
+        // ... when the proofs are swapped, ...
+        assert!(!tx
+            .validate_with_proofs(
+                &pp,
+                &bob_proof,
+                Some(&alice_proof),
+                &root,
+                0,
+                &Default::default()
+            )
+            .unwrap());
+        // ... and when the sender's balance is inflated.
+        let mut inflated_proof = alice_proof;
+        inflated_proof
+            .info
+            .balances
+            .set(AssetId::NATIVE, Amount(11))
+            .unwrap();
+        assert!(!bad_tx
+            .validate_with_proofs(
+                &pp,
+                &inflated_proof,
+                Some(&bob_proof),
+                &root,
+                0,
+                &Default::default()
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn simulate_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, Amount(3)).unwrap();
+        let root = state.root();
+
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(4),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(tx.simulate(&pp, &state), Ok((Amount(5), Amount(7))));
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(11), &alice_sk, &mut rng);
+        assert_eq!(
+            tx.simulate(&pp, &state),
+            Err(SimulationError::InsufficientFunds)
+        );
+        let tx = Transaction::create(&pp, alice_id, AccountId(9), Amount(1), &alice_sk, &mut rng);
+        assert_eq!(
+            tx.simulate(&pp, &state),
+            Err(SimulationError::UnknownRecipient)
+        );
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            WITHDRAWAL_ACCOUNT,
+            Amount(2),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(tx.simulate(&pp, &state), Ok((Amount(8), Amount(0))));
+        // Simulating leaves the state untouched.
+        assert_eq!(state.root(), root);
+    }
+
+    #[test]
+    fn compute_cost_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let transfer = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            AssetId::NATIVE,
+            Amount(5),
+            Amount(0),
+            [0u8; 20],
+            &alice_sk,
+            &mut rng,
+        );
+        let self_transfer =
+            Transaction::create(&pp, alice_id, alice_id, Amount(5), &alice_sk, &mut rng);
+
+        // A transfer updates two accounts, which costs more than updating one, ...
+        assert!(transfer.compute_cost() > withdrawal.compute_cost());
+        assert_eq!(self_transfer.compute_cost(), withdrawal.compute_cost());
+        // ... and each extra signature adds to the cost.
+        let mut consented = transfer.clone();
+        consented.consent = Some(transfer.sign_consent(&pp, &bob_sk, &mut rng));
+        assert_eq!(
+            consented.compute_cost(),
+            transfer.compute_cost() + SIGNATURE_COST
+        );
+        assert_eq!(Transaction::NOOP.compute_cost(), 0);
+    }
+
+    #[test]
+    fn canonical_order_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut txs = (1..=4)
+            .map(|amount| {
+                Transaction::create(&pp, alice_id, bob_id, Amount(amount), &alice_sk, &mut rng)
+            })
+            .collect::<Vec<_>>();
+
+        // The hash doesn't depend on the signature, but on the signed fields.
+        let mut resigned = txs[0].clone();
+        resigned.signature =
+            Transaction::create(&pp, alice_id, bob_id, Amount(1), &alice_sk, &mut rng).signature;
+        assert_eq!(resigned.hash(&pp), txs[0].hash(&pp));
+        // The identifier does.
+        assert_ne!(resigned.id(&pp), txs[0].id(&pp));
+        assert_ne!(txs[1].hash(&pp), txs[0].hash(&pp));
+
+        sort_canonically(&pp, &mut txs);
+        assert!(is_canonically_ordered(&pp, &txs));
+        txs.swap(0, 1);
+        assert!(!is_canonically_ordered(&pp, &txs));
+    }
+
+    #[test]
+    fn structured_message_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.message_encoding = MessageEncoding::Structured;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice's wallet signs the structured digest of the transaction.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        assert_eq!(tx.signed_message(&pp), tx.structured_message());
+        assert!(tx.validate(&pp, &state));
+
+        // Changing any of the fields changes the digest.
+        let tampered = [
+            Transaction {
+                sender: bob_id,
+                ..tx.clone()
+            },
+            Transaction {
+                recipient: alice_id,
+                ..tx.clone()
+            },
+            Transaction {
+                asset_id: AssetId(1),
+                ..tx.clone()
+            },
+            Transaction {
+                amount: Amount(6),
+                ..tx.clone()
+            },
+            Transaction {
+                fee: Amount(1),
+                ..tx.clone()
+            },
+            Transaction {
+                expiry_block_commitment: BlockCommitment::from(1u8),
+                ..tx.clone()
+            },
+            Transaction {
+                params_commitment: ParamsCommitment::from(1u8),
+                ..tx.clone()
+            },
+        ];
+        for tampered in tampered {
+            assert_ne!(tampered.structured_message(), tx.structured_message());
+            assert!(!tampered.validate(&pp, &state));
+        }
+        // Wallets that sign the raw message are rejected.
+        let mut raw_pp = pp.clone();
+        raw_pp.message_encoding = MessageEncoding::Raw;
+        let tx = Transaction::create(&raw_pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let tx = Transaction {
+            params_commitment: pp.commitment(),
+            ..tx
+        };
+        assert!(!tx.validate(&pp, &state));
+    }
+
+    #[test]
+    fn bind_leaf_indices_test() {
+        let mut rng = ark_std::test_rng();
+        for encoding in [MessageEncoding::Sha256, MessageEncoding::Structured] {
+            let mut pp = Parameters::sample(&mut rng);
+            pp.message_encoding = encoding;
+            pp.bind_leaf_indices = true;
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+            // The signer signs the leaf indices of both accounts, ...
+            let mut tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            assert_eq!(tx.leaf_indices(), [alice_id.0 as u64, bob_id.0 as u64]);
+            assert!(tx.validate(&pp, &state));
+            // ... and a signature that doesn't is rejected.
+            let unbound = match encoding {
+                MessageEncoding::Structured => tx.structured_message(),
+                encoding => encoding.encode(tx.raw_message()),
+            };
+            assert_ne!(unbound, tx.signed_message(&pp));
+            tx.signature = AccountSignature::sign(&pp, &alice_sk, &unbound, &mut rng);
+            assert!(!tx.validate(&pp, &state));
+        }
+        assert!(transaction_type(false, true).ends_with(b"uint64 recipientLeafIndex)"));
+        assert_eq!(transaction_type(true, false), BOUND_TRANSACTION_TYPE);
+    }
+
+    #[test]
+    fn bind_recipient_key_test() {
+        use crate::rotation::RotateKey;
+        use crate::signature::{schnorr::Schnorr, SignatureScheme};
+
+        let mut rng = ark_std::test_rng();
+        for encoding in [MessageEncoding::Sha256, MessageEncoding::Structured] {
+            let mut pp = Parameters::sample(&mut rng);
+            pp.message_encoding = encoding;
+            pp.bind_recipient_key = true;
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+            // A transfer that doesn't sign Bob's key is rejected, ...
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            assert!(!tx.validate(&pp, &state));
+            // ... while one that does is accepted, ...
+            let tx = tx.sign_to(&pp, &bob_pk, &alice_sk, &mut rng);
+            assert_ne!(tx.signed_message_to(&pp, &bob_pk), tx.signed_message(&pp));
+            assert!(tx.validate(&pp, &state));
+            let bob_proof = state.prove_account(bob_id).unwrap();
+            assert!(tx
+                .validate_with_proofs(
+                    &pp,
+                    &state.prove_account(alice_id).unwrap(),
+                    Some(&bob_proof),
+                    &state.root(),
+                    state.epoch,
+                    &state.block_commitment,
+                )
+                .unwrap());
+
+            // ... until Bob's identifier points to another key.
+            let (new_pk, _new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+            let rotation = RotateKey::create(&pp, bob_id, new_pk, &bob_sk, &mut rng);
+            state.apply_rotation(&pp, &rotation).unwrap();
+            assert!(!tx.validate(&pp, &state));
+        }
+    }
+
+    #[test]
+    fn bind_pre_root_test() {
+        let mut rng = ark_std::test_rng();
+        for encoding in [MessageEncoding::Sha256, MessageEncoding::Structured] {
+            let mut pp = Parameters::sample(&mut rng);
+            pp.message_encoding = encoding;
+            pp.bind_pre_root = true;
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+            // A transaction that doesn't sign the current root is rejected, ...
+            let tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+            assert!(!tx.validate(&pp, &state));
+            // ... while one that does is accepted, ...
+            let root = state.root();
+            let tx = tx.sign_at(&pp, &bob_pk, &root, &alice_sk, &mut rng);
+            assert!(tx.validate(&pp, &state));
+            assert!(tx
+                .validate_with_proofs(
+                    &pp,
+                    &state.prove_account(alice_id).unwrap(),
+                    Some(&state.prove_account(bob_id).unwrap()),
+                    &root,
+                    state.epoch,
+                    &state.block_commitment,
+                )
+                .unwrap());
+
+            // ... but not once the ledger moves to another root, as on a fork
+            // where Alice was credited more.
+            let mut fork = state.clone();
+            fork.update_balance(alice_id, Amount(30)).unwrap();
+            assert!(!tx.validate(&pp, &fork));
+            let tx = tx.sign_at(&pp, &bob_pk, &fork.root(), &alice_sk, &mut rng);
+            assert!(tx.validate(&pp, &fork));
+            assert!(!tx.validate(&pp, &state));
+        }
+    }
+
+    #[test]
+    fn balance_cap_test() {
+        let mut rng = ark_std::test_rng();
+        let mut pp = Parameters::sample(&mut rng);
+        pp.balance_caps = true;
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, Amount(3)).unwrap();
+        state.set_balance_cap(bob_id, Amount(10)).unwrap();
+
+        // A credit that pushes Bob over his cap is rejected, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(8), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        // ... while one that lands exactly at it is accepted, ...
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(7), &alice_sk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+        assert!(tx
+            .validate_with_proofs(
+                &pp,
+                &state.prove_account(alice_id).unwrap(),
+                Some(&state.prove_account(bob_id).unwrap()),
+                &state.root(),
+                state.epoch,
+                &state.block_commitment,
+            )
+            .unwrap());
+        state.apply_transaction(&pp, &tx).unwrap();
+        // ... and lifting the cap lets Bob be credited again.
+        let tx = Transaction::create(&pp, alice_id, bob_id, Amount(1), &alice_sk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        state.set_balance_cap(bob_id, Amount(0)).unwrap();
+        assert!(tx.validate(&pp, &state));
     }
 }
 
 // Ideas to make exercises more interesting/complex:
-// 1. Add fees
-// 2. Add recipient confirmation requirement if tx amount is too large.
-// 3. Add authority confirmation if tx amount is too large.
-// 4. Create account if it doesn't exist.
-// 5. Add idea for compressing state transitions with repeated senders and recipients.
+// 1. Add recipient confirmation requirement if tx amount is too large.
+// 2. Add authority confirmation if tx amount is too large.
+// 3. Create account if it doesn't exist.
+// 4. Add idea for compressing state transitions with repeated senders and recipients.
@@ -0,0 +1,300 @@
+use crate::account::AccountId;
+use crate::ledger::{
+    personalize_leaf, AccPath, AccRoot, AccountHash, Amount, AssetId, LeafDomain, MerkleConfig,
+    Parameters,
+};
+use crate::transaction::Transaction;
+use ark_crypto_primitives::crh::CRH;
+use ark_crypto_primitives::merkle_tree::MerkleTree;
+
+/// The reserved account identifier that funds are sent to in order to withdraw
+/// them from the ledger. Account identifiers are assigned starting from 1, so
+/// no account is ever registered under this identifier.
+pub const WITHDRAWAL_ACCOUNT: AccountId = AccountId(0);
+
+/// An address on L1, e.g., on Ethereum, that withdrawn funds are paid out to.
+pub type L1Address = [u8; 20];
+
+/// The tag that separates the commitments to withdrawals from other uses of
+/// `AccountHash`.
+pub const WITHDRAWAL_TAG: &[u8] = b"withdrawal";
+
+/// A withdrawal of `amount` of the asset `asset_id` from the ledger, which can
+/// later be claimed by the owner of the account `recipient`.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub struct Withdrawal {
+    /// The account that made the withdrawal.
+    pub recipient: AccountId,
+    /// The asset being withdrawn.
+    pub asset_id: AssetId,
+    /// The amount being withdrawn.
+    pub amount: Amount,
+    /// The address on L1 that the amount is paid out to.
+    pub l1_address: L1Address,
+}
+
+impl Withdrawal {
+    /// The leaf stored for transactions that are not withdrawals.
+    pub const EMPTY: Self = Self {
+        recipient: WITHDRAWAL_ACCOUNT,
+        asset_id: AssetId::NATIVE,
+        amount: Amount(0),
+        l1_address: [0; 20],
+    };
+
+    /// Return the withdrawal made by `tx`, if `tx` is a withdrawal.
+    pub fn from_transaction(tx: &Transaction) -> Option<Self> {
+        tx.is_withdrawal().then_some(Self {
+            recipient: tx.sender,
+            asset_id: tx.asset_id,
+            amount: tx.amount,
+            l1_address: tx.l1_address,
+        })
+    }
+
+    /// Convert the withdrawal to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = self.recipient.to_bytes_le();
+        bytes.extend(self.asset_id.to_bytes_le());
+        bytes.extend(self.amount.to_bytes_le());
+        bytes.extend(self.l1_address);
+        bytes
+    }
+
+    /// Return the leaf of the withdrawal Merkle tree that stores this withdrawal.
+    /// Withdrawals don't fit in a leaf along with their L1 address, and so the
+    /// leaf is a commitment to them, as for account information.
+    pub fn to_leaf(&self, parameters: &Parameters) -> Vec<u8> {
+        // The committed input consists of
+        // (Tag || Withdrawal)
+        let mut input = WITHDRAWAL_TAG.to_vec();
+        input.extend(self.to_bytes_le());
+        let commitment = AccountHash::evaluate(&parameters.account_crh_params, &input).unwrap();
+        personalize_leaf(
+            parameters.leaf_personalization,
+            LeafDomain::Withdrawal,
+            ark_ff::to_bytes![commitment].unwrap(),
+        )
+    }
+}
+
+/// The root of a withdrawal Merkle tree.
+pub type WithdrawalRoot = AccRoot;
+/// A membership proof for a given withdrawal.
+pub type WithdrawalPath = AccPath;
+
+/// An append-only Merkle tree of the withdrawals made in a batch of transactions.
+///
+/// The tree has one leaf per transaction in the batch: the `i`-th leaf stores the
+/// withdrawal made by the `i`-th transaction, or `Withdrawal::EMPTY` if that
+/// transaction is not a withdrawal.
+#[derive(Clone)]
+pub struct WithdrawalTree {
+    tree: MerkleTree<MerkleConfig>,
+    withdrawals: Vec<Option<Withdrawal>>,
+    /// The parameters used to compute the leaves that store the withdrawals.
+    parameters: Parameters,
+}
+
+impl WithdrawalTree {
+    /// Return the number of leaves in the withdrawal tree of a batch of
+    /// `num_transactions` transactions. Merkle trees need a power of two number of
+    /// leaves, and at least two of them.
+    pub fn num_leaves(num_transactions: usize) -> usize {
+        num_transactions.next_power_of_two().max(2)
+    }
+
+    /// Create an empty withdrawal tree for a batch of `num_transactions` transactions.
+    pub fn new(parameters: &Parameters, num_transactions: usize) -> Self {
+        let leaves =
+            vec![Withdrawal::EMPTY.to_leaf(parameters); Self::num_leaves(num_transactions)];
+        let tree = MerkleTree::new(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &leaves,
+        )
+        .unwrap();
+        Self {
+            tree,
+            withdrawals: Vec::with_capacity(num_transactions),
+            parameters: parameters.clone(),
+        }
+    }
+
+    /// Create the withdrawal tree of the batch `transactions`.
+    pub fn from_transactions(parameters: &Parameters, transactions: &[Transaction]) -> Self {
+        let mut tree = Self::new(parameters, transactions.len());
+        for tx in transactions {
+            tree.append(tx);
+        }
+        tree
+    }
+
+    /// Rebuild the withdrawal tree of a batch of `num_transactions` transactions
+    /// from the withdrawals that it made, each along with the index of its
+    /// transaction in the batch, e.g., as an L1 contract learns them from its
+    /// event log. Returns `None` if two withdrawals share an index, or if an
+    /// index is past the end of the batch.
+    pub fn from_events(
+        parameters: &Parameters,
+        num_transactions: usize,
+        withdrawals: &[(usize, Withdrawal)],
+    ) -> Option<Self> {
+        let mut slots = vec![None; num_transactions];
+        for (index, withdrawal) in withdrawals {
+            if slots.get_mut(*index)?.replace(*withdrawal).is_some() {
+                return None;
+            }
+        }
+        let mut tree = Self::new(parameters, num_transactions);
+        for withdrawal in slots {
+            tree.push(withdrawal);
+        }
+        Some(tree)
+    }
+
+    /// Append the leaf for the next transaction `tx` in the batch. Returns the
+    /// index of the leaf if `tx` is a withdrawal, and `None` otherwise.
+    pub fn append(&mut self, tx: &Transaction) -> Option<usize> {
+        self.push(Withdrawal::from_transaction(tx))
+    }
+
+    /// Append the leaf for the next transaction in the batch, which made the
+    /// withdrawal `withdrawal`, if any. Returns the index of the leaf if there
+    /// is a withdrawal, and `None` otherwise.
+    pub fn push(&mut self, withdrawal: Option<Withdrawal>) -> Option<usize> {
+        let index = self.withdrawals.len();
+        assert!(
+            index < 1 << (self.tree.height() - 1),
+            "withdrawal tree is full"
+        );
+        self.withdrawals.push(withdrawal);
+        withdrawal.map(|withdrawal| {
+            self.tree
+                .update(index, &withdrawal.to_leaf(&self.parameters))
+                .expect("should exist");
+            index
+        })
+    }
+
+    /// Append the empty leaf for the next transaction in the batch, because it
+    /// was skipped rather than applied.
+    pub fn skip(&mut self) {
+        assert!(
+            self.withdrawals.len() < 1 << (self.tree.height() - 1),
+            "withdrawal tree is full"
+        );
+        self.withdrawals.push(None);
+    }
+
+    /// Return the root of the withdrawal Merkle tree.
+    pub fn root(&self) -> WithdrawalRoot {
+        self.tree.root()
+    }
+
+    /// Return the withdrawal stored at `index`, if there is one.
+    pub fn get(&self, index: usize) -> Option<&Withdrawal> {
+        self.withdrawals.get(index)?.as_ref()
+    }
+
+    /// Produce a membership proof for the withdrawal stored at `index`, which can
+    /// be used to claim it. Returns `None` if there is no withdrawal at `index`.
+    pub fn generate_proof(&self, index: usize) -> Option<WithdrawalPath> {
+        self.get(index)?;
+        Some(self.tree.generate_proof(index).unwrap())
+    }
+}
+
+/// Check that `path` proves that `withdrawal` is in the withdrawal tree with root `root`.
+pub fn verify_withdrawal(
+    parameters: &Parameters,
+    root: &WithdrawalRoot,
+    withdrawal: &Withdrawal,
+    path: &WithdrawalPath,
+) -> bool {
+    path.verify(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        root,
+        &withdrawal.to_leaf(parameters),
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::State;
+
+    #[test]
+    fn withdrawal_proof_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays Bob, and then withdraws some of her remaining funds to her
+        // address on L1.
+        let transfer = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let l1_address = [0xab; 20];
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            AssetId::NATIVE,
+            Amount(10),
+            Amount(0),
+            l1_address,
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &transfer).unwrap();
+        let state_before_withdrawal = state.clone();
+        state.apply_transaction(&pp, &withdrawal).unwrap();
+        // The withdrawn funds leave the ledger.
+        let balance = |id| state.id_to_account_info[&id].balances.get(AssetId::NATIVE);
+        assert_eq!(balance(alice_id), Some(Amount(5)));
+        assert_eq!(balance(bob_id), Some(Amount(5)));
+
+        let tree = WithdrawalTree::from_transactions(&pp, &[transfer, withdrawal.clone()]);
+        assert!(tree.generate_proof(0).is_none());
+        let path = tree.generate_proof(1).unwrap();
+        let expected = Withdrawal {
+            recipient: alice_id,
+            asset_id: AssetId::NATIVE,
+            amount: Amount(10),
+            l1_address,
+        };
+        assert_eq!(tree.get(1), Some(&expected));
+        assert!(verify_withdrawal(&pp, &tree.root(), &expected, &path));
+
+        // The proof doesn't work for a different amount.
+        let wrong = Withdrawal {
+            amount: Amount(11),
+            ..expected
+        };
+        assert!(!verify_withdrawal(&pp, &tree.root(), &wrong, &path));
+        // Nor for a different asset.
+        let wrong = Withdrawal {
+            asset_id: AssetId(1),
+            ..expected
+        };
+        assert!(!verify_withdrawal(&pp, &tree.root(), &wrong, &path));
+        // Nor for a different L1 address.
+        let wrong = Withdrawal {
+            l1_address: [0xac; 20],
+            ..expected
+        };
+        assert!(!verify_withdrawal(&pp, &tree.root(), &wrong, &path));
+
+        // The L1 address is signed, and so it can't be redirected.
+        let redirected = Transaction {
+            l1_address: [0xac; 20],
+            ..withdrawal.clone()
+        };
+        assert!(withdrawal.validate(&pp, &state_before_withdrawal));
+        assert!(!redirected.validate(&pp, &state_before_withdrawal));
+    }
+}